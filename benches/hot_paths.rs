@@ -0,0 +1,200 @@
+//! Benchmarks of consensus critical hot paths, run with `cargo bench --features bench`.
+
+use avail_light::{
+	block_builder::extrinsics_root,
+	counters::{self, Counter},
+	header::{digest::SmallDigest, HeaderHash, HeaderRef},
+	proof,
+	storage_proof::{build_trie, verify_read_proof},
+	test_utils::{BlockSpec, ChainBuilder},
+	trie::arena::ProofArena,
+};
+use avail_subxt::{config::substrate::Digest, primitives::Header};
+use codec::{Decode, Encode};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use hex_literal::hex;
+use kate_recovery::{
+	data::Cell,
+	matrix::{Dimensions, Position},
+	testnet,
+};
+use sp_core::Blake2Hasher;
+use sp_trie::{LayoutV1, StorageProof};
+use std::sync::Arc;
+
+fn header() -> Header {
+	let mut chain = ChainBuilder::new(&[[1; 32]]).with_seal_key([2; 32]);
+	let spec = BlockSpec {
+		extrinsics: vec![vec![0; 1024]; 16],
+		..Default::default()
+	};
+	let hash = chain.build_on(chain.genesis_hash(), spec).unwrap();
+	chain.header(&hash).unwrap().clone()
+}
+
+fn header_benches(c: &mut Criterion) {
+	let header = header();
+	let encoded = header.encode();
+
+	c.bench_function("header_encode", |b| b.iter(|| black_box(&header).encode()));
+	c.bench_function("header_decode", |b| {
+		b.iter(|| Header::decode(&mut black_box(&encoded[..])).unwrap())
+	});
+	c.bench_function("header_decode_ref", |b| {
+		b.iter(|| HeaderRef::decode(black_box(&encoded)).unwrap())
+	});
+	c.bench_function("header_hash", |b| {
+		b.iter(|| Header::hash_from_scale_encoded(black_box(&encoded)))
+	});
+
+	let digest = header.digest.encode();
+	c.bench_function("digest_decode", |b| {
+		b.iter(|| Digest::decode(&mut black_box(&digest[..])).unwrap())
+	});
+	c.bench_function("digest_decode_small", |b| {
+		b.iter(|| SmallDigest::decode(&mut black_box(&digest[..])).unwrap())
+	});
+}
+
+fn trie_benches(c: &mut Criterion) {
+	let extrinsics = (0..1024u32)
+		.map(|index| index.encode().repeat(64))
+		.collect::<Vec<_>>();
+	c.bench_function("extrinsics_root_1024", |b| {
+		b.iter(|| extrinsics_root(black_box(&extrinsics)))
+	});
+	#[cfg(feature = "parallel")]
+	{
+		use sp_trie::TrieConfiguration;
+
+		let extrinsics = (0..16384u32)
+			.map(|index| index.encode().repeat(64))
+			.collect::<Vec<_>>();
+		c.bench_function("extrinsics_root_16384", |b| {
+			b.iter(|| {
+				sp_trie::LayoutV0::<Blake2Hasher>::ordered_trie_root(
+					black_box(&extrinsics).iter().map(Encode::encode),
+				)
+			})
+		});
+		c.bench_function("extrinsics_root_parallel_16384", |b| {
+			b.iter(|| avail_light::trie::ordered_root::ordered_trie_root(black_box(&extrinsics)))
+		});
+	}
+
+	let entries = (0..1024u32)
+		.map(|index| (index.encode(), index.encode().repeat(8)))
+		.collect::<Vec<_>>();
+	let entries = entries
+		.iter()
+		.map(|(key, value)| (&key[..], &value[..]))
+		.collect::<Vec<_>>();
+	let (root, proof) = build_trie(&entries);
+	let keys = entries
+		.iter()
+		.step_by(64)
+		.map(|(key, _)| key.to_vec())
+		.collect::<Vec<_>>();
+	c.bench_function("verify_read_proof_16_keys", |b| {
+		b.iter(|| verify_read_proof(root, black_box(proof.clone()), &keys).unwrap())
+	});
+
+	// Baseline of the arena, verification with the proof nodes in the memory database
+	c.bench_function("verify_read_proof_16_keys_memory_db", |b| {
+		b.iter(|| {
+			let db = StorageProof::new(black_box(proof.clone())).into_memory_db::<Blake2Hasher>();
+			for key in &keys {
+				sp_trie::read_trie_value::<LayoutV1<Blake2Hasher>, _>(&db, &root, key, None, None)
+					.unwrap();
+			}
+		})
+	});
+	c.bench_function("proof_arena_build_1024", |b| {
+		b.iter(|| ProofArena::new(black_box(&proof)))
+	});
+	c.bench_function("proof_memory_db_build_1024", |b| {
+		b.iter_batched(
+			|| proof.clone(),
+			|proof| StorageProof::new(proof).into_memory_db::<Blake2Hasher>(),
+			BatchSize::SmallInput,
+		)
+	});
+
+	// Lookups copy the reached nodes out of both databases, so they are expected to perform the same
+	let arena = ProofArena::new(&proof);
+	let db = StorageProof::new(proof.clone()).into_memory_db::<Blake2Hasher>();
+	c.bench_function("proof_arena_lookup_16_keys", |b| {
+		b.iter(|| {
+			for key in &keys {
+				sp_trie::read_trie_value::<LayoutV1<Blake2Hasher>, _>(
+					&arena, &root, key, None, None,
+				)
+				.unwrap();
+			}
+		})
+	});
+	c.bench_function("proof_memory_db_lookup_16_keys", |b| {
+		b.iter(|| {
+			for key in &keys {
+				sp_trie::read_trie_value::<LayoutV1<Blake2Hasher>, _>(&db, &root, key, None, None)
+					.unwrap();
+			}
+		})
+	});
+}
+
+fn kzg_benches(c: &mut Criterion) {
+	let public_parameters = Arc::new(testnet::public_params(1024));
+	let dimensions = Dimensions::new(1, 4).unwrap();
+	let commitment = hex!("ab9ffa4687647d9bf36df3659ea5b993493aa38a5851569f67a4562ed013561e0a3b2de536771de28644badb2414b563");
+	// Commitment is a valid curve point, so verification runs the full pairing check
+	let mut content = [0u8; 80];
+	content[..48].copy_from_slice(&commitment);
+	let cells = (0..4)
+		.map(|col| Cell {
+			position: Position { row: 0, col },
+			content,
+		})
+		.collect::<Vec<_>>();
+	let runtime = tokio::runtime::Runtime::new().unwrap();
+
+	c.bench_function("verify_cells_4", |b| {
+		b.iter(|| {
+			runtime
+				.block_on(proof::verify(
+					1,
+					dimensions,
+					black_box(&cells),
+					&[commitment],
+					public_parameters.clone(),
+				))
+				.unwrap()
+		})
+	});
+}
+
+/// Checks that hot paths don't perform redundant work
+fn counter_checks(_: &mut Criterion) {
+	let header = header();
+	counters::reset();
+	let encoded = header.encode();
+	let header_ref = HeaderRef::decode(&encoded).unwrap();
+	header_ref.hash();
+	assert_eq!(counters::get(Counter::HeadersHashed), 1);
+
+	extrinsics_root(&[vec![1], vec![2]]);
+	assert_eq!(counters::get(Counter::TrieRoots), 1);
+
+	let (root, proof) = build_trie(&[(b"key", b"value")]);
+	verify_read_proof(root, proof, &[b"key".to_vec()]).unwrap();
+	assert_eq!(counters::get(Counter::ReadProofsVerified), 1);
+}
+
+criterion_group!(
+	benches,
+	header_benches,
+	trie_benches,
+	kzg_benches,
+	counter_checks
+);
+criterion_main!(benches);
@@ -0,0 +1,11 @@
+#![no_main]
+
+use avail_light::{
+	body::Block,
+	limits::{DecodeLimits, DecodeWithLimits},
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+	let _ = Block::decode_with_limits(data, &DecodeLimits::default());
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use avail_light::header::HeaderRef;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+	if let Ok(header) = HeaderRef::decode(data) {
+		let _ = header.digest.iter().count();
+		let _ = header.decode_extension();
+	}
+});
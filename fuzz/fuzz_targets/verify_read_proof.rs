@@ -0,0 +1,12 @@
+#![no_main]
+
+use avail_light::{fuzzing::ValidReadProof, storage_proof::verify_read_proof};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (ValidReadProof, Vec<u8>)| {
+	let (valid, extra_node) = input;
+	let mut proof = valid.proof;
+	proof.push(extra_node);
+	// Extra nodes must not affect verification of the valid proof
+	assert!(verify_read_proof(valid.state_root, proof, &valid.keys).is_ok());
+});
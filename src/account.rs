@@ -0,0 +1,206 @@
+//! Verified account info and balance queries.
+//!
+//! `System::Account` value is fetched with the read proof through the [`StateClient`], and decoded using the type
+//! from the runtime metadata, so both the current `AccountData` layout (`free`, `reserved`, `frozen`, `flags`) and
+//! the legacy one (`misc_frozen` and `fee_frozen` instead of `frozen`) are supported.
+//!
+//! Account without the storage entry is returned as the default (empty) account, same as the runtime does.
+
+use avail_subxt::utils::H256;
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use sp_core::{blake2_128, twox_128};
+use subxt::{
+	ext::scale_value::{At, Value},
+	metadata::types::StorageEntryType,
+	Metadata,
+};
+
+use crate::{
+	dynamic,
+	state_client::{Client, StateClient},
+};
+
+/// Balances of the account, in the smallest units
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AccountData {
+	pub free: u128,
+	pub reserved: u128,
+	/// Balance which cannot be transferred, but may overlap with the reserved balance
+	pub frozen: u128,
+}
+
+impl AccountData {
+	pub fn total(&self) -> u128 {
+		self.free.saturating_add(self.reserved)
+	}
+
+	/// Free balance which can be transferred, since frozen balance is first covered by the reserved balance
+	pub fn transferable(&self) -> u128 {
+		self.free
+			.saturating_sub(self.frozen.saturating_sub(self.reserved))
+	}
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AccountInfo {
+	pub nonce: u32,
+	pub consumers: u32,
+	pub providers: u32,
+	pub sufficients: u32,
+	pub data: AccountData,
+}
+
+/// Storage key of the `System::Account` entry
+pub fn system_account_storage_key(account: &[u8; 32]) -> Vec<u8> {
+	[
+		&twox_128(b"System")[..],
+		&twox_128(b"Account")[..],
+		&blake2_128(account)[..],
+		&account[..],
+	]
+	.concat()
+}
+
+fn field<T>(value: &Value<T>, name: &str) -> Option<u128> {
+	value.at(name).and_then(|value| value.as_u128())
+}
+
+fn counter<T>(value: &Value<T>, name: &str) -> Result<u32> {
+	field(value, name)
+		.and_then(|counter| u32::try_from(counter).ok())
+		.ok_or_else(|| eyre!("Account field {name} is missing"))
+}
+
+fn balance<T>(value: &Value<T>, name: &str) -> Result<u128> {
+	field(value, name).ok_or_else(|| eyre!("Account data field {name} is missing"))
+}
+
+/// Maps decoded `AccountInfo` value to the typed account info
+pub fn account_from_value<T>(value: &Value<T>) -> Result<AccountInfo> {
+	let data = value
+		.at("data")
+		.ok_or_else(|| eyre!("Account field data is missing"))?;
+	let frozen = match field(data, "frozen") {
+		Some(frozen) => frozen,
+		None => balance(data, "misc_frozen")?.max(balance(data, "fee_frozen")?),
+	};
+	Ok(AccountInfo {
+		nonce: counter(value, "nonce")?,
+		consumers: counter(value, "consumers")?,
+		providers: counter(value, "providers")?,
+		sufficients: counter(value, "sufficients")?,
+		data: AccountData {
+			free: balance(data, "free")?,
+			reserved: balance(data, "reserved")?,
+			frozen,
+		},
+	})
+}
+
+/// Decodes `System::Account` storage value, using the value type from the metadata
+pub fn decode_account_info(metadata: &Metadata, encoded: &[u8]) -> Result<AccountInfo> {
+	let entry = metadata
+		.pallet_by_name("System")
+		.and_then(|pallet| pallet.storage())
+		.and_then(|storage| storage.entry_by_name("Account"))
+		.ok_or_else(|| eyre!("Storage System.Account is not found in metadata"))?;
+	let StorageEntryType::Map { value_ty, .. } = entry.entry_type() else {
+		return Err(eyre!("Storage System.Account is not a map"));
+	};
+	let value = dynamic::decode_as_type(&mut &encoded[..], *value_ty, metadata.types())
+		.map_err(|error| eyre!("Failed to decode account info: {error}"))?;
+	account_from_value(&value)
+}
+
+impl<T: Client> StateClient<T> {
+	/// Returns verified account info at the given block
+	pub async fn system_account(
+		&self,
+		metadata: &Metadata,
+		account: &[u8; 32],
+		block_hash: H256,
+	) -> Result<AccountInfo> {
+		let value = self
+			.storage(system_account_storage_key(account), block_hash)
+			.await
+			.wrap_err("Failed to get account info")?;
+		match value {
+			Some(encoded) => decode_account_info(metadata, &encoded),
+			None => Ok(AccountInfo::default()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use test_case::test_case;
+
+	fn account(data: Vec<(&str, u128)>) -> Value<()> {
+		let data = data
+			.into_iter()
+			.map(|(name, balance)| (name, Value::u128(balance)));
+		Value::named_composite([
+			("nonce", Value::u128(5)),
+			("consumers", Value::u128(1)),
+			("providers", Value::u128(1)),
+			("sufficients", Value::u128(0)),
+			("data", Value::named_composite(data)),
+		])
+	}
+
+	#[test]
+	fn current_account_data() {
+		let value = account(vec![
+			("free", 100),
+			("reserved", 20),
+			("frozen", 50),
+			("flags", 1 << 127),
+		]);
+		let info = account_from_value(&value).unwrap();
+		assert_eq!(info.nonce, 5);
+		assert_eq!(
+			info.data,
+			AccountData {
+				free: 100,
+				reserved: 20,
+				frozen: 50
+			}
+		);
+	}
+
+	#[test]
+	fn legacy_account_data() {
+		let value = account(vec![
+			("free", 100),
+			("reserved", 20),
+			("misc_frozen", 30),
+			("fee_frozen", 50),
+		]);
+		assert_eq!(account_from_value(&value).unwrap().data.frozen, 50);
+		assert!(account_from_value(&account(vec![("free", 100)])).is_err());
+	}
+
+	#[test_case(100, 0, 0 => 100 ; "nothing frozen")]
+	#[test_case(100, 20, 50 => 70 ; "frozen over reserved")]
+	#[test_case(100, 60, 50 => 100 ; "frozen covered by reserved")]
+	#[test_case(10, 0, 50 => 0 ; "frozen over free")]
+	fn transferable(free: u128, reserved: u128, frozen: u128) -> u128 {
+		AccountData {
+			free,
+			reserved,
+			frozen,
+		}
+		.transferable()
+	}
+
+	#[test]
+	fn account_storage_key() {
+		let key = system_account_storage_key(&[1; 32]);
+		assert_eq!(key.len(), 80);
+		assert!(key.ends_with(&[1; 32]));
+	}
+}
@@ -0,0 +1,344 @@
+//! Ancestry queries, answered with proofs which can be verified by other parties (e.g. bridges or dispute games).
+//!
+//! Proof that block A is an ancestor of block B is either:
+//!
+//! * Header chain - headers from A to B, each one the parent of the next, built from the locally stored headers
+//! * MMR - leaf of A in the MMR of B (see [`crate::mmr`]), with the header of B which commits to its MMR root in the
+//!   BEEFY digest, generated with the `MmrApi` runtime API when local headers are missing or too far apart
+//!
+//! Both proofs are verified against the hash of B with [`AncestryProof::verify`], which the verifier has to trust
+//! (e.g. finalized or BEEFY committed block).
+
+use avail_subxt::{primitives::Header as DaHeader, utils::H256};
+use codec::{Decode, Encode};
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use tracing::debug;
+
+use crate::{
+	beefy,
+	data::{Database, Key},
+	fee::RuntimeApi,
+	header::HeaderHash,
+	mmr::{self, LeafProof, MmrLeaf},
+};
+
+const GENERATE_PROOF: &str = "MmrApi_generate_proof";
+
+/// Maximum number of headers in the header chain proof
+pub const MAX_HEADER_CHAIN_LEN: u32 = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct BlockRef {
+	pub number: u32,
+	pub hash: H256,
+}
+
+#[derive(Clone, Debug, Encode, Decode)]
+pub enum AncestryProof {
+	/// Headers from the ancestor to the descendant, inclusive
+	HeaderChain(Vec<DaHeader>),
+	/// Opaque leaf of the ancestor, proven against the MMR root in the descendant header digest
+	Mmr {
+		descendant: DaHeader,
+		leaf: Vec<u8>,
+		proof: LeafProof,
+	},
+}
+
+impl AncestryProof {
+	/// Verifies that the ancestor is an ancestor of (or equal to) the descendant block.
+	pub fn verify(&self, ancestor: &BlockRef, descendant_hash: H256) -> Result<()> {
+		match self {
+			AncestryProof::HeaderChain(headers) => {
+				let (Some(first), Some(last)) = (headers.first(), headers.last()) else {
+					return Err(eyre!("Header chain is empty"));
+				};
+				if first.number != ancestor.number || first.header_hash() != ancestor.hash {
+					return Err(eyre!("Header chain doesn't start with the ancestor"));
+				}
+				if last.header_hash() != descendant_hash {
+					return Err(eyre!("Header chain doesn't end with the descendant"));
+				}
+				for pair in headers.windows(2) {
+					if pair[1].parent_hash != pair[0].header_hash()
+						|| pair[1].number != pair[0].number + 1
+					{
+						return Err(eyre!(
+							"Header {} is not the parent of the next header",
+							pair[0].number
+						));
+					}
+				}
+				Ok(())
+			},
+			AncestryProof::Mmr {
+				descendant,
+				leaf,
+				proof,
+			} => {
+				if descendant.header_hash() != descendant_hash {
+					return Err(eyre!("Header doesn't match the descendant hash"));
+				}
+				let root = beefy::mmr_root(descendant)
+					.ok_or_else(|| eyre!("Descendant header has no MMR root"))?;
+				mmr::verify_leaf_proof(root, std::slice::from_ref(leaf), proof)?;
+				let leaf = MmrLeaf::from_opaque(leaf)?;
+				if leaf.parent_number_and_hash != (ancestor.number, ancestor.hash) {
+					return Err(eyre!(
+						"MMR leaf is of block {} {:?}",
+						leaf.parent_number(),
+						leaf.parent_hash()
+					));
+				}
+				Ok(())
+			},
+		}
+	}
+}
+
+/// Proves ancestry with the local headers, or with the MMR proofs of the runtime
+pub struct Ancestry<T: Database, A: RuntimeApi> {
+	db: T,
+	api: A,
+}
+
+enum LocalChain {
+	Complete(Vec<DaHeader>),
+	/// Chain doesn't reach the ancestor hash at the ancestor number
+	Forked,
+	Incomplete,
+}
+
+impl<T: Database, A: RuntimeApi> Ancestry<T, A> {
+	pub fn new(db: T, api: A) -> Self {
+		Ancestry { db, api }
+	}
+
+	fn local_chain(&self, ancestor: &BlockRef, descendant: &DaHeader) -> Result<LocalChain> {
+		if descendant.number - ancestor.number >= MAX_HEADER_CHAIN_LEN {
+			return Ok(LocalChain::Incomplete);
+		}
+		let mut headers = vec![descendant.clone()];
+		for number in (ancestor.number..descendant.number).rev() {
+			let Some(header) = self.db.get::<DaHeader>(Key::BlockHeader(number))? else {
+				return Ok(LocalChain::Incomplete);
+			};
+			let child = headers.last().expect("Chain is not empty");
+			// Stored header is of the other fork
+			if header.header_hash() != child.parent_hash {
+				return Ok(LocalChain::Incomplete);
+			}
+			headers.push(header);
+		}
+		headers.reverse();
+		match headers[0].header_hash() == ancestor.hash {
+			true => Ok(LocalChain::Complete(headers)),
+			false => Ok(LocalChain::Forked),
+		}
+	}
+
+	async fn mmr_proof(
+		&self,
+		ancestor: &BlockRef,
+		descendant: &DaHeader,
+	) -> Result<(Vec<u8>, LeafProof)> {
+		// Leaf with the ancestor hash is appended in the block after the ancestor, and the MMR root in the
+		// descendant header commits to the leaves up to the descendant, inclusive
+		let data = (vec![ancestor.number + 1], Some(descendant.number)).encode();
+		let result = self
+			.api
+			.call(GENERATE_PROOF, data, Some(descendant.header_hash()))
+			.await?;
+		// Runtime returns `Result<(Vec<EncodableOpaqueLeaf>, LeafProof), mmr::Error>`
+		let result = Result::<(Vec<Vec<u8>>, LeafProof), u8>::decode(&mut &result[..])
+			.wrap_err("Cannot decode MMR proof")?;
+		let (mut leaves, proof) =
+			result.map_err(|error| eyre!("MMR proof generation failed with error {error}"))?;
+		if leaves.len() != 1 {
+			return Err(eyre!("Expected single MMR leaf, received {}", leaves.len()));
+		}
+		Ok((leaves.remove(0), proof))
+	}
+
+	/// Returns proof that the ancestor is an ancestor of the descendant, or `None` if it is not.
+	pub async fn prove(
+		&self,
+		ancestor: &BlockRef,
+		descendant: &DaHeader,
+	) -> Result<Option<AncestryProof>> {
+		if ancestor.number > descendant.number {
+			return Ok(None);
+		}
+		match self.local_chain(ancestor, descendant)? {
+			LocalChain::Complete(headers) => return Ok(Some(AncestryProof::HeaderChain(headers))),
+			LocalChain::Forked => return Ok(None),
+			LocalChain::Incomplete => {
+				debug!(
+					ancestor = ancestor.number,
+					descendant = descendant.number,
+					"Local headers are incomplete, using MMR proof"
+				);
+			},
+		}
+		let (leaf, proof) = self.mmr_proof(ancestor, descendant).await?;
+		let decoded = MmrLeaf::from_opaque(&leaf)?;
+		if decoded.parent_hash() != ancestor.hash {
+			return Ok(None);
+		}
+		let proof = AncestryProof::Mmr {
+			descendant: descendant.clone(),
+			leaf,
+			proof,
+		};
+		proof
+			.verify(ancestor, descendant.header_hash())
+			.wrap_err("Generated MMR proof is invalid")?;
+		Ok(Some(proof))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		beefy::{ConsensusLog, BEEFY_ENGINE_ID},
+		data::mem_db::MemoryDB,
+		eth_bridge::keccak256,
+		fee::MockRuntimeApi,
+		mmr::BeefyNextAuthoritySet,
+	};
+	use avail_subxt::{
+		api::runtime_types::avail_core::{
+			data_lookup::compact::CompactDataLookup,
+			header::extension::{v3, HeaderExtension},
+			kate_commitment::v3::KateCommitment,
+		},
+		config::substrate::{Digest, DigestItem},
+	};
+
+	fn header(number: u32, parent_hash: H256, logs: Vec<DigestItem>) -> DaHeader {
+		DaHeader {
+			parent_hash,
+			number,
+			state_root: H256::zero(),
+			extrinsics_root: H256::zero(),
+			digest: Digest { logs },
+			extension: HeaderExtension::V3(v3::HeaderExtension {
+				commitment: KateCommitment {
+					rows: 1,
+					cols: 4,
+					data_root: H256::zero(),
+					commitment: vec![0; 48],
+				},
+				app_lookup: CompactDataLookup {
+					size: 1,
+					index: vec![],
+				},
+			}),
+		}
+	}
+
+	fn chain(len: u32) -> Vec<DaHeader> {
+		let mut headers = vec![header(0, H256::zero(), vec![])];
+		for number in 1..len {
+			let parent_hash = headers[number as usize - 1].header_hash();
+			headers.push(header(number, parent_hash, vec![]));
+		}
+		headers
+	}
+
+	fn block_ref(header: &DaHeader) -> BlockRef {
+		BlockRef {
+			number: header.number,
+			hash: header.header_hash(),
+		}
+	}
+
+	#[tokio::test]
+	async fn prove_with_local_headers() {
+		let headers = chain(5);
+		let db = MemoryDB::default();
+		for header in &headers[..4] {
+			db.put(Key::BlockHeader(header.number), header.clone())
+				.unwrap();
+		}
+		let ancestry = Ancestry::new(db, MockRuntimeApi::new());
+		let ancestor = block_ref(&headers[1]);
+		let proof = ancestry
+			.prove(&ancestor, &headers[4])
+			.await
+			.unwrap()
+			.unwrap();
+		assert!(matches!(&proof, AncestryProof::HeaderChain(chain) if chain.len() == 4));
+		proof.verify(&ancestor, headers[4].header_hash()).unwrap();
+		assert!(proof
+			.verify(&block_ref(&headers[2]), headers[4].header_hash())
+			.is_err());
+		assert!(proof.verify(&ancestor, headers[3].header_hash()).is_err());
+
+		// Block of the other fork at the ancestor number
+		let other = BlockRef {
+			number: 1,
+			hash: H256::repeat_byte(1),
+		};
+		assert!(ancestry.prove(&other, &headers[4]).await.unwrap().is_none());
+	}
+
+	#[tokio::test]
+	async fn prove_with_mmr() {
+		let genesis = header(0, H256::zero(), vec![]);
+		let leaf = MmrLeaf {
+			version: 0,
+			parent_number_and_hash: (0, genesis.header_hash()),
+			beefy_next_authority_set: BeefyNextAuthoritySet {
+				id: 1,
+				len: 4,
+				keyset_commitment: H256::zero(),
+			},
+			leaf_extra: H256::zero(),
+		}
+		.encode();
+		// MMR of the single leaf
+		let root = keccak256(&leaf);
+		let logs = vec![DigestItem::Consensus(
+			BEEFY_ENGINE_ID,
+			ConsensusLog::MmrRoot(root).encode(),
+		)];
+		let descendant = header(1, genesis.header_hash(), logs);
+		let proof = LeafProof {
+			leaf_indices: vec![0],
+			leaf_count: 1,
+			items: vec![],
+		};
+
+		let mut api = MockRuntimeApi::new();
+		let result = Result::<_, u8>::Ok((vec![leaf.clone()], proof)).encode();
+		// Leaf of the genesis is appended in block 1
+		let expected = (vec![1u32], Some(1u32)).encode();
+		api.expect_call()
+			.withf(move |method, data, _| method == GENERATE_PROOF && *data == expected)
+			.returning(move |_, _, _| {
+				let result = result.clone();
+				Box::pin(async move { Ok(result) })
+			});
+		let ancestry = Ancestry::new(MemoryDB::default(), api);
+		let ancestor = block_ref(&genesis);
+		let proof = ancestry
+			.prove(&ancestor, &descendant)
+			.await
+			.unwrap()
+			.unwrap();
+		assert!(matches!(proof, AncestryProof::Mmr { .. }));
+		proof.verify(&ancestor, descendant.header_hash()).unwrap();
+
+		let other = BlockRef {
+			number: 0,
+			hash: H256::repeat_byte(1),
+		};
+		assert!(ancestry.prove(&other, &descendant).await.unwrap().is_none());
+		assert!(proof.verify(&other, descendant.header_hash()).is_err());
+	}
+}
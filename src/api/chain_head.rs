@@ -0,0 +1,898 @@
+//! Server of the new JSON-RPC spec (`chainHead_v1_*`), so the modern libraries (PAPI, subxt) can connect to the
+//! light client directly.
+//!
+//! # Methods
+//!
+//! * `chainHead_v1_follow` and `chainHead_v1_unfollow` - events of the verified finalized headers, up to
+//!   [`MAX_FOLLOW_SUBSCRIPTIONS`] per connection
+//! * `chainHead_v1_header` - header of the block reported by the follow subscription
+//! * `chainHead_v1_body` - extrinsics of the block, verified against the header extrinsics root
+//! * `chainHead_v1_storage` - `value` and `hash` queries, with values verified against the header state root
+//! * `chainHead_v1_call` - runtime calls, if the executor is configured
+//! * `chainHead_v1_unpin` - accepted, headers are kept in the bounded store instead
+//! * `chainSpec_v1_chainName`, `chainSpec_v1_genesisHash` and `chainSpec_v1_properties` - chain specification
+//!
+//! # Notes
+//!
+//! Light client follows finalized headers, so each new block is reported as the new best and finalized block at
+//! once, and nothing is ever pruned. With `withRuntime`, runtime is fetched with the `Core_version` call, at the
+//! initial block and at the blocks with the `RuntimeEnvironmentUpdated` digest item. Runtime is reported as invalid
+//! if the call fails (e.g. without the executor).
+
+use async_trait::async_trait;
+use avail_subxt::{primitives::Header, utils::H256};
+use codec::{Decode, Encode};
+use color_eyre::{eyre::eyre, Report, Result};
+use futures::{FutureExt, StreamExt};
+use serde_json::{json, Value};
+use sp_core::{blake2_256, Bytes};
+use std::{
+	collections::HashMap,
+	num::NonZeroUsize,
+	sync::{
+		atomic::{AtomicU64, AtomicUsize, Ordering},
+		Arc, Mutex,
+	},
+};
+use tokio::{
+	sync::{broadcast, mpsc},
+	task::JoinHandle,
+};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tracing::{debug, error, warn};
+use warp::{
+	ws::{Message, WebSocket, Ws},
+	Filter, Rejection, Reply,
+};
+
+use crate::{
+	api::v2::optionally,
+	fee::RuntimeApi,
+	header::HeaderHash,
+	network::rpc::Event,
+	runtime_upgrade::has_runtime_environment_updated,
+	verified_rpc::{self, HeaderStore, VerifiedHeaders, VerifiedRpc},
+};
+
+/// Maximum number of the concurrent operations per follow subscription
+const MAX_OPERATIONS: usize = 16;
+
+/// Maximum number of the follow subscriptions per connection, the minimum required by the spec
+pub const MAX_FOLLOW_SUBSCRIPTIONS: usize = 2;
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const LIMIT_REACHED: i64 = -32800;
+const INVALID_BLOCK: i64 = -32801;
+
+/// Chain specification, reported by the `chainSpec_v1_*` methods
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChainSpec {
+	pub name: String,
+	pub genesis_hash: H256,
+	pub properties: Value,
+}
+
+/// Runtime version, as returned by the `Core_version` runtime call
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+struct RuntimeSpec {
+	spec_name: String,
+	impl_name: String,
+	authoring_version: u32,
+	spec_version: u32,
+	impl_version: u32,
+	apis: Vec<([u8; 8], u32)>,
+	transaction_version: u32,
+}
+
+impl RuntimeSpec {
+	fn to_json(&self) -> Value {
+		let apis = self
+			.apis
+			.iter()
+			.map(|(id, version)| (format!("0x{}", hex::encode(id)), json!(version)))
+			.collect::<serde_json::Map<_, _>>();
+		json!({
+			"specName": self.spec_name,
+			"implName": self.impl_name,
+			"specVersion": self.spec_version,
+			"implVersion": self.impl_version,
+			"transactionVersion": self.transaction_version,
+			"apis": apis,
+		})
+	}
+}
+
+#[async_trait]
+pub trait Backend: Send + Sync + 'static {
+	/// Returns the latest verified finalized header
+	fn finalized(&self) -> Option<Header>;
+
+	/// Subscribes to the verified finalized headers
+	fn subscribe(&self) -> broadcast::Receiver<Header>;
+
+	/// Returns verified header with the given hash
+	fn header(&self, hash: H256) -> Option<Header>;
+
+	/// Returns chain specification
+	fn chain_spec(&self) -> ChainSpec;
+
+	/// Returns opaque extrinsics of the block, verified against its extrinsics root
+	async fn body(&self, hash: H256) -> Result<Vec<Vec<u8>>>;
+
+	/// Returns storage value at the block, verified against its state root
+	async fn storage(&self, hash: H256, key: Vec<u8>) -> Result<Option<Vec<u8>>>;
+
+	/// Calls runtime API function with SCALE encoded parameters at the block
+	async fn call(&self, hash: H256, function: &str, parameters: Vec<u8>) -> Result<Vec<u8>>;
+}
+
+/// Backend of the light client verified state, with the optional executor for the runtime calls
+pub struct LightBackend<C: verified_rpc::Client, A: RuntimeApi> {
+	rpc: VerifiedRpc<C, Arc<HeaderStore>>,
+	finalized: Mutex<Option<Header>>,
+	sender: broadcast::Sender<Header>,
+	executor: Option<A>,
+	chain_spec: ChainSpec,
+}
+
+impl<C: verified_rpc::Client, A: RuntimeApi> LightBackend<C, A> {
+	/// Creates backend which serves headers from the shared store of the verified headers
+	pub fn new(
+		rpc: VerifiedRpc<C, Arc<HeaderStore>>,
+		executor: Option<A>,
+		chain_spec: ChainSpec,
+		capacity: NonZeroUsize,
+	) -> Self {
+		let (sender, _) = broadcast::channel(capacity.get());
+		LightBackend {
+			rpc,
+			finalized: Mutex::new(None),
+			sender,
+			executor,
+			chain_spec,
+		}
+	}
+
+	/// Imports the finalized header, which has to be verified by the caller
+	pub fn import_finalized(&self, header: Header) {
+		self.rpc.headers().insert(header.clone());
+		*self.finalized.lock().unwrap() = Some(header.clone());
+		// There may be no follow subscriptions
+		_ = self.sender.send(header);
+	}
+}
+
+#[async_trait]
+impl<C, A> Backend for LightBackend<C, A>
+where
+	C: verified_rpc::Client + Send + Sync + 'static,
+	A: RuntimeApi + Send + Sync + 'static,
+{
+	fn finalized(&self) -> Option<Header> {
+		self.finalized.lock().unwrap().clone()
+	}
+
+	fn subscribe(&self) -> broadcast::Receiver<Header> {
+		self.sender.subscribe()
+	}
+
+	fn header(&self, hash: H256) -> Option<Header> {
+		self.rpc.headers().verified_header(hash)
+	}
+
+	fn chain_spec(&self) -> ChainSpec {
+		self.chain_spec.clone()
+	}
+
+	async fn body(&self, hash: H256) -> Result<Vec<Vec<u8>>> {
+		let block = self.rpc.chain_get_block(hash).await?;
+		Ok(block.extrinsics)
+	}
+
+	async fn storage(&self, hash: H256, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+		self.rpc.state_get_storage(key, hash).await
+	}
+
+	async fn call(&self, hash: H256, function: &str, parameters: Vec<u8>) -> Result<Vec<u8>> {
+		let executor = self
+			.executor
+			.as_ref()
+			.ok_or_else(|| eyre!("Runtime calls are not supported without the executor"))?;
+		executor.call(function, parameters, Some(hash)).await
+	}
+}
+
+/// Imports verified finalized headers into the backend
+pub async fn import_finalized<C, A>(
+	backend: Arc<LightBackend<C, A>>,
+	mut rpc_events: broadcast::Receiver<Event>,
+) where
+	C: verified_rpc::Client,
+	A: RuntimeApi,
+{
+	loop {
+		match rpc_events.recv().await {
+			Ok(Event::HeaderUpdate { header, .. }) => backend.import_finalized(header),
+			Err(broadcast::error::RecvError::Lagged(skipped)) => {
+				warn!(
+					skipped,
+					"Chain head backend lagged behind finalized headers"
+				);
+			},
+			Err(broadcast::error::RecvError::Closed) => return,
+		}
+	}
+}
+
+fn error(code: i64, message: &str) -> Value {
+	json!({ "code": code, "message": message })
+}
+
+/// Returns runtime of the block, in the follow event format
+async fn runtime<B: Backend>(backend: &B, hash: H256) -> Value {
+	let spec = backend
+		.call(hash, "Core_version", vec![])
+		.await
+		.and_then(|output| RuntimeSpec::decode(&mut &output[..]).map_err(Report::from));
+	match spec {
+		Ok(spec) => json!({ "type": "valid", "spec": spec.to_json() }),
+		Err(error) => json!({ "type": "invalid", "error": format!("{error:#}") }),
+	}
+}
+
+struct Follow {
+	task: JoinHandle<()>,
+	operations: Arc<AtomicUsize>,
+}
+
+/// JSON-RPC session of a single connection
+pub struct Session<B: Backend> {
+	backend: Arc<B>,
+	sender: mpsc::UnboundedSender<Value>,
+	follows: Mutex<HashMap<String, Follow>>,
+	next_id: AtomicU64,
+}
+
+impl<B: Backend> Drop for Session<B> {
+	fn drop(&mut self) {
+		for follow in self.follows.lock().unwrap().values() {
+			follow.task.abort();
+		}
+	}
+}
+
+impl<B: Backend> Session<B> {
+	/// Creates session which sends notifications to the sender
+	pub fn new(backend: Arc<B>, sender: mpsc::UnboundedSender<Value>) -> Self {
+		Session {
+			backend,
+			sender,
+			follows: Mutex::new(HashMap::new()),
+			next_id: AtomicU64::new(1),
+		}
+	}
+
+	fn next_id(&self) -> String {
+		self.next_id.fetch_add(1, Ordering::Relaxed).to_string()
+	}
+
+	/// Handles JSON-RPC request, and returns the response
+	pub async fn handle(&self, request: Value) -> Value {
+		let id = request.get("id").cloned().unwrap_or(Value::Null);
+		let method = request
+			.get("method")
+			.and_then(Value::as_str)
+			.unwrap_or_default();
+		let params = match request.get("params") {
+			Some(Value::Array(params)) => params.clone(),
+			_ => vec![],
+		};
+		let result = match method {
+			"chainHead_v1_follow" => self.follow(&params),
+			"chainHead_v1_unfollow" => self.unfollow(&params),
+			"chainHead_v1_header" => self.header(&params),
+			"chainHead_v1_body" => self.body(&params),
+			"chainHead_v1_storage" => self.storage(&params),
+			"chainHead_v1_call" => self.call(&params),
+			"chainHead_v1_unpin" => self.subscription(&params).map(|_| Value::Null),
+			"chainSpec_v1_chainName" => Ok(json!(self.backend.chain_spec().name)),
+			"chainSpec_v1_genesisHash" => Ok(json!(self.backend.chain_spec().genesis_hash)),
+			"chainSpec_v1_properties" => Ok(self.backend.chain_spec().properties),
+			"rpc_methods" => Ok(json!({ "methods": [
+				"chainHead_v1_follow",
+				"chainHead_v1_unfollow",
+				"chainHead_v1_header",
+				"chainHead_v1_body",
+				"chainHead_v1_storage",
+				"chainHead_v1_call",
+				"chainHead_v1_unpin",
+				"chainSpec_v1_chainName",
+				"chainSpec_v1_genesisHash",
+				"chainSpec_v1_properties",
+			]})),
+			_ => Err(error(METHOD_NOT_FOUND, "Method not found")),
+		};
+		match result {
+			Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+			Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+		}
+	}
+
+	fn follow(&self, params: &[Value]) -> Result<Value, Value> {
+		let with_runtime = params.first().and_then(Value::as_bool).unwrap_or(false);
+		let mut follows = self.follows.lock().unwrap();
+		// Stopped subscriptions don't count to the limit
+		follows.retain(|_, follow| !follow.task.is_finished());
+		if follows.len() >= MAX_FOLLOW_SUBSCRIPTIONS {
+			return Err(error(
+				LIMIT_REACHED,
+				"Maximum number of follow subscriptions is reached",
+			));
+		}
+		let subscription = self.next_id();
+		let backend = self.backend.clone();
+		let notify = notifier(self.sender.clone(), subscription.clone());
+		let task = tokio::spawn(async move {
+			// Subscribe first, so no header is missed
+			let mut headers = backend.subscribe();
+			let mut last = match backend.finalized() {
+				Some(header) => header,
+				None => match headers.recv().await {
+					Ok(header) => header,
+					Err(_) => return notify(json!({ "event": "stop" })),
+				},
+			};
+			let hash = last.header_hash();
+			let mut initialized = json!({ "event": "initialized", "finalizedBlockHashes": [hash] });
+			let mut last_runtime = None;
+			if with_runtime {
+				let runtime = runtime(&*backend, hash).await;
+				initialized["finalizedBlockRuntime"] = runtime.clone();
+				last_runtime = Some(runtime);
+			}
+			notify(initialized);
+			loop {
+				let header = match headers.recv().await {
+					Ok(header) => header,
+					Err(error) => {
+						debug!("Stopping chain head subscription: {error}");
+						return notify(json!({ "event": "stop" }));
+					},
+				};
+				if header.number <= last.number {
+					continue;
+				}
+				let hash = header.header_hash();
+				let new_runtime = match last_runtime.as_mut() {
+					Some(last_runtime) if has_runtime_environment_updated(&header) => {
+						let runtime = runtime(&*backend, hash).await;
+						(runtime != *last_runtime).then(|| {
+							*last_runtime = runtime.clone();
+							runtime
+						})
+					},
+					_ => None,
+				};
+				notify(json!({
+					"event": "newBlock",
+					"blockHash": hash,
+					"parentBlockHash": header.parent_hash,
+					"newRuntime": new_runtime,
+				}));
+				notify(json!({ "event": "bestBlockChanged", "bestBlockHash": hash }));
+				notify(json!({
+					"event": "finalized",
+					"finalizedBlockHashes": [hash],
+					"prunedBlockHashes": [],
+				}));
+				last = header;
+			}
+		});
+		let follow = Follow {
+			task,
+			operations: Arc::new(AtomicUsize::new(0)),
+		};
+		follows.insert(subscription.clone(), follow);
+		Ok(json!(subscription))
+	}
+
+	fn subscription(&self, params: &[Value]) -> Result<Option<String>, Value> {
+		let subscription = params
+			.first()
+			.and_then(Value::as_str)
+			.ok_or_else(|| error(INVALID_PARAMS, "Missing follow subscription"))?;
+		let follows = self.follows.lock().unwrap();
+		Ok(follows
+			.contains_key(subscription)
+			.then(|| subscription.to_string()))
+	}
+
+	fn unfollow(&self, params: &[Value]) -> Result<Value, Value> {
+		if let Some(subscription) = self.subscription(params)? {
+			if let Some(follow) = self.follows.lock().unwrap().remove(&subscription) {
+				follow.task.abort();
+			}
+		}
+		Ok(Value::Null)
+	}
+
+	fn block(&self, params: &[Value]) -> Result<H256, Value> {
+		let hash = params
+			.get(1)
+			.and_then(|hash| serde_json::from_value::<H256>(hash.clone()).ok())
+			.ok_or_else(|| error(INVALID_PARAMS, "Invalid block hash"))?;
+		match self.backend.header(hash) {
+			Some(_) => Ok(hash),
+			None => Err(error(
+				INVALID_BLOCK,
+				"Block is not reported by the follow subscription",
+			)),
+		}
+	}
+
+	fn header(&self, params: &[Value]) -> Result<Value, Value> {
+		if self.subscription(params)?.is_none() {
+			return Ok(Value::Null);
+		}
+		let hash = self.block(params)?;
+		let header = self
+			.backend
+			.header(hash)
+			.map(|header| Bytes(header.encode()));
+		Ok(json!(header))
+	}
+
+	/// Starts the operation, returns `None` if the subscription is invalid or the operations limit is reached
+	fn start_operation(&self, subscription: &str) -> Option<(String, Arc<AtomicUsize>)> {
+		let follows = self.follows.lock().unwrap();
+		let operations = follows.get(subscription)?.operations.clone();
+		if operations.fetch_add(1, Ordering::SeqCst) >= MAX_OPERATIONS {
+			operations.fetch_sub(1, Ordering::SeqCst);
+			return None;
+		}
+		Some((self.next_id(), operations))
+	}
+
+	fn body(&self, params: &[Value]) -> Result<Value, Value> {
+		let Some(subscription) = self.subscription(params)? else {
+			return Ok(Value::Null);
+		};
+		let hash = self.block(params)?;
+		let Some((operation_id, operations)) = self.start_operation(&subscription) else {
+			return Ok(json!({ "result": "limitReached" }));
+		};
+
+		let backend = self.backend.clone();
+		let notify = notifier(self.sender.clone(), subscription);
+		let id = operation_id.clone();
+		tokio::spawn(async move {
+			let event = match backend.body(hash).await {
+				Ok(extrinsics) => {
+					// Extrinsics are reported SCALE encoded, with the length prefix
+					let value = extrinsics
+						.iter()
+						.map(|extrinsic| Bytes(extrinsic.encode()))
+						.collect::<Vec<_>>();
+					json!({ "event": "operationBodyDone", "operationId": id, "value": value })
+				},
+				Err(error) => {
+					let error = format!("{error:#}");
+					json!({ "event": "operationError", "operationId": id, "error": error })
+				},
+			};
+			notify(event);
+			operations.fetch_sub(1, Ordering::SeqCst);
+		});
+		Ok(json!({ "result": "started", "operationId": operation_id }))
+	}
+
+	fn storage(&self, params: &[Value]) -> Result<Value, Value> {
+		let Some(subscription) = self.subscription(params)? else {
+			return Ok(Value::Null);
+		};
+		let hash = self.block(params)?;
+		let queries = params
+			.get(2)
+			.and_then(Value::as_array)
+			.ok_or_else(|| error(INVALID_PARAMS, "Invalid storage queries"))?
+			.iter()
+			.map(|query| {
+				let key = serde_json::from_value::<Bytes>(query["key"].clone()).ok()?;
+				let query_type = query["type"].as_str()?.to_string();
+				Some((key.0, query_type))
+			})
+			.collect::<Option<Vec<_>>>()
+			.ok_or_else(|| error(INVALID_PARAMS, "Invalid storage query"))?;
+		let Some((operation_id, operations)) = self.start_operation(&subscription) else {
+			return Ok(json!({ "result": "limitReached" }));
+		};
+
+		let backend = self.backend.clone();
+		let notify = notifier(self.sender.clone(), subscription);
+		let id = operation_id.clone();
+		tokio::spawn(async move {
+			let mut items = vec![];
+			for (key, query_type) in queries {
+				let value = match backend.storage(hash, key.clone()).await {
+					Ok(value) => value,
+					Err(error) => {
+						warn!(?hash, "Chain head storage query failed: {error:#}");
+						let error = format!("{error:#}");
+						notify(
+							json!({ "event": "operationError", "operationId": id, "error": error }),
+						);
+						operations.fetch_sub(1, Ordering::SeqCst);
+						return;
+					},
+				};
+				let Some(value) = value else {
+					continue;
+				};
+				let key = Bytes(key);
+				match query_type.as_str() {
+					"value" => items.push(json!({ "key": key, "value": Bytes(value) })),
+					"hash" => items.push(json!({ "key": key, "hash": H256(blake2_256(&value)) })),
+					query_type => {
+						let error = format!("Unsupported storage query type {query_type}");
+						notify(
+							json!({ "event": "operationError", "operationId": id, "error": error }),
+						);
+						operations.fetch_sub(1, Ordering::SeqCst);
+						return;
+					},
+				}
+			}
+			if !items.is_empty() {
+				notify(
+					json!({ "event": "operationStorageItems", "operationId": id, "items": items }),
+				);
+			}
+			notify(json!({ "event": "operationStorageDone", "operationId": id }));
+			operations.fetch_sub(1, Ordering::SeqCst);
+		});
+		Ok(json!({ "result": "started", "operationId": operation_id }))
+	}
+
+	fn call(&self, params: &[Value]) -> Result<Value, Value> {
+		let Some(subscription) = self.subscription(params)? else {
+			return Ok(Value::Null);
+		};
+		let hash = self.block(params)?;
+		let function = params
+			.get(2)
+			.and_then(Value::as_str)
+			.ok_or_else(|| error(INVALID_PARAMS, "Invalid function"))?
+			.to_string();
+		let parameters = params
+			.get(3)
+			.and_then(|parameters| serde_json::from_value::<Bytes>(parameters.clone()).ok())
+			.ok_or_else(|| error(INVALID_PARAMS, "Invalid call parameters"))?;
+		let Some((operation_id, operations)) = self.start_operation(&subscription) else {
+			return Ok(json!({ "result": "limitReached" }));
+		};
+
+		let backend = self.backend.clone();
+		let notify = notifier(self.sender.clone(), subscription);
+		let id = operation_id.clone();
+		tokio::spawn(async move {
+			let event = match backend.call(hash, &function, parameters.0).await {
+				Ok(output) => {
+					json!({ "event": "operationCallDone", "operationId": id, "output": Bytes(output) })
+				},
+				Err(error) => {
+					let error = format!("{error:#}");
+					json!({ "event": "operationError", "operationId": id, "error": error })
+				},
+			};
+			notify(event);
+			operations.fetch_sub(1, Ordering::SeqCst);
+		});
+		Ok(json!({ "result": "started", "operationId": operation_id }))
+	}
+}
+
+/// Returns function which sends follow event notifications of the subscription
+fn notifier(
+	sender: mpsc::UnboundedSender<Value>,
+	subscription: String,
+) -> impl Fn(Value) + Send + 'static {
+	move |event| {
+		let notification = json!({
+			"jsonrpc": "2.0",
+			"method": "chainHead_v1_followEvent",
+			"params": { "subscription": subscription, "result": event },
+		});
+		// Connection may be closed already
+		_ = sender.send(notification);
+	}
+}
+
+/// Serves JSON-RPC requests of the WebSocket connection
+pub async fn connect<B: Backend>(backend: Arc<B>, web_socket: WebSocket) {
+	let (web_socket_sender, mut web_socket_receiver) = web_socket.split();
+	let (sender, receiver) = mpsc::unbounded_channel::<Value>();
+	let messages = UnboundedReceiverStream::new(receiver)
+		.map(|message| Ok(Message::text(message.to_string())));
+	tokio::task::spawn(messages.forward(web_socket_sender).map(|result| {
+		if let Err(error) = result {
+			error!("Error sending web socket message: {error}");
+		}
+	}));
+
+	let session = Session::new(backend, sender.clone());
+	while let Some(result) = web_socket_receiver.next().await {
+		let message = match result {
+			Err(error) => {
+				error!("Error receiving client message: {error}");
+				continue;
+			},
+			Ok(message) if !message.is_text() => continue,
+			Ok(message) => message,
+		};
+		let response = match serde_json::from_slice::<Value>(message.as_bytes()) {
+			Ok(request) => session.handle(request).await,
+			Err(error) => json!({ "jsonrpc": "2.0", "id": null, "error": error_message(&error) }),
+		};
+		if sender.send(response).is_err() {
+			break;
+		}
+	}
+}
+
+fn error_message(error: &serde_json::Error) -> Value {
+	error(-32700, &format!("Parse error: {error}"))
+}
+
+/// Route of the chain head JSON-RPC server, at the `/rpc` path, if the backend is configured
+pub fn route<B: Backend>(
+	backend: Option<Arc<B>>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+	warp::path!("rpc")
+		.and_then(move || optionally(backend.clone()))
+		.and(warp::ws())
+		.map(|backend: Arc<B>, ws: Ws| {
+			ws.on_upgrade(move |web_socket| connect(backend, web_socket))
+		})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{block_builder::extrinsics_root, simulation};
+
+	struct TestBackend {
+		headers: HeaderStore,
+		sender: broadcast::Sender<Header>,
+		storage: HashMap<Vec<u8>, Vec<u8>>,
+	}
+
+	#[async_trait]
+	impl Backend for TestBackend {
+		fn finalized(&self) -> Option<Header> {
+			None
+		}
+
+		fn subscribe(&self) -> broadcast::Receiver<Header> {
+			self.sender.subscribe()
+		}
+
+		fn header(&self, hash: H256) -> Option<Header> {
+			self.headers.verified_header(hash)
+		}
+
+		fn chain_spec(&self) -> ChainSpec {
+			ChainSpec {
+				name: "Avail Test".to_string(),
+				..Default::default()
+			}
+		}
+
+		async fn body(&self, _: H256) -> Result<Vec<Vec<u8>>> {
+			Ok(extrinsics())
+		}
+
+		async fn storage(&self, _: H256, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+			Ok(self.storage.get(&key).cloned())
+		}
+
+		async fn call(&self, _: H256, function: &str, _: Vec<u8>) -> Result<Vec<u8>> {
+			match function {
+				"Core_version" => Ok(runtime_spec().encode()),
+				_ => Err(eyre!("Executor is not configured")),
+			}
+		}
+	}
+
+	fn runtime_spec() -> RuntimeSpec {
+		RuntimeSpec {
+			spec_name: "avail".to_string(),
+			impl_name: "avail".to_string(),
+			authoring_version: 1,
+			spec_version: 12,
+			impl_version: 0,
+			apis: vec![([1; 8], 4)],
+			transaction_version: 1,
+		}
+	}
+
+	fn extrinsics() -> Vec<Vec<u8>> {
+		vec![vec![1, 2], vec![3]]
+	}
+
+	fn session() -> (
+		Session<TestBackend>,
+		Arc<TestBackend>,
+		mpsc::UnboundedReceiver<Value>,
+	) {
+		let backend = Arc::new(TestBackend {
+			headers: HeaderStore::new(NonZeroUsize::new(8).unwrap()),
+			sender: broadcast::channel(8).0,
+			storage: HashMap::from([(vec![1], vec![42])]),
+		});
+		let (sender, receiver) = mpsc::unbounded_channel();
+		(Session::new(backend.clone(), sender), backend, receiver)
+	}
+
+	async fn request(session: &Session<TestBackend>, method: &str, params: Value) -> Value {
+		let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+		session.handle(request).await
+	}
+
+	async fn event(receiver: &mut mpsc::UnboundedReceiver<Value>) -> Value {
+		receiver.recv().await.unwrap()["params"]["result"].clone()
+	}
+
+	/// Starts the follow subscription, and imports headers 1 and 2
+	async fn follow(
+		session: &Session<TestBackend>,
+		backend: &TestBackend,
+		receiver: &mut mpsc::UnboundedReceiver<Value>,
+	) -> (Value, Vec<H256>) {
+		follow_with_runtime(session, backend, receiver, false).await
+	}
+
+	async fn follow_with_runtime(
+		session: &Session<TestBackend>,
+		backend: &TestBackend,
+		receiver: &mut mpsc::UnboundedReceiver<Value>,
+		with_runtime: bool,
+	) -> (Value, Vec<H256>) {
+		let subscription =
+			request(session, "chainHead_v1_follow", json!([with_runtime])).await["result"].clone();
+		// Wait for the follow task to subscribe
+		tokio::task::yield_now().await;
+		let first = simulation::header(1, H256::zero(), 0);
+		let mut second = simulation::header(2, first.header_hash(), 0);
+		second.extrinsics_root = extrinsics_root(&extrinsics());
+		let hashes = [&first, &second]
+			.map(|header| header.header_hash())
+			.to_vec();
+		for header in [first, second] {
+			backend.headers.insert(header.clone());
+			while backend.sender.send(header.clone()).is_err() {
+				tokio::task::yield_now().await;
+			}
+		}
+		let initialized = event(receiver).await;
+		assert_eq!(initialized["event"], "initialized");
+		assert_eq!(
+			initialized.get("finalizedBlockRuntime").is_some(),
+			with_runtime
+		);
+		(subscription, hashes)
+	}
+
+	#[tokio::test]
+	async fn follow_events() {
+		let (session, backend, mut receiver) = session();
+		let (subscription, hashes) = follow(&session, &backend, &mut receiver).await;
+		let new_block = event(&mut receiver).await;
+		assert_eq!(new_block["event"], "newBlock");
+		assert_eq!(new_block["blockHash"], json!(hashes[1]));
+		assert_eq!(new_block["parentBlockHash"], json!(hashes[0]));
+		assert_eq!(event(&mut receiver).await["event"], "bestBlockChanged");
+		assert_eq!(
+			event(&mut receiver).await["finalizedBlockHashes"],
+			json!([hashes[1]])
+		);
+
+		let header = request(
+			&session,
+			"chainHead_v1_header",
+			json!([subscription, hashes[1]]),
+		)
+		.await;
+		assert!(header["result"].is_string());
+		let unknown = request(
+			&session,
+			"chainHead_v1_header",
+			json!([subscription, H256::zero()]),
+		)
+		.await;
+		assert_eq!(unknown["error"]["code"], INVALID_BLOCK);
+		let stale = request(&session, "chainHead_v1_header", json!(["stale", hashes[1]])).await;
+		assert_eq!(stale["result"], Value::Null);
+		let method = request(&session, "chainHead_v1_stop", json!([subscription])).await;
+		assert_eq!(method["error"]["code"], METHOD_NOT_FOUND);
+	}
+
+	#[tokio::test]
+	async fn follow_with_runtime_and_limit() {
+		let (session, backend, mut receiver) = session();
+		let (subscription, _) = follow_with_runtime(&session, &backend, &mut receiver, true).await;
+		let runtime = runtime(&*backend, H256::zero()).await;
+		assert_eq!(runtime["type"], "valid");
+		assert_eq!(runtime["spec"]["specVersion"], 12);
+		assert_eq!(runtime["spec"]["apis"]["0x0101010101010101"], 4);
+		// Runtime is reported only if the runtime environment is updated
+		assert_eq!(event(&mut receiver).await["newRuntime"], Value::Null);
+
+		request(&session, "chainHead_v1_follow", json!([false])).await;
+		let limited = request(&session, "chainHead_v1_follow", json!([false])).await;
+		assert_eq!(limited["error"]["code"], LIMIT_REACHED);
+		request(&session, "chainHead_v1_unfollow", json!([subscription])).await;
+		let follow = request(&session, "chainHead_v1_follow", json!([false])).await;
+		assert!(follow["result"].is_string());
+	}
+
+	#[tokio::test]
+	async fn chain_spec() {
+		let (session, _, _) = session();
+		let name = request(&session, "chainSpec_v1_chainName", json!([])).await;
+		assert_eq!(name["result"], "Avail Test");
+		let genesis_hash = request(&session, "chainSpec_v1_genesisHash", json!([])).await;
+		assert_eq!(genesis_hash["result"], json!(H256::zero()));
+	}
+
+	#[tokio::test]
+	async fn operations() {
+		let (session, backend, mut receiver) = session();
+		let (subscription, hashes) = follow(&session, &backend, &mut receiver).await;
+		for _ in 0..3 {
+			event(&mut receiver).await;
+		}
+
+		let queries =
+			json!([{ "key": "0x01", "type": "value" }, { "key": "0x02", "type": "value" }]);
+		let params = json!([subscription, hashes[1], queries, null]);
+		let started = request(&session, "chainHead_v1_storage", params).await;
+		assert_eq!(started["result"]["result"], "started");
+		let items = event(&mut receiver).await;
+		assert_eq!(items["event"], "operationStorageItems");
+		assert_eq!(items["items"], json!([{ "key": "0x01", "value": "0x2a" }]));
+		assert_eq!(event(&mut receiver).await["event"], "operationStorageDone");
+
+		let params = json!([subscription, hashes[1], "Metadata_metadata", "0x"]);
+		let started = request(&session, "chainHead_v1_call", params).await;
+		assert_eq!(started["result"]["result"], "started");
+		assert_eq!(event(&mut receiver).await["event"], "operationError");
+
+		let params = json!([subscription, hashes[1]]);
+		let started = request(&session, "chainHead_v1_body", params).await;
+		assert_eq!(started["result"]["result"], "started");
+		let body = event(&mut receiver).await;
+		assert_eq!(body["event"], "operationBodyDone");
+		assert_eq!(body["value"], json!(["0x080102", "0x0403"]));
+	}
+
+	#[tokio::test]
+	async fn body_matches_extrinsics_root() {
+		let (session, backend, mut receiver) = session();
+		let (subscription, hashes) = follow(&session, &backend, &mut receiver).await;
+		for _ in 0..3 {
+			event(&mut receiver).await;
+		}
+
+		let params = json!([subscription, hashes[1]]);
+		request(&session, "chainHead_v1_body", params).await;
+		let body = event(&mut receiver).await;
+		let value: Vec<Bytes> = serde_json::from_value(body["value"].clone()).unwrap();
+		let extrinsics = value
+			.iter()
+			.map(|extrinsic| Vec::<u8>::decode(&mut &extrinsic[..]).unwrap())
+			.collect::<Vec<_>>();
+		let header = backend.header(hashes[1]).unwrap();
+		assert_eq!(extrinsics_root(&extrinsics), header.extrinsics_root);
+	}
+}
@@ -0,0 +1,338 @@
+//! Server of the Kate JSON-RPC methods, so downstream light clients can sample blocks from the nodes built on this
+//! crate, the same way they sample from the full nodes.
+//!
+//! # Methods
+//!
+//! * `kate_queryRows` - rows of the extended matrix, with `null` for rows which are not available
+//! * `kate_queryProof` - concatenated cells with proofs, in the same order as requested positions
+//! * `kate_blockLength` - maximum block length and matrix dimensions
+//!
+//! # Notes
+//!
+//! Cells and rows are served from the Kademlia store (inserted by the fat client, or after the rows
+//! reconstruction), so they are verified before they are served. `kate_queryProof` fails if any of the requested
+//! cells is not available, since the response has no way to express missing cells.
+
+use async_trait::async_trait;
+use avail_subxt::{primitives::Header, utils::H256};
+use color_eyre::{Report, Result};
+use kate_recovery::{
+	data::Cell,
+	matrix::{Dimensions, Position},
+};
+use mockall::automock;
+use serde_json::{json, Value};
+use std::{convert::Infallible, sync::Arc};
+use tracing::warn;
+use warp::{Filter, Rejection, Reply};
+
+use crate::{
+	api::v2::optionally,
+	constants::BlockLength,
+	network::{p2p::Client as P2pClient, rpc::CELL_WITH_PROOF_SIZE},
+	utils::extract_kate,
+	verified_rpc::VerifiedHeaders,
+};
+
+/// Size of the matrix chunk on Avail runtimes
+const CHUNK_SIZE: u32 = 32;
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+#[async_trait]
+#[automock]
+pub trait Backend: Send + Sync + 'static {
+	/// Returns verified header with the given hash
+	fn header(&self, block_hash: H256) -> Option<Header>;
+
+	/// Returns block length limits of the runtime
+	fn block_length(&self) -> BlockLength;
+
+	/// Returns available cells with proofs
+	async fn cells(&self, block_number: u32, positions: &[Position]) -> Result<Vec<Cell>>;
+
+	/// Returns rows, in the same order as requested, with `None` for rows which are not available
+	async fn rows(
+		&self,
+		block_number: u32,
+		dimensions: Dimensions,
+		rows: &[u32],
+	) -> Result<Vec<Option<Vec<u8>>>>;
+}
+
+/// Backend which serves cells and rows from the Kademlia store
+pub struct P2pBackend<H: VerifiedHeaders> {
+	p2p_client: P2pClient,
+	headers: H,
+	block_length: BlockLength,
+}
+
+impl<H: VerifiedHeaders> P2pBackend<H> {
+	pub fn new(p2p_client: P2pClient, headers: H, block_length: BlockLength) -> Self {
+		P2pBackend {
+			p2p_client,
+			headers,
+			block_length,
+		}
+	}
+}
+
+#[async_trait]
+impl<H: VerifiedHeaders + Send + Sync + 'static> Backend for P2pBackend<H> {
+	fn header(&self, block_hash: H256) -> Option<Header> {
+		self.headers.verified_header(block_hash)
+	}
+
+	fn block_length(&self) -> BlockLength {
+		self.block_length
+	}
+
+	async fn cells(&self, block_number: u32, positions: &[Position]) -> Result<Vec<Cell>> {
+		let (cells, _) = self
+			.p2p_client
+			.fetch_cells_from_dht(block_number, positions)
+			.await;
+		Ok(cells)
+	}
+
+	async fn rows(
+		&self,
+		block_number: u32,
+		dimensions: Dimensions,
+		rows: &[u32],
+	) -> Result<Vec<Option<Vec<u8>>>> {
+		let fetched = self
+			.p2p_client
+			.fetch_rows_from_dht(block_number, dimensions, rows)
+			.await;
+		Ok(rows
+			.iter()
+			.map(|&row| fetched.get(row as usize).cloned().flatten())
+			.collect())
+	}
+}
+
+fn error(code: i64, message: &str) -> Value {
+	json!({ "code": code, "message": message })
+}
+
+fn invalid_params(message: &str) -> Value {
+	error(INVALID_PARAMS, message)
+}
+
+/// Returns verified header and matrix dimensions of the block
+fn block(
+	backend: &impl Backend,
+	params: &[Value],
+	index: usize,
+) -> Result<(Header, Dimensions), Value> {
+	let block_hash = params
+		.get(index)
+		.and_then(|hash| serde_json::from_value::<H256>(hash.clone()).ok())
+		.ok_or_else(|| invalid_params("Invalid block hash"))?;
+	let header = backend
+		.header(block_hash)
+		.ok_or_else(|| invalid_params("Block is not verified"))?;
+	let (rows, cols, _, _) = extract_kate(&header.extension);
+	let dimensions =
+		Dimensions::new(rows, cols).ok_or_else(|| invalid_params("Block has no data matrix"))?;
+	Ok((header, dimensions))
+}
+
+async fn query_rows(backend: &impl Backend, params: &[Value]) -> Result<Value, Value> {
+	let rows = params
+		.first()
+		.and_then(|rows| serde_json::from_value::<Vec<u32>>(rows.clone()).ok())
+		.ok_or_else(|| invalid_params("Invalid rows"))?;
+	let (header, dimensions) = block(backend, params, 1)?;
+	if rows.iter().any(|&row| row >= dimensions.extended_rows()) {
+		return Err(invalid_params("Row index is out of the matrix bounds"));
+	}
+	let rows = backend
+		.rows(header.number, dimensions, &rows)
+		.await
+		.map_err(|error| error_response(&error))?;
+	Ok(json!(rows))
+}
+
+fn position(value: &Value) -> Option<Position> {
+	let row = value.get("row")?.as_u64()?.try_into().ok()?;
+	let col = value.get("col")?.as_u64()?.try_into().ok()?;
+	Some(Position { row, col })
+}
+
+async fn query_proof(backend: &impl Backend, params: &[Value]) -> Result<Value, Value> {
+	let positions = params
+		.first()
+		.and_then(Value::as_array)
+		.and_then(|positions| positions.iter().map(position).collect::<Option<Vec<_>>>())
+		.ok_or_else(|| invalid_params("Invalid positions"))?;
+	let (header, dimensions) = block(backend, params, 1)?;
+	if positions.iter().any(|&position| {
+		position.row >= dimensions.extended_rows() || position.col >= dimensions.cols().get()
+	}) {
+		return Err(invalid_params("Position is out of the matrix bounds"));
+	}
+	let cells = backend
+		.cells(header.number, &positions)
+		.await
+		.map_err(|error| error_response(&error))?;
+	let mut proofs = Vec::with_capacity(positions.len() * CELL_WITH_PROOF_SIZE);
+	for position in &positions {
+		let cell = cells
+			.iter()
+			.find(|cell| cell.position == *position)
+			.ok_or_else(|| error(INTERNAL_ERROR, "Cells are not available"))?;
+		proofs.extend_from_slice(&cell.content);
+	}
+	Ok(json!(proofs))
+}
+
+fn block_length(backend: &impl Backend) -> Result<Value, Value> {
+	let BlockLength {
+		normal,
+		operational,
+		mandatory,
+		rows,
+		cols,
+	} = backend.block_length();
+	let (Some(rows), Some(cols)) = (rows, cols) else {
+		return Err(error(INTERNAL_ERROR, "Runtime has no data matrix"));
+	};
+	Ok(json!({
+		"max": { "normal": normal, "operational": operational, "mandatory": mandatory },
+		"cols": cols,
+		"rows": rows,
+		"chunkSize": CHUNK_SIZE,
+	}))
+}
+
+fn error_response(error: &Report) -> Value {
+	warn!("Kate RPC request failed: {error:#}");
+	error(INTERNAL_ERROR, &format!("{error:#}"))
+}
+
+/// Handles JSON-RPC request, and returns the response
+pub async fn handle(backend: &impl Backend, request: Value) -> Value {
+	let id = request.get("id").cloned().unwrap_or(Value::Null);
+	let method = request
+		.get("method")
+		.and_then(Value::as_str)
+		.unwrap_or_default();
+	let params = match request.get("params") {
+		Some(Value::Array(params)) => params.clone(),
+		_ => vec![],
+	};
+	let result = match method {
+		"kate_queryRows" => query_rows(backend, &params).await,
+		"kate_queryProof" => query_proof(backend, &params).await,
+		"kate_blockLength" => block_length(backend),
+		_ => Err(error(METHOD_NOT_FOUND, "Method not found")),
+	};
+	match result {
+		Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+		Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+	}
+}
+
+async fn handle_request<B: Backend>(
+	backend: Arc<B>,
+	request: Value,
+) -> Result<impl Reply, Infallible> {
+	Ok(warp::reply::json(&handle(backend.as_ref(), request).await))
+}
+
+/// Route of the Kate JSON-RPC server, at the `/kate` path, if the backend is configured
+pub fn route<B: Backend>(
+	backend: Option<Arc<B>>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+	warp::post()
+		.and(warp::path!("kate"))
+		.and_then(move || optionally(backend.clone()))
+		.and(warp::body::json())
+		.and_then(handle_request)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{header::HeaderHash, simulation};
+	use color_eyre::eyre::eyre;
+
+	fn backend() -> (MockBackend, H256) {
+		let header = simulation::header(1, H256::zero(), 0);
+		let hash = header.header_hash();
+		let mut backend = MockBackend::new();
+		backend
+			.expect_header()
+			.returning(move |block_hash| (block_hash == hash).then(|| header.clone()));
+		(backend, hash)
+	}
+
+	async fn request(backend: &MockBackend, method: &str, params: Value) -> Value {
+		let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+		handle(backend, request).await
+	}
+
+	#[tokio::test]
+	async fn query_rows_and_proofs() {
+		let (mut backend, hash) = backend();
+		backend.expect_rows().returning(|_, _, rows| {
+			let rows = rows
+				.iter()
+				.map(|&row| (row == 0).then(|| vec![1; 128]))
+				.collect();
+			Box::pin(async move { Ok(rows) })
+		});
+		let cell = Cell {
+			position: Position { row: 1, col: 2 },
+			content: [7; CELL_WITH_PROOF_SIZE],
+		};
+		backend.expect_cells().returning(move |_, _| {
+			let cell = cell.clone();
+			Box::pin(async move { Ok(vec![cell]) })
+		});
+
+		let rows = request(&backend, "kate_queryRows", json!([[0, 1], hash])).await;
+		assert_eq!(rows["result"], json!([vec![1; 128], null]));
+		let out_of_bounds = request(&backend, "kate_queryRows", json!([[2], hash])).await;
+		assert_eq!(out_of_bounds["error"]["code"], INVALID_PARAMS);
+		let unverified = request(&backend, "kate_queryRows", json!([[0], H256::zero()])).await;
+		assert_eq!(unverified["error"]["code"], INVALID_PARAMS);
+
+		let proof = request(
+			&backend,
+			"kate_queryProof",
+			json!([[{ "row": 1, "col": 2 }], hash]),
+		)
+		.await;
+		assert_eq!(proof["result"], json!(vec![7; CELL_WITH_PROOF_SIZE]));
+		let positions = json!([{ "row": 1, "col": 2 }, { "row": 0, "col": 0 }]);
+		let missing = request(&backend, "kate_queryProof", json!([positions, hash])).await;
+		assert_eq!(missing["error"]["code"], INTERNAL_ERROR);
+	}
+
+	#[tokio::test]
+	async fn block_length_and_errors() {
+		let (mut backend, _) = backend();
+		backend.expect_block_length().returning(|| BlockLength {
+			normal: 1024,
+			operational: 2048,
+			mandatory: 2048,
+			rows: Some(256),
+			cols: Some(256),
+		});
+		backend
+			.expect_rows()
+			.returning(|_, _, _| Box::pin(async move { Err(eyre!("Store is not available")) }));
+
+		let length = request(&backend, "kate_blockLength", json!([])).await;
+		assert_eq!(length["result"]["max"]["normal"], 1024);
+		assert_eq!(length["result"]["rows"], 256);
+		assert_eq!(length["result"]["chunkSize"], CHUNK_SIZE);
+		let method = request(&backend, "kate_queryDataProof", json!([0])).await;
+		assert_eq!(method["error"]["code"], METHOD_NOT_FOUND);
+	}
+}
@@ -1,3 +1,5 @@
+pub mod chain_head;
+pub mod kate;
 pub mod server;
 mod v1;
 pub mod v2;
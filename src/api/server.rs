@@ -7,15 +7,19 @@
 //! * `/v1/latest_block` - returns latest processed block
 //! * `/v1/confidence/{block_number}` - returns calculated confidence for a given block number
 //! * `/v1/appdata/{block_number}` - returns decoded extrinsic data for configured app_id and given block number
+//! * `/rpc` - chain head JSON-RPC server (see [`chain_head`]), if enabled
+//! * `/kate` - Kate JSON-RPC server (see [`kate`]), if enabled
 
 use crate::api::v2;
 use crate::data::Database;
 use crate::shutdown::Controller;
 use crate::types::IdentityConfig;
 use crate::{
-	api::v1,
+	api::{chain_head, kate, v1},
 	network::rpc::{self},
+	sampling::SamplingPolicy,
 	types::{RuntimeConfig, State},
+	verified_rpc::HeaderStore,
 };
 use color_eyre::eyre::WrapErr;
 use futures::{Future, FutureExt};
@@ -37,6 +41,8 @@ pub struct Server<T: Database> {
 	pub node_client: rpc::Client,
 	pub ws_clients: v2::types::WsClients,
 	pub shutdown: Controller<String>,
+	pub chain_head: Option<Arc<chain_head::LightBackend<rpc::Client, rpc::Client>>>,
+	pub kate: Option<Arc<kate::P2pBackend<Arc<HeaderStore>>>>,
 }
 
 fn health_route() -> impl Filter<Extract = impl Reply, Error = warp::Rejection> + Clone {
@@ -56,7 +62,8 @@ impl<T: Database + Clone + Send + Sync + 'static> Server<T> {
 			..
 		} = self.cfg.clone();
 
-		let v1_api = v1::routes(self.db.clone(), app_id, self.state.clone());
+		let sampling = SamplingPolicy::from(&self.cfg);
+		let v1_api = v1::routes(self.db.clone(), app_id, self.state.clone(), sampling);
 		let v2_api = v2::routes(
 			self.version.clone(),
 			self.network_version.clone(),
@@ -73,7 +80,15 @@ impl<T: Database + Clone + Send + Sync + 'static> Server<T> {
 			.allow_header("content-type")
 			.allow_methods(vec!["GET", "POST", "DELETE"]);
 
-		let routes = health_route().or(v1_api).or(v2_api).with(cors);
+		let chain_head_api = chain_head::route(self.chain_head.clone());
+		let kate_api = kate::route(self.kate.clone());
+
+		let routes = health_route()
+			.or(v1_api)
+			.or(v2_api)
+			.or(chain_head_api)
+			.or(kate_api)
+			.with(cors);
 
 		let addr = SocketAddr::from_str(format!("{host}:{port}").as_str())
 			.wrap_err("Unable to parse host address from config")
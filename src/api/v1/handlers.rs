@@ -1,9 +1,13 @@
-use super::types::{AppDataQuery, ClientResponse, ConfidenceResponse, LatestBlockResponse, Status};
+use super::types::{
+	AppDataQuery, AvailabilityResponse, ClientResponse, ConfidenceResponse, LatestBlockResponse,
+	Status,
+};
 use crate::{
 	api::v1::types::{Extrinsics, ExtrinsicsDataResponse},
+	backfill, compression,
 	data::{Database, Key},
+	sampling::SamplingPolicy,
 	types::{Mode, OptionBlockRange, State},
-	utils::calculate_confidence,
 };
 use avail_subxt::{
 	api::runtime_types::{da_control::pallet::Call, da_runtime::RuntimeCall},
@@ -31,11 +35,12 @@ pub fn confidence(
 	block_num: u32,
 	db: impl Database,
 	state: Arc<Mutex<State>>,
+	sampling: SamplingPolicy,
 ) -> ClientResponse<ConfidenceResponse> {
 	info!("Got request for confidence for block {block_num}");
 	let res = match db.get(Key::VerifiedCellCount(block_num)) {
 		Ok(Some(count)) => {
-			let confidence = calculate_confidence(count);
+			let confidence = sampling.confidence.confidence(count);
 			let serialised_confidence = serialised_confidence(block_num, confidence);
 			ClientResponse::Normal(ConfidenceResponse {
 				block: block_num,
@@ -62,10 +67,30 @@ pub fn confidence(
 	res
 }
 
+pub fn availability(
+	block_num: u32,
+	db: impl Database,
+	sampling: SamplingPolicy,
+) -> ClientResponse<AvailabilityResponse> {
+	info!("Got request for availability of block {block_num}");
+	let res = match backfill::availability(&db, block_num) {
+		Ok(Some(availability)) => ClientResponse::Normal(AvailabilityResponse {
+			block: block_num,
+			available: availability.is_available(),
+			confidence: sampling.confidence.confidence(availability.verified),
+		}),
+		Ok(None) => ClientResponse::NotFound,
+		Err(e) => ClientResponse::Error(e),
+	};
+	info!("Returning availability: {res:?}");
+	res
+}
+
 pub fn status(
 	app_id: Option<u32>,
 	state: Arc<Mutex<State>>,
 	db: impl Database,
+	sampling: SamplingPolicy,
 ) -> ClientResponse<Status> {
 	let state = state.lock().unwrap();
 	let Some(last) = state.confidence_achieved.last() else {
@@ -73,7 +98,7 @@ pub fn status(
 	};
 	let res = match db.get(Key::VerifiedCellCount(last)) {
 		Ok(Some(count)) => {
-			let confidence = calculate_confidence(count);
+			let confidence = sampling.confidence.confidence(count);
 			ClientResponse::Normal(Status {
 				block_num: last,
 				confidence,
@@ -145,12 +170,18 @@ pub fn appdata(
 						RuntimeCall::DataAvailability(Call::submit_data { data, .. }) => Some(data),
 						_ => None,
 					})
-					.map(|data| general_purpose::STANDARD.encode(data.0.as_slice()))
-					.collect::<Vec<_>>();
-				ClientResponse::Normal(ExtrinsicsDataResponse {
-					block: block_num,
-					extrinsics: Extrinsics::Decoded(xts),
-				})
+					.map(|data| {
+						compression::decompress(&data.0)
+							.map(|data| general_purpose::STANDARD.encode(data.as_slice()))
+					})
+					.collect::<Result<Vec<_>>>();
+				match xts {
+					Ok(xts) => ClientResponse::Normal(ExtrinsicsDataResponse {
+						block: block_num,
+						extrinsics: Extrinsics::Decoded(xts),
+					}),
+					Err(error) => ClientResponse::Error(error),
+				}
 			}
 		},
 
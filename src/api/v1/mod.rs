@@ -1,4 +1,4 @@
-use crate::{data::Database, types::State};
+use crate::{data::Database, sampling::SamplingPolicy, types::State};
 
 use self::types::AppDataQuery;
 use std::{
@@ -28,10 +28,17 @@ fn with_app_id(
 	warp::any().map(move || app_id)
 }
 
+fn with_sampling(
+	sampling: SamplingPolicy,
+) -> impl Filter<Extract = (SamplingPolicy,), Error = Infallible> + Clone {
+	warp::any().map(move || sampling.clone())
+}
+
 pub fn routes(
 	db: impl Database + Clone + Send,
 	app_id: Option<u32>,
 	state: Arc<Mutex<State>>,
+	sampling: SamplingPolicy,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
 	let mode = warp::path!("v1" / "mode")
 		.and(with_app_id(app_id))
@@ -44,8 +51,14 @@ pub fn routes(
 	let confidence = warp::path!("v1" / "confidence" / u32)
 		.and(with_db(db.clone()))
 		.and(with_state(state.clone()))
+		.and(with_sampling(sampling.clone()))
 		.map(handlers::confidence);
 
+	let availability = warp::path!("v1" / "availability" / u32)
+		.and(with_db(db.clone()))
+		.and(with_sampling(sampling.clone()))
+		.map(handlers::availability);
+
 	let appdata = (warp::path!("v1" / "appdata" / u32))
 		.and(warp::query::<AppDataQuery>())
 		.and(with_db(db.clone()))
@@ -57,7 +70,14 @@ pub fn routes(
 		.and(with_app_id(app_id))
 		.and(with_state(state))
 		.and(with_db(db))
+		.and(with_sampling(sampling))
 		.map(handlers::status);
 
-	warp::get().and(mode.or(latest_block).or(confidence).or(appdata).or(status))
+	warp::get().and(
+		mode.or(latest_block)
+			.or(confidence)
+			.or(availability)
+			.or(appdata)
+			.or(status),
+	)
 }
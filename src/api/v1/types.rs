@@ -22,6 +22,13 @@ pub struct ConfidenceResponse {
 	pub serialised_confidence: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AvailabilityResponse {
+	pub block: u32,
+	pub available: bool,
+	pub confidence: f64,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum Extrinsics {
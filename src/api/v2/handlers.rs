@@ -2,17 +2,19 @@ use super::{
 	transactions,
 	types::{
 		block_status, filter_fields, Block, BlockStatus, DataQuery, DataResponse, DataTransaction,
-		Error, FieldsQueryParameter, Header, Status, SubmitResponse, Subscription, SubscriptionId,
-		Transaction, Version, WsClients,
+		Error, FieldsQueryParameter, Header, ReceiptsQuery, ReceiptsResponse, Status,
+		SubmitResponse, Subscription, SubscriptionId, Transaction, Version, WsClients,
 	},
 	ws,
 };
 use crate::{
 	api::v2::types::{ErrorCode, InternalServerError},
+	audit::{self, AuditReport},
 	data::Database,
 	data::Key,
+	receipts,
+	sampling::SamplingPolicy,
 	types::{RuntimeConfig, State},
-	utils::calculate_confidence,
 };
 use avail_subxt::primitives;
 use color_eyre::{eyre::eyre, Result};
@@ -101,10 +103,11 @@ pub async fn block(
 		return Err(Error::not_found());
 	};
 
+	let sampling = SamplingPolicy::from(&config);
 	let confidence = db
 		.get(Key::VerifiedCellCount(block_number))
 		.map_err(Error::internal_server_error)?
-		.map(calculate_confidence);
+		.map(|count| sampling.confidence.confidence(count));
 
 	Ok(Block::new(block_status, confidence))
 }
@@ -134,6 +137,21 @@ pub async fn block_header(
 		.map_err(Error::internal_server_error)
 }
 
+pub async fn block_audit(block_number: u32, db: impl Database) -> Result<AuditReport, Error> {
+	audit::load(&db, block_number)
+		.map_err(Error::internal_server_error)?
+		.ok_or_else(Error::not_found)
+}
+
+pub async fn receipts(query: ReceiptsQuery, db: impl Database) -> Result<ReceiptsResponse, Error> {
+	if query.to < query.from || query.to - query.from >= receipts::MAX_BATCH_SIZE {
+		return Err(Error::bad_request_unknown("Invalid block range"));
+	}
+	receipts::batch(&db, query.from..=query.to)
+		.map(|receipts| ReceiptsResponse { receipts })
+		.map_err(Error::internal_server_error)
+}
+
 pub async fn block_data(
 	block_number: u32,
 	query: DataQuery,
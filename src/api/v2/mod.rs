@@ -12,7 +12,7 @@ use warp::{Filter, Rejection, Reply};
 
 use self::{
 	handlers::{handle_rejection, log_internal_server_error},
-	types::{DataQuery, PublishMessage, Version, WsClients},
+	types::{DataQuery, PublishMessage, ReceiptsQuery, Version, WsClients},
 };
 
 use crate::{
@@ -27,7 +27,7 @@ mod transactions;
 pub mod types;
 mod ws;
 
-async fn optionally<T>(value: Option<T>) -> Result<T, Rejection> {
+pub(crate) async fn optionally<T>(value: Option<T>) -> Result<T, Rejection> {
 	match value {
 		Some(value) => Ok(value),
 		None => Err(warp::reject::not_found()),
@@ -108,6 +108,27 @@ fn block_data_route(
 		.map(log_internal_server_error)
 }
 
+fn block_audit_route(
+	db: impl Database + Clone + Send,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+	warp::path!("v2" / "blocks" / u32 / "audit")
+		.and(warp::get())
+		.and(with_db(db))
+		.then(handlers::block_audit)
+		.map(log_internal_server_error)
+}
+
+fn receipts_route(
+	db: impl Database + Clone + Send,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+	warp::path!("v2" / "receipts")
+		.and(warp::get())
+		.and(warp::query::<ReceiptsQuery>())
+		.and(with_db(db))
+		.then(handlers::receipts)
+		.map(log_internal_server_error)
+}
+
 fn submit_route(
 	submitter: Option<Arc<impl transactions::Submit + Clone + Send + Sync>>,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
@@ -208,6 +229,7 @@ pub fn routes(
 			rpc_client,
 			app_id,
 			pair_signer,
+			compression: config.compression,
 		})
 	});
 
@@ -220,6 +242,8 @@ pub fn routes(
 			db.clone(),
 		))
 		.or(block_data_route(config.clone(), state.clone(), db.clone()))
+		.or(block_audit_route(db.clone()))
+		.or(receipts_route(db.clone()))
 		.or(subscriptions_route(ws_clients.clone()))
 		.or(submit_route(submitter.clone()))
 		.or(ws_route(ws_clients, version, config, submitter, state))
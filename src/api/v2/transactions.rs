@@ -4,8 +4,11 @@ use color_eyre::Result;
 use sp_core::sr25519::Pair;
 use subxt::tx::PairSigner;
 
-use super::types::{SubmitResponse, Transaction};
-use crate::network::rpc;
+use super::types::{Base64, SubmitResponse, Transaction};
+use crate::{
+	compression::{self, CompressionConfig},
+	network::rpc,
+};
 
 #[async_trait]
 pub trait Submit {
@@ -17,6 +20,8 @@ pub struct Submitter {
 	pub rpc_client: rpc::Client,
 	pub app_id: u32,
 	pub pair_signer: PairSigner<AvailConfig, Pair>,
+	/// Compression of the submitted data
+	pub compression: CompressionConfig,
 }
 
 #[async_trait]
@@ -24,7 +29,10 @@ impl Submit for Submitter {
 	async fn submit(&self, transaction: Transaction) -> Result<SubmitResponse> {
 		let ex_event = match transaction {
 			Transaction::Data(data) => {
-				let extrinsic = api::tx().data_availability().submit_data(data.into());
+				let data = compression::compress(&self.compression, &data.0)?;
+				let extrinsic = api::tx()
+					.data_availability()
+					.submit_data(Base64(data).into());
 				let params = AvailExtrinsicParams::new_with_app_id(self.app_id.into());
 				self.rpc_client
 					.submit_signed_and_wait_for_finalized(&extrinsic, &self.pair_signer, params)
@@ -25,7 +25,11 @@ use warp::{
 };
 
 use crate::{
+	audit::AuditReport,
+	compression,
+	da_finality::FinalizedAvailable,
 	network::rpc::Event as RpcEvent,
+	receipts::SignedReceipt,
 	types::{
 		self, block_matrix_partition_format, BlockVerified, OptionBlockRange, RuntimeConfig, State,
 	},
@@ -221,6 +225,7 @@ pub enum Topic {
 	HeaderVerified,
 	ConfidenceAchieved,
 	DataVerified,
+	FinalizedAvailable,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -464,6 +469,27 @@ impl TryFrom<BlockVerified> for PublishMessage {
 	}
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FinalizedAvailableMessage {
+	block_number: u32,
+	hash: H256,
+	confidence: f64,
+}
+
+impl TryFrom<FinalizedAvailable> for PublishMessage {
+	type Error = Report;
+
+	fn try_from(value: FinalizedAvailable) -> Result<Self, Self::Error> {
+		Ok(PublishMessage::FinalizedAvailable(
+			FinalizedAvailableMessage {
+				block_number: value.block_number,
+				hash: value.header_hash,
+				confidence: value.confidence,
+			},
+		))
+	}
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(try_from = "String")]
 pub struct FieldsQueryParameter(pub HashSet<DataField>);
@@ -498,6 +524,29 @@ impl Reply for DataResponse {
 	}
 }
 
+impl Reply for AuditReport {
+	fn into_response(self) -> warp::reply::Response {
+		warp::reply::json(&self).into_response()
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReceiptsQuery {
+	pub from: u32,
+	pub to: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReceiptsResponse {
+	pub receipts: Vec<SignedReceipt>,
+}
+
+impl Reply for ReceiptsResponse {
+	fn into_response(self) -> warp::reply::Response {
+		warp::reply::json(&self).into_response()
+	}
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DataMessage {
 	block_number: u32,
@@ -517,7 +566,10 @@ impl TryFrom<Vec<u8>> for DataTransaction {
 
 	fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
 		Ok(DataTransaction {
-			data: decode_app_data(&value)?.map(Base64),
+			data: decode_app_data(&value)?
+				.map(|data| compression::decompress(&data))
+				.transpose()?
+				.map(Base64),
 			extrinsic: Some(Base64(value)),
 		})
 	}
@@ -557,6 +609,7 @@ pub enum PublishMessage {
 	HeaderVerified(Box<HeaderMessage>),
 	ConfidenceAchieved(ConfidenceMessage),
 	DataVerified(DataMessage),
+	FinalizedAvailable(FinalizedAvailableMessage),
 }
 
 impl PublishMessage {
@@ -564,6 +617,7 @@ impl PublishMessage {
 		match self {
 			PublishMessage::HeaderVerified(_) => (),
 			PublishMessage::ConfidenceAchieved(_) => (),
+			PublishMessage::FinalizedAvailable(_) => (),
 			PublishMessage::DataVerified(data) => {
 				filter_fields(&mut data.data_transactions, fields)
 			},
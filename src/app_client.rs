@@ -19,6 +19,7 @@ use color_eyre::{
 	Result,
 };
 use dusk_plonk::commitment_scheme::kzg10::PublicParameters;
+use futures::{stream, Stream};
 use kate_recovery::{
 	com::{
 		app_specific_rows, columns_positions, decode_app_extrinsics, reconstruct_columns, AppData,
@@ -36,11 +37,13 @@ use std::{
 	collections::{HashMap, HashSet},
 	ops::Range,
 	sync::{Arc, Mutex},
+	time::Instant,
 };
-use tokio::sync::broadcast;
+use tokio::sync::broadcast::{self, error::RecvError};
 use tracing::{debug, error, info, instrument};
 
 use crate::{
+	audit::{self, ReconstructionAttempt},
 	data::{Database, Key},
 	network::{p2p::Client as P2pClient, rpc::Client as RpcClient},
 	proof,
@@ -225,7 +228,7 @@ fn data_cells_from_row(row: usize, row_data: &[u8]) -> Result<Vec<DataCell>> {
 		.collect::<Result<Vec<DataCell>>>()
 }
 
-fn data_cells_from_rows(rows: Vec<Option<Vec<u8>>>) -> Result<Vec<DataCell>> {
+pub(crate) fn data_cells_from_rows(rows: Vec<Option<Vec<u8>>>) -> Result<Vec<DataCell>> {
 	Ok(rows
 		.into_iter()
 		.enumerate() // Add row indexes
@@ -275,7 +278,7 @@ async fn fetch_verified(
 
 #[instrument(skip_all, fields(block = block.block_num), level = "trace")]
 async fn process_block(
-	client: impl Client,
+	client: &impl Client,
 	db: impl Database,
 	cfg: &AppClientConfig,
 	app_id: AppId,
@@ -369,9 +372,29 @@ async fn process_block(
 		missing_rows.len()
 	);
 
-	let dht_rows = client
+	let begin = Instant::now();
+	let reconstructed = client
 		.reconstruct_rows_from_dht(pp, block_number, dimensions, commitments, &missing_rows)
-		.await?;
+		.await;
+	if !missing_rows.is_empty() {
+		let attempt = ReconstructionAttempt {
+			rows: missing_rows.clone(),
+			duration: begin.elapsed().as_millis() as u64,
+			error: reconstructed
+				.as_ref()
+				.err()
+				.map(|error| format!("{error:#}")),
+		};
+		if let Err(error) =
+			audit::record_reconstruction(&db, block_number, block.header_hash, attempt)
+		{
+			error!(
+				block_number,
+				"Cannot record reconstruction attempt: {error:#}"
+			);
+		}
+	}
+	let dht_rows = reconstructed?;
 
 	debug!(
 		block_number,
@@ -402,6 +425,87 @@ async fn process_block(
 	Ok(data)
 }
 
+/// Verified application data of the block
+#[derive(Clone, Debug)]
+pub struct AppBlock {
+	pub block_number: u32,
+	pub block_hash: H256,
+	/// Decoded application extrinsics, empty if block contains no data for the application
+	pub data: AppData,
+}
+
+struct AppDataStream<C: Client, D: Database> {
+	client: C,
+	db: D,
+	cfg: AppClientConfig,
+	app_id: AppId,
+	pp: Arc<PublicParameters>,
+	block_receive: broadcast::Receiver<BlockVerified>,
+}
+
+impl<C: Client, D: Database + Clone> AppDataStream<C, D> {
+	async fn next(&mut self) -> Option<Result<AppBlock>> {
+		let block = match self.block_receive.recv().await {
+			Ok(block) => block,
+			Err(RecvError::Lagged(skipped)) => {
+				return Some(Err(eyre!(
+					"Verified blocks receiver lagged, skipped {skipped}"
+				)))
+			},
+			Err(RecvError::Closed) => return None,
+		};
+
+		let data = match block.lookup.range_of(self.app_id) {
+			None => Ok(vec![]),
+			Some(_) => {
+				let (db, pp) = (self.db.clone(), self.pp.clone());
+				process_block(&self.client, db, &self.cfg, self.app_id, &block, pp).await
+			},
+		};
+
+		Some(data.map(|data| AppBlock {
+			block_number: block.block_num,
+			block_hash: block.header_hash,
+			data,
+		}))
+	}
+
+	fn into_stream(self) -> impl Stream<Item = Result<AppBlock>> {
+		stream::unfold(self, |mut app_data| async move {
+			let item = app_data.next().await?;
+			Some((item, app_data))
+		})
+	}
+}
+
+/// Subscribes to the verified data of the application, in order of verified blocks.
+///
+/// Failed reconstruction and lagging are yielded as errors, without ending the stream, so subscriber can decide
+/// whether missing block is acceptable. Stream ends when verified blocks channel is closed.
+pub fn subscribe_app_data(
+	cfg: AppClientConfig,
+	db: impl Database + Clone + Sync,
+	network_client: P2pClient,
+	rpc_client: RpcClient,
+	app_id: AppId,
+	block_receive: broadcast::Receiver<BlockVerified>,
+	pp: Arc<PublicParameters>,
+) -> impl Stream<Item = Result<AppBlock>> {
+	let client = AppClient {
+		p2p_client: network_client,
+		rpc_client,
+	};
+	AppDataStream {
+		client,
+		db,
+		cfg,
+		app_id,
+		pp,
+		block_receive,
+	}
+	.into_stream()
+}
+
 /// Runs application client.
 ///
 /// # Arguments
@@ -473,7 +577,7 @@ pub async fn run(
 			rpc_client: rpc_client.clone(),
 		};
 		let data =
-			match process_block(app_client, db.clone(), &cfg, app_id, &block, pp.clone()).await {
+			match process_block(&app_client, db.clone(), &cfg, app_id, &block, pp.clone()).await {
 				Ok(data) => data,
 				Err(error) => {
 					error!(block_number, "Cannot process block: {error}");
@@ -500,6 +604,7 @@ mod tests {
 		types::{AppClientConfig, RuntimeConfig},
 	};
 	use avail_core::DataLookup;
+	use futures::StreamExt;
 	use hex_literal::hex;
 	use kate_recovery::{matrix::Dimensions, testnet};
 
@@ -553,11 +658,44 @@ mod tests {
 			.expect_reconstruct_rows_from_dht()
 			.returning(|_, _, _, _, _| Box::pin(async move { Ok(vec![]) }));
 
-		process_block(mock_client, db, &cfg, AppId(1), &block, pp)
+		process_block(&mock_client, db, &cfg, AppId(1), &block, pp)
 			.await
 			.unwrap();
 	}
 
+	#[tokio::test]
+	async fn test_subscribe_skips_blocks_without_app_data() {
+		let (block_sender, block_receive) = broadcast::channel(4);
+		let lookup = DataLookup::from_id_and_len_iter([(0, 1)].into_iter()).unwrap();
+		let block = BlockVerified {
+			header_hash: H256::repeat_byte(1),
+			block_num: 42,
+			dimensions: Dimensions::new(1, 16).unwrap(),
+			lookup,
+			commitments: vec![],
+			confidence: None,
+		};
+		block_sender.send(block).unwrap();
+		drop(block_sender);
+
+		let mut mock_client = MockClient::new();
+		mock_client.expect_fetch_rows_from_dht().never();
+		let app_data = AppDataStream {
+			client: mock_client,
+			db: mem_db::MemoryDB::default(),
+			cfg: AppClientConfig::from(&RuntimeConfig::default()),
+			app_id: AppId(1),
+			pp: Arc::new(testnet::public_params(1024)),
+			block_receive,
+		};
+
+		let blocks = app_data.into_stream().collect::<Vec<_>>().await;
+		assert_eq!(blocks.len(), 1);
+		let block = blocks[0].as_ref().unwrap();
+		assert_eq!(block.block_number, 42);
+		assert!(block.data.is_empty());
+	}
+
 	#[tokio::test]
 	async fn test_process_block_with_rpc() {
 		let cfg = AppClientConfig::from(&RuntimeConfig::default());
@@ -615,7 +753,7 @@ mod tests {
 			.expect_reconstruct_rows_from_dht()
 			.returning(|_, _, _, _, _| Box::pin(async move { Ok(vec![]) }));
 
-		process_block(mock_client, db, &cfg, AppId(1), &block, pp)
+		process_block(&mock_client, db, &cfg, AppId(1), &block, pp)
 			.await
 			.unwrap();
 	}
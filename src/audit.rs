@@ -0,0 +1,238 @@
+//! Per block DA audit reports, for operators who need to prove their sampling activity.
+//!
+//! Report is created by the light client after the block is sampled, with sampled cells and their sources,
+//! fetch and verification timings and the confidence achieved. Reconstruction attempts of the app client are appended
+//! to the report of the same block. Reports are persisted in the database, and served on `/v2/blocks/{n}/audit`.
+//! Only the reports of the last [`RETAINED_REPORTS`] blocks are kept.
+//!
+//! # Notes
+//!
+//! Cells fetched from the DHT are attributed to the DHT, since the Kademlia lookup doesn't report which peer served
+//! the record.
+
+use avail_subxt::utils::H256;
+use codec::{Decode, Encode};
+use color_eyre::{eyre::WrapErr, Result};
+use kate_recovery::{data::Cell, matrix::Position};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::{
+	data::{Database, Key},
+	network::FetchStats,
+};
+
+/// Number of the most recent blocks whose reports are kept (about a week of 20 seconds blocks)
+pub const RETAINED_REPORTS: u32 = 30_240;
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CellSource {
+	Dht,
+	Rpc,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct SampledCell {
+	pub row: u32,
+	pub col: u16,
+	/// Source of the verified cell, `None` if the cell is not fetched or not verified
+	pub source: Option<CellSource>,
+}
+
+/// Fetch and verification timings, in milliseconds
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timings {
+	pub dht_fetch: u64,
+	pub rpc_fetch: Option<u64>,
+	pub proof_verification: u64,
+}
+
+fn millis(seconds: f64) -> u64 {
+	Duration::from_secs_f64(seconds).as_millis() as u64
+}
+
+impl From<&FetchStats> for Timings {
+	fn from(stats: &FetchStats) -> Self {
+		Timings {
+			dht_fetch: millis(stats.dht_fetch_duration),
+			rpc_fetch: stats.rpc_fetch_duration.map(millis),
+			proof_verification: millis(stats.proof_verification_duration),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct ReconstructionAttempt {
+	/// Rows which are reconstructed
+	pub rows: Vec<u32>,
+	/// Duration of the reconstruction, in milliseconds
+	pub duration: u64,
+	/// Error of the failed reconstruction
+	pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct AuditReport {
+	pub block_number: u32,
+	pub block_hash: H256,
+	pub cells: Vec<SampledCell>,
+	pub timings: Timings,
+	/// Confidence achieved, in thousandths of a percent (e.g. `99609` is `99.609%`), `None` if sampling failed
+	pub confidence: Option<u32>,
+	pub reconstructions: Vec<ReconstructionAttempt>,
+}
+
+impl AuditReport {
+	pub fn new(block_number: u32, block_hash: H256) -> Self {
+		AuditReport {
+			block_number,
+			block_hash,
+			cells: vec![],
+			timings: Timings::default(),
+			confidence: None,
+			reconstructions: vec![],
+		}
+	}
+
+	/// Records sampled positions, with the sources of the verified cells
+	pub fn record_cells<'a>(
+		&mut self,
+		positions: &[Position],
+		verified: impl IntoIterator<Item = (&'a Cell, CellSource)>,
+	) {
+		let mut cells = positions
+			.iter()
+			.map(|&Position { row, col }| SampledCell {
+				row,
+				col,
+				source: None,
+			})
+			.collect::<Vec<_>>();
+		for (cell, source) in verified {
+			let Position { row, col } = cell.position;
+			if let Some(sampled) = cells
+				.iter_mut()
+				.find(|cell| cell.row == row && cell.col == col)
+			{
+				sampled.source = Some(source);
+			}
+		}
+		self.cells = cells;
+	}
+
+	pub fn set_confidence(&mut self, confidence: f64) {
+		self.confidence = Some((confidence * 1000.0).round() as u32);
+	}
+
+	/// Returns confidence achieved in percents
+	pub fn confidence_percent(&self) -> Option<f64> {
+		self.confidence.map(|confidence| confidence as f64 / 1000.0)
+	}
+
+	/// Number of the verified cells
+	pub fn verified(&self) -> usize {
+		self.cells
+			.iter()
+			.filter(|cell| cell.source.is_some())
+			.count()
+	}
+}
+
+/// Stores the report, and prunes the report which is not retained anymore
+pub fn store(db: &impl Database, report: AuditReport) -> Result<()> {
+	let block_number = report.block_number;
+	db.put(Key::AuditReport(block_number), report)
+		.wrap_err("Failed to store audit report")?;
+	if let Some(pruned) = block_number.checked_sub(RETAINED_REPORTS) {
+		db.delete(Key::AuditReport(pruned))
+			.wrap_err("Failed to prune audit report")?;
+	}
+	Ok(())
+}
+
+pub fn load(db: &impl Database, block_number: u32) -> Result<Option<AuditReport>> {
+	db.get(Key::AuditReport(block_number))
+		.wrap_err("Failed to load audit report")
+}
+
+/// Appends reconstruction attempt to the block report, creating the report if it doesn't exist
+pub fn record_reconstruction(
+	db: &impl Database,
+	block_number: u32,
+	block_hash: H256,
+	attempt: ReconstructionAttempt,
+) -> Result<()> {
+	let mut report = load(db, block_number)?
+		.filter(|report| report.block_hash == block_hash)
+		.unwrap_or_else(|| AuditReport::new(block_number, block_hash));
+	report.reconstructions.push(attempt);
+	store(db, report)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::data::mem_db::MemoryDB;
+
+	fn cell(row: u32, col: u16) -> Cell {
+		Cell {
+			position: Position { row, col },
+			content: [0; 80],
+		}
+	}
+
+	#[test]
+	fn record_cells() {
+		let positions = [(0, 1), (1, 2), (1, 3)].map(|(row, col)| Position { row, col });
+		let (dht, rpc) = (cell(1, 2), cell(0, 1));
+		let mut report = AuditReport::new(1, H256::repeat_byte(1));
+		report.record_cells(
+			&positions,
+			[(&dht, CellSource::Dht), (&rpc, CellSource::Rpc)],
+		);
+		report.set_confidence(99.609375);
+
+		let sources = report.cells.iter().map(|cell| cell.source.clone());
+		assert_eq!(
+			sources.collect::<Vec<_>>(),
+			[Some(CellSource::Rpc), Some(CellSource::Dht), None]
+		);
+		assert_eq!(report.verified(), 2);
+		assert_eq!(report.confidence, Some(99609));
+		assert_eq!(report.confidence_percent(), Some(99.609));
+	}
+
+	#[test]
+	fn stored_reports() {
+		let db = MemoryDB::default();
+		let mut report = AuditReport::new(5, H256::repeat_byte(1));
+		report.set_confidence(50.0);
+		store(&db, report.clone()).unwrap();
+
+		let attempt = ReconstructionAttempt {
+			rows: vec![1, 2],
+			duration: 10,
+			error: None,
+		};
+		record_reconstruction(&db, 5, H256::repeat_byte(1), attempt.clone()).unwrap();
+		report.reconstructions.push(attempt.clone());
+		assert_eq!(load(&db, 5).unwrap(), Some(report));
+
+		// Report of the other fork is replaced
+		record_reconstruction(&db, 5, H256::repeat_byte(2), attempt).unwrap();
+		let report = load(&db, 5).unwrap().unwrap();
+		assert_eq!(report.confidence, None);
+		assert_eq!(report.reconstructions.len(), 1);
+		assert_eq!(load(&db, 6).unwrap(), None);
+
+		// Report beyond the retention is pruned
+		store(
+			&db,
+			AuditReport::new(5 + RETAINED_REPORTS, H256::repeat_byte(1)),
+		)
+		.unwrap();
+		assert_eq!(load(&db, 5).unwrap(), None);
+		assert!(load(&db, 5 + RETAINED_REPORTS).unwrap().is_some());
+	}
+}
@@ -0,0 +1,323 @@
+//! Historical data availability verification (backfill).
+//!
+//! Walks backwards from the finalized head, up to the configured depth, and samples each block which is not already
+//! verified. Sampling result is stored per block as [`BlockAvailability`], and can be queried with [`availability`]
+//! (or through `/v1/availability/{block_number}` endpoint).
+//!
+//! # Notes
+//!
+//! Unlike the sync client, blocks with missing cells are stored as unavailable instead of being skipped, so the
+//! query can distinguish blocks which are not yet sampled from the unavailable ones.
+
+use async_trait::async_trait;
+use avail_subxt::{primitives::Header as DaHeader, utils::H256};
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use kate_recovery::{commitments, matrix::Dimensions};
+use mockall::automock;
+use std::time::Instant;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+	data::{BlockAvailability, Database, Key},
+	network::{
+		self,
+		rpc::{self, Client as RpcClient},
+	},
+	sync_client::{self, SyncClient, PREFETCH_WINDOW},
+	types::BackfillConfig,
+	utils::{extract_app_lookup, extract_kate},
+};
+
+#[async_trait]
+#[automock]
+pub trait Client {
+	async fn get_header_by_block_number(&self, block_number: u32) -> Result<(DaHeader, H256)>;
+	/// Prefetches headers of the blocks (see [`sync_client::Client::prefetch_headers`])
+	async fn prefetch_headers(&self, block_numbers: &[u32]);
+	fn get_availability(&self, block_number: u32) -> Result<Option<BlockAvailability>>;
+	fn store_availability(&self, block_number: u32, availability: BlockAvailability) -> Result<()>;
+}
+
+#[derive(Clone)]
+pub struct BackfillClient<T: Database + Sync> {
+	db: T,
+	sync_client: SyncClient<T>,
+}
+
+impl<T: Database + Sync + Clone> BackfillClient<T> {
+	pub fn new(db: T, rpc_client: RpcClient) -> Self {
+		BackfillClient {
+			db: db.clone(),
+			sync_client: SyncClient::new(db, rpc_client),
+		}
+	}
+}
+
+#[async_trait]
+impl<T: Database + Sync + Send> Client for BackfillClient<T> {
+	async fn get_header_by_block_number(&self, block_number: u32) -> Result<(DaHeader, H256)> {
+		sync_client::Client::get_header_by_block_number(&self.sync_client, block_number).await
+	}
+
+	async fn prefetch_headers(&self, block_numbers: &[u32]) {
+		sync_client::Client::prefetch_headers(&self.sync_client, block_numbers).await
+	}
+
+	fn get_availability(&self, block_number: u32) -> Result<Option<BlockAvailability>> {
+		availability(&self.db, block_number)
+	}
+
+	fn store_availability(&self, block_number: u32, availability: BlockAvailability) -> Result<()> {
+		self.db
+			.put(Key::BlockAvailability(block_number), availability)
+			.wrap_err("Backfill failed to store block availability")
+	}
+}
+
+/// Gets stored availability of the historical block, `None` if block is not sampled yet
+pub fn availability(db: &impl Database, block_number: u32) -> Result<Option<BlockAvailability>> {
+	db.get(Key::BlockAvailability(block_number))
+		.wrap_err("Backfill failed to get block availability")
+}
+
+/// Blocks before the `head`, up to `depth`, starting from the most recent one
+pub fn backfill_range(head: u32, depth: u32) -> impl Iterator<Item = u32> {
+	(head.saturating_sub(depth)..head).rev()
+}
+
+async fn sample_block(
+	network_client: &impl network::Client,
+	cfg: &BackfillConfig,
+	header: &DaHeader,
+	header_hash: H256,
+) -> Result<BlockAvailability> {
+	let block_number = header.number;
+	let (rows, cols, _, commitment) = extract_kate(&header.extension);
+	let dimensions = Dimensions::new(rows, cols).ok_or_else(|| eyre!("Invalid dimensions"))?;
+	let commitments = commitments::from_slice(&commitment)?;
+
+	let cell_count = cfg.sampling.confidence.cell_count(cfg.confidence);
+	let positions = match extract_app_lookup(&header.extension) {
+		Ok(lookup) => cfg
+			.sampling
+			.strategy
+			.positions(dimensions, &lookup, cell_count),
+		Err(_) => rpc::generate_random_cells(dimensions, cell_count),
+	};
+
+	let (fetched, _, _) = network_client
+		.fetch_verified(
+			block_number,
+			header_hash,
+			dimensions,
+			&commitments,
+			&positions,
+		)
+		.await?;
+
+	Ok(BlockAvailability {
+		sampled: positions.len().try_into()?,
+		verified: fetched.len().try_into()?,
+	})
+}
+
+/// Runs historical data availability verification.
+///
+/// # Arguments
+///
+/// * `client` - Backfill client, used for fetching headers and storing availability
+/// * `network_client` - Used for fetching cells
+/// * `cfg` - Backfill configuration
+/// * `head` - Finalized block at the client startup, backfill starts from the block before it
+pub async fn run(
+	client: impl Client,
+	network_client: impl network::Client,
+	cfg: BackfillConfig,
+	head: u32,
+) {
+	info!(head, depth = cfg.depth, "Starting historical backfill...");
+	let begin = Instant::now();
+
+	let block_numbers = backfill_range(head, cfg.depth).collect::<Vec<_>>();
+	for (index, &block_number) in block_numbers.iter().enumerate() {
+		if index % PREFETCH_WINDOW == 0 {
+			let window = &block_numbers[index..(index + PREFETCH_WINDOW).min(block_numbers.len())];
+			client.prefetch_headers(window).await;
+		}
+		match client.get_availability(block_number) {
+			Ok(None) => (),
+			Ok(Some(_)) => continue,
+			Err(error) => {
+				error!(block_number, "Cannot backfill block: {error:#}");
+				continue;
+			},
+		}
+
+		let (header, header_hash) = match client.get_header_by_block_number(block_number).await {
+			Ok(value) => value,
+			Err(error) => {
+				error!(block_number, "Cannot backfill block: {error:#}");
+				continue;
+			},
+		};
+
+		let availability = match sample_block(&network_client, &cfg, &header, header_hash).await {
+			Ok(availability) => availability,
+			Err(error) => {
+				error!(block_number, "Cannot backfill block: {error:#}");
+				continue;
+			},
+		};
+
+		if !availability.is_available() {
+			warn!(
+				block_number,
+				sampled = availability.sampled,
+				verified = availability.verified,
+				"Historical block is not available"
+			);
+		}
+
+		if let Err(error) = client.store_availability(block_number, availability) {
+			error!(block_number, "Cannot backfill block: {error:#}");
+			continue;
+		}
+		debug!(
+			block_number,
+			verified = availability.verified,
+			"Block backfilled"
+		);
+	}
+
+	info!(head, elapsed = ?begin.elapsed(), "Historical backfill finished");
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		data::mem_db::MemoryDB,
+		types::{BackfillConfig, RuntimeConfig},
+	};
+	use avail_subxt::{
+		api::runtime_types::avail_core::{
+			data_lookup::compact::CompactDataLookup,
+			header::extension::{v3::HeaderExtension, HeaderExtension::V3},
+			kate_commitment::v3::KateCommitment,
+		},
+		config::substrate::Digest,
+	};
+	use kate_recovery::data::Cell;
+	use mockall::predicate::eq;
+	use std::time::Duration;
+	use test_case::test_case;
+
+	fn header(number: u32) -> DaHeader {
+		DaHeader {
+			parent_hash: H256::zero(),
+			number,
+			state_root: H256::zero(),
+			extrinsics_root: H256::zero(),
+			digest: Digest { logs: vec![] },
+			extension: V3(HeaderExtension {
+				commitment: KateCommitment {
+					rows: 1,
+					cols: 4,
+					data_root: H256::zero(),
+					commitment: vec![0; 96],
+				},
+				app_lookup: CompactDataLookup {
+					size: 1,
+					index: vec![],
+				},
+			}),
+		}
+	}
+
+	fn config(depth: u32) -> BackfillConfig {
+		let mut cfg = BackfillConfig::from(&RuntimeConfig::default());
+		cfg.depth = depth;
+		cfg
+	}
+
+	#[test_case(10, 3 => vec![9, 8, 7] ; "recent blocks first")]
+	#[test_case(2, 5 => vec![1, 0] ; "depth beyond genesis")]
+	#[test_case(0, 5 => Vec::<u32>::new() ; "genesis head")]
+	fn range(head: u32, depth: u32) -> Vec<u32> {
+		backfill_range(head, depth).collect()
+	}
+
+	#[test_case(BlockAvailability { sampled: 8, verified: 8 } => true ; "all verified")]
+	#[test_case(BlockAvailability { sampled: 8, verified: 7 } => false ; "missing cells")]
+	#[test_case(BlockAvailability { sampled: 0, verified: 0 } => false ; "not sampled")]
+	fn is_available(availability: BlockAvailability) -> bool {
+		availability.is_available()
+	}
+
+	#[test]
+	fn stored_availability() {
+		let db = MemoryDB::default();
+		assert_eq!(availability(&db, 5).unwrap(), None);
+		let stored = BlockAvailability {
+			sampled: 4,
+			verified: 3,
+		};
+		db.put(Key::BlockAvailability(5), stored).unwrap();
+		assert_eq!(availability(&db, 5).unwrap(), Some(stored));
+	}
+
+	#[tokio::test]
+	async fn backfill_unverified_blocks() {
+		let mut mock_client = MockClient::new();
+		let mut mock_network_client = network::MockClient::new();
+
+		mock_client
+			.expect_get_availability()
+			.with(eq(2))
+			.returning(|_| {
+				Ok(Some(BlockAvailability {
+					sampled: 4,
+					verified: 4,
+				}))
+			});
+		mock_client
+			.expect_get_availability()
+			.returning(|_| Ok(None));
+		mock_client
+			.expect_prefetch_headers()
+			.withf(|block_numbers| block_numbers.to_vec() == [3, 2, 1])
+			.times(1)
+			.returning(|_| Box::pin(async {}));
+		mock_client
+			.expect_get_header_by_block_number()
+			.returning(|block_number| {
+				Box::pin(async move { Ok((header(block_number), H256::repeat_byte(1))) })
+			});
+		mock_network_client
+			.expect_fetch_verified()
+			.returning(|_, _, _, _, positions| {
+				// Last sampled cell is missing
+				let fetched = positions[1..]
+					.iter()
+					.map(|&position| Cell {
+						position,
+						content: [0; 80],
+					})
+					.collect::<Vec<_>>();
+				let unfetched = positions[..1].to_vec();
+				let stats =
+					network::FetchStats::new(positions.len(), 0, Duration::from_secs(0), None);
+				Box::pin(async move { Ok((fetched, unfetched, stats)) })
+			});
+		mock_client
+			.expect_store_availability()
+			.withf(|block_number, availability| *block_number != 2 && !availability.is_available())
+			.times(2)
+			.returning(|_, _| Ok(()));
+
+		run(mock_client, mock_network_client, config(3), 4).await;
+	}
+}
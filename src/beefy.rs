@@ -0,0 +1,399 @@
+//! Decoding and verification of the BEEFY commitments, for the trust-minimized bridging out of Avail.
+//!
+//! BEEFY validators sign (ECDSA over secp256k1) the Keccak hash of the SCALE encoded [`Commitment`], which carries
+//! the MMR root of the chain at the committed block. Signed commitments are received with the BEEFY gossip, or
+//! with the justifications, and verified against the BEEFY authority set with [`verify_commitment`].
+//!
+//! Authority set changes and MMR roots are announced in the BEEFY consensus digests of the headers (see
+//! [`consensus_logs`]), so the authority set can be followed from the headers verified by the light client.
+//!
+//! # Encoding
+//!
+//! Signed commitment is encoded in the compact form, with the bitfield of the signing validators, followed by the
+//! signatures of the validators with bits set (see [`SignedCommitment`]).
+
+use avail_subxt::{config::substrate::DigestItem, primitives::Header, utils::H256};
+use codec::{Decode, Encode, Input, Output};
+use color_eyre::{eyre::eyre, Result};
+use sp_core::ecdsa;
+
+use crate::eth_bridge::keccak256;
+
+pub const BEEFY_ENGINE_ID: [u8; 4] = *b"BEEF";
+/// Payload ID of the MMR root
+pub const MMR_ROOT_ID: [u8; 2] = *b"mh";
+
+/// BEEFY validator set, encoded as `sp_consensus_beefy::ValidatorSet`
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct AuthoritySet {
+	pub validators: Vec<ecdsa::Public>,
+	pub id: u64,
+}
+
+impl AuthoritySet {
+	/// Minimum number of signatures, tolerating less than a third of faulty validators
+	pub fn threshold(&self) -> usize {
+		let faulty = self.validators.len().saturating_sub(1) / 3;
+		self.validators.len() - faulty
+	}
+}
+
+/// Payload items, sorted by the payload ID
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct Payload(pub Vec<([u8; 2], Vec<u8>)>);
+
+impl Payload {
+	pub fn get(&self, id: &[u8; 2]) -> Option<&[u8]> {
+		self.0
+			.iter()
+			.find(|(item_id, _)| item_id == id)
+			.map(|(_, value)| value.as_slice())
+	}
+
+	pub fn mmr_root(&self) -> Option<H256> {
+		self.get(&MMR_ROOT_ID)
+			.and_then(|mut value| H256::decode(&mut value).ok())
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct Commitment {
+	pub payload: Payload,
+	pub block_number: u32,
+	pub validator_set_id: u64,
+}
+
+impl Commitment {
+	/// Message signed by the validators
+	pub fn hash(&self) -> H256 {
+		keccak256(&self.encode())
+	}
+}
+
+/// Commitment with the signatures, in the order of the authority set validators
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignedCommitment {
+	pub commitment: Commitment,
+	pub signatures: Vec<Option<ecdsa::Signature>>,
+}
+
+/// Bit of the validator in the signers bitfield, most significant bit first
+fn signer_bit(index: usize) -> (usize, u8) {
+	(index / 8, 1 << (7 - index % 8))
+}
+
+impl Encode for SignedCommitment {
+	fn encode_to<T: Output + ?Sized>(&self, dest: &mut T) {
+		let mut signatures_from = vec![0u8; self.signatures.len().div_ceil(8)];
+		let mut signatures_compact = vec![];
+		for (index, signature) in self.signatures.iter().enumerate() {
+			if let Some(signature) = signature {
+				let (byte, bit) = signer_bit(index);
+				signatures_from[byte] |= bit;
+				signatures_compact.push(signature);
+			}
+		}
+		self.commitment.encode_to(dest);
+		signatures_from.encode_to(dest);
+		(self.signatures.len() as u32).encode_to(dest);
+		signatures_compact.encode_to(dest);
+	}
+}
+
+impl Decode for SignedCommitment {
+	fn decode<I: Input>(input: &mut I) -> Result<Self, codec::Error> {
+		let commitment = Commitment::decode(input)?;
+		let signatures_from = Vec::<u8>::decode(input)?;
+		let validator_set_len = u32::decode(input)? as usize;
+		let signatures_compact = Vec::<ecdsa::Signature>::decode(input)?;
+		if validator_set_len > signatures_from.len() * 8 {
+			return Err("Signers bitfield is shorter than the validator set".into());
+		}
+		let mut compact = signatures_compact.into_iter();
+		let signatures = (0..validator_set_len)
+			.map(|index| {
+				let (byte, bit) = signer_bit(index);
+				match signatures_from[byte] & bit != 0 {
+					true => compact
+						.next()
+						.map(Some)
+						.ok_or("Missing signature of the signer"),
+					false => Ok(None),
+				}
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+		if compact.next().is_some() {
+			return Err("More signatures than signers".into());
+		}
+		Ok(SignedCommitment {
+			commitment,
+			signatures,
+		})
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum VersionedFinalityProof {
+	#[codec(index = 1)]
+	V1(SignedCommitment),
+}
+
+/// Vote of the single validator
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct VoteMessage {
+	pub commitment: Commitment,
+	pub id: ecdsa::Public,
+	pub signature: ecdsa::Signature,
+}
+
+/// BEEFY gossip message
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum GossipMessage {
+	Vote(VoteMessage),
+	FinalityProof(VersionedFinalityProof),
+}
+
+/// BEEFY consensus digest
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum ConsensusLog {
+	#[codec(index = 1)]
+	AuthoritiesChange(AuthoritySet),
+	/// Index of the disabled validator
+	#[codec(index = 2)]
+	OnDisabled(u32),
+	#[codec(index = 3)]
+	MmrRoot(H256),
+}
+
+/// Decodes BEEFY consensus digests of the header, skipping undecodable items
+pub fn consensus_logs(header: &Header) -> Vec<ConsensusLog> {
+	header
+		.digest
+		.logs
+		.iter()
+		.filter_map(|item| match item {
+			DigestItem::Consensus(engine_id, data) if *engine_id == BEEFY_ENGINE_ID => {
+				ConsensusLog::decode(&mut data.as_slice()).ok()
+			},
+			_ => None,
+		})
+		.collect()
+}
+
+/// Returns the MMR root announced in the header digest
+pub fn mmr_root(header: &Header) -> Option<H256> {
+	consensus_logs(header)
+		.into_iter()
+		.find_map(|log| match log {
+			ConsensusLog::MmrRoot(root) => Some(root),
+			_ => None,
+		})
+}
+
+/// Returns the next authority set announced in the header digest
+pub fn authorities_change(header: &Header) -> Option<AuthoritySet> {
+	consensus_logs(header)
+		.into_iter()
+		.find_map(|log| match log {
+			ConsensusLog::AuthoritiesChange(set) => Some(set),
+			_ => None,
+		})
+}
+
+/// Verifies the signature of the validator over the commitment
+pub fn is_signed(
+	commitment: &Commitment,
+	signature: &ecdsa::Signature,
+	id: &ecdsa::Public,
+) -> bool {
+	ecdsa::Pair::verify_prehashed(signature, &commitment.hash().0, id)
+}
+
+/// Verified commitment of the block
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifiedCommitment {
+	pub block_number: u32,
+	pub validator_set_id: u64,
+	pub mmr_root: Option<H256>,
+	pub signers: usize,
+}
+
+/// Verifies that the commitment is signed by the threshold of the authority set validators.
+pub fn verify_commitment(
+	authority_set: &AuthoritySet,
+	signed: &SignedCommitment,
+) -> Result<VerifiedCommitment> {
+	let commitment = &signed.commitment;
+	if commitment.validator_set_id != authority_set.id {
+		return Err(eyre!(
+			"Commitment of validator set {} cannot be verified with validator set {}",
+			commitment.validator_set_id,
+			authority_set.id
+		));
+	}
+	if signed.signatures.len() != authority_set.validators.len() {
+		return Err(eyre!(
+			"Commitment has {} signatures, validator set has {} validators",
+			signed.signatures.len(),
+			authority_set.validators.len()
+		));
+	}
+	let mut signers = 0;
+	for (index, (signature, id)) in signed
+		.signatures
+		.iter()
+		.zip(&authority_set.validators)
+		.enumerate()
+	{
+		let Some(signature) = signature else {
+			continue;
+		};
+		if !is_signed(commitment, signature, id) {
+			return Err(eyre!("Invalid signature of validator {index}"));
+		}
+		signers += 1;
+	}
+	if signers < authority_set.threshold() {
+		return Err(eyre!(
+			"Commitment is signed by {signers} validators, {} are required",
+			authority_set.threshold()
+		));
+	}
+	Ok(VerifiedCommitment {
+		block_number: commitment.block_number,
+		validator_set_id: commitment.validator_set_id,
+		mmr_root: commitment.payload.mmr_root(),
+		signers,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use avail_subxt::{
+		api::runtime_types::avail_core::{
+			data_lookup::compact::CompactDataLookup,
+			header::extension::{v3, HeaderExtension},
+			kate_commitment::v3::KateCommitment,
+		},
+		config::substrate::Digest,
+	};
+	use sp_core::Pair;
+	use test_case::test_case;
+
+	fn validators() -> Vec<ecdsa::Pair> {
+		(1..=4u8)
+			.map(|i| ecdsa::Pair::from_seed(&[i; 32]))
+			.collect()
+	}
+
+	fn authority_set() -> AuthoritySet {
+		AuthoritySet {
+			validators: validators().iter().map(|pair| pair.public()).collect(),
+			id: 5,
+		}
+	}
+
+	fn commitment() -> Commitment {
+		Commitment {
+			payload: Payload(vec![(MMR_ROOT_ID, H256::repeat_byte(7).encode())]),
+			block_number: 42,
+			validator_set_id: 5,
+		}
+	}
+
+	fn signed(signers: &[usize]) -> SignedCommitment {
+		let commitment = commitment();
+		let signatures = validators()
+			.iter()
+			.enumerate()
+			.map(|(index, pair)| {
+				signers
+					.contains(&index)
+					.then(|| pair.sign_prehashed(&commitment.hash().0))
+			})
+			.collect();
+		SignedCommitment {
+			commitment,
+			signatures,
+		}
+	}
+
+	#[test]
+	fn compact_encoding() {
+		let signed = signed(&[0, 2, 3]);
+		let encoded = signed.encode();
+		let commitment_len = signed.commitment.encode().len();
+		// Bitfield length, bitfield and validator set length
+		assert_eq!(
+			&encoded[commitment_len..commitment_len + 6],
+			&[4, 0b1011_0000, 4, 0, 0, 0]
+		);
+		assert_eq!(SignedCommitment::decode(&mut &encoded[..]).unwrap(), signed);
+
+		let proof = VersionedFinalityProof::V1(signed).encode();
+		assert_eq!(proof[0], 1);
+		assert!(SignedCommitment::decode(&mut &encoded[..encoded.len() - 65]).is_err());
+	}
+
+	#[test_case(&[0, 1, 2] => Ok(3) ; "threshold")]
+	#[test_case(&[0, 1, 2, 3] => Ok(4) ; "all")]
+	#[test_case(&[1, 3] => Err(()) ; "below threshold")]
+	fn verify(signers: &[usize]) -> Result<usize, ()> {
+		verify_commitment(&authority_set(), &signed(signers))
+			.map(|verified| verified.signers)
+			.map_err(|_| ())
+	}
+
+	#[test]
+	fn verify_invalid_commitment() {
+		let set = authority_set();
+		let verified = verify_commitment(&set, &signed(&[0, 1, 2])).unwrap();
+		assert_eq!(verified.mmr_root, Some(H256::repeat_byte(7)));
+
+		let mut tampered = signed(&[0, 1, 2]);
+		tampered.commitment.block_number = 43;
+		assert!(verify_commitment(&set, &tampered).is_err());
+
+		let other_set = AuthoritySet { id: 6, ..set };
+		assert!(verify_commitment(&other_set, &signed(&[0, 1, 2])).is_err());
+	}
+
+	#[test]
+	fn decode_digests() {
+		let set = authority_set();
+		let logs = vec![
+			DigestItem::Consensus(
+				BEEFY_ENGINE_ID,
+				ConsensusLog::AuthoritiesChange(set.clone()).encode(),
+			),
+			DigestItem::Consensus(
+				BEEFY_ENGINE_ID,
+				ConsensusLog::MmrRoot(H256::repeat_byte(1)).encode(),
+			),
+			DigestItem::Consensus(*b"FRNK", vec![1, 2]),
+		];
+		let header = Header {
+			parent_hash: H256::zero(),
+			number: 1,
+			state_root: H256::zero(),
+			extrinsics_root: H256::zero(),
+			digest: Digest { logs },
+			extension: HeaderExtension::V3(v3::HeaderExtension {
+				commitment: KateCommitment {
+					rows: 1,
+					cols: 4,
+					data_root: H256::zero(),
+					commitment: vec![0; 48],
+				},
+				app_lookup: CompactDataLookup {
+					size: 1,
+					index: vec![],
+				},
+			}),
+		};
+		assert_eq!(consensus_logs(&header).len(), 2);
+		assert_eq!(mmr_root(&header), Some(H256::repeat_byte(1)));
+		assert_eq!(authorities_change(&header), Some(set));
+	}
+}
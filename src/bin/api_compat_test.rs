@@ -2,6 +2,7 @@ use avail_light::{
 	data::rocks_db::RocksDB,
 	network::rpc,
 	types::{ExponentialConfig, RetryConfig, State},
+	verification::WorkerPool,
 };
 use clap::Parser;
 use color_eyre::{eyre::Context, Result};
@@ -31,8 +32,18 @@ async fn main() -> Result<()> {
 		retries: 4,
 	});
 
-	let (rpc_client, _, subscriptions) =
-		rpc::init(db, state, &[command_args.url], "DEV", retry_cfg).await?;
+	let workers = WorkerPool::new(1, 16)?;
+	let (rpc_client, _, subscriptions) = rpc::init(
+		db,
+		state,
+		&[command_args.url],
+		"DEV",
+		retry_cfg,
+		workers,
+		None,
+		false,
+	)
+	.await?;
 	tokio::spawn(subscriptions.run());
 
 	let mut correct: bool = true;
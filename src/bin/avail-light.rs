@@ -2,16 +2,46 @@
 
 use avail_core::AppId;
 use avail_light::{
-	api,
+	api::{
+		self,
+		chain_head::{self, ChainSpec, LightBackend},
+		kate::P2pBackend,
+	},
+	backfill::BackfillClient,
+	cache::{CacheConfig, Caches},
+	client::ClientHandle,
+	constants,
 	consts::EXPECTED_SYSTEM_VERSION,
 	data::rocks_db::RocksDB,
+	equivocation,
+	export::{
+		sink::{CsvSink, JsonLinesSink},
+		ExportClient,
+	},
+	light_server::LightServer,
 	maintenance::StaticConfigParams,
-	network::{self, p2p, rpc},
+	memory::{BudgetedClient, MemoryBudget},
+	multi_chain::MultiChain,
+	network::{
+		self,
+		p2p::{
+			self,
+			compact_block::{self, CompactBlockClient},
+			grandpa_gossip, light_server_protocol, NetworkKeypair,
+		},
+		rpc,
+	},
 	shutdown::Controller,
+	state_client::StateClient,
+	subscriptions::Subscriptions,
+	supervisor::Supervisor,
 	sync_client::SyncClient,
 	sync_finality::SyncFinality,
 	telemetry::{self, otlp::MetricAttributes},
-	types::{CliOpts, IdentityConfig, LibP2PConfig, RuntimeConfig, State},
+	trusted_setup::TrustedSetup,
+	types::{CliOpts, ExportFormat, IdentityConfig, LibP2PConfig, RuntimeConfig, State},
+	verification::{self, WorkerPool},
+	verified_rpc::{HeaderStore, VerifiedRpc},
 };
 use clap::Parser;
 use color_eyre::{
@@ -19,10 +49,9 @@ use color_eyre::{
 	Result,
 };
 use kate_recovery::com::AppData;
-use libp2p::{multiaddr::Protocol, Multiaddr};
 use std::{
 	fs,
-	net::Ipv4Addr,
+	num::NonZeroUsize,
 	path::Path,
 	sync::{Arc, Mutex},
 };
@@ -48,16 +77,16 @@ const CLIENT_ROLE: &str = if cfg!(feature = "crawl") {
 
 /// Light Client for Avail Blockchain
 
-fn json_subscriber(log_level: Level) -> impl Subscriber + Send + Sync {
+fn json_subscriber(env_filter: EnvFilter) -> impl Subscriber + Send + Sync {
 	FmtSubscriber::builder()
-		.with_env_filter(EnvFilter::new(format!("avail_light={log_level}")))
+		.with_env_filter(env_filter)
 		.event_format(format::json())
 		.finish()
 }
 
-fn default_subscriber(log_level: Level) -> impl Subscriber + Send + Sync {
+fn default_subscriber(env_filter: EnvFilter) -> impl Subscriber + Send + Sync {
 	FmtSubscriber::builder()
-		.with_env_filter(EnvFilter::new(format!("avail_light={log_level}")))
+		.with_env_filter(env_filter)
 		.with_span_events(format::FmtSpan::CLOSE)
 		.finish()
 }
@@ -70,19 +99,21 @@ fn parse_log_level(log_level: &str, default: Level) -> (Level, Option<ParseLevel
 		.unwrap_or_else(|parse_err| (default, Some(parse_err)))
 }
 
-async fn run(shutdown: Controller<String>) -> Result<()> {
+async fn run(shutdown: Controller<String>) -> Result<ClientHandle<RocksDB>> {
 	let opts = CliOpts::parse();
 
 	let mut cfg: RuntimeConfig = RuntimeConfig::default();
 	cfg.load_runtime_config(&opts)?;
 
 	let (log_level, parse_error) = parse_log_level(&cfg.log_level, Level::INFO);
+	let env_filter =
+		EnvFilter::try_new(cfg.tracing_directives(log_level)).wrap_err("Invalid tracing filter")?;
 
 	if cfg.log_format_json {
-		tracing::subscriber::set_global_default(json_subscriber(log_level))
+		tracing::subscriber::set_global_default(json_subscriber(env_filter))
 			.expect("global json subscriber is set")
 	} else {
-		tracing::subscriber::set_global_default(default_subscriber(log_level))
+		tracing::subscriber::set_global_default(default_subscriber(env_filter))
 			.expect("global default subscriber is set")
 	}
 
@@ -111,19 +142,22 @@ async fn run(shutdown: Controller<String>) -> Result<()> {
 		fs::remove_dir_all(&cfg.avail_path).wrap_err("Failed to remove local state directory")?;
 	}
 
-	if cfg.bootstraps.is_empty() {
-		Err(eyre!("Bootstrap node list must not be empty. Either use a '--network' flag or add a list of bootstrap nodes in the configuration file"))?
-	}
+	cfg.validate()?;
 
 	let db =
 		RocksDB::open(&cfg.avail_path).wrap_err("Avail Light could not initialize database")?;
+	let supervisor = Supervisor::new(shutdown.clone());
 
 	let cfg_libp2p: LibP2PConfig = (&cfg).into();
-	let (id_keys, peer_id) = p2p::keypair(&cfg_libp2p)?;
+	let network_keypair =
+		NetworkKeypair::from_config(&cfg_libp2p, opts.network_keypair_password.as_deref())?;
+	let id_keys = network_keypair.keypair().clone();
+	let peer_id = network_keypair.peer_id().to_string();
+	let listen_addresses = cfg_libp2p.listen_addresses();
 
 	let metric_attributes = MetricAttributes {
 		role: client_role.into(),
-		peer_id,
+		peer_id: peer_id.clone(),
 		ip: RwLock::new("".to_string()),
 		multiaddress: RwLock::new("".to_string()), // Default value is empty until first processed block triggers an update,
 		origin: cfg.origin.clone(),
@@ -153,38 +187,53 @@ async fn run(shutdown: Controller<String>) -> Result<()> {
 	// Create sender channel for P2P event loop commands
 	let (p2p_event_loop_sender, p2p_event_loop_receiver) = mpsc::unbounded_channel();
 
-	let p2p_event_loop = p2p::EventLoop::new(
-		cfg_libp2p,
-		&id_keys,
-		cfg.is_fat_client(),
-		cfg.ws_transport_enable,
-		shutdown.clone(),
-	);
+	let mut p2p_event_loop =
+		p2p::EventLoop::new(cfg_libp2p, &id_keys, cfg.is_fat_client(), shutdown.clone()).await;
+
+	// Inbound light client requests are forwarded to the light server
+	let light_request_receivers = cfg.light_server_enable.then(|| {
+		let (light_request_sender, light_request_receiver) =
+			light_server_protocol::request_channel();
+		let (compact_block_sender, compact_block_receiver) = compact_block::request_channel();
+		p2p_event_loop = p2p_event_loop
+			.with_light_server(light_request_sender)
+			.with_compact_block_server(compact_block_sender);
+		(light_request_receiver, compact_block_receiver)
+	});
 
-	tokio::spawn(
-		shutdown.with_cancel(
-			p2p_event_loop
-				.await
-				.run(ot_metrics.clone(), p2p_event_loop_receiver),
-		),
+	// Inbound GRANDPA gossip messages are forwarded to the observer
+	let gossip_message_receiver = cfg.grandpa_gossip_enable.then(|| {
+		let (gossip_message_sender, gossip_message_receiver) = grandpa_gossip::message_channel();
+		p2p_event_loop = p2p_event_loop.with_grandpa_gossip(gossip_message_sender);
+		gossip_message_receiver
+	});
+
+	supervisor.spawn(
+		"p2p_event_loop",
+		p2p_event_loop.run(ot_metrics.clone(), p2p_event_loop_receiver),
 	);
 
-	let p2p_client = p2p::Client::new(
+	let mut p2p_client = p2p::Client::new(
 		p2p_event_loop_sender,
 		cfg.dht_parallelization_limit,
 		cfg.kad_record_ttl,
 	);
+	if cfg.sign_dht_records {
+		p2p_client = p2p_client.with_record_signing(id_keys.clone());
+	}
 
-	// Start listening on provided port
-	p2p_client
-		.start_listening(construct_multiaddress(cfg.ws_transport_enable, cfg.port))
-		.await
-		.wrap_err("Listening on TCP not to fail.")?;
-	info!("TCP listener started on port {}", cfg.port);
+	// Start listening on provided ports, with each configured transport
+	for address in listen_addresses {
+		p2p_client
+			.start_listening(address.clone())
+			.await
+			.wrap_err_with(|| format!("Listening on {address} not to fail."))?;
+		info!("P2P listener started on {address}");
+	}
 
 	let p2p_clone = p2p_client.to_owned();
 	let cfg_clone = cfg.to_owned();
-	tokio::spawn(shutdown.with_cancel(async move {
+	supervisor.spawn("bootstrap", async move {
 		info!("Bootstraping the DHT with bootstrap nodes...");
 		let bs_result = p2p_clone
 			.bootstrap_on_startup(cfg_clone.bootstraps.iter().map(Into::into).collect())
@@ -197,17 +246,27 @@ async fn run(shutdown: Controller<String>) -> Result<()> {
 				warn!("Bootstrap process: {e:?}.");
 			},
 		}
-	}));
+	});
 
 	#[cfg(feature = "network-analysis")]
-	tokio::task::spawn(shutdown.with_cancel(analyzer::start_traffic_analyzer(cfg.port, 10)));
+	supervisor.spawn(
+		"traffic_analyzer",
+		analyzer::start_traffic_analyzer(cfg.port, 10),
+	);
 
-	let pp = Arc::new(kate_recovery::couscous::public_params());
+	let pp = TrustedSetup::from(&cfg).public_params()?;
 	let raw_pp = pp.to_raw_var_bytes();
 	let public_params_hash = hex::encode(sp_core::blake2_128(&raw_pp));
 	let public_params_len = hex::encode(raw_pp).len();
 	trace!("Public params ({public_params_len}): hash: {public_params_hash}");
 
+	let verification_workers = cfg.verification_workers.unwrap_or_else(num_cpus::get);
+	let workers = WorkerPool::new(verification_workers, cfg.verification_queue_size)?;
+	info!(verification_workers, "Verification workers started");
+
+	let memory_budget = MemoryBudget::new(cfg.memory_budget);
+	let caches = Caches::with_budget((&cfg).into(), &memory_budget);
+
 	let state = Arc::new(Mutex::new(State::default()));
 	let (rpc_client, rpc_events, rpc_subscriptions) = rpc::init(
 		db.clone(),
@@ -215,15 +274,65 @@ async fn run(shutdown: Controller<String>) -> Result<()> {
 		&cfg.full_node_ws,
 		&cfg.genesis_hash,
 		cfg.retry_config.clone(),
+		workers.clone(),
+		Some(caches.clone()),
+		cfg.chain_head_rpc,
 	)
 	.await?;
+	if cfg.full_node_ws.len() > 1 {
+		supervisor.spawn("rpc_endpoints", rpc::check_endpoints(rpc_client.clone()));
+	}
+	// Headers verified by the finality sync, for the queries verified against them
+	let cache_config = CacheConfig::from(&cfg);
+	let verified_headers = Arc::new(HeaderStore::new(cache_config.header_cache_capacity));
+	let rpc_subscriptions = rpc_subscriptions
+		.with_header_store(verified_headers.clone())
+		.with_budget(memory_budget.clone());
+	let rpc_subscriptions = if cfg.report_equivocations {
+		let (reporter, offences) = equivocation::reporter();
+		supervisor.spawn(
+			"equivocation_reporter",
+			equivocation::submit_reports(
+				rpc_client.clone(),
+				identity_cfg.avail_key_pair.clone(),
+				offences,
+			),
+		);
+		rpc_subscriptions.with_reporter(reporter)
+	} else {
+		rpc_subscriptions
+	};
+	let rpc_subscriptions = match gossip_message_receiver {
+		Some(gossip_messages) => {
+			let (justification_sender, justification_receiver) =
+				mpsc::channel(grandpa_gossip::MAX_QUEUED_JUSTIFICATIONS);
+			supervisor.spawn(
+				"grandpa_gossip",
+				grandpa_gossip::run(
+					db.clone(),
+					p2p_client.clone(),
+					gossip_messages,
+					rpc_events.subscribe(),
+					justification_sender,
+				),
+			);
+			rpc_subscriptions.with_gossip_justifications(justification_receiver)
+		},
+		None => rpc_subscriptions,
+	};
 
 	// Subscribing to RPC events before first event is published
 	let publish_rpc_event_receiver = rpc_events.subscribe();
 	let first_header_rpc_event_receiver = rpc_events.subscribe();
 	let client_rpc_event_receiver = rpc_events.subscribe();
+	let da_finality_rpc_event_receiver = rpc_events.subscribe();
 	#[cfg(feature = "crawl")]
 	let crawler_rpc_event_receiver = rpc_events.subscribe();
+	let substrate_telemetry_rpc_event_receiver = rpc_events.subscribe();
+	let runtime_upgrade_rpc_event_receiver = rpc_events.subscribe();
+	let subscriptions_rpc_event_receiver = rpc_events.subscribe();
+	let chain_head_rpc_event_receiver = rpc_events.subscribe();
+	let multi_chain_rpc_event_receiver = rpc_events.subscribe();
 
 	// spawn the RPC Network task for Event Loop to run in the background
 	// and shut it down, without delays
@@ -271,6 +380,40 @@ async fn run(shutdown: Controller<String>) -> Result<()> {
 	let sync_range = cfg.sync_range(block_header.number);
 
 	let ws_clients = api::v2::types::WsClients::default();
+	let receipts_key_pair = identity_cfg.avail_key_pair.clone();
+
+	let chain_head = if cfg.chain_head_server {
+		let (name, properties) = rpc_client.get_chain_spec().await?;
+		let chain_spec = ChainSpec {
+			name,
+			genesis_hash: rpc_client.get_genesis_hash().await?,
+			properties,
+		};
+		let verified_rpc = VerifiedRpc::new(rpc_client.clone(), verified_headers.clone())
+			.with_budget(memory_budget.clone());
+		let backend = Arc::new(LightBackend::new(
+			verified_rpc,
+			Some(rpc_client.clone()),
+			chain_spec,
+			cache_config.header_cache_capacity,
+		));
+		supervisor.spawn(
+			"chain_head",
+			chain_head::import_finalized(backend.clone(), chain_head_rpc_event_receiver),
+		);
+		Some(backend)
+	} else {
+		None
+	};
+
+	let kate = if cfg.kate_server {
+		let metadata = rpc_client.current_client().await.metadata();
+		let block_length = constants::block_length(&metadata)?;
+		let backend = P2pBackend::new(p2p_client.clone(), verified_headers.clone(), block_length);
+		Some(Arc::new(backend))
+	} else {
+		None
+	};
 
 	// Spawn tokio task which runs one http server for handling RPC
 	let server = api::server::Server {
@@ -283,91 +426,256 @@ async fn run(shutdown: Controller<String>) -> Result<()> {
 		node_client: rpc_client.clone(),
 		ws_clients: ws_clients.clone(),
 		shutdown: shutdown.clone(),
+		chain_head,
+		kate,
 	};
-	tokio::task::spawn(shutdown.with_cancel(server.bind()));
+	supervisor.spawn("http_server", server.bind());
 
 	let (block_tx, block_rx) = broadcast::channel::<avail_light::types::BlockVerified>(1 << 7);
 
+	if cfg.sampling_receipts_enable {
+		supervisor.spawn(
+			"sampling_receipts",
+			avail_light::receipts::run(db.clone(), receipts_key_pair, block_tx.subscribe()),
+		);
+	}
+
+	let subscriptions = Subscriptions::new(1 << 7);
+	supervisor.spawn(
+		"subscriptions",
+		avail_light::subscriptions::run(
+			subscriptions.clone(),
+			subscriptions_rpc_event_receiver,
+			block_tx.subscribe(),
+		),
+	);
+
+	let watched_storage_keys = cfg.watched_storage_keys()?;
+	if !watched_storage_keys.is_empty() {
+		supervisor.spawn(
+			"storage_watcher",
+			avail_light::storage_proof::run(
+				rpc_client.clone(),
+				subscriptions.clone(),
+				watched_storage_keys,
+			),
+		);
+	}
+
+	if !cfg.parachains.is_empty() {
+		let multi_chain = MultiChain::new(rpc_client.clone());
+		for &para_id in &cfg.parachains {
+			multi_chain.register_parachain(para_id);
+			supervisor.spawn(
+				"parachain_sync",
+				avail_light::multi_chain::run_parachain(multi_chain.clone(), para_id),
+			);
+		}
+		supervisor.spawn(
+			"relay_chain_sync",
+			avail_light::multi_chain::run_relay(multi_chain, multi_chain_rpc_event_receiver),
+		);
+	}
+
+	supervisor.spawn(
+		"runtime_upgrade",
+		avail_light::runtime_upgrade::run(
+			rpc_client.clone(),
+			Some(caches.clone()),
+			runtime_upgrade_rpc_event_receiver,
+			subscriptions.runtime_updates_sender(),
+		),
+	);
+
 	let data_rx = cfg.app_id.map(AppId).map(|app_id| {
 		let (data_tx, data_rx) = broadcast::channel::<(u32, AppData)>(1 << 7);
-		tokio::task::spawn(shutdown.with_cancel(avail_light::app_client::run(
-			(&cfg).into(),
-			db.clone(),
-			p2p_client.clone(),
-			rpc_client.clone(),
-			app_id,
-			block_tx.subscribe(),
-			pp.clone(),
-			state.clone(),
-			sync_range.clone(),
-			data_tx,
-			shutdown.clone(),
-		)));
+		supervisor.spawn(
+			"app_client",
+			avail_light::app_client::run(
+				(&cfg).into(),
+				db.clone(),
+				p2p_client.clone(),
+				rpc_client.clone(),
+				app_id,
+				block_tx.subscribe(),
+				pp.clone(),
+				state.clone(),
+				sync_range.clone(),
+				data_tx,
+				shutdown.clone(),
+			),
+		);
 		data_rx
 	});
 
-	tokio::task::spawn(shutdown.with_cancel(api::v2::publish(
-		api::v2::types::Topic::HeaderVerified,
-		publish_rpc_event_receiver,
-		ws_clients.clone(),
-	)));
+	supervisor.spawn(
+		"publish_header_verified",
+		api::v2::publish(
+			api::v2::types::Topic::HeaderVerified,
+			publish_rpc_event_receiver,
+			ws_clients.clone(),
+		),
+	);
 
-	tokio::task::spawn(shutdown.with_cancel(api::v2::publish(
-		api::v2::types::Topic::ConfidenceAchieved,
-		block_tx.subscribe(),
-		ws_clients.clone(),
-	)));
+	supervisor.spawn(
+		"publish_confidence_achieved",
+		api::v2::publish(
+			api::v2::types::Topic::ConfidenceAchieved,
+			block_tx.subscribe(),
+			ws_clients.clone(),
+		),
+	);
+
+	let (finalized_available_tx, finalized_available_rx) =
+		broadcast::channel::<avail_light::da_finality::FinalizedAvailable>(1 << 7);
+
+	supervisor.spawn(
+		"da_finality",
+		avail_light::da_finality::run(
+			da_finality_rpc_event_receiver,
+			block_tx.subscribe(),
+			cfg.confidence,
+			finalized_available_tx,
+		),
+	);
+
+	supervisor.spawn(
+		"publish_finalized_available",
+		api::v2::publish(
+			api::v2::types::Topic::FinalizedAvailable,
+			finalized_available_rx,
+			ws_clients.clone(),
+		),
+	);
 
 	if let Some(data_rx) = data_rx {
-		tokio::task::spawn(shutdown.with_cancel(api::v2::publish(
-			api::v2::types::Topic::DataVerified,
-			data_rx,
-			ws_clients,
-		)));
+		supervisor.spawn(
+			"publish_data_verified",
+			api::v2::publish(api::v2::types::Topic::DataVerified, data_rx, ws_clients),
+		);
 	}
 
 	#[cfg(feature = "crawl")]
 	if cfg.crawl.crawl_block {
 		let partition = cfg.crawl.crawl_block_matrix_partition;
-		tokio::task::spawn(shutdown.with_cancel(avail_light::crawl_client::run(
-			crawler_rpc_event_receiver,
-			p2p_client.clone(),
-			cfg.crawl.crawl_block_delay,
-			ot_metrics.clone(),
-			cfg.crawl.crawl_block_mode,
-			partition.unwrap_or(avail_light::crawl_client::ENTIRE_BLOCK),
-		)));
+		supervisor.spawn(
+			"crawl_client",
+			avail_light::crawl_client::run(
+				crawler_rpc_event_receiver,
+				p2p_client.clone(),
+				cfg.crawl.crawl_block_delay,
+				ot_metrics.clone(),
+				cfg.crawl.crawl_block_mode,
+				partition.unwrap_or(avail_light::crawl_client::ENTIRE_BLOCK),
+			),
+		);
 	}
 
 	let sync_client = SyncClient::new(db.clone(), rpc_client.clone());
 
-	let sync_network_client = network::new(
-		p2p_client.clone(),
-		rpc_client.clone(),
-		pp.clone(),
-		cfg.disable_rpc,
+	let sync_network_client = BudgetedClient::new(
+		network::new(
+			p2p_client.clone(),
+			rpc_client.clone(),
+			pp.clone(),
+			workers.clone(),
+			cfg.disable_rpc,
+		),
+		memory_budget.clone(),
 	);
 
 	if cfg.sync_start_block.is_some() {
 		state.lock().unwrap().synced.replace(false);
-		tokio::task::spawn(shutdown.with_cancel(avail_light::sync_client::run(
-			sync_client,
-			sync_network_client,
-			(&cfg).into(),
-			sync_range,
-			block_tx.clone(),
-			state.clone(),
-		)));
+		supervisor.spawn(
+			"sync_client",
+			avail_light::sync_client::run(
+				sync_client,
+				sync_network_client,
+				(&cfg).into(),
+				sync_range,
+				block_tx.clone(),
+				state.clone(),
+			),
+		);
+	}
+
+	if cfg.backfill_depth.is_some() {
+		let backfill_client = BackfillClient::new(db.clone(), rpc_client.clone());
+		let backfill_network_client = BudgetedClient::new(
+			network::new(
+				p2p_client.clone(),
+				rpc_client.clone(),
+				pp.clone(),
+				workers.clone(),
+				cfg.disable_rpc,
+			),
+			memory_budget.clone(),
+		);
+		supervisor.spawn(
+			"backfill",
+			avail_light::backfill::run(
+				backfill_client,
+				backfill_network_client,
+				(&cfg).into(),
+				block_header.number,
+			),
+		);
+	}
+
+	if let Some(export_path) = &cfg.export_path {
+		let state_client = StateClient::new(rpc_client.clone(), NonZeroUsize::new(1024).unwrap());
+		let mut export_client = ExportClient::new(db.clone(), rpc_client.clone(), state_client);
+		if cfg.compact_block_sync {
+			let bodies = CompactBlockClient::new(p2p_client.clone(), rpc_client.clone());
+			export_client = export_client.with_bodies(Arc::new(bodies));
+		}
+		info!(%export_path, format = ?cfg.export_format, "Exporting verified blocks");
+		match cfg.export_format {
+			ExportFormat::JsonLines => supervisor.spawn(
+				"export",
+				avail_light::export::run(
+					export_client,
+					JsonLinesSink::open(export_path).await?,
+					(&cfg).into(),
+					block_tx.subscribe(),
+				),
+			),
+			ExportFormat::Csv => supervisor.spawn(
+				"export",
+				avail_light::export::run(
+					export_client,
+					CsvSink::open(export_path).await?,
+					(&cfg).into(),
+					block_tx.subscribe(),
+				),
+			),
+		}
+	}
+
+	if let Some((light_request_receiver, compact_block_receiver)) = light_request_receivers {
+		let light_server = LightServer::new(db.clone(), rpc_client.clone());
+		supervisor.spawn(
+			"light_server",
+			avail_light::light_server::run(
+				light_server,
+				p2p_client.clone(),
+				light_request_receiver,
+				compact_block_receiver,
+			),
+		);
 	}
 
 	if cfg.sync_finality_enable {
 		let sync_finality = SyncFinality::new(db.clone(), rpc_client.clone());
-		tokio::task::spawn(shutdown.with_cancel(avail_light::sync_finality::run(
-			sync_finality,
-			shutdown.clone(),
-			state.clone(),
-			block_header.clone(),
-		)));
+		supervisor.spawn(
+			"sync_finality",
+			avail_light::sync_finality::run(
+				sync_finality,
+				shutdown.clone(),
+				state.clone(),
+				block_header.clone(),
+			),
+		);
 	} else {
 		let mut s = state
 			.lock()
@@ -376,6 +684,28 @@ async fn run(shutdown: Controller<String>) -> Result<()> {
 		s.finality_synced = true;
 	}
 
+	if let Some(endpoint) = cfg.substrate_telemetry_endpoint.clone() {
+		let node = telemetry::substrate::NodeInfo {
+			name: cfg
+				.substrate_telemetry_name
+				.clone()
+				.unwrap_or_else(|| peer_id.clone()),
+			version: format!("v{version}"),
+			network_id: peer_id,
+			startup_time: chrono::Utc::now().timestamp_millis().to_string(),
+		};
+		supervisor.spawn(
+			"substrate_telemetry",
+			telemetry::substrate::run(
+				endpoint,
+				node,
+				p2p_client.clone(),
+				state.clone(),
+				substrate_telemetry_rpc_event_receiver,
+			),
+		);
+	}
+
 	let static_config_params = StaticConfigParams {
 		block_confidence_treshold: cfg.confidence,
 		replication_factor: cfg.replication_factor,
@@ -383,13 +713,16 @@ async fn run(shutdown: Controller<String>) -> Result<()> {
 		pruning_interval: cfg.store_pruning_interval,
 	};
 
-	tokio::task::spawn(shutdown.with_cancel(avail_light::maintenance::run(
-		p2p_client.clone(),
-		ot_metrics.clone(),
-		block_rx,
-		static_config_params,
-		shutdown.clone(),
-	)));
+	supervisor.spawn(
+		"maintenance",
+		avail_light::maintenance::run(
+			p2p_client.clone(),
+			ot_metrics.clone(),
+			block_rx,
+			static_config_params,
+			shutdown.clone(),
+		),
+	);
 
 	let channels = avail_light::types::ClientChannels {
 		block_sender: block_tx,
@@ -399,42 +732,58 @@ async fn run(shutdown: Controller<String>) -> Result<()> {
 	if let Some(partition) = cfg.block_matrix_partition {
 		let fat_client = avail_light::fat_client::new(p2p_client.clone(), rpc_client.clone());
 
-		tokio::task::spawn(shutdown.with_cancel(avail_light::fat_client::run(
-			fat_client,
-			db.clone(),
-			(&cfg).into(),
-			ot_metrics.clone(),
-			channels,
-			partition,
-			shutdown.clone(),
-		)));
+		supervisor.spawn(
+			"fat_client",
+			avail_light::fat_client::run(
+				fat_client,
+				db.clone(),
+				(&cfg).into(),
+				ot_metrics.clone(),
+				channels,
+				partition,
+				shutdown.clone(),
+			),
+		);
 	} else {
-		let light_network_client = network::new(p2p_client, rpc_client, pp, cfg.disable_rpc);
-
-		tokio::task::spawn(shutdown.with_cancel(avail_light::light_client::run(
-			db.clone(),
-			light_network_client,
-			(&cfg).into(),
-			ot_metrics,
-			state.clone(),
-			channels,
-			shutdown.clone(),
-		)));
+		let light_network_client = BudgetedClient::new(
+			network::new(p2p_client, rpc_client, pp, workers, cfg.disable_rpc),
+			memory_budget,
+		);
+
+		supervisor.spawn(
+			"light_client",
+			avail_light::light_client::run(
+				db.clone(),
+				light_network_client,
+				(&cfg).into(),
+				ot_metrics,
+				state.clone(),
+				channels,
+				shutdown.clone(),
+			),
+		);
 	}
 
-	Ok(())
+	Ok(ClientHandle::new(supervisor, subscriptions, db))
 }
 
-fn construct_multiaddress(is_websocket: bool, port: u16) -> Multiaddr {
-	let tcp_multiaddress = Multiaddr::empty()
-		.with(Protocol::from(Ipv4Addr::UNSPECIFIED))
-		.with(Protocol::Tcp(port));
-
-	if is_websocket {
-		return tcp_multiaddress.with(Protocol::Ws(std::borrow::Cow::Borrowed("avail-light")));
+/// Prints PeerId and listener addresses with the PeerId, without starting the client
+fn export_peer_id(opts: &CliOpts) -> Result<()> {
+	let mut cfg: RuntimeConfig = RuntimeConfig::default();
+	cfg.load_runtime_config(opts)?;
+	let cfg_libp2p: LibP2PConfig = (&cfg).into();
+	if cfg_libp2p.secret_key.is_none() && cfg_libp2p.keypair_path.is_none() {
+		return Err(eyre!(
+			"PeerId is not stable, either `secret_key` or `network_keypair_path` must be configured"
+		));
 	}
-
-	tcp_multiaddress
+	let network_keypair =
+		NetworkKeypair::from_config(&cfg_libp2p, opts.network_keypair_password.as_deref())?;
+	println!("{}", network_keypair.peer_id());
+	for address in cfg_libp2p.listen_addresses() {
+		println!("{}", network_keypair.peer_address(&address));
+	}
+	Ok(())
 }
 
 fn install_panic_hooks(shutdown: Controller<String>) -> Result<()> {
@@ -448,10 +797,18 @@ fn install_panic_hooks(shutdown: Controller<String>) -> Result<()> {
 	eyre_hook.install()?;
 
 	std::panic::set_hook(Box::new(move |panic_info| {
+		let msg = format!("{}", panic_hook.panic_report(panic_info));
+		// panics of the verification jobs are caught, and the worker keeps running
+		if verification::is_worker_thread() {
+			error!(
+				"Verification job panicked: {}",
+				strip_ansi_escapes::strip_str(msg)
+			);
+			return;
+		}
+
 		// trigger shutdown to stop other tasks if panic occurs
 		let _ = shutdown.trigger_shutdown("Panic occurred, shuting down".to_string());
-
-		let msg = format!("{}", panic_hook.panic_report(panic_info));
 		error!("Error: {}", strip_ansi_escapes::strip_str(msg));
 
 		#[cfg(debug_assertions)]
@@ -511,6 +868,11 @@ async fn user_signal() {
 
 #[tokio::main]
 pub async fn main() -> Result<()> {
+	let opts = CliOpts::parse();
+	if opts.export_peer_id {
+		return export_peer_id(&opts);
+	}
+
 	let shutdown = Controller::new();
 
 	// install custom panic hooks
@@ -519,13 +881,20 @@ pub async fn main() -> Result<()> {
 	// spawn a task to watch for ctrl-c signals from user to trigger the shutdown
 	tokio::spawn(shutdown.with_trigger("user signaled shutdown".to_string(), user_signal()));
 
-	if let Err(error) = run(shutdown.clone()).await {
-		error!("{error:#}");
-		return Err(error.wrap_err("Starting Light Client failed"));
+	let client = match run(shutdown.clone()).await {
+		Ok(client) => client,
+		Err(error) => {
+			error!("{error:#}");
+			return Err(error.wrap_err("Starting Light Client failed"));
+		},
 	};
 
 	let reason = shutdown.completed_shutdown().await;
 
+	if let Err(error) = client.shutdown().await {
+		error!("Client shutdown failed: {error:#}");
+	}
+
 	// we are not logging error here since expectation is
 	// to log terminating condition before sending message to this channel
 	Err(eyre!(reason).wrap_err("Running Light Client encountered an error"))
@@ -0,0 +1,257 @@
+//! Canonical blob reference, a compact pointer to the app data which rollups can store on-chain.
+//!
+//! [`BlobRef`] points to the `index`-th data submission of the application in the block, and commits to the blob with
+//! its Keccak-256 hash (the data root leaf). References are SCALE encoded on-chain (72 bytes), and serialized with
+//! serde elsewhere.
+//!
+//! # Resolving
+//!
+//! * Header of the block has to be verified locally
+//! * App extrinsics are taken from the database, if the app client already reconstructed them, otherwise app rows are
+//!   fetched, verified against the header commitments and decoded
+//! * Blob is verified against the reference commitment, and decompressed (see [`crate::compression`])
+
+use avail_core::AppId;
+use avail_subxt::{primitives::Header, utils::H256};
+use codec::{Decode, Encode};
+use color_eyre::{eyre::eyre, Result};
+use dusk_plonk::commitment_scheme::kzg10::PublicParameters;
+use kate_recovery::{
+	com::{app_specific_rows, decode_app_extrinsics},
+	commitments,
+};
+use serde::{Deserialize, Serialize};
+use sp_core::blake2_256;
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::{
+	app_client::data_cells_from_rows,
+	compression,
+	data::{Database, Key},
+	eth_bridge::keccak256,
+	network::cell_fetcher::CellFetcher,
+	types::BlockVerified,
+	utils::decode_app_data,
+	verified_rpc::VerifiedHeaders,
+};
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlobRef {
+	pub block_hash: H256,
+	pub app_id: u32,
+	/// Index of the data submission among the application submissions in the block
+	pub index: u32,
+	/// Keccak-256 hash of the submitted (compressed) blob
+	pub commitment: H256,
+}
+
+impl BlobRef {
+	pub fn new(block_hash: H256, app_id: u32, index: u32, blob: &[u8]) -> Self {
+		BlobRef {
+			block_hash,
+			app_id,
+			index,
+			commitment: keccak256(blob),
+		}
+	}
+
+	/// Verifies that the blob matches the reference commitment
+	pub fn verify(&self, blob: &[u8]) -> Result<()> {
+		let hash = keccak256(blob);
+		if hash != self.commitment {
+			return Err(eyre!(
+				"Blob hash {hash:?} doesn't match commitment {:?}",
+				self.commitment
+			));
+		}
+		Ok(())
+	}
+}
+
+/// Returns the referenced blob among the data of the application extrinsics
+fn select(blob_ref: &BlobRef, extrinsics: &[Vec<u8>]) -> Result<Vec<u8>> {
+	let blobs = extrinsics
+		.iter()
+		.map(|extrinsic| decode_app_data(extrinsic))
+		.filter_map(Result::transpose)
+		.collect::<Result<Vec<_>>>()?;
+	let blob = blobs
+		.into_iter()
+		.nth(blob_ref.index as usize)
+		.ok_or_else(|| {
+			eyre!(
+				"Block has no blob {} of app {}",
+				blob_ref.index,
+				blob_ref.app_id
+			)
+		})?;
+	blob_ref.verify(&blob)?;
+	Ok(blob)
+}
+
+pub struct Resolver<H: VerifiedHeaders, F: CellFetcher, D: Database> {
+	headers: H,
+	fetcher: F,
+	db: D,
+	pp: Arc<PublicParameters>,
+}
+
+impl<H: VerifiedHeaders, F: CellFetcher, D: Database> Resolver<H, F, D> {
+	pub fn new(headers: H, fetcher: F, db: D, pp: Arc<PublicParameters>) -> Self {
+		Resolver {
+			headers,
+			fetcher,
+			db,
+			pp,
+		}
+	}
+
+	/// Returns app extrinsics reconstructed by the app client, if they are stored for the same block
+	fn stored_extrinsics(
+		&self,
+		header: &Header,
+		block_hash: H256,
+		app_id: u32,
+	) -> Result<Option<Vec<Vec<u8>>>> {
+		let stored = self.db.get::<Header>(Key::BlockHeader(header.number))?;
+		if stored.map(|header| Encode::using_encoded(&header, blake2_256)) != Some(block_hash.0) {
+			return Ok(None);
+		}
+		self.db.get(Key::AppData(app_id, header.number))
+	}
+
+	/// Fetches app rows, verifies them against the commitments and decodes app extrinsics
+	async fn reconstruct_extrinsics(&self, header: Header, app_id: u32) -> Result<Vec<Vec<u8>>> {
+		let block = BlockVerified::try_from((header, None))?;
+		let app_id = AppId(app_id);
+		let rows = app_specific_rows(&block.lookup, block.dimensions, app_id);
+		let fetched = self
+			.fetcher
+			.fetch_rows(block.block_num, block.header_hash, &rows)
+			.await?;
+
+		let mut all_rows = vec![None; block.dimensions.extended_rows() as usize];
+		for (&row, data) in rows.iter().zip(fetched) {
+			all_rows[row as usize] = data;
+		}
+		let (_, missing) = commitments::verify_equality(
+			&self.pp,
+			&block.commitments,
+			&all_rows,
+			&block.lookup,
+			block.dimensions,
+			app_id,
+		)?;
+		if !missing.is_empty() {
+			return Err(eyre!(
+				"App rows {missing:?} are not available or not verified"
+			));
+		}
+		let cells = data_cells_from_rows(all_rows)?;
+		Ok(decode_app_extrinsics(
+			&block.lookup,
+			block.dimensions,
+			cells,
+			app_id,
+		)?)
+	}
+
+	/// Fetches, reconstructs and verifies the referenced blob
+	pub async fn resolve(&self, blob_ref: &BlobRef) -> Result<Vec<u8>> {
+		let header = self
+			.headers
+			.verified_header(blob_ref.block_hash)
+			.ok_or_else(|| eyre!("Block {:?} is not verified", blob_ref.block_hash))?;
+		let extrinsics =
+			match self.stored_extrinsics(&header, blob_ref.block_hash, blob_ref.app_id)? {
+				Some(extrinsics) => extrinsics,
+				None => {
+					debug!(block_number = header.number, "Reconstructing app data");
+					self.reconstruct_extrinsics(header, blob_ref.app_id).await?
+				},
+			};
+		compression::decompress(&select(blob_ref, &extrinsics)?)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		compression::CompressionConfig, data::mem_db::MemoryDB,
+		network::cell_fetcher::MockCellFetcher, simulation::header, verified_rpc::HeaderStore,
+	};
+	use codec::Compact;
+	use kate_recovery::testnet;
+	use std::num::NonZeroUsize;
+
+	/// Unsigned `submit_data` extrinsic, prefixed with its length
+	fn data_extrinsic(data: &[u8]) -> Vec<u8> {
+		let mut extrinsic = vec![4, 29, 1];
+		Compact(data.len() as u32).encode_to(&mut extrinsic);
+		extrinsic.extend_from_slice(data);
+		extrinsic.encode()
+	}
+
+	#[test]
+	fn encoding() {
+		let blob_ref = BlobRef::new(H256::repeat_byte(1), 2, 3, b"blob");
+		let encoded = blob_ref.encode();
+		assert_eq!(encoded.len(), 72);
+		assert_eq!(BlobRef::decode(&mut &encoded[..]).unwrap(), blob_ref);
+		let json = serde_json::to_string(&blob_ref).unwrap();
+		assert_eq!(serde_json::from_str::<BlobRef>(&json).unwrap(), blob_ref);
+
+		assert!(blob_ref.verify(b"blob").is_ok());
+		assert!(blob_ref.verify(b"other").is_err());
+	}
+
+	#[test]
+	fn select_blob() {
+		let blob_ref = BlobRef::new(H256::zero(), 1, 0, b"blob");
+		// Extrinsics which are not data submissions fail to decode
+		assert!(select(&blob_ref, &[vec![0]]).is_err());
+		assert!(select(&blob_ref, &[]).is_err());
+
+		let extrinsics = [data_extrinsic(b"first"), data_extrinsic(b"blob")];
+		let blob_ref = BlobRef::new(H256::zero(), 1, 1, b"blob");
+		assert_eq!(select(&blob_ref, &extrinsics).unwrap(), b"blob");
+		// Blob at the index doesn't match the commitment
+		let other = BlobRef {
+			index: 0,
+			..blob_ref
+		};
+		assert!(select(&other, &extrinsics).is_err());
+	}
+
+	#[tokio::test]
+	async fn resolve_stored() {
+		let blob = b"{\"batch\":1}\n".repeat(200);
+		let compressed = compression::compress(&CompressionConfig::default(), &blob).unwrap();
+		assert!(compressed.len() < blob.len());
+
+		let headers = HeaderStore::new(NonZeroUsize::new(8).unwrap());
+		let block = header(1, H256::zero(), 0);
+		let block_hash = headers.insert(block.clone());
+		let db = MemoryDB::default();
+		db.put(Key::BlockHeader(1), block).unwrap();
+		db.put(Key::AppData(1, 1), vec![data_extrinsic(&compressed)])
+			.unwrap();
+		// Stored extrinsics are used, so rows are not fetched
+		let resolver = Resolver::new(
+			headers,
+			MockCellFetcher::new(),
+			db,
+			Arc::new(testnet::public_params(1024)),
+		);
+
+		let blob_ref = BlobRef::new(block_hash, 1, 0, &compressed);
+		assert_eq!(resolver.resolve(&blob_ref).await.unwrap(), blob);
+		let unverified = BlobRef {
+			block_hash: H256::repeat_byte(1),
+			..blob_ref
+		};
+		assert!(resolver.resolve(&unverified).await.is_err());
+	}
+}
@@ -0,0 +1,300 @@
+//! Block builder for test networks and development chains.
+//!
+//! Produces sealed blocks on top of a given parent header, using pluggable executor and seal provider.
+//!
+//! # Flow
+//!
+//! * Initialize block on the executor with the new (unsealed) header
+//! * Apply inherent extrinsics created by the executor from [`InherentData`]
+//! * Apply pushed extrinsics, skipping the ones executor rejects
+//! * Finalize block, compute extrinsics root and attach executor digest items
+//! * Attach pre-runtime digest, compute pre-seal hash and attach seal digest
+//!
+//! # Notes
+//!
+//! Block builder doesn't execute the runtime itself, state root and header extension are provided by the [`Executor`].
+
+use avail_subxt::{
+	api::runtime_types::avail_core::header::extension::HeaderExtension,
+	config::substrate::{Digest, DigestItem},
+	primitives::Header,
+	utils::H256,
+};
+use codec::Encode;
+use color_eyre::{eyre::WrapErr, Result};
+use sp_core::{blake2_256, Blake2Hasher};
+use sp_trie::{LayoutV0, TrieConfiguration};
+use tracing::{debug, warn};
+
+use crate::{
+	counters::{self, Counter},
+	inherents::InherentData,
+};
+
+/// State of the block after all extrinsics are applied
+pub struct FinalizedState {
+	pub state_root: H256,
+	pub extension: HeaderExtension,
+	/// Digest items emitted by the runtime (e.g. consensus logs)
+	pub logs: Vec<DigestItem>,
+}
+
+/// Executor backend used for block construction
+pub trait Executor {
+	/// Initializes block execution on top of the parent, with given unsealed header.
+	fn initialize_block(&mut self, header: &Header) -> Result<()>;
+	/// Creates encoded inherent extrinsics from inherent data, which needs to be applied at the beginning of the block.
+	fn inherent_extrinsics(&mut self, inherent_data: &InherentData) -> Result<Vec<Vec<u8>>>;
+	/// Applies encoded extrinsic to the block state.
+	fn apply_extrinsic(&mut self, extrinsic: &[u8]) -> Result<()>;
+	/// Finalizes the block execution and returns resulting state.
+	fn finalize_block(&mut self) -> Result<FinalizedState>;
+}
+
+/// Provides pre-runtime and seal digest items for the block
+pub trait SealProvider {
+	/// Pre-runtime digest (e.g. slot claim), included in the pre-seal header hash.
+	fn pre_runtime_digest(&self, parent: &Header) -> Result<Option<DigestItem>>;
+	/// Seals pre-seal header hash, returned item is appended as a last digest item.
+	fn seal(&self, pre_hash: H256) -> Result<DigestItem>;
+}
+
+pub struct SealedBlock {
+	pub header: Header,
+	pub extrinsics: Vec<Vec<u8>>,
+}
+
+impl SealedBlock {
+	pub fn hash(&self) -> H256 {
+		Encode::using_encoded(&self.header, blake2_256).into()
+	}
+}
+
+/// Calculates extrinsics root as an ordered trie root of encoded extrinsics.
+/// With the `parallel` feature, root of big blocks is computed on the rayon thread pool.
+pub fn extrinsics_root(extrinsics: &[Vec<u8>]) -> H256 {
+	counters::increment(Counter::TrieRoots);
+	#[cfg(feature = "parallel")]
+	if extrinsics.len() >= crate::trie::ordered_root::PARALLEL_THRESHOLD {
+		return crate::trie::ordered_root::ordered_trie_root(extrinsics);
+	}
+	LayoutV0::<Blake2Hasher>::ordered_trie_root(extrinsics.iter().map(Encode::encode))
+}
+
+pub struct BlockBuilder<E: Executor> {
+	parent: Header,
+	executor: E,
+	extrinsics: Vec<Vec<u8>>,
+}
+
+impl<E: Executor> BlockBuilder<E> {
+	pub fn new(parent: Header, executor: E) -> Self {
+		BlockBuilder {
+			parent,
+			executor,
+			extrinsics: vec![],
+		}
+	}
+
+	/// Adds encoded extrinsic to be applied after inherents
+	pub fn push(&mut self, extrinsic: Vec<u8>) {
+		self.extrinsics.push(extrinsic);
+	}
+
+	/// Builds and seals the block
+	pub fn build(
+		mut self,
+		inherent_data: &InherentData,
+		seal_provider: &impl SealProvider,
+	) -> Result<SealedBlock> {
+		let parent_hash: H256 = Encode::using_encoded(&self.parent, blake2_256).into();
+		let mut logs = vec![];
+		if let Some(pre_runtime) = seal_provider
+			.pre_runtime_digest(&self.parent)
+			.wrap_err("Failed to create pre-runtime digest")?
+		{
+			logs.push(pre_runtime);
+		}
+
+		let mut header = Header {
+			parent_hash,
+			number: self.parent.number + 1,
+			state_root: H256::zero(),
+			extrinsics_root: H256::zero(),
+			digest: Digest { logs },
+			extension: self.parent.extension.clone(),
+		};
+		let block_number = header.number;
+
+		self.executor
+			.initialize_block(&header)
+			.wrap_err("Failed to initialize block")?;
+
+		let mut extrinsics = self
+			.executor
+			.inherent_extrinsics(inherent_data)
+			.wrap_err("Failed to create inherent extrinsics")?;
+
+		for extrinsic in &extrinsics {
+			self.executor
+				.apply_extrinsic(extrinsic)
+				.wrap_err("Failed to apply inherent extrinsic")?;
+		}
+
+		for extrinsic in self.extrinsics {
+			if let Err(error) = self.executor.apply_extrinsic(&extrinsic) {
+				warn!(block_number, "Skipping extrinsic: {error:#}");
+				continue;
+			}
+			extrinsics.push(extrinsic);
+		}
+
+		let FinalizedState {
+			state_root,
+			extension,
+			logs,
+		} = self
+			.executor
+			.finalize_block()
+			.wrap_err("Failed to finalize block")?;
+
+		header.state_root = state_root;
+		header.extrinsics_root = extrinsics_root(&extrinsics);
+		header.extension = extension;
+		header.digest.logs.extend(logs);
+
+		let pre_hash: H256 = Encode::using_encoded(&header, blake2_256).into();
+		let seal = seal_provider
+			.seal(pre_hash)
+			.wrap_err("Failed to seal block")?;
+		header.digest.logs.push(seal);
+
+		debug!(
+			block_number,
+			extrinsics = extrinsics.len(),
+			?pre_hash,
+			"Block sealed"
+		);
+
+		Ok(SealedBlock { header, extrinsics })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::inherents::{
+		create_inherent_data, TimestampProvider, TIMESTAMP_INHERENT_IDENTIFIER,
+	};
+	use avail_subxt::api::runtime_types::avail_core::{
+		data_lookup::compact::CompactDataLookup,
+		header::extension::{v3, HeaderExtension},
+		kate_commitment::v3::KateCommitment,
+	};
+	use color_eyre::eyre::eyre;
+
+	fn extension(rows: u16) -> HeaderExtension {
+		HeaderExtension::V3(v3::HeaderExtension {
+			commitment: KateCommitment {
+				rows,
+				cols: 4,
+				data_root: H256::zero(),
+				commitment: vec![],
+			},
+			app_lookup: CompactDataLookup {
+				size: 0,
+				index: vec![],
+			},
+		})
+	}
+
+	fn genesis() -> Header {
+		Header {
+			parent_hash: H256::zero(),
+			number: 0,
+			state_root: H256::zero(),
+			extrinsics_root: H256::zero(),
+			digest: Digest { logs: vec![] },
+			extension: extension(1),
+		}
+	}
+
+	#[derive(Default)]
+	struct TestExecutor {
+		applied: Vec<Vec<u8>>,
+	}
+
+	impl Executor for TestExecutor {
+		fn initialize_block(&mut self, _: &Header) -> Result<()> {
+			Ok(())
+		}
+
+		fn inherent_extrinsics(&mut self, inherent_data: &InherentData) -> Result<Vec<Vec<u8>>> {
+			let timestamp: Option<u64> = inherent_data.get(&TIMESTAMP_INHERENT_IDENTIFIER)?;
+			Ok(timestamp.into_iter().map(|t| t.encode()).collect())
+		}
+
+		fn apply_extrinsic(&mut self, extrinsic: &[u8]) -> Result<()> {
+			if extrinsic.is_empty() {
+				return Err(eyre!("Empty extrinsic"));
+			}
+			self.applied.push(extrinsic.to_vec());
+			Ok(())
+		}
+
+		fn finalize_block(&mut self) -> Result<FinalizedState> {
+			Ok(FinalizedState {
+				state_root: blake2_256(&self.applied.concat()).into(),
+				extension: extension(2),
+				logs: vec![],
+			})
+		}
+	}
+
+	struct TestSeal;
+
+	impl SealProvider for TestSeal {
+		fn pre_runtime_digest(&self, parent: &Header) -> Result<Option<DigestItem>> {
+			Ok(Some(DigestItem::PreRuntime(
+				*b"TEST",
+				(parent.number + 1).encode(),
+			)))
+		}
+
+		fn seal(&self, pre_hash: H256) -> Result<DigestItem> {
+			Ok(DigestItem::Seal(*b"TEST", pre_hash.0.to_vec()))
+		}
+	}
+
+	#[test]
+	fn build_sealed_block() {
+		let parent = genesis();
+		let parent_hash: H256 = Encode::using_encoded(&parent, blake2_256).into();
+		let mut builder = BlockBuilder::new(parent, TestExecutor::default());
+		builder.push(vec![1, 2, 3]);
+		builder.push(vec![]);
+		let inherent_data =
+			create_inherent_data(&[&TimestampProvider { timestamp: 6_000 }]).unwrap();
+		let block = builder.build(&inherent_data, &TestSeal).unwrap();
+
+		assert_eq!(block.header.number, 1);
+		assert_eq!(block.header.parent_hash, parent_hash);
+		assert_eq!(block.extrinsics, vec![6_000u64.encode(), vec![1, 2, 3]]);
+		assert_eq!(
+			block.header.extrinsics_root,
+			extrinsics_root(&block.extrinsics)
+		);
+		assert_eq!(block.header.digest.logs.len(), 2);
+		assert!(matches!(
+			block.header.digest.logs.last(),
+			Some(DigestItem::Seal(id, _)) if id == b"TEST"
+		));
+	}
+
+	#[test]
+	fn extrinsics_root_depends_on_order() {
+		let root = extrinsics_root(&[vec![1], vec![2]]);
+		assert_ne!(root, extrinsics_root(&[vec![2], vec![1]]));
+		assert_eq!(root, extrinsics_root(&[vec![1], vec![2]]));
+	}
+}
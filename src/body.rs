@@ -0,0 +1,253 @@
+//! Incremental decoding of SCALE encoded block bodies.
+//!
+//! Block body is encoded as a compact prefixed vector of compact prefixed extrinsics. Since blocks can carry
+//! megabytes of data submissions, extrinsics are decoded one at a time from the source, without buffering the
+//! whole body. Both blocking ([`BodyDecoder`]) and async ([`decode_body_stream`]) sources are supported.
+//!
+//! # Limits
+//!
+//! Length prefixes are validated against [`BodyLimits`] before anything is allocated, so malicious prefixes
+//! cannot trigger large allocations.
+
+use avail_subxt::primitives::Header;
+use codec::{Compact, CompactLen, Decode, Encode, IoReader};
+use color_eyre::{eyre::eyre, Result};
+use futures::Stream;
+use std::io::Read;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Block with SCALE encoded extrinsics
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct Block {
+	pub header: Header,
+	pub extrinsics: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BodyLimits {
+	/// Maximum size of the single encoded extrinsic
+	pub max_extrinsic_size: usize,
+	/// Maximum size of the whole encoded body
+	pub max_body_size: usize,
+}
+
+impl Default for BodyLimits {
+	fn default() -> Self {
+		BodyLimits {
+			max_extrinsic_size: 5 * 1024 * 1024,
+			max_body_size: 10 * 1024 * 1024,
+		}
+	}
+}
+
+/// Tracks decoded size and validates length prefixes against the limits.
+struct Guard {
+	limits: BodyLimits,
+	remaining: u32,
+	decoded_size: usize,
+}
+
+impl Guard {
+	fn new(limits: BodyLimits, count: u32, prefix_size: usize) -> Result<Self> {
+		// Every extrinsic takes at least one byte (its length prefix)
+		if count as usize > limits.max_body_size {
+			return Err(eyre!(
+				"Invalid extrinsics count {count}, maximum body size is {}",
+				limits.max_body_size
+			));
+		}
+		Ok(Guard {
+			limits,
+			remaining: count,
+			decoded_size: prefix_size,
+		})
+	}
+
+	fn check(&mut self, length: u32, prefix_size: usize) -> Result<usize> {
+		let length = length as usize;
+		if length > self.limits.max_extrinsic_size {
+			return Err(eyre!(
+				"Extrinsic size {length} exceeds maximum {}",
+				self.limits.max_extrinsic_size
+			));
+		}
+		self.decoded_size += prefix_size + length;
+		if self.decoded_size > self.limits.max_body_size {
+			return Err(eyre!(
+				"Body size exceeds maximum {}",
+				self.limits.max_body_size
+			));
+		}
+		self.remaining -= 1;
+		Ok(length)
+	}
+}
+
+fn compact_size(value: u32) -> usize {
+	Compact::<u32>::compact_len(&value)
+}
+
+/// Decodes extrinsics one at a time from a blocking source
+pub struct BodyDecoder<R: Read> {
+	reader: R,
+	guard: Guard,
+	failed: bool,
+}
+
+impl<R: Read> BodyDecoder<R> {
+	/// Creates decoder, reading the extrinsics count from the source.
+	pub fn new(mut reader: R, limits: BodyLimits) -> Result<Self> {
+		let count = Compact::<u32>::decode(&mut IoReader(&mut reader))?.0;
+		let guard = Guard::new(limits, count, compact_size(count))?;
+		Ok(BodyDecoder {
+			reader,
+			guard,
+			failed: false,
+		})
+	}
+
+	/// Number of extrinsics which are not decoded yet
+	pub fn remaining(&self) -> u32 {
+		self.guard.remaining
+	}
+
+	fn decode_next(&mut self) -> Result<Vec<u8>> {
+		let length = Compact::<u32>::decode(&mut IoReader(&mut self.reader))?.0;
+		let length = self.guard.check(length, compact_size(length))?;
+		let mut extrinsic = vec![0u8; length];
+		self.reader.read_exact(&mut extrinsic)?;
+		Ok(extrinsic)
+	}
+}
+
+impl<R: Read> Iterator for BodyDecoder<R> {
+	type Item = Result<Vec<u8>>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.failed || self.guard.remaining == 0 {
+			return None;
+		}
+		let result = self.decode_next();
+		self.failed = result.is_err();
+		Some(result)
+	}
+}
+
+async fn read_compact<R: AsyncRead + Unpin>(reader: &mut R) -> Result<(u32, usize)> {
+	let first = reader.read_u8().await?;
+	let size = match first & 0b11 {
+		0b00 => 1,
+		0b01 => 2,
+		0b10 => 4,
+		_ => (first >> 2) as usize + 5,
+	};
+	if size > 5 {
+		return Err(eyre!("Compact prefix of {size} bytes is out of range"));
+	}
+	let mut encoded = vec![first; size];
+	reader.read_exact(&mut encoded[1..]).await?;
+	let value = Compact::<u32>::decode(&mut &encoded[..])?.0;
+	Ok((value, size))
+}
+
+/// Decodes extrinsics one at a time from an async source. Stream ends after the first error.
+pub fn decode_body_stream<R: AsyncRead + Unpin>(
+	mut reader: R,
+	limits: BodyLimits,
+) -> impl Stream<Item = Result<Vec<u8>>> {
+	async_stream::stream! {
+		let mut guard = match read_compact(&mut reader).await {
+			Ok((count, size)) => match Guard::new(limits, count, size) {
+				Ok(guard) => guard,
+				Err(error) => {
+					yield Err(error);
+					return;
+				},
+			},
+			Err(error) => {
+				yield Err(error);
+				return;
+			},
+		};
+
+		while guard.remaining > 0 {
+			let length = match read_compact(&mut reader).await {
+				Ok((length, size)) => guard.check(length, size),
+				Err(error) => Err(error),
+			};
+			let length = match length {
+				Ok(length) => length,
+				Err(error) => {
+					yield Err(error);
+					return;
+				},
+			};
+			let mut extrinsic = vec![0u8; length];
+			if let Err(error) = reader.read_exact(&mut extrinsic).await {
+				yield Err(error.into());
+				return;
+			}
+			yield Ok(extrinsic);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::StreamExt;
+
+	fn body() -> Vec<Vec<u8>> {
+		vec![vec![1; 10], vec![], vec![2; 300]]
+	}
+
+	#[test]
+	fn decode_body() {
+		let encoded = body().encode();
+		let decoder = BodyDecoder::new(&encoded[..], BodyLimits::default()).unwrap();
+		assert_eq!(decoder.remaining(), 3);
+		let extrinsics = decoder.collect::<Result<Vec<_>>>().unwrap();
+		assert_eq!(extrinsics, body());
+	}
+
+	#[test]
+	fn extrinsic_size_exceeded() {
+		let encoded = body().encode();
+		let limits = BodyLimits {
+			max_extrinsic_size: 100,
+			..Default::default()
+		};
+		let results = BodyDecoder::new(&encoded[..], limits)
+			.unwrap()
+			.collect::<Vec<_>>();
+		assert_eq!(results.len(), 3);
+		assert!(results[2].is_err());
+	}
+
+	#[test]
+	fn absurd_count_fails() {
+		let encoded = Compact(u32::MAX).encode();
+		assert!(BodyDecoder::new(&encoded[..], BodyLimits::default()).is_err());
+	}
+
+	#[tokio::test]
+	async fn decode_body_async() {
+		let encoded = body().encode();
+		let extrinsics = decode_body_stream(&encoded[..], BodyLimits::default())
+			.collect::<Vec<_>>()
+			.await
+			.into_iter()
+			.collect::<Result<Vec<_>>>()
+			.unwrap();
+		assert_eq!(extrinsics, body());
+	}
+
+	#[tokio::test]
+	async fn truncated_body_async() {
+		let encoded = body().encode();
+		let results = decode_body_stream(&encoded[..50], BodyLimits::default())
+			.collect::<Vec<_>>()
+			.await;
+		assert!(results.last().unwrap().is_err());
+	}
+}
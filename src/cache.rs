@@ -0,0 +1,307 @@
+//! Size-bounded LRU caches for decoded headers.
+//!
+//! Each cache tracks hits and misses, which are exposed as hit rate metrics. Capacities are configured
+//! through [`CacheConfig`] and can be tuned at runtime with [`Cache::resize`]. Caches created with
+//! [`Caches::with_budget`] also account their entries in the [`MemoryBudget`], and evict least recently used entries
+//! when the budget is exceeded.
+
+use avail_subxt::{primitives::Header, utils::H256};
+use codec::Encode;
+use color_eyre::{eyre::WrapErr, Result};
+use lru::LruCache;
+use std::{
+	hash::Hash,
+	mem,
+	num::NonZeroUsize,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc, Mutex,
+	},
+};
+
+use crate::{
+	memory::{Component, MemoryBudget, Reclaim},
+	telemetry::{MetricValue, Metrics},
+	types::RuntimeConfig,
+};
+
+/// Cache configuration (see [RuntimeConfig] for details), capacities are checked by [`RuntimeConfig::validate`]
+#[derive(Clone, Copy, Debug)]
+pub struct CacheConfig {
+	pub header_cache_capacity: NonZeroUsize,
+}
+
+impl From<&RuntimeConfig> for CacheConfig {
+	fn from(val: &RuntimeConfig) -> Self {
+		CacheConfig {
+			header_cache_capacity: NonZeroUsize::new(val.header_cache_capacity)
+				.expect("Header cache capacity is validated"),
+		}
+	}
+}
+
+/// Accounting of the cached entries in the memory budget
+struct Accounting<V> {
+	budget: Arc<MemoryBudget>,
+	component: Component,
+	/// Estimated memory size of the entry
+	size: fn(&V) -> usize,
+}
+
+pub struct Cache<K: Hash + Eq, V: Clone> {
+	inner: Mutex<LruCache<K, V>>,
+	hits: AtomicU64,
+	misses: AtomicU64,
+	accounting: Option<Accounting<V>>,
+}
+
+impl<K: Hash + Eq, V: Clone> Cache<K, V> {
+	pub fn new(capacity: NonZeroUsize) -> Self {
+		Cache {
+			inner: Mutex::new(LruCache::new(capacity)),
+			hits: AtomicU64::new(0),
+			misses: AtomicU64::new(0),
+			accounting: None,
+		}
+	}
+
+	/// Creates cache which accounts its entries in the memory budget, as the given component
+	pub fn with_budget(
+		capacity: NonZeroUsize,
+		budget: Arc<MemoryBudget>,
+		component: Component,
+		size: fn(&V) -> usize,
+	) -> Self {
+		Cache {
+			inner: Mutex::new(LruCache::new(capacity)),
+			hits: AtomicU64::new(0),
+			misses: AtomicU64::new(0),
+			accounting: Some(Accounting {
+				budget,
+				component,
+				size,
+			}),
+		}
+	}
+
+	fn entry_size(&self, key: &K, value: &V) -> usize {
+		self.accounting.as_ref().map_or(0, |accounting| {
+			mem::size_of_val(key) + (accounting.size)(value)
+		})
+	}
+
+	fn free(&self, bytes: usize) {
+		if let Some(accounting) = &self.accounting {
+			accounting.budget.free(accounting.component, bytes);
+		}
+	}
+
+	/// Evicts least recently used entries, until the number of bytes is released or the cache is empty
+	pub fn evict(&self, bytes: usize) -> usize {
+		let mut released = 0;
+		let mut inner = self.inner.lock().unwrap();
+		while released < bytes {
+			let Some((key, value)) = inner.pop_lru() else {
+				break;
+			};
+			released += self.entry_size(&key, &value);
+		}
+		drop(inner);
+		self.free(released);
+		released
+	}
+
+	pub fn get(&self, key: &K) -> Option<V> {
+		let value = self.inner.lock().unwrap().get(key).cloned();
+		let counter = if value.is_some() {
+			&self.hits
+		} else {
+			&self.misses
+		};
+		counter.fetch_add(1, Ordering::Relaxed);
+		value
+	}
+
+	pub fn put(&self, key: K, value: V) {
+		let size = self.entry_size(&key, &value);
+		let replaced = self.inner.lock().unwrap().push(key, value);
+		let Some(accounting) = &self.accounting else {
+			return;
+		};
+		// Replaced entry is either the previous value of the key, or the evicted least recently used entry
+		if let Some((key, value)) = replaced {
+			self.free(self.entry_size(&key, &value));
+		}
+		accounting.budget.allocate(accounting.component, size);
+	}
+
+	/// Returns cached value, or inserts value created by the given function.
+	pub fn get_or_try_insert(&self, key: K, create: impl FnOnce() -> Result<V>) -> Result<V> {
+		if let Some(value) = self.get(&key) {
+			return Ok(value);
+		}
+		let value = create()?;
+		self.put(key, value.clone());
+		Ok(value)
+	}
+
+	/// Changes the cache capacity, evicting least recently used entries if needed.
+	pub fn resize(&self, capacity: NonZeroUsize) {
+		let mut inner = self.inner.lock().unwrap();
+		let mut released = 0;
+		while inner.len() > capacity.get() {
+			if let Some((key, value)) = inner.pop_lru() {
+				released += self.entry_size(&key, &value);
+			}
+		}
+		inner.resize(capacity);
+		drop(inner);
+		self.free(released);
+	}
+
+	pub fn len(&self) -> usize {
+		self.inner.lock().unwrap().len()
+	}
+
+	pub fn clear(&self) {
+		let mut inner = self.inner.lock().unwrap();
+		let released = inner
+			.iter()
+			.map(|(key, value)| self.entry_size(key, value))
+			.sum();
+		inner.clear();
+		drop(inner);
+		self.free(released);
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Ratio of hits in all lookups, in percentages.
+	pub fn hit_rate(&self) -> f64 {
+		let hits = self.hits.load(Ordering::Relaxed);
+		let misses = self.misses.load(Ordering::Relaxed);
+		if hits + misses == 0 {
+			return 0.0;
+		}
+		100.0 * hits as f64 / (hits + misses) as f64
+	}
+}
+
+impl<K: Hash + Eq, V: Clone> Drop for Cache<K, V> {
+	fn drop(&mut self) {
+		self.clear();
+	}
+}
+
+pub struct Caches {
+	/// Decoded headers by header hash
+	pub headers: Cache<H256, Header>,
+}
+
+impl Caches {
+	pub fn new(cfg: CacheConfig) -> Self {
+		Caches {
+			headers: Cache::new(cfg.header_cache_capacity),
+		}
+	}
+
+	/// Creates caches which account their entries in the memory budget, and are evicted when it's exceeded
+	pub fn with_budget(cfg: CacheConfig, budget: &Arc<MemoryBudget>) -> Arc<Self> {
+		let caches = Arc::new(Caches {
+			headers: Cache::with_budget(
+				cfg.header_cache_capacity,
+				budget.clone(),
+				Component::HeaderCache,
+				|header| mem::size_of_val(header) + header.encoded_size(),
+			),
+		});
+		let reclaimer: Arc<dyn Reclaim> = caches.clone();
+		budget.register(Arc::downgrade(&reclaimer));
+		caches
+	}
+
+	pub fn resize(&self, cfg: CacheConfig) {
+		self.headers.resize(cfg.header_cache_capacity);
+	}
+
+	/// Removes all cached entries (e.g. after runtime upgrade, since decoding of cached values can change).
+	pub fn clear(&self) {
+		self.headers.clear();
+	}
+
+	pub async fn record_metrics(&self, metrics: &impl Metrics) -> Result<()> {
+		metrics
+			.record(MetricValue::HeaderCacheHitRate(self.headers.hit_rate()))
+			.await
+			.wrap_err("Failed to record header cache hit rate")
+	}
+}
+
+impl Reclaim for Caches {
+	fn reclaim(&self, bytes: usize) -> usize {
+		self.headers.evict(bytes)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn capacity(value: usize) -> NonZeroUsize {
+		NonZeroUsize::new(value).unwrap()
+	}
+
+	#[test]
+	fn hit_rate() {
+		let cache = Cache::<u32, u32>::new(capacity(2));
+		assert_eq!(cache.hit_rate(), 0.0);
+		cache.put(1, 1);
+		assert_eq!(cache.get(&1), Some(1));
+		assert_eq!(cache.get(&2), None);
+		assert_eq!(cache.hit_rate(), 50.0);
+	}
+
+	#[test]
+	fn resize_evicts_entries() {
+		let cache = Cache::<u32, u32>::new(capacity(3));
+		for key in 1..=3 {
+			cache.put(key, key);
+		}
+		cache.resize(capacity(1));
+		assert_eq!(cache.len(), 1);
+		assert_eq!(cache.get(&3), Some(3));
+	}
+
+	#[test]
+	fn evict_over_budget() {
+		let header = crate::simulation::header(1, H256::zero(), 0);
+		let entry_size = mem::size_of::<H256>() + mem::size_of::<Header>() + header.encoded_size();
+		let budget = MemoryBudget::new(Some(2 * entry_size));
+		let cfg = CacheConfig {
+			header_cache_capacity: capacity(8),
+		};
+		let caches = Caches::with_budget(cfg, &budget);
+		for hash in 0..4u8 {
+			caches.headers.put(H256::repeat_byte(hash), header.clone());
+		}
+		// Least recently used headers are evicted
+		assert_eq!(caches.headers.len(), 2);
+		assert!(caches.headers.get(&H256::repeat_byte(3)).is_some());
+		assert_eq!(budget.usage(Component::HeaderCache), 2 * entry_size);
+
+		caches.headers.clear();
+		assert_eq!(budget.used(), 0);
+	}
+
+	#[test]
+	fn get_or_try_insert() {
+		let cache = Cache::<u32, u32>::new(capacity(2));
+		assert_eq!(cache.get_or_try_insert(1, || Ok(10)).unwrap(), 10);
+		assert_eq!(cache.get_or_try_insert(1, || Ok(20)).unwrap(), 10);
+		assert!(cache
+			.get_or_try_insert(2, || Err(color_eyre::eyre::eyre!("Failed")))
+			.is_err());
+	}
+}
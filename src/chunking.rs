@@ -0,0 +1,304 @@
+//! Chunking protocol for blobs which exceed the block limits, with integrity verification on reassembly.
+//!
+//! # Protocol
+//!
+//! * Blob is split into chunk blobs, each one fitting into a single extrinsic, prefixed with [`CHUNK_MAGIC`] and
+//!   carrying the checksum of the whole blob and the sequence number of the chunk
+//! * Chunk blobs are submitted first (across as many blocks as needed), and the [`Manifest`] (prefixed with
+//!   [`MANIFEST_MAGIC`]) is submitted once all chunks are finalized, with the checksums, lengths and locations of
+//!   the chunks
+//! * Blob is reassembled from the manifest pointer: chunks are fetched from their locations, and verified against
+//!   the manifest checksums and sequence numbers, and the reassembled blob against the blob checksum
+//!
+//! Checksums are Keccak-256 hashes, same as the data root leaves.
+
+use avail_subxt::utils::H256;
+use codec::{Decode, Encode};
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use x25519_dalek::StaticSecret;
+
+use crate::{
+	data::Database,
+	eth_bridge::keccak256,
+	inbox::{self, BlobPointer, ChunkPointer},
+	submission::DataSubmitter,
+	verified_rpc::Client,
+};
+
+pub const CHUNK_MAGIC: [u8; 4] = *b"AVCH";
+pub const MANIFEST_MAGIC: [u8; 4] = *b"AVMF";
+
+/// Maximum length of the encoded chunk blob, excluding the chunk data (with up to 5 bytes of the length prefix)
+pub const CHUNK_OVERHEAD: usize = CHUNK_MAGIC.len() + 32 + 4 + 5;
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct ChunkBlob {
+	/// Checksum of the whole blob
+	pub blob_checksum: H256,
+	pub sequence: u32,
+	pub data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct ChunkEntry {
+	pub checksum: H256,
+	pub length: u32,
+	/// Extrinsic of the chunk blob
+	pub pointer: ChunkPointer,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+	pub blob_checksum: H256,
+	pub length: u64,
+	/// Chunks in the sequence order
+	pub chunks: Vec<ChunkEntry>,
+}
+
+fn encode_with_magic(magic: &[u8], value: &impl Encode) -> Vec<u8> {
+	let mut encoded = magic.to_vec();
+	value.encode_to(&mut encoded);
+	encoded
+}
+
+fn decode_with_magic<T: Decode>(magic: &[u8], data: &[u8]) -> Result<T> {
+	let Some(mut encoded) = data.strip_prefix(magic) else {
+		return Err(eyre!(
+			"Blob has no {} prefix",
+			String::from_utf8_lossy(magic)
+		));
+	};
+	T::decode(&mut encoded).wrap_err("Failed to decode blob")
+}
+
+impl ChunkBlob {
+	pub fn encode_blob(&self) -> Vec<u8> {
+		encode_with_magic(&CHUNK_MAGIC, self)
+	}
+
+	pub fn decode_blob(data: &[u8]) -> Result<Self> {
+		decode_with_magic(&CHUNK_MAGIC, data)
+	}
+}
+
+impl Manifest {
+	pub fn encode_blob(&self) -> Vec<u8> {
+		encode_with_magic(&MANIFEST_MAGIC, self)
+	}
+
+	pub fn decode_blob(data: &[u8]) -> Result<Self> {
+		decode_with_magic(&MANIFEST_MAGIC, data)
+	}
+}
+
+/// Splits blob into chunk blobs, with up to `chunk_size` bytes of data
+pub fn split(blob: &[u8], chunk_size: usize) -> Result<Vec<ChunkBlob>> {
+	if blob.is_empty() || chunk_size == 0 {
+		return Err(eyre!("Blob and chunk size must not be empty"));
+	}
+	let blob_checksum = keccak256(blob);
+	Ok(blob
+		.chunks(chunk_size)
+		.enumerate()
+		.map(|(sequence, data)| ChunkBlob {
+			blob_checksum,
+			sequence: sequence as u32,
+			data: data.to_vec(),
+		})
+		.collect())
+}
+
+/// Creates manifest of the submitted chunks, with the pointers in the chunks order
+pub fn manifest(chunks: &[ChunkBlob], pointers: &[ChunkPointer]) -> Result<Manifest> {
+	let Some(first) = chunks.first() else {
+		return Err(eyre!("Manifest has no chunks"));
+	};
+	if chunks.len() != pointers.len() {
+		return Err(eyre!(
+			"Chunks count {} doesn't match pointers count {}",
+			chunks.len(),
+			pointers.len()
+		));
+	}
+	let entries = chunks
+		.iter()
+		.zip(pointers)
+		.map(|(chunk, &pointer)| ChunkEntry {
+			checksum: keccak256(&chunk.data),
+			length: chunk.data.len() as u32,
+			pointer,
+		})
+		.collect::<Vec<_>>();
+	Ok(Manifest {
+		blob_checksum: first.blob_checksum,
+		length: chunks.iter().map(|chunk| chunk.data.len() as u64).sum(),
+		chunks: entries,
+	})
+}
+
+/// Reassembles blob from the chunks in the manifest order, and verifies its integrity
+pub fn reassemble(manifest: &Manifest, chunks: Vec<ChunkBlob>) -> Result<Vec<u8>> {
+	if chunks.len() != manifest.chunks.len() {
+		return Err(eyre!(
+			"Expected {} chunks, got {}",
+			manifest.chunks.len(),
+			chunks.len()
+		));
+	}
+	// Manifest is not trusted, so its length is not used for the allocation
+	let chunks_length = manifest.chunks.iter().try_fold(0u64, |length, entry| {
+		length.checked_add(entry.length as u64)
+	});
+	if chunks_length != Some(manifest.length) {
+		return Err(eyre!(
+			"Manifest length {} doesn't match lengths of the chunks",
+			manifest.length
+		));
+	}
+	let mut blob = vec![];
+	for (sequence, (entry, chunk)) in manifest.chunks.iter().zip(chunks).enumerate() {
+		if chunk.sequence != sequence as u32 || chunk.blob_checksum != manifest.blob_checksum {
+			return Err(eyre!(
+				"Chunk {} of blob {:?} is not chunk {sequence} of the manifest",
+				chunk.sequence,
+				chunk.blob_checksum
+			));
+		}
+		if chunk.data.len() != entry.length as usize || keccak256(&chunk.data) != entry.checksum {
+			return Err(eyre!("Chunk {sequence} doesn't match manifest checksum"));
+		}
+		blob.extend(chunk.data);
+	}
+	if blob.len() as u64 != manifest.length || keccak256(&blob) != manifest.blob_checksum {
+		return Err(eyre!("Reassembled blob doesn't match manifest checksum"));
+	}
+	Ok(blob)
+}
+
+/// Submits blob in chunks, and returns pointer to the manifest
+pub async fn submit(submitter: &DataSubmitter, app_id: u32, blob: &[u8]) -> Result<BlobPointer> {
+	let chunk_size = submitter
+		.limits()
+		.max_extrinsic_data
+		.checked_sub(CHUNK_OVERHEAD + submitter.blob_overhead())
+		.filter(|&size| size > 0)
+		.ok_or_else(|| eyre!("Maximum extrinsic data length is too small for chunks"))?;
+	let chunks = split(blob, chunk_size)?;
+	let blobs = chunks.iter().map(ChunkBlob::encode_blob).collect();
+	let receipts = submitter
+		.submit_data_batch(app_id, blobs)
+		.await?
+		.into_result()?;
+	let pointers = receipts
+		.iter()
+		.map(|receipts| match receipts.as_slice() {
+			[receipt] => Ok(ChunkPointer::from(receipt)),
+			_ => Err(eyre!("Chunk is not submitted in a single extrinsic")),
+		})
+		.collect::<Result<Vec<_>>>()?;
+
+	let manifest = manifest(&chunks, &pointers)?;
+	let receipts = submitter
+		.submit_data(app_id, manifest.encode_blob())
+		.await?;
+	info!(app_id, chunks = chunks.len(), "Chunked blob submitted");
+	Ok(BlobPointer {
+		app_id,
+		chunks: receipts.iter().map(ChunkPointer::from).collect(),
+	})
+}
+
+/// Fetches manifest and chunks of the blob, and reassembles the blob
+pub async fn fetch(
+	client: &impl Client,
+	db: &impl Database,
+	key: Option<&StaticSecret>,
+	manifest_pointer: &BlobPointer,
+) -> Result<Vec<u8>> {
+	let mut encoded = vec![];
+	for chunk in &manifest_pointer.chunks {
+		encoded.extend(inbox::fetch_chunk(client, db, manifest_pointer.app_id, chunk).await?);
+	}
+	let manifest = Manifest::decode_blob(&inbox::decode_submitted(key, &encoded)?)?;
+
+	let mut chunks = Vec::with_capacity(manifest.chunks.len());
+	for (sequence, entry) in manifest.chunks.iter().enumerate() {
+		let data = inbox::fetch_chunk(client, db, manifest_pointer.app_id, &entry.pointer).await?;
+		let chunk = ChunkBlob::decode_blob(&inbox::decode_submitted(key, &data)?)?;
+		// Fails early, before the rest of the chunks is fetched
+		if chunk.data.len() != entry.length as usize {
+			return Err(eyre!(
+				"Chunk {sequence} length {} doesn't match manifest length {}",
+				chunk.data.len(),
+				entry.length
+			));
+		}
+		chunks.push(chunk);
+	}
+	reassemble(&manifest, chunks)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use test_case::test_case;
+
+	fn pointer(extrinsic_index: u32) -> ChunkPointer {
+		ChunkPointer {
+			block_number: 1,
+			block_hash: H256::repeat_byte(1),
+			extrinsic_index,
+		}
+	}
+
+	fn chunked(blob: &[u8]) -> (Manifest, Vec<ChunkBlob>) {
+		let chunks = split(blob, 4).unwrap();
+		let pointers = (0..chunks.len() as u32).map(pointer).collect::<Vec<_>>();
+		(manifest(&chunks, &pointers).unwrap(), chunks)
+	}
+
+	#[test]
+	fn split_and_reassemble() {
+		let blob = (0..10).collect::<Vec<u8>>();
+		let (manifest, chunks) = chunked(&blob);
+		assert_eq!(chunks.len(), 3);
+		assert_eq!(manifest.length, 10);
+		assert_eq!(manifest.chunks[2].length, 2);
+
+		let encoded = chunks
+			.iter()
+			.map(ChunkBlob::encode_blob)
+			.collect::<Vec<_>>();
+		let decoded = encoded
+			.iter()
+			.map(|blob| ChunkBlob::decode_blob(blob))
+			.collect::<Result<Vec<_>>>()
+			.unwrap();
+		assert!(Manifest::decode_blob(&encoded[0]).is_err());
+		let manifest = Manifest::decode_blob(&manifest.encode_blob()).unwrap();
+		assert_eq!(reassemble(&manifest, decoded).unwrap(), blob);
+	}
+
+	#[test_case(|chunks| { chunks.swap(0, 1); } ; "out of order")]
+	#[test_case(|chunks| { chunks.pop(); } ; "missing chunk")]
+	#[test_case(|chunks| { chunks[1].data[0] ^= 1; } ; "corrupted chunk")]
+	#[test_case(|chunks| { chunks[1].blob_checksum = H256::zero(); } ; "chunk of other blob")]
+	fn reassemble_fails(tamper: fn(&mut Vec<ChunkBlob>)) {
+		let (manifest, mut chunks) = chunked(&(0..10).collect::<Vec<u8>>());
+		tamper(&mut chunks);
+		assert!(reassemble(&manifest, chunks).is_err());
+	}
+
+	#[test_case(|manifest| { manifest.length = u64::MAX; } ; "length of manifest")]
+	#[test_case(|manifest| { manifest.chunks[1].length = 5; } ; "length of chunk")]
+	fn invalid_manifest(tamper: fn(&mut Manifest)) {
+		let (mut manifest, chunks) = chunked(&(0..10).collect::<Vec<u8>>());
+		tamper(&mut manifest);
+		assert!(reassemble(&manifest, chunks).is_err());
+	}
+}
@@ -0,0 +1,241 @@
+//! Top-level light client configuration.
+//!
+//! [`ClientBuilder`] collects configuration from chain, database, network, sampling and pruning settings in one place,
+//! and rejects inconsistent combinations before any of the subsystems is started. Result is validated [`Config`],
+//! which dereferences to [`RuntimeConfig`], so it can be used wherever runtime configuration is expected.
+//! Running client is controlled through the [`ClientHandle`].
+
+use color_eyre::Result;
+use kate_recovery::matrix::Partition;
+use std::ops::Deref;
+
+use crate::{
+	data::Database,
+	subscriptions::Subscriptions,
+	supervisor::Supervisor,
+	types::{KademliaMode, MultiaddrConfig, P2PTransport, RuntimeConfig, SecretKey},
+};
+
+/// Validated light client configuration
+#[derive(Clone, Debug)]
+pub struct Config(RuntimeConfig);
+
+impl Config {
+	pub fn into_inner(self) -> RuntimeConfig {
+		self.0
+	}
+}
+
+impl Deref for Config {
+	type Target = RuntimeConfig;
+
+	fn deref(&self) -> &Self::Target {
+		&self.0
+	}
+}
+
+impl TryFrom<RuntimeConfig> for Config {
+	type Error = color_eyre::Report;
+
+	fn try_from(cfg: RuntimeConfig) -> Result<Self> {
+		cfg.validate()?;
+		Ok(Config(cfg))
+	}
+}
+
+/// Builder of the light client [`Config`], starting from [`RuntimeConfig`] defaults
+#[derive(Clone, Debug, Default)]
+pub struct ClientBuilder {
+	cfg: RuntimeConfig,
+}
+
+impl ClientBuilder {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Starts from the existing configuration (e.g. loaded from the configuration file)
+	pub fn from_config(cfg: RuntimeConfig) -> Self {
+		ClientBuilder { cfg }
+	}
+
+	/// Sets chain to connect to, with its genesis hash, full node endpoints and bootstrap nodes
+	pub fn chain(
+		mut self,
+		genesis_hash: impl Into<String>,
+		full_node_ws: Vec<String>,
+		bootstraps: Vec<MultiaddrConfig>,
+	) -> Self {
+		self.cfg.genesis_hash = genesis_hash.into();
+		self.cfg.full_node_ws = full_node_ws;
+		self.cfg.bootstraps = bootstraps;
+		self
+	}
+
+	pub fn database_path(mut self, path: impl Into<String>) -> Self {
+		self.cfg.avail_path = path.into();
+		self
+	}
+
+	/// Sets secret key of the libp2p keypair
+	pub fn network_key(mut self, secret_key: SecretKey) -> Self {
+		self.cfg.secret_key = Some(secret_key);
+		self
+	}
+
+	pub fn ports(mut self, p2p_port: u16, http_server_port: u16) -> Self {
+		self.cfg.port = p2p_port;
+		self.cfg.http_server_port = http_server_port;
+		self
+	}
+
+	pub fn kademlia_mode(mut self, mode: KademliaMode) -> Self {
+		self.cfg.operation_mode = mode;
+		self
+	}
+
+	/// Sets confidence threshold used to calculate number of sampled cells
+	pub fn confidence(mut self, confidence: f64) -> Self {
+		self.cfg.confidence = confidence;
+		self
+	}
+
+	/// Runs client in fat client mode, fetching given block matrix partition
+	pub fn partition(mut self, partition: Partition) -> Self {
+		self.cfg.block_matrix_partition = Some(partition);
+		self
+	}
+
+	pub fn app_id(mut self, app_id: u32) -> Self {
+		self.cfg.app_id = Some(app_id);
+		self
+	}
+
+	pub fn sync_start_block(mut self, block_number: u32) -> Self {
+		self.cfg.sync_start_block = Some(block_number);
+		self
+	}
+
+	/// Sets DHT records TTL, publication and replication intervals, and store pruning interval in blocks
+	pub fn pruning(
+		mut self,
+		record_ttl: u64,
+		publication_interval: u32,
+		replication_interval: u32,
+		store_pruning_interval: u32,
+	) -> Self {
+		self.cfg.kad_record_ttl = record_ttl;
+		self.cfg.publication_interval = publication_interval;
+		self.cfg.replication_interval = replication_interval;
+		self.cfg.store_pruning_interval = store_pruning_interval;
+		self
+	}
+
+	pub fn disable_rpc(mut self, disable_rpc: bool) -> Self {
+		self.cfg.disable_rpc = disable_rpc;
+		self
+	}
+
+	pub fn sync_finality(mut self, enable: bool) -> Self {
+		self.cfg.sync_finality_enable = enable;
+		self
+	}
+
+	pub fn ws_transport(mut self, enable: bool) -> Self {
+		self.cfg.ws_transport_enable = enable;
+		self
+	}
+
+	pub fn transports(mut self, transports: Vec<P2PTransport>) -> Self {
+		self.cfg.transports = transports;
+		self
+	}
+
+	/// Watches hex encoded storage keys, verified changes are received with the storage changes subscription
+	pub fn watch_storage(mut self, keys: Vec<String>) -> Self {
+		self.cfg.watched_storage_keys = keys;
+		self
+	}
+
+	pub fn build(self) -> Result<Config> {
+		Config::try_from(self.cfg)
+	}
+}
+
+/// Handle of the running light client, with tasks spawned by the supervisor
+pub struct ClientHandle<D: Database> {
+	supervisor: Supervisor,
+	subscriptions: Subscriptions,
+	db: D,
+}
+
+impl<D: Database> ClientHandle<D> {
+	pub fn new(supervisor: Supervisor, subscriptions: Subscriptions, db: D) -> Self {
+		ClientHandle {
+			supervisor,
+			subscriptions,
+			db,
+		}
+	}
+
+	pub fn supervisor(&self) -> &Supervisor {
+		&self.supervisor
+	}
+
+	/// Subscriptions to the events of the running client
+	pub fn subscriptions(&self) -> &Subscriptions {
+		&self.subscriptions
+	}
+
+	/// Triggers shutdown (unless it is already triggered), and resolves once all tasks are finished
+	/// and the database is flushed.
+	pub async fn shutdown(self) -> Result<()> {
+		let _ = self
+			.supervisor
+			.shutdown()
+			.trigger_shutdown("Client shutdown requested".to_string());
+		self.supervisor.join().await;
+		self.db.flush()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use test_case::test_case;
+
+	fn builder() -> ClientBuilder {
+		let bootstrap =
+			"/ip4/127.0.0.1/tcp/39000/p2p/12D3KooWE2xXc6C2JzeaCaEg7jvZLogWyjLsB5dA3iw5o3KcF9ds"
+				.to_string()
+				.try_into()
+				.map(MultiaddrConfig::Compact)
+				.unwrap();
+		ClientBuilder::new().chain("DEV", vec!["ws://127.0.0.1:9944".into()], vec![bootstrap])
+	}
+
+	#[test]
+	fn build_valid_config() {
+		let cfg = builder()
+			.database_path("test_path")
+			.confidence(92.0)
+			.build()
+			.unwrap();
+		assert_eq!(cfg.avail_path, "test_path");
+		assert_eq!(cfg.confidence, 92.0);
+	}
+
+	#[test_case(ClientBuilder::new() ; "missing bootstraps")]
+	#[test_case(builder().chain("DEV", vec![], builder().cfg.bootstraps) ; "missing full node")]
+	#[test_case(builder().database_path("") ; "empty database path")]
+	#[test_case(builder().confidence(100.0) ; "confidence out of range")]
+	#[test_case(builder().ports(7000, 7000) ; "same ports")]
+	#[test_case(builder().partition(Partition { number: 3, fraction: 2 }) ; "invalid partition")]
+	#[test_case(builder().partition(Partition { number: 1, fraction: 2 }).disable_rpc(true) ; "fat client without rpc")]
+	#[test_case(builder().pruning(3600, 7200, 60, 180) ; "publication after ttl")]
+	#[test_case(builder().pruning(86400, 43200, 10800, 0) ; "zero pruning interval")]
+	#[test_case(builder().watch_storage(vec!["0xzz".into()]) ; "invalid storage key")]
+	fn build_invalid_config(builder: ClientBuilder) {
+		assert!(builder.build().is_err());
+	}
+}
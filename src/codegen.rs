@@ -0,0 +1,568 @@
+//! Generation of typed Rust code from the runtime metadata, for the users who prefer compile time types over the
+//! dynamic values (see [`crate::dynamic`]).
+//!
+//! [`generate`] renders calls, events and storage of the selected pallets (e.g. [`DATA_AVAILABILITY`]) as Rust
+//! source, together with all types they reference. It is meant to be used from the build script:
+//!
+//! ```ignore
+//! let metadata = Metadata::decode(&mut &fs::read("metadata.scale")?[..])?;
+//! let code = codegen::generate(&metadata, &[codegen::DATA_AVAILABILITY])?;
+//! fs::write(Path::new(&env::var("OUT_DIR")?).join("runtime.rs"), code)?;
+//! // In the crate: include!(concat!(env!("OUT_DIR"), "/runtime.rs"));
+//! ```
+//!
+//! # Generated code
+//!
+//! * `types` - structs and enums of the referenced types, named by the last segment of their path
+//! * `<pallet>::calls` - struct per call, with `CALL_INDEX` and `encode_call` (prefixed with pallet and call indices)
+//! * `<pallet>::events::Event` - enum of the pallet events, decoded after the pallet index
+//! * `<pallet>::storage` - value type alias and key function per storage entry
+//!
+//! Generated code depends on `codec` (parity-scale-codec) and `sp_core` crates. `Option` and `Result` are mapped to
+//! the standard types, bounded collections to their inner types, and directly recursive fields are boxed. Bit
+//! sequences are not supported.
+//!
+//! Code generated from the test metadata is kept in `test_utils/generated.rs`, and compiled and checked by the tests.
+
+use color_eyre::{eyre::eyre, Result};
+use scale_info::{form::PortableForm, Field, PortableRegistry, TypeDef, TypeDefPrimitive, Variant};
+use sp_core::twox_128;
+use std::{
+	collections::{BTreeMap, HashMap, HashSet},
+	fmt::Write,
+};
+use subxt::{
+	metadata::types::{StorageEntryType, StorageHasher},
+	Metadata,
+};
+
+use crate::pretty::snake_case;
+
+pub const DATA_AVAILABILITY: &str = "DataAvailability";
+
+const DERIVES: &str = "#[derive(Clone, Debug, PartialEq, Eq, ::codec::Encode, ::codec::Decode)]";
+
+/// Types which are encoded as their only field
+const TRANSPARENT: &[&str] = &[
+	"BoundedVec",
+	"WeakBoundedVec",
+	"BoundedBTreeMap",
+	"BoundedBTreeSet",
+];
+
+const KEYWORDS: &[&str] = &[
+	"as", "async", "await", "box", "break", "const", "continue", "dyn", "else", "enum", "extern",
+	"false", "final", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+	"mut", "pub", "ref", "return", "static", "struct", "trait", "true", "type", "unsafe", "use",
+	"where", "while", "yield",
+];
+
+fn ident(name: &str) -> String {
+	match KEYWORDS.contains(&name) {
+		true => format!("r#{name}"),
+		false => name.to_string(),
+	}
+}
+
+/// Converts call name to the struct name, e.g. `submit_data` to `SubmitData`
+fn camel_case(name: &str) -> String {
+	name.split('_')
+		.map(|part| {
+			let mut chars = part.chars();
+			chars
+				.next()
+				.map(|first| first.to_uppercase().chain(chars).collect::<String>())
+				.unwrap_or_default()
+		})
+		.collect()
+}
+
+/// Generates type definitions of the registry types referenced by the generated items
+struct Types<'a> {
+	registry: &'a PortableRegistry,
+	names: HashMap<u32, String>,
+	used: HashSet<String>,
+	/// Types whose definition is being generated, fields of these types are boxed
+	in_progress: HashSet<u32>,
+	definitions: BTreeMap<String, String>,
+}
+
+impl<'a> Types<'a> {
+	fn new(registry: &'a PortableRegistry) -> Self {
+		Types {
+			registry,
+			names: HashMap::new(),
+			used: HashSet::new(),
+			in_progress: HashSet::new(),
+			definitions: BTreeMap::new(),
+		}
+	}
+
+	fn field_types(fields: &[Field<PortableForm>]) -> Vec<u32> {
+		fields.iter().map(|field| field.ty.id).collect()
+	}
+
+	/// Returns Rust type of the registry type, generating its definition if needed
+	fn type_name(&mut self, id: u32) -> Result<String> {
+		if let Some(name) = self.names.get(&id) {
+			return Ok(match self.in_progress.contains(&id) {
+				true => format!("::std::boxed::Box<{name}>"),
+				false => name.clone(),
+			});
+		}
+		let ty = self
+			.registry
+			.resolve(id)
+			.ok_or_else(|| eyre!("Type {id} is not found in the registry"))?;
+		let path = ty.path.segments.last().map(String::as_str);
+		match (&ty.type_def, path) {
+			(TypeDef::Primitive(primitive), _) => Ok(primitive_name(primitive).to_string()),
+			(TypeDef::Sequence(sequence), _) => {
+				Ok(format!("Vec<{}>", self.type_name(sequence.type_param.id)?))
+			},
+			(TypeDef::Array(array), _) => Ok(format!(
+				"[{}; {}]",
+				self.type_name(array.type_param.id)?,
+				array.len
+			)),
+			(TypeDef::Tuple(tuple), _) => {
+				let fields = tuple
+					.fields
+					.iter()
+					.map(|field| self.type_name(field.id))
+					.collect::<Result<Vec<_>>>()?;
+				Ok(match fields.len() {
+					1 => format!("({},)", fields[0]),
+					_ => format!("({})", fields.join(", ")),
+				})
+			},
+			(TypeDef::Compact(compact), _) => Ok(format!(
+				"::codec::Compact<{}>",
+				self.type_name(compact.type_param.id)?
+			)),
+			(TypeDef::BitSequence(_), _) => Err(eyre!("Bit sequence type {id} is not supported")),
+			(TypeDef::Variant(variant), Some("Option")) => {
+				let some = variant_field_types(&variant.variants, "Some");
+				let [some] = some[..] else {
+					return Err(eyre!("Invalid option type {id}"));
+				};
+				Ok(format!("Option<{}>", self.type_name(some)?))
+			},
+			(TypeDef::Variant(variant), Some("Result")) => {
+				let ok = variant_field_types(&variant.variants, "Ok");
+				let err = variant_field_types(&variant.variants, "Err");
+				let ([ok], [err]) = (&ok[..], &err[..]) else {
+					return Err(eyre!("Invalid result type {id}"));
+				};
+				Ok(format!(
+					"Result<{}, {}>",
+					self.type_name(*ok)?,
+					self.type_name(*err)?
+				))
+			},
+			(TypeDef::Composite(composite), Some(path))
+				if TRANSPARENT.contains(&path) && composite.fields.len() == 1 =>
+			{
+				self.type_name(composite.fields[0].ty.id)
+			},
+			(TypeDef::Composite(_) | TypeDef::Variant(_), path) => {
+				let path = path.ok_or_else(|| eyre!("Type {id} has no path"))?;
+				let name = match self.used.insert(path.to_string()) {
+					true => path.to_string(),
+					false => format!("{path}{id}"),
+				};
+				self.used.insert(name.clone());
+				self.names.insert(id, name.clone());
+				self.in_progress.insert(id);
+				let definition = match &ty.type_def {
+					TypeDef::Composite(composite) => {
+						format!("pub struct {name}{}", self.fields(&composite.fields, true)?)
+					},
+					TypeDef::Variant(variant) => self.enum_definition(&name, &variant.variants)?,
+					_ => unreachable!("Only composites and variants are defined"),
+				};
+				self.in_progress.remove(&id);
+				self.definitions
+					.insert(name.clone(), format!("{DERIVES}\n{definition}\n"));
+				Ok(name)
+			},
+		}
+	}
+
+	/// Renders struct or variant fields, `public` fields are used in structs
+	fn fields(&mut self, fields: &[Field<PortableForm>], public: bool) -> Result<String> {
+		let visibility = if public { "pub " } else { "" };
+		let named = !fields.is_empty() && fields.iter().all(|field| field.name.is_some());
+		let mut rendered = vec![];
+		for field in fields {
+			let (attribute, ty) = self.field_type(field.ty.id)?;
+			let name = match &field.name {
+				Some(name) if named => format!("{}: ", ident(name)),
+				_ => String::new(),
+			};
+			rendered.push(format!("{attribute}{visibility}{name}{ty}"));
+		}
+		let end = if public { ";" } else { "" };
+		Ok(match (fields.is_empty(), named) {
+			(true, _) => end.to_string(),
+			(false, true) => format!(" {{\n\t{},\n}}", rendered.join(",\n\t")),
+			(false, false) => format!("({}){end}", rendered.join(", ")),
+		})
+	}
+
+	/// Returns codec attribute and type of the field, compact fields are annotated instead of wrapped
+	fn field_type(&mut self, id: u32) -> Result<(String, String)> {
+		let ty = self
+			.registry
+			.resolve(id)
+			.ok_or_else(|| eyre!("Type {id} is not found in the registry"))?;
+		match &ty.type_def {
+			TypeDef::Compact(compact) => Ok((
+				"#[codec(compact)] ".to_string(),
+				self.type_name(compact.type_param.id)?,
+			)),
+			_ => Ok((String::new(), self.type_name(id)?)),
+		}
+	}
+
+	fn enum_definition(
+		&mut self,
+		name: &str,
+		variants: &[Variant<PortableForm>],
+	) -> Result<String> {
+		let mut definition = format!("pub enum {name} {{\n");
+		for variant in variants {
+			let fields = self.fields(&variant.fields, false)?.replace('\n', "\n\t");
+			writeln!(
+				definition,
+				"\t#[codec(index = {})]\n\t{}{fields},",
+				variant.index, variant.name
+			)?;
+		}
+		definition.push('}');
+		Ok(definition)
+	}
+}
+
+fn variant_field_types(variants: &[Variant<PortableForm>], name: &str) -> Vec<u32> {
+	variants
+		.iter()
+		.find(|variant| variant.name == name)
+		.map(|variant| Types::field_types(&variant.fields))
+		.unwrap_or_default()
+}
+
+fn primitive_name(primitive: &TypeDefPrimitive) -> &'static str {
+	match primitive {
+		TypeDefPrimitive::Bool => "bool",
+		TypeDefPrimitive::Char => "char",
+		TypeDefPrimitive::Str => "String",
+		TypeDefPrimitive::U8 => "u8",
+		TypeDefPrimitive::U16 => "u16",
+		TypeDefPrimitive::U32 => "u32",
+		TypeDefPrimitive::U64 => "u64",
+		TypeDefPrimitive::U128 => "u128",
+		TypeDefPrimitive::U256 | TypeDefPrimitive::I256 => "[u8; 32]",
+		TypeDefPrimitive::I8 => "i8",
+		TypeDefPrimitive::I16 => "i16",
+		TypeDefPrimitive::I32 => "i32",
+		TypeDefPrimitive::I64 => "i64",
+		TypeDefPrimitive::I128 => "i128",
+	}
+}
+
+/// Expression which hashes the encoded `key` with the storage hasher
+fn hash_expression(hasher: &StorageHasher) -> &'static str {
+	match hasher {
+		StorageHasher::Blake2_128 => "::sp_core::blake2_128(&key).to_vec()",
+		StorageHasher::Blake2_256 => "::sp_core::blake2_256(&key).to_vec()",
+		StorageHasher::Blake2_128Concat => "[&::sp_core::blake2_128(&key)[..], &key[..]].concat()",
+		StorageHasher::Twox128 => "::sp_core::twox_128(&key).to_vec()",
+		StorageHasher::Twox256 => "::sp_core::twox_256(&key).to_vec()",
+		StorageHasher::Twox64Concat => "[&::sp_core::twox_64(&key)[..], &key[..]].concat()",
+		StorageHasher::Identity => "key",
+	}
+}
+
+fn indent(code: &str) -> String {
+	code.trim_end()
+		.lines()
+		.map(|line| match line.is_empty() {
+			true => "\n".to_string(),
+			false => format!("\t{line}\n"),
+		})
+		.collect()
+}
+
+fn calls(types: &mut Types, variants: &[Variant<PortableForm>]) -> Result<String> {
+	let mut code = String::new();
+	for variant in variants {
+		let name = camel_case(&variant.name);
+		let fields = types.fields(&variant.fields, true)?;
+		writeln!(code, "{DERIVES}\npub struct {name}{fields}\n")?;
+		writeln!(code, "impl {name} {{")?;
+		writeln!(code, "\tpub const CALL_INDEX: u8 = {};\n", variant.index)?;
+		writeln!(
+			code,
+			"\t/// Encoded call, prefixed with the pallet and call indices"
+		)?;
+		writeln!(code, "\tpub fn encode_call(&self) -> Vec<u8> {{")?;
+		writeln!(
+			code,
+			"\t\tlet mut call = vec![super::PALLET_INDEX, Self::CALL_INDEX];"
+		)?;
+		writeln!(code, "\t\t::codec::Encode::encode_to(self, &mut call);")?;
+		writeln!(code, "\t\tcall\n\t}}\n}}\n")?;
+	}
+	Ok(code)
+}
+
+fn storage(types: &mut Types, pallet: &str, metadata: &Metadata) -> Result<String> {
+	let Some(storage) = metadata
+		.pallet_by_name(pallet)
+		.and_then(|pallet| pallet.storage())
+	else {
+		return Ok(String::new());
+	};
+	let mut code = String::new();
+	for entry in storage.entries() {
+		let name = snake_case(entry.name());
+		let prefix = [
+			twox_128(pallet.as_bytes()),
+			twox_128(entry.name().as_bytes()),
+		]
+		.concat();
+		let (value_ty, keys) = match entry.entry_type() {
+			StorageEntryType::Plain(value_ty) => (*value_ty, vec![]),
+			StorageEntryType::Map {
+				hashers,
+				key_ty,
+				value_ty,
+			} => {
+				// Multiple keys are described as a tuple
+				let key_types = match &types.registry.resolve(*key_ty).map(|ty| &ty.type_def) {
+					Some(TypeDef::Tuple(tuple)) if hashers.len() > 1 => {
+						tuple.fields.iter().map(|field| field.id).collect()
+					},
+					_ => vec![*key_ty],
+				};
+				if key_types.len() != hashers.len() {
+					return Err(eyre!(
+						"Storage {pallet}.{} has invalid key hashers",
+						entry.name()
+					));
+				}
+				(*value_ty, hashers.iter().zip(key_types).collect())
+			},
+		};
+		let value = types.type_name(value_ty)?;
+		let mut args = vec![];
+		for (index, (_, key_ty)) in keys.iter().enumerate() {
+			args.push(format!("key{index}: &{}", types.type_name(*key_ty)?));
+		}
+		writeln!(code, "pub type {} = {value};\n", camel_case(&name))?;
+		writeln!(code, "/// Storage key of `{pallet}.{}`", entry.name())?;
+		writeln!(code, "pub fn {name}_key({}) -> Vec<u8> {{", args.join(", "))?;
+		if keys.is_empty() {
+			writeln!(code, "\tvec!{prefix:?}\n}}\n")?;
+			continue;
+		}
+		writeln!(code, "\tlet mut storage_key = vec!{prefix:?};")?;
+		for (index, (hasher, _)) in keys.iter().enumerate() {
+			writeln!(code, "\tlet key = ::codec::Encode::encode(key{index});")?;
+			writeln!(code, "\tstorage_key.extend({});", hash_expression(hasher))?;
+		}
+		writeln!(code, "\tstorage_key\n}}\n")?;
+	}
+	Ok(code)
+}
+
+/// Generates Rust source with calls, events and storage of the given pallets
+pub fn generate(metadata: &Metadata, pallets: &[&str]) -> Result<String> {
+	let mut types = Types::new(metadata.types());
+	let mut modules = String::new();
+	for &pallet in pallets {
+		let pallet_metadata = metadata
+			.pallet_by_name(pallet)
+			.ok_or_else(|| eyre!("Pallet {pallet} is not found in metadata"))?;
+		let mut module = format!(
+			"pub const PALLET_INDEX: u8 = {};\n\n",
+			pallet_metadata.index()
+		);
+		if let Some(variants) = pallet_metadata.call_variants() {
+			let calls = calls(&mut types, variants)?;
+			write!(
+				module,
+				"pub mod calls {{\n\tuse super::super::types::*;\n\n{}}}\n\n",
+				indent(&calls)
+			)?;
+		}
+		if let Some(variants) = pallet_metadata.event_variants() {
+			let events = types.enum_definition("Event", variants)?;
+			let events = format!("{DERIVES}\n{events}\n");
+			write!(
+				module,
+				"pub mod events {{\n\tuse super::super::types::*;\n\n{}}}\n\n",
+				indent(&events)
+			)?;
+		}
+		let storage = storage(&mut types, pallet, metadata)?;
+		if !storage.is_empty() {
+			write!(
+				module,
+				"pub mod storage {{\n\tuse super::super::types::*;\n\n{}}}\n",
+				indent(&storage)
+			)?;
+		}
+		writeln!(
+			modules,
+			"#[allow(dead_code, unused_imports, clippy::all)]\npub mod {} {{\n{}}}\n",
+			snake_case(pallet),
+			indent(&module)
+		)?;
+	}
+
+	let definitions = types
+		.definitions
+		.into_values()
+		.collect::<Vec<_>>()
+		.join("\n");
+	let mut code = String::from("// Generated from the runtime metadata, do not edit.\n\n");
+	writeln!(
+		code,
+		"#[allow(dead_code, clippy::all)]\npub mod types {{\n{}}}\n",
+		indent(&definitions)
+	)?;
+	code.push_str(modules.trim_end());
+	code.push('\n');
+	Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::metadata::{
+		self, metadata, AppId, DataAvailabilityCall, DataAvailabilityEvent, RuntimeCall,
+		RuntimeEvent,
+	};
+	use codec::{Decode, Encode};
+	use scale_info::{meta_type, Registry, TypeInfo};
+	use sp_core::blake2_128;
+
+	/// Code generated from the test metadata, compiled with the tests
+	mod generated {
+		include!("test_utils/generated.rs");
+	}
+
+	use generated::{data_availability, types};
+
+	#[derive(Encode, Decode, TypeInfo)]
+	enum Status {
+		Pending,
+		Included { block: u32 },
+		Failed(Option<u8>),
+	}
+
+	#[derive(Encode, Decode, TypeInfo)]
+	struct Submission {
+		r#type: u8,
+		#[codec(compact)]
+		app_id: u32,
+		data: Vec<u8>,
+		status: Status,
+		children: Vec<Submission>,
+		result: Result<(u8,), [u16; 2]>,
+	}
+
+	#[test]
+	fn type_definitions() {
+		let mut registry = Registry::new();
+		let id = registry.register_type(&meta_type::<Submission>()).id;
+		let registry = PortableRegistry::from(registry);
+		let mut types = Types::new(&registry);
+		assert_eq!(types.type_name(id).unwrap(), "Submission");
+
+		let submission = &types.definitions["Submission"];
+		for field in [
+			"pub r#type: u8",
+			"#[codec(compact)] pub app_id: u32",
+			"pub data: Vec<u8>",
+			"pub status: Status",
+			"pub children: Vec<::std::boxed::Box<Submission>>",
+			"pub result: Result<(u8,), [u16; 2]>",
+		] {
+			assert!(submission.contains(field), "{field} is missing");
+		}
+		let status = &types.definitions["Status"];
+		assert!(status.contains("#[codec(index = 0)]\n\tPending,"));
+		assert!(status.contains("Included {\n\t\tblock: u32,\n\t},"));
+		assert!(status.contains("Failed(Option<u8>),"));
+	}
+
+	#[test]
+	fn generated_code() {
+		// Regenerate the file when the generator changes
+		let code = generate(&metadata(), &[DATA_AVAILABILITY]).unwrap();
+		assert_eq!(code, include_str!("test_utils/generated.rs"));
+	}
+
+	#[test]
+	fn generated_calls() {
+		let call = data_availability::calls::SubmitData {
+			data: b"avail".to_vec(),
+		};
+		let expected = RuntimeCall::DataAvailability(DataAvailabilityCall::submit_data {
+			data: b"avail".to_vec(),
+		});
+		assert_eq!(call.encode_call(), expected.encode());
+		assert_eq!(
+			data_availability::PALLET_INDEX,
+			metadata::DATA_AVAILABILITY_INDEX
+		);
+	}
+
+	#[test]
+	fn generated_events() {
+		let event = RuntimeEvent::DataAvailability(DataAvailabilityEvent::ApplicationKeyCreated {
+			key: b"key".to_vec(),
+			owner: [1; 32],
+			id: AppId(7),
+		});
+		// Pallet index is skipped
+		let encoded = event.encode();
+		let decoded = data_availability::events::Event::decode(&mut &encoded[1..]).unwrap();
+		let expected = data_availability::events::Event::ApplicationKeyCreated {
+			key: b"key".to_vec(),
+			owner: [1; 32],
+			id: types::AppId(7),
+		};
+		assert_eq!(decoded, expected);
+	}
+
+	#[test]
+	fn generated_storage() {
+		let prefix = [twox_128(b"DataAvailability"), twox_128(b"NextAppId")].concat();
+		assert_eq!(data_availability::storage::next_app_id_key(), prefix);
+
+		let key = b"key".to_vec();
+		let encoded_key = key.encode();
+		let prefix = [twox_128(b"DataAvailability"), twox_128(b"AppKeys")].concat();
+		let expected = [&prefix[..], &blake2_128(&encoded_key)[..], &encoded_key[..]].concat();
+		assert_eq!(data_availability::storage::app_keys_key(&key), expected);
+
+		let info = metadata::AppKeyInfo {
+			owner: [2; 32],
+			id: AppId(3),
+		};
+		let decoded = data_availability::storage::AppKeys::decode(&mut &info.encode()[..]).unwrap();
+		assert_eq!(decoded.owner, [2; 32]);
+		assert_eq!(decoded.id, types::AppId(3));
+	}
+
+	#[test]
+	fn names() {
+		assert_eq!(camel_case("submit_data"), "SubmitData");
+		assert_eq!(snake_case("DataAvailability"), "data_availability");
+		assert_eq!(ident("type"), "r#type");
+	}
+}
@@ -0,0 +1,257 @@
+//! Transparent compression of the submitted app data, to reduce DA costs of the compressible (e.g. text heavy)
+//! rollup data.
+//!
+//! Compressed blob is prefixed with the [`HEADER_SIZE`] bytes header:
+//!
+//! * [`MAGIC`] bytes
+//! * Header [`VERSION`], single byte
+//! * Codec, single byte (see [`Codec`])
+//! * Original length of the blob, 4 bytes little endian
+//! * Checksum, first 4 bytes of the Blake2-256 hash of the version, codec, length and payload
+//!
+//! Blobs smaller than the configured minimum size, or blobs which don't compress well enough are submitted as is,
+//! and [`decompress`] returns data without the valid header unchanged. Blobs which start with the magic bytes are
+//! always prefixed with the header (with [`Codec::None`]), so they are not mistaken for the compressed ones.
+//!
+//! # Notes
+//!
+//! Blobs of other applications can start with the magic bytes, so header is recognized only if its version is known
+//! and its checksum matches, otherwise the blob is returned as is. Zstandard is the default since it compresses text
+//! much better, Snappy is faster on larger blobs.
+
+use color_eyre::{eyre::eyre, Result};
+use serde::{Deserialize, Serialize};
+use sp_core::blake2_256;
+
+/// Magic bytes of the compressed blob header
+pub const MAGIC: [u8; 3] = *b"AVZ";
+
+/// Version of the compressed blob header
+pub const VERSION: u8 = 1;
+
+const CHECKSUM_SIZE: usize = 4;
+
+pub const HEADER_SIZE: usize = MAGIC.len() + 6 + CHECKSUM_SIZE;
+
+/// Maximum original length of the blob, so malicious headers can't trigger huge allocations on decompression
+pub const MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum Codec {
+	None = 0,
+	Zstd = 1,
+	Snappy = 2,
+}
+
+impl TryFrom<u8> for Codec {
+	type Error = color_eyre::Report;
+
+	fn try_from(value: u8) -> Result<Self> {
+		match value {
+			0 => Ok(Codec::None),
+			1 => Ok(Codec::Zstd),
+			2 => Ok(Codec::Snappy),
+			_ => Err(eyre!("Unknown compression codec {value}")),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct CompressionConfig {
+	/// Codec of the submitted blobs, `none` disables compression
+	pub codec: Codec,
+	/// Minimum length of the blob which is compressed
+	pub min_size: usize,
+	/// Minimum savings, in percents of the blob length, for the compressed blob to be submitted
+	pub min_savings: u8,
+}
+
+impl Default for CompressionConfig {
+	fn default() -> Self {
+		CompressionConfig {
+			codec: Codec::Zstd,
+			min_size: 1024,
+			min_savings: 10,
+		}
+	}
+}
+
+impl CompressionConfig {
+	pub fn disabled() -> Self {
+		CompressionConfig {
+			codec: Codec::None,
+			..Default::default()
+		}
+	}
+}
+
+fn zstd_decompress(data: &[u8], len: usize) -> Result<Vec<u8>> {
+	// Decompression fails if the blob is larger than the header length
+	zstd::bulk::decompress(data, len).map_err(|error| eyre!("Zstd decompression failed: {error}"))
+}
+
+fn snappy_decompress(data: &[u8], len: usize) -> Result<Vec<u8>> {
+	let decompressed_len = snap::raw::decompress_len(data)
+		.map_err(|error| eyre!("Snappy decompression failed: {error}"))?;
+	if decompressed_len != len {
+		return Err(eyre!(
+			"Snappy blob length {decompressed_len} doesn't match header length {len}"
+		));
+	}
+	snap::raw::Decoder::new()
+		.decompress_vec(data)
+		.map_err(|error| eyre!("Snappy decompression failed: {error}"))
+}
+
+fn checksum(header: &[u8], payload: &[u8]) -> [u8; CHECKSUM_SIZE] {
+	let hash = blake2_256(&[header, payload].concat());
+	hash[..CHECKSUM_SIZE].try_into().expect("Hash is 32 bytes")
+}
+
+fn with_header(codec: Codec, len: usize, data: &[u8]) -> Vec<u8> {
+	let mut encoded = Vec::with_capacity(HEADER_SIZE + data.len());
+	encoded.extend_from_slice(&MAGIC);
+	encoded.push(VERSION);
+	encoded.push(codec as u8);
+	encoded.extend_from_slice(&(len as u32).to_le_bytes());
+	let checksum = checksum(&encoded[MAGIC.len()..], data);
+	encoded.extend_from_slice(&checksum);
+	encoded.extend_from_slice(data);
+	encoded
+}
+
+/// Returns codec, original length and payload of the blob, if it has the valid header
+fn parse_header(data: &[u8]) -> Option<(u8, usize, &[u8])> {
+	if data.len() < HEADER_SIZE || !data.starts_with(&MAGIC) || data[MAGIC.len()] != VERSION {
+		return None;
+	}
+	let (header, payload) = data.split_at(HEADER_SIZE);
+	let (fields, expected) =
+		header[MAGIC.len()..].split_at(HEADER_SIZE - MAGIC.len() - CHECKSUM_SIZE);
+	if checksum(fields, payload) != expected {
+		return None;
+	}
+	let len = u32::from_le_bytes(fields[2..].try_into().expect("Length is 4 bytes"));
+	Some((fields[1], len as usize, payload))
+}
+
+/// Compresses blob with the configured codec, if the blob is large and compressible enough
+pub fn compress(config: &CompressionConfig, data: &[u8]) -> Result<Vec<u8>> {
+	if data.len() > MAX_DECOMPRESSED_SIZE {
+		return Err(eyre!(
+			"Blob length {} exceeds maximum {MAX_DECOMPRESSED_SIZE}",
+			data.len()
+		));
+	}
+	let compressed = match config.codec {
+		Codec::None => None,
+		_ if data.len() < config.min_size => None,
+		Codec::Zstd => Some(
+			zstd::bulk::compress(data, ZSTD_LEVEL)
+				.map_err(|error| eyre!("Zstd compression failed: {error}"))?,
+		),
+		Codec::Snappy => Some(
+			snap::raw::Encoder::new()
+				.compress_vec(data)
+				.map_err(|error| eyre!("Snappy compression failed: {error}"))?,
+		),
+	};
+	let max_len = data
+		.len()
+		.saturating_sub(data.len() * config.min_savings as usize / 100);
+	match compressed {
+		Some(compressed) if HEADER_SIZE + compressed.len() <= max_len => {
+			Ok(with_header(config.codec, data.len(), &compressed))
+		},
+		_ if data.starts_with(&MAGIC) => Ok(with_header(Codec::None, data.len(), data)),
+		_ => Ok(data.to_vec()),
+	}
+}
+
+/// Decompresses blob prefixed with the valid header, blobs without it are returned unchanged
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+	let Some((codec, len, payload)) = parse_header(data) else {
+		return Ok(data.to_vec());
+	};
+	let codec = Codec::try_from(codec)?;
+	if len > MAX_DECOMPRESSED_SIZE {
+		return Err(eyre!(
+			"Blob length {len} exceeds maximum {MAX_DECOMPRESSED_SIZE}"
+		));
+	}
+	let decompressed = match codec {
+		Codec::None => payload.to_vec(),
+		Codec::Zstd => zstd_decompress(payload, len)?,
+		Codec::Snappy => snappy_decompress(payload, len)?,
+	};
+	if decompressed.len() != len {
+		return Err(eyre!(
+			"Decompressed length {} doesn't match header length {len}",
+			decompressed.len()
+		));
+	}
+	Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use test_case::test_case;
+
+	fn text() -> Vec<u8> {
+		b"{\"from\":\"0xabc\",\"to\":\"0xdef\",\"value\":100}\n".repeat(100)
+	}
+
+	#[test_case(Codec::Zstd ; "zstd")]
+	#[test_case(Codec::Snappy ; "snappy")]
+	fn compress_roundtrip(codec: Codec) {
+		let config = CompressionConfig {
+			codec,
+			..Default::default()
+		};
+		let compressed = compress(&config, &text()).unwrap();
+		assert!(compressed.len() < text().len() / 2);
+		assert_eq!(&compressed[..MAGIC.len()], MAGIC);
+		assert_eq!(compressed[MAGIC.len()], VERSION);
+		assert_eq!(compressed[MAGIC.len() + 1], codec as u8);
+		assert_eq!(decompress(&compressed).unwrap(), text());
+
+		// Header with the wrong checksum is not recognized
+		let mut corrupted = compressed.clone();
+		corrupted[MAGIC.len() + 2] ^= 1;
+		assert_eq!(decompress(&corrupted).unwrap(), corrupted);
+		// Header with the valid checksum and the wrong length
+		let invalid = with_header(codec, text().len() - 1, &compressed[HEADER_SIZE..]);
+		assert!(decompress(&invalid).is_err());
+	}
+
+	#[test_case(CompressionConfig::disabled(), text() ; "disabled")]
+	#[test_case(CompressionConfig::default(), b"short blob".to_vec() ; "below minimum size")]
+	#[test_case(CompressionConfig::default(), (0..2048).map(|_| rand::random()).collect() ; "incompressible")]
+	fn submitted_as_is(config: CompressionConfig, data: Vec<u8>) {
+		let compressed = compress(&config, &data).unwrap();
+		assert_eq!(compressed, data);
+		assert_eq!(decompress(&compressed).unwrap(), data);
+	}
+
+	#[test]
+	fn magic_prefixed_blob() {
+		let data = b"AVZ blob".to_vec();
+		let compressed = compress(&CompressionConfig::default(), &data).unwrap();
+		assert_eq!(compressed.len(), HEADER_SIZE + data.len());
+		assert_eq!(compressed[MAGIC.len() + 1], Codec::None as u8);
+		assert_eq!(decompress(&compressed).unwrap(), data);
+		assert!(decompress(&with_header(Codec::Zstd, MAX_DECOMPRESSED_SIZE + 1, b"")).is_err());
+	}
+
+	#[test_case(b"AVZ\x05 blob of other application".to_vec() ; "unknown version")]
+	#[test_case([&MAGIC[..], &[VERSION, 9], &[0; 12]].concat() ; "wrong checksum")]
+	fn foreign_blob(data: Vec<u8>) {
+		assert_eq!(decompress(&data).unwrap(), data);
+	}
+}
@@ -0,0 +1,224 @@
+//! Runtime constants from the metadata, so clients can respect chain limits without hard-coding them.
+//!
+//! [`constants`] decodes any pallet constant as a dynamic value, using its type from the metadata. Typed accessors
+//! are provided for the common ones:
+//!
+//! * [`existential_deposit`] - `Balances::ExistentialDeposit`
+//! * [`block_length`] - `System::BlockLength`, including Avail matrix dimensions if present
+//! * [`block_weights`] - `System::BlockWeights`
+//! * [`max_app_data_length`] - `DataAvailability::MaxAppDataLength`
+
+use color_eyre::{eyre::eyre, Result};
+use subxt::{
+	ext::scale_value::{At, Composite, Value, ValueDef},
+	Metadata,
+};
+
+use crate::dynamic;
+
+/// Decodes the pallet constant, using its type from the metadata
+pub fn constants(metadata: &Metadata, pallet: &str, name: &str) -> Result<Value> {
+	let constant = metadata
+		.pallet_by_name(pallet)
+		.and_then(|pallet_metadata| pallet_metadata.constant_by_name(name))
+		.ok_or_else(|| eyre!("Constant {pallet}.{name} is not found in metadata"))?;
+	dynamic::decode_as_type(&mut constant.value(), constant.ty(), metadata.types())
+		.map_err(|error| eyre!("Failed to decode constant {pallet}.{name}: {error}"))
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Weight {
+	pub ref_time: u64,
+	pub proof_size: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockWeights {
+	pub base_block: Weight,
+	pub max_block: Weight,
+	/// Maximum weight of the normal dispatch class extrinsic, if limited
+	pub max_extrinsic: Option<Weight>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockLength {
+	/// Maximum block length of the normal dispatch class
+	pub normal: u32,
+	pub operational: u32,
+	pub mandatory: u32,
+	/// Maximum number of the matrix rows, on Avail runtimes
+	pub rows: Option<u32>,
+	/// Maximum number of the matrix columns, on Avail runtimes
+	pub cols: Option<u32>,
+}
+
+/// Returns number of the primitive value, or of the single field newtype (e.g. `BlockLengthRows`)
+fn number<T>(value: &Value<T>) -> Option<u128> {
+	match &value.value {
+		ValueDef::Composite(Composite::Unnamed(values)) if values.len() == 1 => number(&values[0]),
+		ValueDef::Composite(Composite::Named(values)) if values.len() == 1 => number(&values[0].1),
+		_ => value.as_u128(),
+	}
+}
+
+fn field<T, N: TryFrom<u128>>(value: &Value<T>, name: &str) -> Result<N> {
+	value
+		.at(name)
+		.and_then(number)
+		.and_then(|number| N::try_from(number).ok())
+		.ok_or_else(|| eyre!("Constant field {name} is missing or invalid"))
+}
+
+/// Maps value of the `Option` variant, `None` if the value is not set
+fn option<T, R>(value: &Value<T>, map: impl Fn(&Value<T>) -> Result<R>) -> Result<Option<R>> {
+	match &value.value {
+		ValueDef::Variant(variant) if variant.name == "None" => Ok(None),
+		ValueDef::Variant(variant) if variant.name == "Some" => {
+			let some = variant
+				.values
+				.values()
+				.next()
+				.ok_or_else(|| eyre!("Optional value is missing"))?;
+			map(some).map(Some)
+		},
+		_ => Err(eyre!("Expected optional value")),
+	}
+}
+
+fn at<'a, T>(value: &'a Value<T>, name: &str) -> Result<&'a Value<T>> {
+	value
+		.at(name)
+		.ok_or_else(|| eyre!("Constant field {name} is missing"))
+}
+
+pub fn weight_from_value<T>(value: &Value<T>) -> Result<Weight> {
+	Ok(Weight {
+		ref_time: field(value, "ref_time")?,
+		proof_size: field(value, "proof_size")?,
+	})
+}
+
+/// Maps decoded `BlockWeights` value to the typed block weights
+pub fn block_weights_from_value<T>(value: &Value<T>) -> Result<BlockWeights> {
+	let normal = at(at(value, "per_class")?, "normal")?;
+	Ok(BlockWeights {
+		base_block: weight_from_value(at(value, "base_block")?)?,
+		max_block: weight_from_value(at(value, "max_block")?)?,
+		max_extrinsic: option(at(normal, "max_extrinsic")?, weight_from_value)?,
+	})
+}
+
+/// Maps decoded `BlockLength` value to the typed block length
+pub fn block_length_from_value<T>(value: &Value<T>) -> Result<BlockLength> {
+	let max = at(value, "max")?;
+	Ok(BlockLength {
+		normal: field(max, "normal")?,
+		operational: field(max, "operational")?,
+		mandatory: field(max, "mandatory")?,
+		rows: value.at("rows").map(|_| field(value, "rows")).transpose()?,
+		cols: value.at("cols").map(|_| field(value, "cols")).transpose()?,
+	})
+}
+
+fn number_constant<N: TryFrom<u128>>(metadata: &Metadata, pallet: &str, name: &str) -> Result<N> {
+	number(&constants(metadata, pallet, name)?)
+		.and_then(|number| N::try_from(number).ok())
+		.ok_or_else(|| eyre!("Constant {pallet}.{name} is not a valid number"))
+}
+
+/// Minimum balance of the account, in the smallest units
+pub fn existential_deposit(metadata: &Metadata) -> Result<u128> {
+	number_constant(metadata, "Balances", "ExistentialDeposit")
+}
+
+pub fn block_length(metadata: &Metadata) -> Result<BlockLength> {
+	block_length_from_value(&constants(metadata, "System", "BlockLength")?)
+}
+
+pub fn block_weights(metadata: &Metadata) -> Result<BlockWeights> {
+	block_weights_from_value(&constants(metadata, "System", "BlockWeights")?)
+}
+
+/// Maximum size of the submitted application data, in bytes
+pub fn max_app_data_length(metadata: &Metadata) -> Result<u32> {
+	number_constant(metadata, "DataAvailability", "MaxAppDataLength")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn weight(ref_time: u128, proof_size: u128) -> Value<()> {
+		Value::named_composite([
+			("ref_time", Value::u128(ref_time)),
+			("proof_size", Value::u128(proof_size)),
+		])
+	}
+
+	fn per_class(value: impl Fn() -> Value<()>) -> Value<()> {
+		Value::named_composite([
+			("normal", value()),
+			("operational", value()),
+			("mandatory", value()),
+		])
+	}
+
+	#[test]
+	fn block_weights() {
+		let class = || {
+			Value::named_composite([
+				("base_extrinsic", weight(1, 0)),
+				(
+					"max_extrinsic",
+					Value::unnamed_variant("Some", [weight(100, 50)]),
+				),
+				("max_total", Value::unnamed_variant("None", [])),
+				("reserved", Value::unnamed_variant("None", [])),
+			])
+		};
+		let value = Value::named_composite([
+			("base_block", weight(5, 0)),
+			("max_block", weight(200, 100)),
+			("per_class", per_class(class)),
+		]);
+		assert_eq!(
+			block_weights_from_value(&value).unwrap(),
+			BlockWeights {
+				base_block: Weight {
+					ref_time: 5,
+					proof_size: 0
+				},
+				max_block: Weight {
+					ref_time: 200,
+					proof_size: 100
+				},
+				max_extrinsic: Some(Weight {
+					ref_time: 100,
+					proof_size: 50
+				}),
+			}
+		);
+		assert!(block_weights_from_value(&weight(1, 1)).is_err());
+	}
+
+	#[test]
+	fn block_length() {
+		let max = per_class(|| Value::u128(5 * 1024 * 1024));
+		let substrate = Value::named_composite([("max", max.clone())]);
+		let length = block_length_from_value(&substrate).unwrap();
+		assert_eq!(length.normal, 5 * 1024 * 1024);
+		assert_eq!((length.rows, length.cols), (None, None));
+
+		let avail = Value::named_composite([
+			("max", max),
+			("cols", Value::unnamed_composite([Value::u128(256)])),
+			("rows", Value::unnamed_composite([Value::u128(1024)])),
+			("chunk_size", Value::u128(32)),
+		]);
+		let length = block_length_from_value(&avail).unwrap();
+		assert_eq!((length.rows, length.cols), (Some(1024), Some(256)));
+
+		let overflow = Value::named_composite([("max", per_class(|| Value::u128(1 << 40)))]);
+		assert!(block_length_from_value(&overflow).is_err());
+	}
+}
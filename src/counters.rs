@@ -0,0 +1,41 @@
+//! Internal counters of consensus critical operations.
+//!
+//! Counters are used by benchmarks to detect redundant work (e.g. hashing the same header twice), and
+//! are only updated if `bench` feature is enabled, so they don't cost anything in regular builds.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Counter {
+	HeadersHashed,
+	TrieRoots,
+	ReadProofsVerified,
+	CellsVerified,
+}
+
+const COUNTERS_LEN: usize = 4;
+
+static COUNTERS: [AtomicU64; COUNTERS_LEN] = [
+	AtomicU64::new(0),
+	AtomicU64::new(0),
+	AtomicU64::new(0),
+	AtomicU64::new(0),
+];
+
+#[inline]
+pub fn increment(counter: Counter) {
+	#[cfg(feature = "bench")]
+	COUNTERS[counter as usize].fetch_add(1, Ordering::Relaxed);
+	#[cfg(not(feature = "bench"))]
+	let _ = counter;
+}
+
+pub fn get(counter: Counter) -> u64 {
+	COUNTERS[counter as usize].load(Ordering::Relaxed)
+}
+
+pub fn reset() {
+	for counter in &COUNTERS {
+		counter.store(0, Ordering::Relaxed);
+	}
+}
@@ -0,0 +1,251 @@
+//! Notifications for blocks which are both finalized and available.
+//!
+//! Combines GRANDPA verified headers from the RPC subscription loop with confidence reported by the sampling
+//! subsystem, and emits [`FinalizedAvailable`] once block is finalized and its confidence is above the threshold.
+//! Blocks which are not reaching the threshold are never emitted, so consumers (e.g. rollups) can act on every
+//! received notification.
+
+use avail_subxt::utils::H256;
+use std::collections::BTreeMap;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, error, info, warn};
+
+use crate::{header::cached::CachedHeader, network::rpc::Event, types::BlockVerified};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FinalizedAvailable {
+	pub block_number: u32,
+	pub header_hash: H256,
+	pub confidence: f64,
+}
+
+/// Tracks finalized and sampled blocks until both conditions are met
+pub struct DaFinalityTracker {
+	threshold: f64,
+	finalized: BTreeMap<u32, H256>,
+	sampled: BTreeMap<u32, (H256, f64)>,
+	last_notified: Option<u32>,
+}
+
+impl DaFinalityTracker {
+	pub fn new(threshold: f64) -> Self {
+		DaFinalityTracker {
+			threshold,
+			finalized: BTreeMap::new(),
+			sampled: BTreeMap::new(),
+			last_notified: None,
+		}
+	}
+
+	fn is_stale(&self, block_number: u32) -> bool {
+		self.last_notified
+			.map_or(false, |last| block_number <= last)
+	}
+
+	pub fn on_finalized(&mut self, header: &CachedHeader) -> Option<FinalizedAvailable> {
+		if self.is_stale(header.number) {
+			return None;
+		}
+		self.finalized.insert(header.number, header.hash());
+		self.try_notify(header.number)
+	}
+
+	pub fn on_block_verified(&mut self, block: &BlockVerified) -> Option<FinalizedAvailable> {
+		let block_number = block.block_num;
+		let Some(confidence) = block.confidence else {
+			debug!(block_number, "Block is not sampled, skipping");
+			return None;
+		};
+		if self.is_stale(block_number) {
+			return None;
+		}
+		if confidence < self.threshold {
+			info!(
+				block_number,
+				confidence, "Block confidence is below the threshold"
+			);
+			self.finalized.remove(&block_number);
+			return None;
+		}
+		self.sampled
+			.insert(block_number, (block.header_hash, confidence));
+		self.try_notify(block_number)
+	}
+
+	fn try_notify(&mut self, block_number: u32) -> Option<FinalizedAvailable> {
+		let finalized_hash = self.finalized.get(&block_number)?;
+		let (sampled_hash, confidence) = self.sampled.get(&block_number)?;
+		if finalized_hash != sampled_hash {
+			warn!(
+				block_number,
+				"Sampled block hash doesn't match finalized block hash"
+			);
+			self.sampled.remove(&block_number);
+			return None;
+		}
+
+		let notification = FinalizedAvailable {
+			block_number,
+			header_hash: *finalized_hash,
+			confidence: *confidence,
+		};
+
+		// Blocks are finalized in order, so older pending blocks are not relevant anymore
+		self.last_notified = Some(block_number);
+		self.finalized = self.finalized.split_off(&(block_number + 1));
+		self.sampled = self.sampled.split_off(&(block_number + 1));
+		Some(notification)
+	}
+}
+
+/// Runs tracker over finalized headers and verified blocks, and sends [`FinalizedAvailable`] notifications.
+///
+/// # Arguments
+///
+/// * `rpc_events` - GRANDPA verified headers
+/// * `block_receiver` - Blocks with confidence from light or fat client
+/// * `threshold` - Minimal confidence required for notification
+/// * `sender` - Notifications sender
+pub async fn run(
+	mut rpc_events: broadcast::Receiver<Event>,
+	mut block_receiver: broadcast::Receiver<BlockVerified>,
+	threshold: f64,
+	sender: broadcast::Sender<FinalizedAvailable>,
+) {
+	info!("Starting DA finality notifier...");
+	let mut tracker = DaFinalityTracker::new(threshold);
+
+	loop {
+		let notification = tokio::select! {
+			event = rpc_events.recv() => match event {
+				Ok(Event::HeaderUpdate { header, .. }) => tracker.on_finalized(&header.into()),
+				Err(RecvError::Lagged(skipped)) => {
+					warn!(skipped, "Finalized headers receiver lagged");
+					continue;
+				},
+				Err(RecvError::Closed) => {
+					error!("Finalized headers channel closed");
+					return;
+				},
+			},
+			block = block_receiver.recv() => match block {
+				Ok(block) => tracker.on_block_verified(&block),
+				Err(RecvError::Lagged(skipped)) => {
+					warn!(skipped, "Verified blocks receiver lagged");
+					continue;
+				},
+				Err(RecvError::Closed) => {
+					error!("Verified blocks channel closed");
+					return;
+				},
+			},
+		};
+
+		let Some(notification) = notification else {
+			continue;
+		};
+
+		info!(
+			block_number = notification.block_number,
+			confidence = notification.confidence,
+			"Block is finalized and available"
+		);
+
+		// Sending fails only if there are no receivers, which is not an error
+		let _ = sender.send(notification);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use avail_core::DataLookup;
+	use avail_subxt::{
+		api::runtime_types::avail_core::{
+			data_lookup::compact::CompactDataLookup,
+			header::extension::{v3, HeaderExtension},
+			kate_commitment::v3::KateCommitment,
+		},
+		config::substrate::Digest,
+		primitives::Header,
+	};
+	use kate_recovery::matrix::Dimensions;
+
+	fn header(number: u32) -> CachedHeader {
+		CachedHeader::new(Header {
+			parent_hash: H256::zero(),
+			number,
+			state_root: H256::zero(),
+			extrinsics_root: H256::zero(),
+			digest: Digest { logs: vec![] },
+			extension: HeaderExtension::V3(v3::HeaderExtension {
+				commitment: KateCommitment {
+					rows: 1,
+					cols: 4,
+					data_root: H256::zero(),
+					commitment: vec![],
+				},
+				app_lookup: CompactDataLookup {
+					size: 0,
+					index: vec![],
+				},
+			}),
+		})
+	}
+
+	fn verified(header: &CachedHeader, confidence: Option<f64>) -> BlockVerified {
+		BlockVerified {
+			header_hash: header.hash(),
+			block_num: header.number,
+			dimensions: Dimensions::new(1, 4).unwrap(),
+			lookup: DataLookup::from_id_and_len_iter(vec![(0u32, 1usize)].into_iter()).unwrap(),
+			commitments: vec![],
+			confidence,
+		}
+	}
+
+	#[test]
+	fn notifies_when_finalized_and_available() {
+		let mut tracker = DaFinalityTracker::new(90.0);
+		let header = header(1);
+
+		assert_eq!(tracker.on_finalized(&header), None);
+		let notification = tracker
+			.on_block_verified(&verified(&header, Some(99.0)))
+			.unwrap();
+		assert_eq!(notification.block_number, 1);
+		assert_eq!(notification.confidence, 99.0);
+
+		// Duplicate events are ignored
+		assert_eq!(tracker.on_finalized(&header), None);
+	}
+
+	#[test]
+	fn skips_blocks_below_threshold() {
+		let mut tracker = DaFinalityTracker::new(90.0);
+		let header = header(1);
+
+		assert_eq!(
+			tracker.on_block_verified(&verified(&header, Some(50.0))),
+			None
+		);
+		assert_eq!(tracker.on_block_verified(&verified(&header, None)), None);
+		assert_eq!(tracker.on_finalized(&header), None);
+	}
+
+	#[test]
+	fn prunes_older_pending_blocks() {
+		let mut tracker = DaFinalityTracker::new(90.0);
+		let (first, second) = (header(1), header(2));
+
+		tracker.on_finalized(&first);
+		tracker.on_finalized(&second);
+		assert!(tracker
+			.on_block_verified(&verified(&second, Some(99.0)))
+			.is_some());
+		assert_eq!(
+			tracker.on_block_verified(&verified(&first, Some(99.0))),
+			None
+		);
+	}
+}
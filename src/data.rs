@@ -26,6 +26,12 @@ pub trait Database {
 
 	/// Deletes value from the database for the given key.
 	fn delete(&self, key: Key) -> Result<()>;
+
+	/// Flushes pending writes to the persistent storage.
+	/// Default implementation does nothing, which is correct for in-memory databases.
+	fn flush(&self) -> Result<()> {
+		Ok(())
+	}
 }
 
 /// Column family for confidence factor
@@ -40,15 +46,55 @@ pub const APP_DATA_CF: &str = "avail_light_app_data_cf";
 /// Column family for state
 pub const STATE_CF: &str = "avail_light_state_cf";
 
+/// Column family for availability of historical blocks
+pub const AVAILABILITY_CF: &str = "avail_light_availability_cf";
+
+/// Column family for epoch (validator set) data
+pub const EPOCH_CF: &str = "avail_light_epoch_cf";
+
+/// Column family for the per block DA audit reports
+pub const AUDIT_CF: &str = "avail_light_audit_cf";
+
+/// Column family for the signed sampling receipts
+pub const RECEIPTS_CF: &str = "avail_light_receipts_cf";
+
 /// Sync finality checkpoint key name
 const FINALITY_SYNC_CHECKPOINT_KEY: &str = "finality_sync_checkpoint";
 
+/// Stored epochs index key name
+const EPOCH_INDEX_KEY: &str = "epoch_index";
+
+/// Key of the last exported block number
+const EXPORT_CHECKPOINT_KEY: &str = "export_checkpoint";
+
+/// Canonical chain feed state key name
+const FEED_STATE_KEY: &str = "feed_state";
+
+/// Prefix of the canonical chain feed event keys
+const FEED_EVENT_KEY: &str = "feed_event";
+
+/// Sync progress checkpoint key name
+const SYNC_CHECKPOINT_KEY: &str = "sync_checkpoint";
+
+/// Prefix of the sync journal entry keys
+const SYNC_JOURNAL_KEY: &str = "sync_journal";
+
 #[derive(Clone)]
 pub enum Key {
 	AppData(u32, u32),
 	BlockHeader(u32),
 	VerifiedCellCount(u32),
 	FinalitySyncCheckpoint,
+	BlockAvailability(u32),
+	Epoch(u64),
+	EpochIndex,
+	ExportCheckpoint,
+	FeedState,
+	FeedEvent(u64),
+	AuditReport(u32),
+	SamplingReceipt(u32),
+	SyncCheckpoint,
+	SyncJournal(u64),
 }
 
 #[derive(Serialize, Deserialize, Debug, Decode, Encode)]
@@ -57,3 +103,37 @@ pub struct FinalitySyncCheckpoint {
 	pub set_id: u64,
 	pub validator_set: Vec<ed25519::Public>,
 }
+
+/// Epoch is the period in which validator set with the given set ID is finalizing blocks
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Decode, Encode)]
+pub struct EpochDescriptor {
+	pub set_id: u64,
+	/// First block finalized by the validator set
+	pub start_block: u32,
+	pub validator_set: Vec<ed25519::Public>,
+	/// Next validator set, once the change is announced
+	pub next_validator_set: Option<Vec<ed25519::Public>>,
+}
+
+/// Range of the stored epochs (set IDs), inclusive
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Decode, Encode)]
+pub struct EpochIndex {
+	pub earliest: u64,
+	pub latest: u64,
+}
+
+/// Result of the historical block sampling
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Decode, Encode)]
+pub struct BlockAvailability {
+	/// Number of sampled cells
+	pub sampled: u32,
+	/// Number of fetched and verified cells
+	pub verified: u32,
+}
+
+impl BlockAvailability {
+	/// Block is considered available if all sampled cells are verified
+	pub fn is_available(&self) -> bool {
+		self.sampled > 0 && self.verified >= self.sampled
+	}
+}
@@ -1,5 +1,7 @@
 use crate::data::{
-	Database, Key, APP_DATA_CF, BLOCK_HEADER_CF, CONFIDENCE_FACTOR_CF, FINALITY_SYNC_CHECKPOINT_KEY,
+	Database, Key, APP_DATA_CF, AUDIT_CF, AVAILABILITY_CF, BLOCK_HEADER_CF, CONFIDENCE_FACTOR_CF,
+	EPOCH_CF, EPOCH_INDEX_KEY, EXPORT_CHECKPOINT_KEY, FEED_EVENT_KEY, FEED_STATE_KEY,
+	FINALITY_SYNC_CHECKPOINT_KEY, RECEIPTS_CF, SYNC_CHECKPOINT_KEY, SYNC_JOURNAL_KEY,
 };
 use color_eyre::eyre::{eyre, Result};
 use serde::{Deserialize, Serialize};
@@ -66,6 +68,20 @@ impl From<Key> for HashMapKey {
 				HashMapKey(format!("{CONFIDENCE_FACTOR_CF}:{block_number}"))
 			},
 			Key::FinalitySyncCheckpoint => HashMapKey(FINALITY_SYNC_CHECKPOINT_KEY.to_string()),
+			Key::BlockAvailability(block_number) => {
+				HashMapKey(format!("{AVAILABILITY_CF}:{block_number}"))
+			},
+			Key::Epoch(set_id) => HashMapKey(format!("{EPOCH_CF}:{set_id}")),
+			Key::EpochIndex => HashMapKey(EPOCH_INDEX_KEY.to_string()),
+			Key::ExportCheckpoint => HashMapKey(EXPORT_CHECKPOINT_KEY.to_string()),
+			Key::FeedState => HashMapKey(FEED_STATE_KEY.to_string()),
+			Key::FeedEvent(cursor) => HashMapKey(format!("{FEED_EVENT_KEY}:{cursor}")),
+			Key::AuditReport(block_number) => HashMapKey(format!("{AUDIT_CF}:{block_number}")),
+			Key::SamplingReceipt(block_number) => {
+				HashMapKey(format!("{RECEIPTS_CF}:{block_number}"))
+			},
+			Key::SyncCheckpoint => HashMapKey(SYNC_CHECKPOINT_KEY.to_string()),
+			Key::SyncJournal(sequence) => HashMapKey(format!("{SYNC_JOURNAL_KEY}:{sequence}")),
 		}
 	}
 }
@@ -1,11 +1,17 @@
-use crate::data::{self, Key, APP_DATA_CF, BLOCK_HEADER_CF, CONFIDENCE_FACTOR_CF, STATE_CF};
+use crate::data::{
+	self, Key, APP_DATA_CF, AUDIT_CF, AVAILABILITY_CF, BLOCK_HEADER_CF, CONFIDENCE_FACTOR_CF,
+	EPOCH_CF, RECEIPTS_CF, STATE_CF,
+};
 use codec::{Decode, Encode};
 use color_eyre::eyre::{eyre, Context, Result};
 use rocksdb::{ColumnFamilyDescriptor, Options};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
-use super::FINALITY_SYNC_CHECKPOINT_KEY;
+use super::{
+	EPOCH_INDEX_KEY, EXPORT_CHECKPOINT_KEY, FEED_EVENT_KEY, FEED_STATE_KEY,
+	FINALITY_SYNC_CHECKPOINT_KEY, SYNC_CHECKPOINT_KEY, SYNC_JOURNAL_KEY,
+};
 
 #[derive(Clone)]
 pub struct RocksDB {
@@ -19,6 +25,10 @@ impl RocksDB {
 			ColumnFamilyDescriptor::new(BLOCK_HEADER_CF, Options::default()),
 			ColumnFamilyDescriptor::new(APP_DATA_CF, Options::default()),
 			ColumnFamilyDescriptor::new(STATE_CF, Options::default()),
+			ColumnFamilyDescriptor::new(AVAILABILITY_CF, Options::default()),
+			ColumnFamilyDescriptor::new(EPOCH_CF, Options::default()),
+			ColumnFamilyDescriptor::new(AUDIT_CF, Options::default()),
+			ColumnFamilyDescriptor::new(RECEIPTS_CF, Options::default()),
 		];
 
 		let mut db_opts = Options::default();
@@ -50,6 +60,26 @@ impl From<Key> for (Option<&'static str>, Vec<u8>) {
 				Some(STATE_CF),
 				FINALITY_SYNC_CHECKPOINT_KEY.as_bytes().to_vec(),
 			),
+			Key::BlockAvailability(block_number) => {
+				(Some(AVAILABILITY_CF), block_number.to_be_bytes().to_vec())
+			},
+			Key::Epoch(set_id) => (Some(EPOCH_CF), set_id.to_be_bytes().to_vec()),
+			Key::EpochIndex => (Some(STATE_CF), EPOCH_INDEX_KEY.as_bytes().to_vec()),
+			Key::ExportCheckpoint => (Some(STATE_CF), EXPORT_CHECKPOINT_KEY.as_bytes().to_vec()),
+			Key::FeedState => (Some(STATE_CF), FEED_STATE_KEY.as_bytes().to_vec()),
+			Key::FeedEvent(cursor) => (
+				Some(STATE_CF),
+				[FEED_EVENT_KEY.as_bytes(), &cursor.to_be_bytes()].concat(),
+			),
+			Key::AuditReport(block_number) => (Some(AUDIT_CF), block_number.to_be_bytes().to_vec()),
+			Key::SamplingReceipt(block_number) => {
+				(Some(RECEIPTS_CF), block_number.to_be_bytes().to_vec())
+			},
+			Key::SyncCheckpoint => (Some(STATE_CF), SYNC_CHECKPOINT_KEY.as_bytes().to_vec()),
+			Key::SyncJournal(sequence) => (
+				Some(STATE_CF),
+				[SYNC_JOURNAL_KEY.as_bytes(), &sequence.to_be_bytes()].concat(),
+			),
 		}
 	}
 }
@@ -126,4 +156,29 @@ impl data::Database for RocksDB {
 			.delete_cf(&cf_handle, key)
 			.wrap_err("Delete operation with Column Family failed on RocksDB")
 	}
+
+	fn flush(&self) -> Result<()> {
+		self.db
+			.flush()
+			.wrap_err("Flush operation failed on RocksDB")?;
+		for cf in [
+			CONFIDENCE_FACTOR_CF,
+			BLOCK_HEADER_CF,
+			APP_DATA_CF,
+			STATE_CF,
+			AVAILABILITY_CF,
+			EPOCH_CF,
+			AUDIT_CF,
+			RECEIPTS_CF,
+		] {
+			let cf_handle = self
+				.db
+				.cf_handle(cf)
+				.ok_or_else(|| eyre!("Couldn't get Column Family handle from RocksDB"))?;
+			self.db
+				.flush_cf(&cf_handle)
+				.wrap_err("Flush operation with Column Family failed on RocksDB")?;
+		}
+		Ok(())
+	}
 }
@@ -0,0 +1,305 @@
+//! Dynamically typed values, encoded and decoded with the types from the metadata type registry.
+//!
+//! Values are [`scale_value`] values, the same model subxt uses, so arbitrary calls, storage values and constants can
+//! be handled without the generated code:
+//!
+//! * Composites (structs and tuples) - with named or unnamed fields
+//! * Variants (enums) - by variant name, index is taken from the registry
+//! * Sequences and arrays - as unnamed composites, array length is checked on encoding
+//! * Primitives - integers are widened to 128 bits, and range checked on encoding
+//! * Compact values - as their inner values
+//! * Bit sequences - with `u8` to `u64` stores and both bit orders
+//!
+//! # Notes
+//!
+//! Encoded input is not trusted. Before decoding, input is walked with its type, and lengths of sequences are checked
+//! against the remaining input. Sequences of zero sized elements don't consume any input, so their total number of
+//! elements is capped to [`MAX_ZERO_SIZED_ELEMENTS`].
+
+use codec::{Compact, Decode};
+use color_eyre::{eyre::eyre, Result};
+use scale_info::{form::PortableForm, PortableRegistry, Type, TypeDef, TypeDefPrimitive};
+use subxt::ext::scale_value;
+
+pub use scale_value::Value;
+
+/// Maximum number of zero sized elements in all sequences of the decoded value
+pub const MAX_ZERO_SIZED_ELEMENTS: usize = 1024;
+
+/// Maximum nesting of the decoded types
+const MAX_DEPTH: usize = 128;
+
+/// Decodes value of the given type
+pub fn decode_as_type(
+	input: &mut &[u8],
+	type_id: u32,
+	registry: &PortableRegistry,
+) -> Result<Value> {
+	Checker::new(registry).check(&mut &input[..], type_id, 0)?;
+	scale_value::scale::decode_as_type(input, type_id, registry)
+		.map(Value::remove_context)
+		.map_err(|error| eyre!("Cannot decode type {type_id}: {error}"))
+}
+
+/// Encodes value as the given type
+pub fn encode_as_type(
+	value: &Value,
+	type_id: u32,
+	registry: &PortableRegistry,
+	dest: &mut Vec<u8>,
+) -> Result<()> {
+	scale_value::scale::encode_as_type(value, type_id, registry, dest)
+		.map_err(|error| eyre!("Cannot encode type {type_id}: {error}"))
+}
+
+fn resolve(registry: &PortableRegistry, type_id: u32) -> Result<&Type<PortableForm>> {
+	registry
+		.resolve(type_id)
+		.ok_or_else(|| eyre!("Type {type_id} is not found in the registry"))
+}
+
+fn skip(input: &mut &[u8], len: usize) -> Result<()> {
+	if input.len() < len {
+		return Err(eyre!("Input is truncated, {len} bytes expected"));
+	}
+	*input = &input[len..];
+	Ok(())
+}
+
+fn decode_len(input: &mut &[u8]) -> Result<usize> {
+	Compact::<u32>::decode(input)
+		.map(|len| len.0 as usize)
+		.map_err(|error| eyre!("Cannot decode length: {error}"))
+}
+
+fn primitive_size(primitive: &TypeDefPrimitive) -> Option<usize> {
+	match primitive {
+		TypeDefPrimitive::Bool | TypeDefPrimitive::U8 | TypeDefPrimitive::I8 => Some(1),
+		TypeDefPrimitive::U16 | TypeDefPrimitive::I16 => Some(2),
+		TypeDefPrimitive::Char | TypeDefPrimitive::U32 | TypeDefPrimitive::I32 => Some(4),
+		TypeDefPrimitive::U64 | TypeDefPrimitive::I64 => Some(8),
+		TypeDefPrimitive::U128 | TypeDefPrimitive::I128 => Some(16),
+		TypeDefPrimitive::U256 | TypeDefPrimitive::I256 => Some(32),
+		TypeDefPrimitive::Str => None,
+	}
+}
+
+/// Walks the input with its type, without building the value
+struct Checker<'a> {
+	registry: &'a PortableRegistry,
+	/// Remaining number of zero sized elements
+	zero_sized: usize,
+}
+
+impl<'a> Checker<'a> {
+	fn new(registry: &'a PortableRegistry) -> Self {
+		Checker {
+			registry,
+			zero_sized: MAX_ZERO_SIZED_ELEMENTS,
+		}
+	}
+
+	fn is_zero_sized(&self, type_id: u32, depth: usize) -> Result<bool> {
+		if depth > MAX_DEPTH {
+			return Err(eyre!("Type {type_id} is nested too deep"));
+		}
+		let depth = depth + 1;
+		let zero_sized = match &resolve(self.registry, type_id)?.type_def {
+			TypeDef::Composite(composite) => {
+				for field in &composite.fields {
+					if !self.is_zero_sized(field.ty.id, depth)? {
+						return Ok(false);
+					}
+				}
+				true
+			},
+			TypeDef::Tuple(tuple) => {
+				for field in &tuple.fields {
+					if !self.is_zero_sized(field.id, depth)? {
+						return Ok(false);
+					}
+				}
+				true
+			},
+			TypeDef::Array(array) => {
+				array.len == 0 || self.is_zero_sized(array.type_param.id, depth)?
+			},
+			_ => false,
+		};
+		Ok(zero_sized)
+	}
+
+	fn check(&mut self, input: &mut &[u8], type_id: u32, depth: usize) -> Result<()> {
+		if depth > MAX_DEPTH {
+			return Err(eyre!("Type {type_id} is nested too deep"));
+		}
+		let depth = depth + 1;
+		match &resolve(self.registry, type_id)?.type_def {
+			TypeDef::Composite(composite) => {
+				for field in &composite.fields {
+					self.check(input, field.ty.id, depth)?;
+				}
+			},
+			TypeDef::Variant(variant) => {
+				let index = u8::decode(input)
+					.map_err(|error| eyre!("Cannot decode variant index: {error}"))?;
+				let variant = variant
+					.variants
+					.iter()
+					.find(|variant| variant.index == index)
+					.ok_or_else(|| eyre!("Variant {index} of type {type_id} is not found"))?;
+				for field in &variant.fields {
+					self.check(input, field.ty.id, depth)?;
+				}
+			},
+			TypeDef::Sequence(sequence) => {
+				let len = decode_len(input)?;
+				self.check_sequence(input, sequence.type_param.id, len, depth)?;
+			},
+			TypeDef::Array(array) => {
+				self.check_sequence(input, array.type_param.id, array.len as usize, depth)?;
+			},
+			TypeDef::Tuple(tuple) => {
+				for field in &tuple.fields {
+					self.check(input, field.id, depth)?;
+				}
+			},
+			TypeDef::Primitive(primitive) => {
+				let len = match primitive_size(primitive) {
+					Some(size) => size,
+					None => decode_len(input)?,
+				};
+				skip(input, len)?;
+			},
+			TypeDef::Compact(compact) => {
+				// Compact encoded empty composites and tuples take no input
+				if !self.is_zero_sized(compact.type_param.id, depth)? {
+					Compact::<u128>::decode(input)
+						.map_err(|error| eyre!("Cannot decode compact value: {error}"))?;
+				}
+			},
+			TypeDef::BitSequence(bits) => {
+				let store_size = match &resolve(self.registry, bits.bit_store_type.id)?.type_def {
+					TypeDef::Primitive(primitive) => primitive_size(primitive),
+					_ => None,
+				}
+				.ok_or_else(|| eyre!("Unsupported bit store type {}", bits.bit_store_type.id))?;
+				let len = decode_len(input)?;
+				skip(input, len.div_ceil(store_size * 8) * store_size)?;
+			},
+		}
+		Ok(())
+	}
+
+	fn check_sequence(
+		&mut self,
+		input: &mut &[u8],
+		type_id: u32,
+		len: usize,
+		depth: usize,
+	) -> Result<()> {
+		if self.is_zero_sized(type_id, depth)? {
+			if len > self.zero_sized {
+				return Err(eyre!(
+					"Too many zero sized elements ({len}) of type {type_id}"
+				));
+			}
+			self.zero_sized -= len;
+		} else if len > input.len() {
+			// Each element takes at least one byte
+			return Err(eyre!(
+				"Sequence of {len} elements is longer than the remaining input"
+			));
+		}
+		for _ in 0..len {
+			self.check(input, type_id, depth)?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use codec::Encode;
+	use scale_info::{meta_type, Registry, TypeInfo};
+	use scale_value::At;
+	use test_case::test_case;
+
+	#[derive(Encode, TypeInfo)]
+	enum Kind {
+		Normal,
+		Tip(u32),
+		Named { id: u16 },
+	}
+
+	#[derive(Encode, TypeInfo)]
+	struct Transfer {
+		dest: [u8; 4],
+		#[codec(compact)]
+		value: u128,
+		memo: Vec<u8>,
+		kinds: Vec<Kind>,
+		pair: (bool, i8),
+	}
+
+	fn registry<T: TypeInfo + 'static>() -> (u32, PortableRegistry) {
+		let mut registry = Registry::new();
+		let id = registry.register_type(&meta_type::<T>()).id;
+		(id, registry.into())
+	}
+
+	#[test]
+	fn decode_and_encode() {
+		let transfer = Transfer {
+			dest: [1, 2, 3, 4],
+			value: 1_500,
+			memo: b"avail".to_vec(),
+			kinds: vec![Kind::Normal, Kind::Tip(7), Kind::Named { id: 9 }],
+			pair: (true, -1),
+		};
+		let encoded = transfer.encode();
+		let (id, registry) = registry::<Transfer>();
+		let value = decode_as_type(&mut &encoded[..], id, &registry).unwrap();
+
+		assert_eq!(value.at("value").and_then(Value::as_u128), Some(1_500));
+		let kind = value.at("kinds").and_then(|kinds| kinds.at(2)).unwrap();
+		assert_eq!(kind.at("id").and_then(Value::as_u128), Some(9));
+		assert_eq!(
+			value.at("pair").and_then(|pair| pair.at(1)),
+			Some(&Value::i128(-1))
+		);
+
+		let mut reencoded = vec![];
+		encode_as_type(&value, id, &registry, &mut reencoded).unwrap();
+		assert_eq!(reencoded, encoded);
+	}
+
+	#[test_case(Value::u128(255) => true ; "in range")]
+	#[test_case(Value::u128(256) => false ; "out of range")]
+	#[test_case(Value::i128(-1) => false ; "negative")]
+	#[test_case(Value::bool(true) => false ; "wrong type")]
+	fn encode_u8(value: Value) -> bool {
+		let (id, registry) = registry::<u8>();
+		encode_as_type(&value, id, &registry, &mut vec![]).is_ok()
+	}
+
+	#[test_case(Compact(3u32).encode() => true ; "few zero sized elements")]
+	#[test_case(Compact(u32::MAX).encode() => false ; "too many zero sized elements")]
+	fn decode_zero_sized(encoded: Vec<u8>) -> bool {
+		let (id, registry) = registry::<Vec<Vec<()>>>();
+		let encoded = [Compact(1u32).encode(), encoded].concat();
+		decode_as_type(&mut &encoded[..], id, &registry).is_ok()
+	}
+
+	#[test]
+	fn reject_long_sequence() {
+		let (id, registry) = registry::<Vec<u16>>();
+		// Two elements, one byte of input
+		let encoded = [Compact(2u32).encode(), vec![1]].concat();
+		assert!(decode_as_type(&mut &encoded[..], id, &registry).is_err());
+		let (id, registry) = registry::<Vec<Vec<u8>>>();
+		let encoded = [Compact(u32::MAX).encode(), vec![0; 8]].concat();
+		assert!(decode_as_type(&mut &encoded[..], id, &registry).is_err());
+	}
+}
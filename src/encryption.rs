@@ -0,0 +1,309 @@
+//! Encryption envelope for the private app data, so private rollups can use public DA.
+//!
+//! Blob is encrypted with a random content key (ChaCha20-Poly1305), and the content key is wrapped for each recipient
+//! with the key derived (HKDF-SHA256) from the X25519 shared secret of the ephemeral key and the recipient key.
+//! Blob should be compressed before sealing, since the ciphertext doesn't compress.
+//!
+//! # Envelope format
+//!
+//! [`ENVELOPE_MAGIC`] bytes, followed by the SCALE encoded [`Envelope`].
+//!
+//! # Keystore
+//!
+//! Recipient keys are stored in the [`Keystore`] directory, one `<name>.key` file per key. Keys are encrypted with
+//! ChaCha20-Poly1305 if password is provided, with the encryption key derived from the password using
+//! PBKDF2-HMAC-SHA256, same as the network keypair:
+//!
+//! * Plain - `0x00` followed by 32 bytes secret key
+//! * Encrypted - `0x01`, followed by 16 bytes salt, 12 bytes nonce and encrypted secret key
+
+use chacha20poly1305::{
+	aead::{Aead, KeyInit, Payload},
+	ChaCha20Poly1305, Key, Nonce,
+};
+use codec::{Decode, Encode};
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use hkdf::Hkdf;
+use hmac::Hmac;
+use rand::{thread_rng, RngCore};
+use sha2::Sha256;
+use std::{
+	fs,
+	path::{Path, PathBuf},
+};
+use tracing::info;
+use x25519_dalek::{PublicKey, SharedSecret, StaticSecret};
+
+use crate::utils;
+
+pub const ENVELOPE_MAGIC: [u8; 4] = *b"AVEN";
+
+const KEY_INFO: &[u8] = b"avail-light-envelope";
+const NONCE_SIZE: usize = 12;
+const SALT_SIZE: usize = 16;
+const PBKDF2_ROUNDS: u32 = 100_000;
+const PLAIN: u8 = 0;
+const ENCRYPTED: u8 = 1;
+
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct Recipient {
+	pub public_key: [u8; 32],
+	/// Content key, encrypted with the key derived from the shared secret
+	pub wrapped_key: Vec<u8>,
+}
+
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+	pub ephemeral_key: [u8; 32],
+	pub recipients: Vec<Recipient>,
+	pub nonce: [u8; NONCE_SIZE],
+	pub ciphertext: Vec<u8>,
+}
+
+/// Derives the key wrapping key for the recipient, fails on the low order keys
+fn wrapping_key(shared: SharedSecret, ephemeral: &PublicKey, recipient: &PublicKey) -> Result<Key> {
+	if !shared.was_contributory() {
+		return Err(eyre!("Invalid public key"));
+	}
+	let salt = [&ephemeral.as_bytes()[..], &recipient.as_bytes()[..]].concat();
+	let mut key = Key::default();
+	Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+		.expand(KEY_INFO, &mut key)
+		.map_err(|_| eyre!("Cannot derive wrapping key"))?;
+	Ok(key)
+}
+
+/// Encrypts blob to the recipient public keys
+pub fn seal(recipients: &[PublicKey], blob: &[u8]) -> Result<Vec<u8>> {
+	if recipients.is_empty() {
+		return Err(eyre!("Envelope has no recipients"));
+	}
+	let ephemeral = StaticSecret::random_from_rng(thread_rng());
+	let ephemeral_key = PublicKey::from(&ephemeral);
+	let mut content_key = Key::default();
+	let mut nonce = [0u8; NONCE_SIZE];
+	thread_rng().fill_bytes(&mut content_key);
+	thread_rng().fill_bytes(&mut nonce);
+
+	let recipients = recipients
+		.iter()
+		.map(|recipient| {
+			let shared = ephemeral.diffie_hellman(recipient);
+			let key = wrapping_key(shared, &ephemeral_key, recipient)?;
+			// Wrapping key is unique per ephemeral key, so the zero nonce is never reused
+			let wrapped_key = ChaCha20Poly1305::new(&key)
+				.encrypt(&Nonce::default(), content_key.as_slice())
+				.map_err(|_| eyre!("Cannot wrap content key"))?;
+			Ok(Recipient {
+				public_key: recipient.to_bytes(),
+				wrapped_key,
+			})
+		})
+		.collect::<Result<Vec<_>>>()?;
+	let payload = Payload {
+		msg: blob,
+		aad: &ENVELOPE_MAGIC,
+	};
+	let ciphertext = ChaCha20Poly1305::new(&content_key)
+		.encrypt(Nonce::from_slice(&nonce), payload)
+		.map_err(|_| eyre!("Cannot encrypt blob"))?;
+
+	let envelope = Envelope {
+		ephemeral_key: ephemeral_key.to_bytes(),
+		recipients,
+		nonce,
+		ciphertext,
+	};
+	Ok([&ENVELOPE_MAGIC[..], &envelope.encode()].concat())
+}
+
+/// Maximum length of the envelope, excluding the blob length (with up to 5 bytes of the length prefixes)
+pub fn overhead(recipients: usize) -> usize {
+	// Wrapped key is the encrypted content key with the authentication tag
+	let recipient = 32 + 1 + 32 + 16;
+	ENVELOPE_MAGIC.len() + 32 + 5 + recipients * recipient + NONCE_SIZE + 5 + 16
+}
+
+pub fn is_sealed(data: &[u8]) -> bool {
+	data.starts_with(&ENVELOPE_MAGIC)
+}
+
+/// Decrypts blob with the recipient secret key
+pub fn open(secret: &StaticSecret, data: &[u8]) -> Result<Vec<u8>> {
+	let Some(mut encoded) = data.strip_prefix(&ENVELOPE_MAGIC) else {
+		return Err(eyre!("Blob is not an encryption envelope"));
+	};
+	let envelope = Envelope::decode(&mut encoded).wrap_err("Invalid encryption envelope")?;
+	let public_key = PublicKey::from(secret);
+	let recipient = envelope
+		.recipients
+		.iter()
+		.find(|recipient| recipient.public_key == public_key.to_bytes())
+		.ok_or_else(|| {
+			let public_key = hex::encode(public_key.as_bytes());
+			eyre!("Envelope is not encrypted to the key {public_key}")
+		})?;
+
+	let ephemeral_key = PublicKey::from(envelope.ephemeral_key);
+	let shared = secret.diffie_hellman(&ephemeral_key);
+	let key = wrapping_key(shared, &ephemeral_key, &public_key)?;
+	let content_key = ChaCha20Poly1305::new(&key)
+		.decrypt(&Nonce::default(), &recipient.wrapped_key[..])
+		.map_err(|_| eyre!("Cannot unwrap content key"))?;
+	if content_key.len() != 32 {
+		return Err(eyre!("Invalid content key length"));
+	}
+	let payload = Payload {
+		msg: &envelope.ciphertext,
+		aad: &ENVELOPE_MAGIC,
+	};
+	ChaCha20Poly1305::new(Key::from_slice(&content_key))
+		.decrypt(Nonce::from_slice(&envelope.nonce), payload)
+		.map_err(|_| eyre!("Cannot decrypt blob"))
+}
+
+/// Directory of the recipient keys
+pub struct Keystore {
+	path: PathBuf,
+	password: Option<String>,
+}
+
+fn encryption_key(password: &str, salt: &[u8]) -> Key {
+	let mut key = Key::default();
+	pbkdf2::pbkdf2::<Hmac<Sha256>>(password.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+	key
+}
+
+impl Keystore {
+	pub fn open(path: impl AsRef<Path>, password: Option<String>) -> Result<Self> {
+		let path = path.as_ref().to_path_buf();
+		fs::create_dir_all(&path)
+			.wrap_err_with(|| format!("Cannot create keystore {}", path.display()))?;
+		Ok(Keystore { path, password })
+	}
+
+	fn key_path(&self, name: &str) -> Result<PathBuf> {
+		let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+		if name.is_empty() || !name.chars().all(valid) {
+			return Err(eyre!("Invalid key name {name:?}"));
+		}
+		Ok(self.path.join(format!("{name}.key")))
+	}
+
+	fn encode(&self, secret: &StaticSecret) -> Result<Vec<u8>> {
+		let Some(password) = &self.password else {
+			return Ok([&[PLAIN][..], &secret.as_bytes()[..]].concat());
+		};
+		let mut salt = [0u8; SALT_SIZE];
+		let mut nonce = [0u8; NONCE_SIZE];
+		thread_rng().fill_bytes(&mut salt);
+		thread_rng().fill_bytes(&mut nonce);
+		let encrypted = ChaCha20Poly1305::new(&encryption_key(password, &salt))
+			.encrypt(Nonce::from_slice(&nonce), &secret.as_bytes()[..])
+			.map_err(|_| eyre!("Cannot encrypt key"))?;
+		Ok([&[ENCRYPTED], &salt[..], &nonce[..], &encrypted[..]].concat())
+	}
+
+	fn decode(&self, bytes: &[u8]) -> Result<StaticSecret> {
+		let secret = match (bytes.split_first(), &self.password) {
+			(Some((&PLAIN, secret)), _) => secret.to_vec(),
+			(Some((&ENCRYPTED, _)), None) => {
+				return Err(eyre!("Key is encrypted, password is required"))
+			},
+			(Some((&ENCRYPTED, encrypted)), Some(password)) => {
+				if encrypted.len() < SALT_SIZE + NONCE_SIZE {
+					return Err(eyre!("Invalid encrypted key"));
+				}
+				let (salt, encrypted) = encrypted.split_at(SALT_SIZE);
+				let (nonce, encrypted) = encrypted.split_at(NONCE_SIZE);
+				ChaCha20Poly1305::new(&encryption_key(password, salt))
+					.decrypt(Nonce::from_slice(nonce), encrypted)
+					.map_err(|_| eyre!("Cannot decrypt key, invalid password"))?
+			},
+			_ => return Err(eyre!("Unknown key format")),
+		};
+		let secret: [u8; 32] = secret.try_into().map_err(|_| eyre!("Invalid key length"))?;
+		Ok(StaticSecret::from(secret))
+	}
+
+	/// Generates and stores a new key, fails if the key already exists
+	pub fn generate(&self, name: &str) -> Result<PublicKey> {
+		let path = self.key_path(name)?;
+		if path.exists() {
+			return Err(eyre!("Key {name} already exists"));
+		}
+		let secret = StaticSecret::random_from_rng(thread_rng());
+		utils::write_secret_file(&path, &self.encode(&secret)?)
+			.wrap_err_with(|| format!("Cannot store key {}", path.display()))?;
+		let public_key = PublicKey::from(&secret);
+		info!(
+			name,
+			public_key = hex::encode(public_key.as_bytes()),
+			"Encryption key generated"
+		);
+		Ok(public_key)
+	}
+
+	pub fn load(&self, name: &str) -> Result<StaticSecret> {
+		let path = self.key_path(name)?;
+		let bytes =
+			fs::read(&path).wrap_err_with(|| format!("Cannot read key {}", path.display()))?;
+		self.decode(&bytes)
+	}
+
+	pub fn public_key(&self, name: &str) -> Result<PublicKey> {
+		Ok(PublicKey::from(&self.load(name)?))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn secret() -> StaticSecret {
+		StaticSecret::random_from_rng(thread_rng())
+	}
+
+	#[test]
+	fn seal_and_open() {
+		let (alice, bob, eve) = (secret(), secret(), secret());
+		let recipients = [PublicKey::from(&alice), PublicKey::from(&bob)];
+		let sealed = seal(&recipients, b"private batch").unwrap();
+		assert!(is_sealed(&sealed));
+		assert_eq!(open(&alice, &sealed).unwrap(), b"private batch");
+		assert_eq!(open(&bob, &sealed).unwrap(), b"private batch");
+		assert!(open(&eve, &sealed).is_err());
+
+		let mut tampered = sealed.clone();
+		*tampered.last_mut().unwrap() ^= 1;
+		assert!(open(&alice, &tampered).is_err());
+		assert!(seal(&[], b"private batch").is_err());
+		assert!(seal(&[PublicKey::from([0; 32])], b"private batch").is_err());
+	}
+
+	#[test]
+	fn keystore() {
+		let path =
+			std::env::temp_dir().join(format!("avail_light_keystore_{}", thread_rng().next_u64()));
+		let keystore = Keystore::open(&path, Some("password".to_string())).unwrap();
+		let public_key = keystore.generate("rollup").unwrap();
+		assert!(keystore.generate("rollup").is_err());
+		assert!(keystore.generate("../rollup").is_err());
+		assert_eq!(keystore.public_key("rollup").unwrap(), public_key);
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			let metadata = fs::metadata(keystore.key_path("rollup").unwrap()).unwrap();
+			assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+		}
+
+		let sealed = seal(&[public_key], b"private batch").unwrap();
+		let secret = keystore.load("rollup").unwrap();
+		assert_eq!(open(&secret, &sealed).unwrap(), b"private batch");
+		assert!(Keystore::open(&path, None).unwrap().load("rollup").is_err());
+		fs::remove_dir_all(path).unwrap();
+	}
+}
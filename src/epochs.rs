@@ -0,0 +1,170 @@
+//! Persistence of the epoch (validator set) data.
+//!
+//! Epoch descriptors are stored keyed by set ID, together with the index of the stored range, so the latest epoch
+//! is loaded in constant time after restart, instead of recomputing validator sets from genesis. Next validator set
+//! is stored with the current epoch as soon as the change is announced in the header.
+//!
+//! Pruning is tied to finality: once a block in the epoch is finalized, epochs older than [`RETAINED_EPOCHS`] before
+//! it are removed.
+
+use color_eyre::{eyre::WrapErr, Result};
+use sp_core::ed25519;
+use tracing::debug;
+
+use crate::{
+	data::{Database, EpochDescriptor, EpochIndex, Key},
+	finality::ValidatorSet,
+};
+
+/// Number of finalized epochs kept in the database, before the epoch of the finalized block
+pub const RETAINED_EPOCHS: u64 = 32;
+
+pub fn get_epoch(db: &impl Database, set_id: u64) -> Result<Option<EpochDescriptor>> {
+	db.get(Key::Epoch(set_id))
+		.wrap_err("Failed to get epoch descriptor")
+}
+
+/// Returns the descriptor of the latest stored epoch
+pub fn latest_epoch(db: &impl Database) -> Result<Option<EpochDescriptor>> {
+	let index: Option<EpochIndex> = db
+		.get(Key::EpochIndex)
+		.wrap_err("Failed to get epoch index")?;
+	match index {
+		Some(index) => get_epoch(db, index.latest),
+		None => Ok(None),
+	}
+}
+
+/// Returns the validator set of the stored epoch
+pub fn validator_set(db: &impl Database, set_id: u64) -> Result<Option<ValidatorSet>> {
+	Ok(get_epoch(db, set_id)?.map(|epoch| ValidatorSet {
+		set_id: epoch.set_id,
+		validator_set: epoch.validator_set,
+	}))
+}
+
+/// Stores epoch descriptor, replacing the existing one with the same set ID
+pub fn store_epoch(db: &impl Database, epoch: EpochDescriptor) -> Result<()> {
+	let index: Option<EpochIndex> = db
+		.get(Key::EpochIndex)
+		.wrap_err("Failed to get epoch index")?;
+	let index = match index {
+		Some(EpochIndex { earliest, latest }) => EpochIndex {
+			earliest: earliest.min(epoch.set_id),
+			latest: latest.max(epoch.set_id),
+		},
+		None => EpochIndex {
+			earliest: epoch.set_id,
+			latest: epoch.set_id,
+		},
+	};
+	db.put(Key::Epoch(epoch.set_id), epoch)
+		.wrap_err("Failed to store epoch descriptor")?;
+	db.put(Key::EpochIndex, index)
+		.wrap_err("Failed to store epoch index")
+}
+
+/// Stores announced next validator set with the current epoch, if the epoch is stored
+pub fn announce_next_epoch(
+	db: &impl Database,
+	set_id: u64,
+	next_validator_set: Vec<ed25519::Public>,
+) -> Result<()> {
+	let Some(mut epoch) = get_epoch(db, set_id)? else {
+		debug!(
+			set_id,
+			"Epoch is not stored, skipping next epoch announcement"
+		);
+		return Ok(());
+	};
+	epoch.next_validator_set = Some(next_validator_set);
+	db.put(Key::Epoch(set_id), epoch)
+		.wrap_err("Failed to store epoch descriptor")
+}
+
+/// Removes epochs older than `retained` epochs before the finalized one, returning number of pruned epochs
+pub fn prune_epochs(db: &impl Database, finalized_set_id: u64, retained: u64) -> Result<u64> {
+	let index: Option<EpochIndex> = db
+		.get(Key::EpochIndex)
+		.wrap_err("Failed to get epoch index")?;
+	let Some(index) = index else {
+		return Ok(0);
+	};
+	let earliest = finalized_set_id.saturating_sub(retained).min(index.latest);
+	if earliest <= index.earliest {
+		return Ok(0);
+	}
+	for set_id in index.earliest..earliest {
+		db.delete(Key::Epoch(set_id))
+			.wrap_err("Failed to delete epoch descriptor")?;
+	}
+	db.put(
+		Key::EpochIndex,
+		EpochIndex {
+			earliest,
+			latest: index.latest,
+		},
+	)
+	.wrap_err("Failed to store epoch index")?;
+	Ok(earliest - index.earliest)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::data::mem_db::MemoryDB;
+
+	fn epoch(set_id: u64) -> EpochDescriptor {
+		EpochDescriptor {
+			set_id,
+			start_block: set_id as u32 * 100,
+			validator_set: vec![ed25519::Public::from_raw([set_id as u8; 32])],
+			next_validator_set: None,
+		}
+	}
+
+	#[test]
+	fn latest_epoch_after_restart() {
+		let db = MemoryDB::default();
+		assert_eq!(latest_epoch(&db).unwrap(), None);
+		for set_id in 0..3 {
+			store_epoch(&db, epoch(set_id)).unwrap();
+		}
+		assert_eq!(latest_epoch(&db).unwrap(), Some(epoch(2)));
+		assert_eq!(
+			validator_set(&db, 1).unwrap().map(|set| set.set_id),
+			Some(1)
+		);
+	}
+
+	#[test]
+	fn next_epoch_announcement() {
+		let db = MemoryDB::default();
+		store_epoch(&db, epoch(0)).unwrap();
+		let next = epoch(1).validator_set;
+		announce_next_epoch(&db, 0, next.clone()).unwrap();
+		assert_eq!(
+			get_epoch(&db, 0).unwrap().unwrap().next_validator_set,
+			Some(next.clone())
+		);
+		// Announcements for unknown epochs are ignored
+		announce_next_epoch(&db, 5, next).unwrap();
+		assert_eq!(get_epoch(&db, 5).unwrap(), None);
+	}
+
+	#[test]
+	fn prune_finalized_epochs() {
+		let db = MemoryDB::default();
+		for set_id in 0..10 {
+			store_epoch(&db, epoch(set_id)).unwrap();
+		}
+		assert_eq!(prune_epochs(&db, 5, 2).unwrap(), 3);
+		assert_eq!(get_epoch(&db, 2).unwrap(), None);
+		assert_eq!(get_epoch(&db, 3).unwrap(), Some(epoch(3)));
+		// Already pruned epochs are not pruned again
+		assert_eq!(prune_epochs(&db, 5, 2).unwrap(), 0);
+		// Latest epoch is never pruned
+		assert_eq!(prune_epochs(&db, 20, 2).unwrap(), 6);
+		assert_eq!(latest_epoch(&db).unwrap(), Some(epoch(9)));
+	}
+}
@@ -0,0 +1,644 @@
+//! Detection of BABE and GRANDPA equivocations, and construction of the report calls.
+//!
+//! * BABE - two different headers authored in the same slot by the same authority, detected from the BABE
+//!   pre-runtime digests of the observed headers
+//! * GRANDPA - two precommits for different blocks signed by the same voter in the same round, detected from the
+//!   observed justifications
+//!
+//! # Reporting
+//!
+//! Report call consists of the equivocation proof and the key ownership proof of the offender, which is generated
+//! with the runtime API at the block in which the report is submitted ([`babe_key_owner_proof`] and
+//! [`grandpa_key_owner_proof`]). [`EquivocationReporter`] observes headers and justifications of the subscription
+//! loop, and [`submit_reports`] submits the report calls as signed extrinsics, so the reporter is rewarded once the
+//! offender is slashed.
+//!
+//! # Notes
+//!
+//! Unsigned `report_equivocation_unsigned` calls are not used, since runtime accepts them only from the local
+//! block authors, and rejects them when submitted over RPC (`TransactionSource::External`).
+
+use avail_subxt::{config::substrate::DigestItem, primitives::Header as DaHeader, utils::H256};
+use codec::{Decode, Encode, Output};
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use sp_core::{blake2_256, ed25519, sr25519, Pair};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use subxt::{utils::AccountId32, Metadata};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::{
+	extrinsic::{calls::CallIndex, ExtrinsicBuilder, ExtrinsicParams},
+	fee::RuntimeApi,
+	network::rpc::Client as RpcClient,
+	nonce::AccountNonceProvider,
+	types::{GrandpaJustification, Precommit, SignerMessage},
+};
+
+pub const BABE_ENGINE_ID: [u8; 4] = *b"BABE";
+const GENERATE_BABE_KEY_OWNERSHIP_PROOF: &str = "BabeApi_generate_key_ownership_proof";
+const GENERATE_GRANDPA_KEY_OWNERSHIP_PROOF: &str = "GrandpaApi_generate_key_ownership_proof";
+const BABE_CURRENT_EPOCH: &str = "BabeApi_current_epoch";
+/// Maximum number of detected equivocations waiting to be reported
+const MAX_PENDING_REPORTS: usize = 64;
+/// `Equivocation::Precommit` variant index
+const PRECOMMIT_EQUIVOCATION: u8 = 1;
+
+/// Authority and slot claimed in the BABE pre-runtime digest
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SlotClaim {
+	pub slot: u64,
+	pub authority_index: u32,
+}
+
+/// Decodes slot claim from the BABE pre-runtime digest, if present
+pub fn slot_claim(header: &DaHeader) -> Option<SlotClaim> {
+	header.digest.logs.iter().find_map(|item| match item {
+		DigestItem::PreRuntime(engine_id, data) if *engine_id == BABE_ENGINE_ID => {
+			// Authority index and slot follow the variant index in all pre-digest variants
+			let (authority_index, slot) = <(u32, u64)>::decode(&mut data.get(1..)?).ok()?;
+			Some(SlotClaim {
+				slot,
+				authority_index,
+			})
+		},
+		_ => None,
+	})
+}
+
+/// Two headers authored by the same authority in the same slot
+#[derive(Clone, Debug)]
+pub struct BabeEquivocation {
+	pub claim: SlotClaim,
+	pub first_header: DaHeader,
+	pub second_header: DaHeader,
+}
+
+impl BabeEquivocation {
+	/// Creates equivocation proof, with the offender from the authorities of the slot epoch
+	pub fn proof(&self, authorities: &[[u8; 32]]) -> Option<BabeEquivocationProof> {
+		let offender = *authorities.get(self.claim.authority_index as usize)?;
+		Some(BabeEquivocationProof {
+			offender,
+			slot: self.claim.slot,
+			first_header: self.first_header.clone(),
+			second_header: self.second_header.clone(),
+		})
+	}
+}
+
+/// Encoded as `sp_consensus_slots::EquivocationProof`
+#[derive(Clone, Debug, Encode)]
+pub struct BabeEquivocationProof {
+	/// sr25519 public key of the authority
+	pub offender: [u8; 32],
+	pub slot: u64,
+	pub first_header: DaHeader,
+	pub second_header: DaHeader,
+}
+
+/// Keeps the first header of each observed slot claim, until the slot is pruned
+#[derive(Default)]
+pub struct BabeEquivocationDetector {
+	claims: BTreeMap<SlotClaim, (H256, DaHeader)>,
+	reported: HashSet<SlotClaim>,
+}
+
+impl BabeEquivocationDetector {
+	/// Observes header, returning equivocation if another header with the same slot claim was observed.
+	/// Equivocation is returned only once per slot claim.
+	pub fn observe_header(&mut self, header: &DaHeader) -> Option<BabeEquivocation> {
+		let claim = slot_claim(header)?;
+		let hash: H256 = Encode::using_encoded(header, blake2_256).into();
+		let Some((first_hash, first_header)) = self.claims.get(&claim) else {
+			self.claims.insert(claim, (hash, header.clone()));
+			return None;
+		};
+		if *first_hash == hash || !self.reported.insert(claim) {
+			return None;
+		}
+		warn!(
+			slot = claim.slot,
+			authority_index = claim.authority_index,
+			"BABE equivocation detected"
+		);
+		Some(BabeEquivocation {
+			claim,
+			first_header: first_header.clone(),
+			second_header: header.clone(),
+		})
+	}
+
+	/// Forgets slots before the given one (e.g. slot of the finalized block, since equivocations cannot be reported
+	/// for blocks which are too old)
+	pub fn prune(&mut self, slot: u64) {
+		self.claims.retain(|claim, _| claim.slot >= slot);
+		self.reported.retain(|claim| claim.slot >= slot);
+	}
+}
+
+/// Encoded as `finality_grandpa::Equivocation` of precommits
+#[derive(Clone, Debug, Encode)]
+pub struct PrecommitEquivocation {
+	pub round_number: u64,
+	pub identity: ed25519::Public,
+	pub first: (Precommit, ed25519::Signature),
+	pub second: (Precommit, ed25519::Signature),
+}
+
+/// Encoded as `sp_consensus_grandpa::EquivocationProof`
+#[derive(Clone, Debug)]
+pub struct GrandpaEquivocationProof {
+	pub set_id: u64,
+	pub equivocation: PrecommitEquivocation,
+}
+
+impl Encode for GrandpaEquivocationProof {
+	fn encode_to<T: Output + ?Sized>(&self, dest: &mut T) {
+		self.set_id.encode_to(dest);
+		PRECOMMIT_EQUIVOCATION.encode_to(dest);
+		self.equivocation.encode_to(dest);
+	}
+}
+
+fn is_signed(
+	precommit: &Precommit,
+	signature: &ed25519::Signature,
+	voter: &ed25519::Public,
+	round: u64,
+	set_id: u64,
+) -> bool {
+	let message = Encode::encode(&(
+		&SignerMessage::PrecommitMessage(precommit.clone()),
+		&round,
+		&set_id,
+	));
+	<ed25519::Pair as Pair>::verify(signature, message, voter)
+}
+
+type Votes = HashMap<ed25519::Public, (Precommit, ed25519::Signature)>;
+
+/// Keeps validly signed precommits of each observed round, until the set is pruned
+#[derive(Default)]
+pub struct GrandpaEquivocationDetector {
+	rounds: BTreeMap<(u64, u64), Votes>,
+	reported: HashSet<(u64, u64, ed25519::Public)>,
+}
+
+impl GrandpaEquivocationDetector {
+	/// Observes justification of the given validator set, returning equivocations of voters which signed precommits
+	/// for different blocks in the same round. Precommits with invalid signatures are ignored.
+	pub fn observe_justification(
+		&mut self,
+		set_id: u64,
+		justification: &GrandpaJustification,
+	) -> Vec<GrandpaEquivocationProof> {
+		let round = justification.round;
+		let votes = self.rounds.entry((set_id, round)).or_default();
+		let mut equivocations = vec![];
+
+		for signed in &justification.commit.precommits {
+			if !is_signed(
+				&signed.precommit,
+				&signed.signature,
+				&signed.id,
+				round,
+				set_id,
+			) {
+				debug!(set_id, round, voter = ?signed.id, "Ignoring invalid precommit signature");
+				continue;
+			}
+			let Some((first, first_signature)) = votes.get(&signed.id) else {
+				votes.insert(
+					signed.id,
+					(signed.precommit.clone(), signed.signature.clone()),
+				);
+				continue;
+			};
+			if first.target_hash == signed.precommit.target_hash
+				|| !self.reported.insert((set_id, round, signed.id))
+			{
+				continue;
+			}
+			warn!(set_id, round, voter = ?signed.id, "GRANDPA equivocation detected");
+			equivocations.push(GrandpaEquivocationProof {
+				set_id,
+				equivocation: PrecommitEquivocation {
+					round_number: round,
+					identity: signed.id,
+					first: (first.clone(), first_signature.clone()),
+					second: (signed.precommit.clone(), signed.signature.clone()),
+				},
+			});
+		}
+		equivocations
+	}
+
+	/// Forgets rounds of the validator sets before the given one
+	pub fn prune(&mut self, set_id: u64) {
+		self.rounds
+			.retain(|(round_set_id, _), _| *round_set_id >= set_id);
+		self.reported
+			.retain(|(round_set_id, _, _)| *round_set_id >= set_id);
+	}
+}
+
+/// Encoded key ownership proof (`MembershipProof`) of the offender, generated by the runtime
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyOwnerProof(pub Vec<u8>);
+
+async fn key_owner_proof(
+	api: &impl RuntimeApi,
+	method: &str,
+	data: Vec<u8>,
+	at: H256,
+) -> Result<Option<KeyOwnerProof>> {
+	let result = api.call(method, data, Some(at)).await?;
+	// Runtime returns `Option<OpaqueKeyOwnershipProof>`, which wraps encoded proof
+	let proof = Option::<Vec<u8>>::decode(&mut &result[..])
+		.wrap_err_with(|| format!("Cannot decode {method} result"))?;
+	Ok(proof.map(KeyOwnerProof))
+}
+
+/// Generates key ownership proof of the BABE authority, `None` if the authority is not in the current session
+pub async fn babe_key_owner_proof(
+	api: &impl RuntimeApi,
+	at: H256,
+	slot: u64,
+	offender: [u8; 32],
+) -> Result<Option<KeyOwnerProof>> {
+	let data = (slot, offender).encode();
+	key_owner_proof(api, GENERATE_BABE_KEY_OWNERSHIP_PROOF, data, at).await
+}
+
+/// Generates key ownership proof of the GRANDPA voter, `None` if the voter is not in the current session
+pub async fn grandpa_key_owner_proof(
+	api: &impl RuntimeApi,
+	at: H256,
+	set_id: u64,
+	offender: ed25519::Public,
+) -> Result<Option<KeyOwnerProof>> {
+	let data = (set_id, offender).encode();
+	key_owner_proof(api, GENERATE_GRANDPA_KEY_OWNERSHIP_PROOF, data, at).await
+}
+
+/// Returns sr25519 public keys of the BABE authorities in the epoch of the given block
+pub async fn babe_authorities(api: &impl RuntimeApi, at: H256) -> Result<Vec<[u8; 32]>> {
+	let result = api.call(BABE_CURRENT_EPOCH, vec![], Some(at)).await?;
+	// Epoch index, start slot and duration precede the authorities in `sp_consensus_babe::Epoch`
+	let (_, _, _, authorities) = <(u64, u64, u64, Vec<([u8; 32], u64)>)>::decode(&mut &result[..])
+		.wrap_err_with(|| format!("Cannot decode {BABE_CURRENT_EPOCH} result"))?;
+	Ok(authorities
+		.into_iter()
+		.map(|(authority, _)| authority)
+		.collect())
+}
+
+/// Indices of the equivocation report calls in the current runtime
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReportCalls {
+	pub babe: CallIndex,
+	pub grandpa: CallIndex,
+}
+
+impl ReportCalls {
+	/// Report calls for the signed extrinsics, reporter is rewarded once the offender is slashed
+	pub fn signed(metadata: &Metadata) -> Result<Self> {
+		Ok(ReportCalls {
+			babe: CallIndex::from_metadata(metadata, "Babe", "report_equivocation")?,
+			grandpa: CallIndex::from_metadata(metadata, "Grandpa", "report_equivocation")?,
+		})
+	}
+
+	fn report(index: CallIndex, proof: &impl Encode, key_owner_proof: &KeyOwnerProof) -> Vec<u8> {
+		let mut encoded = index.encode();
+		proof.encode_to(&mut encoded);
+		encoded.extend_from_slice(&key_owner_proof.0);
+		encoded
+	}
+
+	pub fn babe(&self, proof: &BabeEquivocationProof, key_owner_proof: &KeyOwnerProof) -> Vec<u8> {
+		Self::report(self.babe, proof, key_owner_proof)
+	}
+
+	pub fn grandpa(
+		&self,
+		proof: &GrandpaEquivocationProof,
+		key_owner_proof: &KeyOwnerProof,
+	) -> Vec<u8> {
+		Self::report(self.grandpa, proof, key_owner_proof)
+	}
+}
+
+/// Equivocation detected by the [`EquivocationReporter`]
+#[derive(Clone, Debug)]
+pub enum Offence {
+	Babe(BabeEquivocation),
+	Grandpa(GrandpaEquivocationProof),
+}
+
+/// Observes headers and justifications, and sends detected equivocations to [`submit_reports`]
+pub struct EquivocationReporter {
+	babe: BabeEquivocationDetector,
+	grandpa: GrandpaEquivocationDetector,
+	sender: mpsc::Sender<Offence>,
+}
+
+/// Creates reporter, with the receiver of the detected equivocations
+pub fn reporter() -> (EquivocationReporter, mpsc::Receiver<Offence>) {
+	let (sender, receiver) = mpsc::channel(MAX_PENDING_REPORTS);
+	let reporter = EquivocationReporter {
+		babe: Default::default(),
+		grandpa: Default::default(),
+		sender,
+	};
+	(reporter, receiver)
+}
+
+impl EquivocationReporter {
+	fn send(&self, offence: Offence) {
+		if let Err(error) = self.sender.try_send(offence) {
+			warn!("Equivocation is not reported: {error}");
+		}
+	}
+
+	pub fn observe_header(&mut self, header: &DaHeader) {
+		if let Some(equivocation) = self.babe.observe_header(header) {
+			self.send(Offence::Babe(equivocation));
+		}
+	}
+
+	pub fn observe_justification(&mut self, set_id: u64, justification: &GrandpaJustification) {
+		for proof in self.grandpa.observe_justification(set_id, justification) {
+			self.send(Offence::Grandpa(proof));
+		}
+	}
+
+	/// Forgets slots before the finalized header, and rounds of the validator sets before its set
+	pub fn finalized(&mut self, header: &DaHeader, set_id: u64) {
+		if let Some(claim) = slot_claim(header) {
+			self.babe.prune(claim.slot);
+		}
+		self.grandpa.prune(set_id);
+	}
+}
+
+/// Creates report call of the offence, `None` if the offender is not in the current session anymore
+async fn report_call(
+	api: &impl RuntimeApi,
+	calls: &ReportCalls,
+	offence: &Offence,
+	at: H256,
+) -> Result<Option<Vec<u8>>> {
+	match offence {
+		Offence::Babe(equivocation) => {
+			// Authorities of the slot epoch are the ones in the parent block, unless the slot starts new epoch
+			let authorities = babe_authorities(api, equivocation.first_header.parent_hash).await?;
+			let proof = equivocation.proof(&authorities).ok_or_else(|| {
+				eyre!(
+					"Authority {} is not in the epoch",
+					equivocation.claim.authority_index
+				)
+			})?;
+			let key_owner_proof = babe_key_owner_proof(api, at, proof.slot, proof.offender).await?;
+			Ok(key_owner_proof.map(|key_owner_proof| calls.babe(&proof, &key_owner_proof)))
+		},
+		Offence::Grandpa(proof) => {
+			let identity = proof.equivocation.identity;
+			let key_owner_proof = grandpa_key_owner_proof(api, at, proof.set_id, identity).await?;
+			Ok(key_owner_proof.map(|key_owner_proof| calls.grandpa(proof, &key_owner_proof)))
+		},
+	}
+}
+
+async fn submit_report(
+	rpc_client: &RpcClient,
+	pair: &sr25519::Pair,
+	nonces: &AccountNonceProvider<RpcClient>,
+	offence: &Offence,
+) -> Result<()> {
+	let at = rpc_client.get_finalized_head_hash().await?;
+	let metadata = rpc_client.current_client().await.metadata();
+	let calls = ReportCalls::signed(&metadata)?;
+	let Some(call) = report_call(rpc_client, &calls, offence, at).await? else {
+		info!("Offender is not in the current session, equivocation is not reported");
+		return Ok(());
+	};
+
+	let genesis_hash = rpc_client.get_genesis_hash().await?;
+	let runtime_version = rpc_client.get_runtime_version().await?;
+	let builder = ExtrinsicBuilder::new(genesis_hash, (&runtime_version).into());
+	let account = AccountId32(pair.public().0);
+	// Nonce is released if submission fails
+	let nonce = nonces.allocate(&account).await?;
+	let params = ExtrinsicParams {
+		nonce: nonce.value(),
+		..Default::default()
+	};
+	let extrinsic = builder.sign(&builder.payload(call, params), pair);
+	let events = rpc_client
+		.submit_from_bytes_and_wait_for_finalized(extrinsic)
+		.await
+		.wrap_err("Failed to submit equivocation report")?;
+	nonce.included();
+	info!(block_hash = ?events.block_hash(), "Equivocation reported");
+	Ok(())
+}
+
+/// Submits signed report extrinsics of the detected equivocations, until the reporter is dropped.
+/// Equivocations which cannot be reported are logged and skipped.
+pub async fn submit_reports(
+	rpc_client: RpcClient,
+	pair: sr25519::Pair,
+	mut offences: mpsc::Receiver<Offence>,
+) {
+	let nonces = AccountNonceProvider::new(rpc_client.clone());
+	while let Some(offence) = offences.recv().await {
+		if let Err(error) = submit_report(&rpc_client, &pair, &nonces, &offence).await {
+			warn!("Cannot report equivocation: {error:#}");
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{fee::MockRuntimeApi, header::HeaderHash, test_utils::sign_justification};
+	use avail_subxt::{
+		api::runtime_types::avail_core::{
+			data_lookup::compact::CompactDataLookup,
+			header::extension::{v3, HeaderExtension},
+			kate_commitment::v3::KateCommitment,
+		},
+		config::substrate::Digest,
+	};
+	use mockall::predicate::eq;
+
+	const CALLS: ReportCalls = ReportCalls {
+		babe: CallIndex(10, 1),
+		grandpa: CallIndex(17, 1),
+	};
+
+	fn header(slot: u64, authority_index: u32, state_root: u8) -> DaHeader {
+		let mut pre_digest = vec![1];
+		(authority_index, slot).encode_to(&mut pre_digest);
+		pre_digest.extend([0; 96]);
+		DaHeader {
+			parent_hash: H256::zero(),
+			number: 1,
+			state_root: H256::repeat_byte(state_root),
+			extrinsics_root: H256::zero(),
+			digest: Digest {
+				logs: vec![DigestItem::PreRuntime(BABE_ENGINE_ID, pre_digest)],
+			},
+			extension: HeaderExtension::V3(v3::HeaderExtension {
+				commitment: KateCommitment {
+					rows: 1,
+					cols: 4,
+					data_root: H256::zero(),
+					commitment: vec![0; 48],
+				},
+				app_lookup: CompactDataLookup {
+					size: 0,
+					index: vec![],
+				},
+			}),
+		}
+	}
+
+	#[test]
+	fn detect_babe_equivocation() {
+		let mut detector = BabeEquivocationDetector::default();
+		assert!(detector.observe_header(&header(5, 1, 1)).is_none());
+		assert!(detector.observe_header(&header(5, 1, 1)).is_none());
+		assert!(detector.observe_header(&header(5, 2, 2)).is_none());
+		assert!(detector.observe_header(&header(6, 1, 2)).is_none());
+
+		let equivocation = detector.observe_header(&header(5, 1, 2)).unwrap();
+		assert_eq!(
+			equivocation.claim,
+			SlotClaim {
+				slot: 5,
+				authority_index: 1
+			}
+		);
+		assert_eq!(
+			equivocation.first_header.header_hash(),
+			header(5, 1, 1).header_hash()
+		);
+		// Equivocation is reported once
+		assert!(detector.observe_header(&header(5, 1, 3)).is_none());
+
+		let proof = equivocation.proof(&[[0; 32], [1; 32]]).unwrap();
+		assert_eq!(proof.offender, [1; 32]);
+		assert!(equivocation.proof(&[[0; 32]]).is_none());
+
+		detector.prune(6);
+		assert!(detector.observe_header(&header(5, 1, 4)).is_none());
+	}
+
+	#[test]
+	fn report_detected_equivocations() {
+		let (mut reporter, mut offences) = reporter();
+		reporter.observe_header(&header(5, 1, 1));
+		reporter.observe_header(&header(5, 1, 2));
+		assert!(matches!(offences.try_recv(), Ok(Offence::Babe(_))));
+
+		let pairs = [ed25519::Pair::from_seed(&[1; 32])];
+		reporter.observe_justification(3, &sign_justification(&header(1, 0, 1), &pairs, 3, 7));
+		reporter.observe_justification(3, &sign_justification(&header(1, 0, 2), &pairs, 3, 7));
+		assert!(matches!(offences.try_recv(), Ok(Offence::Grandpa(_))));
+		assert!(offences.try_recv().is_err());
+	}
+
+	#[tokio::test]
+	async fn decode_babe_authorities() {
+		let mut api = MockRuntimeApi::new();
+		api.expect_call()
+			.with(eq(BABE_CURRENT_EPOCH), eq(vec![]), eq(Some(H256::zero())))
+			.returning(|_, _, _| {
+				let authorities = vec![([1u8; 32], 1u64), ([2u8; 32], 1u64)];
+				// Randomness and epoch config follow the authorities
+				let epoch = (
+					1u64,
+					100u64,
+					600u64,
+					authorities,
+					[0u8; 32],
+					(1u64, 4u64),
+					2u8,
+				);
+				Box::pin(async move { Ok(epoch.encode()) })
+			});
+		let authorities = babe_authorities(&api, H256::zero()).await.unwrap();
+		assert_eq!(authorities, vec![[1; 32], [2; 32]]);
+	}
+
+	#[test]
+	fn detect_grandpa_equivocation() {
+		let pairs = [[1; 32], [2; 32]].map(|seed| ed25519::Pair::from_seed(&seed));
+		let first = sign_justification(&header(1, 0, 1), &pairs, 3, 7);
+		let second = sign_justification(&header(1, 0, 2), &pairs[..1], 3, 7);
+		let mut detector = GrandpaEquivocationDetector::default();
+
+		assert!(detector.observe_justification(3, &first).is_empty());
+		// Same votes in other round are not equivocations
+		let other_round = sign_justification(&header(1, 0, 2), &pairs, 3, 8);
+		assert!(detector.observe_justification(3, &other_round).is_empty());
+
+		let equivocations = detector.observe_justification(3, &second);
+		assert_eq!(equivocations.len(), 1);
+		let proof = &equivocations[0];
+		assert_eq!(proof.set_id, 3);
+		assert_eq!(proof.equivocation.identity, pairs[0].public());
+		assert_eq!(proof.equivocation.round_number, 7);
+		assert!(detector.observe_justification(3, &second).is_empty());
+	}
+
+	#[test]
+	fn ignore_invalid_precommit_signatures() {
+		let pairs = [ed25519::Pair::from_seed(&[1; 32])];
+		let first = sign_justification(&header(1, 0, 1), &pairs, 3, 7);
+		// Signed for a different set, so signatures are invalid for set 3
+		let second = sign_justification(&header(1, 0, 2), &pairs, 4, 7);
+		let mut detector = GrandpaEquivocationDetector::default();
+		assert!(detector.observe_justification(3, &first).is_empty());
+		assert!(detector.observe_justification(3, &second).is_empty());
+	}
+
+	#[tokio::test]
+	async fn grandpa_report_call() {
+		let pairs = [ed25519::Pair::from_seed(&[1; 32])];
+		let mut detector = GrandpaEquivocationDetector::default();
+		detector.observe_justification(3, &sign_justification(&header(1, 0, 1), &pairs, 3, 7));
+		let proof = detector
+			.observe_justification(3, &sign_justification(&header(1, 0, 2), &pairs, 3, 7))
+			.pop()
+			.unwrap();
+
+		let mut api = MockRuntimeApi::new();
+		let data = (3u64, proof.equivocation.identity).encode();
+		api.expect_call()
+			.with(
+				eq(GENERATE_GRANDPA_KEY_OWNERSHIP_PROOF),
+				eq(data),
+				eq(Some(H256::zero())),
+			)
+			.returning(|_, _, _| Box::pin(async move { Ok(Some(vec![4, 5, 6]).encode()) }));
+		let key_owner_proof =
+			grandpa_key_owner_proof(&api, H256::zero(), 3, proof.equivocation.identity)
+				.await
+				.unwrap()
+				.unwrap();
+		assert_eq!(key_owner_proof, KeyOwnerProof(vec![4, 5, 6]));
+
+		let call = CALLS.grandpa(&proof, &key_owner_proof);
+		assert_eq!(call[..2], [17, 1]);
+		assert_eq!(call[2..10], 3u64.encode()[..]);
+		assert_eq!(call[10], PRECOMMIT_EQUIVOCATION);
+		assert_eq!(call[call.len() - 3..], [4, 5, 6]);
+	}
+}
@@ -0,0 +1,198 @@
+//! Encoding of Avail headers, data roots and validator sets for the Ethereum attestation bridge contracts.
+//!
+//! # Layouts
+//!
+//! * Header - ABI encoded `(bytes32 parentHash, uint32 number, bytes32 stateRoot, bytes32 extrinsicsRoot, bytes32 dataRoot)`
+//! * Validator set - ABI encoded `(uint64 setId, bytes32[] authorities)`
+//! * Data root leaf - `keccak256(dataRoot)`
+//!
+//! RLP encoding of the same header fields is provided for contracts verifying RLP encoded payloads.
+
+use avail_subxt::{primitives::Header, utils::H256};
+use codec::Encode;
+use ethabi::{ethereum_types::U256, Token};
+use sp_core::{blake2_256, ed25519};
+use tiny_keccak::{Hasher, Keccak};
+
+use crate::utils::extract_kate;
+
+pub mod commitment;
+
+pub fn keccak256(data: &[u8]) -> H256 {
+	let mut hasher = Keccak::v256();
+	let mut output = [0u8; 32];
+	hasher.update(data);
+	hasher.finalize(&mut output);
+	H256(output)
+}
+
+/// Header fields attested by the bridge
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BridgeHeader {
+	/// Blake2 hash of the SCALE encoded header
+	pub hash: H256,
+	pub parent_hash: H256,
+	pub number: u32,
+	pub state_root: H256,
+	pub extrinsics_root: H256,
+	pub data_root: H256,
+}
+
+impl From<&Header> for BridgeHeader {
+	fn from(header: &Header) -> Self {
+		let (_, _, data_root, _) = extract_kate(&header.extension);
+		BridgeHeader {
+			hash: Encode::using_encoded(header, blake2_256).into(),
+			parent_hash: header.parent_hash,
+			number: header.number,
+			state_root: header.state_root,
+			extrinsics_root: header.extrinsics_root,
+			data_root,
+		}
+	}
+}
+
+fn bytes32(hash: &H256) -> Token {
+	Token::FixedBytes(hash.as_bytes().to_vec())
+}
+
+impl BridgeHeader {
+	pub fn abi_encode(&self) -> Vec<u8> {
+		ethabi::encode(&[
+			bytes32(&self.parent_hash),
+			Token::Uint(U256::from(self.number)),
+			bytes32(&self.state_root),
+			bytes32(&self.extrinsics_root),
+			bytes32(&self.data_root),
+		])
+	}
+
+	pub fn rlp_encode(&self) -> Vec<u8> {
+		rlp::encode_list(&[
+			rlp::encode_bytes(self.parent_hash.as_bytes()),
+			rlp::encode_uint(self.number as u64),
+			rlp::encode_bytes(self.state_root.as_bytes()),
+			rlp::encode_bytes(self.extrinsics_root.as_bytes()),
+			rlp::encode_bytes(self.data_root.as_bytes()),
+		])
+	}
+
+	/// Keccak digest of the ABI encoded header, as calculated by the bridge contracts
+	pub fn digest(&self) -> H256 {
+		keccak256(&self.abi_encode())
+	}
+
+	/// Leaf of the data root, used in data root commitment Merkle tree
+	pub fn data_root_leaf(&self) -> H256 {
+		keccak256(self.data_root.as_bytes())
+	}
+}
+
+pub fn abi_encode_validator_set(set_id: u64, validator_set: &[ed25519::Public]) -> Vec<u8> {
+	let authorities = validator_set
+		.iter()
+		.map(|public| Token::FixedBytes(public.0.to_vec()))
+		.collect();
+	ethabi::encode(&[Token::Uint(U256::from(set_id)), Token::Array(authorities)])
+}
+
+/// Keccak digest of the ABI encoded validator set
+pub fn validator_set_digest(set_id: u64, validator_set: &[ed25519::Public]) -> H256 {
+	keccak256(&abi_encode_validator_set(set_id, validator_set))
+}
+
+/// Minimal RLP encoder, sufficient for encoding byte strings, integers and lists.
+pub mod rlp {
+	fn encode_length(length: usize, offset: u8) -> Vec<u8> {
+		if length < 56 {
+			return vec![offset + length as u8];
+		}
+		let length_bytes = length
+			.to_be_bytes()
+			.into_iter()
+			.skip_while(|&byte| byte == 0)
+			.collect::<Vec<_>>();
+		[vec![offset + 55 + length_bytes.len() as u8], length_bytes].concat()
+	}
+
+	pub fn encode_bytes(bytes: &[u8]) -> Vec<u8> {
+		if bytes.len() == 1 && bytes[0] < 0x80 {
+			return bytes.to_vec();
+		}
+		[encode_length(bytes.len(), 0x80), bytes.to_vec()].concat()
+	}
+
+	pub fn encode_uint(value: u64) -> Vec<u8> {
+		let bytes = value
+			.to_be_bytes()
+			.into_iter()
+			.skip_while(|&byte| byte == 0)
+			.collect::<Vec<_>>();
+		encode_bytes(&bytes)
+	}
+
+	pub fn encode_list(items: &[Vec<u8>]) -> Vec<u8> {
+		let payload = items.concat();
+		[encode_length(payload.len(), 0xc0), payload].concat()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use hex_literal::hex;
+	use test_case::test_case;
+
+	#[test]
+	fn keccak256_empty() {
+		assert_eq!(
+			keccak256(&[]),
+			H256(hex!(
+				"c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+			))
+		);
+	}
+
+	#[test]
+	fn abi_encode_header() {
+		let header = BridgeHeader {
+			hash: H256::zero(),
+			parent_hash: H256::repeat_byte(1),
+			number: 5,
+			state_root: H256::repeat_byte(2),
+			extrinsics_root: H256::repeat_byte(3),
+			data_root: H256::repeat_byte(4),
+		};
+		let encoded = header.abi_encode();
+		assert_eq!(encoded.len(), 5 * 32);
+		assert_eq!(&encoded[..32], &[1u8; 32]);
+		assert_eq!(encoded[63], 5);
+		assert_eq!(&encoded[128..], &[4u8; 32]);
+	}
+
+	#[test]
+	fn abi_encode_validators() {
+		let validators = vec![ed25519::Public([1u8; 32]), ed25519::Public([2u8; 32])];
+		let encoded = abi_encode_validator_set(7, &validators);
+		// set id, array offset, array length and two elements
+		assert_eq!(encoded.len(), 5 * 32);
+		assert_eq!(encoded[31], 7);
+		assert_eq!(encoded[95], 2);
+	}
+
+	#[test_case(&[] => hex!("80").to_vec() ; "empty bytes")]
+	#[test_case(&[0x7f] => hex!("7f").to_vec() ; "single byte")]
+	#[test_case(b"dog" => hex!("83646f67").to_vec() ; "short string")]
+	fn rlp_encode_bytes(bytes: &[u8]) -> Vec<u8> {
+		rlp::encode_bytes(bytes)
+	}
+
+	#[test]
+	fn rlp_encode_list() {
+		let list = rlp::encode_list(&[rlp::encode_bytes(b"cat"), rlp::encode_bytes(b"dog")]);
+		assert_eq!(list, hex!("c88363617483646f67").to_vec());
+		assert_eq!(rlp::encode_uint(0), hex!("80").to_vec());
+		assert_eq!(rlp::encode_uint(1024), hex!("820400").to_vec());
+		assert_eq!(rlp::encode_bytes(&[0u8; 56])[..2], hex!("b838"));
+	}
+}
@@ -0,0 +1,199 @@
+//! Data root commitment batches, as consumed by VectorX-style bridges.
+//!
+//! Data roots of consecutive blocks in range `(start_block, end_block]` are leaves of a SHA-256 Merkle tree,
+//! padded with zero leaves to the batch size (power of two). Root of the tree is the data root commitment
+//! attested on Ethereum, and individual data roots are proven with Merkle proofs against it.
+
+use avail_subxt::{primitives::Header, utils::H256};
+use color_eyre::{eyre::eyre, Result};
+use sha2::{Digest, Sha256};
+
+use crate::utils::extract_kate;
+
+fn sha256(left: &H256, right: &H256) -> H256 {
+	let mut hasher = Sha256::new();
+	hasher.update(left.as_bytes());
+	hasher.update(right.as_bytes());
+	H256(hasher.finalize().into())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DataRootProof {
+	pub block_number: u32,
+	pub data_root: H256,
+	/// Leaf index in the batch
+	pub index: usize,
+	/// Sibling hashes, from the leaf level up to the root
+	pub siblings: Vec<H256>,
+}
+
+impl DataRootProof {
+	pub fn verify(&self, commitment: H256) -> bool {
+		let (root, _) =
+			self.siblings
+				.iter()
+				.fold((self.data_root, self.index), |(node, index), sibling| {
+					let parent = if index % 2 == 0 {
+						sha256(&node, sibling)
+					} else {
+						sha256(sibling, &node)
+					};
+					(parent, index / 2)
+				});
+		root == commitment
+	}
+}
+
+/// Data root commitment over the range of consecutive blocks
+#[derive(Clone, Debug)]
+pub struct DataRootBatch {
+	pub start_block: u32,
+	pub end_block: u32,
+	/// Tree levels, from leaves to the root
+	levels: Vec<Vec<H256>>,
+}
+
+impl DataRootBatch {
+	/// Creates batch from consecutive headers, covering blocks in range `(start_block, end_block]`.
+	pub fn from_headers(headers: &[Header], batch_size: usize) -> Result<Self> {
+		let first = headers.first().ok_or_else(|| eyre!("Headers are empty"))?;
+		if let Some(pair) = headers
+			.windows(2)
+			.find(|pair| pair[0].number + 1 != pair[1].number)
+		{
+			return Err(eyre!(
+				"Headers are not consecutive: {} is followed by {}",
+				pair[0].number,
+				pair[1].number
+			));
+		}
+		let data_roots = headers
+			.iter()
+			.map(|header| extract_kate(&header.extension).2)
+			.collect::<Vec<_>>();
+		let start_block = first
+			.number
+			.checked_sub(1)
+			.ok_or_else(|| eyre!("Genesis block has no data root to commit"))?;
+		Self::new(start_block, data_roots, batch_size)
+	}
+
+	pub fn new(start_block: u32, data_roots: Vec<H256>, batch_size: usize) -> Result<Self> {
+		if !batch_size.is_power_of_two() {
+			return Err(eyre!("Batch size {batch_size} is not a power of two"));
+		}
+		if data_roots.is_empty() || data_roots.len() > batch_size {
+			return Err(eyre!(
+				"Invalid number of data roots {}, batch size is {batch_size}",
+				data_roots.len()
+			));
+		}
+
+		let end_block = u32::try_from(data_roots.len())
+			.ok()
+			.and_then(|len| start_block.checked_add(len))
+			.ok_or_else(|| eyre!("Batch starting at {start_block} exceeds block number range"))?;
+		let mut leaves = data_roots;
+		leaves.resize(batch_size, H256::zero());
+
+		let mut levels = vec![leaves];
+		while levels.last().map_or(false, |level| level.len() > 1) {
+			let level = levels.last().expect("Levels are not empty");
+			let parents = level
+				.chunks_exact(2)
+				.map(|pair| sha256(&pair[0], &pair[1]))
+				.collect();
+			levels.push(parents);
+		}
+
+		Ok(DataRootBatch {
+			start_block,
+			end_block,
+			levels,
+		})
+	}
+
+	pub fn commitment(&self) -> H256 {
+		self.levels
+			.last()
+			.and_then(|root| root.first())
+			.copied()
+			.unwrap_or_default()
+	}
+
+	/// Creates Merkle proof of the block data root against the batch commitment.
+	pub fn proof(&self, block_number: u32) -> Result<DataRootProof> {
+		if block_number <= self.start_block || block_number > self.end_block {
+			return Err(eyre!(
+				"Block {block_number} is not in range ({}, {}]",
+				self.start_block,
+				self.end_block
+			));
+		}
+		let index = (block_number - self.start_block - 1) as usize;
+		let siblings = self
+			.levels
+			.iter()
+			.take(self.levels.len() - 1)
+			.enumerate()
+			.map(|(height, level)| level[(index >> height) ^ 1])
+			.collect();
+
+		Ok(DataRootProof {
+			block_number,
+			data_root: self.levels[0][index],
+			index,
+			siblings,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn data_roots(count: u8) -> Vec<H256> {
+		(1..=count).map(H256::repeat_byte).collect()
+	}
+
+	#[test]
+	fn proofs_verify_against_commitment() {
+		let batch = DataRootBatch::new(100, data_roots(5), 8).unwrap();
+		assert_eq!(batch.end_block, 105);
+		let commitment = batch.commitment();
+		for block_number in 101..=105 {
+			let proof = batch.proof(block_number).unwrap();
+			assert_eq!(proof.siblings.len(), 3);
+			assert!(proof.verify(commitment));
+			assert!(!proof.verify(H256::zero()));
+		}
+		assert!(batch.proof(100).is_err());
+		assert!(batch.proof(106).is_err());
+	}
+
+	#[test]
+	fn commitment_of_two_leaves() {
+		let batch = DataRootBatch::new(0, data_roots(2), 2).unwrap();
+		assert_eq!(
+			batch.commitment(),
+			sha256(&H256::repeat_byte(1), &H256::repeat_byte(2))
+		);
+	}
+
+	#[test]
+	fn invalid_batch() {
+		assert!(DataRootBatch::new(0, data_roots(3), 6).is_err());
+		assert!(DataRootBatch::new(0, data_roots(3), 2).is_err());
+		assert!(DataRootBatch::new(0, vec![], 2).is_err());
+		assert!(DataRootBatch::new(u32::MAX, data_roots(1), 2).is_err());
+	}
+
+	#[test]
+	fn batch_from_genesis_fails() {
+		use crate::simulation::header;
+
+		let headers = vec![header(0, H256::zero(), 0), header(1, H256::zero(), 0)];
+		assert!(DataRootBatch::from_headers(&headers, 2).is_err());
+		assert!(DataRootBatch::from_headers(&headers[1..], 2).is_ok());
+	}
+}
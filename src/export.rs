@@ -0,0 +1,579 @@
+//! Export of the verified blocks, extrinsics, events and data availability stats into the pluggable sinks.
+//!
+//! # Flow
+//!
+//! * On each verified block, export all blocks since the last exported one (on startup, since the checkpoint)
+//! * Block header is taken from the database (stored by the light client once verified), or fetched over RPC
+//! * Block body is fetched over RPC, or as a compact block from the peers (see [`ExportClient::with_bodies`]), and
+//!   verified against the header extrinsics root, and `System::Events` against the state root
+//! * Events are decoded with the metadata of the block runtime version, which is fetched once per spec version
+//! * Records are written to the [`Sink`], which is flushed before the checkpoint is stored
+//!
+//! # Notes
+//!
+//! Export is at least once: block which is written, but not checkpointed before the crash, is exported again after
+//! restart. Sinks are expected to be idempotent (e.g. [`sink::PostgresSink`] ignores conflicting rows).
+
+use async_trait::async_trait;
+use avail_subxt::{primitives::Header as DaHeader, utils::H256, AvailConfig};
+use codec::Encode;
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use mockall::automock;
+use serde::Serialize;
+use sp_core::{blake2_256, twox_128};
+use std::{
+	collections::HashMap,
+	sync::{Arc, Mutex},
+};
+use subxt::{
+	events::{Events, Phase},
+	Metadata,
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+	block_builder::extrinsics_root,
+	data::{Database, Key},
+	network::rpc::Client as RpcClient,
+	state_client::StateClient,
+	types::{BlockVerified, ExportConfig},
+	utils::extract_kate,
+	verified_rpc::Client as BodyClient,
+};
+
+pub mod sink;
+
+pub use sink::Sink;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BlockRecord {
+	pub number: u32,
+	pub hash: H256,
+	pub parent_hash: H256,
+	pub state_root: H256,
+	pub extrinsics_root: H256,
+	pub extrinsics_count: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ExtrinsicRecord {
+	pub block_number: u32,
+	pub index: u32,
+	pub hash: H256,
+	pub size: u32,
+	pub signed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EventRecord {
+	pub block_number: u32,
+	pub index: u32,
+	/// Index of the extrinsic which emitted the event, if any
+	pub extrinsic_index: Option<u32>,
+	pub pallet: String,
+	pub variant: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DaStatsRecord {
+	pub block_number: u32,
+	pub rows: u16,
+	pub cols: u16,
+	/// Number of cells verified by the light client, if block is sampled
+	pub verified_cells: Option<u32>,
+	/// Confidence achieved by the light client, known only for the blocks verified while exporting
+	pub confidence: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ExportRecord {
+	Block(BlockRecord),
+	Extrinsic(ExtrinsicRecord),
+	Event(EventRecord),
+	DaStats(DaStatsRecord),
+}
+
+/// Column value of the record, for sinks with tabular format
+#[derive(Clone, Debug, PartialEq)]
+pub enum Field {
+	Number(u64),
+	Float(f64),
+	Text(String),
+	Bool(bool),
+	Null,
+}
+
+fn hash_field(hash: &H256) -> Field {
+	Field::Text(format!("{hash:?}"))
+}
+
+fn optional(value: Option<u32>) -> Field {
+	value.map_or(Field::Null, |value| Field::Number(value.into()))
+}
+
+impl ExportRecord {
+	pub fn table(&self) -> &'static str {
+		match self {
+			ExportRecord::Block(_) => "blocks",
+			ExportRecord::Extrinsic(_) => "extrinsics",
+			ExportRecord::Event(_) => "events",
+			ExportRecord::DaStats(_) => "da_stats",
+		}
+	}
+
+	pub fn columns(&self) -> &'static [&'static str] {
+		match self {
+			ExportRecord::Block(_) => &[
+				"number",
+				"hash",
+				"parent_hash",
+				"state_root",
+				"extrinsics_root",
+				"extrinsics_count",
+			],
+			ExportRecord::Extrinsic(_) => &["block_number", "index", "hash", "size", "signed"],
+			ExportRecord::Event(_) => &[
+				"block_number",
+				"index",
+				"extrinsic_index",
+				"pallet",
+				"variant",
+			],
+			ExportRecord::DaStats(_) => &[
+				"block_number",
+				"rows",
+				"cols",
+				"verified_cells",
+				"confidence",
+			],
+		}
+	}
+
+	/// Values in the order of [`Self::columns`]
+	pub fn fields(&self) -> Vec<Field> {
+		match self {
+			ExportRecord::Block(block) => vec![
+				Field::Number(block.number.into()),
+				hash_field(&block.hash),
+				hash_field(&block.parent_hash),
+				hash_field(&block.state_root),
+				hash_field(&block.extrinsics_root),
+				Field::Number(block.extrinsics_count.into()),
+			],
+			ExportRecord::Extrinsic(extrinsic) => vec![
+				Field::Number(extrinsic.block_number.into()),
+				Field::Number(extrinsic.index.into()),
+				hash_field(&extrinsic.hash),
+				Field::Number(extrinsic.size.into()),
+				Field::Bool(extrinsic.signed),
+			],
+			ExportRecord::Event(event) => vec![
+				Field::Number(event.block_number.into()),
+				Field::Number(event.index.into()),
+				optional(event.extrinsic_index),
+				Field::Text(event.pallet.clone()),
+				Field::Text(event.variant.clone()),
+			],
+			ExportRecord::DaStats(stats) => vec![
+				Field::Number(stats.block_number.into()),
+				Field::Number(stats.rows.into()),
+				Field::Number(stats.cols.into()),
+				optional(stats.verified_cells),
+				stats.confidence.map_or(Field::Null, Field::Float),
+			],
+		}
+	}
+}
+
+/// Block with verified body and events
+#[derive(Clone, Debug)]
+pub struct VerifiedBlock {
+	pub header: DaHeader,
+	pub hash: H256,
+	pub extrinsics: Vec<Vec<u8>>,
+	pub events: Vec<EventRecord>,
+	pub verified_cells: Option<u32>,
+}
+
+/// Signed flag of the extrinsic version byte
+const SIGNED_FLAG: u8 = 0b1000_0000;
+
+impl VerifiedBlock {
+	pub fn records(self, confidence: Option<f64>) -> Vec<ExportRecord> {
+		let number = self.header.number;
+		let (rows, cols, _, _) = extract_kate(&self.header.extension);
+		let mut records = vec![ExportRecord::Block(BlockRecord {
+			number,
+			hash: self.hash,
+			parent_hash: self.header.parent_hash,
+			state_root: self.header.state_root,
+			extrinsics_root: self.header.extrinsics_root,
+			extrinsics_count: self.extrinsics.len() as u32,
+		})];
+		records.extend(
+			self.extrinsics
+				.iter()
+				.enumerate()
+				.map(|(index, extrinsic)| {
+					ExportRecord::Extrinsic(ExtrinsicRecord {
+						block_number: number,
+						index: index as u32,
+						// Extrinsic hash is the hash of the length prefixed extrinsic
+						hash: Encode::using_encoded(extrinsic, blake2_256).into(),
+						size: extrinsic.len() as u32,
+						signed: extrinsic
+							.first()
+							.is_some_and(|version| version & SIGNED_FLAG != 0),
+					})
+				}),
+		);
+		records.extend(self.events.into_iter().map(ExportRecord::Event));
+		records.push(ExportRecord::DaStats(DaStatsRecord {
+			block_number: number,
+			rows,
+			cols,
+			verified_cells: self.verified_cells,
+			confidence,
+		}));
+		records
+	}
+}
+
+#[async_trait]
+#[automock]
+pub trait Client {
+	async fn get_verified_block(&self, block_number: u32) -> Result<VerifiedBlock>;
+	fn get_checkpoint(&self) -> Result<Option<u32>>;
+	fn store_checkpoint(&self, block_number: u32) -> Result<()>;
+}
+
+#[derive(Clone)]
+pub struct ExportClient<T: Database> {
+	db: T,
+	rpc_client: RpcClient,
+	state_client: StateClient<RpcClient>,
+	/// Metadata by the runtime spec version
+	metadata: Arc<Mutex<HashMap<u32, Metadata>>>,
+	bodies: Arc<dyn BodyClient + Send + Sync>,
+}
+
+impl<T: Database> ExportClient<T> {
+	pub fn new(db: T, rpc_client: RpcClient, state_client: StateClient<RpcClient>) -> Self {
+		ExportClient {
+			db,
+			bodies: Arc::new(rpc_client.clone()),
+			rpc_client,
+			state_client,
+			metadata: Default::default(),
+		}
+	}
+
+	/// Fetches block bodies with the given client, e.g. [`crate::network::p2p::compact_block::CompactBlockClient`]
+	pub fn with_bodies(mut self, bodies: Arc<dyn BodyClient + Send + Sync>) -> Self {
+		self.bodies = bodies;
+		self
+	}
+
+	/// Returns metadata of the runtime at the given block, blocks exported after the runtime upgrade are decoded
+	/// with the upgraded metadata
+	async fn metadata_at(&self, block_hash: H256) -> Result<Metadata> {
+		let spec_version = self
+			.rpc_client
+			.get_runtime_version_at(block_hash)
+			.await?
+			.spec_version;
+		if let Some(metadata) = self.metadata.lock().unwrap().get(&spec_version) {
+			return Ok(metadata.clone());
+		}
+		let metadata = self.rpc_client.get_metadata_at(block_hash).await?;
+		debug!(spec_version, "Fetched metadata of the exported runtime");
+		self.metadata
+			.lock()
+			.unwrap()
+			.insert(spec_version, metadata.clone());
+		Ok(metadata)
+	}
+}
+
+/// Storage key of the `System::Events`
+pub fn events_storage_key() -> Vec<u8> {
+	[twox_128(b"System"), twox_128(b"Events")].concat()
+}
+
+#[async_trait]
+impl<T: Database + Sync + Send> Client for ExportClient<T> {
+	async fn get_verified_block(&self, block_number: u32) -> Result<VerifiedBlock> {
+		let stored: Option<DaHeader> = self
+			.db
+			.get(Key::BlockHeader(block_number))
+			.wrap_err("Export failed to get block header")?;
+		let (header, hash) = match stored {
+			Some(header) => {
+				let hash = Encode::using_encoded(&header, blake2_256).into();
+				(header, hash)
+			},
+			None => {
+				self.rpc_client
+					.get_header_by_block_number(block_number)
+					.await?
+			},
+		};
+
+		let extrinsics = self.bodies.get_block_body(hash).await?;
+		if extrinsics_root(&extrinsics) != header.extrinsics_root {
+			return Err(eyre!(
+				"Block {block_number} body doesn't match extrinsics root"
+			));
+		}
+
+		let encoded_events = self
+			.state_client
+			.storage(events_storage_key(), hash)
+			.await
+			.wrap_err("Export failed to get events")?
+			.unwrap_or_default();
+		let metadata = self
+			.metadata_at(hash)
+			.await
+			.wrap_err("Export failed to get metadata")?;
+		let events = Events::<AvailConfig>::new(metadata, hash, encoded_events)
+			.iter()
+			.map(|event| {
+				let event = event.wrap_err("Export failed to decode event")?;
+				let extrinsic_index = match event.phase() {
+					Phase::ApplyExtrinsic(index) => Some(index),
+					Phase::Initialization | Phase::Finalization => None,
+				};
+				Ok(EventRecord {
+					block_number,
+					index: event.index(),
+					extrinsic_index,
+					pallet: event.pallet_name().to_string(),
+					variant: event.variant_name().to_string(),
+				})
+			})
+			.collect::<Result<Vec<_>>>()?;
+
+		let verified_cells = self
+			.db
+			.get(Key::VerifiedCellCount(block_number))
+			.wrap_err("Export failed to get verified cell count")?;
+
+		Ok(VerifiedBlock {
+			header,
+			hash,
+			extrinsics,
+			events,
+			verified_cells,
+		})
+	}
+
+	fn get_checkpoint(&self) -> Result<Option<u32>> {
+		self.db
+			.get(Key::ExportCheckpoint)
+			.wrap_err("Export failed to get checkpoint")
+	}
+
+	fn store_checkpoint(&self, block_number: u32) -> Result<()> {
+		self.db
+			.put(Key::ExportCheckpoint, block_number)
+			.wrap_err("Export failed to store checkpoint")
+	}
+}
+
+/// Exports blocks in the inclusive range, storing checkpoint after each block
+async fn export_blocks(
+	client: &impl Client,
+	sink: &mut impl Sink,
+	from: u32,
+	to: u32,
+	confidence: Option<f64>,
+) -> Result<()> {
+	for block_number in from..=to {
+		let block = client.get_verified_block(block_number).await?;
+		// Confidence is known only for the block which triggered the export
+		let confidence = confidence.filter(|_| block_number == to);
+		for record in block.records(confidence) {
+			sink.write(&record).await?;
+		}
+		sink.flush().await?;
+		client.store_checkpoint(block_number)?;
+		debug!(block_number, "Block exported");
+	}
+	Ok(())
+}
+
+/// Runs export of the verified blocks.
+///
+/// # Arguments
+///
+/// * `client` - Export client, used for fetching verified blocks and storing checkpoints
+/// * `sink` - Destination of the exported records
+/// * `cfg` - Export configuration
+/// * `block_receiver` - Verified blocks, which trigger the export
+pub async fn run(
+	client: impl Client,
+	mut sink: impl Sink,
+	cfg: ExportConfig,
+	mut block_receiver: broadcast::Receiver<BlockVerified>,
+) {
+	let mut next = match client.get_checkpoint() {
+		Ok(checkpoint) => checkpoint.map(|number| number + 1).or(cfg.start_block),
+		Err(error) => {
+			error!("Cannot start export: {error:#}");
+			return;
+		},
+	};
+	info!(start_block = next, "Starting export...");
+
+	loop {
+		let block = match block_receiver.recv().await {
+			Ok(block) => block,
+			Err(RecvError::Lagged(skipped)) => {
+				// Skipped blocks are exported on the next verified block
+				warn!(skipped, "Verified blocks receiver lagged");
+				continue;
+			},
+			Err(RecvError::Closed) => {
+				error!("Verified blocks channel closed");
+				return;
+			},
+		};
+
+		let from = next.unwrap_or(block.block_num);
+		if block.block_num < from {
+			continue;
+		}
+		match export_blocks(&client, &mut sink, from, block.block_num, block.confidence).await {
+			Ok(()) => next = Some(block.block_num + 1),
+			Err(error) => {
+				// Export is retried from the checkpoint on the next verified block
+				error!(
+					block_number = block.block_num,
+					"Cannot export block: {error:#}"
+				);
+				next = client
+					.get_checkpoint()
+					.ok()
+					.flatten()
+					.map(|number| number + 1)
+					.or(Some(from));
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::export::sink::MockSink;
+	use avail_subxt::{
+		api::runtime_types::avail_core::{
+			data_lookup::compact::CompactDataLookup,
+			header::extension::{v3, HeaderExtension},
+			kate_commitment::v3::KateCommitment,
+		},
+		config::substrate::Digest,
+	};
+	use mockall::predicate::eq;
+
+	fn block(number: u32) -> VerifiedBlock {
+		let header = DaHeader {
+			parent_hash: H256::zero(),
+			number,
+			state_root: H256::zero(),
+			extrinsics_root: H256::zero(),
+			digest: Digest { logs: vec![] },
+			extension: HeaderExtension::V3(v3::HeaderExtension {
+				commitment: KateCommitment {
+					rows: 2,
+					cols: 4,
+					data_root: H256::zero(),
+					commitment: vec![],
+				},
+				app_lookup: CompactDataLookup {
+					size: 0,
+					index: vec![],
+				},
+			}),
+		};
+		VerifiedBlock {
+			header,
+			hash: H256::repeat_byte(number as u8),
+			extrinsics: vec![vec![4, 0, 0], vec![0x84, 0, 0]],
+			events: vec![EventRecord {
+				block_number: number,
+				index: 0,
+				extrinsic_index: Some(1),
+				pallet: "System".to_string(),
+				variant: "ExtrinsicSuccess".to_string(),
+			}],
+			verified_cells: Some(8),
+		}
+	}
+
+	#[test]
+	fn block_records() {
+		let records = block(5).records(Some(99.9));
+		assert_eq!(records.len(), 5);
+		let signed = records
+			.iter()
+			.filter_map(|record| match record {
+				ExportRecord::Extrinsic(extrinsic) => Some(extrinsic.signed),
+				_ => None,
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(signed, vec![false, true]);
+		let ExportRecord::DaStats(stats) = records.last().unwrap() else {
+			panic!("DA stats are not the last record");
+		};
+		assert_eq!((stats.rows, stats.cols), (2, 4));
+		assert_eq!(stats.confidence, Some(99.9));
+		for record in &records {
+			assert_eq!(record.columns().len(), record.fields().len());
+		}
+	}
+
+	#[tokio::test]
+	async fn resume_from_checkpoint() {
+		let mut client = MockClient::new();
+		client
+			.expect_get_verified_block()
+			.returning(|number| Box::pin(async move { Ok(block(number)) }));
+		for number in 3..=5 {
+			client
+				.expect_store_checkpoint()
+				.with(eq(number))
+				.times(1)
+				.returning(|_| Ok(()));
+		}
+		let mut sink = MockSink::new();
+		sink.expect_write()
+			.times(15)
+			.returning(|_| Box::pin(async { Ok(()) }));
+		sink.expect_flush()
+			.times(3)
+			.returning(|| Box::pin(async { Ok(()) }));
+
+		export_blocks(&client, &mut sink, 3, 5, None).await.unwrap();
+	}
+
+	#[tokio::test]
+	async fn failed_block_is_not_checkpointed() {
+		let mut client = MockClient::new();
+		client
+			.expect_get_verified_block()
+			.with(eq(3))
+			.returning(|_| Box::pin(async { Err(eyre!("Body doesn't match")) }));
+		client.expect_store_checkpoint().never();
+		let mut sink = MockSink::new();
+		sink.expect_write().never();
+
+		assert!(export_blocks(&client, &mut sink, 3, 5, None).await.is_err());
+	}
+}
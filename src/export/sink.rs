@@ -0,0 +1,268 @@
+//! Destinations of the exported records.
+//!
+//! * [`JsonLinesSink`] - all records in a single file, one JSON object per line, tagged with the record type
+//! * [`CsvSink`] - one CSV file per record type (table), with the header line
+//! * [`PostgresSink`] - insert statements, executed through the [`SqlExecutor`], so the database driver is chosen
+//!   by the embedder
+
+use async_trait::async_trait;
+use color_eyre::{eyre::WrapErr, Result};
+use mockall::automock;
+use std::{collections::HashMap, path::PathBuf};
+use tokio::{
+	fs::{File, OpenOptions},
+	io::{AsyncWrite, AsyncWriteExt, BufWriter},
+};
+
+use super::{ExportRecord, Field};
+
+#[async_trait]
+#[automock]
+pub trait Sink: Send {
+	async fn write(&mut self, record: &ExportRecord) -> Result<()>;
+	/// Persists written records, called after all records of the block are written
+	async fn flush(&mut self) -> Result<()>;
+}
+
+pub struct JsonLinesSink<W: AsyncWrite + Unpin + Send> {
+	writer: BufWriter<W>,
+}
+
+impl JsonLinesSink<File> {
+	/// Opens file for appending, so records are not lost on restart
+	pub async fn open(path: &str) -> Result<Self> {
+		let file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(path)
+			.await
+			.wrap_err_with(|| format!("Cannot open export file {path}"))?;
+		Ok(Self::new(file))
+	}
+}
+
+impl<W: AsyncWrite + Unpin + Send> JsonLinesSink<W> {
+	pub fn new(writer: W) -> Self {
+		JsonLinesSink {
+			writer: BufWriter::new(writer),
+		}
+	}
+}
+
+#[async_trait]
+impl<W: AsyncWrite + Unpin + Send> Sink for JsonLinesSink<W> {
+	async fn write(&mut self, record: &ExportRecord) -> Result<()> {
+		let mut line = serde_json::to_vec(record).wrap_err("Cannot encode record")?;
+		line.push(b'\n');
+		self.writer
+			.write_all(&line)
+			.await
+			.wrap_err("Cannot write record")
+	}
+
+	async fn flush(&mut self) -> Result<()> {
+		self.writer.flush().await.wrap_err("Cannot flush records")
+	}
+}
+
+fn csv_value(field: &Field) -> String {
+	match field {
+		Field::Number(number) => number.to_string(),
+		Field::Float(float) => float.to_string(),
+		Field::Bool(value) => value.to_string(),
+		Field::Null => String::new(),
+		Field::Text(text) if text.contains([',', '"', '\n', '\r']) => {
+			format!("\"{}\"", text.replace('"', "\"\""))
+		},
+		Field::Text(text) => text.clone(),
+	}
+}
+
+fn csv_line(values: impl IntoIterator<Item = String>) -> Vec<u8> {
+	let mut line = values
+		.into_iter()
+		.collect::<Vec<_>>()
+		.join(",")
+		.into_bytes();
+	line.push(b'\n');
+	line
+}
+
+/// Writes records into `{directory}/{table}.csv` files
+pub struct CsvSink {
+	directory: PathBuf,
+	files: HashMap<&'static str, BufWriter<File>>,
+}
+
+impl CsvSink {
+	/// Creates export directory if it doesn't exist, existing files are appended to
+	pub async fn open(directory: impl Into<PathBuf>) -> Result<Self> {
+		let directory = directory.into();
+		tokio::fs::create_dir_all(&directory)
+			.await
+			.wrap_err_with(|| format!("Cannot create export directory {}", directory.display()))?;
+		Ok(CsvSink {
+			directory,
+			files: HashMap::new(),
+		})
+	}
+
+	async fn file(&mut self, record: &ExportRecord) -> Result<&mut BufWriter<File>> {
+		let table = record.table();
+		if !self.files.contains_key(table) {
+			let path = self.directory.join(format!("{table}.csv"));
+			let file = OpenOptions::new()
+				.create(true)
+				.append(true)
+				.open(&path)
+				.await
+				.wrap_err_with(|| format!("Cannot open export file {}", path.display()))?;
+			let is_empty = file.metadata().await.map_or(true, |meta| meta.len() == 0);
+			let mut writer = BufWriter::new(file);
+			if is_empty {
+				let header = record.columns().iter().map(|column| column.to_string());
+				writer
+					.write_all(&csv_line(header))
+					.await
+					.wrap_err("Cannot write header")?;
+			}
+			self.files.insert(table, writer);
+		}
+		Ok(self.files.get_mut(table).expect("file is opened"))
+	}
+}
+
+#[async_trait]
+impl Sink for CsvSink {
+	async fn write(&mut self, record: &ExportRecord) -> Result<()> {
+		let line = csv_line(record.fields().iter().map(csv_value));
+		self.file(record)
+			.await?
+			.write_all(&line)
+			.await
+			.wrap_err("Cannot write record")
+	}
+
+	async fn flush(&mut self) -> Result<()> {
+		for file in self.files.values_mut() {
+			file.flush().await.wrap_err("Cannot flush records")?;
+		}
+		Ok(())
+	}
+}
+
+/// Executes SQL statements with positional (`$1`, `$2`, ...) parameters, e.g. with `tokio-postgres` client
+#[async_trait]
+pub trait SqlExecutor: Send {
+	async fn execute(&mut self, statement: &str, params: &[Field]) -> Result<()>;
+	async fn begin(&mut self) -> Result<()>;
+	async fn commit(&mut self) -> Result<()>;
+}
+
+/// Inserts records into the tables named by the record type, with the block records of the same block committed
+/// in a single transaction. Tables are expected to have unique keys (e.g. block number and index), since records
+/// of the same block can be exported twice.
+pub struct PostgresSink<E: SqlExecutor> {
+	executor: E,
+	in_transaction: bool,
+}
+
+impl<E: SqlExecutor> PostgresSink<E> {
+	pub fn new(executor: E) -> Self {
+		PostgresSink {
+			executor,
+			in_transaction: false,
+		}
+	}
+}
+
+pub fn insert_statement(record: &ExportRecord) -> String {
+	let columns = record.columns();
+	let params = (1..=columns.len())
+		.map(|index| format!("${index}"))
+		.collect::<Vec<_>>();
+	format!(
+		"INSERT INTO {} ({}) VALUES ({}) ON CONFLICT DO NOTHING",
+		record.table(),
+		columns.join(", "),
+		params.join(", ")
+	)
+}
+
+#[async_trait]
+impl<E: SqlExecutor> Sink for PostgresSink<E> {
+	async fn write(&mut self, record: &ExportRecord) -> Result<()> {
+		if !self.in_transaction {
+			self.executor.begin().await?;
+			self.in_transaction = true;
+		}
+		self.executor
+			.execute(&insert_statement(record), &record.fields())
+			.await
+	}
+
+	async fn flush(&mut self) -> Result<()> {
+		if !self.in_transaction {
+			return Ok(());
+		}
+		self.executor.commit().await?;
+		self.in_transaction = false;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::export::{EventRecord, ExtrinsicRecord};
+	use avail_subxt::utils::H256;
+
+	fn event(pallet: &str) -> ExportRecord {
+		ExportRecord::Event(EventRecord {
+			block_number: 1,
+			index: 0,
+			extrinsic_index: None,
+			pallet: pallet.to_string(),
+			variant: "Deposit".to_string(),
+		})
+	}
+
+	#[tokio::test]
+	async fn json_lines() {
+		let mut output = vec![];
+		let mut sink = JsonLinesSink::new(&mut output);
+		sink.write(&event("Balances")).await.unwrap();
+		sink.write(&event("System")).await.unwrap();
+		sink.flush().await.unwrap();
+		drop(sink);
+
+		let lines = String::from_utf8(output).unwrap();
+		let first = lines.lines().next().unwrap();
+		let value: serde_json::Value = serde_json::from_str(first).unwrap();
+		assert_eq!(value["type"], "event");
+		assert_eq!(value["pallet"], "Balances");
+		assert_eq!(lines.lines().count(), 2);
+	}
+
+	#[test]
+	fn csv_escaping() {
+		let values = event("Bal\"an,ces").fields();
+		let line = csv_line(values.iter().map(csv_value));
+		assert_eq!(line, b"1,0,,\"Bal\"\"an,ces\",Deposit\n");
+	}
+
+	#[test]
+	fn postgres_insert() {
+		let record = ExportRecord::Extrinsic(ExtrinsicRecord {
+			block_number: 1,
+			index: 0,
+			hash: H256::zero(),
+			size: 10,
+			signed: true,
+		});
+		assert_eq!(
+			insert_statement(&record),
+			"INSERT INTO extrinsics (block_number, index, hash, size, signed) VALUES ($1, $2, $3, $4, $5) ON CONFLICT DO NOTHING"
+		);
+	}
+}
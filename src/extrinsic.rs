@@ -0,0 +1,403 @@
+//! Construction and signing of Avail extrinsics, bound to the runtime version.
+//!
+//! Signed extrinsic commits to the runtime spec and transaction versions, so payloads created before a runtime
+//! upgrade are rejected after it. [`UnsignedPayload`] keeps the [`RuntimeVersionSnapshot`] it was created with, and
+//! [`ExtrinsicBuilder::refresh`] rebuilds stale payloads with the current runtime version before signing.
+//!
+//! # Signed extensions
+//!
+//! * Extra - era, compact nonce, compact tip, compact app ID and metadata hash mode
+//! * Additional signed - spec version, transaction version, genesis hash, era block hash and metadata hash
+//!
+//! Metadata hash (`CheckMetadataHash` extension, RFC-0078) is encoded only if the runtime supports it, see
+//! [`MetadataHash`].
+//!
+//! Wrapper calls (batch, proxy and multisig) are built with [`calls::WrapperCalls`], and payloads for the air-gapped
+//! signers are exported with [`offline::SigningRequest`] (or as [`uos`] QR codes). Opaque extrinsics are decoded
+//! with [`decode::Extrinsic::decode_parts`], and submission failures with [`errors::DispatchError`].
+
+use avail_subxt::utils::H256;
+use codec::{Compact, Encode};
+use serde::{Deserialize, Serialize};
+use sp_core::{blake2_256, sr25519, Pair};
+use subxt::Metadata;
+
+use crate::runtime_upgrade::RuntimeUpdated;
+
+pub mod calls;
+pub mod decode;
+pub mod errors;
+pub mod offline;
+pub mod uos;
+
+/// Version of the signed extrinsic format
+const SIGNED_EXTRINSIC_V4: u8 = 0b1000_0100;
+/// Version of the unsigned extrinsic format
+const UNSIGNED_EXTRINSIC_V4: u8 = 0b0000_0100;
+/// `MultiAddress::Id` variant index
+const MULTI_ADDRESS_ID: u8 = 0;
+/// `MultiSignature::Sr25519` variant index
+const MULTI_SIGNATURE_SR25519: u8 = 1;
+/// Identifier of the RFC-0078 signed extension
+const CHECK_METADATA_HASH: &str = "CheckMetadataHash";
+
+/// Runtime versions signed payload commits to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuntimeVersionSnapshot {
+	pub spec_version: u32,
+	pub transaction_version: u32,
+}
+
+impl From<&RuntimeUpdated> for RuntimeVersionSnapshot {
+	fn from(updated: &RuntimeUpdated) -> Self {
+		RuntimeVersionSnapshot {
+			spec_version: updated.spec_version,
+			transaction_version: updated.transaction_version,
+		}
+	}
+}
+
+impl From<&crate::types::RuntimeVersion> for RuntimeVersionSnapshot {
+	fn from(version: &crate::types::RuntimeVersion) -> Self {
+		RuntimeVersionSnapshot {
+			spec_version: version.spec_version,
+			transaction_version: version.transaction_version,
+		}
+	}
+}
+
+/// Transaction mortality
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Era {
+	Immortal,
+	/// Transaction is valid for `period` blocks, starting from the block with given number and hash
+	Mortal {
+		period: u64,
+		block_number: u64,
+		block_hash: H256,
+	},
+}
+
+impl Era {
+	/// Encodes era in the same way as `sp_runtime::generic::Era`
+	fn encode_to(&self, dest: &mut Vec<u8>) {
+		let Era::Mortal {
+			period,
+			block_number,
+			..
+		} = *self
+		else {
+			return dest.push(0);
+		};
+		let period = period.checked_next_power_of_two().unwrap_or(1 << 16);
+		let period = period.clamp(4, 1 << 16);
+		let phase = block_number % period;
+		let quantize_factor = (period >> 12).max(1);
+		let quantized_phase = phase / quantize_factor * quantize_factor;
+		let encoded = (period.trailing_zeros() - 1).clamp(1, 15) as u16
+			| ((quantized_phase / quantize_factor) << 4) as u16;
+		encoded.encode_to(dest);
+	}
+
+	fn block_hash(&self, genesis_hash: H256) -> H256 {
+		match self {
+			Era::Immortal => genesis_hash,
+			Era::Mortal { block_hash, .. } => *block_hash,
+		}
+	}
+}
+
+/// Metadata hash signed extension (RFC-0078), which proves to the runtime that the signer has seen the same metadata
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataHash {
+	/// Runtime has no `CheckMetadataHash` extension, nothing is encoded
+	#[default]
+	Unsupported,
+	/// Extension is present, but the signer doesn't commit to the metadata hash
+	Disabled,
+	/// Signer commits to the metadata hash, extrinsic is rejected if it doesn't match the runtime metadata
+	Enabled(H256),
+}
+
+impl MetadataHash {
+	/// Selects the mode supported by the runtime, with the metadata hash computed by the signer (if any)
+	pub fn for_runtime(metadata: &Metadata, hash: Option<H256>) -> Self {
+		if !supports_metadata_hash(metadata) {
+			return MetadataHash::Unsupported;
+		}
+		hash.map_or(MetadataHash::Disabled, MetadataHash::Enabled)
+	}
+
+	fn encode_extra(&self, dest: &mut Vec<u8>) {
+		match self {
+			MetadataHash::Unsupported => (),
+			MetadataHash::Disabled => dest.push(0),
+			MetadataHash::Enabled(_) => dest.push(1),
+		}
+	}
+
+	fn encode_additional(&self, dest: &mut Vec<u8>) {
+		match self {
+			MetadataHash::Unsupported => (),
+			MetadataHash::Disabled => None::<H256>.encode_to(dest),
+			MetadataHash::Enabled(hash) => Some(hash).encode_to(dest),
+		}
+	}
+}
+
+/// Checks if the runtime has the `CheckMetadataHash` signed extension
+pub fn supports_metadata_hash(metadata: &Metadata) -> bool {
+	metadata
+		.extrinsic()
+		.signed_extensions()
+		.iter()
+		.any(|extension| extension.identifier() == CHECK_METADATA_HASH)
+}
+
+/// Signed extension parameters provided by the sender
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtrinsicParams {
+	pub nonce: u32,
+	pub tip: u128,
+	pub app_id: u32,
+	pub era: Era,
+	pub metadata_hash: MetadataHash,
+}
+
+impl Default for ExtrinsicParams {
+	fn default() -> Self {
+		ExtrinsicParams {
+			nonce: 0,
+			tip: 0,
+			app_id: 0,
+			era: Era::Immortal,
+			metadata_hash: MetadataHash::Unsupported,
+		}
+	}
+}
+
+/// Call with signed extensions, bound to the runtime version it was created with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnsignedPayload {
+	/// Encoded call, including pallet and call indices
+	pub call: Vec<u8>,
+	pub params: ExtrinsicParams,
+	pub runtime: RuntimeVersionSnapshot,
+	pub genesis_hash: H256,
+}
+
+impl UnsignedPayload {
+	fn encode_extra(&self, dest: &mut Vec<u8>) {
+		self.params.era.encode_to(dest);
+		Compact(self.params.nonce).encode_to(dest);
+		Compact(self.params.tip).encode_to(dest);
+		Compact(self.params.app_id).encode_to(dest);
+		self.params.metadata_hash.encode_extra(dest);
+	}
+
+	fn encode_additional(&self, dest: &mut Vec<u8>) {
+		self.runtime.spec_version.encode_to(dest);
+		self.runtime.transaction_version.encode_to(dest);
+		self.genesis_hash.encode_to(dest);
+		self.params
+			.era
+			.block_hash(self.genesis_hash)
+			.encode_to(dest);
+		self.params.metadata_hash.encode_additional(dest);
+	}
+
+	/// Encoded signed extensions, extra followed by additional signed data
+	pub fn extensions(&self) -> Vec<u8> {
+		let mut extensions = vec![];
+		self.encode_extra(&mut extensions);
+		self.encode_additional(&mut extensions);
+		extensions
+	}
+
+	/// Payload which is signed by the sender, hashed if longer than 256 bytes
+	pub fn signer_payload(&self) -> Vec<u8> {
+		let mut payload = self.call.clone();
+		payload.extend(self.extensions());
+		if payload.len() > 256 {
+			return blake2_256(&payload).to_vec();
+		}
+		payload
+	}
+
+	/// Payload is stale if it was created for a different runtime version
+	pub fn is_stale(&self, runtime: &RuntimeVersionSnapshot) -> bool {
+		self.runtime != *runtime
+	}
+
+	/// Encodes signed extrinsic with given signer and signature of the [`Self::signer_payload`]
+	pub fn encode_signed(
+		&self,
+		signer: &sr25519::Public,
+		signature: &sr25519::Signature,
+	) -> Vec<u8> {
+		let mut extrinsic = vec![SIGNED_EXTRINSIC_V4, MULTI_ADDRESS_ID];
+		extrinsic.extend_from_slice(signer.as_ref());
+		extrinsic.push(MULTI_SIGNATURE_SR25519);
+		extrinsic.extend_from_slice(signature.as_ref());
+		self.encode_extra(&mut extrinsic);
+		extrinsic.extend_from_slice(&self.call);
+		// Extrinsic is encoded as a compact prefixed vector
+		extrinsic.encode()
+	}
+
+	pub fn sign(&self, pair: &sr25519::Pair) -> Vec<u8> {
+		let signature = pair.sign(&self.signer_payload());
+		self.encode_signed(&pair.public(), &signature)
+	}
+}
+
+/// Encodes unsigned extrinsic (e.g. equivocation report), which is validated by the runtime instead of the signature
+pub fn encode_unsigned(call: &[u8]) -> Vec<u8> {
+	let mut extrinsic = vec![UNSIGNED_EXTRINSIC_V4];
+	extrinsic.extend_from_slice(call);
+	extrinsic.encode()
+}
+
+/// Creates payloads for the current runtime version
+#[derive(Clone, Debug)]
+pub struct ExtrinsicBuilder {
+	genesis_hash: H256,
+	runtime: RuntimeVersionSnapshot,
+}
+
+impl ExtrinsicBuilder {
+	pub fn new(genesis_hash: H256, runtime: RuntimeVersionSnapshot) -> Self {
+		ExtrinsicBuilder {
+			genesis_hash,
+			runtime,
+		}
+	}
+
+	pub fn runtime(&self) -> RuntimeVersionSnapshot {
+		self.runtime
+	}
+
+	/// Updates runtime version (e.g. on [`RuntimeUpdated`] event)
+	pub fn set_runtime(&mut self, runtime: RuntimeVersionSnapshot) {
+		self.runtime = runtime;
+	}
+
+	pub fn payload(&self, call: Vec<u8>, params: ExtrinsicParams) -> UnsignedPayload {
+		UnsignedPayload {
+			call,
+			params,
+			runtime: self.runtime,
+			genesis_hash: self.genesis_hash,
+		}
+	}
+
+	/// Rebuilds payload if it is stale, returns `None` if payload is bound to current runtime version
+	pub fn refresh(&self, payload: &UnsignedPayload) -> Option<UnsignedPayload> {
+		payload
+			.is_stale(&self.runtime)
+			.then(|| self.payload(payload.call.clone(), payload.params))
+	}
+
+	/// Signs payload, rebuilding it first if it is stale
+	pub fn sign(&self, payload: &UnsignedPayload, pair: &sr25519::Pair) -> Vec<u8> {
+		match self.refresh(payload) {
+			Some(payload) => payload.sign(pair),
+			None => payload.sign(pair),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use codec::Decode;
+	use test_case::test_case;
+
+	const V1: RuntimeVersionSnapshot = RuntimeVersionSnapshot {
+		spec_version: 10,
+		transaction_version: 1,
+	};
+	const V2: RuntimeVersionSnapshot = RuntimeVersionSnapshot {
+		spec_version: 11,
+		transaction_version: 2,
+	};
+
+	fn builder() -> ExtrinsicBuilder {
+		ExtrinsicBuilder::new(H256::repeat_byte(1), V1)
+	}
+
+	#[test]
+	fn refresh_stale_payload() {
+		let mut builder = builder();
+		let payload = builder.payload(vec![29, 1, 0], ExtrinsicParams::default());
+		assert!(builder.refresh(&payload).is_none());
+
+		builder.set_runtime(V2);
+		assert!(payload.is_stale(&builder.runtime()));
+		let refreshed = builder.refresh(&payload).unwrap();
+		assert_eq!(refreshed.runtime, V2);
+		assert_eq!(refreshed.call, payload.call);
+		assert_ne!(refreshed.signer_payload(), payload.signer_payload());
+	}
+
+	#[test]
+	fn signed_extrinsic_verifies() {
+		let pair = sr25519::Pair::from_string("//Alice", None).unwrap();
+		let payload = builder().payload(
+			vec![29, 1, 4, 42],
+			ExtrinsicParams {
+				nonce: 3,
+				app_id: 1,
+				..Default::default()
+			},
+		);
+		let extrinsic = payload.sign(&pair);
+
+		let extrinsic = Vec::<u8>::decode(&mut &extrinsic[..]).unwrap();
+		assert_eq!(extrinsic[0], SIGNED_EXTRINSIC_V4);
+		assert_eq!(&extrinsic[2..34], pair.public().as_ref());
+		let signature = sr25519::Signature::from_slice(&extrinsic[35..99]).unwrap();
+		assert!(sr25519::Pair::verify(
+			&signature,
+			payload.signer_payload(),
+			&pair.public()
+		));
+		assert!(extrinsic.ends_with(&payload.call));
+	}
+
+	#[test]
+	fn long_payload_is_hashed() {
+		let payload = builder().payload(vec![0; 512], ExtrinsicParams::default());
+		assert_eq!(payload.signer_payload().len(), 32);
+	}
+
+	#[test_case(MetadataHash::Unsupported => 0 ; "unsupported")]
+	#[test_case(MetadataHash::Disabled => 2 ; "disabled")]
+	#[test_case(MetadataHash::Enabled(H256::zero()) => 34 ; "enabled")]
+	fn metadata_hash_length(metadata_hash: MetadataHash) -> usize {
+		let payload = |metadata_hash| {
+			let params = ExtrinsicParams {
+				metadata_hash,
+				..Default::default()
+			};
+			builder().payload(vec![29, 1, 0], params).signer_payload()
+		};
+		payload(metadata_hash).len() - payload(MetadataHash::Unsupported).len()
+	}
+
+	#[test]
+	fn encode_unsigned_extrinsic() {
+		assert_eq!(encode_unsigned(&[10, 1, 42]), vec![16, 4, 10, 1, 42]);
+	}
+
+	#[test_case(Era::Immortal => vec![0] ; "immortal")]
+	#[test_case(Era::Mortal { period: 64, block_number: 42, block_hash: H256::zero() } => vec![165, 2] ; "mortal")]
+	#[test_case(Era::Mortal { period: 100, block_number: 1000, block_hash: H256::zero() } => vec![134, 6] ; "mortal rounded period")]
+	fn encode_era(era: Era) -> Vec<u8> {
+		let mut encoded = vec![];
+		era.encode_to(&mut encoded);
+		encoded
+	}
+}
@@ -0,0 +1,204 @@
+//! Builders of the wrapper calls, commonly used by wallets.
+//!
+//! Inner calls are provided encoded, so any call (including other wrapper calls) can be wrapped:
+//!
+//! * `Utility.batch` and `Utility.batch_all` - dispatch multiple calls, `batch_all` reverts all if one fails
+//! * `Proxy.proxy` - dispatch call on behalf of the proxied account
+//! * `Multisig.as_multi` - approve and dispatch call of a multisig account
+
+use codec::{Compact, Decode, Encode};
+use color_eyre::Result;
+use sp_core::blake2_256;
+use subxt::Metadata;
+
+use crate::runtime_upgrade;
+
+/// Entropy prefix used for multisig account derivation
+const MULTISIG_PREFIX: &[u8; 16] = b"modlpy/utilisuba";
+
+/// Pallet and call index, which prefixes encoded call
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode)]
+pub struct CallIndex(pub u8, pub u8);
+
+impl CallIndex {
+	pub fn from_metadata(metadata: &Metadata, pallet: &str, call: &str) -> Result<Self> {
+		let (pallet_index, call_index) = runtime_upgrade::call_index(metadata, pallet, call)?;
+		Ok(CallIndex(pallet_index, call_index))
+	}
+}
+
+/// Indices of the wrapper calls in the current runtime
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WrapperCalls {
+	pub batch: CallIndex,
+	pub batch_all: CallIndex,
+	pub proxy: CallIndex,
+	pub as_multi: CallIndex,
+}
+
+impl WrapperCalls {
+	pub fn from_metadata(metadata: &Metadata) -> Result<Self> {
+		Ok(WrapperCalls {
+			batch: CallIndex::from_metadata(metadata, "Utility", "batch")?,
+			batch_all: CallIndex::from_metadata(metadata, "Utility", "batch_all")?,
+			proxy: CallIndex::from_metadata(metadata, "Proxy", "proxy")?,
+			as_multi: CallIndex::from_metadata(metadata, "Multisig", "as_multi")?,
+		})
+	}
+
+	fn batch_with(index: CallIndex, calls: &[Vec<u8>]) -> Vec<u8> {
+		let mut encoded = index.encode();
+		Compact(calls.len() as u32).encode_to(&mut encoded);
+		for call in calls {
+			encoded.extend_from_slice(call);
+		}
+		encoded
+	}
+
+	pub fn batch(&self, calls: &[Vec<u8>]) -> Vec<u8> {
+		Self::batch_with(self.batch, calls)
+	}
+
+	pub fn batch_all(&self, calls: &[Vec<u8>]) -> Vec<u8> {
+		Self::batch_with(self.batch_all, calls)
+	}
+
+	/// Dispatches call on behalf of the `real` account, optionally requiring given proxy type index
+	pub fn proxy(&self, real: [u8; 32], force_proxy_type: Option<u8>, call: &[u8]) -> Vec<u8> {
+		let mut encoded = self.proxy.encode();
+		// MultiAddress::Id
+		encoded.push(0);
+		real.encode_to(&mut encoded);
+		force_proxy_type.encode_to(&mut encoded);
+		encoded.extend_from_slice(call);
+		encoded
+	}
+
+	/// Approves and dispatches call once threshold is reached. Timepoint of the first approval is required for
+	/// all subsequent approvals, and other signatories are sorted as required by the runtime.
+	pub fn as_multi(
+		&self,
+		multisig: &Multisig,
+		timepoint: Option<Timepoint>,
+		call: &[u8],
+		max_weight: Weight,
+	) -> Vec<u8> {
+		let mut encoded = self.as_multi.encode();
+		multisig.threshold.encode_to(&mut encoded);
+		multisig.other_signatories.encode_to(&mut encoded);
+		timepoint.encode_to(&mut encoded);
+		encoded.extend_from_slice(call);
+		max_weight.encode_to(&mut encoded);
+		encoded
+	}
+}
+
+/// Block number and extrinsic index of the first multisig approval
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode)]
+pub struct Timepoint {
+	pub height: u32,
+	pub index: u32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct Weight {
+	#[codec(compact)]
+	pub ref_time: u64,
+	#[codec(compact)]
+	pub proof_size: u64,
+}
+
+/// Multisig account, from the perspective of one of the signatories
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Multisig {
+	pub threshold: u16,
+	/// Sorted signatories, excluding the sender
+	pub other_signatories: Vec<[u8; 32]>,
+}
+
+impl Multisig {
+	pub fn new(threshold: u16, sender: [u8; 32], signatories: &[[u8; 32]]) -> Self {
+		let mut other_signatories = signatories
+			.iter()
+			.filter(|&&signatory| signatory != sender)
+			.cloned()
+			.collect::<Vec<_>>();
+		other_signatories.sort();
+		other_signatories.dedup();
+		Multisig {
+			threshold,
+			other_signatories,
+		}
+	}
+
+	/// Multisig account ID, derived from all sorted signatories and threshold
+	pub fn account_id(&self, sender: [u8; 32]) -> [u8; 32] {
+		let mut signatories = self.other_signatories.clone();
+		signatories.push(sender);
+		signatories.sort();
+		(MULTISIG_PREFIX, signatories, self.threshold).using_encoded(blake2_256)
+	}
+}
+
+/// Hash of the call, which identifies multisig operation
+pub fn multisig_call_hash(call: &[u8]) -> [u8; 32] {
+	blake2_256(call)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const CALLS: WrapperCalls = WrapperCalls {
+		batch: CallIndex(1, 0),
+		batch_all: CallIndex(1, 2),
+		proxy: CallIndex(40, 0),
+		as_multi: CallIndex(34, 1),
+	};
+
+	#[test]
+	fn encode_batch() {
+		let calls = vec![vec![29, 1, 0], vec![29, 1, 4, 42]];
+		assert_eq!(CALLS.batch(&calls), vec![1, 0, 8, 29, 1, 0, 29, 1, 4, 42]);
+		assert_eq!(CALLS.batch_all(&calls)[..2], [1, 2]);
+	}
+
+	#[test]
+	fn encode_proxy() {
+		let encoded = CALLS.proxy([7; 32], Some(3), &[29, 1, 0]);
+		assert_eq!(encoded[..3], [40, 0, 0]);
+		assert_eq!(encoded[3..35], [7; 32]);
+		assert_eq!(encoded[35..], [1, 3, 29, 1, 0]);
+	}
+
+	#[test]
+	fn encode_as_multi() {
+		let multisig = Multisig::new(2, [1; 32], &[[3; 32], [1; 32], [2; 32]]);
+		assert_eq!(multisig.other_signatories, vec![[2; 32], [3; 32]]);
+
+		let timepoint = Timepoint {
+			height: 5,
+			index: 1,
+		};
+		let encoded = CALLS.as_multi(&multisig, Some(timepoint), &[29, 1, 0], Weight::default());
+		let mut expected = vec![34, 1, 2, 0, 8];
+		expected.extend([2; 32]);
+		expected.extend([3; 32]);
+		expected.extend([1, 5, 0, 0, 0, 1, 0, 0, 0, 29, 1, 0, 0, 0]);
+		assert_eq!(encoded, expected);
+	}
+
+	#[test]
+	fn multisig_account_is_independent_of_sender() {
+		let signatories = [[1; 32], [2; 32], [3; 32]];
+		let account_ids = signatories
+			.iter()
+			.map(|&sender| Multisig::new(2, sender, &signatories).account_id(sender))
+			.collect::<Vec<_>>();
+		assert!(account_ids.windows(2).all(|ids| ids[0] == ids[1]));
+		assert_ne!(
+			account_ids[0],
+			Multisig::new(3, [1; 32], &signatories).account_id([1; 32])
+		);
+	}
+}
@@ -0,0 +1,333 @@
+//! Introspection of the opaque extrinsics, e.g. for explorers and policy filters.
+//!
+//! [`Extrinsic::decode_parts`] splits the extrinsic into the signer address, signature, signed extensions and call.
+//! Extensions and call arguments are decoded as dynamic values, using types from the runtime metadata, and
+//! [`ExtrinsicParts::encode`] encodes them back, so filters can also modify extrinsics.
+//!
+//! # Notes
+//!
+//! Only extrinsic format version 4 is supported. Address and signature are decoded as `MultiAddress` and
+//! `MultiSignature`, which are used by the Avail runtime.
+
+use codec::{Compact, Decode, Encode};
+use color_eyre::{eyre::eyre, Result};
+use subxt::{ext::scale_value::Value, Metadata};
+
+use crate::dynamic;
+
+/// Extrinsic format version, without the signed bit
+const EXTRINSIC_VERSION: u8 = 4;
+const SIGNED_BIT: u8 = 0b1000_0000;
+
+/// `MultiAddress` of the signer
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum Address {
+	Id([u8; 32]),
+	Index(#[codec(compact)] u32),
+	Raw(Vec<u8>),
+	Address32([u8; 32]),
+	Address20([u8; 20]),
+}
+
+/// `MultiSignature` of the signed payload
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum Signature {
+	Ed25519([u8; 64]),
+	Sr25519([u8; 64]),
+	Ecdsa([u8; 65]),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignedExtension {
+	pub identifier: String,
+	/// Extra data of the extension, additional signed data is not a part of the extrinsic
+	pub value: Value,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Call {
+	pub pallet: String,
+	pub variant: String,
+	/// Arguments by field name, or by index for the unnamed fields
+	pub args: Vec<(String, Value)>,
+}
+
+impl Call {
+	pub fn arg(&self, name: &str) -> Option<&Value> {
+		self.args
+			.iter()
+			.find(|(arg, _)| arg == name)
+			.map(|(_, value)| value)
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExtrinsicParts {
+	/// Signer address and signature, `None` for unsigned extrinsics
+	pub signature: Option<(Address, Signature)>,
+	pub extensions: Vec<SignedExtension>,
+	pub call: Call,
+}
+
+/// SCALE encoded extrinsic, as included in the block body (compact length prefixed)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Extrinsic(pub Vec<u8>);
+
+/// Splits extrinsic into signature and the rest (extensions and call), without the metadata
+fn split(encoded: &[u8]) -> Result<(Option<(Address, Signature)>, &[u8])> {
+	let input = &mut &encoded[..];
+	let length = Compact::<u32>::decode(input)
+		.map_err(|error| eyre!("Cannot decode extrinsic length: {error}"))?
+		.0 as usize;
+	if input.len() != length {
+		return Err(eyre!(
+			"Extrinsic length is {length}, but {} bytes are left",
+			input.len()
+		));
+	}
+	let version = u8::decode(input).map_err(|error| eyre!("Extrinsic is empty: {error}"))?;
+	if version & !SIGNED_BIT != EXTRINSIC_VERSION {
+		return Err(eyre!(
+			"Unsupported extrinsic version {}",
+			version & !SIGNED_BIT
+		));
+	}
+	if version & SIGNED_BIT == 0 {
+		return Ok((None, input));
+	}
+	let address =
+		Address::decode(input).map_err(|error| eyre!("Cannot decode signer address: {error}"))?;
+	let signature =
+		Signature::decode(input).map_err(|error| eyre!("Cannot decode signature: {error}"))?;
+	Ok((Some((address, signature)), input))
+}
+
+fn decode_value(input: &mut &[u8], type_id: u32, metadata: &Metadata, name: &str) -> Result<Value> {
+	dynamic::decode_as_type(input, type_id, metadata.types())
+		.map_err(|error| eyre!("Failed to decode {name}: {error}"))
+}
+
+fn encode_value(
+	value: &Value,
+	type_id: u32,
+	metadata: &Metadata,
+	name: &str,
+	dest: &mut Vec<u8>,
+) -> Result<()> {
+	dynamic::encode_as_type(value, type_id, metadata.types(), dest)
+		.map_err(|error| eyre!("Failed to encode {name}: {error}"))
+}
+
+impl Extrinsic {
+	/// Decodes signature, signed extensions and call, using types from the metadata
+	pub fn decode_parts(&self, metadata: &Metadata) -> Result<ExtrinsicParts> {
+		let (signature, mut input) = split(&self.0)?;
+		let input = &mut input;
+
+		let mut extensions = vec![];
+		if signature.is_some() {
+			for extension in metadata.extrinsic().signed_extensions() {
+				let identifier = extension.identifier().to_string();
+				let value = decode_value(input, extension.extra_ty(), metadata, &identifier)?;
+				extensions.push(SignedExtension { identifier, value });
+			}
+		}
+
+		let (pallet_index, call_index) = <(u8, u8)>::decode(input)
+			.map_err(|error| eyre!("Cannot decode call index: {error}"))?;
+		let pallet = metadata
+			.pallet_by_index(pallet_index)
+			.ok_or_else(|| eyre!("Pallet {pallet_index} is not found in metadata"))?;
+		let variant = pallet
+			.call_variant_by_index(call_index)
+			.ok_or_else(|| eyre!("Call {call_index} of pallet {} is not found", pallet.name()))?;
+		let mut args = vec![];
+		for (index, field) in variant.fields.iter().enumerate() {
+			let name = field.name.clone().unwrap_or_else(|| index.to_string());
+			let value = decode_value(input, field.ty.id, metadata, &name)?;
+			args.push((name, value));
+		}
+		if !input.is_empty() {
+			return Err(eyre!("{} bytes are left after the call", input.len()));
+		}
+
+		Ok(ExtrinsicParts {
+			signature,
+			extensions,
+			call: Call {
+				pallet: pallet.name().to_string(),
+				variant: variant.name.clone(),
+				args,
+			},
+		})
+	}
+}
+
+impl ExtrinsicParts {
+	/// Encodes call, including pallet and call indices
+	pub fn encode_call(&self, metadata: &Metadata) -> Result<Vec<u8>> {
+		let Call {
+			pallet,
+			variant,
+			args,
+		} = &self.call;
+		let pallet_metadata = metadata
+			.pallet_by_name(pallet)
+			.ok_or_else(|| eyre!("Pallet {pallet} is not found in metadata"))?;
+		let variant_metadata = pallet_metadata
+			.call_variant_by_name(variant)
+			.ok_or_else(|| eyre!("Call {pallet}.{variant} is not found in metadata"))?;
+		if variant_metadata.fields.len() != args.len() {
+			return Err(eyre!(
+				"Call {pallet}.{variant} has {} arguments, {} provided",
+				variant_metadata.fields.len(),
+				args.len()
+			));
+		}
+		let mut encoded = vec![pallet_metadata.index(), variant_metadata.index];
+		for (field, (name, value)) in variant_metadata.fields.iter().zip(args) {
+			encode_value(value, field.ty.id, metadata, name, &mut encoded)?;
+		}
+		Ok(encoded)
+	}
+
+	/// Encodes extrinsic, compact length prefixed
+	pub fn encode(&self, metadata: &Metadata) -> Result<Vec<u8>> {
+		let mut extrinsic = vec![];
+		match &self.signature {
+			None => extrinsic.push(EXTRINSIC_VERSION),
+			Some((address, signature)) => {
+				extrinsic.push(EXTRINSIC_VERSION | SIGNED_BIT);
+				address.encode_to(&mut extrinsic);
+				signature.encode_to(&mut extrinsic);
+				let signed_extensions = metadata.extrinsic().signed_extensions();
+				if signed_extensions.len() != self.extensions.len() {
+					return Err(eyre!(
+						"Runtime has {} signed extensions, {} provided",
+						signed_extensions.len(),
+						self.extensions.len()
+					));
+				}
+				for (metadata_extension, extension) in
+					signed_extensions.iter().zip(&self.extensions)
+				{
+					if metadata_extension.identifier() != extension.identifier {
+						return Err(eyre!(
+							"Expected signed extension {}, found {}",
+							metadata_extension.identifier(),
+							extension.identifier
+						));
+					}
+					encode_value(
+						&extension.value,
+						metadata_extension.extra_ty(),
+						metadata,
+						&extension.identifier,
+						&mut extrinsic,
+					)?;
+				}
+			},
+		}
+		extrinsic.extend(self.encode_call(metadata)?);
+		Ok(extrinsic.encode())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		extrinsic::{encode_unsigned, ExtrinsicBuilder, ExtrinsicParams, RuntimeVersionSnapshot},
+		test_utils::metadata,
+	};
+	use avail_subxt::utils::H256;
+	use sp_core::{sr25519, Pair};
+
+	#[test]
+	fn split_signed_extrinsic() {
+		let runtime = RuntimeVersionSnapshot {
+			spec_version: 10,
+			transaction_version: 1,
+		};
+		let builder = ExtrinsicBuilder::new(H256::repeat_byte(1), runtime);
+		let payload = builder.payload(vec![3, 1, 42], ExtrinsicParams::default());
+		let pair = sr25519::Pair::from_seed(&[1; 32]);
+		let extrinsic = payload.sign(&pair);
+
+		let (signature, rest) = split(&extrinsic).unwrap();
+		let (address, Signature::Sr25519(signature)) = signature.unwrap() else {
+			panic!("Expected sr25519 signature");
+		};
+		assert_eq!(address, Address::Id(pair.public().0));
+		let signature = sr25519::Signature::from_raw(signature);
+		assert!(sr25519::Pair::verify(
+			&signature,
+			payload.signer_payload(),
+			&pair.public()
+		));
+		// Immortal era, nonce, tip and app ID, followed by the call
+		assert_eq!(rest, [0, 0, 0, 0, 3, 1, 42]);
+	}
+
+	#[test]
+	fn decode_and_encode_parts() {
+		let metadata = metadata::metadata();
+		let call = [
+			vec![metadata::DATA_AVAILABILITY_INDEX, 1],
+			b"avail".to_vec().encode(),
+		]
+		.concat();
+		// Nonce 5 and app ID 3, compact encoded
+		let extensions = (Compact(5u32), Compact(3u32)).encode();
+		let signed = [
+			vec![EXTRINSIC_VERSION | SIGNED_BIT],
+			Address::Id([1; 32]).encode(),
+			Signature::Sr25519([2; 64]).encode(),
+			extensions,
+			call.clone(),
+		]
+		.concat()
+		.encode();
+
+		let parts = Extrinsic(signed.clone()).decode_parts(&metadata).unwrap();
+		assert_eq!(
+			parts.signature,
+			Some((Address::Id([1; 32]), Signature::Sr25519([2; 64])))
+		);
+		let identifiers = parts
+			.extensions
+			.iter()
+			.map(|extension| extension.identifier.as_str())
+			.collect::<Vec<_>>();
+		assert_eq!(identifiers, ["CheckNonce", "CheckAppId"]);
+		assert_eq!(parts.call.pallet, "DataAvailability");
+		assert_eq!(parts.call.variant, "submit_data");
+		assert!(parts.call.arg("data").is_some());
+		assert_eq!(parts.encode_call(&metadata).unwrap(), call);
+		assert_eq!(parts.encode(&metadata).unwrap(), signed);
+
+		let unsigned = encode_unsigned(&call);
+		let parts = Extrinsic(unsigned.clone()).decode_parts(&metadata).unwrap();
+		assert!(parts.signature.is_none() && parts.extensions.is_empty());
+		assert_eq!(parts.encode(&metadata).unwrap(), unsigned);
+
+		// Modified argument is encoded with its type
+		let mut modified = parts.clone();
+		modified.call.args[0].1 = Value::from_bytes(b"data");
+		let encoded = modified.encode(&metadata).unwrap();
+		let decoded = Extrinsic(encoded).decode_parts(&metadata).unwrap();
+		assert_eq!(decoded.call.args, modified.call.args);
+	}
+
+	#[test]
+	fn split_unsigned_extrinsic() {
+		let (signature, rest) = split(&encode_unsigned(&[3, 1, 42])).unwrap();
+		assert!(signature.is_none());
+		assert_eq!(rest, [3, 1, 42]);
+
+		let mut truncated = encode_unsigned(&[3, 1, 42]);
+		truncated.pop();
+		assert!(split(&truncated).is_err());
+		assert!(split(&vec![5u8, 1, 2].encode()).is_err());
+	}
+}
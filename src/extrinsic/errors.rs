@@ -0,0 +1,197 @@
+//! Typed transaction validity and dispatch errors, so submission failures are reported by name (e.g.
+//! `balances.InsufficientBalance`) instead of the opaque bytes.
+//!
+//! [`TransactionValidityError`] is returned by the transaction pool validation (`TaggedTransactionQueue` runtime
+//! API), and [`DispatchError`] is a field of the `System.ExtrinsicFailed` event. Module errors are resolved to the
+//! pallet and error variant names with [`DispatchError::describe`], using the error types from the runtime metadata.
+//!
+//! Submitted extrinsics are validated before submission, and their events are checked after finalization (see
+//! [`crate::network::rpc::Client`]), so both kinds of failures are returned as described errors.
+
+use codec::{Decode, Encode};
+use std::fmt;
+use subxt::Metadata;
+
+use crate::pretty::snake_case;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum InvalidTransaction {
+	Call,
+	Payment,
+	Future,
+	Stale,
+	BadProof,
+	AncientBirthBlock,
+	ExhaustsResources,
+	Custom(u8),
+	BadMandatory,
+	MandatoryValidation,
+	BadSigner,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum UnknownTransaction {
+	CannotLookup,
+	NoUnsignedValidator,
+	Custom(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum TransactionValidityError {
+	Invalid(InvalidTransaction),
+	Unknown(UnknownTransaction),
+}
+
+impl fmt::Display for TransactionValidityError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			TransactionValidityError::Invalid(error) => write!(f, "invalid.{error:?}"),
+			TransactionValidityError::Unknown(error) => write!(f, "unknown.{error:?}"),
+		}
+	}
+}
+
+/// Decodes the result of the `TaggedTransactionQueue_validate_transaction` runtime API, returning the validity error
+/// of the invalid transaction. Valid transaction details are not decoded.
+pub fn validity_error(encoded: &[u8]) -> Result<Option<TransactionValidityError>, codec::Error> {
+	match encoded.split_first() {
+		Some((0, _)) => Ok(None),
+		Some((1, mut error)) => TransactionValidityError::decode(&mut error).map(Some),
+		_ => Err("Invalid transaction validity result".into()),
+	}
+}
+
+/// Error of the pallet, identified by the pallet index and the error variant index (first byte of `error`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct ModuleError {
+	pub index: u8,
+	pub error: [u8; 4],
+}
+
+impl ModuleError {
+	/// Resolves pallet and error variant names, e.g. `("Balances", "InsufficientBalance")`
+	pub fn resolve(&self, metadata: &Metadata) -> Option<(String, String)> {
+		let pallet = metadata.pallet_by_index(self.index)?;
+		let variant = pallet.error_variant_by_index(self.error[0])?;
+		Some((pallet.name().to_string(), variant.name.clone()))
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum TokenError {
+	FundsUnavailable,
+	OnlyProvider,
+	BelowMinimum,
+	CannotCreate,
+	UnknownAsset,
+	Frozen,
+	Unsupported,
+	CannotCreateHold,
+	NotExpendable,
+	Blocked,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum ArithmeticError {
+	Underflow,
+	Overflow,
+	DivisionByZero,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum TransactionalError {
+	LimitReached,
+	NoLayer,
+}
+
+/// Error of the failed extrinsic dispatch; message of the `Other` error is not encoded by the runtime
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum DispatchError {
+	Other,
+	CannotLookup,
+	BadOrigin,
+	Module(ModuleError),
+	ConsumerRemaining,
+	NoProviders,
+	TooManyConsumers,
+	Token(TokenError),
+	Arithmetic(ArithmeticError),
+	Transactional(TransactionalError),
+	Exhausted,
+	Corruption,
+	Unavailable,
+	RootNotAllowed,
+}
+
+impl fmt::Display for DispatchError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			DispatchError::Module(ModuleError { index, error }) => {
+				write!(f, "module({index}).error({})", error[0])
+			},
+			DispatchError::Token(error) => write!(f, "token.{error:?}"),
+			DispatchError::Arithmetic(error) => write!(f, "arithmetic.{error:?}"),
+			DispatchError::Transactional(error) => write!(f, "transactional.{error:?}"),
+			error => write!(f, "{error:?}"),
+		}
+	}
+}
+
+impl DispatchError {
+	/// Describes the error, with the module errors resolved to `pallet.Error` (e.g. `balances.InsufficientBalance`)
+	pub fn describe(&self, metadata: &Metadata) -> String {
+		match self {
+			DispatchError::Module(error) => match error.resolve(metadata) {
+				Some((pallet, variant)) => format!("{}.{variant}", snake_case(&pallet)),
+				None => self.to_string(),
+			},
+			_ => self.to_string(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use test_case::test_case;
+
+	#[test_case(&[0, 1] => "invalid.Payment" ; "payment")]
+	#[test_case(&[0, 7, 42] => "invalid.Custom(42)" ; "custom")]
+	#[test_case(&[1, 0] => "unknown.CannotLookup" ; "unknown")]
+	fn transaction_validity(encoded: &[u8]) -> String {
+		TransactionValidityError::decode(&mut &encoded[..])
+			.unwrap()
+			.to_string()
+	}
+
+	#[test]
+	fn validity_result() {
+		// Valid transaction details are ignored
+		assert_eq!(validity_error(&[0, 1, 2, 3]).unwrap(), None);
+		assert_eq!(
+			validity_error(&[1, 0, 1]).unwrap(),
+			Some(TransactionValidityError::Invalid(
+				InvalidTransaction::Payment
+			))
+		);
+		assert!(validity_error(&[2]).is_err());
+		assert!(validity_error(&[]).is_err());
+	}
+
+	#[test]
+	fn dispatch_errors() {
+		let module = DispatchError::decode(&mut &[3, 6, 2, 0, 0, 0][..]).unwrap();
+		assert_eq!(
+			module,
+			DispatchError::Module(ModuleError {
+				index: 6,
+				error: [2, 0, 0, 0]
+			})
+		);
+		assert_eq!(module.to_string(), "module(6).error(2)");
+		let token = DispatchError::decode(&mut &[7, 0][..]).unwrap();
+		assert_eq!(token.to_string(), "token.FundsUnavailable");
+		assert_eq!(DispatchError::BadOrigin.encode(), [2]);
+		assert!(DispatchError::decode(&mut &[14][..]).is_err());
+	}
+}
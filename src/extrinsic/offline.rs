@@ -0,0 +1,152 @@
+//! Split signing of extrinsics, for the air-gapped signers and hardware wallets.
+//!
+//! Online side creates the [`SigningRequest`] from the unsigned payload and exports it as JSON. Request contains
+//! both the payload parts (so signer can decode and display the call) and the exact bytes to be signed. Signer checks
+//! that the signed bytes match the payload parts before signing, so a tampered request is not signed blindly.
+//! Detached signature is returned to the online side, which verifies it and assembles the signed extrinsic.
+//!
+//! Payload encoding is deterministic, the same request always results in the same signed bytes.
+
+use avail_subxt::utils::H256;
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use serde::{Deserialize, Serialize};
+use sp_core::{sr25519, Bytes, Pair};
+
+use super::{ExtrinsicParams, RuntimeVersionSnapshot, UnsignedPayload};
+
+/// Unsigned payload exported for the offline signer
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SigningRequest {
+	pub signer: sr25519::Public,
+	pub call: Bytes,
+	pub params: ExtrinsicParams,
+	pub runtime: RuntimeVersionSnapshot,
+	pub genesis_hash: H256,
+	/// Bytes to be signed, hashed if the payload is longer than 256 bytes
+	pub signer_payload: Bytes,
+}
+
+impl SigningRequest {
+	pub fn new(payload: &UnsignedPayload, signer: sr25519::Public) -> Self {
+		SigningRequest {
+			signer,
+			call: payload.call.clone().into(),
+			params: payload.params,
+			runtime: payload.runtime,
+			genesis_hash: payload.genesis_hash,
+			signer_payload: payload.signer_payload().into(),
+		}
+	}
+
+	pub fn payload(&self) -> UnsignedPayload {
+		UnsignedPayload {
+			call: self.call.to_vec(),
+			params: self.params,
+			runtime: self.runtime,
+			genesis_hash: self.genesis_hash,
+		}
+	}
+
+	pub fn to_json(&self) -> Result<String> {
+		serde_json::to_string(self).wrap_err("Failed to encode signing request")
+	}
+
+	pub fn from_json(json: &str) -> Result<Self> {
+		serde_json::from_str(json).wrap_err("Failed to decode signing request")
+	}
+
+	/// Checks that the bytes to be signed are derived from the payload parts
+	pub fn verify(&self) -> Result<()> {
+		if self.payload().signer_payload() != self.signer_payload.0 {
+			return Err(eyre!("Signer payload doesn't match the request"));
+		}
+		Ok(())
+	}
+
+	/// Signs the request on the offline signer, returning detached signature
+	pub fn sign(&self, pair: &sr25519::Pair) -> Result<sr25519::Signature> {
+		self.verify()?;
+		if pair.public() != self.signer {
+			return Err(eyre!("Request is not for the signer {}", pair.public()));
+		}
+		Ok(pair.sign(&self.signer_payload))
+	}
+
+	/// Assembles the signed extrinsic from the detached signature, returned by the offline signer
+	pub fn assemble(&self, signature: &[u8]) -> Result<Vec<u8>> {
+		self.verify()?;
+		let signature = sr25519::Signature::try_from(signature)
+			.map_err(|_| eyre!("Invalid signature length {}", signature.len()))?;
+		if !sr25519::Pair::verify(&signature, &self.signer_payload, &self.signer) {
+			return Err(eyre!("Invalid signature of the signing request"));
+		}
+		Ok(self.payload().encode_signed(&self.signer, &signature))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::extrinsic::{Era, ExtrinsicBuilder, MetadataHash};
+
+	fn request(pair: &sr25519::Pair) -> SigningRequest {
+		let builder = ExtrinsicBuilder::new(
+			H256::repeat_byte(1),
+			RuntimeVersionSnapshot {
+				spec_version: 10,
+				transaction_version: 1,
+			},
+		);
+		let params = ExtrinsicParams {
+			nonce: 7,
+			tip: 1_000_000_000_000_000_000,
+			app_id: 1,
+			era: Era::Mortal {
+				period: 64,
+				block_number: 42,
+				block_hash: H256::repeat_byte(2),
+			},
+			metadata_hash: MetadataHash::Enabled(H256::repeat_byte(3)),
+		};
+		SigningRequest::new(&builder.payload(vec![29, 1, 4, 42], params), pair.public())
+	}
+
+	#[test]
+	fn offline_signing_roundtrip() {
+		let pair = sr25519::Pair::from_string("//Alice", None).unwrap();
+		let request = request(&pair);
+		let exported = SigningRequest::from_json(&request.to_json().unwrap()).unwrap();
+		assert_eq!(exported, request);
+
+		let signature = exported.sign(&pair).unwrap();
+		let extrinsic = request.assemble(signature.as_ref()).unwrap();
+		assert_eq!(
+			extrinsic,
+			request.payload().encode_signed(&pair.public(), &signature)
+		);
+	}
+
+	#[test]
+	fn tampered_request() {
+		let pair = sr25519::Pair::from_string("//Alice", None).unwrap();
+		let mut tampered = request(&pair);
+		tampered.call = vec![29, 1, 4, 43].into();
+		assert!(tampered.verify().is_err());
+		assert!(tampered.sign(&pair).is_err());
+		let bob = sr25519::Pair::from_string("//Bob", None).unwrap();
+		assert!(request(&pair).sign(&bob).is_err());
+	}
+
+	#[test]
+	fn invalid_signature() {
+		let pair = sr25519::Pair::from_string("//Alice", None).unwrap();
+		let request = request(&pair);
+		let bob = sr25519::Pair::from_string("//Bob", None).unwrap();
+		let signature = bob.sign(&request.signer_payload);
+		assert!(request.assemble(signature.as_ref()).is_err());
+		assert!(request.assemble(&[0; 32]).is_err());
+	}
+}
@@ -0,0 +1,197 @@
+//! Universal Offline Signatures (UOS) QR payloads, used by Parity Signer and Polkadot Vault.
+//!
+//! # Transaction payload
+//!
+//! * `0x53` - Substrate payload prefix
+//! * `0x01` - sr25519 crypto
+//! * `0x02` - transaction with the additional signed data
+//! * Signer public key, compact prefixed call, signed extensions and genesis hash
+//!
+//! Genesis hash is used by the signer only to select the network specs (and metadata) for decoding the call, signed
+//! payload is the call followed by the extensions, same as [`UnsignedPayload::signer_payload`].
+//!
+//! Payloads are split into the multipart frames, each shown as a separate QR code. Signer returns the hex encoded
+//! `MultiSignature` as a single QR code, which is parsed with [`parse_signature`]. Rendering of the QR codes is left
+//! to the embedder.
+
+use codec::{Compact, Encode};
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use sp_core::sr25519;
+
+use super::{offline::SigningRequest, UnsignedPayload};
+
+/// Substrate payload prefix
+const SUBSTRATE: u8 = 0x53;
+const CRYPTO_SR25519: u8 = 0x01;
+/// Transaction with the call, extensions (including additional signed data) and genesis hash
+const SIGN_TRANSACTION: u8 = 0x02;
+/// Multipart frame prefix
+const MULTIPART: u8 = 0x00;
+/// Multipart frame prefix, frame count and frame index
+const FRAME_HEADER_SIZE: usize = 5;
+/// Default frame data size, which fits into the QR code scannable by the phone camera
+pub const DEFAULT_FRAME_SIZE: usize = 1024;
+
+/// Encodes transaction payload to be signed by the sr25519 key
+pub fn transaction_payload(payload: &UnsignedPayload, signer: &sr25519::Public) -> Vec<u8> {
+	let mut encoded = vec![SUBSTRATE, CRYPTO_SR25519, SIGN_TRANSACTION];
+	encoded.extend_from_slice(signer.as_ref());
+	Compact(payload.call.len() as u32).encode_to(&mut encoded);
+	encoded.extend_from_slice(&payload.call);
+	encoded.extend(payload.extensions());
+	payload.genesis_hash.encode_to(&mut encoded);
+	encoded
+}
+
+/// Splits payload into the multipart QR frames, with up to `frame_size` bytes of payload in each frame
+pub fn frames(payload: &[u8], frame_size: usize) -> Result<Vec<Vec<u8>>> {
+	let chunks = payload.chunks(frame_size.max(1)).collect::<Vec<_>>();
+	let count = u16::try_from(chunks.len())
+		.map_err(|_| eyre!("Payload doesn't fit into {} frames", u16::MAX))?;
+	Ok(chunks
+		.into_iter()
+		.enumerate()
+		.map(|(index, chunk)| {
+			let mut frame = vec![MULTIPART];
+			frame.extend_from_slice(&count.to_be_bytes());
+			frame.extend_from_slice(&(index as u16).to_be_bytes());
+			frame.extend_from_slice(chunk);
+			frame
+		})
+		.collect())
+}
+
+/// Reassembles payload from the scanned multipart frames, in any order
+pub fn join_frames(frames: &[Vec<u8>]) -> Result<Vec<u8>> {
+	let mut chunks: Vec<Option<&[u8]>> = vec![];
+	for frame in frames {
+		if frame.len() < FRAME_HEADER_SIZE || frame[0] != MULTIPART {
+			return Err(eyre!("Invalid multipart frame"));
+		}
+		let count = u16::from_be_bytes([frame[1], frame[2]]) as usize;
+		let index = u16::from_be_bytes([frame[3], frame[4]]) as usize;
+		if chunks.is_empty() {
+			chunks.resize(count, None);
+		}
+		if count != chunks.len() || index >= count {
+			return Err(eyre!("Frame {index} of {count} doesn't match the payload"));
+		}
+		chunks[index] = Some(&frame[FRAME_HEADER_SIZE..]);
+	}
+	if chunks.is_empty() {
+		return Err(eyre!("No frames scanned"));
+	}
+	chunks
+		.into_iter()
+		.enumerate()
+		.map(|(index, chunk)| chunk.ok_or_else(|| eyre!("Frame {index} is missing")))
+		.collect::<Result<Vec<_>>>()
+		.map(|chunks| chunks.concat())
+}
+
+/// Parses hex encoded signature QR code, either `MultiSignature` or plain sr25519 signature
+pub fn parse_signature(qr: &str) -> Result<sr25519::Signature> {
+	let qr = qr.trim();
+	let bytes = hex::decode(qr.strip_prefix("0x").unwrap_or(qr))
+		.wrap_err("Signature is not hex encoded")?;
+	let signature = match bytes.split_first() {
+		Some((&CRYPTO_SR25519, signature)) if signature.len() == 64 => signature,
+		Some((crypto, _)) if bytes.len() == 65 => {
+			return Err(eyre!("Unsupported signature crypto {crypto}"));
+		},
+		_ => &bytes[..],
+	};
+	sr25519::Signature::try_from(signature)
+		.map_err(|_| eyre!("Invalid signature length {}", signature.len()))
+}
+
+impl SigningRequest {
+	/// Encodes request as UOS QR frames
+	pub fn uos_frames(&self, frame_size: usize) -> Result<Vec<Vec<u8>>> {
+		self.verify()?;
+		frames(
+			&transaction_payload(&self.payload(), &self.signer),
+			frame_size,
+		)
+	}
+
+	/// Assembles the signed extrinsic from the signature QR code
+	pub fn assemble_from_qr(&self, qr: &str) -> Result<Vec<u8>> {
+		self.assemble(parse_signature(qr)?.as_ref())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::extrinsic::{ExtrinsicBuilder, ExtrinsicParams, RuntimeVersionSnapshot};
+	use avail_subxt::utils::H256;
+	use sp_core::Pair;
+	use test_case::test_case;
+
+	fn request(pair: &sr25519::Pair, call: Vec<u8>) -> SigningRequest {
+		let runtime = RuntimeVersionSnapshot {
+			spec_version: 10,
+			transaction_version: 1,
+		};
+		let builder = ExtrinsicBuilder::new(H256::repeat_byte(1), runtime);
+		let payload = builder.payload(call, ExtrinsicParams::default());
+		SigningRequest::new(&payload, pair.public())
+	}
+
+	#[test]
+	fn transaction_payload_layout() {
+		let pair = sr25519::Pair::from_string("//Alice", None).unwrap();
+		let request = request(&pair, vec![29, 1, 4, 42]);
+		let payload = transaction_payload(&request.payload(), &pair.public());
+		assert_eq!(payload[..3], [0x53, 0x01, 0x02]);
+		assert_eq!(&payload[3..35], pair.public().as_ref());
+		assert_eq!(payload[35..40], [16, 29, 1, 4, 42]);
+		assert_eq!(
+			payload[40..payload.len() - 32],
+			request.payload().extensions()
+		);
+		assert!(payload.ends_with(H256::repeat_byte(1).as_bytes()));
+		// Signer signs the call followed by the extensions
+		assert_eq!(
+			[&payload[36..40], &payload[40..payload.len() - 32]].concat(),
+			request.signer_payload.0
+		);
+	}
+
+	#[test_case(10, 3 => 4 ; "partial last frame")]
+	#[test_case(9, 3 => 3 ; "full frames")]
+	#[test_case(1, 1024 => 1 ; "single frame")]
+	fn multipart_frames(length: usize, frame_size: usize) -> usize {
+		let payload = (0..length as u8).collect::<Vec<_>>();
+		let mut frames = frames(&payload, frame_size).unwrap();
+		frames.reverse();
+		assert_eq!(join_frames(&frames).unwrap(), payload);
+		frames.len()
+	}
+
+	#[test]
+	fn missing_frame() {
+		let frames = frames(&[0; 10], 3).unwrap();
+		assert!(join_frames(&frames[1..]).is_err());
+		assert!(join_frames(&[]).is_err());
+	}
+
+	#[test]
+	fn signature_qr() {
+		let pair = sr25519::Pair::from_string("//Alice", None).unwrap();
+		let request = request(&pair, vec![29, 1, 4, 42]);
+		let signature = request.sign(&pair).unwrap();
+		let multi_signature = format!("01{}", hex::encode(&signature));
+		assert_eq!(parse_signature(&multi_signature).unwrap(), signature);
+		assert_eq!(
+			parse_signature(&format!("0x{}", hex::encode(&signature))).unwrap(),
+			signature
+		);
+		assert!(parse_signature(&format!("00{}", hex::encode(&signature))).is_err());
+		assert!(request.assemble_from_qr(&multi_signature).is_ok());
+	}
+}
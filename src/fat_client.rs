@@ -22,7 +22,7 @@ use kate_recovery::{data::Cell, matrix::RowIndex};
 use mockall::automock;
 use sp_core::blake2_256;
 use std::{sync::Arc, time::Instant};
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, instrument, warn, Span};
 
 use crate::{
 	data::{Database, Key},
@@ -72,6 +72,7 @@ impl Client for FatClient {
 	}
 }
 
+#[instrument(skip_all, fields(block_number = header.number, block_hash = tracing::field::Empty), level = "info")]
 pub async fn process_block(
 	client: &impl Client,
 	db: impl Database,
@@ -88,6 +89,7 @@ pub async fn process_block(
 
 	let block_number = header.number;
 	let header_hash: H256 = Encode::using_encoded(header, blake2_256).into();
+	Span::current().record("block_hash", tracing::field::display(header_hash));
 	let block_delay = received_at.elapsed().as_secs();
 	info!(block_number, block_delay, "Processing finalized block",);
 
@@ -146,7 +148,7 @@ pub async fn process_block(
 				.insert_cells_into_dht(block_number, batch_rpc_fetched.clone())
 				.await
 			{
-				debug!("Error inserting cells into DHT: {e}");
+				debug!(block_number, "Error inserting cells into DHT: {e}");
 			}
 
 			rpc_fetched.extend(batch_rpc_fetched);
@@ -176,10 +178,13 @@ pub async fn process_block(
 		let data_rows = data::rows(dimensions, &data_cells);
 
 		if let Err(e) = client.insert_rows_into_dht(block_number, data_rows).await {
-			debug!("Error inserting rows into DHT: {e}");
+			debug!(block_number, "Error inserting rows into DHT: {e}");
 		}
 	} else {
-		warn!("No rows has been inserted into DHT since partition size is less than one row.")
+		warn!(
+			block_number,
+			"No rows has been inserted into DHT since partition size is less than one row."
+		)
 	}
 
 	Ok(())
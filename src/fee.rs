@@ -0,0 +1,175 @@
+//! Transaction fee estimation, using `TransactionPaymentApi` runtime API.
+//!
+//! Fees are estimated for the signed extrinsic, before it is submitted. Runtime API is called through the
+//! [`RuntimeApi`] backend, which is either a local executor or the `state_call` RPC of the full node,
+//! and [`WithFallback`] can be used to fall back to the RPC if the executor fails.
+
+use async_trait::async_trait;
+use avail_subxt::utils::H256;
+use codec::{Decode, Encode};
+use color_eyre::{eyre::WrapErr, Result};
+use mockall::automock;
+use tracing::warn;
+
+use crate::{extrinsic::calls::Weight, network::rpc::Client as RpcClient};
+
+const QUERY_INFO: &str = "TransactionPaymentApi_query_info";
+const QUERY_FEE_DETAILS: &str = "TransactionPaymentApi_query_fee_details";
+
+#[async_trait]
+#[automock]
+pub trait RuntimeApi {
+	/// Calls runtime API method with SCALE encoded arguments, at the given block or at the best block
+	async fn call(&self, method: &str, data: Vec<u8>, at: Option<H256>) -> Result<Vec<u8>>;
+}
+
+#[async_trait]
+impl RuntimeApi for RpcClient {
+	async fn call(&self, method: &str, data: Vec<u8>, at: Option<H256>) -> Result<Vec<u8>> {
+		self.state_call(method, data, at).await
+	}
+}
+
+/// Calls the primary backend, and the fallback backend if the primary one fails
+pub struct WithFallback<P: RuntimeApi, F: RuntimeApi> {
+	pub primary: P,
+	pub fallback: F,
+}
+
+#[async_trait]
+impl<P: RuntimeApi + Sync, F: RuntimeApi + Sync> RuntimeApi for WithFallback<P, F> {
+	async fn call(&self, method: &str, data: Vec<u8>, at: Option<H256>) -> Result<Vec<u8>> {
+		match self.primary.call(method, data.clone(), at).await {
+			Ok(result) => Ok(result),
+			Err(error) => {
+				warn!(method, "Runtime API call failed, using fallback: {error:#}");
+				self.fallback.call(method, data, at).await
+			},
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum DispatchClass {
+	Normal,
+	Operational,
+	Mandatory,
+}
+
+/// Decoded `RuntimeDispatchInfo`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct DispatchInfo {
+	pub weight: Weight,
+	pub class: DispatchClass,
+	/// Inclusion fee, without the tip
+	pub partial_fee: u128,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct InclusionFee {
+	pub base_fee: u128,
+	pub len_fee: u128,
+	pub adjusted_weight_fee: u128,
+}
+
+/// Decoded `FeeDetails`, tip is not encoded by the runtime
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct FeeDetails {
+	/// Unsigned extrinsics are not paying inclusion fee
+	pub inclusion_fee: Option<InclusionFee>,
+}
+
+impl FeeDetails {
+	pub fn inclusion_fee(&self) -> u128 {
+		self.inclusion_fee
+			.map(|fee| fee.base_fee + fee.len_fee + fee.adjusted_weight_fee)
+			.unwrap_or_default()
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeeEstimate {
+	pub info: DispatchInfo,
+	pub details: FeeDetails,
+}
+
+/// Estimates fee of the encoded signed extrinsic, at the given block or at the best block.
+pub async fn estimate_fee(
+	api: &impl RuntimeApi,
+	extrinsic: &[u8],
+	at: Option<H256>,
+) -> Result<FeeEstimate> {
+	let mut data = extrinsic.to_vec();
+	(extrinsic.len() as u32).encode_to(&mut data);
+
+	let info = api.call(QUERY_INFO, data.clone(), at).await?;
+	let info = DispatchInfo::decode(&mut &info[..]).wrap_err("Failed to decode dispatch info")?;
+
+	let details = api.call(QUERY_FEE_DETAILS, data, at).await?;
+	let details = FeeDetails::decode(&mut &details[..]).wrap_err("Failed to decode fee details")?;
+
+	Ok(FeeEstimate { info, details })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use color_eyre::eyre::eyre;
+	use mockall::predicate::{always, eq};
+
+	const EXTRINSIC: [u8; 4] = [12, 29, 1, 0];
+
+	fn info() -> DispatchInfo {
+		DispatchInfo {
+			weight: Weight {
+				ref_time: 1_000_000,
+				proof_size: 1_000,
+			},
+			class: DispatchClass::Normal,
+			partial_fee: 123_000,
+		}
+	}
+
+	fn details() -> FeeDetails {
+		FeeDetails {
+			inclusion_fee: Some(InclusionFee {
+				base_fee: 100_000,
+				len_fee: 3_000,
+				adjusted_weight_fee: 20_000,
+			}),
+		}
+	}
+
+	fn mock_api() -> MockRuntimeApi {
+		let data = [&EXTRINSIC[..], &4u32.encode()].concat();
+		let mut api = MockRuntimeApi::new();
+		api.expect_call()
+			.with(eq(QUERY_INFO), eq(data.clone()), always())
+			.returning(|_, _, _| Box::pin(async move { Ok(info().encode()) }));
+		api.expect_call()
+			.with(eq(QUERY_FEE_DETAILS), eq(data), always())
+			.returning(|_, _, _| Box::pin(async move { Ok(details().encode()) }));
+		api
+	}
+
+	#[tokio::test]
+	async fn estimate() {
+		let estimate = estimate_fee(&mock_api(), &EXTRINSIC, None).await.unwrap();
+		assert_eq!(estimate.info, info());
+		assert_eq!(estimate.details.inclusion_fee(), estimate.info.partial_fee);
+	}
+
+	#[tokio::test]
+	async fn estimate_with_fallback() {
+		let mut primary = MockRuntimeApi::new();
+		primary
+			.expect_call()
+			.returning(|_, _, _| Box::pin(async move { Err(eyre!("Executor failed")) }));
+		let api = WithFallback {
+			primary,
+			fallback: mock_api(),
+		};
+		let estimate = estimate_fee(&api, &EXTRINSIC, None).await.unwrap();
+		assert_eq!(estimate.details, details());
+	}
+}
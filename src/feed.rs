@@ -0,0 +1,316 @@
+//! Re-org aware feed of the canonical chain changes.
+//!
+//! Feed follows the best block of the [`ForkTree`]. When the best block changes, blocks of the abandoned branch are
+//! emitted as [`FeedEvent::Reverted`] (head first), followed by the blocks of the new branch as
+//! [`FeedEvent::Applied`] (oldest first).
+//!
+//! Every event gets a cursor, which is monotonically increasing and never reused. Events and the feed state are
+//! persisted in the database, so cursors survive restarts. Consumers get exactly-once processing by storing the
+//! cursor of the last processed event together with its effects, and resuming with [`Feed::events_since`].
+//! Events which are processed by all consumers can be removed with [`Feed::prune`].
+
+use avail_subxt::utils::H256;
+use codec::{Decode, Encode};
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::{
+	data::{Database, Key},
+	fork_choice::{BlockInfo, ForkTree},
+};
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct FeedBlock {
+	pub hash: H256,
+	pub number: u32,
+	pub parent_hash: H256,
+}
+
+impl From<&BlockInfo> for FeedBlock {
+	fn from(block: &BlockInfo) -> Self {
+		FeedBlock {
+			hash: block.hash,
+			number: block.number,
+			parent_hash: block.parent_hash,
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FeedEvent {
+	/// Block became part of the canonical chain
+	Applied(FeedBlock),
+	/// Block is no longer part of the canonical chain
+	Reverted(FeedBlock),
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct CursoredEvent {
+	pub cursor: u64,
+	pub event: FeedEvent,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+struct FeedState {
+	/// Cursor of the next event, cursors start from 1
+	next_cursor: u64,
+	/// Cursor of the oldest retained event
+	first_cursor: u64,
+	/// Applied blocks, from the latest finalized block to the head
+	canonical: Vec<FeedBlock>,
+}
+
+impl Default for FeedState {
+	fn default() -> Self {
+		FeedState {
+			next_cursor: 1,
+			first_cursor: 1,
+			canonical: vec![],
+		}
+	}
+}
+
+pub struct Feed<T: Database> {
+	db: T,
+	state: FeedState,
+	sender: broadcast::Sender<CursoredEvent>,
+}
+
+impl<T: Database> Feed<T> {
+	/// Loads feed state from the database, or starts an empty feed.
+	pub fn load(db: T, capacity: usize) -> Result<Self> {
+		let state = db
+			.get(Key::FeedState)
+			.wrap_err("Feed failed to get state")?
+			.unwrap_or_default();
+		Ok(Feed {
+			db,
+			state,
+			sender: broadcast::channel(capacity).0,
+		})
+	}
+
+	/// Subscribes to the live events. Subscriber which lags behind should resume with [`Feed::events_since`].
+	pub fn subscribe(&self) -> broadcast::Receiver<CursoredEvent> {
+		self.sender.subscribe()
+	}
+
+	pub fn head(&self) -> Option<&FeedBlock> {
+		self.state.canonical.last()
+	}
+
+	/// Cursor of the latest event, or 0 if there are no events
+	pub fn last_cursor(&self) -> u64 {
+		self.state.next_cursor - 1
+	}
+
+	/// Moves feed head to the given best block of the fork tree, returning emitted events.
+	/// Empty feed starts from the root of the tree.
+	pub fn update(&mut self, tree: &ForkTree, best: &H256) -> Result<Vec<CursoredEvent>> {
+		if tree.get(best).is_none() {
+			return Err(eyre!("Block {best:?} is not imported"));
+		}
+
+		let mut state = self.state.clone();
+		let mut applied = vec![];
+		let mut ancestor = None;
+		for block in tree.ancestry(best) {
+			if let Some(position) = state.canonical.iter().rposition(|b| b.hash == block.hash) {
+				ancestor = Some(position);
+				break;
+			}
+			applied.push(FeedBlock::from(block));
+		}
+
+		let reverted = match ancestor {
+			Some(position) => state.canonical.split_off(position + 1),
+			None if state.canonical.is_empty() => vec![],
+			None => return Err(eyre!("Block {best:?} has no common ancestor with the feed")),
+		};
+
+		let mut events = vec![];
+		for block in reverted.into_iter().rev() {
+			events.push(state.next_event(FeedEvent::Reverted(block)));
+		}
+		for block in applied.into_iter().rev() {
+			state.canonical.push(block.clone());
+			events.push(state.next_event(FeedEvent::Applied(block)));
+		}
+
+		// Events are stored before the state, so event after the stored next cursor is overwritten on retry
+		for event in &events {
+			self.db
+				.put(Key::FeedEvent(event.cursor), event)
+				.wrap_err("Feed failed to store event")?;
+		}
+		self.store(state)?;
+
+		for event in &events {
+			// Error means there are no subscribers
+			_ = self.sender.send(event.clone());
+		}
+		Ok(events)
+	}
+
+	/// Forgets applied blocks below the finalized block, since they cannot be reverted.
+	pub fn finalize(&mut self, number: u32) -> Result<()> {
+		let mut state = self.state.clone();
+		state.canonical.retain(|block| block.number >= number);
+		self.store(state)
+	}
+
+	/// Returns retained events after the given cursor.
+	pub fn events_since(&self, cursor: u64) -> Result<Vec<CursoredEvent>> {
+		if cursor + 1 < self.state.first_cursor {
+			return Err(eyre!("Feed events after cursor {cursor} are pruned"));
+		}
+		(cursor + 1..self.state.next_cursor)
+			.map(|cursor| {
+				self.db
+					.get(Key::FeedEvent(cursor))
+					.wrap_err("Feed failed to get event")?
+					.ok_or_else(|| eyre!("Feed event {cursor} is missing"))
+			})
+			.collect()
+	}
+
+	/// Removes events up to and including the given cursor.
+	pub fn prune(&mut self, cursor: u64) -> Result<()> {
+		let first = self.state.first_cursor;
+		let last = cursor.min(self.last_cursor());
+		if last < first {
+			return Ok(());
+		}
+		let mut state = self.state.clone();
+		state.first_cursor = last + 1;
+		// State is stored first, so events are never returned once deletion starts
+		self.store(state)?;
+		for cursor in first..=last {
+			self.db
+				.delete(Key::FeedEvent(cursor))
+				.wrap_err("Feed failed to delete event")?;
+		}
+		Ok(())
+	}
+
+	fn store(&mut self, state: FeedState) -> Result<()> {
+		self.db
+			.put(Key::FeedState, &state)
+			.wrap_err("Feed failed to store state")?;
+		self.state = state;
+		Ok(())
+	}
+}
+
+impl FeedState {
+	fn next_event(&mut self, event: FeedEvent) -> CursoredEvent {
+		let cursor = self.next_cursor;
+		self.next_cursor += 1;
+		CursoredEvent { cursor, event }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{data::mem_db::MemoryDB, fork_choice::LongestChain};
+
+	fn block(hash: u8, number: u32, parent: u8) -> BlockInfo {
+		BlockInfo {
+			hash: H256::repeat_byte(hash),
+			number,
+			parent_hash: H256::repeat_byte(parent),
+			confidence: None,
+		}
+	}
+
+	fn summary(events: &[CursoredEvent]) -> Vec<(u64, bool, u8)> {
+		events
+			.iter()
+			.map(|CursoredEvent { cursor, event }| match event {
+				FeedEvent::Applied(block) => (*cursor, true, block.hash[0]),
+				FeedEvent::Reverted(block) => (*cursor, false, block.hash[0]),
+			})
+			.collect()
+	}
+
+	// 0 - 1 - 2
+	//      \
+	//       3 - 4
+	fn tree() -> ForkTree {
+		let mut tree = ForkTree::new(block(0, 0, 0xff), Box::new(LongestChain));
+		for block in [
+			block(1, 1, 0),
+			block(2, 2, 1),
+			block(3, 2, 1),
+			block(4, 3, 3),
+		] {
+			tree.import(block).unwrap();
+		}
+		tree
+	}
+
+	#[test]
+	fn reorg() {
+		let tree = tree();
+		let mut feed = Feed::load(MemoryDB::default(), 10).unwrap();
+
+		let events = feed.update(&tree, &H256::repeat_byte(2)).unwrap();
+		assert_eq!(
+			summary(&events),
+			vec![(1, true, 0), (2, true, 1), (3, true, 2)]
+		);
+
+		let events = feed.update(&tree, &H256::repeat_byte(4)).unwrap();
+		assert_eq!(
+			summary(&events),
+			vec![(4, false, 2), (5, true, 3), (6, true, 4)]
+		);
+		assert_eq!(feed.head().unwrap().hash, H256::repeat_byte(4));
+
+		assert!(feed
+			.update(&tree, &H256::repeat_byte(4))
+			.unwrap()
+			.is_empty());
+	}
+
+	#[test]
+	fn cursors_survive_restart() {
+		let tree = tree();
+		let db = MemoryDB::default();
+		let mut feed = Feed::load(db.clone(), 10).unwrap();
+		feed.update(&tree, &H256::repeat_byte(2)).unwrap();
+
+		let mut feed = Feed::load(db, 10).unwrap();
+		assert_eq!(feed.last_cursor(), 3);
+		assert_eq!(
+			summary(&feed.events_since(1).unwrap()),
+			vec![(2, true, 1), (3, true, 2)]
+		);
+
+		let mut receiver = feed.subscribe();
+		feed.update(&tree, &H256::repeat_byte(4)).unwrap();
+		assert_eq!(receiver.try_recv().unwrap().cursor, 4);
+	}
+
+	#[test]
+	fn prune_and_finalize() {
+		let tree = tree();
+		let mut feed = Feed::load(MemoryDB::default(), 10).unwrap();
+		feed.update(&tree, &H256::repeat_byte(2)).unwrap();
+
+		feed.prune(2).unwrap();
+		assert!(feed.events_since(0).is_err());
+		assert_eq!(summary(&feed.events_since(2).unwrap()), vec![(3, true, 2)]);
+
+		// Fork point (block 1) is forgotten, so the reorg cannot be followed
+		feed.finalize(2).unwrap();
+		assert!(feed.update(&tree, &H256::repeat_byte(4)).is_err());
+		assert!(feed.update(&tree, &H256::repeat_byte(9)).is_err());
+	}
+}
@@ -106,7 +106,7 @@ pub fn check_finality(
 		.ok_or(eyre!("Not signed by supermajority of validator set!"))
 }
 
-fn is_signed_by_supermajority(num_signatures: usize, validator_set_size: usize) -> bool {
+pub(crate) fn is_signed_by_supermajority(num_signatures: usize, validator_set_size: usize) -> bool {
 	let supermajority = (validator_set_size * 2 / 3) + 1;
 	num_signatures >= supermajority
 }
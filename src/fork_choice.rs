@@ -0,0 +1,445 @@
+//! Fork tree of imported, not yet pruned blocks, with pluggable fork-choice rule.
+//!
+//! # Strategies
+//!
+//! * [`LongestChain`] - leaf with the highest block number
+//! * [`Ghost`] - greedy heaviest observed subtree, starting from the finalized block
+//! * [`FinalityConstrained`] - wraps another rule, considering only descendants of the finalized block
+//! * [`ConfidenceWeighted`] - leaf with the highest accumulated DA confidence since finalized block
+//!
+//! Custom rules can be injected by implementing [`ForkChoice`] trait.
+//!
+//! Headers imported with [`ForkTree::import_header`] are checked for extension consistency first, so malformed headers
+//! never enter the tree. Tree created with [`ForkTree::with_budget`] accounts its blocks in the
+//! [`MemoryBudget`], and rejects imports while the budget is exceeded.
+
+use avail_subxt::{
+	api::runtime_types::avail_core::header::extension::HeaderExtension, primitives::Header,
+	utils::H256,
+};
+use codec::Encode;
+use color_eyre::{eyre::eyre, Result};
+use sp_core::blake2_256;
+use std::{
+	collections::{HashMap, HashSet},
+	mem,
+	sync::Arc,
+};
+
+use crate::{
+	header::{
+		cached::CachedHeader,
+		consistency::{check_extension, ExtensionLimits},
+		digest::SmallHeader,
+	},
+	memory::{Component, MemoryBudget},
+};
+
+/// Estimated memory size of the imported block, including its entries in the children index
+const BLOCK_SIZE: usize = mem::size_of::<BlockInfo>() + 2 * mem::size_of::<H256>();
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockInfo {
+	pub hash: H256,
+	pub number: u32,
+	pub parent_hash: H256,
+	/// Data availability confidence, if block is sampled
+	pub confidence: Option<f64>,
+}
+
+/// Rule used by the [`ForkTree`] to select the best block
+pub trait ForkChoice {
+	/// Selects the best block among given leaves.
+	fn select_best(&self, tree: &ForkTree, leaves: &[H256]) -> Option<H256>;
+}
+
+pub struct LongestChain;
+
+impl ForkChoice for LongestChain {
+	fn select_best(&self, tree: &ForkTree, leaves: &[H256]) -> Option<H256> {
+		leaves
+			.iter()
+			.filter_map(|hash| tree.get(hash))
+			// On equal height, prefer the lowest hash to get deterministic result
+			.max_by(|a, b| a.number.cmp(&b.number).then(b.hash.cmp(&a.hash)))
+			.map(|block| block.hash)
+	}
+}
+
+pub struct Ghost;
+
+impl ForkChoice for Ghost {
+	fn select_best(&self, tree: &ForkTree, leaves: &[H256]) -> Option<H256> {
+		let mut current = tree.finalized();
+		loop {
+			let heaviest = tree
+				.children(&current)
+				.iter()
+				.filter(|child| leaves.iter().any(|leaf| tree.is_descendant(leaf, child)))
+				.max_by(|a, b| (tree.subtree_size(a).cmp(&tree.subtree_size(b))).then(b.cmp(a)))
+				.copied();
+			match heaviest {
+				Some(child) => current = child,
+				None => return Some(current),
+			}
+		}
+	}
+}
+
+/// Considers only leaves which are descendants of the finalized block
+pub struct FinalityConstrained<F: ForkChoice>(pub F);
+
+impl<F: ForkChoice> ForkChoice for FinalityConstrained<F> {
+	fn select_best(&self, tree: &ForkTree, leaves: &[H256]) -> Option<H256> {
+		let finalized = tree.finalized();
+		let leaves = leaves
+			.iter()
+			.filter(|leaf| tree.is_descendant(leaf, &finalized))
+			.copied()
+			.collect::<Vec<_>>();
+		self.0.select_best(tree, &leaves)
+	}
+}
+
+/// Selects leaf with the highest sum of DA confidence since finalized block.
+/// Blocks without confidence don't contribute to the weight.
+pub struct ConfidenceWeighted;
+
+impl ForkChoice for ConfidenceWeighted {
+	fn select_best(&self, tree: &ForkTree, leaves: &[H256]) -> Option<H256> {
+		let finalized = tree.finalized();
+		let weight = |leaf: &H256| -> f64 {
+			tree.ancestry(leaf)
+				.take_while(|block| block.hash != finalized)
+				.filter_map(|block| block.confidence)
+				.sum()
+		};
+		leaves
+			.iter()
+			.map(|leaf| (leaf, weight(leaf)))
+			.max_by(|(a, a_weight), (b, b_weight)| a_weight.total_cmp(b_weight).then(b.cmp(a)))
+			.map(|(leaf, _)| *leaf)
+	}
+}
+
+pub struct ForkTree {
+	blocks: HashMap<H256, BlockInfo>,
+	children: HashMap<H256, Vec<H256>>,
+	finalized: H256,
+	fork_choice: Box<dyn ForkChoice + Send + Sync>,
+	budget: Option<Arc<MemoryBudget>>,
+}
+
+impl ForkTree {
+	pub fn new(finalized: BlockInfo, fork_choice: Box<dyn ForkChoice + Send + Sync>) -> Self {
+		let hash = finalized.hash;
+		ForkTree {
+			blocks: HashMap::from([(hash, finalized)]),
+			children: HashMap::new(),
+			finalized: hash,
+			fork_choice,
+			budget: None,
+		}
+	}
+
+	/// Accounts imported blocks in the memory budget
+	pub fn with_budget(mut self, budget: Arc<MemoryBudget>) -> Self {
+		budget.allocate(Component::ForkTree, self.blocks.len() * BLOCK_SIZE);
+		self.budget = Some(budget);
+		self
+	}
+
+	/// Imports block, parent block has to be already imported.
+	pub fn import(&mut self, block: BlockInfo) -> Result<()> {
+		if self.blocks.contains_key(&block.hash) {
+			return Ok(());
+		}
+		let parent = self
+			.blocks
+			.get(&block.parent_hash)
+			.ok_or_else(|| eyre!("Parent block {} is not imported", block.parent_hash))?;
+		if parent.number + 1 != block.number {
+			return Err(eyre!(
+				"Invalid block number {}, parent block number is {}",
+				block.number,
+				parent.number
+			));
+		}
+		if let Some(budget) = &self.budget {
+			if !budget.try_allocate(Component::ForkTree, BLOCK_SIZE) {
+				return Err(eyre!(
+					"Memory budget exceeded, block {} is not imported",
+					block.hash
+				));
+			}
+		}
+		self.children
+			.entry(block.parent_hash)
+			.or_default()
+			.push(block.hash);
+		self.blocks.insert(block.hash, block);
+		Ok(())
+	}
+
+	/// Checks header extension consistency, and imports header as not yet sampled block.
+	pub fn import_header(&mut self, header: &Header, limits: &ExtensionLimits) -> Result<H256> {
+		let block = BlockInfo {
+			hash: Encode::using_encoded(header, blake2_256).into(),
+			number: header.number,
+			parent_hash: header.parent_hash,
+			confidence: None,
+		};
+		self.import_checked(block, &header.extension, limits)
+	}
+
+	/// Imports header with the cached hash and the small digest, see [`ForkTree::import_header`].
+	pub fn import_cached_header(
+		&mut self,
+		header: &CachedHeader<SmallHeader>,
+		limits: &ExtensionLimits,
+	) -> Result<H256> {
+		let block = BlockInfo {
+			hash: header.hash(),
+			number: header.number,
+			parent_hash: header.parent_hash,
+			confidence: None,
+		};
+		self.import_checked(block, &header.extension, limits)
+	}
+
+	/// Checks header extension consistency, and imports the block
+	fn import_checked(
+		&mut self,
+		block: BlockInfo,
+		extension: &HeaderExtension,
+		limits: &ExtensionLimits,
+	) -> Result<H256> {
+		let hash = block.hash;
+		check_extension(extension, limits)
+			.map_err(|error| eyre!("Malformed header {hash:?}: {error}"))?;
+		self.import(block)?;
+		Ok(hash)
+	}
+
+	/// Updates block confidence, once block is sampled.
+	pub fn set_confidence(&mut self, hash: &H256, confidence: f64) {
+		if let Some(block) = self.blocks.get_mut(hash) {
+			block.confidence = Some(confidence);
+		}
+	}
+
+	/// Marks block as finalized, block has to be descendant of the current finalized block.
+	pub fn finalize(&mut self, hash: H256) -> Result<()> {
+		if !self.is_descendant(&hash, &self.finalized) {
+			return Err(eyre!("Block {hash} is not a descendant of finalized block"));
+		}
+		self.finalized = hash;
+		Ok(())
+	}
+
+	/// Prunes all blocks that are not descendants of the finalized block.
+	pub fn prune(&mut self) {
+		// Descendants of the finalized block are collected from the children index, in linear time
+		let mut retained = HashSet::from([self.finalized]);
+		let mut pending = vec![self.finalized];
+		while let Some(hash) = pending.pop() {
+			for child in self.children(&hash) {
+				if retained.insert(*child) {
+					pending.push(*child);
+				}
+			}
+		}
+		let pruned = self.blocks.len() - retained.len();
+		self.blocks.retain(|block, _| retained.contains(block));
+		self.children.retain(|block, _| retained.contains(block));
+		if let Some(budget) = &self.budget {
+			budget.free(Component::ForkTree, pruned * BLOCK_SIZE);
+		}
+	}
+
+	pub fn get(&self, hash: &H256) -> Option<&BlockInfo> {
+		self.blocks.get(hash)
+	}
+
+	pub fn finalized(&self) -> H256 {
+		self.finalized
+	}
+
+	pub fn children(&self, hash: &H256) -> &[H256] {
+		self.children.get(hash).map(Vec::as_slice).unwrap_or(&[])
+	}
+
+	pub fn leaves(&self) -> Vec<H256> {
+		self.blocks
+			.keys()
+			.filter(|hash| self.children(hash).is_empty())
+			.copied()
+			.collect()
+	}
+
+	/// Iterates from the given block towards the root of the tree, including the block itself.
+	pub fn ancestry<'a>(&'a self, hash: &H256) -> impl Iterator<Item = &'a BlockInfo> {
+		std::iter::successors(self.blocks.get(hash), |block| {
+			self.blocks.get(&block.parent_hash)
+		})
+	}
+
+	/// Checks if block is descendant of (or equal to) the ancestor block.
+	pub fn is_descendant(&self, hash: &H256, ancestor: &H256) -> bool {
+		self.ancestry(hash).any(|block| block.hash == *ancestor)
+	}
+
+	/// Number of blocks in the subtree, including its root.
+	pub fn subtree_size(&self, hash: &H256) -> usize {
+		1 + self
+			.children(hash)
+			.iter()
+			.map(|child| self.subtree_size(child))
+			.sum::<usize>()
+	}
+
+	/// Selects the best block using configured fork-choice rule.
+	pub fn best_block(&self) -> Option<&BlockInfo> {
+		let leaves = self.leaves();
+		self.fork_choice
+			.select_best(self, &leaves)
+			.and_then(|hash| self.get(&hash))
+	}
+}
+
+impl Drop for ForkTree {
+	fn drop(&mut self) {
+		if let Some(budget) = &self.budget {
+			budget.free(Component::ForkTree, self.blocks.len() * BLOCK_SIZE);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn block(hash: u8, number: u32, parent: u8, confidence: Option<f64>) -> BlockInfo {
+		BlockInfo {
+			hash: H256::repeat_byte(hash),
+			number,
+			parent_hash: H256::repeat_byte(parent),
+			confidence,
+		}
+	}
+
+	// 0 - 1 - 2 - 3
+	//      \
+	//       4 - 5
+	//        \
+	//         6
+	fn tree(fork_choice: Box<dyn ForkChoice + Send + Sync>) -> ForkTree {
+		let mut tree = ForkTree::new(block(0, 0, 0xff, None), fork_choice);
+		for block in [
+			block(1, 1, 0, Some(50.0)),
+			block(2, 2, 1, Some(50.0)),
+			block(3, 3, 2, None),
+			block(4, 2, 1, Some(99.0)),
+			block(5, 3, 4, Some(99.0)),
+			block(6, 3, 4, None),
+		] {
+			tree.import(block).unwrap();
+		}
+		tree
+	}
+
+	#[test]
+	fn longest_chain() {
+		let tree = tree(Box::new(LongestChain));
+		assert_eq!(tree.best_block().unwrap().hash, H256::repeat_byte(3));
+	}
+
+	#[test]
+	fn ghost() {
+		let tree = tree(Box::new(Ghost));
+		let best = tree.best_block().unwrap().hash;
+		assert_eq!(best, H256::repeat_byte(5));
+	}
+
+	#[test]
+	fn confidence_weighted() {
+		let tree = tree(Box::new(ConfidenceWeighted));
+		assert_eq!(tree.best_block().unwrap().hash, H256::repeat_byte(5));
+	}
+
+	#[test]
+	fn finality_constrained() {
+		let mut tree = tree(Box::new(FinalityConstrained(LongestChain)));
+		tree.import(block(7, 4, 3, None)).unwrap();
+		tree.finalize(H256::repeat_byte(4)).unwrap();
+		assert_eq!(tree.best_block().unwrap().hash, H256::repeat_byte(5));
+
+		tree.prune();
+		assert!(tree.get(&H256::repeat_byte(7)).is_none());
+		assert!(tree.get(&H256::repeat_byte(1)).is_none());
+		assert_eq!(tree.leaves().len(), 2);
+	}
+
+	#[test]
+	fn import_requires_parent() {
+		let mut tree = tree(Box::new(LongestChain));
+		assert!(tree.import(block(8, 5, 9, None)).is_err());
+		assert!(tree.import(block(8, 5, 3, None)).is_err());
+	}
+
+	#[test]
+	fn import_within_budget() {
+		let budget = MemoryBudget::new(Some(3 * BLOCK_SIZE));
+		let mut tree = ForkTree::new(block(0, 0, 0xff, None), Box::new(LongestChain))
+			.with_budget(budget.clone());
+		tree.import(block(1, 1, 0, None)).unwrap();
+		tree.import(block(2, 2, 1, None)).unwrap();
+		assert!(tree.import(block(3, 3, 2, None)).is_err());
+
+		tree.finalize(H256::repeat_byte(1)).unwrap();
+		tree.prune();
+		tree.import(block(3, 3, 2, None)).unwrap();
+		drop(tree);
+		assert_eq!(budget.used(), 0);
+	}
+
+	#[test]
+	fn import_header_rejects_malformed() {
+		use avail_subxt::{
+			api::runtime_types::avail_core::{
+				data_lookup::compact::CompactDataLookup,
+				header::extension::{v3, HeaderExtension},
+				kate_commitment::v3::KateCommitment,
+			},
+			config::substrate::Digest,
+		};
+
+		let header = |data_root: H256| Header {
+			parent_hash: H256::repeat_byte(3),
+			number: 4,
+			state_root: H256::zero(),
+			extrinsics_root: H256::zero(),
+			digest: Digest { logs: vec![] },
+			extension: HeaderExtension::V3(v3::HeaderExtension {
+				commitment: KateCommitment {
+					rows: 1,
+					cols: 4,
+					data_root,
+					commitment: vec![0; 96],
+				},
+				app_lookup: CompactDataLookup {
+					size: 0,
+					index: vec![],
+				},
+			}),
+		};
+
+		let mut tree = tree(Box::new(LongestChain));
+		let limits = ExtensionLimits::default();
+		assert!(tree
+			.import_header(&header(H256::repeat_byte(1)), &limits)
+			.is_err());
+		let hash = tree.import_header(&header(H256::zero()), &limits).unwrap();
+		assert_eq!(tree.best_block().unwrap().hash, hash);
+	}
+}
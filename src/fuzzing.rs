@@ -0,0 +1,168 @@
+//! Arbitrary values and structurally valid encodings for fuzzing decode and verify paths.
+//!
+//! Foreign types are wrapped into newtypes (e.g. [`ArbitraryHeader`]), since [`Arbitrary`] cannot be
+//! implemented for them directly. Purely random input rarely gets past the first length prefix, so
+//! `valid_*` helpers generate encodings which decode successfully, to be mutated by the fuzzer.
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+use avail_subxt::{
+	api::runtime_types::avail_core::{
+		data_lookup::compact::CompactDataLookup,
+		header::extension::{v3, HeaderExtension},
+		kate_commitment::v3::KateCommitment,
+	},
+	config::substrate::{Digest, DigestItem},
+	primitives::Header,
+	utils::H256,
+};
+use codec::Encode;
+use sp_trie::StorageProof;
+
+use crate::{body::Block, storage_proof::build_trie};
+
+/// Maximum number of generated digest items, extrinsics and trie entries
+const MAX_ITEMS: usize = 16;
+
+fn h256(u: &mut Unstructured) -> Result<H256> {
+	Ok(H256(u.arbitrary()?))
+}
+
+fn items<T>(u: &mut Unstructured, item: impl Fn(&mut Unstructured) -> Result<T>) -> Result<Vec<T>> {
+	let len = u.int_in_range(0..=MAX_ITEMS)?;
+	(0..len).map(|_| item(u)).collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct ArbitraryDigestItem(pub DigestItem);
+
+impl<'a> Arbitrary<'a> for ArbitraryDigestItem {
+	fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+		let item = match u.int_in_range(0..=4)? {
+			0 => DigestItem::PreRuntime(u.arbitrary()?, u.arbitrary()?),
+			1 => DigestItem::Consensus(u.arbitrary()?, u.arbitrary()?),
+			2 => DigestItem::Seal(u.arbitrary()?, u.arbitrary()?),
+			3 => DigestItem::Other(u.arbitrary()?),
+			_ => DigestItem::RuntimeEnvironmentUpdated,
+		};
+		Ok(ArbitraryDigestItem(item))
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct ArbitraryHeader(pub Header);
+
+impl<'a> Arbitrary<'a> for ArbitraryHeader {
+	fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+		let logs = items(u, |u| Ok(ArbitraryDigestItem::arbitrary(u)?.0))?;
+		let extension = HeaderExtension::V3(v3::HeaderExtension {
+			commitment: KateCommitment {
+				rows: u.arbitrary()?,
+				cols: u.arbitrary()?,
+				data_root: h256(u)?,
+				commitment: u.arbitrary()?,
+			},
+			app_lookup: CompactDataLookup {
+				size: u.arbitrary()?,
+				index: vec![],
+			},
+		});
+		Ok(ArbitraryHeader(Header {
+			parent_hash: h256(u)?,
+			number: u.arbitrary()?,
+			state_root: h256(u)?,
+			extrinsics_root: h256(u)?,
+			digest: Digest { logs },
+			extension,
+		}))
+	}
+}
+
+impl<'a> Arbitrary<'a> for Block {
+	fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+		Ok(Block {
+			header: ArbitraryHeader::arbitrary(u)?.0,
+			extrinsics: items(u, |u| u.arbitrary())?,
+		})
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct ArbitraryStorageProof(pub StorageProof);
+
+impl<'a> Arbitrary<'a> for ArbitraryStorageProof {
+	fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+		let nodes: Vec<Vec<u8>> = items(u, |u| u.arbitrary())?;
+		Ok(ArbitraryStorageProof(StorageProof::new(nodes)))
+	}
+}
+
+/// Valid read proof of the random trie
+#[derive(Clone, Debug)]
+pub struct ValidReadProof {
+	pub state_root: H256,
+	pub proof: Vec<Vec<u8>>,
+	/// Keys of the trie entries
+	pub keys: Vec<Vec<u8>>,
+}
+
+impl<'a> Arbitrary<'a> for ValidReadProof {
+	fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+		let entries: Vec<(Vec<u8>, Vec<u8>)> = items(u, |u| u.arbitrary())?;
+		let entries = entries
+			.iter()
+			.map(|(key, value)| (&key[..], &value[..]))
+			.collect::<Vec<_>>();
+		let (state_root, proof) = build_trie(&entries);
+		Ok(ValidReadProof {
+			state_root,
+			proof,
+			keys: entries.iter().map(|(key, _)| key.to_vec()).collect(),
+		})
+	}
+}
+
+pub fn valid_header_encoding(u: &mut Unstructured) -> Result<Vec<u8>> {
+	Ok(ArbitraryHeader::arbitrary(u)?.0.encode())
+}
+
+pub fn valid_block_encoding(u: &mut Unstructured) -> Result<Vec<u8>> {
+	Ok(Block::arbitrary(u)?.encode())
+}
+
+pub fn valid_storage_proof_encoding(u: &mut Unstructured) -> Result<Vec<u8>> {
+	Ok(ValidReadProof::arbitrary(u)?.proof.encode())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		header::HeaderRef,
+		limits::{DecodeLimits, DecodeWithLimits},
+		storage_proof::verify_read_proof,
+	};
+
+	const SEED: [u8; 4096] = [7; 4096];
+
+	#[test]
+	fn valid_encodings_decode() {
+		let mut u = Unstructured::new(&SEED);
+		let encoded = valid_block_encoding(&mut u).unwrap();
+		assert!(Block::decode_with_limits(&encoded, &DecodeLimits::default()).is_ok());
+		let encoded = valid_header_encoding(&mut u).unwrap();
+		assert!(HeaderRef::decode(&encoded)
+			.and_then(|header| header.decode_extension())
+			.is_ok());
+	}
+
+	#[test]
+	fn valid_read_proof_verifies() {
+		let mut u = Unstructured::new(&SEED);
+		let ValidReadProof {
+			state_root,
+			proof,
+			keys,
+		} = ValidReadProof::arbitrary(&mut u).unwrap();
+		assert!(verify_read_proof(state_root, proof, &keys).is_ok());
+	}
+}
@@ -0,0 +1,515 @@
+//! OpenGov referenda tracking and voting calls.
+//!
+//! `Referenda::ReferendumInfoFor` entries are read through the [`StateClient`], verified against the state root, and
+//! decoded using the type from the runtime metadata (referendum status contains the runtime specific origin and
+//! proposal). [`ReferendumTracker`] compares statuses between the blocks, and reports changes (e.g. decision started,
+//! confirmation started, or referendum approved), so dashboards are built from verified data only.
+//!
+//! Votes and delegations are built with [`ConvictionVotingCalls`].
+
+use avail_subxt::utils::H256;
+use codec::{Compact, Encode};
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use sp_core::{blake2_128, twox_128};
+use std::collections::HashMap;
+use subxt::{
+	ext::scale_value::{At, Value, ValueDef},
+	metadata::types::StorageEntryType,
+	Metadata,
+};
+
+use crate::{
+	dynamic,
+	extrinsic::calls::CallIndex,
+	state_client::{Client, StateClient},
+};
+
+pub type ReferendumIndex = u32;
+pub type TrackId = u16;
+
+/// `MultiAddress::Id` variant index
+const MULTI_ADDRESS_ID: u8 = 0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Tally {
+	pub ayes: u128,
+	pub nays: u128,
+	pub support: u128,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Deciding {
+	pub since: u32,
+	/// Block at which confirmation period ends, if referendum is confirming
+	pub confirming: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReferendumStatus {
+	Ongoing {
+		track: TrackId,
+		submitted: u32,
+		/// Decision deposit is placed
+		has_decision_deposit: bool,
+		deciding: Option<Deciding>,
+		tally: Tally,
+	},
+	Approved {
+		since: u32,
+	},
+	Rejected {
+		since: u32,
+	},
+	Cancelled {
+		since: u32,
+	},
+	TimedOut {
+		since: u32,
+	},
+	Killed {
+		since: u32,
+	},
+}
+
+impl ReferendumStatus {
+	pub fn is_ongoing(&self) -> bool {
+		matches!(self, ReferendumStatus::Ongoing { .. })
+	}
+}
+
+/// Storage key of the `Referenda::ReferendumInfoFor` entry
+pub fn referendum_info_storage_key(index: ReferendumIndex) -> Vec<u8> {
+	let encoded = index.encode();
+	[
+		&twox_128(b"Referenda")[..],
+		&twox_128(b"ReferendumInfoFor")[..],
+		&blake2_128(&encoded)[..],
+		&encoded[..],
+	]
+	.concat()
+}
+
+fn number<T>(value: Option<&Value<T>>, name: &str) -> Result<u128> {
+	value
+		.and_then(|value| value.as_u128())
+		.ok_or_else(|| eyre!("Referendum field {name} is missing"))
+}
+
+fn block<T>(value: Option<&Value<T>>, name: &str) -> Result<u32> {
+	u32::try_from(number(value, name)?).wrap_err_with(|| format!("Invalid block number {name}"))
+}
+
+/// Returns the value of `Some`, or `None` for the decoded `Option`
+fn option<T>(value: Option<&Value<T>>) -> Option<&Value<T>> {
+	match value.map(|value| &value.value) {
+		Some(ValueDef::Variant(variant)) if variant.name == "Some" => {
+			value.and_then(|value| value.at(0))
+		},
+		_ => None,
+	}
+}
+
+/// Maps decoded `ReferendumInfo` value to the referendum status
+pub fn status_from_value<T>(value: &Value<T>) -> Result<ReferendumStatus> {
+	let ValueDef::Variant(variant) = &value.value else {
+		return Err(eyre!("Referendum info is not a variant"));
+	};
+	let since = || block(value.at(0), "since");
+	let status = match variant.name.as_str() {
+		"Ongoing" => {
+			let status = value.at(0);
+			let tally = status.at("tally");
+			let deciding = option(status.at("deciding"))
+				.map(|deciding| -> Result<Deciding> {
+					Ok(Deciding {
+						since: block(deciding.at("since"), "since")?,
+						confirming: option(deciding.at("confirming"))
+							.map(|confirming| block(Some(confirming), "confirming"))
+							.transpose()?,
+					})
+				})
+				.transpose()?;
+			ReferendumStatus::Ongoing {
+				track: u16::try_from(number(status.at("track"), "track")?)
+					.wrap_err("Invalid track")?,
+				submitted: block(status.at("submitted"), "submitted")?,
+				has_decision_deposit: option(status.at("decision_deposit")).is_some(),
+				deciding,
+				tally: Tally {
+					ayes: number(tally.at("ayes"), "ayes")?,
+					nays: number(tally.at("nays"), "nays")?,
+					support: number(tally.at("support"), "support")?,
+				},
+			}
+		},
+		"Approved" => ReferendumStatus::Approved { since: since()? },
+		"Rejected" => ReferendumStatus::Rejected { since: since()? },
+		"Cancelled" => ReferendumStatus::Cancelled { since: since()? },
+		"TimedOut" => ReferendumStatus::TimedOut { since: since()? },
+		"Killed" => ReferendumStatus::Killed { since: since()? },
+		name => return Err(eyre!("Unknown referendum status {name}")),
+	};
+	Ok(status)
+}
+
+/// Decodes `Referenda::ReferendumInfoFor` storage value, using the value type from the metadata
+pub fn decode_referendum_info(metadata: &Metadata, encoded: &[u8]) -> Result<ReferendumStatus> {
+	let entry = metadata
+		.pallet_by_name("Referenda")
+		.and_then(|pallet| pallet.storage())
+		.and_then(|storage| storage.entry_by_name("ReferendumInfoFor"))
+		.ok_or_else(|| eyre!("Storage Referenda.ReferendumInfoFor is not found in metadata"))?;
+	let StorageEntryType::Map { value_ty, .. } = entry.entry_type() else {
+		return Err(eyre!("Storage Referenda.ReferendumInfoFor is not a map"));
+	};
+	let value = dynamic::decode_as_type(&mut &encoded[..], *value_ty, metadata.types())
+		.map_err(|error| eyre!("Failed to decode referendum info: {error}"))?;
+	status_from_value(&value)
+}
+
+impl<T: Client> StateClient<T> {
+	/// Returns verified status of the referendum at the given block
+	pub async fn referendum(
+		&self,
+		metadata: &Metadata,
+		index: ReferendumIndex,
+		block_hash: H256,
+	) -> Result<Option<ReferendumStatus>> {
+		let value = self
+			.storage(referendum_info_storage_key(index), block_hash)
+			.await
+			.wrap_err("Failed to get referendum info")?;
+		value
+			.map(|encoded| decode_referendum_info(metadata, &encoded))
+			.transpose()
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReferendumEvent {
+	Submitted,
+	DecisionStarted,
+	ConfirmStarted,
+	ConfirmAborted,
+	TallyUpdated,
+	/// Referendum is no longer ongoing, see the status for the outcome
+	Concluded,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReferendumChange {
+	pub index: ReferendumIndex,
+	pub event: ReferendumEvent,
+	pub status: ReferendumStatus,
+}
+
+fn change_event(
+	previous: Option<&ReferendumStatus>,
+	current: &ReferendumStatus,
+) -> Option<ReferendumEvent> {
+	use ReferendumStatus::Ongoing;
+	match (previous, current) {
+		(Some(previous), current) if previous == current => None,
+		(_, current) if !current.is_ongoing() => Some(ReferendumEvent::Concluded),
+		(None, _) => Some(ReferendumEvent::Submitted),
+		(
+			Some(Ongoing {
+				deciding: before, ..
+			}),
+			Ongoing {
+				deciding: after, ..
+			},
+		) => {
+			let confirming =
+				|deciding: &Option<Deciding>| deciding.is_some_and(|d| d.confirming.is_some());
+			Some(match (before, after) {
+				(None, Some(_)) => ReferendumEvent::DecisionStarted,
+				_ if !confirming(before) && confirming(after) => ReferendumEvent::ConfirmStarted,
+				_ if confirming(before) && !confirming(after) => ReferendumEvent::ConfirmAborted,
+				_ => ReferendumEvent::TallyUpdated,
+			})
+		},
+		// Concluded referendum is never ongoing again
+		(Some(_), _) => None,
+	}
+}
+
+/// Tracks status of the referenda across blocks
+#[derive(Clone, Debug, Default)]
+pub struct ReferendumTracker {
+	referenda: HashMap<ReferendumIndex, ReferendumStatus>,
+}
+
+impl ReferendumTracker {
+	/// Updates status of the referendum, returning change since the previous update
+	pub fn update(
+		&mut self,
+		index: ReferendumIndex,
+		status: ReferendumStatus,
+	) -> Option<ReferendumChange> {
+		let event = change_event(self.referenda.get(&index), &status)?;
+		self.referenda.insert(index, status);
+		Some(ReferendumChange {
+			index,
+			event,
+			status,
+		})
+	}
+
+	pub fn status(&self, index: ReferendumIndex) -> Option<&ReferendumStatus> {
+		self.referenda.get(&index)
+	}
+
+	/// Referenda which are still ongoing, and need to be queried on the next block
+	pub fn ongoing(&self) -> impl Iterator<Item = ReferendumIndex> + '_ {
+		self.referenda
+			.iter()
+			.filter(|(_, status)| status.is_ongoing())
+			.map(|(index, _)| *index)
+	}
+
+	/// Forgets concluded referenda
+	pub fn prune(&mut self) {
+		self.referenda.retain(|_, status| status.is_ongoing());
+	}
+}
+
+/// Vote conviction, which multiplies voting power by locking the balance for longer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode)]
+pub enum Conviction {
+	None,
+	Locked1x,
+	Locked2x,
+	Locked3x,
+	Locked4x,
+	Locked5x,
+	Locked6x,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccountVote {
+	Standard {
+		aye: bool,
+		conviction: Conviction,
+		balance: u128,
+	},
+	Split {
+		aye: u128,
+		nay: u128,
+	},
+	SplitAbstain {
+		aye: u128,
+		nay: u128,
+		abstain: u128,
+	},
+}
+
+impl Encode for AccountVote {
+	fn encode_to<W: codec::Output + ?Sized>(&self, dest: &mut W) {
+		match *self {
+			AccountVote::Standard {
+				aye,
+				conviction,
+				balance,
+			} => {
+				dest.push_byte(0);
+				// Vote is encoded as a single byte, with aye flag in the highest bit
+				dest.push_byte(conviction as u8 | if aye { 0x80 } else { 0 });
+				balance.encode_to(dest);
+			},
+			AccountVote::Split { aye, nay } => {
+				dest.push_byte(1);
+				(aye, nay).encode_to(dest);
+			},
+			AccountVote::SplitAbstain { aye, nay, abstain } => {
+				dest.push_byte(2);
+				(aye, nay, abstain).encode_to(dest);
+			},
+		}
+	}
+}
+
+/// Indices of the conviction voting calls in the current runtime
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConvictionVotingCalls {
+	pub vote: CallIndex,
+	pub remove_vote: CallIndex,
+	pub delegate: CallIndex,
+	pub undelegate: CallIndex,
+}
+
+impl ConvictionVotingCalls {
+	pub fn from_metadata(metadata: &Metadata) -> Result<Self> {
+		let call = |name| CallIndex::from_metadata(metadata, "ConvictionVoting", name);
+		Ok(ConvictionVotingCalls {
+			vote: call("vote")?,
+			remove_vote: call("remove_vote")?,
+			delegate: call("delegate")?,
+			undelegate: call("undelegate")?,
+		})
+	}
+
+	pub fn vote(&self, index: ReferendumIndex, vote: AccountVote) -> Vec<u8> {
+		(self.vote, Compact(index), vote).encode()
+	}
+
+	/// Removes the vote, unlocking the balance once the conviction lock expires
+	pub fn remove_vote(&self, track: Option<TrackId>, index: ReferendumIndex) -> Vec<u8> {
+		(self.remove_vote, track, index).encode()
+	}
+
+	/// Delegates voting power on the track to another account
+	pub fn delegate(
+		&self,
+		track: TrackId,
+		to: &[u8; 32],
+		conviction: Conviction,
+		balance: u128,
+	) -> Vec<u8> {
+		(
+			self.delegate,
+			track,
+			(MULTI_ADDRESS_ID, to),
+			conviction,
+			balance,
+		)
+			.encode()
+	}
+
+	pub fn undelegate(&self, track: TrackId) -> Vec<u8> {
+		(self.undelegate, track).encode()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use test_case::test_case;
+
+	fn none() -> Value<()> {
+		Value::unnamed_variant("None", [])
+	}
+
+	fn some(value: Value<()>) -> Value<()> {
+		Value::unnamed_variant("Some", [value])
+	}
+
+	fn ongoing(deciding: Value<()>) -> Value<()> {
+		let status = Value::named_composite([
+			("track", Value::u128(1)),
+			("origin", Value::unnamed_variant("Origins", [])),
+			("submitted", Value::u128(100)),
+			("decision_deposit", some(Value::u128(10))),
+			("deciding", deciding),
+			(
+				"tally",
+				Value::named_composite([
+					("ayes", Value::u128(30)),
+					("nays", Value::u128(20)),
+					("support", Value::u128(25)),
+				]),
+			),
+			("in_queue", Value::bool(false)),
+		]);
+		Value::unnamed_variant("Ongoing", [status])
+	}
+
+	fn deciding(confirming: Value<()>) -> Value<()> {
+		some(Value::named_composite([
+			("since", Value::u128(110)),
+			("confirming", confirming),
+		]))
+	}
+
+	#[test]
+	fn decode_ongoing() {
+		let status = status_from_value(&ongoing(deciding(some(Value::u128(120))))).unwrap();
+		assert_eq!(
+			status,
+			ReferendumStatus::Ongoing {
+				track: 1,
+				submitted: 100,
+				has_decision_deposit: true,
+				deciding: Some(Deciding {
+					since: 110,
+					confirming: Some(120)
+				}),
+				tally: Tally {
+					ayes: 30,
+					nays: 20,
+					support: 25
+				},
+			}
+		);
+	}
+
+	#[test]
+	fn decode_concluded() {
+		let approved = Value::unnamed_variant("Approved", [Value::u128(200), none(), none()]);
+		assert_eq!(
+			status_from_value(&approved).unwrap(),
+			ReferendumStatus::Approved { since: 200 }
+		);
+		let unknown = Value::unnamed_variant("Unknown", []);
+		assert!(status_from_value(&unknown).is_err());
+	}
+
+	#[test]
+	fn track_changes() {
+		let mut tracker = ReferendumTracker::default();
+		let mut update = |value| {
+			let status = status_from_value(&value).unwrap();
+			tracker.update(0, status).map(|change| change.event)
+		};
+		assert_eq!(update(ongoing(none())), Some(ReferendumEvent::Submitted));
+		assert_eq!(update(ongoing(none())), None);
+		assert_eq!(
+			update(ongoing(deciding(none()))),
+			Some(ReferendumEvent::DecisionStarted)
+		);
+		assert_eq!(
+			update(ongoing(deciding(some(Value::u128(120))))),
+			Some(ReferendumEvent::ConfirmStarted)
+		);
+		assert_eq!(
+			update(ongoing(deciding(none()))),
+			Some(ReferendumEvent::ConfirmAborted)
+		);
+		let rejected = Value::unnamed_variant("Rejected", [Value::u128(200), none(), none()]);
+		assert_eq!(update(rejected), Some(ReferendumEvent::Concluded));
+
+		assert_eq!(tracker.ongoing().count(), 0);
+		tracker.prune();
+		assert_eq!(tracker.status(0), None);
+	}
+
+	#[test_case(AccountVote::Standard { aye: true, conviction: Conviction::Locked2x, balance: 1 } => vec![0, 0x82] ; "standard aye")]
+	#[test_case(AccountVote::Standard { aye: false, conviction: Conviction::None, balance: 1 } => vec![0, 0] ; "standard nay")]
+	#[test_case(AccountVote::Split { aye: 1, nay: 2 } => vec![1, 1] ; "split")]
+	fn encode_vote(vote: AccountVote) -> Vec<u8> {
+		vote.encode()[..2].to_vec()
+	}
+
+	#[test]
+	fn encode_calls() {
+		let calls = ConvictionVotingCalls {
+			vote: CallIndex(20, 0),
+			remove_vote: CallIndex(20, 4),
+			delegate: CallIndex(20, 1),
+			undelegate: CallIndex(20, 2),
+		};
+		let vote = AccountVote::Split { aye: 1, nay: 2 };
+		assert_eq!(calls.vote(5, vote)[..4], [20, 0, 20, 1]);
+		assert_eq!(
+			calls.remove_vote(Some(1), 5),
+			vec![20, 4, 1, 1, 0, 5, 0, 0, 0]
+		);
+		let delegate = calls.delegate(1, &[2; 32], Conviction::Locked1x, 3);
+		assert_eq!(delegate[..5], [20, 1, 1, 0, 0]);
+		assert_eq!(delegate[37], 1);
+		assert_eq!(delegate.len(), 38 + 16);
+	}
+}
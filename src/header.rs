@@ -0,0 +1,391 @@
+//! Zero-copy decoding of SCALE encoded headers and digests.
+//!
+//! [`HeaderRef`] and [`DigestItemSliceRef`] reference the input buffer instead of allocating a vector per digest
+//! payload. Header extension is kept encoded, and can be decoded on demand with [`HeaderRef::decode_extension`].
+//! Header hash can be calculated without decoding at all, using [`HeaderHash::hash_from_scale_encoded`].
+//! Decoded extension can be checked for internal consistency with [`consistency::check_extension`].
+//! Digest items of custom engines can be decoded into user types with [`registry::DigestRegistry`].
+//! Extensions of the legacy runtimes are decoded as well, and normalized with [`versioned::decode_extension_any`].
+//! Digests are kept owned without per-item allocations with [`digest::SmallDigest`], e.g. in the imported
+//! [`digest::SmallHeader`].
+//! Decoded headers which are looked up by hash repeatedly can keep their hash in [`cached::CachedHeader`].
+//! Digest items of the unknown variants are kept with their raw payload in [`OwnedDigestItem`], so they survive
+//! decoding into the owned digest.
+
+use avail_subxt::{
+	api::runtime_types::avail_core::header::extension::HeaderExtension,
+	config::substrate::DigestItem, primitives::Header, utils::H256,
+};
+use codec::{Compact, Decode, Encode, Output};
+use color_eyre::{eyre::eyre, Result};
+use sp_core::blake2_256;
+
+use crate::counters::{self, Counter};
+
+pub mod cached;
+pub mod consistency;
+pub mod digest;
+pub mod download;
+pub mod registry;
+pub mod versioned;
+
+const OTHER: u8 = 0;
+const CONSENSUS: u8 = 4;
+const SEAL: u8 = 5;
+const PRE_RUNTIME: u8 = 6;
+const RUNTIME_ENVIRONMENT_UPDATED: u8 = 8;
+
+pub trait HeaderHash: Encode {
+	/// Calculates header hash from SCALE encoded header, without decoding it.
+	fn hash_from_scale_encoded(encoded: &[u8]) -> H256;
+
+	/// Calculates hash of the decoded header, by encoding it first.
+	fn header_hash(&self) -> H256 {
+		self.using_encoded(Self::hash_from_scale_encoded)
+	}
+}
+
+impl HeaderHash for Header {
+	fn hash_from_scale_encoded(encoded: &[u8]) -> H256 {
+		counters::increment(Counter::HeadersHashed);
+		blake2_256(encoded).into()
+	}
+}
+
+pub(crate) fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+	if input.len() < len {
+		return Err(eyre!(
+			"Not enough data: expected {len} bytes, got {}",
+			input.len()
+		));
+	}
+	let (bytes, rest) = input.split_at(len);
+	*input = rest;
+	Ok(bytes)
+}
+
+pub(crate) fn take_array<'a, const N: usize>(input: &mut &'a [u8]) -> Result<&'a [u8; N]> {
+	Ok(take(input, N)?
+		.try_into()
+		.expect("Slice has the exact length"))
+}
+
+pub(crate) fn take_compact(input: &mut &[u8]) -> Result<u32> {
+	Ok(Compact::<u32>::decode(input)?.0)
+}
+
+pub(crate) fn take_bytes<'a>(input: &mut &'a [u8]) -> Result<&'a [u8]> {
+	let len = take_compact(input)? as usize;
+	take(input, len)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DigestItemRef<'a> {
+	PreRuntime(&'a [u8; 4], &'a [u8]),
+	Consensus(&'a [u8; 4], &'a [u8]),
+	Seal(&'a [u8; 4], &'a [u8]),
+	Other(&'a [u8]),
+	RuntimeEnvironmentUpdated,
+	/// Item of the variant unknown to this version, with the length prefixed payload (as all known variants with
+	/// payload have), so it can be skipped and re-encoded losslessly.
+	Unknown(u8, &'a [u8]),
+}
+
+impl<'a> DigestItemRef<'a> {
+	pub(crate) fn decode(input: &mut &'a [u8]) -> Result<Self> {
+		let [variant] = *take_array::<1>(input)?;
+		Ok(match variant {
+			PRE_RUNTIME => DigestItemRef::PreRuntime(take_array(input)?, take_bytes(input)?),
+			CONSENSUS => DigestItemRef::Consensus(take_array(input)?, take_bytes(input)?),
+			SEAL => DigestItemRef::Seal(take_array(input)?, take_bytes(input)?),
+			OTHER => DigestItemRef::Other(take_bytes(input)?),
+			RUNTIME_ENVIRONMENT_UPDATED => DigestItemRef::RuntimeEnvironmentUpdated,
+			_ => DigestItemRef::Unknown(variant, take_bytes(input)?),
+		})
+	}
+
+	pub fn is_unknown(&self) -> bool {
+		matches!(self, DigestItemRef::Unknown(..))
+	}
+
+	/// Converts to owned digest item, unknown items are kept with their raw payload.
+	pub fn to_owned(&self) -> OwnedDigestItem {
+		let item = match *self {
+			DigestItemRef::PreRuntime(id, data) => DigestItem::PreRuntime(*id, data.to_vec()),
+			DigestItemRef::Consensus(id, data) => DigestItem::Consensus(*id, data.to_vec()),
+			DigestItemRef::Seal(id, data) => DigestItem::Seal(*id, data.to_vec()),
+			DigestItemRef::Other(data) => DigestItem::Other(data.to_vec()),
+			DigestItemRef::RuntimeEnvironmentUpdated => DigestItem::RuntimeEnvironmentUpdated,
+			DigestItemRef::Unknown(variant, data) => {
+				return OwnedDigestItem::Unknown(variant, data.to_vec())
+			},
+		};
+		OwnedDigestItem::Known(item)
+	}
+
+	/// Encodes item, including the unknown ones, exactly as it was decoded.
+	pub fn encode(&self) -> Vec<u8> {
+		let (variant, id, data): (u8, Option<&[u8; 4]>, Option<&[u8]>) = match *self {
+			DigestItemRef::PreRuntime(id, data) => (PRE_RUNTIME, Some(id), Some(data)),
+			DigestItemRef::Consensus(id, data) => (CONSENSUS, Some(id), Some(data)),
+			DigestItemRef::Seal(id, data) => (SEAL, Some(id), Some(data)),
+			DigestItemRef::Other(data) => (OTHER, None, Some(data)),
+			DigestItemRef::RuntimeEnvironmentUpdated => (RUNTIME_ENVIRONMENT_UPDATED, None, None),
+			DigestItemRef::Unknown(variant, data) => (variant, None, Some(data)),
+		};
+		let mut encoded = vec![variant];
+		if let Some(id) = id {
+			encoded.extend_from_slice(id);
+		}
+		if let Some(data) = data {
+			data.encode_to(&mut encoded);
+		}
+		encoded
+	}
+}
+
+/// Owned digest item, with the same SCALE encoding as the [`DigestItemRef`] it was created from.
+/// [`DigestItem`] has no variant for the items unknown to this version, so they are kept with their raw payload.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OwnedDigestItem {
+	Known(DigestItem),
+	Unknown(u8, Vec<u8>),
+}
+
+impl OwnedDigestItem {
+	pub fn to_ref(&self) -> DigestItemRef<'_> {
+		match self {
+			OwnedDigestItem::Known(DigestItem::PreRuntime(id, data)) => {
+				DigestItemRef::PreRuntime(id, data)
+			},
+			OwnedDigestItem::Known(DigestItem::Consensus(id, data)) => {
+				DigestItemRef::Consensus(id, data)
+			},
+			OwnedDigestItem::Known(DigestItem::Seal(id, data)) => DigestItemRef::Seal(id, data),
+			OwnedDigestItem::Known(DigestItem::Other(data)) => DigestItemRef::Other(data),
+			OwnedDigestItem::Known(DigestItem::RuntimeEnvironmentUpdated) => {
+				DigestItemRef::RuntimeEnvironmentUpdated
+			},
+			OwnedDigestItem::Unknown(variant, data) => DigestItemRef::Unknown(*variant, data),
+		}
+	}
+
+	pub fn is_unknown(&self) -> bool {
+		matches!(self, OwnedDigestItem::Unknown(..))
+	}
+}
+
+impl Encode for OwnedDigestItem {
+	fn encode_to<T: Output + ?Sized>(&self, dest: &mut T) {
+		dest.write(&self.to_ref().encode());
+	}
+}
+
+impl From<DigestItem> for OwnedDigestItem {
+	fn from(item: DigestItem) -> Self {
+		OwnedDigestItem::Known(item)
+	}
+}
+
+impl TryFrom<OwnedDigestItem> for DigestItem {
+	type Error = color_eyre::Report;
+
+	fn try_from(item: OwnedDigestItem) -> Result<Self> {
+		match item {
+			OwnedDigestItem::Known(item) => Ok(item),
+			OwnedDigestItem::Unknown(variant, _) => {
+				Err(eyre!("Unknown digest item variant {variant}"))
+			},
+		}
+	}
+}
+
+/// Owned digest, which keeps the unknown items (see [`OwnedDigestItem`])
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode)]
+pub struct OwnedDigest {
+	pub logs: Vec<OwnedDigestItem>,
+}
+
+/// Encoded digest items, validated on creation and decoded lazily on iteration
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DigestItemSliceRef<'a> {
+	len: usize,
+	encoded: &'a [u8],
+}
+
+impl<'a> DigestItemSliceRef<'a> {
+	fn decode(input: &mut &'a [u8]) -> Result<Self> {
+		let len = take_compact(input)? as usize;
+		let start = *input;
+		for _ in 0..len {
+			DigestItemRef::decode(input)?;
+		}
+		let encoded = &start[..start.len() - input.len()];
+		Ok(DigestItemSliceRef { len, encoded })
+	}
+
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = DigestItemRef<'a>> {
+		let mut encoded = self.encoded;
+		// Items are validated on creation
+		(0..self.len)
+			.map(move |_| DigestItemRef::decode(&mut encoded).expect("Digest item is valid"))
+	}
+
+	/// Returns true if any of the items has variant unknown to this version
+	pub fn has_unknown(&self) -> bool {
+		self.iter().any(|item| item.is_unknown())
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeaderRef<'a> {
+	pub parent_hash: &'a [u8; 32],
+	pub number: u32,
+	pub state_root: &'a [u8; 32],
+	pub extrinsics_root: &'a [u8; 32],
+	pub digest: DigestItemSliceRef<'a>,
+	/// SCALE encoded header extension
+	pub extension: &'a [u8],
+	encoded: &'a [u8],
+}
+
+impl<'a> HeaderRef<'a> {
+	pub fn decode(encoded: &'a [u8]) -> Result<Self> {
+		let mut input = encoded;
+		let parent_hash = take_array(&mut input)?;
+		let number = take_compact(&mut input)?;
+		let state_root = take_array(&mut input)?;
+		let extrinsics_root = take_array(&mut input)?;
+		let digest = DigestItemSliceRef::decode(&mut input)?;
+		Ok(HeaderRef {
+			parent_hash,
+			number,
+			state_root,
+			extrinsics_root,
+			digest,
+			extension: input,
+			encoded,
+		})
+	}
+
+	pub fn hash(&self) -> H256 {
+		Header::hash_from_scale_encoded(self.encoded)
+	}
+
+	/// Decodes extension of any known version, normalized to the current layout
+	pub fn decode_extension(&self) -> Result<HeaderExtension> {
+		let (_, extension) = versioned::decode_extension_any(self.extension)?;
+		Ok(extension)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use avail_subxt::{
+		api::runtime_types::avail_core::{
+			data_lookup::compact::CompactDataLookup, header::extension::v3,
+			kate_commitment::v3::KateCommitment,
+		},
+		config::substrate::Digest,
+	};
+	use codec::Encode;
+
+	fn header() -> Header {
+		Header {
+			parent_hash: H256::repeat_byte(1),
+			number: 1_000_000,
+			state_root: H256::repeat_byte(2),
+			extrinsics_root: H256::repeat_byte(3),
+			digest: Digest {
+				logs: vec![
+					DigestItem::PreRuntime(*b"BABE", vec![1, 2, 3]),
+					DigestItem::Other(vec![4]),
+					DigestItem::RuntimeEnvironmentUpdated,
+					DigestItem::Seal(*b"BABE", vec![5; 64]),
+				],
+			},
+			extension: HeaderExtension::V3(v3::HeaderExtension {
+				commitment: KateCommitment {
+					rows: 1,
+					cols: 4,
+					data_root: H256::repeat_byte(4),
+					commitment: vec![6; 48],
+				},
+				app_lookup: CompactDataLookup {
+					size: 1,
+					index: vec![],
+				},
+			}),
+		}
+	}
+
+	#[test]
+	fn decode_header_ref() {
+		let header = header();
+		let encoded = header.encode();
+		let header_ref = HeaderRef::decode(&encoded).unwrap();
+
+		assert_eq!(header_ref.parent_hash, &header.parent_hash.0);
+		assert_eq!(header_ref.number, header.number);
+		assert_eq!(header_ref.state_root, &header.state_root.0);
+		assert_eq!(header_ref.extrinsics_root, &header.extrinsics_root.0);
+		assert_eq!(
+			header_ref
+				.digest
+				.iter()
+				.map(|item| DigestItem::try_from(item.to_owned()).unwrap())
+				.collect::<Vec<_>>(),
+			header.digest.logs
+		);
+		assert_eq!(
+			header_ref.decode_extension().unwrap().encode(),
+			header.extension.encode()
+		);
+		assert_eq!(
+			header_ref.hash(),
+			Encode::using_encoded(&header, blake2_256).into()
+		);
+	}
+
+	#[test]
+	fn decode_truncated_header() {
+		let encoded = header().encode();
+		assert!(HeaderRef::decode(&encoded[..100]).is_err());
+	}
+
+	#[test]
+	fn unknown_digest_item() {
+		let mut encoded = header().encode();
+		// Digest starts after parent hash, compact block number, state and extrinsics roots
+		let digest_start = 32 + Compact(1_000_000u32).encode().len() + 64;
+		let unknown = [vec![7], vec![1, 2, 3].encode()].concat();
+		// Digest has 4 items, and the unknown item is appended as 5th
+		encoded[digest_start] = Compact(5u32).encode()[0];
+		let items_end = digest_start + header().digest.encode().len();
+		encoded.splice(items_end..items_end, unknown.clone());
+
+		let header_ref = HeaderRef::decode(&encoded).unwrap();
+		assert!(header_ref.digest.has_unknown());
+		let item = header_ref.digest.iter().last().unwrap();
+		assert_eq!(item, DigestItemRef::Unknown(7, &[1, 2, 3]));
+		assert_eq!(item.encode(), unknown);
+		let owned = item.to_owned();
+		assert_eq!(owned, OwnedDigestItem::Unknown(7, vec![1, 2, 3]));
+		assert_eq!(owned.encode(), unknown);
+		assert!(DigestItem::try_from(owned).is_err());
+		assert!(header_ref.decode_extension().is_ok());
+
+		for (item, owned) in header_ref.digest.iter().zip(header().digest.logs) {
+			assert_eq!(item.encode(), owned.encode());
+		}
+	}
+}
@@ -0,0 +1,110 @@
+//! Header with the lazily cached block hash.
+//!
+//! Hash of the plain [`Header`] is calculated by encoding and hashing the whole header on each call. [`CachedHeader`]
+//! calculates it once, on the first [`CachedHeader::hash`] call, or at decode time from the raw bytes with
+//! [`CachedHeader::decode`], so headers which are looked up by hash repeatedly (e.g. during sync) are hashed once.
+//!
+//! # Notes
+//!
+//! Header is exposed only by reference, so the cached hash cannot get out of sync with the header. Header type is
+//! [`Header`] by default, other types have to have the same SCALE encoding (e.g. [`super::digest::SmallHeader`]).
+
+use avail_subxt::{primitives::Header, utils::H256};
+use codec::{Decode, Encode, Output};
+use color_eyre::{eyre::eyre, Result};
+use std::{ops::Deref, sync::OnceLock};
+
+use super::HeaderHash;
+
+#[derive(Clone, Debug)]
+pub struct CachedHeader<H = Header> {
+	header: H,
+	hash: OnceLock<H256>,
+}
+
+impl<H: Encode + Decode> CachedHeader<H> {
+	pub fn new(header: H) -> Self {
+		CachedHeader {
+			header,
+			hash: OnceLock::new(),
+		}
+	}
+
+	/// Decodes SCALE encoded header, and calculates its hash from the encoded bytes
+	pub fn decode(encoded: &[u8]) -> Result<Self> {
+		let mut input = encoded;
+		let header = H::decode(&mut input)?;
+		// Hash of the raw bytes matches the hash of the re-encoded header only if the whole input is decoded
+		if !input.is_empty() {
+			return Err(eyre!("Header has {} trailing bytes", input.len()));
+		}
+		Ok(CachedHeader {
+			header,
+			hash: OnceLock::from(Header::hash_from_scale_encoded(encoded)),
+		})
+	}
+
+	/// Returns block hash, which is calculated on the first call
+	pub fn hash(&self) -> H256 {
+		*self
+			.hash
+			.get_or_init(|| self.header.using_encoded(Header::hash_from_scale_encoded))
+	}
+
+	pub fn header(&self) -> &H {
+		&self.header
+	}
+
+	pub fn into_inner(self) -> H {
+		self.header
+	}
+}
+
+impl<H> Deref for CachedHeader<H> {
+	type Target = H;
+
+	fn deref(&self) -> &H {
+		&self.header
+	}
+}
+
+impl<H: Encode + Decode> From<H> for CachedHeader<H> {
+	fn from(header: H) -> Self {
+		CachedHeader::new(header)
+	}
+}
+
+impl<H: Encode> Encode for CachedHeader<H> {
+	fn size_hint(&self) -> usize {
+		self.header.size_hint()
+	}
+
+	fn encode_to<T: Output + ?Sized>(&self, dest: &mut T) {
+		self.header.encode_to(dest)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::simulation::header;
+
+	#[test]
+	fn hash_once() {
+		let header = header(1, H256::repeat_byte(1), 0);
+		let hash = header.header_hash();
+
+		let cached = CachedHeader::new(header.clone());
+		assert!(cached.hash.get().is_none());
+		assert_eq!(cached.hash(), hash);
+		assert_eq!(cached.hash.get(), Some(&hash));
+		assert_eq!(cached.clone().hash.get(), Some(&hash));
+
+		let encoded = header.encode();
+		let decoded = CachedHeader::<Header>::decode(&encoded).unwrap();
+		assert_eq!(decoded.hash.get(), Some(&hash));
+		assert_eq!(decoded.encode(), encoded);
+		assert_eq!(decoded.number, 1);
+		assert!(CachedHeader::<Header>::decode(&[encoded, vec![0]].concat()).is_err());
+	}
+}
@@ -0,0 +1,138 @@
+//! Internal consistency of the header extension.
+//!
+//! Header extension is checked before the header is imported into the fork tree, so malformed headers are rejected
+//! before sampling. Checks don't require any block data:
+//!
+//! * Matrix dimensions are powers of two, within the limits of the runtime, or `0x0` for blocks without data
+//! * Commitment contains one KZG commitment per extended row
+//! * Data root is not zero if the block contains application data
+//!
+//! Data root commits to the bridge messages as well, so blocks without application data can have non-zero data root.
+
+use avail_subxt::{
+	api::runtime_types::avail_core::header::extension::{v3, HeaderExtension},
+	utils::H256,
+};
+use std::fmt;
+
+/// Size of the single KZG commitment
+pub const COMMITMENT_SIZE: usize = 48;
+
+/// Matrix dimensions allowed by the runtime
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExtensionLimits {
+	pub max_rows: u16,
+	pub max_cols: u16,
+}
+
+impl Default for ExtensionLimits {
+	fn default() -> Self {
+		ExtensionLimits {
+			max_rows: 256,
+			max_cols: 256,
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExtensionError {
+	InvalidDimensions { rows: u16, cols: u16 },
+	CommitmentLength { length: usize, expected: usize },
+	MissingDataRoot,
+}
+
+impl fmt::Display for ExtensionError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ExtensionError::InvalidDimensions { rows, cols } => {
+				write!(f, "Invalid matrix dimensions {rows}x{cols}")
+			},
+			ExtensionError::CommitmentLength { length, expected } => {
+				write!(
+					f,
+					"Commitment length {length} doesn't match expected {expected}"
+				)
+			},
+			ExtensionError::MissingDataRoot => write!(f, "Block has app data, but no data root"),
+		}
+	}
+}
+
+impl std::error::Error for ExtensionError {}
+
+fn is_valid_dimension(value: u16, max: u16) -> bool {
+	value.is_power_of_two() && value <= max
+}
+
+/// Checks internal consistency of the header extension
+pub fn check_extension(
+	extension: &HeaderExtension,
+	limits: &ExtensionLimits,
+) -> Result<(), ExtensionError> {
+	let HeaderExtension::V3(v3::HeaderExtension {
+		commitment,
+		app_lookup,
+	}) = extension;
+
+	let (rows, cols) = (commitment.rows, commitment.cols);
+	let has_data = app_lookup.size > 0;
+	let is_empty_matrix = rows == 0 && cols == 0 && !has_data;
+	if !is_empty_matrix
+		&& (!is_valid_dimension(rows, limits.max_rows)
+			|| !is_valid_dimension(cols, limits.max_cols))
+	{
+		return Err(ExtensionError::InvalidDimensions { rows, cols });
+	}
+
+	// Rows are extended twice, and each extended row is committed to
+	let expected = rows as usize * 2 * COMMITMENT_SIZE;
+	let length = commitment.commitment.len();
+	if length != expected {
+		return Err(ExtensionError::CommitmentLength { length, expected });
+	}
+
+	if has_data && commitment.data_root == H256::zero() {
+		return Err(ExtensionError::MissingDataRoot);
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use avail_subxt::api::runtime_types::avail_core::{
+		data_lookup::compact::CompactDataLookup, kate_commitment::v3::KateCommitment,
+	};
+	use test_case::test_case;
+
+	fn extension(rows: u16, cols: u16, commitments: usize, size: u32, root: u8) -> HeaderExtension {
+		HeaderExtension::V3(v3::HeaderExtension {
+			commitment: KateCommitment {
+				rows,
+				cols,
+				data_root: H256::repeat_byte(root),
+				commitment: vec![0; commitments * COMMITMENT_SIZE],
+			},
+			app_lookup: CompactDataLookup {
+				size,
+				index: vec![],
+			},
+		})
+	}
+
+	#[test_case(extension(2, 4, 4, 3, 1) => Ok(()) ; "valid")]
+	#[test_case(extension(1, 4, 2, 0, 0) => Ok(()) ; "empty block")]
+	#[test_case(extension(3, 4, 6, 3, 1) => Err(ExtensionError::InvalidDimensions { rows: 3, cols: 4 }) ; "rows not power of two")]
+	#[test_case(extension(2, 512, 4, 3, 1) => Err(ExtensionError::InvalidDimensions { rows: 2, cols: 512 }) ; "cols above limit")]
+	#[test_case(extension(0, 4, 0, 0, 0) => Err(ExtensionError::InvalidDimensions { rows: 0, cols: 4 }) ; "zero rows")]
+	#[test_case(extension(2, 4, 2, 3, 1) => Err(ExtensionError::CommitmentLength { length: 96, expected: 192 }) ; "commitment too short")]
+	#[test_case(extension(2, 4, 4, 3, 0) => Err(ExtensionError::MissingDataRoot) ; "missing data root")]
+	#[test_case(extension(0, 0, 0, 0, 0) => Ok(()) ; "empty matrix")]
+	#[test_case(extension(0, 0, 0, 0, 1) => Ok(()) ; "empty matrix with bridge data root")]
+	#[test_case(extension(1, 4, 2, 0, 1) => Ok(()) ; "bridge only data root")]
+	#[test_case(extension(0, 0, 0, 3, 1) => Err(ExtensionError::InvalidDimensions { rows: 0, cols: 0 }) ; "empty matrix with app data")]
+	#[test_case(extension(0, 0, 2, 0, 0) => Err(ExtensionError::CommitmentLength { length: 96, expected: 0 }) ; "empty matrix with commitments")]
+	fn check(extension: HeaderExtension) -> Result<(), ExtensionError> {
+		check_extension(&extension, &ExtensionLimits::default())
+	}
+}
@@ -0,0 +1,398 @@
+//! Owned digest with small-size optimized storage, for the bulk import of headers.
+//!
+//! [`SmallDigest`] mirrors [`Digest`] (and [`SmallDigestItem`] mirrors [`DigestItem`]), and has the same SCALE
+//! encoding, but keeps up to [`INLINE_LOGS`] items and payloads up to [`INLINE_PAYLOAD`] bytes inline. Most headers
+//! have two or three digest items with short payloads (e.g. BABE pre-runtime digest and seal), so their digests are
+//! decoded without any heap allocation. Items of the unknown variants are kept with their raw payload (as
+//! [`DigestItemRef::Unknown`]), so headers of the newer runtimes are imported and hashed losslessly.
+//!
+//! [`SmallHeader`] is the [`Header`] with the small digest, used for the headers imported by the
+//! [`crate::sync_machine::SyncMachine`].
+
+use avail_subxt::{
+	api::runtime_types::avail_core::header::extension::HeaderExtension,
+	config::substrate::{Digest, DigestItem},
+	primitives::Header,
+	utils::H256,
+};
+use codec::{Compact, Decode, Encode, Input, Output};
+use color_eyre::{eyre::eyre, Result};
+use smallvec::SmallVec;
+
+use super::{
+	DigestItemRef, DigestItemSliceRef, CONSENSUS, OTHER, PRE_RUNTIME, RUNTIME_ENVIRONMENT_UPDATED,
+	SEAL,
+};
+
+/// Number of digest items stored inline
+pub const INLINE_LOGS: usize = 4;
+/// Length of the digest item payload stored inline, fits the BABE primary pre-digest (109 bytes) and the seal
+pub const INLINE_PAYLOAD: usize = 128;
+
+pub type Payload = SmallVec<[u8; INLINE_PAYLOAD]>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SmallDigestItem {
+	PreRuntime([u8; 4], Payload),
+	Consensus([u8; 4], Payload),
+	Seal([u8; 4], Payload),
+	Other(Payload),
+	RuntimeEnvironmentUpdated,
+	/// Item of the variant unknown to this version, with the length prefixed payload
+	Unknown(u8, Payload),
+}
+
+impl SmallDigestItem {
+	/// Returns borrowed view of the item, e.g. for the digest registry
+	pub fn to_ref(&self) -> DigestItemRef<'_> {
+		match self {
+			SmallDigestItem::PreRuntime(id, data) => DigestItemRef::PreRuntime(id, data),
+			SmallDigestItem::Consensus(id, data) => DigestItemRef::Consensus(id, data),
+			SmallDigestItem::Seal(id, data) => DigestItemRef::Seal(id, data),
+			SmallDigestItem::Other(data) => DigestItemRef::Other(data),
+			SmallDigestItem::RuntimeEnvironmentUpdated => DigestItemRef::RuntimeEnvironmentUpdated,
+			SmallDigestItem::Unknown(variant, data) => DigestItemRef::Unknown(*variant, data),
+		}
+	}
+
+	pub fn is_unknown(&self) -> bool {
+		matches!(self, SmallDigestItem::Unknown(..))
+	}
+}
+
+impl Encode for SmallDigestItem {
+	fn size_hint(&self) -> usize {
+		match self {
+			SmallDigestItem::PreRuntime(_, data)
+			| SmallDigestItem::Consensus(_, data)
+			| SmallDigestItem::Seal(_, data) => 1 + 4 + payload_size(data),
+			SmallDigestItem::Other(data) | SmallDigestItem::Unknown(_, data) => {
+				1 + payload_size(data)
+			},
+			SmallDigestItem::RuntimeEnvironmentUpdated => 1,
+		}
+	}
+
+	fn encode_to<T: Output + ?Sized>(&self, dest: &mut T) {
+		let (variant, id, data): (u8, Option<&[u8; 4]>, Option<&[u8]>) = match self {
+			SmallDigestItem::PreRuntime(id, data) => (PRE_RUNTIME, Some(id), Some(&data[..])),
+			SmallDigestItem::Consensus(id, data) => (CONSENSUS, Some(id), Some(&data[..])),
+			SmallDigestItem::Seal(id, data) => (SEAL, Some(id), Some(&data[..])),
+			SmallDigestItem::Other(data) => (OTHER, None, Some(&data[..])),
+			SmallDigestItem::RuntimeEnvironmentUpdated => (RUNTIME_ENVIRONMENT_UPDATED, None, None),
+			SmallDigestItem::Unknown(variant, data) => (*variant, None, Some(&data[..])),
+		};
+		dest.push_byte(variant);
+		if let Some(id) = id {
+			dest.write(id);
+		}
+		if let Some(data) = data {
+			data.encode_to(dest);
+		}
+	}
+}
+
+fn payload_size(payload: &Payload) -> usize {
+	Compact(payload.len() as u32).size_hint() + payload.len()
+}
+
+fn decode_payload<I: Input>(input: &mut I) -> Result<Payload, codec::Error> {
+	let len = Compact::<u32>::decode(input)?.0 as usize;
+	// Length prefix is checked before the allocation, so malicious prefixes cannot trigger large allocations
+	if input
+		.remaining_len()?
+		.is_some_and(|remaining| remaining < len)
+	{
+		return Err("Not enough data for digest item payload".into());
+	}
+	let mut payload = Payload::from_elem(0, len);
+	input.read(&mut payload)?;
+	Ok(payload)
+}
+
+impl Decode for SmallDigestItem {
+	fn decode<I: Input>(input: &mut I) -> Result<Self, codec::Error> {
+		let variant = input.read_byte()?;
+		Ok(match variant {
+			PRE_RUNTIME => {
+				SmallDigestItem::PreRuntime(Decode::decode(input)?, decode_payload(input)?)
+			},
+			CONSENSUS => SmallDigestItem::Consensus(Decode::decode(input)?, decode_payload(input)?),
+			SEAL => SmallDigestItem::Seal(Decode::decode(input)?, decode_payload(input)?),
+			OTHER => SmallDigestItem::Other(decode_payload(input)?),
+			RUNTIME_ENVIRONMENT_UPDATED => SmallDigestItem::RuntimeEnvironmentUpdated,
+			_ => SmallDigestItem::Unknown(variant, decode_payload(input)?),
+		})
+	}
+}
+
+impl<'a> From<DigestItemRef<'a>> for SmallDigestItem {
+	fn from(item: DigestItemRef<'a>) -> Self {
+		match item {
+			DigestItemRef::PreRuntime(id, data) => {
+				SmallDigestItem::PreRuntime(*id, Payload::from_slice(data))
+			},
+			DigestItemRef::Consensus(id, data) => {
+				SmallDigestItem::Consensus(*id, Payload::from_slice(data))
+			},
+			DigestItemRef::Seal(id, data) => SmallDigestItem::Seal(*id, Payload::from_slice(data)),
+			DigestItemRef::Other(data) => SmallDigestItem::Other(Payload::from_slice(data)),
+			DigestItemRef::RuntimeEnvironmentUpdated => SmallDigestItem::RuntimeEnvironmentUpdated,
+			DigestItemRef::Unknown(variant, data) => {
+				SmallDigestItem::Unknown(variant, Payload::from_slice(data))
+			},
+		}
+	}
+}
+
+impl From<&DigestItem> for SmallDigestItem {
+	fn from(item: &DigestItem) -> Self {
+		match item {
+			DigestItem::PreRuntime(id, data) => {
+				SmallDigestItem::PreRuntime(*id, Payload::from_slice(data))
+			},
+			DigestItem::Consensus(id, data) => {
+				SmallDigestItem::Consensus(*id, Payload::from_slice(data))
+			},
+			DigestItem::Seal(id, data) => SmallDigestItem::Seal(*id, Payload::from_slice(data)),
+			DigestItem::Other(data) => SmallDigestItem::Other(Payload::from_slice(data)),
+			DigestItem::RuntimeEnvironmentUpdated => SmallDigestItem::RuntimeEnvironmentUpdated,
+		}
+	}
+}
+
+impl TryFrom<SmallDigestItem> for DigestItem {
+	type Error = color_eyre::Report;
+
+	/// Unknown items cannot be represented by the [`DigestItem`]
+	fn try_from(item: SmallDigestItem) -> Result<Self> {
+		Ok(match item {
+			SmallDigestItem::PreRuntime(id, data) => DigestItem::PreRuntime(id, data.into_vec()),
+			SmallDigestItem::Consensus(id, data) => DigestItem::Consensus(id, data.into_vec()),
+			SmallDigestItem::Seal(id, data) => DigestItem::Seal(id, data.into_vec()),
+			SmallDigestItem::Other(data) => DigestItem::Other(data.into_vec()),
+			SmallDigestItem::RuntimeEnvironmentUpdated => DigestItem::RuntimeEnvironmentUpdated,
+			SmallDigestItem::Unknown(variant, _) => {
+				return Err(eyre!("Unknown digest item variant {variant}"))
+			},
+		})
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SmallDigest {
+	pub logs: SmallVec<[SmallDigestItem; INLINE_LOGS]>,
+}
+
+impl SmallDigest {
+	/// Checks if any of the items or payloads didn't fit into the inline storage
+	pub fn spilled(&self) -> bool {
+		self.logs.spilled()
+			|| self.logs.iter().any(|item| match item {
+				SmallDigestItem::PreRuntime(_, data)
+				| SmallDigestItem::Consensus(_, data)
+				| SmallDigestItem::Seal(_, data)
+				| SmallDigestItem::Other(data)
+				| SmallDigestItem::Unknown(_, data) => data.spilled(),
+				SmallDigestItem::RuntimeEnvironmentUpdated => false,
+			})
+	}
+}
+
+impl Encode for SmallDigest {
+	fn size_hint(&self) -> usize {
+		Compact(self.logs.len() as u32).size_hint()
+			+ self.logs.iter().map(Encode::size_hint).sum::<usize>()
+	}
+
+	fn encode_to<T: Output + ?Sized>(&self, dest: &mut T) {
+		Compact(self.logs.len() as u32).encode_to(dest);
+		for item in &self.logs {
+			item.encode_to(dest);
+		}
+	}
+}
+
+impl Decode for SmallDigest {
+	fn decode<I: Input>(input: &mut I) -> Result<Self, codec::Error> {
+		let len = Compact::<u32>::decode(input)?.0;
+		let mut logs = SmallVec::new();
+		// Number of items is not trusted, so the capacity is not reserved upfront
+		for _ in 0..len {
+			logs.push(SmallDigestItem::decode(input)?);
+		}
+		Ok(SmallDigest { logs })
+	}
+}
+
+impl<'a> From<DigestItemSliceRef<'a>> for SmallDigest {
+	fn from(digest: DigestItemSliceRef<'a>) -> Self {
+		SmallDigest {
+			logs: digest.iter().map(SmallDigestItem::from).collect(),
+		}
+	}
+}
+
+impl From<&Digest> for SmallDigest {
+	fn from(digest: &Digest) -> Self {
+		SmallDigest {
+			logs: digest.logs.iter().map(SmallDigestItem::from).collect(),
+		}
+	}
+}
+
+impl TryFrom<SmallDigest> for Digest {
+	type Error = color_eyre::Report;
+
+	fn try_from(digest: SmallDigest) -> Result<Self> {
+		let logs = digest
+			.logs
+			.into_iter()
+			.map(DigestItem::try_from)
+			.collect::<Result<_>>()?;
+		Ok(Digest { logs })
+	}
+}
+
+/// Header with the [`SmallDigest`], same SCALE encoding as the [`Header`]
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct SmallHeader {
+	pub parent_hash: H256,
+	#[codec(compact)]
+	pub number: u32,
+	pub state_root: H256,
+	pub extrinsics_root: H256,
+	pub digest: SmallDigest,
+	pub extension: HeaderExtension,
+}
+
+impl From<&Header> for SmallHeader {
+	fn from(header: &Header) -> Self {
+		SmallHeader {
+			parent_hash: header.parent_hash,
+			number: header.number,
+			state_root: header.state_root,
+			extrinsics_root: header.extrinsics_root,
+			digest: SmallDigest::from(&header.digest),
+			extension: header.extension.clone(),
+		}
+	}
+}
+
+impl TryFrom<SmallHeader> for Header {
+	type Error = color_eyre::Report;
+
+	fn try_from(header: SmallHeader) -> Result<Self> {
+		Ok(Header {
+			parent_hash: header.parent_hash,
+			number: header.number,
+			state_root: header.state_root,
+			extrinsics_root: header.extrinsics_root,
+			digest: header.digest.try_into()?,
+			extension: header.extension,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		fork_choice::{BlockInfo, ForkTree, LongestChain},
+		header::{cached::CachedHeader, consistency::ExtensionLimits, HeaderHash},
+		simulation::header,
+	};
+
+	fn digest() -> Digest {
+		Digest {
+			logs: vec![
+				// BABE primary pre-digest: authority index, slot, VRF output and proof
+				DigestItem::PreRuntime(*b"BABE", vec![1; 109]),
+				DigestItem::Consensus(*b"FRNK", vec![]),
+				DigestItem::Seal(*b"BABE", vec![5; 64]),
+			],
+		}
+	}
+
+	#[test]
+	fn same_encoding_as_digest() {
+		let digest = digest();
+		let encoded = digest.encode();
+		let small = SmallDigest::decode(&mut &encoded[..]).unwrap();
+		assert!(!small.spilled());
+		assert_eq!(small.encode(), encoded);
+		assert_eq!(small.size_hint(), encoded.len());
+		assert_eq!(small, SmallDigest::from(&digest));
+		assert_eq!(Digest::try_from(small).unwrap().logs, digest.logs);
+
+		let items = &encoded[1..];
+		let slice = DigestItemSliceRef {
+			len: 3,
+			encoded: items,
+		};
+		let from_ref = SmallDigest::from(slice);
+		assert_eq!(from_ref.logs[2].to_ref(), slice.iter().last().unwrap());
+	}
+
+	#[test]
+	fn spill_large_digests() {
+		let mut digest = digest();
+		digest
+			.logs
+			.push(DigestItem::Other(vec![4; INLINE_PAYLOAD + 1]));
+		let small = SmallDigest::from(&digest);
+		assert!(small.spilled());
+		assert_eq!(small.encode(), digest.encode());
+
+		// Payload length prefix exceeds the input
+		let mut encoded = DigestItem::Other(vec![]).encode();
+		encoded[1] = Compact(63u32).encode()[0];
+		assert!(SmallDigestItem::decode(&mut &encoded[..]).is_err());
+	}
+
+	#[test]
+	fn same_encoding_as_header() {
+		let mut header = header(1, H256::repeat_byte(1), 0);
+		header.digest = digest();
+		let encoded = header.encode();
+		let small = SmallHeader::decode(&mut &encoded[..]).unwrap();
+		assert!(!small.digest.spilled());
+		assert_eq!(small.encode(), encoded);
+		assert_eq!(SmallHeader::from(&header).encode(), encoded);
+		assert_eq!(Header::try_from(small).unwrap().encode(), encoded);
+	}
+
+	#[test]
+	fn import_header_with_unknown_item() {
+		let mut header = header(1, H256::repeat_byte(1), 0);
+		header.digest = digest();
+		let mut small = SmallHeader::from(&header);
+		let unknown = SmallDigestItem::Unknown(7, Payload::from_slice(&[1, 2, 3]));
+		small.digest.logs.push(unknown.clone());
+		let encoded = small.encode();
+		assert_eq!(small.size_hint(), encoded.len());
+
+		let cached = CachedHeader::<SmallHeader>::decode(&encoded).unwrap();
+		assert_eq!(cached.digest.logs[3], unknown);
+		assert_eq!(
+			cached.digest.logs[3].to_ref(),
+			DigestItemRef::Unknown(7, &[1, 2, 3])
+		);
+		assert_eq!(cached.encode(), encoded);
+		assert!(Header::try_from(cached.header().clone()).is_err());
+
+		let hash = Header::hash_from_scale_encoded(&encoded);
+		let finalized = BlockInfo {
+			hash: header.parent_hash,
+			number: 0,
+			parent_hash: H256::zero(),
+			confidence: None,
+		};
+		let mut tree = ForkTree::new(finalized, Box::new(LongestChain));
+		let imported = tree
+			.import_cached_header(&cached, &ExtensionLimits::default())
+			.unwrap();
+		assert_eq!(imported, hash);
+		assert_eq!(tree.best_block().unwrap().hash, hash);
+	}
+}
@@ -0,0 +1,479 @@
+//! Parallel download of the header ranges, with batch sizes adapted to the peers.
+//!
+//! [`Downloader`] keeps the queue of the missing header ranges, and assigns them to the idle peers in batches. Each
+//! peer has at most one range in flight, so ranges are downloaded from all peers in parallel. Batch size of the peer
+//! is adapted after each response, so the response takes about [`TARGET_RESPONSE_TIME`] at the measured throughput.
+//!
+//! # Notes
+//!
+//! * Ranges which time out, fail, or are invalid are queued again and assigned to another peer, up to
+//!   [`MAX_REQUEST_ATTEMPTS`] times
+//! * Partial responses are accepted, and the rest of the range is queued again
+//! * Peers with lower response times are preferred, and peers which failed the range are excluded for that range
+//!
+//! Like [`crate::sync_machine::SyncMachine`], downloader doesn't perform any IO, and doesn't read the clock.
+
+use libp2p::PeerId;
+use std::{
+	collections::{BTreeMap, HashMap, HashSet},
+	time::{Duration, Instant},
+};
+use tracing::debug;
+
+use super::{cached::CachedHeader, digest::SmallHeader};
+use crate::sync_machine::{RequestId, MAX_REQUEST_ATTEMPTS, REQUEST_TIMEOUT};
+
+/// Response time towards which the batch sizes are adapted
+pub const TARGET_RESPONSE_TIME: Duration = Duration::from_secs(2);
+pub const INITIAL_BATCH_SIZE: u32 = 64;
+pub const MIN_BATCH_SIZE: u32 = 8;
+pub const MAX_BATCH_SIZE: u32 = 512;
+/// Weight of the latest sample in the moving averages
+const SMOOTHING: f64 = 0.3;
+
+/// Request of the consecutive headers, starting with the block number `from`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RangeRequest {
+	pub request_id: RequestId,
+	pub peer_id: PeerId,
+	pub from: u32,
+	pub count: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeerStats {
+	pub batch_size: u32,
+	/// Moving average of the response time
+	pub latency: Option<Duration>,
+	/// Moving average of the headers received per second
+	pub throughput: Option<f64>,
+}
+
+impl Default for PeerStats {
+	fn default() -> Self {
+		PeerStats {
+			batch_size: INITIAL_BATCH_SIZE,
+			latency: None,
+			throughput: None,
+		}
+	}
+}
+
+fn average(current: Option<f64>, sample: f64) -> f64 {
+	current.map_or(sample, |current| current + SMOOTHING * (sample - current))
+}
+
+impl PeerStats {
+	/// Updates averages with the response, and adapts batch size to the throughput
+	fn update(&mut self, elapsed: Duration, received: u32) {
+		let elapsed = elapsed.max(Duration::from_millis(1));
+		let latency = average(self.latency.map(|l| l.as_secs_f64()), elapsed.as_secs_f64());
+		let throughput = average(self.throughput, received as f64 / elapsed.as_secs_f64());
+		self.latency = Some(Duration::from_secs_f64(latency));
+		self.throughput = Some(throughput);
+		// Batch size changes at most twice per response, so single outliers don't dominate
+		let target = (throughput * TARGET_RESPONSE_TIME.as_secs_f64()) as u32;
+		self.batch_size = target
+			.clamp(self.batch_size / 2, self.batch_size.saturating_mul(2))
+			.clamp(MIN_BATCH_SIZE, MAX_BATCH_SIZE);
+	}
+
+	fn timed_out(&mut self) {
+		self.batch_size = (self.batch_size / 2).max(MIN_BATCH_SIZE);
+	}
+}
+
+#[derive(Clone, Debug)]
+struct Range {
+	from: u32,
+	/// Last block number of the range, inclusive
+	to: u32,
+	attempts: u32,
+	/// Peers which failed to deliver the range
+	failed: HashSet<PeerId>,
+}
+
+impl Range {
+	fn new(from: u32, to: u32) -> Self {
+		Range {
+			from,
+			to,
+			attempts: 0,
+			failed: HashSet::new(),
+		}
+	}
+
+	fn contains(&self, number: u32) -> bool {
+		(self.from..=self.to).contains(&number)
+	}
+
+	fn count(&self) -> u32 {
+		self.to - self.from + 1
+	}
+}
+
+struct InFlight {
+	peer_id: PeerId,
+	range: Range,
+	sent: Instant,
+}
+
+/// Checks that headers are consecutive blocks of the range, linked by the parent hashes
+fn is_valid(range: &Range, headers: &[CachedHeader<SmallHeader>]) -> bool {
+	let linked = headers
+		.windows(2)
+		.all(|pair| pair[1].parent_hash == pair[0].hash());
+	let numbered = headers
+		.iter()
+		.zip(range.from..)
+		.all(|(header, number)| header.number == number);
+	!headers.is_empty() && headers.len() as u32 <= range.count() && numbered && linked
+}
+
+#[derive(Default)]
+pub struct Downloader {
+	stats: HashMap<PeerId, PeerStats>,
+	/// Ranges which are not assigned, by the first block number
+	queue: BTreeMap<u32, Range>,
+	in_flight: HashMap<RequestId, InFlight>,
+}
+
+impl Downloader {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn stats(&self, peer_id: &PeerId) -> Option<&PeerStats> {
+		self.stats.get(peer_id)
+	}
+
+	pub fn in_flight(&self) -> usize {
+		self.in_flight.len()
+	}
+
+	pub fn peer(&self, request_id: RequestId) -> Option<PeerId> {
+		self.in_flight
+			.get(&request_id)
+			.map(|request| request.peer_id)
+	}
+
+	fn ranges(&self) -> impl Iterator<Item = &Range> {
+		let in_flight = self.in_flight.values().map(|request| &request.range);
+		self.queue.values().chain(in_flight)
+	}
+
+	/// Checks if the block is queued or in flight
+	pub fn is_pending(&self, number: u32) -> bool {
+		self.ranges().any(|range| range.contains(number))
+	}
+
+	/// Queues the blocks of the range (inclusive) which are not already pending
+	pub fn queue(&mut self, from: u32, to: u32) {
+		let mut pending = self
+			.ranges()
+			.map(|range| (range.from, range.to))
+			.collect::<Vec<_>>();
+		pending.sort_unstable();
+		let mut next = from;
+		for (start, end) in pending {
+			if next > to || start > to {
+				break;
+			}
+			if start > next {
+				self.queue.insert(next, Range::new(next, start - 1));
+			}
+			next = next.max(end.saturating_add(1));
+		}
+		if next <= to {
+			self.queue.insert(next, Range::new(next, to));
+		}
+	}
+
+	/// Drops queued blocks up to the finalized block number
+	pub fn prune(&mut self, finalized_number: u32) {
+		let queue = std::mem::take(&mut self.queue);
+		for (_, mut range) in queue {
+			if range.to <= finalized_number {
+				continue;
+			}
+			range.from = range.from.max(finalized_number + 1);
+			self.queue.insert(range.from, range);
+		}
+	}
+
+	/// Assigns queued ranges up to the `window_end` block to the idle peers, with the peers' batch sizes
+	pub fn assign(
+		&mut self,
+		now: Instant,
+		peers: &HashMap<PeerId, u32>,
+		window_end: u32,
+		next_request_id: &mut RequestId,
+	) -> Vec<RangeRequest> {
+		let busy = self
+			.in_flight
+			.values()
+			.map(|request| request.peer_id)
+			.collect::<HashSet<_>>();
+		let mut idle = peers
+			.iter()
+			.filter(|(peer_id, _)| !busy.contains(peer_id))
+			.map(|(peer_id, best_number)| (*peer_id, *best_number))
+			.collect::<Vec<_>>();
+		// Peers with the lowest response times first, then unmeasured peers, by peer ID for the deterministic result
+		let latency = |peer_id: &PeerId| self.stats.get(peer_id).and_then(|stats| stats.latency);
+		idle.sort_by_key(|(peer_id, _)| (latency(peer_id).is_none(), latency(peer_id), *peer_id));
+
+		let mut requests = vec![];
+		for (peer_id, best_number) in idle {
+			let last = best_number.min(window_end);
+			let Some(from) = self
+				.queue
+				.values()
+				.find(|range| range.from <= last && !range.failed.contains(&peer_id))
+				.map(|range| range.from)
+			else {
+				continue;
+			};
+			let mut range = self.queue.remove(&from).expect("Range is queued");
+			let batch_size = self.stats.entry(peer_id).or_default().batch_size;
+			let to = range.to.min(last).min(from.saturating_add(batch_size - 1));
+			if to < range.to {
+				let rest = Range {
+					from: to + 1,
+					..range.clone()
+				};
+				self.queue.insert(rest.from, rest);
+			}
+			range.to = to;
+
+			let request_id = *next_request_id;
+			*next_request_id += 1;
+			requests.push(RangeRequest {
+				request_id,
+				peer_id,
+				from,
+				count: range.count(),
+			});
+			let request = InFlight {
+				peer_id,
+				range,
+				sent: now,
+			};
+			self.in_flight.insert(request_id, request);
+		}
+		requests
+	}
+
+	/// Completes the request, returning accepted headers, or none if the response is invalid
+	pub fn complete(
+		&mut self,
+		now: Instant,
+		request_id: RequestId,
+		headers: Vec<CachedHeader<SmallHeader>>,
+		peers: &HashMap<PeerId, u32>,
+	) -> Vec<CachedHeader<SmallHeader>> {
+		let Some(request) = self.in_flight.remove(&request_id) else {
+			return vec![];
+		};
+		if !is_valid(&request.range, &headers) {
+			debug!(peer_id = %request.peer_id, from = request.range.from, "Invalid header range response");
+			self.requeue(request.range, request.peer_id, peers);
+			return vec![];
+		}
+		let received = headers.len() as u32;
+		self.stats
+			.entry(request.peer_id)
+			.or_default()
+			.update(now.saturating_duration_since(request.sent), received);
+		if received < request.range.count() {
+			let rest = Range {
+				from: request.range.from + received,
+				..request.range
+			};
+			self.queue.insert(rest.from, rest);
+		}
+		headers
+	}
+
+	/// Queues the failed range again, unless it failed too many times or no other peer can deliver it
+	fn requeue(&mut self, mut range: Range, peer_id: PeerId, peers: &HashMap<PeerId, u32>) {
+		range.attempts += 1;
+		range.failed.insert(peer_id);
+		let available = peers.iter().any(|(peer_id, best_number)| {
+			*best_number >= range.from && !range.failed.contains(peer_id)
+		});
+		if range.attempts >= MAX_REQUEST_ATTEMPTS || !available {
+			debug!(
+				from = range.from,
+				to = range.to,
+				"Header range request failed"
+			);
+			return;
+		}
+		self.queue.insert(range.from, range);
+	}
+
+	pub fn fail(&mut self, request_id: RequestId, peers: &HashMap<PeerId, u32>) {
+		if let Some(request) = self.in_flight.remove(&request_id) {
+			self.requeue(request.range, request.peer_id, peers);
+		}
+	}
+
+	/// Reassigns ranges of the timed out requests, and reduces batch sizes of their peers
+	pub fn expire(&mut self, now: Instant, peers: &HashMap<PeerId, u32>) {
+		let mut expired = self
+			.in_flight
+			.iter()
+			.filter(|(_, request)| request.sent + REQUEST_TIMEOUT <= now)
+			.map(|(request_id, _)| *request_id)
+			.collect::<Vec<_>>();
+		expired.sort_unstable();
+		for request_id in expired {
+			let request = self
+				.in_flight
+				.remove(&request_id)
+				.expect("Request is in flight");
+			if let Some(stats) = self.stats.get_mut(&request.peer_id) {
+				stats.timed_out();
+			}
+			self.requeue(request.range, request.peer_id, peers);
+		}
+	}
+
+	/// Reassigns ranges of the disconnected peer
+	pub fn remove_peer(&mut self, peer_id: &PeerId, peers: &HashMap<PeerId, u32>) {
+		self.stats.remove(peer_id);
+		let mut failed = self
+			.in_flight
+			.iter()
+			.filter(|(_, request)| request.peer_id == *peer_id)
+			.map(|(request_id, _)| *request_id)
+			.collect::<Vec<_>>();
+		failed.sort_unstable();
+		for request_id in failed {
+			self.fail(request_id, peers);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::simulation::{chain, header};
+	use avail_subxt::utils::H256;
+
+	fn peers(count: usize, best_number: u32) -> HashMap<PeerId, u32> {
+		(0..count)
+			.map(|_| (PeerId::random(), best_number))
+			.collect()
+	}
+
+	fn ranges(requests: &[RangeRequest]) -> Vec<(u32, u32)> {
+		let mut ranges = requests
+			.iter()
+			.map(|request| (request.from, request.count))
+			.collect::<Vec<_>>();
+		ranges.sort_unstable();
+		ranges
+	}
+
+	/// Chain from genesis with the given number of blocks, excluding genesis
+	fn cached_chain(len: u32) -> Vec<CachedHeader<SmallHeader>> {
+		chain(&header(0, H256::zero(), 0), len, 0)
+			.iter()
+			.map(|header| CachedHeader::new(SmallHeader::from(header)))
+			.collect()
+	}
+
+	#[test]
+	fn assign_ranges_to_idle_peers() {
+		let now = Instant::now();
+		let peers = peers(3, 1000);
+		let mut downloader = Downloader::new();
+		let mut next_request_id = 0;
+		downloader.queue(1, 100);
+		downloader.queue(50, 150);
+		assert!(downloader.is_pending(150) && !downloader.is_pending(151));
+
+		let requests = downloader.assign(now, &peers, 1000, &mut next_request_id);
+		assert_eq!(ranges(&requests), [(1, 64), (65, 36), (101, 50)]);
+		// All peers are busy
+		assert!(downloader
+			.assign(now, &peers, 1000, &mut next_request_id)
+			.is_empty());
+		// Ranges are limited by the download window
+		downloader.queue(151, 1000);
+		downloader.fail(requests[0].request_id, &peers);
+		let requests = downloader.assign(now, &peers, 160, &mut next_request_id);
+		assert_eq!(ranges(&requests), [(151, 10)]);
+	}
+
+	#[test]
+	fn adapt_batch_sizes() {
+		let now = Instant::now();
+		let peers = peers(1, 1000);
+		let peer_id = *peers.keys().next().unwrap();
+		let mut downloader = Downloader::new();
+		let mut next_request_id = 0;
+		let headers = cached_chain(1000);
+		downloader.queue(1, 1000);
+
+		// Slow response halves the batch size, fast responses double it
+		let mut batch_sizes = vec![];
+		let mut from = 0;
+		for elapsed in [10000, 100, 100] {
+			let request = downloader.assign(now, &peers, 1000, &mut next_request_id)[0];
+			let range = headers[from..from + request.count as usize].to_vec();
+			from += request.count as usize;
+			let later = now + Duration::from_millis(elapsed);
+			let accepted = downloader.complete(later, request.request_id, range, &peers);
+			assert_eq!(accepted.len(), request.count as usize);
+			batch_sizes.push(downloader.stats(&peer_id).unwrap().batch_size);
+		}
+		assert_eq!(batch_sizes, [32, 64, 128]);
+
+		// Expired request reduces the batch size, and is dropped without the other peers
+		downloader.assign(now, &peers, 1000, &mut next_request_id);
+		downloader.expire(now + REQUEST_TIMEOUT, &peers);
+		assert_eq!(downloader.stats(&peer_id).unwrap().batch_size, 64);
+		assert_eq!(downloader.in_flight(), 0);
+		assert!(!downloader.is_pending(from as u32 + 1));
+	}
+
+	#[test]
+	fn reassign_invalid_and_partial_ranges() {
+		let now = Instant::now();
+		let peers = peers(2, 100);
+		let mut downloader = Downloader::new();
+		let mut next_request_id = 0;
+		let headers = cached_chain(100);
+		downloader.queue(1, 100);
+		let requests = downloader.assign(now, &peers, 100, &mut next_request_id);
+		let (first, second) = match requests[0].from {
+			1 => (requests[0], requests[1]),
+			_ => (requests[1], requests[0]),
+		};
+
+		// Headers which are not linked are rejected
+		let mut invalid = headers[..64].to_vec();
+		invalid.swap(1, 2);
+		assert!(downloader
+			.complete(now, first.request_id, invalid, &peers)
+			.is_empty());
+		let partial = headers[64..80].to_vec();
+		assert_eq!(
+			downloader
+				.complete(now, second.request_id, partial, &peers)
+				.len(),
+			16
+		);
+
+		let requests = downloader.assign(now, &peers, 100, &mut next_request_id);
+		let reassigned = requests
+			.iter()
+			.map(|request| (request.peer_id, request.from))
+			.collect::<HashSet<_>>();
+		assert!(reassigned.contains(&(second.peer_id, 1)));
+		assert!(reassigned.contains(&(first.peer_id, 81)));
+	}
+}
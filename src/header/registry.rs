@@ -0,0 +1,182 @@
+//! Decoding of the digest items of custom consensus and DA engines.
+//!
+//! Engine IDs are associated with decoder closures in the [`DigestRegistry`]. Pre-runtime, consensus and seal items
+//! are matched by their engine ID, and `Other` items are matched if the payload is prefixed with the engine ID.
+//! Decoded item keeps the raw item, so it can be re-encoded exactly as it was received.
+
+use codec::Decode;
+use color_eyre::{eyre::eyre, Result};
+use std::{any::Any, collections::HashMap, fmt};
+
+use super::{DigestItemRef, OwnedDigestItem};
+
+pub type EngineId = [u8; 4];
+
+/// Kind of the digest item, passed to the decoder together with the payload
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DigestItemKind {
+	PreRuntime,
+	Consensus,
+	Seal,
+	/// Payload is without the engine ID prefix
+	Other,
+}
+
+type Decoder =
+	Box<dyn Fn(DigestItemKind, &[u8]) -> Result<Box<dyn Any + Send + Sync>> + Send + Sync>;
+
+#[derive(Default)]
+pub struct DigestRegistry {
+	decoders: HashMap<EngineId, Decoder>,
+}
+
+impl fmt::Debug for DigestRegistry {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let engines = self
+			.decoders
+			.keys()
+			.map(|id| String::from_utf8_lossy(id).into_owned())
+			.collect::<Vec<_>>();
+		f.debug_struct("DigestRegistry")
+			.field("engines", &engines)
+			.finish()
+	}
+}
+
+impl DigestRegistry {
+	/// Registers decoder of the engine items, replacing previously registered one.
+	pub fn register<T, F>(&mut self, engine_id: EngineId, decoder: F)
+	where
+		T: Any + Send + Sync,
+		F: Fn(DigestItemKind, &[u8]) -> Result<T> + Send + Sync + 'static,
+	{
+		let decoder = move |kind, payload: &[u8]| {
+			decoder(kind, payload).map(|value| Box::new(value) as Box<dyn Any + Send + Sync>)
+		};
+		self.decoders.insert(engine_id, Box::new(decoder));
+	}
+
+	/// Registers SCALE decoder of the engine items, with all payload bytes consumed.
+	pub fn register_scale<T: Decode + Any + Send + Sync>(&mut self, engine_id: EngineId) {
+		self.register(engine_id, move |_, mut payload: &[u8]| {
+			let value = T::decode(&mut payload)?;
+			if !payload.is_empty() {
+				return Err(eyre!("Digest item has {} trailing bytes", payload.len()));
+			}
+			Ok(value)
+		});
+	}
+
+	pub fn is_registered(&self, engine_id: &EngineId) -> bool {
+		self.decoders.contains_key(engine_id)
+	}
+
+	/// Decodes item with the registered decoder. Items of unregistered engines are returned without a value.
+	pub fn decode<'a>(&self, item: DigestItemRef<'a>) -> Result<DecodedDigestItem<'a>> {
+		let engine = match item {
+			DigestItemRef::PreRuntime(id, payload) => {
+				Some((*id, DigestItemKind::PreRuntime, payload))
+			},
+			DigestItemRef::Consensus(id, payload) => {
+				Some((*id, DigestItemKind::Consensus, payload))
+			},
+			DigestItemRef::Seal(id, payload) => Some((*id, DigestItemKind::Seal, payload)),
+			DigestItemRef::Other(payload) if payload.len() >= 4 => {
+				let (id, payload) = payload.split_at(4);
+				let id: EngineId = id.try_into().expect("Engine ID has 4 bytes");
+				Some((id, DigestItemKind::Other, payload))
+			},
+			_ => None,
+		};
+		let Some((engine_id, kind, payload)) = engine else {
+			return Ok(DecodedDigestItem { item, value: None });
+		};
+		let Some(decoder) = self.decoders.get(&engine_id) else {
+			return Ok(DecodedDigestItem { item, value: None });
+		};
+		let value = decoder(kind, payload).map_err(|error| {
+			eyre!(
+				"Invalid {kind:?} digest item of engine {}: {error}",
+				String::from_utf8_lossy(&engine_id)
+			)
+		})?;
+		Ok(DecodedDigestItem {
+			item,
+			value: Some(value),
+		})
+	}
+}
+
+pub struct DecodedDigestItem<'a> {
+	/// Raw digest item
+	pub item: DigestItemRef<'a>,
+	value: Option<Box<dyn Any + Send + Sync>>,
+}
+
+impl<'a> DecodedDigestItem<'a> {
+	/// Returns decoded value, if the item is decoded into the given type
+	pub fn value<T: Any>(&self) -> Option<&T> {
+		self.value.as_ref().and_then(|value| value.downcast_ref())
+	}
+
+	pub fn is_decoded(&self) -> bool {
+		self.value.is_some()
+	}
+
+	pub fn to_owned(&self) -> OwnedDigestItem {
+		self.item.to_owned()
+	}
+
+	/// Encodes the raw item, which is identical to the received encoding
+	pub fn encode(&self) -> Vec<u8> {
+		self.item.encode()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use avail_subxt::config::substrate::DigestItem;
+	use codec::Encode;
+
+	#[derive(Debug, PartialEq, Decode)]
+	struct Commitment {
+		height: u32,
+		root: [u8; 4],
+	}
+
+	#[test]
+	fn decode_registered_items() {
+		let mut registry = DigestRegistry::default();
+		registry.register_scale::<Commitment>(*b"MYDA");
+		registry.register(*b"BEEF", |kind, payload| Ok((kind, payload.len())));
+
+		let payload = (7u32, [1u8; 4]).encode();
+		let item = DigestItemRef::Consensus(b"MYDA", &payload);
+		let decoded = registry.decode(item).unwrap();
+		assert_eq!(
+			decoded.value::<Commitment>(),
+			Some(&Commitment {
+				height: 7,
+				root: [1; 4]
+			})
+		);
+		assert!(decoded.value::<u32>().is_none());
+		assert_eq!(
+			decoded.encode(),
+			DigestItem::Consensus(*b"MYDA", payload.clone()).encode()
+		);
+
+		let other = [b"BEEF".as_slice(), &[1, 2, 3]].concat();
+		let decoded = registry.decode(DigestItemRef::Other(&other)).unwrap();
+		assert_eq!(decoded.value(), Some(&(DigestItemKind::Other, 3usize)));
+
+		let decoded = registry.decode(DigestItemRef::Seal(b"BABE", &[1])).unwrap();
+		assert!(!decoded.is_decoded());
+
+		// Registered engine with invalid payload
+		assert!(registry
+			.decode(DigestItemRef::PreRuntime(b"MYDA", &payload[..6]))
+			.is_err());
+	}
+}
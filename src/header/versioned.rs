@@ -0,0 +1,298 @@
+//! Decoding of the header extensions of the legacy and the current runtimes.
+//!
+//! Extension is a SCALE encoded enum, with the variant index identifying the [`HeaderVersion`]. Legacy extensions
+//! are normalized into the current (V3) extension, so the rest of the crate handles a single layout.
+//!
+//! Version can be selected by the runtime spec version, with [`VersionSchedule`], or detected by trial decoding
+//! of all known versions, with [`decode_extension_any`]. Both report the decoded version explicitly, and unknown
+//! (future) versions are reported as errors, instead of being misinterpreted.
+//!
+//! # Layouts
+//!
+//! Legacy layouts follow the `kate_commitment::v1` and `kate_commitment::v2` types of `avail-core`, which are
+//! registered in the metadata of the runtimes which produced them:
+//!
+//! * V1 - compact encoded `rows` and `cols`, `data_root`, `commitment`, followed by the compact data lookup
+//! * V2 - as V1, with optional `data_root` (blocks without application data have none)
+//! * V3 - current layout, decoded with the runtime types
+//!
+//! Headers are decoded with [`decode_extension_any`] (see [`super::HeaderRef::decode_extension`]), so headers of
+//! the legacy runtimes, e.g. fetched during the backfill, are decoded as well.
+//!
+//! # Notes
+//!
+//! Normalized extension doesn't encode to the original bytes, so header hash has to be calculated from the
+//! received encoding, e.g. with [`super::HeaderRef::hash`].
+
+use avail_subxt::{
+	api::runtime_types::avail_core::{
+		data_lookup::compact::CompactDataLookup,
+		header::extension::{v3, HeaderExtension},
+		kate_commitment::v3::KateCommitment,
+	},
+	utils::H256,
+};
+use codec::Decode;
+use color_eyre::{eyre::eyre, Result};
+use std::fmt;
+
+use super::HeaderRef;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HeaderVersion {
+	V1,
+	V2,
+	V3,
+}
+
+impl HeaderVersion {
+	/// Known versions, from the latest
+	pub const ALL: [HeaderVersion; 3] = [HeaderVersion::V3, HeaderVersion::V2, HeaderVersion::V1];
+
+	/// Index of the extension enum variant
+	pub fn variant(&self) -> u8 {
+		match self {
+			HeaderVersion::V1 => 0,
+			HeaderVersion::V2 => 1,
+			HeaderVersion::V3 => 2,
+		}
+	}
+
+	pub fn from_variant(variant: u8) -> Option<Self> {
+		Self::ALL
+			.into_iter()
+			.find(|version| version.variant() == variant)
+	}
+}
+
+impl fmt::Display for HeaderVersion {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{self:?}")
+	}
+}
+
+/// Legacy commitment, with required (V1) or optional (V2) data root
+#[derive(Decode)]
+struct LegacyCommitment<R: Decode> {
+	#[codec(compact)]
+	rows: u16,
+	#[codec(compact)]
+	cols: u16,
+	data_root: R,
+	commitment: Vec<u8>,
+}
+
+#[derive(Decode)]
+struct LegacyExtension<R: Decode> {
+	commitment: LegacyCommitment<R>,
+	app_lookup: CompactDataLookup,
+}
+
+impl<R: Decode + Into<Option<H256>>> LegacyExtension<R> {
+	/// Normalizes extension, with the zero data root if the root is missing
+	fn normalize(self) -> HeaderExtension {
+		let LegacyExtension {
+			commitment,
+			app_lookup,
+		} = self;
+		HeaderExtension::V3(v3::HeaderExtension {
+			commitment: KateCommitment {
+				rows: commitment.rows,
+				cols: commitment.cols,
+				data_root: commitment.data_root.into().unwrap_or_default(),
+				commitment: commitment.commitment,
+			},
+			app_lookup,
+		})
+	}
+}
+
+/// Decodes extension of the given version, normalized to the current layout.
+pub fn decode_extension(version: HeaderVersion, encoded: &[u8]) -> Result<HeaderExtension> {
+	let Some((&variant, mut input)) = encoded.split_first() else {
+		return Err(eyre!("Header extension is empty"));
+	};
+	if variant != version.variant() {
+		return Err(eyre!(
+			"Header extension variant {variant} doesn't match version {version}"
+		));
+	}
+	let extension = match version {
+		HeaderVersion::V1 => LegacyExtension::<H256>::decode(&mut input)?.normalize(),
+		HeaderVersion::V2 => LegacyExtension::<Option<H256>>::decode(&mut input)?.normalize(),
+		HeaderVersion::V3 => {
+			input = encoded;
+			HeaderExtension::decode(&mut input)?
+		},
+	};
+	if !input.is_empty() {
+		return Err(eyre!(
+			"Header extension {version} has {} trailing bytes",
+			input.len()
+		));
+	}
+	Ok(extension)
+}
+
+/// Decodes extension by trying all known versions, from the latest.
+pub fn decode_extension_any(encoded: &[u8]) -> Result<(HeaderVersion, HeaderExtension)> {
+	for version in HeaderVersion::ALL {
+		if let Ok(extension) = decode_extension(version, encoded) {
+			return Ok((version, extension));
+		}
+	}
+	match encoded.first() {
+		Some(&variant) if HeaderVersion::from_variant(variant).is_none() => Err(eyre!(
+			"Unsupported header extension version, variant {variant}"
+		)),
+		_ => Err(eyre!("Header extension doesn't match any known version")),
+	}
+}
+
+/// Header versions by the runtime spec version
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionSchedule {
+	/// First spec version using the header version, sorted by spec version
+	changes: Vec<(u32, HeaderVersion)>,
+}
+
+impl Default for VersionSchedule {
+	fn default() -> Self {
+		VersionSchedule {
+			changes: vec![(0, HeaderVersion::V3)],
+		}
+	}
+}
+
+impl VersionSchedule {
+	pub fn new(mut changes: Vec<(u32, HeaderVersion)>) -> Self {
+		changes.sort();
+		VersionSchedule { changes }
+	}
+
+	/// Returns header version used by the given spec version, or the earliest version if spec version predates
+	/// the schedule.
+	pub fn version_at(&self, spec_version: u32) -> HeaderVersion {
+		self.changes
+			.iter()
+			.rev()
+			.find(|(from, _)| *from <= spec_version)
+			.or(self.changes.first())
+			.map_or(HeaderVersion::V3, |(_, version)| *version)
+	}
+}
+
+impl<'a> HeaderRef<'a> {
+	/// Decodes extension with the version scheduled for the given spec version.
+	pub fn decode_extension_at(
+		&self,
+		schedule: &VersionSchedule,
+		spec_version: u32,
+	) -> Result<(HeaderVersion, HeaderExtension)> {
+		let version = schedule.version_at(spec_version);
+		let extension = decode_extension(version, self.extension)?;
+		Ok((version, extension))
+	}
+
+	/// Decodes extension of any known version.
+	pub fn decode_extension_any(&self) -> Result<(HeaderVersion, HeaderExtension)> {
+		decode_extension_any(self.extension)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use codec::{Compact, Encode};
+
+	fn legacy_extension(variant: u8, data_root: Vec<u8>) -> Vec<u8> {
+		let lookup = CompactDataLookup {
+			size: 2,
+			index: vec![],
+		};
+		[
+			vec![variant],
+			Compact(4u16).encode(),
+			Compact(8u16).encode(),
+			data_root,
+			vec![3u8; 48].encode(),
+			lookup.encode(),
+		]
+		.concat()
+	}
+
+	fn v1_extension() -> Vec<u8> {
+		legacy_extension(0, H256::repeat_byte(1).encode())
+	}
+
+	#[test]
+	fn decode_legacy_extension() {
+		let encoded = v1_extension();
+		let (version, extension) = decode_extension_any(&encoded).unwrap();
+		assert_eq!(version, HeaderVersion::V1);
+		let HeaderExtension::V3(v3::HeaderExtension {
+			commitment,
+			app_lookup,
+		}) = extension;
+		assert_eq!((commitment.rows, commitment.cols), (4, 8));
+		assert_eq!(commitment.data_root, H256::repeat_byte(1));
+		assert_eq!(app_lookup.size, 2);
+
+		assert!(decode_extension(HeaderVersion::V2, &encoded).is_err());
+		assert!(decode_extension(HeaderVersion::V1, &encoded[..encoded.len() - 1]).is_err());
+	}
+
+	#[test]
+	fn decode_v2_extension_without_data_root() {
+		let encoded = legacy_extension(1, None::<H256>.encode());
+		let (version, extension) = decode_extension_any(&encoded).unwrap();
+		assert_eq!(version, HeaderVersion::V2);
+		let HeaderExtension::V3(v3::HeaderExtension { commitment, .. }) = extension;
+		assert_eq!((commitment.rows, commitment.cols), (4, 8));
+		assert_eq!(commitment.data_root, H256::zero());
+
+		let encoded = legacy_extension(1, Some(H256::repeat_byte(1)).encode());
+		let (_, extension) = decode_extension_any(&encoded).unwrap();
+		let HeaderExtension::V3(v3::HeaderExtension { commitment, .. }) = extension;
+		assert_eq!(commitment.data_root, H256::repeat_byte(1));
+	}
+
+	#[test]
+	fn decode_current_extension() {
+		let extension = HeaderExtension::V3(v3::HeaderExtension {
+			commitment: KateCommitment {
+				rows: 1,
+				cols: 4,
+				data_root: H256::zero(),
+				commitment: vec![6; 48],
+			},
+			app_lookup: CompactDataLookup {
+				size: 1,
+				index: vec![],
+			},
+		});
+		let encoded = extension.encode();
+		assert_eq!(encoded[0], HeaderVersion::V3.variant());
+		let (version, decoded) = decode_extension_any(&encoded).unwrap();
+		assert_eq!(version, HeaderVersion::V3);
+		assert_eq!(decoded.encode(), encoded);
+
+		let mut future = encoded;
+		future[0] = 9;
+		let error = decode_extension_any(&future).unwrap_err();
+		assert!(error.to_string().contains("variant 9"));
+	}
+
+	#[test]
+	fn schedule() {
+		let schedule = VersionSchedule::new(vec![
+			(20, HeaderVersion::V3),
+			(1, HeaderVersion::V1),
+			(10, HeaderVersion::V2),
+		]);
+		assert_eq!(schedule.version_at(0), HeaderVersion::V1);
+		assert_eq!(schedule.version_at(15), HeaderVersion::V2);
+		assert_eq!(schedule.version_at(20), HeaderVersion::V3);
+		assert_eq!(VersionSchedule::default().version_at(5), HeaderVersion::V3);
+	}
+}
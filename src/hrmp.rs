@@ -0,0 +1,259 @@
+//! Verification of HRMP (XCMP-lite) channels and messages, using relay chain storage proofs.
+//!
+//! # Verified storage
+//!
+//! * `Hrmp::HrmpChannels` - channel metadata, including message count, total size and MQC head
+//! * `Hrmp::HrmpChannelContents` - messages which are not yet processed by the recipient
+//! * `Hrmp::HrmpIngressChannelsIndex` and `Hrmp::HrmpEgressChannelsIndex` - inbound and outbound channels of the parachain
+//!
+//! Message contents are verified against channel metadata, and optionally against the message queue chain (MQC) head.
+
+use avail_subxt::utils::H256;
+use codec::{Decode, Encode};
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use sp_core::{blake2_256, twox_128, twox_64};
+
+use crate::{multi_chain::ParaId, storage_proof::verify_read_proof};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct HrmpChannelId {
+	pub sender: ParaId,
+	pub recipient: ParaId,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct HrmpChannel {
+	pub max_capacity: u32,
+	pub max_total_size: u32,
+	pub max_message_size: u32,
+	pub msg_count: u32,
+	pub total_size: u32,
+	pub mqc_head: Option<H256>,
+	pub sender_deposit: u128,
+	pub recipient_deposit: u128,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct InboundHrmpMessage {
+	/// Relay chain block number in which message was sent
+	pub sent_at: u32,
+	pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifiedChannel {
+	pub id: HrmpChannelId,
+	pub channel: HrmpChannel,
+	pub messages: Vec<InboundHrmpMessage>,
+}
+
+fn storage_map_key(pallet: &[u8], storage: &[u8], key: &impl Encode) -> Vec<u8> {
+	let encoded_key = key.encode();
+	[
+		&twox_128(pallet)[..],
+		&twox_128(storage)[..],
+		&twox_64(&encoded_key)[..],
+		&encoded_key[..],
+	]
+	.concat()
+}
+
+pub fn channel_storage_key(id: &HrmpChannelId) -> Vec<u8> {
+	storage_map_key(b"Hrmp", b"HrmpChannels", id)
+}
+
+pub fn channel_contents_storage_key(id: &HrmpChannelId) -> Vec<u8> {
+	storage_map_key(b"Hrmp", b"HrmpChannelContents", id)
+}
+
+pub fn ingress_index_storage_key(para_id: ParaId) -> Vec<u8> {
+	storage_map_key(b"Hrmp", b"HrmpIngressChannelsIndex", &para_id)
+}
+
+pub fn egress_index_storage_key(para_id: ParaId) -> Vec<u8> {
+	storage_map_key(b"Hrmp", b"HrmpEgressChannelsIndex", &para_id)
+}
+
+/// Calculates MQC head after appending messages to the chain with the given head.
+/// Message data is hashed SCALE encoded (length prefixed), same as in the relay chain runtime.
+pub fn mqc_head(previous: H256, messages: &[InboundHrmpMessage]) -> H256 {
+	messages.iter().fold(previous, |head, message| {
+		let data_hash = H256::from(message.data.using_encoded(blake2_256));
+		(head, message.sent_at, data_hash)
+			.using_encoded(blake2_256)
+			.into()
+	})
+}
+
+fn read_value<T: Decode>(state_root: H256, proof: Vec<Vec<u8>>, key: Vec<u8>) -> Result<Option<T>> {
+	let Some((_, value)) = verify_read_proof(state_root, proof, &[key])?.pop() else {
+		return Ok(None);
+	};
+	value
+		.map(|value| T::decode(&mut &value[..]))
+		.transpose()
+		.wrap_err("Failed to decode HRMP storage value")
+}
+
+/// Verifies HRMP channel and its contents against the relay chain state root.
+/// Proof must contain both channel and channel contents storage entries.
+pub fn verify_channel(
+	relay_state_root: H256,
+	id: HrmpChannelId,
+	proof: Vec<Vec<u8>>,
+) -> Result<VerifiedChannel> {
+	let channel: HrmpChannel =
+		read_value(relay_state_root, proof.clone(), channel_storage_key(&id))?
+			.ok_or_else(|| eyre!("HRMP channel {}->{} is not found", id.sender, id.recipient))?;
+	let messages: Vec<InboundHrmpMessage> =
+		read_value(relay_state_root, proof, channel_contents_storage_key(&id))?.unwrap_or_default();
+
+	if channel.msg_count as usize != messages.len() {
+		return Err(eyre!(
+			"Message count mismatch: channel has {}, contents has {}",
+			channel.msg_count,
+			messages.len()
+		));
+	}
+
+	let total_size = messages
+		.iter()
+		.map(|message| message.data.len())
+		.sum::<usize>();
+	if channel.total_size as usize != total_size {
+		return Err(eyre!(
+			"Total size mismatch: channel has {}, contents has {total_size}",
+			channel.total_size
+		));
+	}
+
+	if let Some(message) = messages
+		.iter()
+		.find(|message| message.data.len() > channel.max_message_size as usize)
+	{
+		return Err(eyre!(
+			"Message sent at {} exceeds max message size",
+			message.sent_at
+		));
+	}
+
+	Ok(VerifiedChannel {
+		id,
+		channel,
+		messages,
+	})
+}
+
+impl VerifiedChannel {
+	/// Verifies messages against channel MQC head, given the MQC head before the first message in the channel.
+	pub fn verify_mqc(&self, previous: H256) -> Result<()> {
+		let expected = self.channel.mqc_head.unwrap_or_default();
+		let actual = mqc_head(previous, &self.messages);
+		if expected != actual {
+			return Err(eyre!(
+				"MQC head mismatch: expected {expected:?}, got {actual:?}"
+			));
+		}
+		Ok(())
+	}
+}
+
+/// Verifies inbound channel senders of the parachain
+pub fn verify_ingress_index(
+	relay_state_root: H256,
+	para_id: ParaId,
+	proof: Vec<Vec<u8>>,
+) -> Result<Vec<ParaId>> {
+	read_value(relay_state_root, proof, ingress_index_storage_key(para_id))
+		.map(Option::unwrap_or_default)
+}
+
+/// Verifies outbound channel recipients of the parachain
+pub fn verify_egress_index(
+	relay_state_root: H256,
+	para_id: ParaId,
+	proof: Vec<Vec<u8>>,
+) -> Result<Vec<ParaId>> {
+	read_value(relay_state_root, proof, egress_index_storage_key(para_id))
+		.map(Option::unwrap_or_default)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::storage_proof::build_trie;
+
+	const ID: HrmpChannelId = HrmpChannelId {
+		sender: 2000,
+		recipient: 2001,
+	};
+
+	fn messages() -> Vec<InboundHrmpMessage> {
+		vec![
+			InboundHrmpMessage {
+				sent_at: 10,
+				data: vec![1, 2, 3],
+			},
+			InboundHrmpMessage {
+				sent_at: 11,
+				data: vec![4],
+			},
+		]
+	}
+
+	fn channel(msg_count: u32, total_size: u32) -> HrmpChannel {
+		HrmpChannel {
+			max_capacity: 10,
+			max_total_size: 1024,
+			max_message_size: 128,
+			msg_count,
+			total_size,
+			mqc_head: Some(mqc_head(H256::zero(), &messages())),
+			sender_deposit: 0,
+			recipient_deposit: 0,
+		}
+	}
+
+	fn channel_proof(channel: HrmpChannel) -> (H256, Vec<Vec<u8>>) {
+		let channel_key = channel_storage_key(&ID);
+		let contents_key = channel_contents_storage_key(&ID);
+		let index_key = ingress_index_storage_key(ID.recipient);
+		build_trie(&[
+			(&channel_key, &channel.encode()),
+			(&contents_key, &messages().encode()),
+			(&index_key, &vec![ID.sender].encode()),
+		])
+	}
+
+	#[test]
+	fn verify_valid_channel() {
+		let (root, proof) = channel_proof(channel(2, 4));
+		let verified = verify_channel(root, ID, proof.clone()).unwrap();
+		assert_eq!(verified.messages, messages());
+		verified.verify_mqc(H256::zero()).unwrap();
+		assert!(verified.verify_mqc(H256::repeat_byte(1)).is_err());
+		assert_eq!(
+			verify_ingress_index(root, ID.recipient, proof).unwrap(),
+			vec![ID.sender]
+		);
+	}
+
+	#[test]
+	fn mqc_head_hashes_encoded_data() {
+		let expected: H256 =
+			hex_literal::hex!("0fd9f6e4bc86e0f715fc7d660a66d69824c42c327ae627107ba655d66f69245b")
+				.into();
+		assert_eq!(mqc_head(H256::zero(), &messages()), expected);
+	}
+
+	#[test]
+	fn verify_inconsistent_channel() {
+		let (root, proof) = channel_proof(channel(3, 4));
+		assert!(verify_channel(root, ID, proof).is_err());
+		let (root, proof) = channel_proof(channel(2, 5));
+		assert!(verify_channel(root, ID, proof).is_err());
+	}
+}
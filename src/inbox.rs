@@ -0,0 +1,350 @@
+//! Data availability inbox, a stable interface for the rollup frameworks integrating Avail DA.
+//!
+//! [`DataAvailabilityInbox`] posts blobs, waits for their availability, fetches and verifies them by the
+//! [`BlobPointer`]. [`ClientInbox`] is implemented on top of the client:
+//!
+//! * Blobs are posted with the [`DataSubmitter`], so blobs larger than a single extrinsic are split into chunks
+//! * Inclusion is awaited until blocks of all chunks are sampled with the required confidence
+//! * Chunks are fetched from the block bodies, verified against the extrinsics root of the locally verified headers
+//!   and decompressed (and decrypted with the inbox key, if configured)
+//!
+//! [`MemoryInbox`] (and the generated `MockDataAvailabilityInbox`) can be used for testing of the integrations.
+
+use async_trait::async_trait;
+use avail_subxt::{primitives::Header, utils::H256};
+use codec::{Decode, Encode};
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use mockall::automock;
+use serde::{Deserialize, Serialize};
+use sp_core::blake2_256;
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+use tokio::time::{sleep, timeout};
+use tracing::debug;
+use x25519_dalek::StaticSecret;
+
+use crate::{
+	audit,
+	block_builder::extrinsics_root,
+	compression,
+	data::{Database, Key},
+	encryption,
+	eth_bridge::keccak256,
+	submission::{DataSubmitter, InclusionReceipt},
+	utils::decode_app_extrinsic,
+	verified_rpc::Client,
+};
+
+/// Interval of the availability checks, while awaiting inclusion
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Chunk of the blob, submitted in a single extrinsic
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkPointer {
+	pub block_number: u32,
+	pub block_hash: H256,
+	pub extrinsic_index: u32,
+}
+
+impl From<&InclusionReceipt> for ChunkPointer {
+	fn from(receipt: &InclusionReceipt) -> Self {
+		ChunkPointer {
+			block_number: receipt.block_number,
+			block_hash: receipt.block_hash,
+			extrinsic_index: receipt.extrinsic_index,
+		}
+	}
+}
+
+/// Pointer to the posted blob, chunks are in the blob data order
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct BlobPointer {
+	pub app_id: u32,
+	pub chunks: Vec<ChunkPointer>,
+}
+
+#[async_trait]
+#[automock]
+pub trait DataAvailabilityInbox {
+	/// Posts blob, and returns its pointer once all chunks are finalized
+	async fn post_blob(&self, blob: Vec<u8>) -> Result<BlobPointer>;
+
+	/// Waits until blocks of all blob chunks are available, fails on timeout
+	async fn await_inclusion(&self, pointer: &BlobPointer, timeout: Duration) -> Result<()>;
+
+	/// Fetches blob, with chunks verified against the verified block headers
+	async fn fetch_blob(&self, pointer: &BlobPointer) -> Result<Vec<u8>>;
+
+	/// Verifies that blob chunks are included in the verified blocks
+	async fn verify_inclusion(&self, pointer: &BlobPointer) -> Result<bool>;
+}
+
+/// Inbox of the application, on top of the light client verified headers and sampling results
+pub struct ClientInbox<C: Client, D: Database> {
+	submitter: DataSubmitter,
+	client: C,
+	db: D,
+	app_id: u32,
+	/// Required confidence of the blocks, in percents
+	confidence: f64,
+	/// Key of the encrypted blobs
+	key: Option<StaticSecret>,
+}
+
+impl<C: Client, D: Database> ClientInbox<C, D> {
+	pub fn new(submitter: DataSubmitter, client: C, db: D, app_id: u32, confidence: f64) -> Self {
+		ClientInbox {
+			submitter,
+			client,
+			db,
+			app_id,
+			confidence,
+			key: None,
+		}
+	}
+
+	/// Decrypts fetched blobs with the recipient key
+	pub fn with_key(mut self, key: StaticSecret) -> Self {
+		self.key = Some(key);
+		self
+	}
+
+	fn is_available(&self, block_number: u32) -> Result<bool> {
+		let confidence =
+			audit::load(&self.db, block_number)?.and_then(|report| report.confidence_percent());
+		Ok(confidence.is_some_and(|confidence| confidence >= self.confidence))
+	}
+}
+
+/// Returns header of the chunk block, if it is verified and stored by the light client
+fn verified_header(db: &impl Database, chunk: &ChunkPointer) -> Result<Option<Header>> {
+	let header = db
+		.get::<Header>(Key::BlockHeader(chunk.block_number))
+		.wrap_err("Failed to get block header")?;
+	Ok(header.filter(|header| Encode::using_encoded(header, blake2_256) == chunk.block_hash.0))
+}
+
+/// Fetches data of the chunk, verified against the extrinsics root of the verified header and the application ID
+pub(crate) async fn fetch_chunk(
+	client: &impl Client,
+	db: &impl Database,
+	app_id: u32,
+	chunk: &ChunkPointer,
+) -> Result<Vec<u8>> {
+	let ChunkPointer {
+		block_number,
+		block_hash,
+		extrinsic_index,
+	} = *chunk;
+	let header =
+		verified_header(db, chunk)?.ok_or_else(|| eyre!("Block {block_number} is not verified"))?;
+	let extrinsics = client
+		.get_block_body(block_hash)
+		.await
+		.wrap_err("Failed to get block body")?;
+	if extrinsics_root(&extrinsics) != header.extrinsics_root {
+		return Err(eyre!(
+			"Block {block_number} body doesn't match extrinsics root"
+		));
+	}
+	let extrinsic = extrinsics
+		.get(extrinsic_index as usize)
+		.ok_or_else(|| eyre!("Block {block_number} has no extrinsic {extrinsic_index}"))?;
+	// Extrinsics are decoded with the length prefix
+	let (extrinsic_app_id, data) = decode_app_extrinsic(&extrinsic.encode())?.ok_or_else(|| {
+		eyre!("Extrinsic {block_number}-{extrinsic_index} is not a data submission")
+	})?;
+	if extrinsic_app_id != app_id {
+		return Err(eyre!(
+			"Extrinsic {block_number}-{extrinsic_index} is submitted with app ID {extrinsic_app_id}, expected {app_id}"
+		));
+	}
+	Ok(data)
+}
+
+/// Decrypts (if the key is provided) and decompresses submitted blob
+pub(crate) fn decode_submitted(key: Option<&StaticSecret>, data: &[u8]) -> Result<Vec<u8>> {
+	match key {
+		Some(key) if encryption::is_sealed(data) => {
+			compression::decompress(&encryption::open(key, data)?)
+		},
+		_ => compression::decompress(data),
+	}
+}
+
+#[async_trait]
+impl<C, D> DataAvailabilityInbox for ClientInbox<C, D>
+where
+	C: Client + Sync + Send,
+	D: Database + Sync + Send,
+{
+	async fn post_blob(&self, blob: Vec<u8>) -> Result<BlobPointer> {
+		let receipts = self.submitter.submit_data(self.app_id, blob).await?;
+		let chunks = receipts.iter().map(ChunkPointer::from).collect();
+		Ok(BlobPointer {
+			app_id: self.app_id,
+			chunks,
+		})
+	}
+
+	async fn await_inclusion(&self, pointer: &BlobPointer, duration: Duration) -> Result<()> {
+		let available = async {
+			for chunk in &pointer.chunks {
+				while !self.is_available(chunk.block_number)? {
+					sleep(POLL_INTERVAL).await;
+				}
+			}
+			Ok(())
+		};
+		timeout(duration, available)
+			.await
+			.map_err(|_| eyre!("Blob is not available after {duration:?}"))?
+	}
+
+	async fn fetch_blob(&self, pointer: &BlobPointer) -> Result<Vec<u8>> {
+		let mut blob = vec![];
+		for chunk in &pointer.chunks {
+			blob.extend(fetch_chunk(&self.client, &self.db, pointer.app_id, chunk).await?);
+		}
+		decode_submitted(self.key.as_ref(), &blob)
+	}
+
+	async fn verify_inclusion(&self, pointer: &BlobPointer) -> Result<bool> {
+		for chunk in &pointer.chunks {
+			if verified_header(&self.db, chunk)?.is_none() {
+				return Ok(false);
+			}
+			if let Err(error) = fetch_chunk(&self.client, &self.db, pointer.app_id, chunk).await {
+				debug!(
+					block_number = chunk.block_number,
+					"Chunk is not included: {error:#}"
+				);
+				return Ok(false);
+			}
+		}
+		Ok(true)
+	}
+}
+
+/// In-memory inbox, for testing of the rollup integrations
+#[derive(Default)]
+pub struct MemoryInbox {
+	app_id: u32,
+	blobs: Mutex<HashMap<ChunkPointer, Vec<u8>>>,
+}
+
+impl MemoryInbox {
+	pub fn new(app_id: u32) -> Self {
+		MemoryInbox {
+			app_id,
+			blobs: Mutex::new(HashMap::new()),
+		}
+	}
+}
+
+#[async_trait]
+impl DataAvailabilityInbox for MemoryInbox {
+	async fn post_blob(&self, blob: Vec<u8>) -> Result<BlobPointer> {
+		let mut blobs = self.blobs.lock().unwrap();
+		let chunk = ChunkPointer {
+			block_number: blobs.len() as u32 + 1,
+			block_hash: keccak256(&blob),
+			extrinsic_index: 1,
+		};
+		blobs.insert(chunk, blob);
+		Ok(BlobPointer {
+			app_id: self.app_id,
+			chunks: vec![chunk],
+		})
+	}
+
+	async fn await_inclusion(&self, pointer: &BlobPointer, _: Duration) -> Result<()> {
+		if !self.verify_inclusion(pointer).await? {
+			return Err(eyre!("Blob is not posted"));
+		}
+		Ok(())
+	}
+
+	async fn fetch_blob(&self, pointer: &BlobPointer) -> Result<Vec<u8>> {
+		let blobs = self.blobs.lock().unwrap();
+		let mut blob = vec![];
+		for chunk in &pointer.chunks {
+			blob.extend(
+				blobs
+					.get(chunk)
+					.ok_or_else(|| eyre!("Blob is not posted"))?,
+			);
+		}
+		Ok(blob)
+	}
+
+	async fn verify_inclusion(&self, pointer: &BlobPointer) -> Result<bool> {
+		let blobs = self.blobs.lock().unwrap();
+		Ok(pointer.app_id == self.app_id
+			&& pointer.chunks.iter().all(|chunk| blobs.contains_key(chunk)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		data::mem_db::MemoryDB,
+		test_utils::{BlockSpec, ChainBuilder},
+		verified_rpc::MockClient,
+	};
+
+	#[tokio::test]
+	async fn memory_inbox() {
+		let inbox = MemoryInbox::new(1);
+		let pointer = inbox.post_blob(b"rollup batch".to_vec()).await.unwrap();
+		inbox
+			.await_inclusion(&pointer, Duration::ZERO)
+			.await
+			.unwrap();
+		assert_eq!(inbox.fetch_blob(&pointer).await.unwrap(), b"rollup batch");
+
+		let encoded = BlobPointer::decode(&mut &pointer.encode()[..]).unwrap();
+		assert_eq!(encoded, pointer);
+		let other_app = BlobPointer {
+			app_id: 2,
+			..pointer
+		};
+		assert!(!inbox.verify_inclusion(&other_app).await.unwrap());
+	}
+
+	#[tokio::test]
+	async fn unverified_chunks() {
+		let mut chain = ChainBuilder::new(&[[1; 32]]);
+		let spec = BlockSpec {
+			extrinsics: vec![vec![4, 1, 2, 3]],
+			..Default::default()
+		};
+		let hash = chain.build_on(chain.genesis_hash(), spec).unwrap();
+		let block = chain.block(&hash).unwrap().clone();
+		let db = MemoryDB::default();
+		db.put(Key::BlockHeader(block.header.number), block.header.clone())
+			.unwrap();
+
+		let mut client = MockClient::new();
+		client
+			.expect_get_block_body()
+			.returning(|_| Box::pin(async move { Ok(vec![vec![4, 1, 2, 4]]) }));
+		let chunk = ChunkPointer {
+			block_number: block.header.number,
+			block_hash: hash,
+			extrinsic_index: 0,
+		};
+		// Body doesn't match extrinsics root
+		assert!(fetch_chunk(&client, &db, 1, &chunk).await.is_err());
+		// Block is not verified
+		let unknown = ChunkPointer {
+			block_hash: H256::zero(),
+			..chunk
+		};
+		assert!(verified_header(&db, &unknown).unwrap().is_none());
+		assert!(fetch_chunk(&client, &db, 1, &unknown).await.is_err());
+	}
+}
@@ -0,0 +1,225 @@
+//! Inherent data providers used by the block authoring and import paths.
+//!
+//! Inherent data is a SCALE encoded map of 8-byte identifiers to SCALE encoded values,
+//! compatible with the `InherentData` type used by the Substrate runtime.
+//!
+//! # Providers
+//!
+//! * [`TimestampProvider`] - current time in milliseconds (`timstap0`)
+//! * [`SlotProvider`] - BABE (`babeslot`) or Aura (`auraslot`) slot derived from timestamp
+//! * [`DataAvailabilityProvider`] - Avail specific data root of the block (`availda0`)
+
+use avail_subxt::utils::H256;
+use codec::{Decode, Encode};
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use std::{
+	collections::BTreeMap,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub type InherentIdentifier = [u8; 8];
+
+pub const TIMESTAMP_INHERENT_IDENTIFIER: InherentIdentifier = *b"timstap0";
+pub const BABE_SLOT_INHERENT_IDENTIFIER: InherentIdentifier = *b"babeslot";
+pub const AURA_SLOT_INHERENT_IDENTIFIER: InherentIdentifier = *b"auraslot";
+pub const DA_INHERENT_IDENTIFIER: InherentIdentifier = *b"availda0";
+
+/// Map of inherent identifiers to SCALE encoded inherent values
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct InherentData {
+	data: BTreeMap<InherentIdentifier, Vec<u8>>,
+}
+
+impl InherentData {
+	/// Inserts encoded value under the identifier, fails if identifier is already present.
+	pub fn put<T: Encode>(&mut self, identifier: InherentIdentifier, value: &T) -> Result<()> {
+		if self.data.contains_key(&identifier) {
+			return Err(eyre!(
+				"Inherent data for {} already exists",
+				String::from_utf8_lossy(&identifier)
+			));
+		}
+		self.data.insert(identifier, value.encode());
+		Ok(())
+	}
+
+	/// Decodes value stored under the identifier, if any.
+	pub fn get<T: Decode>(&self, identifier: &InherentIdentifier) -> Result<Option<T>> {
+		self.data
+			.get(identifier)
+			.map(|value| T::decode(&mut &value[..]))
+			.transpose()
+			.wrap_err_with(|| {
+				format!(
+					"Failed to decode inherent data for {}",
+					String::from_utf8_lossy(identifier)
+				)
+			})
+	}
+
+	pub fn len(&self) -> usize {
+		self.data.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.data.is_empty()
+	}
+}
+
+pub trait InherentDataProvider {
+	fn provide_inherent_data(&self, inherent_data: &mut InherentData) -> Result<()>;
+}
+
+/// Creates inherent data from the list of providers
+pub fn create_inherent_data(providers: &[&dyn InherentDataProvider]) -> Result<InherentData> {
+	let mut inherent_data = InherentData::default();
+	for provider in providers {
+		provider.provide_inherent_data(&mut inherent_data)?;
+	}
+	Ok(inherent_data)
+}
+
+/// Provides timestamp in milliseconds since UNIX epoch
+#[derive(Clone, Copy, Debug)]
+pub struct TimestampProvider {
+	pub timestamp: u64,
+}
+
+impl TimestampProvider {
+	pub fn from_system_time() -> Result<Self> {
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.wrap_err("System time is before UNIX epoch")?
+			.as_millis() as u64;
+		Ok(TimestampProvider { timestamp })
+	}
+}
+
+impl InherentDataProvider for TimestampProvider {
+	fn provide_inherent_data(&self, inherent_data: &mut InherentData) -> Result<()> {
+		inherent_data.put(TIMESTAMP_INHERENT_IDENTIFIER, &self.timestamp)
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlotKind {
+	Babe,
+	Aura,
+}
+
+impl SlotKind {
+	fn identifier(&self) -> InherentIdentifier {
+		match self {
+			SlotKind::Babe => BABE_SLOT_INHERENT_IDENTIFIER,
+			SlotKind::Aura => AURA_SLOT_INHERENT_IDENTIFIER,
+		}
+	}
+}
+
+/// Provides slot number derived from timestamp and slot duration
+#[derive(Clone, Copy, Debug)]
+pub struct SlotProvider {
+	pub kind: SlotKind,
+	pub slot: u64,
+}
+
+impl SlotProvider {
+	pub fn from_timestamp(kind: SlotKind, timestamp: u64, slot_duration: Duration) -> Result<Self> {
+		let duration = slot_duration.as_millis() as u64;
+		if duration == 0 {
+			return Err(eyre!("Slot duration cannot be zero"));
+		}
+		let slot = timestamp / duration;
+		Ok(SlotProvider { kind, slot })
+	}
+}
+
+impl InherentDataProvider for SlotProvider {
+	fn provide_inherent_data(&self, inherent_data: &mut InherentData) -> Result<()> {
+		inherent_data.put(self.kind.identifier(), &self.slot)
+	}
+}
+
+/// Provides Avail specific inherent data (data root of submitted data)
+#[derive(Clone, Copy, Debug)]
+pub struct DataAvailabilityProvider {
+	pub data_root: H256,
+}
+
+impl InherentDataProvider for DataAvailabilityProvider {
+	fn provide_inherent_data(&self, inherent_data: &mut InherentData) -> Result<()> {
+		inherent_data.put(DA_INHERENT_IDENTIFIER, &self.data_root)
+	}
+}
+
+/// Verifies that timestamp inherent is not behind the parent and not too far in the future
+pub fn verify_timestamp(
+	inherent_data: &InherentData,
+	parent_timestamp: u64,
+	now: u64,
+	max_drift: Duration,
+) -> Result<u64> {
+	let timestamp: u64 = inherent_data
+		.get(&TIMESTAMP_INHERENT_IDENTIFIER)?
+		.ok_or_else(|| eyre!("Timestamp inherent data is missing"))?;
+
+	if timestamp <= parent_timestamp {
+		return Err(eyre!(
+			"Timestamp {timestamp} is not after parent timestamp {parent_timestamp}"
+		));
+	}
+
+	if timestamp > now + max_drift.as_millis() as u64 {
+		return Err(eyre!("Timestamp {timestamp} is too far in the future"));
+	}
+
+	Ok(timestamp)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use test_case::test_case;
+
+	#[test]
+	fn inherent_data_encoding_roundtrip() {
+		let inherent_data = create_inherent_data(&[
+			&TimestampProvider { timestamp: 12_000 },
+			&SlotProvider::from_timestamp(SlotKind::Babe, 12_000, Duration::from_secs(6)).unwrap(),
+			&DataAvailabilityProvider {
+				data_root: H256::repeat_byte(1),
+			},
+		])
+		.unwrap();
+
+		let encoded = inherent_data.encode();
+		let decoded = InherentData::decode(&mut &encoded[..]).unwrap();
+		assert_eq!(decoded, inherent_data);
+		assert_eq!(decoded.len(), 3);
+		assert_eq!(
+			decoded.get::<u64>(&BABE_SLOT_INHERENT_IDENTIFIER).unwrap(),
+			Some(2)
+		);
+		assert_eq!(
+			decoded.get::<H256>(&DA_INHERENT_IDENTIFIER).unwrap(),
+			Some(H256::repeat_byte(1))
+		);
+	}
+
+	#[test]
+	fn duplicate_inherent_fails() {
+		let provider = TimestampProvider { timestamp: 1 };
+		assert!(create_inherent_data(&[&provider, &provider]).is_err());
+	}
+
+	#[test_case(5_000, 4_000, 5_000 => true ; "valid timestamp")]
+	#[test_case(4_000, 4_000, 5_000 => false ; "not after parent")]
+	#[test_case(20_000, 4_000, 5_000 => false ; "too far in the future")]
+	fn verify_timestamp_bounds(timestamp: u64, parent: u64, now: u64) -> bool {
+		let inherent_data = create_inherent_data(&[&TimestampProvider { timestamp }]).unwrap();
+		verify_timestamp(&inherent_data, parent, now, Duration::from_secs(10)).is_ok()
+	}
+}
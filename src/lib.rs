@@ -1,18 +1,84 @@
+pub mod account;
+pub mod ancestry;
 pub mod api;
 pub mod app_client;
+pub mod audit;
+pub mod backfill;
+pub mod beefy;
+pub mod blob_ref;
+pub mod block_builder;
+pub mod body;
+pub mod cache;
+pub mod chunking;
+pub mod client;
+#[cfg(feature = "codegen")]
+pub mod codegen;
+pub mod compression;
+pub mod constants;
 pub mod consts;
+pub mod counters;
 #[cfg(feature = "crawl")]
 pub mod crawl_client;
+pub mod da_finality;
 pub mod data;
+pub mod dynamic;
+pub mod encryption;
+pub mod epochs;
+pub mod equivocation;
+pub mod eth_bridge;
+pub mod export;
+pub mod extrinsic;
 pub mod fat_client;
+pub mod fee;
+pub mod feed;
 pub mod finality;
+pub mod fork_choice;
+#[cfg(feature = "arbitrary")]
+pub mod fuzzing;
+pub mod governance;
+pub mod header;
+pub mod hrmp;
+pub mod inbox;
+pub mod inherents;
 pub mod light_client;
+pub mod light_server;
+pub mod limits;
 pub mod maintenance;
+pub mod memory;
+pub mod mmr;
+pub mod multi_chain;
 pub mod network;
+pub mod nonce;
+pub mod pretty;
 pub mod proof;
+pub mod receipts;
+pub mod runtime;
+pub mod runtime_upgrade;
+pub mod sampling;
+pub mod session_keys;
 pub mod shutdown;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod simulation;
+pub mod snapshot;
+pub mod staking;
+pub mod state_client;
+pub mod storage_proof;
+pub mod submission;
+pub mod subscriptions;
+pub mod supervisor;
 pub mod sync_client;
+pub mod sync_driver;
 pub mod sync_finality;
+pub mod sync_journal;
+pub mod sync_machine;
 pub mod telemetry;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+pub mod tip;
+pub mod trie;
+pub mod trusted_setup;
 pub mod types;
+pub mod utilization;
 pub mod utils;
+pub mod verification;
+pub mod verified_rpc;
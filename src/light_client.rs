@@ -8,7 +8,7 @@
 //! * Generate random cells for random data sampling (8 cells currently)
 //! * Retrieve cell proofs from a) DHT and/or b) via RPC call from the node, in that order
 //! * Verify proof using the received cells
-//! * Calculate block confidence and store it in RocksDB
+//! * Calculate block confidence and store it in RocksDB, with the audit report of the sampling
 //! * Insert cells to to DHT for remote fetch
 //! * Notify the consumer (app client) a new block has been verified
 //!
@@ -17,19 +17,18 @@
 //! In case delay is configured, block processing is delayed for configured time.
 //! In case RPC is disabled, RPC calls will be skipped.
 
-use avail_subxt::{primitives::Header, utils::H256};
-use codec::Encode;
 use color_eyre::{eyre::WrapErr, Result};
 use kate_recovery::{commitments, matrix::Dimensions};
-use sp_core::blake2_256;
 use std::{
 	sync::{Arc, Mutex},
 	time::Instant,
 };
-use tracing::{error, info};
+use tracing::{debug, error, info, instrument, warn, Span};
 
 use crate::{
+	audit::{self, AuditReport, CellSource, Timings},
 	data::{Database, Key},
+	header::cached::CachedHeader,
 	network::{
 		self,
 		rpc::{self, Event},
@@ -37,15 +36,24 @@ use crate::{
 	shutdown::Controller,
 	telemetry::{MetricCounter, MetricValue, Metrics},
 	types::{self, ClientChannels, LightClientConfig, OptionBlockRange, State},
-	utils::{calculate_confidence, extract_kate},
+	utils::{extract_app_lookup, extract_kate},
 };
 
+/// Stores the audit report, failure is logged since it doesn't affect sampling
+fn store_report(db: &impl Database, report: AuditReport) {
+	let block_number = report.block_number;
+	if let Err(error) = audit::store(db, report) {
+		warn!(block_number, "{error:#}");
+	}
+}
+
+#[instrument(skip_all, fields(block_number = header.number, block_hash = tracing::field::Empty), level = "info")]
 pub async fn process_block(
 	db: impl Database,
 	network_client: &impl network::Client,
 	metrics: &Arc<impl Metrics>,
 	cfg: &LightClientConfig,
-	header: Header,
+	header: &CachedHeader,
 	received_at: Instant,
 	state: Arc<Mutex<State>>,
 ) -> Result<Option<f64>> {
@@ -55,7 +63,8 @@ pub async fn process_block(
 		.await?;
 
 	let block_number = header.number;
-	let header_hash: H256 = Encode::using_encoded(&header, blake2_256).into();
+	let header_hash = header.hash();
+	Span::current().record("block_hash", tracing::field::display(header_hash));
 
 	info!(
 		{ block_number, block_delay = received_at.elapsed().as_secs()},
@@ -77,8 +86,20 @@ pub async fn process_block(
 	}
 
 	let commitments = commitments::from_slice(&commitment)?;
-	let cell_count = rpc::cell_count_for_confidence(cfg.confidence);
-	let positions = rpc::generate_random_cells(dimensions, cell_count);
+	let cell_count = cfg.sampling.confidence.cell_count(cfg.confidence);
+	let positions = match extract_app_lookup(&header.extension) {
+		Ok(lookup) => cfg
+			.sampling
+			.strategy
+			.positions(dimensions, &lookup, cell_count),
+		Err(error) => {
+			warn!(
+				block_number,
+				"Invalid app lookup, sampling random cells: {error:?}"
+			);
+			rpc::generate_random_cells(dimensions, cell_count)
+		},
+	};
 	info!(
 		block_number,
 		"cells_requested" = positions.len(),
@@ -112,6 +133,12 @@ pub async fn process_block(
 		))
 		.await?;
 
+	metrics
+		.record(MetricValue::ProofVerificationDuration(
+			fetch_stats.proof_verification_duration,
+		))
+		.await?;
+
 	if let Some(rpc_fetched) = fetch_stats.rpc_fetched {
 		metrics
 			.record(MetricValue::NodeRPCFetched(rpc_fetched))
@@ -124,8 +151,21 @@ pub async fn process_block(
 			.await?;
 	}
 
+	let mut report = AuditReport::new(block_number, header_hash);
+	// Cells fetched from DHT are followed by the cells fetched from RPC
+	let sources = (0..fetched.len()).map(|i| {
+		if i < fetch_stats.dht_fetched as usize {
+			CellSource::Dht
+		} else {
+			CellSource::Rpc
+		}
+	});
+	report.record_cells(&positions, fetched.iter().zip(sources));
+	report.timings = Timings::from(&fetch_stats);
+
 	if positions.len() > fetched.len() {
 		error!(block_number, "Failed to fetch {} cells", unfetched.len());
+		store_report(&db, report);
 		return Ok(None);
 	}
 
@@ -135,7 +175,7 @@ pub async fn process_block(
 
 	state.lock().unwrap().confidence_achieved.set(block_number);
 
-	let confidence = calculate_confidence(fetched.len() as u32);
+	let confidence = cfg.sampling.confidence.confidence(fetched.len() as u32);
 	info!(
 		block_number,
 		"confidence" = confidence,
@@ -146,6 +186,9 @@ pub async fn process_block(
 		.record(MetricValue::BlockConfidence(confidence))
 		.await?;
 
+	report.set_confidence(confidence);
+	store_report(&db, report);
+
 	// push latest mined block's header into column family specified
 	// for keeping block headers, to be used
 	// later for verifying DHT stored data
@@ -154,9 +197,15 @@ pub async fn process_block(
 	// another competing thread, which syncs all block headers
 	// in range [0, LATEST], where LATEST = latest block number
 	// when this process started
-	db.put(Key::BlockHeader(block_number), header)
+	db.put(Key::BlockHeader(block_number), header.header().clone())
 		.wrap_err("Light Client failed to store Block Header")?;
 
+	debug!(
+		block_number,
+		elapsed = ?received_at.elapsed(),
+		"Block processing finished"
+	);
+
 	Ok(Some(confidence))
 }
 
@@ -206,12 +255,14 @@ pub async fn run(
 			tokio::time::sleep(seconds).await;
 		}
 
+		// Hash is calculated once, while the block is processed
+		let header = CachedHeader::from(header);
 		let process_block_result = process_block(
 			db.clone(),
 			&network_client,
 			&metrics,
 			&cfg,
-			header.clone(),
+			&header,
 			received_at,
 			state.clone(),
 		)
@@ -225,7 +276,7 @@ pub async fn run(
 			},
 		};
 
-		let Ok(client_msg) = types::BlockVerified::try_from((header, confidence)) else {
+		let Ok(client_msg) = types::BlockVerified::try_from((&header, confidence)) else {
 			error!("Cannot create message from header");
 			continue;
 		};
@@ -257,6 +308,7 @@ mod tests {
 			kate_commitment::v3::KateCommitment,
 		},
 		config::substrate::Digest,
+		primitives::Header,
 	};
 	use hex_literal::hex;
 	use kate_recovery::{data::Cell, matrix::Position};
@@ -347,7 +399,7 @@ mod tests {
 			&mock_network_client,
 			&Arc::new(mock_metrics),
 			&cfg,
-			header,
+			&header.into(),
 			recv,
 			state,
 		)
@@ -0,0 +1,461 @@
+//! Light server, serving light client requests of the peers.
+//!
+//! Enabled with `light_server_enable`, for nodes which have resources to serve other light clients.
+//!
+//! # Flow
+//!
+//! * Inbound light server protocol requests are forwarded from the P2P event loop
+//! * Remote header requests (by number, by hash and ranges) are served from the locally stored headers
+//! * Remote read requests are served with read proofs fetched from RPC, verified against the state root of the
+//!   locally stored header, so only proofs which are valid for verified headers are served
+//! * Compact block requests are served with block bodies fetched from RPC, verified against the extrinsics root of
+//!   the locally stored header
+//!
+//! # Notes
+//!
+//! Requests for blocks which are not stored locally are served with an empty response. Cell requests are served by
+//! the P2P event loop itself, with the cell exchange protocol.
+
+use async_trait::async_trait;
+use avail_subxt::{primitives::Header as DaHeader, utils::H256};
+use codec::Encode;
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use mockall::automock;
+use sp_core::blake2_256;
+use tracing::{debug, info, trace, warn};
+
+use crate::{
+	block_builder::extrinsics_root,
+	body::Block,
+	data::{Database, Key},
+	network::{
+		p2p::{
+			self,
+			compact_block::{self, CompactBlockRequestReceiver, InboundCompactBlockRequest},
+			light_server_protocol::{
+				InboundLightRequest, LightRequest, LightRequestReceiver, LightResponse,
+				MAX_READ_KEYS, MAX_REMOTE_HEADERS,
+			},
+		},
+		rpc::Client as RpcClient,
+	},
+	storage_proof,
+};
+
+#[async_trait]
+#[automock]
+pub trait Client {
+	fn get_header(&self, block_number: u32) -> Result<Option<DaHeader>>;
+	async fn get_read_proof(&self, keys: Vec<Vec<u8>>, block_hash: H256) -> Result<Vec<Vec<u8>>>;
+	/// Returns unverified header with the given hash
+	async fn get_header_by_hash(&self, block_hash: H256) -> Result<DaHeader>;
+	async fn get_block_body(&self, block_hash: H256) -> Result<Vec<Vec<u8>>>;
+}
+
+#[derive(Clone)]
+pub struct LightServer<T: Database> {
+	db: T,
+	rpc_client: RpcClient,
+}
+
+impl<T: Database> LightServer<T> {
+	pub fn new(db: T, rpc_client: RpcClient) -> Self {
+		LightServer { db, rpc_client }
+	}
+}
+
+#[async_trait]
+impl<T: Database + Sync + Send> Client for LightServer<T> {
+	fn get_header(&self, block_number: u32) -> Result<Option<DaHeader>> {
+		self.db
+			.get(Key::BlockHeader(block_number))
+			.wrap_err("Light server failed to get block header")
+	}
+
+	async fn get_read_proof(&self, keys: Vec<Vec<u8>>, block_hash: H256) -> Result<Vec<Vec<u8>>> {
+		self.rpc_client.get_read_proof(keys, block_hash).await
+	}
+
+	async fn get_header_by_hash(&self, block_hash: H256) -> Result<DaHeader> {
+		self.rpc_client.get_header_by_hash(block_hash).await
+	}
+
+	async fn get_block_body(&self, block_hash: H256) -> Result<Vec<Vec<u8>>> {
+		self.rpc_client.get_block_body(block_hash).await
+	}
+}
+
+async fn read_proof(
+	client: &impl Client,
+	block_number: u32,
+	block_hash: H256,
+	keys: Vec<Vec<u8>>,
+) -> Result<Option<Vec<Vec<u8>>>> {
+	if keys.len() > MAX_READ_KEYS {
+		return Ok(None);
+	}
+	let Some(header) = client.get_header(block_number)? else {
+		return Ok(None);
+	};
+	let hash: H256 = Encode::using_encoded(&header, blake2_256).into();
+	if hash != block_hash {
+		return Ok(None);
+	}
+	let proof = client.get_read_proof(keys.clone(), block_hash).await?;
+	storage_proof::verify_read_proof(header.state_root, proof.clone(), &keys)?;
+	Ok(Some(proof))
+}
+
+/// Returns consecutive locally stored headers, starting with the block number `from`, up to the first missing one
+fn verified_headers(client: &impl Client, from: u32, count: u32) -> Result<Vec<Vec<u8>>> {
+	let mut headers = vec![];
+	let mut parent_hash = None;
+	for number in from..from.saturating_add(count.min(MAX_REMOTE_HEADERS)) {
+		let Some(header) = client.get_header(number)? else {
+			break;
+		};
+		if parent_hash.is_some_and(|parent_hash| parent_hash != header.parent_hash) {
+			break;
+		}
+		let encoded = header.encode();
+		parent_hash = Some(H256(blake2_256(&encoded)));
+		headers.push(encoded);
+	}
+	Ok(headers)
+}
+
+/// Returns locally stored header with the given hash
+async fn verified_header(client: &impl Client, block_hash: H256) -> Result<Option<DaHeader>> {
+	let number = client.get_header_by_hash(block_hash).await?.number;
+	let Some(header) = client.get_header(number)? else {
+		return Ok(None);
+	};
+	let hash: H256 = Encode::using_encoded(&header, blake2_256).into();
+	Ok((hash == block_hash).then_some(header))
+}
+
+/// Returns block with the locally stored header, and the body verified against its extrinsics root
+async fn verified_block(client: &impl Client, block_hash: H256) -> Result<Option<Block>> {
+	let Some(header) = verified_header(client, block_hash).await? else {
+		return Ok(None);
+	};
+	let extrinsics = client.get_block_body(block_hash).await?;
+	if extrinsics_root(&extrinsics) != header.extrinsics_root {
+		return Err(eyre!(
+			"Block {block_hash:?} body doesn't match extrinsics root"
+		));
+	}
+	Ok(Some(Block { header, extrinsics }))
+}
+
+/// Serves compact block request, failed requests are served with empty response
+pub async fn serve_compact_block(
+	client: &impl Client,
+	request: compact_block::CompactBlockRequest,
+) -> compact_block::CompactBlockResponse {
+	let block_hash = request.block_hash();
+	let block = verified_block(client, block_hash)
+		.await
+		.unwrap_or_else(|error| {
+			warn!(?block_hash, "Cannot serve compact block: {error:#}");
+			None
+		});
+	compact_block::serve(block.as_ref(), &request)
+}
+
+/// Serves light client request, failed requests are served with empty response
+pub async fn serve(client: &impl Client, request: LightRequest) -> LightResponse {
+	match request {
+		LightRequest::RemoteHeader { block_number } => {
+			let header = client.get_header(block_number).unwrap_or_else(|error| {
+				warn!(block_number, "Cannot serve remote header: {error:#}");
+				None
+			});
+			LightResponse::Header(header.map(|header| header.encode()))
+		},
+		LightRequest::RemoteRead {
+			block_number,
+			block_hash,
+			keys,
+		} => {
+			let proof = read_proof(client, block_number, block_hash.into(), keys)
+				.await
+				.unwrap_or_else(|error| {
+					warn!(block_number, "Cannot serve remote read: {error:#}");
+					None
+				});
+			LightResponse::ReadProof(proof)
+		},
+		LightRequest::RemoteHeaders { from, count } => {
+			let headers = verified_headers(client, from, count).unwrap_or_else(|error| {
+				warn!(from, count, "Cannot serve remote headers: {error:#}");
+				vec![]
+			});
+			LightResponse::Headers(headers)
+		},
+		LightRequest::RemoteHeaderByHash { block_hash } => {
+			let block_hash = H256(block_hash);
+			let header = verified_header(client, block_hash)
+				.await
+				.unwrap_or_else(|error| {
+					warn!(?block_hash, "Cannot serve remote header: {error:#}");
+					None
+				});
+			LightResponse::Header(header.map(|header| header.encode()))
+		},
+	}
+}
+
+/// Runs light server, until the P2P event loop stops forwarding requests.
+///
+/// # Arguments
+///
+/// * `client` - Light server client, used for accessing headers, read proofs and block bodies
+/// * `p2p_client` - Used for sending responses
+/// * `requests` - Inbound light server protocol requests, forwarded from the P2P event loop
+/// * `compact_block_requests` - Inbound compact block requests, forwarded from the P2P event loop
+pub async fn run(
+	client: impl Client,
+	p2p_client: p2p::Client,
+	mut requests: LightRequestReceiver,
+	mut compact_block_requests: CompactBlockRequestReceiver,
+) {
+	info!("Starting light server...");
+
+	loop {
+		tokio::select! {
+			Some(InboundLightRequest { peer, request, channel }) = requests.recv() => {
+				trace!(%peer, "Serving light client request: {request:?}");
+				let response = serve(&client, request).await;
+				if let Err(error) = p2p_client.respond_light(channel, response) {
+					debug!(%peer, "Cannot respond to light client request: {error:#}");
+				}
+			},
+			Some(InboundCompactBlockRequest { peer, request, channel }) = compact_block_requests.recv() => {
+				trace!(%peer, "Serving compact block request: {request:?}");
+				let response = serve_compact_block(&client, request).await;
+				if let Err(error) = p2p_client.respond_compact_block(channel, response) {
+					debug!(%peer, "Cannot respond to compact block request: {error:#}");
+				}
+			},
+			else => break,
+		}
+	}
+
+	info!("Light server stopped");
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use avail_subxt::{
+		api::runtime_types::avail_core::{
+			data_lookup::compact::CompactDataLookup,
+			header::extension::{v3::HeaderExtension, HeaderExtension::V3},
+			kate_commitment::v3::KateCommitment,
+		},
+		config::substrate::Digest,
+	};
+	use mockall::predicate::eq;
+
+	fn header(state_root: H256) -> DaHeader {
+		DaHeader {
+			parent_hash: H256::zero(),
+			number: 42,
+			state_root,
+			extrinsics_root: H256::zero(),
+			digest: Digest { logs: vec![] },
+			extension: V3(HeaderExtension {
+				commitment: KateCommitment {
+					rows: 1,
+					cols: 4,
+					data_root: H256::zero(),
+					commitment: vec![0; 96],
+				},
+				app_lookup: CompactDataLookup {
+					size: 0,
+					index: vec![],
+				},
+			}),
+		}
+	}
+
+	fn hash(header: &DaHeader) -> [u8; 32] {
+		Encode::using_encoded(header, blake2_256)
+	}
+
+	#[tokio::test]
+	async fn serve_remote_header() {
+		let stored = header(H256::zero());
+		let encoded = stored.encode();
+		let mut mock_client = MockClient::new();
+		mock_client
+			.expect_get_header()
+			.with(eq(42))
+			.returning(move |_| Ok(Some(stored.clone())));
+		mock_client.expect_get_header().returning(|_| Ok(None));
+
+		let request = LightRequest::RemoteHeader { block_number: 42 };
+		let response = serve(&mock_client, request).await;
+		assert_eq!(response, LightResponse::Header(Some(encoded)));
+
+		let request = LightRequest::RemoteHeader { block_number: 43 };
+		let response = serve(&mock_client, request).await;
+		assert_eq!(response, LightResponse::Header(None));
+	}
+
+	#[tokio::test]
+	async fn serve_remote_headers() {
+		let first = header(H256::zero());
+		let second = DaHeader {
+			number: 43,
+			parent_hash: hash(&first).into(),
+			..header(H256::zero())
+		};
+		let other = DaHeader {
+			number: 44,
+			..header(H256::zero())
+		};
+		let encoded = vec![first.encode(), second.encode()];
+		let mut mock_client = MockClient::new();
+		for header in [first, second.clone(), other] {
+			mock_client
+				.expect_get_header()
+				.with(eq(header.number))
+				.returning(move |_| Ok(Some(header.clone())));
+		}
+
+		// Third header is not a child of the second one
+		let request = LightRequest::RemoteHeaders {
+			from: 42,
+			count: 10,
+		};
+		let response = serve(&mock_client, request).await;
+		assert_eq!(response, LightResponse::Headers(encoded));
+
+		let unverified = second.clone();
+		mock_client.expect_get_header_by_hash().returning(move |_| {
+			let header = unverified.clone();
+			Box::pin(async move { Ok(header) })
+		});
+		let request = LightRequest::RemoteHeaderByHash {
+			block_hash: hash(&second),
+		};
+		let response = serve(&mock_client, request).await;
+		assert_eq!(response, LightResponse::Header(Some(second.encode())));
+		let request = LightRequest::RemoteHeaderByHash {
+			block_hash: [1; 32],
+		};
+		let response = serve(&mock_client, request).await;
+		assert_eq!(response, LightResponse::Header(None));
+	}
+
+	#[tokio::test]
+	async fn serve_verified_read_proof() {
+		let (root, proof) = storage_proof::build_trie(&[(b"key", b"value")]);
+		let stored = header(root);
+		let block_hash = hash(&stored);
+		let mut mock_client = MockClient::new();
+		mock_client
+			.expect_get_header()
+			.returning(move |_| Ok(Some(stored.clone())));
+		let served = proof.clone();
+		mock_client.expect_get_read_proof().returning(move |_, _| {
+			let proof = served.clone();
+			Box::pin(async move { Ok(proof) })
+		});
+
+		let request = LightRequest::RemoteRead {
+			block_number: 42,
+			block_hash,
+			keys: vec![b"key".to_vec()],
+		};
+		let response = serve(&mock_client, request).await;
+		assert_eq!(response, LightResponse::ReadProof(Some(proof)));
+	}
+
+	#[tokio::test]
+	async fn reject_invalid_read_proof() {
+		let (_, proof) = storage_proof::build_trie(&[(b"key", b"value")]);
+		let stored = header(H256::repeat_byte(1));
+		let block_hash = hash(&stored);
+		let mut mock_client = MockClient::new();
+		mock_client
+			.expect_get_header()
+			.returning(move |_| Ok(Some(stored.clone())));
+		mock_client.expect_get_read_proof().returning(move |_, _| {
+			let proof = proof.clone();
+			Box::pin(async move { Ok(proof) })
+		});
+
+		let request = LightRequest::RemoteRead {
+			block_number: 42,
+			block_hash,
+			keys: vec![b"key".to_vec()],
+		};
+		let response = serve(&mock_client, request).await;
+		assert_eq!(response, LightResponse::ReadProof(None));
+	}
+
+	#[tokio::test]
+	async fn serve_verified_compact_block() {
+		let extrinsics = vec![vec![4, 1, 2, 3]];
+		let mut stored = header(H256::zero());
+		stored.extrinsics_root = extrinsics_root(&extrinsics);
+		let block_hash = hash(&stored);
+		let mut mock_client = MockClient::new();
+		let unverified = stored.clone();
+		mock_client.expect_get_header_by_hash().returning(move |_| {
+			let header = unverified.clone();
+			Box::pin(async move { Ok(header) })
+		});
+		let verified = stored.clone();
+		mock_client
+			.expect_get_header()
+			.with(eq(42))
+			.returning(move |_| Ok(Some(verified.clone())));
+		let body = extrinsics.clone();
+		mock_client.expect_get_block_body().returning(move |_| {
+			let body = body.clone();
+			Box::pin(async move { Ok(body) })
+		});
+
+		let request = compact_block::CompactBlockRequest::Extrinsics {
+			block_hash,
+			indices: vec![0],
+		};
+		let response = serve_compact_block(&mock_client, request).await;
+		assert_eq!(
+			response,
+			compact_block::CompactBlockResponse::Extrinsics(Some(extrinsics))
+		);
+
+		// Block of the unknown hash is not served
+		let request = compact_block::CompactBlockRequest::Block {
+			block_hash: [1; 32],
+		};
+		let response = serve_compact_block(&mock_client, request).await;
+		assert_eq!(response, compact_block::CompactBlockResponse::Block(None));
+	}
+
+	#[tokio::test]
+	async fn reject_unknown_block_hash() {
+		let stored = header(H256::zero());
+		let mut mock_client = MockClient::new();
+		mock_client
+			.expect_get_header()
+			.returning(move |_| Ok(Some(stored.clone())));
+		mock_client.expect_get_read_proof().never();
+
+		let request = LightRequest::RemoteRead {
+			block_number: 42,
+			block_hash: [1; 32],
+			keys: vec![b"key".to_vec()],
+		};
+		let response = serve(&mock_client, request).await;
+		assert_eq!(response, LightResponse::ReadProof(None));
+	}
+}
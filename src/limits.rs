@@ -0,0 +1,359 @@
+//! Bounded decoding of untrusted input.
+//!
+//! Network input can claim absurd vector lengths. [`DecodeWithLimits`] validates every length prefix against
+//! [`DecodeLimits`] before decoding, and fails with typed [`DecodeError`] instead of attempting the allocation.
+//! Digest items of the unknown variants are kept in the [`OwnedDigest`], and rejected with
+//! [`DecodeError::UnknownDigestItem`] only when decoded into the [`Digest`], which has no variant for them.
+
+use avail_subxt::{
+	api::runtime_types::avail_core::header::extension::HeaderExtension,
+	config::substrate::{Digest, DigestItem},
+	primitives::Header,
+	utils::H256,
+};
+use codec::Decode;
+use sp_trie::StorageProof;
+use std::fmt;
+
+use crate::{
+	body::Block,
+	header::{take_array, take_bytes, take_compact, DigestItemRef, OwnedDigest, OwnedDigestItem},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeLimits {
+	/// Maximum size of the encoded input
+	pub max_total_size: usize,
+	pub max_extrinsics: usize,
+	pub max_extrinsic_size: usize,
+	pub max_digest_items: usize,
+	pub max_digest_item_size: usize,
+	pub max_proof_nodes: usize,
+	pub max_node_size: usize,
+}
+
+impl Default for DecodeLimits {
+	fn default() -> Self {
+		DecodeLimits {
+			max_total_size: 10 * 1024 * 1024,
+			max_extrinsics: 65536,
+			max_extrinsic_size: 5 * 1024 * 1024,
+			max_digest_items: 32,
+			max_digest_item_size: 64 * 1024,
+			max_proof_nodes: 4096,
+			max_node_size: 64 * 1024,
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+	InputTooLarge { size: usize, max: usize },
+	TooManyExtrinsics { count: usize, max: usize },
+	ExtrinsicTooLarge { size: usize, max: usize },
+	TooManyDigestItems { count: usize, max: usize },
+	DigestItemTooLarge { size: usize, max: usize },
+	UnknownDigestItem(u8),
+	TooManyProofNodes { count: usize, max: usize },
+	ProofNodeTooLarge { size: usize, max: usize },
+	TrailingBytes(usize),
+	Invalid(String),
+}
+
+impl fmt::Display for DecodeError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			DecodeError::InputTooLarge { size, max } => {
+				write!(f, "Input size {size} exceeds maximum {max}")
+			},
+			DecodeError::TooManyExtrinsics { count, max } => {
+				write!(f, "Extrinsics count {count} exceeds maximum {max}")
+			},
+			DecodeError::ExtrinsicTooLarge { size, max } => {
+				write!(f, "Extrinsic size {size} exceeds maximum {max}")
+			},
+			DecodeError::TooManyDigestItems { count, max } => {
+				write!(f, "Digest items count {count} exceeds maximum {max}")
+			},
+			DecodeError::DigestItemTooLarge { size, max } => {
+				write!(f, "Digest item size {size} exceeds maximum {max}")
+			},
+			DecodeError::UnknownDigestItem(variant) => {
+				write!(f, "Digest item variant {variant} is unknown")
+			},
+			DecodeError::TooManyProofNodes { count, max } => {
+				write!(f, "Proof nodes count {count} exceeds maximum {max}")
+			},
+			DecodeError::ProofNodeTooLarge { size, max } => {
+				write!(f, "Proof node size {size} exceeds maximum {max}")
+			},
+			DecodeError::TrailingBytes(count) => write!(f, "Input has {count} trailing bytes"),
+			DecodeError::Invalid(message) => write!(f, "Invalid input: {message}"),
+		}
+	}
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<color_eyre::Report> for DecodeError {
+	fn from(error: color_eyre::Report) -> Self {
+		DecodeError::Invalid(error.to_string())
+	}
+}
+
+impl From<codec::Error> for DecodeError {
+	fn from(error: codec::Error) -> Self {
+		DecodeError::Invalid(error.to_string())
+	}
+}
+
+fn check(
+	value: usize,
+	max: usize,
+	error: fn(usize, usize) -> DecodeError,
+) -> Result<(), DecodeError> {
+	if value > max {
+		return Err(error(value, max));
+	}
+	Ok(())
+}
+
+pub trait DecodeWithLimits: Sized {
+	/// Decodes value from the input, consuming the decoded bytes.
+	fn decode_limited(input: &mut &[u8], limits: &DecodeLimits) -> Result<Self, DecodeError>;
+
+	/// Decodes value from the whole input, failing if there are trailing bytes.
+	fn decode_with_limits(input: &[u8], limits: &DecodeLimits) -> Result<Self, DecodeError> {
+		check(input.len(), limits.max_total_size, |size, max| {
+			DecodeError::InputTooLarge { size, max }
+		})?;
+		let mut input = input;
+		let value = Self::decode_limited(&mut input, limits)?;
+		if !input.is_empty() {
+			return Err(DecodeError::TrailingBytes(input.len()));
+		}
+		Ok(value)
+	}
+}
+
+impl DecodeWithLimits for OwnedDigest {
+	fn decode_limited(input: &mut &[u8], limits: &DecodeLimits) -> Result<Self, DecodeError> {
+		let count = take_compact(input)? as usize;
+		check(count, limits.max_digest_items, |count, max| {
+			DecodeError::TooManyDigestItems { count, max }
+		})?;
+		let logs = (0..count)
+			.map(|_| {
+				let start = *input;
+				let item = DigestItemRef::decode(input)?;
+				check(
+					start.len() - input.len(),
+					limits.max_digest_item_size,
+					|size, max| DecodeError::DigestItemTooLarge { size, max },
+				)?;
+				Ok(item.to_owned())
+			})
+			.collect::<Result<_, DecodeError>>()?;
+		Ok(OwnedDigest { logs })
+	}
+}
+
+impl DecodeWithLimits for Digest {
+	fn decode_limited(input: &mut &[u8], limits: &DecodeLimits) -> Result<Self, DecodeError> {
+		let logs = OwnedDigest::decode_limited(input, limits)?
+			.logs
+			.into_iter()
+			.map(|item| match item {
+				OwnedDigestItem::Known(item) => Ok(item),
+				OwnedDigestItem::Unknown(variant, _) => {
+					Err(DecodeError::UnknownDigestItem(variant))
+				},
+			})
+			.collect::<Result<Vec<DigestItem>, DecodeError>>()?;
+		Ok(Digest { logs })
+	}
+}
+
+impl DecodeWithLimits for Header {
+	fn decode_limited(input: &mut &[u8], limits: &DecodeLimits) -> Result<Self, DecodeError> {
+		let parent_hash = H256(*take_array(input)?);
+		let number = take_compact(input)?;
+		let state_root = H256(*take_array(input)?);
+		let extrinsics_root = H256(*take_array(input)?);
+		let digest = Digest::decode_limited(input, limits)?;
+		let extension = HeaderExtension::decode(input)?;
+		Ok(Header {
+			parent_hash,
+			number,
+			state_root,
+			extrinsics_root,
+			digest,
+			extension,
+		})
+	}
+}
+
+impl DecodeWithLimits for Block {
+	fn decode_limited(input: &mut &[u8], limits: &DecodeLimits) -> Result<Self, DecodeError> {
+		let header = Header::decode_limited(input, limits)?;
+		let count = take_compact(input)? as usize;
+		check(count, limits.max_extrinsics, |count, max| {
+			DecodeError::TooManyExtrinsics { count, max }
+		})?;
+		let extrinsics = (0..count)
+			.map(|_| {
+				let extrinsic = take_bytes(input)?;
+				check(extrinsic.len(), limits.max_extrinsic_size, |size, max| {
+					DecodeError::ExtrinsicTooLarge { size, max }
+				})?;
+				Ok(extrinsic.to_vec())
+			})
+			.collect::<Result<_, DecodeError>>()?;
+		Ok(Block { header, extrinsics })
+	}
+}
+
+impl DecodeWithLimits for StorageProof {
+	fn decode_limited(input: &mut &[u8], limits: &DecodeLimits) -> Result<Self, DecodeError> {
+		let count = take_compact(input)? as usize;
+		check(count, limits.max_proof_nodes, |count, max| {
+			DecodeError::TooManyProofNodes { count, max }
+		})?;
+		let nodes = (0..count)
+			.map(|_| {
+				let node = take_bytes(input)?;
+				check(node.len(), limits.max_node_size, |size, max| {
+					DecodeError::ProofNodeTooLarge { size, max }
+				})?;
+				Ok(node.to_vec())
+			})
+			.collect::<Result<Vec<_>, DecodeError>>()?;
+		Ok(StorageProof::new(nodes))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use avail_subxt::api::runtime_types::avail_core::{
+		data_lookup::compact::CompactDataLookup, header::extension::v3,
+		kate_commitment::v3::KateCommitment,
+	};
+	use codec::{Compact, Encode};
+
+	fn header(logs: Vec<DigestItem>) -> Header {
+		Header {
+			parent_hash: H256::repeat_byte(1),
+			number: 42,
+			state_root: H256::repeat_byte(2),
+			extrinsics_root: H256::repeat_byte(3),
+			digest: Digest { logs },
+			extension: HeaderExtension::V3(v3::HeaderExtension {
+				commitment: KateCommitment {
+					rows: 1,
+					cols: 4,
+					data_root: H256::repeat_byte(4),
+					commitment: vec![6; 48],
+				},
+				app_lookup: CompactDataLookup {
+					size: 1,
+					index: vec![],
+				},
+			}),
+		}
+	}
+
+	#[test]
+	fn decode_block() {
+		let block = Block {
+			header: header(vec![DigestItem::PreRuntime(*b"BABE", vec![1, 2, 3])]),
+			extrinsics: vec![vec![1; 10], vec![2; 20]],
+		};
+		let decoded = Block::decode_with_limits(&block.encode(), &DecodeLimits::default()).unwrap();
+		assert_eq!(decoded, block);
+	}
+
+	#[test]
+	fn too_many_extrinsics() {
+		let header = header(vec![]);
+		let encoded = [header.encode(), Compact(u32::MAX).encode()].concat();
+		let result = Block::decode_with_limits(&encoded, &DecodeLimits::default());
+		assert_eq!(
+			result,
+			Err(DecodeError::TooManyExtrinsics {
+				count: u32::MAX as usize,
+				max: 65536
+			})
+		);
+	}
+
+	#[test]
+	fn digest_item_too_large() {
+		let digest = Digest {
+			logs: vec![DigestItem::Other(vec![0; 100])],
+		};
+		let limits = DecodeLimits {
+			max_digest_item_size: 50,
+			..Default::default()
+		};
+		let result = Digest::decode_with_limits(&digest.encode(), &limits);
+		assert!(matches!(
+			result,
+			Err(DecodeError::DigestItemTooLarge { .. })
+		));
+	}
+
+	#[test]
+	fn keep_unknown_digest_items() {
+		let mut encoded = Digest {
+			logs: vec![DigestItem::Seal(*b"BABE", vec![5; 64])],
+		}
+		.encode();
+		let unknown = [vec![7], vec![1u8, 2, 3].encode()].concat();
+		encoded[0] = Compact(2u32).encode()[0];
+		encoded.extend_from_slice(&unknown);
+
+		let digest = OwnedDigest::decode_with_limits(&encoded, &DecodeLimits::default()).unwrap();
+		assert_eq!(
+			digest.logs,
+			vec![
+				OwnedDigestItem::Known(DigestItem::Seal(*b"BABE", vec![5; 64])),
+				OwnedDigestItem::Unknown(7, vec![1, 2, 3]),
+			]
+		);
+		assert_eq!(digest.encode(), encoded);
+
+		let limits = DecodeLimits {
+			max_digest_item_size: 4,
+			..Default::default()
+		};
+		let unknown_only = [Compact(1u32).encode(), unknown].concat();
+		let result = OwnedDigest::decode_with_limits(&unknown_only, &limits);
+		assert!(matches!(
+			result,
+			Err(DecodeError::DigestItemTooLarge { size: 5, max: 4 })
+		));
+		let result = Digest::decode_with_limits(&encoded, &DecodeLimits::default());
+		assert_eq!(result, Err(DecodeError::UnknownDigestItem(7)));
+	}
+
+	#[test]
+	fn decode_storage_proof() {
+		let nodes = vec![vec![1u8; 10], vec![2u8; 100]];
+		let limits = DecodeLimits {
+			max_node_size: 50,
+			..Default::default()
+		};
+		let result = StorageProof::decode_with_limits(&nodes.encode(), &limits);
+		assert!(matches!(result, Err(DecodeError::ProofNodeTooLarge { .. })));
+		let proof = StorageProof::decode_with_limits(&nodes.encode(), &DecodeLimits::default());
+		assert_eq!(proof.unwrap().into_nodes().len(), 2);
+	}
+
+	#[test]
+	fn trailing_bytes() {
+		let encoded = [Digest { logs: vec![] }.encode(), vec![0]].concat();
+		let result = Digest::decode_with_limits(&encoded, &DecodeLimits::default());
+		assert_eq!(result, Err(DecodeError::TrailingBytes(1)));
+	}
+}
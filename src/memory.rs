@@ -0,0 +1,273 @@
+//! Memory budget, shared by the components which keep data in memory (e.g. on the mobile and browser deployments).
+//!
+//! Components account their usage against the [`MemoryBudget`]:
+//!
+//! * [`Component::ForkTree`] - imported blocks, imports are rejected while the budget is exceeded
+//! * [`Component::HeaderCache`] - cached entries, which are evicted when the budget is exceeded (see [`Reclaim`])
+//! * [`Component::PendingBodies`], [`Component::SamplingBuffers`] - memory reserved by the in-flight requests, which
+//!   wait until enough memory is released (see [`MemoryBudget::reserve`])
+//!
+//! # Notes
+//!
+//! Usage is estimated from the sizes of the kept values, not from the allocator statistics, so the budget should
+//! leave some headroom for the rest of the client. Budget without the limit only tracks the usage.
+
+use async_trait::async_trait;
+use color_eyre::{eyre::eyre, Result};
+use kate_recovery::{
+	config,
+	data::Cell,
+	matrix::{Dimensions, Position},
+};
+use sp_core::H256;
+use std::{
+	mem,
+	sync::{Arc, Mutex, Weak},
+};
+use tokio::sync::Notify;
+use tracing::debug;
+
+use crate::network::{self, FetchStats};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Component {
+	ForkTree,
+	HeaderCache,
+	PendingBodies,
+	SamplingBuffers,
+}
+
+const COMPONENTS: usize = 4;
+
+/// Component which can release memory on demand, e.g. cache which evicts entries
+pub trait Reclaim: Send + Sync {
+	/// Releases at least `bytes` if possible, returning the number of released bytes
+	fn reclaim(&self, bytes: usize) -> usize;
+}
+
+pub struct MemoryBudget {
+	limit: Option<usize>,
+	usage: Mutex<[usize; COMPONENTS]>,
+	reclaimers: Mutex<Vec<Weak<dyn Reclaim>>>,
+	released: Notify,
+}
+
+impl MemoryBudget {
+	/// Creates budget with the limit in bytes, or without the limit
+	pub fn new(limit: Option<usize>) -> Arc<Self> {
+		Arc::new(MemoryBudget {
+			limit,
+			usage: Mutex::new([0; COMPONENTS]),
+			reclaimers: Mutex::new(vec![]),
+			released: Notify::new(),
+		})
+	}
+
+	pub fn limit(&self) -> Option<usize> {
+		self.limit
+	}
+
+	/// Total usage of all components, in bytes
+	pub fn used(&self) -> usize {
+		self.usage.lock().unwrap().iter().sum()
+	}
+
+	pub fn usage(&self, component: Component) -> usize {
+		self.usage.lock().unwrap()[component as usize]
+	}
+
+	pub fn is_exceeded(&self) -> bool {
+		self.limit.is_some_and(|limit| self.used() > limit)
+	}
+
+	/// Registers component which releases memory when the budget is exceeded, in the registration order
+	pub fn register(&self, reclaimer: Weak<dyn Reclaim>) {
+		self.reclaimers.lock().unwrap().push(reclaimer);
+	}
+
+	/// Asks registered components to release memory, until `bytes` more fit into the budget
+	fn reclaim(&self, bytes: usize) {
+		let Some(limit) = self.limit else {
+			return;
+		};
+		let reclaimers = self.reclaimers.lock().unwrap().clone();
+		for reclaimer in reclaimers.iter().filter_map(Weak::upgrade) {
+			let used = self.used();
+			if used + bytes <= limit {
+				return;
+			}
+			let released = reclaimer.reclaim(used + bytes - limit);
+			debug!(released, "Memory reclaimed");
+		}
+	}
+
+	/// Accounts memory regardless of the limit, releasing memory of the other components if the budget is exceeded
+	pub fn allocate(&self, component: Component, bytes: usize) {
+		self.usage.lock().unwrap()[component as usize] += bytes;
+		if self.is_exceeded() {
+			self.reclaim(0);
+		}
+	}
+
+	/// Accounts memory if it fits into the budget, after releasing memory of the other components if needed
+	pub fn try_allocate(&self, component: Component, bytes: usize) -> bool {
+		self.reclaim(bytes);
+		let mut usage = self.usage.lock().unwrap();
+		let used = usage.iter().sum::<usize>();
+		if self.limit.is_some_and(|limit| used + bytes > limit) {
+			return false;
+		}
+		usage[component as usize] += bytes;
+		true
+	}
+
+	pub fn free(&self, component: Component, bytes: usize) {
+		let mut usage = self.usage.lock().unwrap();
+		let usage = &mut usage[component as usize];
+		*usage = usage.saturating_sub(bytes);
+		self.released.notify_waiters();
+	}
+
+	/// Reserves memory if it fits into the budget, it's released once the reservation is dropped
+	pub fn try_reserve(
+		self: &Arc<Self>,
+		component: Component,
+		bytes: usize,
+	) -> Option<Reservation> {
+		self.try_allocate(component, bytes).then(|| Reservation {
+			budget: self.clone(),
+			component,
+			bytes,
+		})
+	}
+
+	/// Reserves memory, waiting until enough memory is released by the other reservations
+	pub async fn reserve(
+		self: &Arc<Self>,
+		component: Component,
+		bytes: usize,
+	) -> Result<Reservation> {
+		if self.limit.is_some_and(|limit| bytes > limit) {
+			return Err(eyre!(
+				"Reservation of {bytes} bytes exceeds the memory budget"
+			));
+		}
+		loop {
+			// Waiter is created before the check, so releases in between are not missed
+			let released = self.released.notified();
+			if let Some(reservation) = self.try_reserve(component, bytes) {
+				return Ok(reservation);
+			}
+			released.await;
+		}
+	}
+}
+
+/// Reserved memory, which is released on drop
+#[must_use]
+pub struct Reservation {
+	budget: Arc<MemoryBudget>,
+	component: Component,
+	bytes: usize,
+}
+
+impl Reservation {
+	pub fn bytes(&self) -> usize {
+		self.bytes
+	}
+}
+
+impl Drop for Reservation {
+	fn drop(&mut self) {
+		self.budget.free(self.component, self.bytes);
+	}
+}
+
+/// Network client which reserves sampling buffers of the fetched cells in the memory budget
+pub struct BudgetedClient<C> {
+	client: C,
+	budget: Arc<MemoryBudget>,
+}
+
+impl<C> BudgetedClient<C> {
+	pub fn new(client: C, budget: Arc<MemoryBudget>) -> Self {
+		BudgetedClient { client, budget }
+	}
+}
+
+#[async_trait]
+impl<C: network::Client + Send + Sync> network::Client for BudgetedClient<C> {
+	async fn fetch_verified(
+		&self,
+		block_number: u32,
+		block_hash: H256,
+		dimensions: Dimensions,
+		commitments: &[[u8; config::COMMITMENT_SIZE]],
+		positions: &[Position],
+	) -> Result<(Vec<Cell>, Vec<Position>, FetchStats)> {
+		let bytes = positions.len() * mem::size_of::<Cell>();
+		let _reservation = self
+			.budget
+			.reserve(Component::SamplingBuffers, bytes)
+			.await?;
+		self.client
+			.fetch_verified(block_number, block_hash, dimensions, commitments, positions)
+			.await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	struct TestReclaimer {
+		budget: Arc<MemoryBudget>,
+	}
+
+	impl Reclaim for TestReclaimer {
+		fn reclaim(&self, bytes: usize) -> usize {
+			let released = bytes.min(self.budget.usage(Component::HeaderCache));
+			self.budget.free(Component::HeaderCache, released);
+			released
+		}
+	}
+
+	#[test]
+	fn reclaim_when_exceeded() {
+		let budget = MemoryBudget::new(Some(100));
+		let reclaimer: Arc<dyn Reclaim> = Arc::new(TestReclaimer {
+			budget: budget.clone(),
+		});
+		budget.register(Arc::downgrade(&reclaimer));
+
+		budget.allocate(Component::HeaderCache, 80);
+		assert!(budget.try_allocate(Component::ForkTree, 50));
+		assert_eq!(budget.usage(Component::HeaderCache), 50);
+		assert_eq!(budget.used(), 100);
+		// Fork tree memory cannot be reclaimed
+		assert!(budget.try_allocate(Component::ForkTree, 50));
+		assert!(!budget.try_allocate(Component::ForkTree, 1));
+		assert_eq!(budget.usage(Component::ForkTree), 100);
+	}
+
+	#[tokio::test]
+	async fn reserve_with_backpressure() {
+		let budget = MemoryBudget::new(Some(100));
+		let first = budget.reserve(Component::PendingBodies, 60).await.unwrap();
+		assert!(budget.try_reserve(Component::PendingBodies, 60).is_none());
+		assert!(budget.reserve(Component::PendingBodies, 101).await.is_err());
+
+		let waiting = tokio::spawn({
+			let budget = budget.clone();
+			async move { budget.reserve(Component::SamplingBuffers, 60).await }
+		});
+		tokio::time::sleep(Duration::from_millis(10)).await;
+		assert!(!waiting.is_finished());
+		drop(first);
+		let second = waiting.await.unwrap().unwrap();
+		assert_eq!(budget.usage(Component::SamplingBuffers), second.bytes());
+		drop(second);
+		assert_eq!(budget.used(), 0);
+	}
+}
@@ -0,0 +1,441 @@
+//! Verification of the Merkle Mountain Range proofs, and decoding of the MMR leaves.
+//!
+//! Each block appends a leaf with the parent block hash to the chain MMR, and its root is committed with the BEEFY
+//! commitments (see [`crate::beefy`]). Proof of the leaves against the committed root proves inclusion of the
+//! ancient blocks, without the headers in between.
+//!
+//! # Layout
+//!
+//! Nodes are numbered by position in the post-order of the trees, and hashed with Keccak (`keccak256(left ++ right)`).
+//! Peaks are bagged from the right to the left (`keccak256(right ++ left)`). Proof items are the siblings on the
+//! path from the leaves to their peaks, the peaks without proven leaves, and the bagged peaks on the right side of
+//! the last proven leaf, as generated by the `mmr_generateProof` RPC.
+
+use avail_subxt::utils::H256;
+use codec::{Decode, Encode};
+use color_eyre::{eyre::eyre, Result};
+use std::collections::VecDeque;
+
+use crate::eth_bridge::keccak256;
+
+/// Supported major version of the leaf
+const LEAF_MAJOR_VERSION: u8 = 0;
+
+/// Commitment to the next BEEFY authority set
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct BeefyNextAuthoritySet {
+	pub id: u64,
+	pub len: u32,
+	/// Merkle root of the authority addresses
+	pub keyset_commitment: H256,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct MmrLeaf {
+	/// Major version in the upper 3 bits, minor version in the lower 5 bits
+	pub version: u8,
+	pub parent_number_and_hash: (u32, H256),
+	pub beefy_next_authority_set: BeefyNextAuthoritySet,
+	/// Extra data, e.g. the root of the parachain heads
+	pub leaf_extra: H256,
+}
+
+impl MmrLeaf {
+	/// Decodes the opaque leaf, as returned by the RPC
+	pub fn from_opaque(encoded: &[u8]) -> Result<Self> {
+		let mut input = encoded;
+		let leaf = MmrLeaf::decode(&mut input)?;
+		if !input.is_empty() {
+			return Err(eyre!("MMR leaf has {} trailing bytes", input.len()));
+		}
+		let major = leaf.version >> 5;
+		if major != LEAF_MAJOR_VERSION {
+			return Err(eyre!("Unsupported MMR leaf major version {major}"));
+		}
+		Ok(leaf)
+	}
+
+	pub fn parent_number(&self) -> u32 {
+		self.parent_number_and_hash.0
+	}
+
+	pub fn parent_hash(&self) -> H256 {
+		self.parent_number_and_hash.1
+	}
+
+	pub fn hash(&self) -> H256 {
+		keccak256(&self.encode())
+	}
+}
+
+/// Proof of the leaves, encoded as `sp_mmr_primitives::LeafProof`
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct LeafProof {
+	pub leaf_indices: Vec<u64>,
+	pub leaf_count: u64,
+	pub items: Vec<H256>,
+}
+
+fn merge(left: &H256, right: &H256) -> H256 {
+	keccak256(&[left.as_bytes(), right.as_bytes()].concat())
+}
+
+/// Number of nodes in the MMR with the given number of leaves
+pub fn mmr_size(leaf_count: u64) -> Result<u64> {
+	leaf_count
+		.checked_mul(2)
+		.map(|size| size - leaf_count.count_ones() as u64)
+		.ok_or_else(|| eyre!("MMR with {leaf_count} leaves is too large"))
+}
+
+/// Position of the leaf node
+pub fn leaf_index_to_pos(index: u64) -> Result<u64> {
+	let leaf_count = index
+		.checked_add(1)
+		.ok_or_else(|| eyre!("Leaf index {index} is too large"))?;
+	Ok(mmr_size(leaf_count)? - leaf_count.trailing_zeros() as u64 - 1)
+}
+
+fn sibling_offset(height: u32) -> u64 {
+	(2 << height) - 1
+}
+
+fn parent_offset(height: u32) -> u64 {
+	2 << height
+}
+
+/// Height of the node, leaves have height 0
+fn pos_height(pos: u64) -> u32 {
+	let mut pos = pos + 1;
+	let all_ones = |n: u64| n != 0 && n.count_zeros() == n.leading_zeros();
+	while !all_ones(pos) {
+		let most_significant_bit = 1 << (63 - pos.leading_zeros());
+		pos -= most_significant_bit - 1;
+	}
+	63 - pos.leading_zeros()
+}
+
+/// Positions of the peaks, from the left
+pub fn peaks(mmr_size: u64) -> Vec<u64> {
+	if mmr_size == 0 {
+		return vec![];
+	}
+	// Positions are calculated in `u128`, so siblings beyond the largest MMR don't overflow
+	let mmr_size = mmr_size as u128;
+	// Highest peak is the root of the largest full tree which fits
+	let peak_pos = |height: u32| (1u128 << (height + 1)) - 2;
+	let mut height = 0;
+	while peak_pos(height + 1) < mmr_size {
+		height += 1;
+	}
+	let mut pos = peak_pos(height);
+	let mut peaks = vec![pos as u64];
+	loop {
+		// Right sibling of the peak, or its descendants, until within the MMR
+		pos += sibling_offset(height) as u128;
+		while pos > mmr_size - 1 {
+			if height == 0 {
+				return peaks;
+			}
+			pos -= parent_offset(height - 1) as u128;
+			height -= 1;
+		}
+		peaks.push(pos as u64);
+	}
+}
+
+/// Bags peaks from the right to the left
+fn bag_peaks(mut peaks: Vec<H256>) -> Option<H256> {
+	while peaks.len() > 1 {
+		let right = peaks.pop()?;
+		let left = peaks.pop()?;
+		peaks.push(merge(&right, &left));
+	}
+	peaks.pop()
+}
+
+fn peak_root(
+	leaves: Vec<(u64, H256)>,
+	peak_pos: u64,
+	items: &mut impl Iterator<Item = H256>,
+) -> Result<H256> {
+	let invalid = || eyre!("Invalid MMR proof of the peak {peak_pos}");
+	let mut queue = leaves
+		.into_iter()
+		.map(|(pos, hash)| (pos, hash, 0))
+		.collect::<VecDeque<_>>();
+	while let Some((pos, hash, height)) = queue.pop_front() {
+		if pos == peak_pos {
+			return queue.is_empty().then_some(hash).ok_or_else(invalid);
+		}
+		let is_right = pos_height(pos + 1) > height;
+		let sibling_pos = match is_right {
+			true => pos - sibling_offset(height),
+			false => pos + sibling_offset(height),
+		};
+		let sibling = match queue.front() {
+			Some((front_pos, _, _)) if *front_pos == sibling_pos => queue
+				.pop_front()
+				.map(|(_, hash, _)| hash)
+				.ok_or_else(invalid)?,
+			_ => items.next().ok_or_else(invalid)?,
+		};
+		let (parent_pos, parent) = match is_right {
+			true => (pos + 1, merge(&sibling, &hash)),
+			false => (pos + parent_offset(height), merge(&hash, &sibling)),
+		};
+		if parent_pos > peak_pos {
+			return Err(invalid());
+		}
+		queue.push_back((parent_pos, parent, height + 1));
+	}
+	Err(invalid())
+}
+
+/// Calculates MMR root from the leaf hashes at the positions, and the proof items
+fn calculate_root(mut leaves: Vec<(u64, H256)>, mmr_size: u64, items: &[H256]) -> Result<H256> {
+	leaves.sort_by_key(|(pos, _)| *pos);
+	if let Some([(pos, _), _]) = leaves.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+		return Err(eyre!("MMR proof has duplicate leaf position {pos}"));
+	}
+	let mut items = items.iter().copied();
+	let mut peak_hashes = vec![];
+	let mut leaves = leaves.into_iter().peekable();
+	for peak_pos in peaks(mmr_size) {
+		let mut peak_leaves = vec![];
+		while let Some(leaf) = leaves.next_if(|(pos, _)| *pos <= peak_pos) {
+			peak_leaves.push(leaf);
+		}
+		let hash = match peak_leaves.as_slice() {
+			[(pos, hash)] if *pos == peak_pos => *hash,
+			// Peak without proven leaves, or the bagged peaks on the right
+			[] => match items.next() {
+				Some(hash) => hash,
+				None => break,
+			},
+			_ => peak_root(peak_leaves, peak_pos, &mut items)?,
+		};
+		peak_hashes.push(hash);
+	}
+	if leaves.next().is_some() {
+		return Err(eyre!("MMR proof leaves are out of range"));
+	}
+	peak_hashes.extend(items.next());
+	if items.next().is_some() {
+		return Err(eyre!("MMR proof has unused items"));
+	}
+	bag_peaks(peak_hashes).ok_or_else(|| eyre!("MMR proof has no peaks"))
+}
+
+/// Verifies opaque leaves (in the order of the proof leaf indices) against the MMR root.
+pub fn verify_leaf_proof(root: H256, leaves: &[Vec<u8>], proof: &LeafProof) -> Result<()> {
+	if leaves.is_empty() || leaves.len() != proof.leaf_indices.len() {
+		return Err(eyre!(
+			"MMR proof has {} leaf indices for {} leaves",
+			proof.leaf_indices.len(),
+			leaves.len()
+		));
+	}
+	if let Some(index) = proof
+		.leaf_indices
+		.iter()
+		.find(|&&index| index >= proof.leaf_count)
+	{
+		return Err(eyre!(
+			"Leaf index {index} is out of range, MMR has {} leaves",
+			proof.leaf_count
+		));
+	}
+	let mmr_size = mmr_size(proof.leaf_count)?;
+	let hashes = proof
+		.leaf_indices
+		.iter()
+		.zip(leaves)
+		.map(|(&index, leaf)| Ok((leaf_index_to_pos(index)?, keccak256(leaf))))
+		.collect::<Result<_>>()?;
+	let calculated = calculate_root(hashes, mmr_size, &proof.items)?;
+	if calculated != root {
+		return Err(eyre!(
+			"Calculated MMR root {calculated:?} doesn't match root {root:?}"
+		));
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use test_case::test_case;
+
+	/// All MMR nodes, by position
+	fn build(leaves: &[Vec<u8>]) -> Vec<H256> {
+		let mut nodes = vec![];
+		for leaf in leaves {
+			nodes.push(keccak256(leaf));
+			let mut height = 0;
+			while pos_height(nodes.len() as u64) > height {
+				let pos = nodes.len() as u64;
+				let left = nodes[(pos - parent_offset(height)) as usize];
+				let right = nodes[(pos - 1) as usize];
+				nodes.push(merge(&left, &right));
+				height += 1;
+			}
+		}
+		nodes
+	}
+
+	fn root(nodes: &[H256]) -> H256 {
+		let peaks = peaks(nodes.len() as u64)
+			.into_iter()
+			.map(|pos| nodes[pos as usize])
+			.collect();
+		bag_peaks(peaks).unwrap()
+	}
+
+	fn generate_proof(nodes: &[H256], mut positions: Vec<u64>) -> Vec<H256> {
+		positions.sort();
+		let mut items = vec![];
+		let mut rhs_peaks = 0;
+		for peak_pos in peaks(nodes.len() as u64) {
+			let mut queue = positions
+				.iter()
+				.filter(|&&pos| {
+					pos <= peak_pos && pos + parent_offset(pos_height(peak_pos)) > peak_pos
+				})
+				.map(|&pos| (pos, 0))
+				.collect::<VecDeque<_>>();
+			positions.retain(|&pos| pos > peak_pos);
+			if queue.is_empty() {
+				items.push(nodes[peak_pos as usize]);
+				rhs_peaks += 1;
+				continue;
+			}
+			rhs_peaks = 0;
+			while let Some((pos, height)) = queue.pop_front() {
+				if pos == peak_pos {
+					break;
+				}
+				let is_right = pos_height(pos + 1) > height;
+				let (sibling_pos, parent_pos) = match is_right {
+					true => (pos - sibling_offset(height), pos + 1),
+					false => (pos + sibling_offset(height), pos + parent_offset(height)),
+				};
+				if queue.front().map(|(pos, _)| *pos) == Some(sibling_pos) {
+					queue.pop_front();
+				} else {
+					items.push(nodes[sibling_pos as usize]);
+				}
+				queue.push_back((parent_pos, height + 1));
+			}
+		}
+		if rhs_peaks > 1 {
+			let rhs = items.split_off(items.len() - rhs_peaks);
+			items.push(bag_peaks(rhs).unwrap());
+		}
+		items
+	}
+
+	fn leaves(count: u8) -> Vec<Vec<u8>> {
+		(0..count).map(|i| vec![i; 8]).collect()
+	}
+
+	#[test_case(1 => vec![0] ; "single leaf")]
+	#[test_case(7 => vec![6, 9, 10] ; "seven leaves")]
+	#[test_case(8 => vec![14] ; "full tree")]
+	fn mmr_peaks(leaf_count: u64) -> Vec<u64> {
+		peaks(mmr_size(leaf_count).unwrap())
+	}
+
+	#[test]
+	fn leaf_positions() {
+		let positions = (0..7)
+			.map(|index| leaf_index_to_pos(index).unwrap())
+			.collect::<Vec<_>>();
+		assert_eq!(positions, vec![0, 1, 3, 4, 7, 8, 10]);
+	}
+
+	#[test_case(7, vec![0] ; "first leaf")]
+	#[test_case(7, vec![6] ; "last leaf")]
+	#[test_case(7, vec![1, 4] ; "leaves of different peaks")]
+	#[test_case(11, vec![2, 3, 9] ; "multiple leaves")]
+	#[test_case(1, vec![0] ; "single leaf")]
+	fn verify_proof(leaf_count: u8, indices: Vec<u64>) {
+		let leaves = leaves(leaf_count);
+		let nodes = build(&leaves);
+		assert_eq!(nodes.len() as u64, mmr_size(leaf_count as u64).unwrap());
+		let positions = indices
+			.iter()
+			.map(|&index| leaf_index_to_pos(index).unwrap())
+			.collect();
+		let proof = LeafProof {
+			leaf_indices: indices.clone(),
+			leaf_count: leaf_count as u64,
+			items: generate_proof(&nodes, positions),
+		};
+		let proven = indices
+			.iter()
+			.map(|&index| leaves[index as usize].clone())
+			.collect::<Vec<_>>();
+		verify_leaf_proof(root(&nodes), &proven, &proof).unwrap();
+
+		let mut wrong = proven.clone();
+		wrong[0] = vec![99];
+		assert!(verify_leaf_proof(root(&nodes), &wrong, &proof).is_err());
+		let mut extra = proof.clone();
+		extra.items.push(H256::zero());
+		assert!(verify_leaf_proof(root(&nodes), &proven, &extra).is_err());
+	}
+
+	#[test]
+	fn reject_duplicate_leaves() {
+		let leaves = leaves(7);
+		let nodes = build(&leaves);
+		let proof = LeafProof {
+			leaf_indices: vec![1, 1],
+			leaf_count: 7,
+			items: generate_proof(&nodes, vec![leaf_index_to_pos(1).unwrap()]),
+		};
+		// Second leaf would be ignored, if duplicate positions were deduplicated
+		let proven = vec![leaves[1].clone(), vec![99]];
+		assert!(verify_leaf_proof(root(&nodes), &proven, &proof).is_err());
+	}
+
+	#[test]
+	fn reject_oversized_mmr() {
+		assert!(mmr_size(u64::MAX).is_err());
+		assert!(leaf_index_to_pos(u64::MAX).is_err());
+		let size = mmr_size(u64::MAX / 2).unwrap();
+		assert_eq!(peaks(size).len(), 63);
+
+		let proof = LeafProof {
+			leaf_indices: vec![u64::MAX - 1],
+			leaf_count: u64::MAX,
+			items: vec![],
+		};
+		assert!(verify_leaf_proof(H256::zero(), &[vec![0]], &proof).is_err());
+	}
+
+	#[test]
+	fn decode_leaf() {
+		let leaf = MmrLeaf {
+			version: 0b0000_0001,
+			parent_number_and_hash: (41, H256::repeat_byte(1)),
+			beefy_next_authority_set: BeefyNextAuthoritySet {
+				id: 5,
+				len: 4,
+				keyset_commitment: H256::repeat_byte(2),
+			},
+			leaf_extra: H256::repeat_byte(3),
+		};
+		let encoded = leaf.encode();
+		assert_eq!(MmrLeaf::from_opaque(&encoded).unwrap(), leaf);
+		assert_eq!(leaf.hash(), keccak256(&encoded));
+
+		let future = MmrLeaf {
+			version: 0b0010_0000,
+			..leaf
+		};
+		assert!(MmrLeaf::from_opaque(&future.encode()).is_err());
+		assert!(MmrLeaf::from_opaque(&[encoded, vec![0]].concat()).is_err());
+	}
+}
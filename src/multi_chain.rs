@@ -0,0 +1,287 @@
+//! Multiple chains synced in one light client instance.
+//!
+//! Relay chain (e.g. Avail) is synced using the existing light client subsystems, while registered parachains
+//! are tracked through the relay chain: on each finalized relay chain header, parachain heads are fetched with
+//! read proofs of the `Paras::Heads` storage and verified against the relay chain state root.
+//! Networking (RPC client) is shared between all the chains, while sync state is kept per chain.
+//!
+//! # Flow
+//!
+//! * [`run_relay`] drives [`MultiChain::on_relay_finalized`] from the finalized relay chain headers
+//! * Finalized relay chain header is published to the parachain sync tasks
+//! * [`run_parachain`] task per registered parachain verifies its head against the latest finalized relay chain header
+//!
+//! # Notes
+//!
+//! Parachain tasks only see the latest finalized relay chain header, intermediate headers are skipped if the
+//! parachain sync is slower than the relay chain finality.
+
+use avail_subxt::{config::substrate::Digest, primitives::Header, utils::H256};
+use codec::{Decode, Encode};
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use sp_core::{blake2_256, twox_128, twox_64};
+use std::{
+	collections::HashMap,
+	sync::{Arc, Mutex},
+};
+use tokio::sync::{
+	broadcast::{self, error::RecvError},
+	watch,
+};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+	header::HeaderHash,
+	network::rpc::{Client, Event},
+	storage_proof::verify_read_proof,
+};
+
+pub type ParaId = u32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChainId {
+	Relay,
+	Parachain(ParaId),
+}
+
+/// Sync state of a single chain
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChainState {
+	pub finalized_number: Option<u32>,
+	pub finalized_hash: Option<H256>,
+	pub state_root: Option<H256>,
+}
+
+impl ChainState {
+	fn update(&mut self, number: u32, hash: H256, state_root: H256) -> bool {
+		if self
+			.finalized_number
+			.map_or(false, |finalized| number <= finalized)
+		{
+			return false;
+		}
+		self.finalized_number = Some(number);
+		self.finalized_hash = Some(hash);
+		self.state_root = Some(state_root);
+		true
+	}
+}
+
+/// Generic Substrate header, used by parachains
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct ParachainHeader {
+	pub parent_hash: H256,
+	#[codec(compact)]
+	pub number: u32,
+	pub state_root: H256,
+	pub extrinsics_root: H256,
+	pub digest: Digest,
+}
+
+impl ParachainHeader {
+	pub fn hash(&self) -> H256 {
+		Encode::using_encoded(self, blake2_256).into()
+	}
+}
+
+/// Storage key of the parachain head in the relay chain (`Paras::Heads`)
+pub fn para_head_storage_key(para_id: ParaId) -> Vec<u8> {
+	let encoded_id = para_id.encode();
+	[
+		&twox_128(b"Paras")[..],
+		&twox_128(b"Heads")[..],
+		&twox_64(&encoded_id)[..],
+		&encoded_id[..],
+	]
+	.concat()
+}
+
+/// Verifies parachain head against the relay chain state root, using `Paras::Heads` read proof.
+pub fn verify_parachain_head(
+	relay_state_root: H256,
+	para_id: ParaId,
+	proof: Vec<Vec<u8>>,
+) -> Result<ParachainHeader> {
+	let key = para_head_storage_key(para_id);
+	let (_, value) = verify_read_proof(relay_state_root, proof, &[key])?
+		.pop()
+		.ok_or_else(|| eyre!("Parachain head value is missing"))?;
+	let value = value.ok_or_else(|| eyre!("Parachain {para_id} head is not found"))?;
+	// Head data is stored as SCALE encoded byte vector, containing encoded header
+	let head_data = Vec::<u8>::decode(&mut &value[..]).wrap_err("Invalid head data")?;
+	ParachainHeader::decode(&mut &head_data[..]).wrap_err("Invalid parachain header")
+}
+
+#[derive(Clone)]
+pub struct MultiChain {
+	rpc_client: Client,
+	chains: Arc<Mutex<HashMap<ChainId, ChainState>>>,
+	relay_finalized: watch::Sender<Option<Header>>,
+}
+
+impl MultiChain {
+	pub fn new(rpc_client: Client) -> Self {
+		let chains = HashMap::from([(ChainId::Relay, ChainState::default())]);
+		let (relay_finalized, _) = watch::channel(None);
+		MultiChain {
+			rpc_client,
+			chains: Arc::new(Mutex::new(chains)),
+			relay_finalized,
+		}
+	}
+
+	/// Registers parachain to be synced through the relay chain.
+	pub fn register_parachain(&self, para_id: ParaId) {
+		self.chains
+			.lock()
+			.unwrap()
+			.entry(ChainId::Parachain(para_id))
+			.or_default();
+	}
+
+	pub fn parachains(&self) -> Vec<ParaId> {
+		self.chains
+			.lock()
+			.unwrap()
+			.keys()
+			.filter_map(|chain| match chain {
+				ChainId::Relay => None,
+				ChainId::Parachain(para_id) => Some(*para_id),
+			})
+			.collect()
+	}
+
+	pub fn state(&self, chain: ChainId) -> Option<ChainState> {
+		self.chains.lock().unwrap().get(&chain).cloned()
+	}
+
+	fn update(&self, chain: ChainId, number: u32, hash: H256, state_root: H256) -> Result<bool> {
+		let mut chains = self.chains.lock().unwrap();
+		let state = chains
+			.get_mut(&chain)
+			.ok_or_else(|| eyre!("Chain {chain:?} is not registered"))?;
+		Ok(state.update(number, hash, state_root))
+	}
+
+	/// Processes finalized relay chain header, and publishes it to the parachain sync tasks.
+	/// Relay chain header must be verified (e.g. by GRANDPA justification) before calling this function.
+	pub fn on_relay_finalized(&self, header: &Header) -> Result<()> {
+		let hash = header.header_hash();
+		if !self.update(ChainId::Relay, header.number, hash, header.state_root)? {
+			debug!(
+				block_number = header.number,
+				"Relay chain header already finalized"
+			);
+			return Ok(());
+		}
+		self.relay_finalized.send_replace(Some(header.clone()));
+		Ok(())
+	}
+
+	/// Syncs parachain head, verified against the given finalized relay chain header.
+	pub async fn sync_parachain(&self, para_id: ParaId, relay_header: &Header) -> Result<()> {
+		let key = para_head_storage_key(para_id);
+		let proof = self
+			.rpc_client
+			.get_read_proof(vec![key], relay_header.header_hash())
+			.await
+			.wrap_err_with(|| format!("Failed to fetch parachain {para_id} head proof"))?;
+		let para_header = verify_parachain_head(relay_header.state_root, para_id, proof)?;
+		let para_hash = para_header.hash();
+		if self.update(
+			ChainId::Parachain(para_id),
+			para_header.number,
+			para_hash,
+			para_header.state_root,
+		)? {
+			info!(
+				para_id,
+				block_number = para_header.number,
+				relay_block_number = relay_header.number,
+				"Parachain head verified"
+			);
+		} else {
+			debug!(para_id, "Parachain head not changed");
+		}
+		Ok(())
+	}
+}
+
+/// Drives the relay chain sync from the finalized headers, until the events channel is closed.
+pub async fn run_relay(multi_chain: MultiChain, mut rpc_events: broadcast::Receiver<Event>) {
+	info!("Starting relay chain sync...");
+	loop {
+		let header = match rpc_events.recv().await {
+			Ok(Event::HeaderUpdate { header, .. }) => header,
+			Err(RecvError::Lagged(skipped)) => {
+				warn!(skipped, "Finalized headers receiver lagged");
+				continue;
+			},
+			Err(RecvError::Closed) => {
+				error!("Finalized headers channel closed");
+				return;
+			},
+		};
+		if let Err(error) = multi_chain.on_relay_finalized(&header) {
+			error!(
+				block_number = header.number,
+				"Relay chain sync failed: {error:#}"
+			);
+		}
+	}
+}
+
+/// Syncs registered parachain on each finalized relay chain header published by the relay chain sync.
+pub async fn run_parachain(multi_chain: MultiChain, para_id: ParaId) {
+	info!(para_id, "Starting parachain sync...");
+	let mut relay_finalized = multi_chain.relay_finalized.subscribe();
+	while relay_finalized.changed().await.is_ok() {
+		let Some(relay_header) = relay_finalized.borrow_and_update().clone() else {
+			continue;
+		};
+		if let Err(error) = multi_chain.sync_parachain(para_id, &relay_header).await {
+			warn!(para_id, "Parachain sync failed: {error:#}");
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::storage_proof::build_trie;
+
+	fn para_header(number: u32) -> ParachainHeader {
+		ParachainHeader {
+			parent_hash: H256::repeat_byte(1),
+			number,
+			state_root: H256::repeat_byte(2),
+			extrinsics_root: H256::repeat_byte(3),
+			digest: Digest { logs: vec![] },
+		}
+	}
+
+	#[test]
+	fn verify_para_head() {
+		let header = para_header(42);
+		let key = para_head_storage_key(2000);
+		let value = header.encode().encode();
+		let (root, proof) = build_trie(&[(&key, &value)]);
+
+		assert_eq!(
+			verify_parachain_head(root, 2000, proof.clone()).unwrap(),
+			header
+		);
+		assert!(verify_parachain_head(root, 2001, proof).is_err());
+	}
+
+	#[test]
+	fn chain_state_is_monotonic() {
+		let mut state = ChainState::default();
+		assert!(state.update(2, H256::zero(), H256::zero()));
+		assert!(!state.update(1, H256::zero(), H256::zero()));
+		assert_eq!(state.finalized_number, Some(2));
+	}
+}
@@ -10,10 +10,12 @@ use mockall::automock;
 use sp_core::H256;
 use std::{sync::Arc, time::Duration};
 use tokio::time::Instant;
-use tracing::{debug, info};
+use tracing::{debug, info, instrument};
 
-use crate::proof;
+use crate::{proof, verification::WorkerPool};
+use cell_fetcher::CellFetcher;
 
+pub mod cell_fetcher;
 pub mod p2p;
 pub mod rpc;
 
@@ -36,6 +38,8 @@ pub struct FetchStats {
 	pub dht_fetch_duration: f64,
 	pub rpc_fetched: Option<f64>,
 	pub rpc_fetch_duration: Option<f64>,
+	/// Total duration of the cell proof verification, in seconds
+	pub proof_verification_duration: f64,
 }
 
 type RPCFetchStats = (usize, Duration);
@@ -53,106 +57,75 @@ impl FetchStats {
 			dht_fetch_duration: dht_fetch_duration.as_secs_f64(),
 			rpc_fetched: rpc_fetch_stats.map(|(rpc_fetched, _)| rpc_fetched as f64),
 			rpc_fetch_duration: rpc_fetch_stats.map(|(_, duration)| duration.as_secs_f64()),
+			proof_verification_duration: 0.0,
 		}
 	}
 }
 
-struct DHTWithRPCFallbackClient {
+struct DHTWithRPCFallbackClient<F> {
 	p2p_client: p2p::Client,
-	rpc_client: rpc::Client,
+	fallback: F,
 	pp: Arc<PublicParameters>,
+	workers: WorkerPool,
 	disable_rpc: bool,
 }
 
 type Commitments = [[u8; config::COMMITMENT_SIZE]];
 
-impl DHTWithRPCFallbackClient {
-	async fn fetch_verified_from_dht(
-		&self,
-		block_number: u32,
-		dimensions: Dimensions,
-		commitments: &Commitments,
-		positions: &[Position],
-	) -> Result<(Vec<Cell>, Vec<Position>, Duration)> {
-		let begin = Instant::now();
-
-		let (mut dht_fetched, mut unfetched) = self
-			.p2p_client
-			.fetch_cells_from_dht(block_number, positions)
-			.await;
-
-		let fetch_elapsed = begin.elapsed();
-
-		let (verified, mut unverified) = proof::verify(
-			block_number,
-			dimensions,
-			&dht_fetched,
-			commitments,
-			self.pp.clone(),
-		)
-		.await
-		.context("Failed to verify fetched cells")?;
-
-		info!(
-			block_number,
-			cells_total = positions.len(),
-			cells_fetched = dht_fetched.len(),
-			cells_verified = verified.len(),
-			fetch_elapsed = ?fetch_elapsed,
-			proof_verification_elapsed = ?(begin.elapsed() - fetch_elapsed),
-			"Cells fetched from DHT"
-		);
+/// Fetches cells using the fetcher, and verifies them against the commitments.
+/// Returns verified cells and positions of cells which are not fetched or not verified.
+#[allow(clippy::too_many_arguments)]
+async fn fetch_verified_from(
+	fetcher: &(impl CellFetcher + Sync),
+	source: &str,
+	block_number: u32,
+	block_hash: H256,
+	dimensions: Dimensions,
+	commitments: &Commitments,
+	positions: &[Position],
+	pp: Arc<PublicParameters>,
+	workers: &WorkerPool,
+) -> Result<(Vec<Cell>, Vec<Position>, Duration, Duration)> {
+	let begin = Instant::now();
 
-		dht_fetched.retain(|cell| verified.contains(&cell.position));
-		unfetched.append(&mut unverified);
+	let mut fetched = fetcher
+		.fetch_cells(block_number, block_hash, positions)
+		.await?;
+	fetched.retain(|cell| positions.contains(&cell.position));
+	let mut unfetched = positions
+		.iter()
+		.filter(|&position| !fetched.iter().any(|cell| &cell.position == position))
+		.cloned()
+		.collect::<Vec<_>>();
 
-		Ok((dht_fetched, unfetched, fetch_elapsed))
-	}
+	let fetch_elapsed = begin.elapsed();
+	let verification_begin = Instant::now();
 
-	async fn fetch_verified_from_rpc(
-		&self,
-		block_number: u32,
-		block_hash: H256,
-		dimensions: Dimensions,
-		commitments: &Commitments,
-		positions: &[Position],
-	) -> Result<(Vec<Cell>, Vec<Position>, Duration)> {
-		let begin = Instant::now();
+	let (verified, mut unverified) =
+		proof::verify(block_number, dimensions, &fetched, commitments, pp, workers)
+			.await
+			.context("Failed to verify fetched cells")?;
+	let verification_elapsed = verification_begin.elapsed();
 
-		let mut fetched = self
-			.rpc_client
-			.request_kate_proof(block_hash, positions)
-			.await?;
+	info!(
+		block_number,
+		cells_total = positions.len(),
+		cells_fetched = fetched.len(),
+		cells_verified = verified.len(),
+		fetch_elapsed = ?fetch_elapsed,
+		proof_verification_elapsed = ?verification_elapsed,
+		"Cells fetched from {source}"
+	);
 
-		let fetch_elapsed = begin.elapsed();
+	fetched.retain(|cell| verified.contains(&cell.position));
+	unfetched.append(&mut unverified);
 
-		let (verified, unverified) = proof::verify(
-			block_number,
-			dimensions,
-			&fetched,
-			commitments,
-			self.pp.clone(),
-		)
-		.await
-		.context("Failed to verify fetched cells")?;
-
-		info!(
-			block_number,
-			cells_total = positions.len(),
-			cells_fetched = fetched.len(),
-			cells_verified = verified.len(),
-			fetch_elapsed = ?fetch_elapsed,
-			proof_verification_elapsed = ?(begin.elapsed() - fetch_elapsed),
-			"Cells fetched from RPC"
-		);
-
-		fetched.retain(|cell| verified.contains(&cell.position));
-		Ok((fetched, unverified, fetch_elapsed))
-	}
+	Ok((fetched, unfetched, fetch_elapsed, verification_elapsed))
 }
 
 #[async_trait]
-impl Client for DHTWithRPCFallbackClient {
+impl<F: CellFetcher + Send + Sync> Client for DHTWithRPCFallbackClient<F> {
+	#[instrument(skip_all, fields(block_number = block_number, block_hash = %block_hash, cells = positions.len()), level = "debug")]
 	async fn fetch_verified(
 		&self,
 		block_number: u32,
@@ -161,23 +134,46 @@ impl Client for DHTWithRPCFallbackClient {
 		commitments: &Commitments,
 		positions: &[Position],
 	) -> Result<(Vec<Cell>, Vec<Position>, FetchStats)> {
-		let (dht_fetched, unfetched, dht_fetch_duration) = self
-			.fetch_verified_from_dht(block_number, dimensions, commitments, positions)
+		let (dht_fetched, unfetched, dht_fetch_duration, dht_verification_duration) =
+			fetch_verified_from(
+				&self.p2p_client,
+				"DHT",
+				block_number,
+				block_hash,
+				dimensions,
+				commitments,
+				positions,
+				self.pp.clone(),
+				&self.workers,
+			)
 			.await?;
 
+		debug!(
+			block_number,
+			dht_fetched = dht_fetched.len(),
+			?dht_fetch_duration,
+			?dht_verification_duration,
+			"Cells fetched from DHT"
+		);
+
 		if self.disable_rpc {
-			let stats =
+			let mut stats =
 				FetchStats::new(positions.len(), dht_fetched.len(), dht_fetch_duration, None);
+			stats.proof_verification_duration = dht_verification_duration.as_secs_f64();
 			return Ok((dht_fetched, unfetched, stats));
 		};
 
-		let (rpc_fetched, unfetched, rpc_fetch_duration) = self
-			.fetch_verified_from_rpc(
+		let (rpc_fetched, unfetched, rpc_fetch_duration, rpc_verification_duration) =
+			fetch_verified_from(
+				&self.fallback,
+				"RPC",
 				block_number,
 				block_hash,
 				dimensions,
 				commitments,
 				&unfetched,
+				self.pp.clone(),
+				&self.workers,
 			)
 			.await?;
 
@@ -186,15 +182,25 @@ impl Client for DHTWithRPCFallbackClient {
 			.insert_cells_into_dht(block_number, rpc_fetched.clone())
 			.await
 		{
-			debug!("Error inserting cells into DHT: {error}");
+			debug!(block_number, "Error inserting cells into DHT: {error}");
 		}
 
-		let stats = FetchStats::new(
+		debug!(
+			block_number,
+			rpc_fetched = rpc_fetched.len(),
+			?rpc_fetch_duration,
+			?rpc_verification_duration,
+			"Cells fetched from RPC"
+		);
+
+		let mut stats = FetchStats::new(
 			positions.len(),
 			dht_fetched.len(),
 			dht_fetch_duration,
 			Some((rpc_fetched.len(), rpc_fetch_duration)),
 		);
+		stats.proof_verification_duration =
+			(dht_verification_duration + rpc_verification_duration).as_secs_f64();
 
 		let mut fetched = vec![];
 		fetched.extend(dht_fetched);
@@ -208,12 +214,52 @@ pub fn new(
 	p2p_client: p2p::Client,
 	rpc_client: rpc::Client,
 	pp: Arc<PublicParameters>,
+	workers: WorkerPool,
 	disable_rpc: bool,
 ) -> impl Client {
 	DHTWithRPCFallbackClient {
 		p2p_client,
-		rpc_client,
+		fallback: rpc_client,
 		pp,
+		workers,
 		disable_rpc,
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use cell_fetcher::MockCellFetcher;
+	use kate_recovery::testnet;
+	use mockall::predicate::eq;
+
+	#[tokio::test]
+	async fn unfetched_cells_are_returned() {
+		let positions = vec![Position { row: 0, col: 1 }, Position { row: 1, col: 0 }];
+		let mut fetcher = MockCellFetcher::new();
+		fetcher
+			.expect_fetch_cells()
+			.with(eq(1), eq(H256::zero()), eq(positions.clone()))
+			.times(1)
+			.returning(|_, _, _| Box::pin(async move { Ok(vec![]) }));
+
+		let dimensions = Dimensions::new(2, 2).unwrap();
+		let pp = Arc::new(testnet::public_params(1024));
+		let workers = WorkerPool::new(1, 1).unwrap();
+		let (fetched, unfetched, _, _) = fetch_verified_from(
+			&fetcher,
+			"RPC",
+			1,
+			H256::zero(),
+			dimensions,
+			&[[0; config::COMMITMENT_SIZE]; 2],
+			&positions,
+			pp,
+			&workers,
+		)
+		.await
+		.unwrap();
+		assert!(fetched.is_empty());
+		assert_eq!(unfetched, positions);
+	}
+}
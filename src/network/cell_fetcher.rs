@@ -0,0 +1,226 @@
+//! Transport-agnostic fetching of cells and rows of the block.
+//!
+//! [`CellFetcher`] is implemented by the full node RPC client (`kate_queryProof` and `kate_queryRows` methods),
+//! by the P2P client (DHT lookups), and by [`P2pCellFetcher`], which requests cells directly from peers using the
+//! cell exchange protocol.
+//! Fetched cells are not verified, so callers are expected to verify them against the commitments.
+
+use async_trait::async_trait;
+use avail_subxt::utils::H256;
+use color_eyre::Result;
+use futures::future::join_all;
+use kate_recovery::{config, data::Cell, matrix::Position};
+use libp2p::PeerId;
+use mockall::automock;
+use tracing::debug;
+
+use super::{
+	p2p::{
+		self,
+		cell_exchange::{CellRequest, CellResponse, MAX_REQUEST_ENTRIES},
+	},
+	rpc,
+};
+
+#[async_trait]
+#[automock]
+pub trait CellFetcher {
+	/// Fetches cells with proofs, returns only cells which are found
+	async fn fetch_cells(
+		&self,
+		block_number: u32,
+		block_hash: H256,
+		positions: &[Position],
+	) -> Result<Vec<Cell>>;
+
+	/// Fetches rows, in the same order as requested, with `None` for rows which are not found
+	async fn fetch_rows(
+		&self,
+		block_number: u32,
+		block_hash: H256,
+		rows: &[u32],
+	) -> Result<Vec<Option<Vec<u8>>>>;
+}
+
+#[async_trait]
+impl CellFetcher for rpc::Client {
+	async fn fetch_cells(
+		&self,
+		_block_number: u32,
+		block_hash: H256,
+		positions: &[Position],
+	) -> Result<Vec<Cell>> {
+		self.request_kate_proof(block_hash, positions).await
+	}
+
+	async fn fetch_rows(
+		&self,
+		_block_number: u32,
+		block_hash: H256,
+		rows: &[u32],
+	) -> Result<Vec<Option<Vec<u8>>>> {
+		self.request_kate_rows(rows.to_vec(), block_hash).await
+	}
+}
+
+#[async_trait]
+impl CellFetcher for p2p::Client {
+	async fn fetch_cells(
+		&self,
+		block_number: u32,
+		_block_hash: H256,
+		positions: &[Position],
+	) -> Result<Vec<Cell>> {
+		let (fetched, _) = self.fetch_cells_from_dht(block_number, positions).await;
+		Ok(fetched)
+	}
+
+	async fn fetch_rows(
+		&self,
+		block_number: u32,
+		_block_hash: H256,
+		rows: &[u32],
+	) -> Result<Vec<Option<Vec<u8>>>> {
+		let fetch = |&row| self.fetch_row_from_dht(block_number, row);
+		let fetched = join_all(rows.iter().map(fetch)).await;
+		Ok(fetched
+			.into_iter()
+			.map(|row| row.map(|(_, row)| row))
+			.collect())
+	}
+}
+
+/// Fetches cells from the given peers, requesting cells not found on one peer from the next one
+#[derive(Clone)]
+pub struct P2pCellFetcher {
+	client: p2p::Client,
+	peers: Vec<PeerId>,
+}
+
+impl P2pCellFetcher {
+	pub fn new(client: p2p::Client, peers: Vec<PeerId>) -> Self {
+		P2pCellFetcher { client, peers }
+	}
+
+	async fn request(&self, peer_id: PeerId, requests: Vec<CellRequest>) -> Vec<Option<Vec<u8>>> {
+		let mut values = vec![];
+		for request in requests {
+			match self.client.request_cells(peer_id, request).await {
+				Ok(CellResponse(response)) => values.extend(response),
+				Err(error) => {
+					debug!(%peer_id, "Cell exchange request failed: {error:#}");
+					return values;
+				},
+			}
+		}
+		values
+	}
+}
+
+fn cell_from_value(position: Position, value: Vec<u8>) -> Option<Cell> {
+	let content: [u8; config::COMMITMENT_SIZE + config::CHUNK_SIZE] = value.try_into().ok()?;
+	Some(Cell { position, content })
+}
+
+#[async_trait]
+impl CellFetcher for P2pCellFetcher {
+	async fn fetch_cells(
+		&self,
+		block_number: u32,
+		_block_hash: H256,
+		positions: &[Position],
+	) -> Result<Vec<Cell>> {
+		let mut fetched = vec![];
+		let mut remaining = positions.to_vec();
+		for &peer_id in &self.peers {
+			if remaining.is_empty() {
+				break;
+			}
+			let requests = remaining
+				.chunks(MAX_REQUEST_ENTRIES)
+				.map(|positions| CellRequest::Cells {
+					block_number,
+					positions: positions.iter().map(|p| (p.row, p.col)).collect(),
+				})
+				.collect();
+			let values = self.request(peer_id, requests).await;
+			let mut values = values.into_iter();
+			remaining.retain(|&position| {
+				let cell = values
+					.next()
+					.flatten()
+					.and_then(|value| cell_from_value(position, value));
+				let found = cell.is_some();
+				fetched.extend(cell);
+				!found
+			});
+		}
+		Ok(fetched)
+	}
+
+	async fn fetch_rows(
+		&self,
+		block_number: u32,
+		_block_hash: H256,
+		rows: &[u32],
+	) -> Result<Vec<Option<Vec<u8>>>> {
+		let mut fetched = vec![None; rows.len()];
+		for &peer_id in &self.peers {
+			let missing = (0..rows.len())
+				.filter(|&i| fetched[i].is_none())
+				.collect::<Vec<_>>();
+			if missing.is_empty() {
+				break;
+			}
+			let requests = missing
+				.chunks(MAX_REQUEST_ENTRIES)
+				.map(|indices| CellRequest::Rows {
+					block_number,
+					rows: indices.iter().map(|&i| rows[i]).collect(),
+				})
+				.collect();
+			let values = self.request(peer_id, requests).await;
+			for (i, value) in missing.into_iter().zip(values) {
+				fetched[i] = value;
+			}
+		}
+		Ok(fetched)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use mockall::predicate::eq;
+
+	#[test]
+	fn cell_from_invalid_value() {
+		let position = Position { row: 0, col: 1 };
+		assert!(cell_from_value(position, vec![1; 80]).is_some());
+		assert!(cell_from_value(position, vec![1; 32]).is_none());
+	}
+
+	#[tokio::test]
+	async fn mock_fetcher() {
+		let positions = vec![Position { row: 0, col: 1 }];
+		let mut fetcher = MockCellFetcher::new();
+		fetcher
+			.expect_fetch_cells()
+			.with(eq(1), eq(H256::zero()), eq(positions.clone()))
+			.returning(|_, _, positions| {
+				let cells = positions
+					.iter()
+					.map(|&position| Cell {
+						position,
+						content: [0; 80],
+					})
+					.collect();
+				Box::pin(async move { Ok(cells) })
+			});
+		let cells = fetcher
+			.fetch_cells(1, H256::zero(), &positions)
+			.await
+			.unwrap();
+		assert_eq!(cells.len(), 1);
+	}
+}
@@ -1,13 +1,15 @@
 use allow_block_list::BlockedPeers;
-use color_eyre::{eyre::WrapErr, Report, Result};
+use color_eyre::{Report, Result};
+use futures::{AsyncRead, AsyncWrite};
 use libp2p::{
-	autonat, dcutr, identify, identity,
+	autonat,
+	core::{muxing::StreamMuxerBox, transport::Boxed, upgrade},
+	dcutr, dns, identify, identity,
 	kad::{self, PeerRecord, QueryId},
-	mdns, noise, ping, relay,
+	mdns, noise, ping, relay, request_response,
 	swarm::NetworkBehaviour,
-	tcp, upnp, yamux, PeerId, Swarm, SwarmBuilder,
+	tcp, upnp, websocket, yamux, PeerId, Swarm, SwarmBuilder, Transport,
 };
-use multihash::{self, Hasher};
 use std::collections::HashMap;
 use tokio::sync::{
 	mpsc::{self},
@@ -17,14 +19,23 @@ use tracing::info;
 
 #[cfg(feature = "network-analysis")]
 pub mod analyzer;
+pub mod block_announce;
+pub mod cell_exchange;
 mod client;
+pub mod compact_block;
 mod event_loop;
+pub mod grandpa_gossip;
 mod kad_mem_store;
+pub mod light_server_protocol;
+mod network_keypair;
+pub mod rate_limit;
+pub mod signed_record;
 
-use crate::types::{LibP2PConfig, SecretKey};
+use crate::types::{LibP2PConfig, P2PTransport};
 pub use client::Client;
 pub use event_loop::EventLoop;
 pub use kad_mem_store::MemoryStoreConfig;
+pub use network_keypair::NetworkKeypair;
 
 use self::{client::BlockStat, kad_mem_store::MemoryStore};
 use libp2p_allow_block_list as allow_block_list;
@@ -36,12 +47,21 @@ pub enum QueryChannel {
 	Bootstrap(oneshot::Sender<Result<()>>),
 }
 
+type CellResponseSender = oneshot::Sender<Result<cell_exchange::CellResponse>>;
+type LightResponseSender = oneshot::Sender<Result<light_server_protocol::LightResponse>>;
+type CompactBlockResponseSender = oneshot::Sender<Result<compact_block::CompactBlockResponse>>;
+
 pub struct EventLoopEntries<'a> {
 	swarm: &'a mut Swarm<Behaviour>,
 	pending_kad_queries: &'a mut HashMap<QueryId, QueryChannel>,
 	pending_swarm_events: &'a mut HashMap<PeerId, oneshot::Sender<Result<()>>>,
 	/// <block_num, (total_cells, result_cell_counter, time_stat)>
 	active_blocks: &'a mut HashMap<u32, BlockStat>,
+	pending_cell_requests: &'a mut HashMap<request_response::OutboundRequestId, CellResponseSender>,
+	pending_light_requests:
+		&'a mut HashMap<request_response::OutboundRequestId, LightResponseSender>,
+	pending_compact_block_requests:
+		&'a mut HashMap<request_response::OutboundRequestId, CompactBlockResponseSender>,
 }
 
 impl<'a> EventLoopEntries<'a> {
@@ -50,12 +70,27 @@ impl<'a> EventLoopEntries<'a> {
 		pending_kad_queries: &'a mut HashMap<QueryId, QueryChannel>,
 		pending_swarm_events: &'a mut HashMap<PeerId, oneshot::Sender<Result<()>>>,
 		active_blocks: &'a mut HashMap<u32, BlockStat>,
+		pending_cell_requests: &'a mut HashMap<
+			request_response::OutboundRequestId,
+			CellResponseSender,
+		>,
+		pending_light_requests: &'a mut HashMap<
+			request_response::OutboundRequestId,
+			LightResponseSender,
+		>,
+		pending_compact_block_requests: &'a mut HashMap<
+			request_response::OutboundRequestId,
+			CompactBlockResponseSender,
+		>,
 	) -> Self {
 		Self {
 			swarm,
 			pending_kad_queries,
 			pending_swarm_events,
 			active_blocks,
+			pending_cell_requests,
+			pending_light_requests,
+			pending_compact_block_requests,
 		}
 	}
 
@@ -71,6 +106,32 @@ impl<'a> EventLoopEntries<'a> {
 		self.pending_swarm_events.insert(peer_id, result_sender);
 	}
 
+	pub fn insert_cell_request(
+		&mut self,
+		request_id: request_response::OutboundRequestId,
+		result_sender: CellResponseSender,
+	) {
+		self.pending_cell_requests.insert(request_id, result_sender);
+	}
+
+	pub fn insert_light_request(
+		&mut self,
+		request_id: request_response::OutboundRequestId,
+		result_sender: LightResponseSender,
+	) {
+		self.pending_light_requests
+			.insert(request_id, result_sender);
+	}
+
+	pub fn insert_compact_block_request(
+		&mut self,
+		request_id: request_response::OutboundRequestId,
+		result_sender: CompactBlockResponseSender,
+	) {
+		self.pending_compact_block_requests
+			.insert(request_id, result_sender);
+	}
+
 	pub fn behavior_mut(&mut self) -> &mut Behaviour {
 		self.swarm.behaviour_mut()
 	}
@@ -102,6 +163,10 @@ pub struct Behaviour {
 	dcutr: dcutr::Behaviour,
 	upnp: upnp::tokio::Behaviour,
 	blocked_peers: allow_block_list::Behaviour<BlockedPeers>,
+	cell_exchange: request_response::Behaviour<cell_exchange::Codec>,
+	light_server_protocol: request_response::Behaviour<light_server_protocol::Codec>,
+	compact_block: request_response::Behaviour<compact_block::Codec>,
+	grandpa_gossip: request_response::Behaviour<grandpa_gossip::Codec>,
 }
 
 fn generate_config(config: libp2p::swarm::Config, cfg: &LibP2PConfig) -> libp2p::swarm::Config {
@@ -113,11 +178,75 @@ fn generate_config(config: libp2p::swarm::Config, cfg: &LibP2PConfig) -> libp2p:
 		.with_per_connection_event_buffer_size(cfg.per_connection_event_buffer_size)
 }
 
+type BoxedTransport = Boxed<(PeerId, StreamMuxerBox)>;
+type TransportError = Box<dyn std::error::Error + Send + Sync>;
+
+fn tcp_transport() -> tcp::tokio::Transport {
+	tcp::tokio::Transport::new(tcp::Config::default().port_reuse(false).nodelay(false))
+}
+
+// Upgrades stream transport with Noise authentication and Yamux multiplexing
+fn upgrade<T>(transport: T, key: &identity::Keypair) -> Result<BoxedTransport, TransportError>
+where
+	T: Transport + Send + Unpin + 'static,
+	T::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+	T::Error: Send + Sync + 'static,
+	T::Dial: Send + 'static,
+	T::ListenerUpgrade: Send + 'static,
+{
+	Ok(transport
+		.upgrade(upgrade::Version::V1Lazy)
+		.authenticate(noise::Config::new(key)?)
+		.multiplex(yamux::Config::default())
+		.map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+		.boxed())
+}
+
+#[cfg(feature = "webrtc")]
+fn webrtc_transport(key: &identity::Keypair) -> Result<BoxedTransport, TransportError> {
+	let certificate = libp2p_webrtc::tokio::Certificate::generate(&mut rand::thread_rng())?;
+	Ok(
+		libp2p_webrtc::tokio::Transport::new(key.clone(), certificate)
+			.map(|(peer_id, connection), _| (peer_id, StreamMuxerBox::new(connection)))
+			.boxed(),
+	)
+}
+
+#[cfg(not(feature = "webrtc"))]
+fn webrtc_transport(_: &identity::Keypair) -> Result<BoxedTransport, TransportError> {
+	Err("WebRTC transport requires webrtc feature".into())
+}
+
+// Combines configured transports, dialing and listening on each address with the transport which supports it
+fn transport(
+	transports: &[P2PTransport],
+	key: &identity::Keypair,
+) -> Result<BoxedTransport, TransportError> {
+	let mut combined: Option<BoxedTransport> = None;
+	for transport in transports {
+		let next = match transport {
+			P2PTransport::Tcp => upgrade(dns::tokio::Transport::system(tcp_transport())?, key)?,
+			P2PTransport::WebSocket => upgrade(
+				websocket::WsConfig::new(dns::tokio::Transport::system(tcp_transport())?),
+				key,
+			)?,
+			P2PTransport::WebRtc => webrtc_transport(key)?,
+		};
+		combined = Some(match combined {
+			Some(combined) => combined
+				.or_transport(next)
+				.map(|output, _| output.into_inner())
+				.boxed(),
+			None => next,
+		});
+	}
+	combined.ok_or_else(|| "No P2P transport configured".into())
+}
+
 async fn build_swarm(
 	cfg: &LibP2PConfig,
 	id_keys: &libp2p::identity::Keypair,
 	kad_store: MemoryStore,
-	is_ws_transport: bool,
 ) -> Result<Swarm<Behaviour>> {
 	// create Identify Protocol Config
 	let identify_cfg =
@@ -136,10 +265,6 @@ async fn build_swarm(
 
 	// build the Swarm, connecting the lower transport logic with the
 	// higher layer network behaviour logic
-	let tokio_swarm = SwarmBuilder::with_existing_identity(id_keys.clone()).with_tokio();
-
-	let mut swarm;
-
 	let behaviour = |key: &identity::Keypair, relay_client| {
 		Ok(Behaviour {
 			ping: ping::Behaviour::new(ping::Config::new()),
@@ -151,30 +276,20 @@ async fn build_swarm(
 			mdns: mdns::Behaviour::new(mdns::Config::default(), key.public().to_peer_id())?,
 			upnp: upnp::tokio::Behaviour::default(),
 			blocked_peers: allow_block_list::Behaviour::default(),
+			cell_exchange: cell_exchange::behaviour(),
+			light_server_protocol: light_server_protocol::behaviour(cfg.light_server),
+			compact_block: compact_block::behaviour(cfg.light_server),
+			grandpa_gossip: grandpa_gossip::behaviour(cfg.grandpa_gossip),
 		})
 	};
 
-	if is_ws_transport {
-		swarm = tokio_swarm
-			.with_websocket(noise::Config::new, yamux::Config::default)
-			.await?
-			.with_relay_client(noise::Config::new, yamux::Config::default)?
-			.with_behaviour(behaviour)?
-			.with_swarm_config(|c| generate_config(c, cfg))
-			.build();
-	} else {
-		swarm = tokio_swarm
-			.with_tcp(
-				tcp::Config::default().port_reuse(false).nodelay(false),
-				noise::Config::new,
-				yamux::Config::default,
-			)?
-			.with_dns()?
-			.with_relay_client(noise::Config::new, yamux::Config::default)?
-			.with_behaviour(behaviour)?
-			.with_swarm_config(|c| generate_config(c, cfg))
-			.build();
-	}
+	let mut swarm = SwarmBuilder::with_existing_identity(id_keys.clone())
+		.with_tokio()
+		.with_other_transport(|key| transport(&cfg.transports, key))?
+		.with_relay_client(noise::Config::new, yamux::Config::default)?
+		.with_behaviour(behaviour)?
+		.with_swarm_config(|c| generate_config(c, cfg))
+		.build();
 
 	info!("Local peerID: {}", swarm.local_peer_id());
 
@@ -190,28 +305,3 @@ async fn build_swarm(
 
 	Ok(swarm)
 }
-
-// Keypair function creates identity Keypair for a local node.
-// From such generated keypair it derives multihash identifier of the local peer.
-pub fn keypair(cfg: &LibP2PConfig) -> Result<(libp2p::identity::Keypair, String)> {
-	let keypair = match cfg.secret_key.as_ref() {
-		// If seed is provided, generate secret key from seed
-		Some(SecretKey::Seed { seed }) => {
-			let seed_digest = multihash::Sha3_256::digest(seed.as_bytes());
-			identity::Keypair::ed25519_from_bytes(seed_digest)
-				.wrap_err("error generating secret key from seed")?
-		},
-		// Import secret key if provided
-		Some(SecretKey::Key { key }) => {
-			let mut decoded_key = [0u8; 32];
-			hex::decode_to_slice(key.clone().into_bytes(), &mut decoded_key)
-				.wrap_err("error decoding secret key from config")?;
-			identity::Keypair::ed25519_from_bytes(decoded_key)
-				.wrap_err("error importing secret key")?
-		},
-		// If neither seed nor secret key provided, generate secret key from random seed
-		None => identity::Keypair::generate_ed25519(),
-	};
-	let peer_id = PeerId::from(keypair.public()).to_string();
-	Ok((keypair, peer_id))
-}
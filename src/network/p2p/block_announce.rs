@@ -0,0 +1,285 @@
+//! Validation of the block announcements, before the announced blocks are scheduled for download.
+//!
+//! Announcements carry SCALE encoded Avail headers, and are sanity checked without fetching any block data:
+//!
+//! * Header decodes, and extension decodes without trailing bytes
+//! * Header extension is internally consistent (see [`check_extension`])
+//! * Header is sealed
+//! * Block is not a repeat of the recent announcement of the same peer
+//!
+//! Announcements of competing forks can have the same or lower block number, so only repeated hashes are rejected.
+//!
+//! Each failed check costs the announcing peer reputation, and valid announcements slowly restore it. Peers with
+//! reputation below [`BANNED_THRESHOLD`] should be disconnected and blocked.
+
+use avail_subxt::utils::H256;
+use codec::{Decode, Encode};
+use libp2p::PeerId;
+use std::{
+	collections::{HashMap, VecDeque},
+	fmt,
+};
+
+use crate::header::{
+	consistency::{check_extension, ExtensionError, ExtensionLimits},
+	DigestItemRef, HeaderRef,
+};
+
+/// Reputation gained for the valid announcement
+pub const VALID_ANNOUNCE: i32 = 16;
+/// Reputation below which peer is banned
+pub const BANNED_THRESHOLD: i32 = -4096;
+/// Number of recent announcements per peer, checked for repeats
+const RECENT_ANNOUNCES: usize = 32;
+
+/// Block announcement, with the SCALE encoded header
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct BlockAnnounce {
+	pub header: Vec<u8>,
+	pub is_best: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AnnounceError {
+	Malformed(String),
+	InvalidExtension(ExtensionError),
+	MissingSeal,
+	Repeated { number: u32, hash: H256 },
+}
+
+impl AnnounceError {
+	/// Reputation cost of the invalid announcement
+	pub fn cost(&self) -> i32 {
+		match self {
+			AnnounceError::Malformed(_) => -1024,
+			AnnounceError::InvalidExtension(_) => -1024,
+			AnnounceError::MissingSeal => -512,
+			AnnounceError::Repeated { .. } => -128,
+		}
+	}
+}
+
+impl fmt::Display for AnnounceError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			AnnounceError::Malformed(error) => write!(f, "Malformed announcement: {error}"),
+			AnnounceError::InvalidExtension(error) => {
+				write!(f, "Invalid header extension: {error}")
+			},
+			AnnounceError::MissingSeal => write!(f, "Announced header is not sealed"),
+			AnnounceError::Repeated { number, hash } => {
+				write!(f, "Block {number} ({hash:?}) is already announced")
+			},
+		}
+	}
+}
+
+impl std::error::Error for AnnounceError {}
+
+/// Announced block, ready to be scheduled for download
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValidAnnounce {
+	pub number: u32,
+	pub hash: H256,
+	pub is_best: bool,
+}
+
+/// Checks announced header, without checking the announcing peer history
+pub fn check_announce(
+	announce: &BlockAnnounce,
+	limits: &ExtensionLimits,
+) -> Result<ValidAnnounce, AnnounceError> {
+	let header = HeaderRef::decode(&announce.header)
+		.map_err(|error| AnnounceError::Malformed(error.to_string()))?;
+	let extension = header
+		.decode_extension()
+		.map_err(|error| AnnounceError::Malformed(error.to_string()))?;
+	check_extension(&extension, limits).map_err(AnnounceError::InvalidExtension)?;
+	if !header
+		.digest
+		.iter()
+		.any(|item| matches!(item, DigestItemRef::Seal(..)))
+	{
+		return Err(AnnounceError::MissingSeal);
+	}
+	Ok(ValidAnnounce {
+		number: header.number,
+		hash: header.hash(),
+		is_best: announce.is_best,
+	})
+}
+
+#[derive(Default)]
+struct PeerState {
+	reputation: i32,
+	recent: VecDeque<H256>,
+}
+
+/// Validates announcements, keeping track of the announcing peers
+#[derive(Default)]
+pub struct BlockAnnounceValidator {
+	limits: ExtensionLimits,
+	peers: HashMap<PeerId, PeerState>,
+}
+
+impl BlockAnnounceValidator {
+	pub fn new(limits: ExtensionLimits) -> Self {
+		BlockAnnounceValidator {
+			limits,
+			peers: HashMap::new(),
+		}
+	}
+
+	/// Validates announcement and updates the peer reputation
+	pub fn validate(
+		&mut self,
+		peer_id: PeerId,
+		announce: &BlockAnnounce,
+	) -> Result<ValidAnnounce, AnnounceError> {
+		let peer = self.peers.entry(peer_id).or_default();
+		let result = check_announce(announce, &self.limits).and_then(|valid| {
+			if peer.recent.contains(&valid.hash) {
+				return Err(AnnounceError::Repeated {
+					number: valid.number,
+					hash: valid.hash,
+				});
+			}
+			Ok(valid)
+		});
+		match &result {
+			Ok(valid) => {
+				if peer.recent.len() == RECENT_ANNOUNCES {
+					peer.recent.pop_front();
+				}
+				peer.recent.push_back(valid.hash);
+				peer.reputation = peer.reputation.saturating_add(VALID_ANNOUNCE).min(0);
+			},
+			Err(error) => peer.reputation = peer.reputation.saturating_add(error.cost()),
+		}
+		result
+	}
+
+	pub fn reputation(&self, peer_id: &PeerId) -> i32 {
+		self.peers
+			.get(peer_id)
+			.map(|peer| peer.reputation)
+			.unwrap_or_default()
+	}
+
+	pub fn is_banned(&self, peer_id: &PeerId) -> bool {
+		self.reputation(peer_id) < BANNED_THRESHOLD
+	}
+
+	/// Forgets disconnected peer, reputation of the banned peers is kept
+	pub fn remove_peer(&mut self, peer_id: &PeerId) {
+		if !self.is_banned(peer_id) {
+			self.peers.remove(peer_id);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use avail_subxt::{
+		api::runtime_types::avail_core::{
+			data_lookup::compact::CompactDataLookup,
+			header::extension::{v3, HeaderExtension},
+			kate_commitment::v3::KateCommitment,
+		},
+		config::substrate::{Digest, DigestItem},
+		primitives::Header,
+	};
+	use test_case::test_case;
+
+	fn header(number: u32, rows: u16, sealed: bool) -> Vec<u8> {
+		fork_header(number, rows, sealed, 0)
+	}
+
+	fn fork_header(number: u32, rows: u16, sealed: bool, fork: u8) -> Vec<u8> {
+		let logs = match sealed {
+			true => vec![DigestItem::Seal(*b"BABE", vec![1; 64])],
+			false => vec![],
+		};
+		Header {
+			parent_hash: H256::repeat_byte(fork),
+			number,
+			state_root: H256::zero(),
+			extrinsics_root: H256::zero(),
+			digest: Digest { logs },
+			extension: HeaderExtension::V3(v3::HeaderExtension {
+				commitment: KateCommitment {
+					rows,
+					cols: 4,
+					data_root: H256::zero(),
+					commitment: vec![0; 2 * 48],
+				},
+				app_lookup: CompactDataLookup {
+					size: 0,
+					index: vec![],
+				},
+			}),
+		}
+		.encode()
+	}
+
+	fn announce(header: Vec<u8>) -> BlockAnnounce {
+		BlockAnnounce {
+			header,
+			is_best: true,
+		}
+	}
+
+	#[test_case(header(1, 1, true) => Ok(1) ; "valid")]
+	#[test_case(vec![1, 2, 3] => matches Err(AnnounceError::Malformed(_)) ; "malformed header")]
+	#[test_case(header(1, 1, true)[..100].to_vec() => matches Err(AnnounceError::Malformed(_)) ; "truncated extension")]
+	#[test_case(header(1, 3, true) => matches Err(AnnounceError::InvalidExtension(_)) ; "invalid extension")]
+	#[test_case(header(1, 1, false) => Err(AnnounceError::MissingSeal) ; "missing seal")]
+	fn check(header: Vec<u8>) -> Result<u32, AnnounceError> {
+		check_announce(&announce(header), &ExtensionLimits::default()).map(|valid| valid.number)
+	}
+
+	#[test]
+	fn repeated_announcements() {
+		let mut validator = BlockAnnounceValidator::default();
+		let peer_id = PeerId::random();
+		assert!(validator
+			.validate(peer_id, &announce(header(2, 1, true)))
+			.is_ok());
+		assert!(matches!(
+			validator.validate(peer_id, &announce(header(2, 1, true))),
+			Err(AnnounceError::Repeated { number: 2, .. })
+		));
+		assert!(validator
+			.validate(peer_id, &announce(header(3, 1, true)))
+			.is_ok());
+		assert!(validator.reputation(&peer_id) < 0);
+	}
+
+	#[test]
+	fn competing_forks_are_valid() {
+		let mut validator = BlockAnnounceValidator::default();
+		let peer_id = PeerId::random();
+		for header in [
+			fork_header(3, 1, true, 0),
+			fork_header(3, 1, true, 1),
+			fork_header(2, 1, true, 2),
+		] {
+			assert!(validator.validate(peer_id, &announce(header)).is_ok());
+		}
+		assert_eq!(validator.reputation(&peer_id), 0);
+	}
+
+	#[test]
+	fn ban_misbehaving_peer() {
+		let mut validator = BlockAnnounceValidator::default();
+		let peer_id = PeerId::random();
+		for _ in 0..5 {
+			assert!(!validator.is_banned(&peer_id));
+			_ = validator.validate(peer_id, &announce(vec![0]));
+		}
+		assert!(validator.is_banned(&peer_id));
+		validator.remove_peer(&peer_id);
+		assert!(validator.is_banned(&peer_id));
+	}
+}
@@ -0,0 +1,225 @@
+//! Request/response protocol for fetching cells and rows directly from peers.
+//!
+//! Peers serve cells and rows from their local Kademlia store, so light clients which already sampled the block
+//! can serve it to other light clients, without the DHT lookup. Messages are SCALE encoded, and response entries are
+//! in the same order as requested cells or rows, with `None` for entries which are not found.
+//! Signed records are served unwrapped, so responses contain raw cell and row values regardless of record signing.
+
+use async_trait::async_trait;
+use codec::{Decode, Encode};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use kate_recovery::matrix::{Position, RowIndex};
+use libp2p::{
+	kad::{store::RecordStore, RecordKey},
+	request_response, StreamProtocol,
+};
+use std::io;
+
+use super::{kad_mem_store::MemoryStore, signed_record};
+
+pub const PROTOCOL_NAME: StreamProtocol = StreamProtocol::new("/avail/cells/1");
+
+/// Maximum number of cells or rows in a single request
+pub const MAX_REQUEST_ENTRIES: usize = 1024;
+const MAX_REQUEST_SIZE: u64 = 16 * 1024;
+const MAX_RESPONSE_SIZE: u64 = 16 * 1024 * 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum CellRequest {
+	/// Cells at `(row, col)` positions
+	Cells {
+		block_number: u32,
+		positions: Vec<(u32, u16)>,
+	},
+	Rows {
+		block_number: u32,
+		rows: Vec<u32>,
+	},
+}
+
+impl CellRequest {
+	fn references(&self) -> Vec<String> {
+		match self {
+			CellRequest::Cells {
+				block_number,
+				positions,
+			} => positions
+				.iter()
+				.map(|&(row, col)| Position { row, col }.reference(*block_number))
+				.collect(),
+			CellRequest::Rows { block_number, rows } => rows
+				.iter()
+				.map(|&row| RowIndex(row).reference(*block_number))
+				.collect(),
+		}
+	}
+
+	fn len(&self) -> usize {
+		match self {
+			CellRequest::Cells { positions, .. } => positions.len(),
+			CellRequest::Rows { rows, .. } => rows.len(),
+		}
+	}
+}
+
+/// Record values of the requested cells or rows
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct CellResponse(pub Vec<Option<Vec<u8>>>);
+
+/// Serves request from the local store, oversized requests are served with empty response.
+/// If records are signed, values are verified and unwrapped, and records with invalid signature are not served.
+pub fn serve(store: &MemoryStore, request: &CellRequest, signed: bool) -> CellResponse {
+	if request.len() > MAX_REQUEST_ENTRIES {
+		return CellResponse::default();
+	}
+	let values = request
+		.references()
+		.into_iter()
+		.map(|reference| RecordKey::new(&reference))
+		.map(|key| {
+			let record = store.get(&key)?;
+			if signed {
+				signed_record::open(&record).ok()
+			} else {
+				Some(record.value.clone())
+			}
+		})
+		.collect();
+	CellResponse(values)
+}
+
+pub(super) async fn read<T, M>(io: &mut T, limit: u64) -> io::Result<M>
+where
+	T: AsyncRead + Unpin + Send,
+	M: Decode,
+{
+	let mut buffer = vec![];
+	io.take(limit).read_to_end(&mut buffer).await?;
+	M::decode(&mut &buffer[..]).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+pub(super) async fn write<T, M>(io: &mut T, message: M) -> io::Result<()>
+where
+	T: AsyncWrite + Unpin + Send,
+	M: Encode,
+{
+	io.write_all(&message.encode()).await?;
+	io.close().await
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Codec;
+
+#[async_trait]
+impl request_response::Codec for Codec {
+	type Protocol = StreamProtocol;
+	type Request = CellRequest;
+	type Response = CellResponse;
+
+	async fn read_request<T>(&mut self, _: &Self::Protocol, io: &mut T) -> io::Result<CellRequest>
+	where
+		T: AsyncRead + Unpin + Send,
+	{
+		read(io, MAX_REQUEST_SIZE).await
+	}
+
+	async fn read_response<T>(&mut self, _: &Self::Protocol, io: &mut T) -> io::Result<CellResponse>
+	where
+		T: AsyncRead + Unpin + Send,
+	{
+		read(io, MAX_RESPONSE_SIZE).await
+	}
+
+	async fn write_request<T>(
+		&mut self,
+		_: &Self::Protocol,
+		io: &mut T,
+		request: CellRequest,
+	) -> io::Result<()>
+	where
+		T: AsyncWrite + Unpin + Send,
+	{
+		write(io, request).await
+	}
+
+	async fn write_response<T>(
+		&mut self,
+		_: &Self::Protocol,
+		io: &mut T,
+		response: CellResponse,
+	) -> io::Result<()>
+	where
+		T: AsyncWrite + Unpin + Send,
+	{
+		write(io, response).await
+	}
+}
+
+pub fn behaviour() -> request_response::Behaviour<Codec> {
+	request_response::Behaviour::new(
+		[(PROTOCOL_NAME, request_response::ProtocolSupport::Full)],
+		request_response::Config::default(),
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::io::Cursor;
+	use libp2p::{identity::Keypair, kad::Record, PeerId};
+	use request_response::Codec as _;
+
+	#[test]
+	fn serve_from_store() {
+		let mut store = MemoryStore::new(PeerId::random());
+		let record = Record::new(RecordKey::new(&"42:1:2"), vec![7; 80]);
+		store.put(record).unwrap();
+
+		let request = CellRequest::Cells {
+			block_number: 42,
+			positions: vec![(1, 2), (1, 3)],
+		};
+		assert_eq!(
+			serve(&store, &request, false),
+			CellResponse(vec![Some(vec![7; 80]), None])
+		);
+	}
+
+	#[test]
+	fn serve_signed_records_unwrapped() {
+		let keypair = Keypair::generate_ed25519();
+		let mut store = MemoryStore::new(PeerId::random());
+		let record = Record::new(RecordKey::new(&"42:1:2"), vec![7; 80]);
+		store
+			.put(signed_record::sign(&keypair, record).unwrap())
+			.unwrap();
+		let mut forged = Record::new(RecordKey::new(&"42:1:3"), vec![8; 80]);
+		forged.publisher = Some(keypair.public().to_peer_id());
+		store.put(forged).unwrap();
+
+		let request = CellRequest::Cells {
+			block_number: 42,
+			positions: vec![(1, 2), (1, 3)],
+		};
+		assert_eq!(
+			serve(&store, &request, true),
+			CellResponse(vec![Some(vec![7; 80]), None])
+		);
+	}
+
+	#[tokio::test]
+	async fn codec_roundtrip() {
+		let request = CellRequest::Rows {
+			block_number: 42,
+			rows: vec![0, 2],
+		};
+		let mut io = Cursor::new(vec![]);
+		Codec
+			.write_request(&PROTOCOL_NAME, &mut io, request.clone())
+			.await
+			.unwrap();
+		io.set_position(0);
+		let decoded = Codec.read_request(&PROTOCOL_NAME, &mut io).await.unwrap();
+		assert_eq!(decoded, request);
+	}
+}
@@ -1,4 +1,9 @@
-use super::{Command, CommandSender, EventLoopEntries, QueryChannel, SendableCommand};
+use super::{
+	cell_exchange::{CellRequest, CellResponse},
+	compact_block::{CompactBlockRequest, CompactBlockResponse},
+	light_server_protocol::{LightRequest, LightResponse},
+	signed_record, Command, CommandSender, EventLoopEntries, QueryChannel, SendableCommand,
+};
 use color_eyre::{
 	eyre::{eyre, WrapErr},
 	Report, Result,
@@ -10,7 +15,9 @@ use kate_recovery::{
 	matrix::{Dimensions, Position, RowIndex},
 };
 use libp2p::{
+	identity::Keypair,
 	kad::{PeerRecord, Quorum, Record, RecordKey},
+	request_response::ResponseChannel,
 	swarm::dial_opts::DialOpts,
 	Multiaddr, PeerId,
 };
@@ -20,7 +27,7 @@ use std::{
 	time::{Duration, Instant},
 };
 use tokio::sync::oneshot;
-use tracing::{debug, trace};
+use tracing::{debug, instrument, trace};
 
 #[derive(Clone)]
 pub struct Client {
@@ -29,6 +36,8 @@ pub struct Client {
 	dht_parallelization_limit: usize,
 	/// Cell time to live in DHT (in seconds)
 	ttl: u64,
+	/// Keypair used for signing published records, if record signing is enabled
+	record_keypair: Option<Keypair>,
 }
 
 struct DHTCell(Cell);
@@ -152,6 +161,23 @@ impl Command for AddAddress {
 	fn abort(&mut self, _error: Report) {}
 }
 
+struct BlockPeer {
+	peer_id: PeerId,
+}
+
+impl Command for BlockPeer {
+	fn run(&mut self, mut entries: EventLoopEntries) -> Result<()> {
+		entries.behavior_mut().kademlia.remove_peer(&self.peer_id);
+		entries
+			.behavior_mut()
+			.blocked_peers
+			.block_peer(self.peer_id);
+		Ok(())
+	}
+
+	fn abort(&mut self, _error: Report) {}
+}
+
 struct Bootstrap {
 	response_sender: Option<oneshot::Sender<Result<()>>>,
 }
@@ -485,12 +511,161 @@ impl Command for AddAutonatServer {
 	}
 }
 
+struct RequestCells {
+	peer_id: PeerId,
+	request: CellRequest,
+	response_sender: Option<oneshot::Sender<Result<CellResponse>>>,
+}
+
+impl Command for RequestCells {
+	fn run(&mut self, mut entries: EventLoopEntries) -> Result<()> {
+		let request_id = entries
+			.behavior_mut()
+			.cell_exchange
+			.send_request(&self.peer_id, self.request.clone());
+
+		// insert response channel into cell requests pending map
+		let response_sender = self.response_sender.take().unwrap();
+		entries.insert_cell_request(request_id, response_sender);
+		Ok(())
+	}
+
+	fn abort(&mut self, error: Report) {
+		self.response_sender
+			.take()
+			.unwrap()
+			.send(Err(error))
+			.expect("RequestCells receiver dropped");
+	}
+}
+
+struct RequestLight {
+	peer_id: PeerId,
+	request: LightRequest,
+	response_sender: Option<oneshot::Sender<Result<LightResponse>>>,
+}
+
+impl Command for RequestLight {
+	fn run(&mut self, mut entries: EventLoopEntries) -> Result<()> {
+		let request_id = entries
+			.behavior_mut()
+			.light_server_protocol
+			.send_request(&self.peer_id, self.request.clone());
+
+		// insert response channel into light requests pending map
+		let response_sender = self.response_sender.take().unwrap();
+		entries.insert_light_request(request_id, response_sender);
+		Ok(())
+	}
+
+	fn abort(&mut self, error: Report) {
+		self.response_sender
+			.take()
+			.unwrap()
+			.send(Err(error))
+			.expect("RequestLight receiver dropped");
+	}
+}
+
+struct RespondLight {
+	channel: Option<ResponseChannel<LightResponse>>,
+	response: Option<LightResponse>,
+}
+
+impl Command for RespondLight {
+	fn run(&mut self, mut entries: EventLoopEntries) -> Result<()> {
+		let (Some(channel), Some(response)) = (self.channel.take(), self.response.take()) else {
+			return Ok(());
+		};
+		entries
+			.behavior_mut()
+			.light_server_protocol
+			.send_response(channel, response)
+			.map_err(|_| eyre!("Light response channel closed"))
+	}
+
+	fn abort(&mut self, error: Report) {
+		debug!("Light response not sent: {error}");
+	}
+}
+
+struct RequestCompactBlock {
+	peer_id: PeerId,
+	request: CompactBlockRequest,
+	response_sender: Option<oneshot::Sender<Result<CompactBlockResponse>>>,
+}
+
+impl Command for RequestCompactBlock {
+	fn run(&mut self, mut entries: EventLoopEntries) -> Result<()> {
+		let request_id = entries
+			.behavior_mut()
+			.compact_block
+			.send_request(&self.peer_id, self.request.clone());
+
+		// insert response channel into compact block requests pending map
+		let response_sender = self.response_sender.take().unwrap();
+		entries.insert_compact_block_request(request_id, response_sender);
+		Ok(())
+	}
+
+	fn abort(&mut self, error: Report) {
+		self.response_sender
+			.take()
+			.unwrap()
+			.send(Err(error))
+			.expect("RequestCompactBlock receiver dropped");
+	}
+}
+
+struct RespondCompactBlock {
+	channel: Option<ResponseChannel<CompactBlockResponse>>,
+	response: Option<CompactBlockResponse>,
+}
+
+impl Command for RespondCompactBlock {
+	fn run(&mut self, mut entries: EventLoopEntries) -> Result<()> {
+		let (Some(channel), Some(response)) = (self.channel.take(), self.response.take()) else {
+			return Ok(());
+		};
+		entries
+			.behavior_mut()
+			.compact_block
+			.send_response(channel, response)
+			.map_err(|_| eyre!("Compact block response channel closed"))
+	}
+
+	fn abort(&mut self, error: Report) {
+		debug!("Compact block response not sent: {error}");
+	}
+}
+
 impl Client {
 	pub fn new(sender: CommandSender, dht_parallelization_limit: usize, ttl: u64) -> Self {
 		Self {
 			command_sender: sender,
 			dht_parallelization_limit,
 			ttl,
+			record_keypair: None,
+		}
+	}
+
+	/// Enables signing of published records, and verification of fetched records
+	pub fn with_record_signing(mut self, keypair: Keypair) -> Self {
+		self.record_keypair = Some(keypair);
+		self
+	}
+
+	fn seal(&self, record: Record) -> Result<Record> {
+		match &self.record_keypair {
+			Some(keypair) => signed_record::sign(keypair, record),
+			None => Ok(record),
+		}
+	}
+
+	fn unseal(&self, record: Record) -> Result<Vec<u8>> {
+		match self.record_keypair {
+			Some(_) => signed_record::open(&record),
+			None => Ok(record.value),
 		}
 	}
 
@@ -524,6 +699,13 @@ impl Client {
 			.context("failed to add address to the routing table")
 	}
 
+	/// Removes the peer from the routing table, and blocks its connections
+	pub fn block_peer(&self, peer_id: PeerId) -> Result<()> {
+		self.command_sender
+			.send(Box::new(BlockPeer { peer_id }))
+			.context("failed to block peer")
+	}
+
 	pub async fn dial_peer(&self, peer_id: PeerId, peer_address: Multiaddr) -> Result<()> {
 		self.execute_sync(|response_sender| {
 			Box::new(DialPeer {
@@ -660,8 +842,16 @@ impl Client {
 			Ok(peer_record) => {
 				trace!("Fetched cell {reference} from the DHT");
 
+				let value = match self.unseal(peer_record.record) {
+					Ok(value) => value,
+					Err(error) => {
+						debug!("Cell {reference} record is not valid: {error}");
+						return None;
+					},
+				};
+
 				let try_content: Result<[u8; config::COMMITMENT_SIZE + config::CHUNK_SIZE], _> =
-					peer_record.record.value.try_into();
+					value.try_into();
 
 				let Ok(content) = try_content else {
 					debug!("Cannot convert cell {reference} into 80 bytes");
@@ -677,7 +867,7 @@ impl Client {
 		}
 	}
 
-	async fn fetch_row_from_dht(
+	pub(crate) async fn fetch_row_from_dht(
 		&self,
 		block_number: u32,
 		row_index: u32,
@@ -689,7 +879,13 @@ impl Client {
 		trace!("Getting DHT record for reference {}", reference);
 
 		match self.get_kad_record(record_key).await {
-			Ok(peer_record) => Some((row_index.0, peer_record.record.value)),
+			Ok(peer_record) => match self.unseal(peer_record.record) {
+				Ok(value) => Some((row_index.0, value)),
+				Err(error) => {
+					debug!("Row {reference} record is not valid: {error}");
+					None
+				},
+			},
 			Err(error) => {
 				debug!("Row {reference} not found in the DHT: {error}");
 				None
@@ -704,6 +900,7 @@ impl Client {
 	///
 	/// * `block_number` - Block number
 	/// * `positions` - Cell positions to fetch
+	#[instrument(skip_all, fields(block_number = block_number, cells = positions.len()), level = "trace")]
 	pub async fn fetch_cells_from_dht(
 		&self,
 		block_number: u32,
@@ -753,16 +950,16 @@ impl Client {
 		rows
 	}
 
+	#[instrument(skip_all, fields(block_number = block_num, records = records.len()), level = "trace")]
 	async fn insert_into_dht(&self, records: Vec<(String, Record)>, block_num: u32) -> Result<()> {
 		if records.is_empty() {
 			return Err(eyre!("Cant send empty record list."));
 		}
-		self.put_kad_record(
-			records.into_iter().map(|e| e.1).collect(),
-			Quorum::One,
-			block_num,
-		)
-		.await
+		let records = records
+			.into_iter()
+			.map(|(_, record)| self.seal(record))
+			.collect::<Result<Vec<_>>>()?;
+		self.put_kad_record(records, Quorum::One, block_num).await
 	}
 
 	/// Inserts cells into the DHT.
@@ -808,6 +1005,82 @@ impl Client {
 		self.insert_into_dht(records, block).await
 	}
 
+	/// Requests cells or rows directly from the peer, using cell exchange protocol
+	pub async fn request_cells(
+		&self,
+		peer_id: PeerId,
+		request: CellRequest,
+	) -> Result<CellResponse> {
+		self.execute_sync(|response_sender| {
+			Box::new(RequestCells {
+				peer_id,
+				request,
+				response_sender: Some(response_sender),
+			})
+		})
+		.await
+	}
+
+	/// Sends light client request to the peer, which has to run light server
+	pub async fn request_light(
+		&self,
+		peer_id: PeerId,
+		request: LightRequest,
+	) -> Result<LightResponse> {
+		self.execute_sync(|response_sender| {
+			Box::new(RequestLight {
+				peer_id,
+				request,
+				response_sender: Some(response_sender),
+			})
+		})
+		.await
+	}
+
+	/// Responds to the inbound light client request
+	pub fn respond_light(
+		&self,
+		channel: ResponseChannel<LightResponse>,
+		response: LightResponse,
+	) -> Result<()> {
+		self.command_sender
+			.send(Box::new(RespondLight {
+				channel: Some(channel),
+				response: Some(response),
+			}))
+			.context("failed to send light response")
+	}
+
+	/// Sends compact block request to the peer, which has to run light server
+	pub async fn request_compact_block(
+		&self,
+		peer_id: PeerId,
+		request: CompactBlockRequest,
+	) -> Result<CompactBlockResponse> {
+		self.execute_sync(|response_sender| {
+			Box::new(RequestCompactBlock {
+				peer_id,
+				request,
+				response_sender: Some(response_sender),
+			})
+		})
+		.await
+	}
+
+	/// Responds to the inbound compact block request
+	pub fn respond_compact_block(
+		&self,
+		channel: ResponseChannel<CompactBlockResponse>,
+		response: CompactBlockResponse,
+	) -> Result<()> {
+		self.command_sender
+			.send(Box::new(RespondCompactBlock {
+				channel: Some(channel),
+				response: Some(response),
+			}))
+			.context("failed to send compact block response")
+	}
+
 	pub async fn get_multiaddress_and_ip(&self) -> Result<Vec<String>> {
 		let addr = self
 			.get_multiaddress()
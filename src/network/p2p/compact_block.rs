@@ -0,0 +1,608 @@
+//! Request/response protocol for syncing block bodies as compact blocks.
+//!
+//! Most extrinsics of the new block are already known to the node (e.g. from the transaction pool, or from the
+//! recently seen blocks), so instead of the full body, peer sends the header with short extrinsic IDs. Body is
+//! reconstructed from the known extrinsics, and only the unknown ones are requested from the peer.
+//!
+//! # Flow
+//!
+//! * Request [`CompactBlockRequest::Block`] by the block hash
+//! * Create [`PartialBlock`] from the received [`CompactBlock`] and the known extrinsics
+//! * Request missing extrinsics with [`PartialBlock::request`], and add them with [`PartialBlock::fill`]
+//! * Verify the reconstructed body against the header extrinsics root with [`PartialBlock::into_block`]
+//!
+//! [`CompactBlockClient`] runs the flow against the connected peers, with the extrinsics of the recently fetched
+//! blocks as known extrinsics, and falls back to the RPC if no peer serves the block. Requests are served by the
+//! nodes with enabled light server (see [`crate::light_server`]), from the bodies verified against the locally
+//! stored headers.
+//!
+//! # Notes
+//!
+//! Short IDs are keyed with the block hash, so collisions cannot be precomputed ahead of the block. Known
+//! extrinsics with colliding short IDs are not used. If the reconstructed body still doesn't match the extrinsics
+//! root (a collision with an extrinsic not in the block), all extrinsics have to be requested again. Inherents are
+//! never in the pool, so they are sent prefilled.
+
+use async_trait::async_trait;
+use avail_subxt::{primitives::Header, utils::H256};
+use codec::{Decode, Encode};
+use color_eyre::{eyre::eyre, Result};
+use futures::{AsyncRead, AsyncWrite};
+use libp2p::{request_response, PeerId, StreamProtocol};
+use lru::LruCache;
+use sp_core::blake2_256;
+use std::{collections::HashMap, io, num::NonZeroUsize, str::FromStr, sync::Mutex};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+use super::{
+	cell_exchange::{read, write},
+	Client,
+};
+use crate::{block_builder::extrinsics_root, body::Block, verified_rpc};
+
+pub const PROTOCOL_NAME: StreamProtocol = StreamProtocol::new("/avail/compact-block/1");
+
+const MAX_REQUEST_SIZE: u64 = 64 * 1024;
+const MAX_RESPONSE_SIZE: u64 = 16 * 1024 * 1024;
+/// Maximum number of inbound requests waiting for the light server
+pub const MAX_QUEUED_REQUESTS: usize = 128;
+/// Maximum number of peers asked for the block, before falling back
+const MAX_PEERS: usize = 3;
+/// Number of the recently seen extrinsics kept for the reconstruction
+const KNOWN_EXTRINSICS: usize = 4096;
+
+/// Signed flag of the extrinsic version byte
+const SIGNED_FLAG: u8 = 0b1000_0000;
+
+pub type ShortId = [u8; 8];
+
+/// Short ID of the extrinsic in the block, first 8 bytes of the hash keyed with the block hash
+pub fn short_id(block_hash: &H256, extrinsic: &[u8]) -> ShortId {
+	let hash = blake2_256(&[block_hash.as_bytes(), extrinsic].concat());
+	hash[..8].try_into().expect("Hash has at least 8 bytes")
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct CompactBlock {
+	pub header: Header,
+	/// Short IDs of the extrinsics which are not prefilled, in block order
+	pub short_ids: Vec<ShortId>,
+	/// Extrinsics with their index in the block, sorted by index
+	pub prefilled: Vec<(u32, Vec<u8>)>,
+}
+
+impl CompactBlock {
+	/// Creates compact block, with unsigned extrinsics (inherents) prefilled.
+	pub fn new(block: &Block) -> Self {
+		let hash: H256 = Encode::using_encoded(&block.header, blake2_256).into();
+		let mut short_ids = vec![];
+		let mut prefilled = vec![];
+		for (index, extrinsic) in block.extrinsics.iter().enumerate() {
+			let signed = extrinsic
+				.first()
+				.is_some_and(|version| version & SIGNED_FLAG != 0);
+			if signed {
+				short_ids.push(short_id(&hash, extrinsic));
+			} else {
+				prefilled.push((index as u32, extrinsic.clone()));
+			}
+		}
+		CompactBlock {
+			header: block.header.clone(),
+			short_ids,
+			prefilled,
+		}
+	}
+
+	pub fn hash(&self) -> H256 {
+		Encode::using_encoded(&self.header, blake2_256).into()
+	}
+
+	pub fn extrinsics_count(&self) -> usize {
+		self.short_ids.len() + self.prefilled.len()
+	}
+}
+
+/// Returns block extrinsics at the given indices, or `None` if any index is out of range.
+pub fn select_extrinsics(block: &Block, indices: &[u32]) -> Option<Vec<Vec<u8>>> {
+	indices
+		.iter()
+		.map(|&index| block.extrinsics.get(index as usize).cloned())
+		.collect()
+}
+
+enum Slot {
+	Known(Vec<u8>),
+	Missing(ShortId),
+}
+
+/// Block reconstructed from the compact block and the known extrinsics
+pub struct PartialBlock {
+	header: Header,
+	hash: H256,
+	slots: Vec<Slot>,
+}
+
+impl PartialBlock {
+	/// Reconstructs block body from the known extrinsics, leaving unknown extrinsics missing.
+	pub fn new<'a>(
+		compact: CompactBlock,
+		known: impl IntoIterator<Item = &'a [u8]>,
+	) -> Result<Self> {
+		let hash = compact.hash();
+		let count = compact.extrinsics_count();
+
+		// Colliding extrinsics are ambiguous, so they are not used
+		let mut by_short_id = HashMap::<ShortId, Option<&[u8]>>::new();
+		for extrinsic in known {
+			by_short_id
+				.entry(short_id(&hash, extrinsic))
+				.and_modify(|entry| *entry = None)
+				.or_insert(Some(extrinsic));
+		}
+
+		let mut short_ids = compact.short_ids.into_iter();
+		let mut prefilled = compact.prefilled.into_iter().peekable();
+		let mut slots = Vec::with_capacity(count);
+		for index in 0..count {
+			if let Some((_, extrinsic)) = prefilled.next_if(|(i, _)| *i as usize == index) {
+				slots.push(Slot::Known(extrinsic));
+				continue;
+			}
+			let Some(id) = short_ids.next() else {
+				return Err(eyre!("Invalid prefilled extrinsic indices"));
+			};
+			match by_short_id.get(&id) {
+				Some(Some(extrinsic)) => slots.push(Slot::Known(extrinsic.to_vec())),
+				_ => slots.push(Slot::Missing(id)),
+			}
+		}
+		if prefilled.next().is_some() {
+			return Err(eyre!("Invalid prefilled extrinsic indices"));
+		}
+
+		Ok(PartialBlock {
+			header: compact.header,
+			hash,
+			slots,
+		})
+	}
+
+	pub fn hash(&self) -> H256 {
+		self.hash
+	}
+
+	/// Indices of the missing extrinsics
+	pub fn missing(&self) -> Vec<u32> {
+		self.slots
+			.iter()
+			.enumerate()
+			.filter(|(_, slot)| matches!(slot, Slot::Missing(_)))
+			.map(|(index, _)| index as u32)
+			.collect()
+	}
+
+	pub fn is_complete(&self) -> bool {
+		self.slots.iter().all(|slot| matches!(slot, Slot::Known(_)))
+	}
+
+	/// Request of the missing extrinsics, or `None` if block is complete
+	pub fn request(&self) -> Option<CompactBlockRequest> {
+		let indices = self.missing();
+		(!indices.is_empty()).then(|| CompactBlockRequest::Extrinsics {
+			block_hash: self.hash.into(),
+			indices,
+		})
+	}
+
+	/// Adds requested extrinsics, which have to match the short IDs of the missing extrinsics.
+	pub fn fill(&mut self, indices: &[u32], extrinsics: Vec<Vec<u8>>) -> Result<()> {
+		if indices.len() != extrinsics.len() {
+			return Err(eyre!(
+				"Expected {} extrinsics, received {}",
+				indices.len(),
+				extrinsics.len()
+			));
+		}
+		for (&index, extrinsic) in indices.iter().zip(extrinsics) {
+			let Some(slot) = self.slots.get_mut(index as usize) else {
+				return Err(eyre!("Extrinsic index {index} is out of range"));
+			};
+			if let Slot::Missing(id) = slot {
+				if *id != short_id(&self.hash, &extrinsic) {
+					return Err(eyre!("Extrinsic {index} doesn't match its short ID"));
+				}
+			}
+			*slot = Slot::Known(extrinsic);
+		}
+		Ok(())
+	}
+
+	/// Returns block with the body verified against the header extrinsics root.
+	pub fn into_block(self) -> Result<Block> {
+		let extrinsics = self
+			.slots
+			.into_iter()
+			.enumerate()
+			.map(|(index, slot)| match slot {
+				Slot::Known(extrinsic) => Ok(extrinsic),
+				Slot::Missing(_) => Err(eyre!("Extrinsic {index} is missing")),
+			})
+			.collect::<Result<Vec<_>>>()?;
+		let root = extrinsics_root(&extrinsics);
+		if root != self.header.extrinsics_root {
+			return Err(eyre!(
+				"Reconstructed extrinsics root {root:?} doesn't match header extrinsics root {:?}",
+				self.header.extrinsics_root
+			));
+		}
+		Ok(Block {
+			header: self.header,
+			extrinsics,
+		})
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum CompactBlockRequest {
+	Block {
+		block_hash: [u8; 32],
+	},
+	/// Extrinsics of the block at the given indices
+	Extrinsics {
+		block_hash: [u8; 32],
+		indices: Vec<u32>,
+	},
+}
+
+impl CompactBlockRequest {
+	pub fn block_hash(&self) -> H256 {
+		match self {
+			CompactBlockRequest::Block { block_hash }
+			| CompactBlockRequest::Extrinsics { block_hash, .. } => (*block_hash).into(),
+		}
+	}
+}
+
+/// Responses are `None` if the block is not available
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum CompactBlockResponse {
+	Block(Option<CompactBlock>),
+	/// Extrinsics in the requested order
+	Extrinsics(Option<Vec<Vec<u8>>>),
+}
+
+/// Serves request from the requested block, if it is available
+pub fn serve(block: Option<&Block>, request: &CompactBlockRequest) -> CompactBlockResponse {
+	match request {
+		CompactBlockRequest::Block { .. } => {
+			CompactBlockResponse::Block(block.map(CompactBlock::new))
+		},
+		CompactBlockRequest::Extrinsics { indices, .. } => CompactBlockResponse::Extrinsics(
+			block.and_then(|block| select_extrinsics(block, indices)),
+		),
+	}
+}
+
+/// Inbound request, with the channel for the response
+pub struct InboundCompactBlockRequest {
+	pub peer: PeerId,
+	pub request: CompactBlockRequest,
+	pub channel: request_response::ResponseChannel<CompactBlockResponse>,
+}
+
+pub type CompactBlockRequestSender = mpsc::Sender<InboundCompactBlockRequest>;
+pub type CompactBlockRequestReceiver = mpsc::Receiver<InboundCompactBlockRequest>;
+
+pub fn request_channel() -> (CompactBlockRequestSender, CompactBlockRequestReceiver) {
+	mpsc::channel(MAX_QUEUED_REQUESTS)
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Codec;
+
+#[async_trait]
+impl request_response::Codec for Codec {
+	type Protocol = StreamProtocol;
+	type Request = CompactBlockRequest;
+	type Response = CompactBlockResponse;
+
+	async fn read_request<T>(
+		&mut self,
+		_: &Self::Protocol,
+		io: &mut T,
+	) -> io::Result<CompactBlockRequest>
+	where
+		T: AsyncRead + Unpin + Send,
+	{
+		read(io, MAX_REQUEST_SIZE).await
+	}
+
+	async fn read_response<T>(
+		&mut self,
+		_: &Self::Protocol,
+		io: &mut T,
+	) -> io::Result<CompactBlockResponse>
+	where
+		T: AsyncRead + Unpin + Send,
+	{
+		read(io, MAX_RESPONSE_SIZE).await
+	}
+
+	async fn write_request<T>(
+		&mut self,
+		_: &Self::Protocol,
+		io: &mut T,
+		request: CompactBlockRequest,
+	) -> io::Result<()>
+	where
+		T: AsyncWrite + Unpin + Send,
+	{
+		write(io, request).await
+	}
+
+	async fn write_response<T>(
+		&mut self,
+		_: &Self::Protocol,
+		io: &mut T,
+		response: CompactBlockResponse,
+	) -> io::Result<()>
+	where
+		T: AsyncWrite + Unpin + Send,
+	{
+		write(io, response).await
+	}
+}
+
+pub fn behaviour(is_server: bool) -> request_response::Behaviour<Codec> {
+	let support = match is_server {
+		true => request_response::ProtocolSupport::Full,
+		false => request_response::ProtocolSupport::Outbound,
+	};
+	request_response::Behaviour::new(
+		[(PROTOCOL_NAME, support)],
+		request_response::Config::default(),
+	)
+}
+
+/// Block body client, which reconstructs bodies from the compact blocks of the connected peers and the extrinsics
+/// of the recently fetched blocks, and falls back to the given client
+pub struct CompactBlockClient<C> {
+	p2p_client: Client,
+	fallback: C,
+	/// Recently seen extrinsics by their hash
+	known: Mutex<LruCache<H256, Vec<u8>>>,
+}
+
+impl<C: verified_rpc::Client + Sync> CompactBlockClient<C> {
+	pub fn new(p2p_client: Client, fallback: C) -> Self {
+		let capacity = NonZeroUsize::new(KNOWN_EXTRINSICS).expect("Capacity is not zero");
+		CompactBlockClient {
+			p2p_client,
+			fallback,
+			known: Mutex::new(LruCache::new(capacity)),
+		}
+	}
+
+	fn remember(&self, extrinsics: &[Vec<u8>]) {
+		let mut known = self.known.lock().unwrap();
+		for extrinsic in extrinsics {
+			known.put(blake2_256(extrinsic).into(), extrinsic.clone());
+		}
+	}
+
+	/// Creates partial block from the known extrinsics, or without them if `use_known` is false
+	fn partial(&self, compact: CompactBlock, use_known: bool) -> Result<PartialBlock> {
+		if !use_known {
+			return PartialBlock::new(compact, []);
+		}
+		let known = self.known.lock().unwrap();
+		PartialBlock::new(
+			compact,
+			known.iter().map(|(_, extrinsic)| extrinsic.as_slice()),
+		)
+	}
+
+	async fn fill(&self, peer: PeerId, partial: &mut PartialBlock) -> Result<()> {
+		let Some(request) = partial.request() else {
+			return Ok(());
+		};
+		let indices = partial.missing();
+		let response = self.p2p_client.request_compact_block(peer, request).await?;
+		let CompactBlockResponse::Extrinsics(Some(extrinsics)) = response else {
+			return Err(eyre!("Peer {peer} didn't send missing extrinsics"));
+		};
+		partial.fill(&indices, extrinsics)
+	}
+
+	/// Fetches compact block from the peer, and reconstructs the block. If the reconstructed body doesn't match the
+	/// extrinsics root, all extrinsics are requested again.
+	async fn fetch_from(&self, peer: PeerId, block_hash: H256) -> Result<Block> {
+		let request = CompactBlockRequest::Block {
+			block_hash: block_hash.into(),
+		};
+		let response = self.p2p_client.request_compact_block(peer, request).await?;
+		let CompactBlockResponse::Block(Some(compact)) = response else {
+			return Err(eyre!("Peer {peer} doesn't have block {block_hash:?}"));
+		};
+		if compact.hash() != block_hash {
+			return Err(eyre!("Peer {peer} sent block other than {block_hash:?}"));
+		}
+
+		let mut partial = self.partial(compact.clone(), true)?;
+		self.fill(peer, &mut partial).await?;
+		match partial.into_block() {
+			Ok(block) => Ok(block),
+			Err(error) => {
+				debug!(%peer, "Requesting all extrinsics of the block: {error:#}");
+				let mut partial = self.partial(compact, false)?;
+				self.fill(peer, &mut partial).await?;
+				partial.into_block()
+			},
+		}
+	}
+
+	async fn fetch(&self, block_hash: H256) -> Result<Vec<Vec<u8>>> {
+		let peers = self.p2p_client.list_connected_peers().await?;
+		let peers = peers
+			.iter()
+			.filter_map(|peer| PeerId::from_str(peer).ok())
+			.take(MAX_PEERS);
+		for peer in peers {
+			match self.fetch_from(peer, block_hash).await {
+				Ok(block) => return Ok(block.extrinsics),
+				Err(error) => debug!(%peer, "Cannot fetch compact block: {error:#}"),
+			}
+		}
+		Err(eyre!("No peer served compact block {block_hash:?}"))
+	}
+}
+
+#[async_trait]
+impl<C: verified_rpc::Client + Sync + Send> verified_rpc::Client for CompactBlockClient<C> {
+	async fn get_read_proof(&self, keys: Vec<Vec<u8>>, block_hash: H256) -> Result<Vec<Vec<u8>>> {
+		self.fallback.get_read_proof(keys, block_hash).await
+	}
+
+	/// Body fetched from the peers matches the block hash, body fetched from the fallback has to be verified by the
+	/// caller
+	async fn get_block_body(&self, block_hash: H256) -> Result<Vec<Vec<u8>>> {
+		let extrinsics = match self.fetch(block_hash).await {
+			Ok(extrinsics) => extrinsics,
+			Err(error) => {
+				debug!("Fetching block body from the fallback: {error:#}");
+				self.fallback.get_block_body(block_hash).await?
+			},
+		};
+		self.remember(&extrinsics);
+		Ok(extrinsics)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use avail_subxt::{
+		api::runtime_types::avail_core::{
+			data_lookup::compact::CompactDataLookup,
+			header::extension::{v3, HeaderExtension},
+			kate_commitment::v3::KateCommitment,
+		},
+		config::substrate::Digest,
+	};
+	use futures::io::Cursor;
+	use request_response::Codec as _;
+
+	fn block() -> Block {
+		let extrinsics = vec![
+			vec![0x04, 1, 2],
+			vec![SIGNED_FLAG | 0x04, 1],
+			vec![SIGNED_FLAG | 0x04, 2],
+			vec![SIGNED_FLAG | 0x04, 3],
+		];
+		let header = Header {
+			parent_hash: H256::zero(),
+			number: 1,
+			state_root: H256::zero(),
+			extrinsics_root: extrinsics_root(&extrinsics),
+			digest: Digest { logs: vec![] },
+			extension: HeaderExtension::V3(v3::HeaderExtension {
+				commitment: KateCommitment {
+					rows: 1,
+					cols: 4,
+					data_root: H256::zero(),
+					commitment: vec![0; 48],
+				},
+				app_lookup: CompactDataLookup {
+					size: 1,
+					index: vec![],
+				},
+			}),
+		};
+		Block { header, extrinsics }
+	}
+
+	#[test]
+	fn reconstruct_block() {
+		let block = block();
+		let compact = CompactBlock::new(&block);
+		assert_eq!(compact.prefilled.len(), 1);
+		assert_eq!(compact.short_ids.len(), 3);
+
+		let pool = [
+			block.extrinsics[1].clone(),
+			block.extrinsics[3].clone(),
+			vec![9],
+		];
+		let mut partial = PartialBlock::new(compact, pool.iter().map(Vec::as_slice)).unwrap();
+		assert_eq!(partial.missing(), vec![2]);
+
+		let Some(CompactBlockRequest::Extrinsics { indices, .. }) = partial.request() else {
+			panic!("Expected extrinsics request");
+		};
+		assert!(partial.fill(&indices, vec![vec![1]]).is_err());
+		let extrinsics = select_extrinsics(&block, &indices).unwrap();
+		partial.fill(&indices, extrinsics).unwrap();
+		assert!(partial.is_complete());
+		assert_eq!(partial.into_block().unwrap(), block);
+	}
+
+	#[test]
+	fn invalid_compact_block() {
+		let block = block();
+		let mut compact = CompactBlock::new(&block);
+		compact.prefilled[0].0 = 7;
+		assert!(PartialBlock::new(compact, []).is_err());
+
+		let mut compact = CompactBlock::new(&block);
+		compact.header.extrinsics_root = H256::zero();
+		let known = block.extrinsics.iter().map(Vec::as_slice);
+		let partial = PartialBlock::new(compact, known).unwrap();
+		assert!(partial.is_complete());
+		assert!(partial.into_block().is_err());
+	}
+
+	#[test]
+	fn serve_requests() {
+		let block = block();
+		let block_hash = CompactBlock::new(&block).hash().into();
+		let request = CompactBlockRequest::Block { block_hash };
+		assert_eq!(
+			serve(Some(&block), &request),
+			CompactBlockResponse::Block(Some(CompactBlock::new(&block)))
+		);
+		assert_eq!(serve(None, &request), CompactBlockResponse::Block(None));
+
+		let request = CompactBlockRequest::Extrinsics {
+			block_hash,
+			indices: vec![2, 0],
+		};
+		let expected = vec![block.extrinsics[2].clone(), block.extrinsics[0].clone()];
+		assert_eq!(
+			serve(Some(&block), &request),
+			CompactBlockResponse::Extrinsics(Some(expected))
+		);
+		let request = CompactBlockRequest::Extrinsics {
+			block_hash,
+			indices: vec![4],
+		};
+		assert_eq!(
+			serve(Some(&block), &request),
+			CompactBlockResponse::Extrinsics(None)
+		);
+	}
+
+	#[tokio::test]
+	async fn codec_roundtrip() {
+		let response = CompactBlockResponse::Block(Some(CompactBlock::new(&block())));
+		let mut io = Cursor::new(vec![]);
+		Codec
+			.write_response(&PROTOCOL_NAME, &mut io, response.clone())
+			.await
+			.unwrap();
+		io.set_position(0);
+		let decoded = Codec.read_response(&PROTOCOL_NAME, &mut io).await.unwrap();
+		assert_eq!(decoded, response);
+	}
+}
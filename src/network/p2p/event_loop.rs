@@ -1,3 +1,4 @@
+use codec::Encode;
 use color_eyre::{eyre::eyre, Result};
 use futures::StreamExt;
 use libp2p::{
@@ -10,7 +11,7 @@ use libp2p::{
 	},
 	mdns,
 	multiaddr::Protocol,
-	ping,
+	ping, request_response,
 	swarm::{
 		dial_opts::{DialOpts, PeerCondition},
 		ConnectionError, SwarmEvent,
@@ -18,23 +19,37 @@ use libp2p::{
 	upnp, Multiaddr, PeerId, Swarm,
 };
 use rand::seq::SliceRandom;
-use std::{collections::HashMap, str::FromStr, sync::Arc, time::Duration};
+use std::{
+	collections::HashMap,
+	str::FromStr,
+	sync::Arc,
+	time::{Duration, Instant as StdInstant},
+};
 use tokio::{
-	sync::oneshot,
+	sync::{mpsc::error::TrySendError, oneshot},
 	time::{interval_at, Instant, Interval},
 };
 use tracing::{debug, error, info, trace, warn};
 
 use crate::{
-	network::p2p::kad_mem_store::MemoryStore,
+	network::p2p::{
+		cell_exchange,
+		compact_block::{CompactBlockRequestSender, InboundCompactBlockRequest},
+		grandpa_gossip::{GossipMessageSender, InboundGossipMessage},
+		kad_mem_store::MemoryStore,
+		light_server_protocol::{InboundLightRequest, LightRequestSender},
+		rate_limit::RateLimiter,
+		signed_record,
+	},
 	shutdown::Controller,
 	telemetry::{MetricCounter, MetricValue, Metrics},
 	types::{AgentVersion, IdentifyConfig, KademliaMode, LibP2PConfig, TimeToLive},
 };
 
 use super::{
-	build_swarm, client::BlockStat, Behaviour, BehaviourEvent, CommandReceiver, EventLoopEntries,
-	QueryChannel, SendableCommand,
+	build_swarm, client::BlockStat, Behaviour, BehaviourEvent, CellResponseSender, CommandReceiver,
+	CompactBlockResponseSender, EventLoopEntries, LightResponseSender, QueryChannel,
+	SendableCommand,
 };
 
 // RelayState keeps track of all things relay related
@@ -81,6 +96,8 @@ struct EventLoopConfig {
 	identity_data: IdentifyConfig,
 	is_fat_client: bool,
 	kad_record_ttl: TimeToLive,
+	// Reject unsigned or invalid incoming records
+	verify_records: bool,
 }
 
 pub struct EventLoop {
@@ -93,6 +110,21 @@ pub struct EventLoop {
 	bootstrap: BootstrapState,
 	/// Blocks we monitor for PUT success rate
 	active_blocks: HashMap<u32, BlockStat>,
+	// Tracking outbound cell exchange requests
+	pending_cell_requests: HashMap<request_response::OutboundRequestId, CellResponseSender>,
+	// Tracking outbound light client requests
+	pending_light_requests: HashMap<request_response::OutboundRequestId, LightResponseSender>,
+	// Inbound light client requests are forwarded to the light server, if enabled
+	light_requests: Option<LightRequestSender>,
+	// Tracking outbound compact block requests
+	pending_compact_block_requests:
+		HashMap<request_response::OutboundRequestId, CompactBlockResponseSender>,
+	// Inbound compact block requests are forwarded to the light server, if enabled
+	compact_block_requests: Option<CompactBlockRequestSender>,
+	// Inbound GRANDPA gossip messages are forwarded to the observer, if enabled
+	gossip_messages: Option<GossipMessageSender>,
+	// Limits of the inbound requests, response bytes are limited for the cell exchange only
+	rate_limiter: RateLimiter,
 	shutdown: Controller<String>,
 
 	event_loop_config: EventLoopConfig,
@@ -126,14 +158,13 @@ impl EventLoop {
 		cfg: LibP2PConfig,
 		id_keys: &Keypair,
 		is_fat_client: bool,
-		is_ws_transport: bool,
 		shutdown: Controller<String>,
 	) -> Self {
 		let bootstrap_interval = cfg.bootstrap_interval;
 		let peer_id = id_keys.public().to_peer_id();
 		let store = MemoryStore::with_config(peer_id, (&cfg).into());
 
-		let swarm = build_swarm(&cfg, id_keys, store, is_ws_transport)
+		let swarm = build_swarm(&cfg, id_keys, store)
 			.await
 			.expect("Unable to build swarm.");
 
@@ -152,15 +183,41 @@ impl EventLoop {
 				timer: interval_at(Instant::now() + bootstrap_interval, bootstrap_interval),
 			},
 			active_blocks: Default::default(),
+			pending_cell_requests: Default::default(),
+			pending_light_requests: Default::default(),
+			light_requests: None,
+			pending_compact_block_requests: Default::default(),
+			compact_block_requests: None,
+			gossip_messages: None,
+			rate_limiter: RateLimiter::new(cfg.rate_limit),
 			shutdown,
 			event_loop_config: EventLoopConfig {
 				identity_data: cfg.identify,
 				is_fat_client,
 				kad_record_ttl: TimeToLive(cfg.kademlia.kad_record_ttl),
+				verify_records: cfg.kademlia.sign_records,
 			},
 		}
 	}
 
+	/// Forwards inbound light client requests to the light server
+	pub fn with_light_server(mut self, light_requests: LightRequestSender) -> Self {
+		self.light_requests = Some(light_requests);
+		self
+	}
+
+	/// Forwards inbound compact block requests to the light server
+	pub fn with_compact_block_server(mut self, requests: CompactBlockRequestSender) -> Self {
+		self.compact_block_requests = Some(requests);
+		self
+	}
+
+	/// Forwards inbound GRANDPA gossip messages to the observer
+	pub fn with_grandpa_gossip(mut self, messages: GossipMessageSender) -> Self {
+		self.gossip_messages = Some(messages);
+		self
+	}
+
 	pub async fn run(mut self, metrics: Arc<impl Metrics>, mut command_receiver: CommandReceiver) {
 		// shutdown will wait as long as this token is not dropped
 		let _delay_token = self
@@ -234,6 +291,12 @@ impl EventLoop {
 							metrics.count(MetricCounter::IncomingPutRecord).await;
 							match record {
 								Some(mut record) => {
+									if self.event_loop_config.verify_records {
+										if let Err(error) = signed_record::open(&record) {
+											debug!("Rejected record from {source:?}: {error}");
+											return;
+										}
+									}
 									let ttl = &self.event_loop_config.kad_record_ttl;
 
 									// Set TTL for all incoming records
@@ -444,6 +507,233 @@ impl EventLoop {
 						.await;
 				}
 			},
+			SwarmEvent::Behaviour(BehaviourEvent::CellExchange(event)) => match event {
+				request_response::Event::Message { peer, message } => match message {
+					request_response::Message::Request {
+						request_id,
+						request,
+						channel,
+					} => {
+						trace!(%peer, "Cell exchange request received: {request:?}");
+						if let Err(error) = self.rate_limiter.check_request(peer, StdInstant::now())
+						{
+							debug!(%peer, "Cell exchange request dropped: {error}");
+							metrics.count(MetricCounter::RateLimitedRequest).await;
+							return;
+						}
+						let signed = self.event_loop_config.verify_records;
+						let store = self.swarm.behaviour_mut().kademlia.store_mut();
+						let response = cell_exchange::serve(store, &request, signed);
+						if let Err(error) =
+							self.rate_limiter
+								.reserve(request_id, peer, response.encoded_size())
+						{
+							debug!(%peer, "Cell exchange response dropped: {error}");
+							metrics.count(MetricCounter::RateLimitedRequest).await;
+							return;
+						}
+						if self
+							.swarm
+							.behaviour_mut()
+							.cell_exchange
+							.send_response(channel, response)
+							.is_err()
+						{
+							self.rate_limiter.release(&request_id);
+							debug!(%peer, "Cell exchange response channel closed");
+						}
+					},
+					request_response::Message::Response {
+						request_id,
+						response,
+					} => {
+						if let Some(ch) = self.pending_cell_requests.remove(&request_id) {
+							_ = ch.send(Ok(response));
+						}
+					},
+				},
+				request_response::Event::OutboundFailure {
+					peer,
+					request_id,
+					error,
+				} => {
+					trace!(%peer, "Cell exchange request failed: {error}");
+					if let Some(ch) = self.pending_cell_requests.remove(&request_id) {
+						_ = ch.send(Err(error.into()));
+					}
+				},
+				request_response::Event::InboundFailure {
+					peer,
+					request_id,
+					error,
+				} => {
+					self.rate_limiter.release(&request_id);
+					trace!(%peer, "Cell exchange response failed: {error}");
+				},
+				request_response::Event::ResponseSent { request_id, .. } => {
+					self.rate_limiter.release(&request_id);
+				},
+			},
+			SwarmEvent::Behaviour(BehaviourEvent::LightServerProtocol(event)) => match event {
+				request_response::Event::Message { peer, message } => match message {
+					request_response::Message::Request {
+						request, channel, ..
+					} => {
+						trace!(%peer, "Light client request received: {request:?}");
+						if let Err(error) = self.rate_limiter.check_request(peer, StdInstant::now())
+						{
+							debug!(%peer, "Light client request dropped: {error}");
+							metrics.count(MetricCounter::RateLimitedRequest).await;
+							return;
+						}
+						let Some(light_requests) = &self.light_requests else {
+							debug!(%peer, "Light server is disabled, request dropped");
+							return;
+						};
+						let request = InboundLightRequest {
+							peer,
+							request,
+							channel,
+						};
+						match light_requests.try_send(request) {
+							Ok(()) => {},
+							Err(TrySendError::Full(_)) => {
+								debug!(%peer, "Light server is busy, request dropped");
+								metrics.count(MetricCounter::RateLimitedRequest).await;
+							},
+							Err(TrySendError::Closed(_)) => {
+								debug!(%peer, "Light server stopped, request dropped");
+							},
+						}
+					},
+					request_response::Message::Response {
+						request_id,
+						response,
+					} => {
+						if let Some(ch) = self.pending_light_requests.remove(&request_id) {
+							_ = ch.send(Ok(response));
+						}
+					},
+				},
+				request_response::Event::OutboundFailure {
+					peer,
+					request_id,
+					error,
+				} => {
+					trace!(%peer, "Light client request failed: {error}");
+					if let Some(ch) = self.pending_light_requests.remove(&request_id) {
+						_ = ch.send(Err(error.into()));
+					}
+				},
+				request_response::Event::InboundFailure { peer, error, .. } => {
+					trace!(%peer, "Light client response failed: {error}");
+				},
+				request_response::Event::ResponseSent { .. } => {},
+			},
+			SwarmEvent::Behaviour(BehaviourEvent::CompactBlock(event)) => match event {
+				request_response::Event::Message { peer, message } => match message {
+					request_response::Message::Request {
+						request, channel, ..
+					} => {
+						trace!(%peer, "Compact block request received: {request:?}");
+						if let Err(error) = self.rate_limiter.check_request(peer, StdInstant::now())
+						{
+							debug!(%peer, "Compact block request dropped: {error}");
+							metrics.count(MetricCounter::RateLimitedRequest).await;
+							return;
+						}
+						let Some(compact_block_requests) = &self.compact_block_requests else {
+							debug!(%peer, "Light server is disabled, request dropped");
+							return;
+						};
+						let request = InboundCompactBlockRequest {
+							peer,
+							request,
+							channel,
+						};
+						match compact_block_requests.try_send(request) {
+							Ok(()) => {},
+							Err(TrySendError::Full(_)) => {
+								debug!(%peer, "Light server is busy, request dropped");
+								metrics.count(MetricCounter::RateLimitedRequest).await;
+							},
+							Err(TrySendError::Closed(_)) => {
+								debug!(%peer, "Light server stopped, request dropped");
+							},
+						}
+					},
+					request_response::Message::Response {
+						request_id,
+						response,
+					} => {
+						if let Some(ch) = self.pending_compact_block_requests.remove(&request_id) {
+							_ = ch.send(Ok(response));
+						}
+					},
+				},
+				request_response::Event::OutboundFailure {
+					peer,
+					request_id,
+					error,
+				} => {
+					trace!(%peer, "Compact block request failed: {error}");
+					if let Some(ch) = self.pending_compact_block_requests.remove(&request_id) {
+						_ = ch.send(Err(error.into()));
+					}
+				},
+				request_response::Event::InboundFailure { peer, error, .. } => {
+					trace!(%peer, "Compact block response failed: {error}");
+				},
+				request_response::Event::ResponseSent { .. } => {},
+			},
+			SwarmEvent::Behaviour(BehaviourEvent::GrandpaGossip(event)) => match event {
+				request_response::Event::Message {
+					peer,
+					message: request_response::Message::Request {
+						request, channel, ..
+					},
+				} => {
+					// Messages are acknowledged regardless of the validation, which is done by the observer
+					_ = self
+						.swarm
+						.behaviour_mut()
+						.grandpa_gossip
+						.send_response(channel, ());
+					if let Err(error) = self.rate_limiter.check_request(peer, StdInstant::now()) {
+						debug!(%peer, "GRANDPA gossip message dropped: {error}");
+						metrics.count(MetricCounter::RateLimitedRequest).await;
+						return;
+					}
+					let Some(gossip_messages) = &self.gossip_messages else {
+						debug!(%peer, "GRANDPA gossip observer is disabled, message dropped");
+						return;
+					};
+					let message = InboundGossipMessage {
+						peer,
+						message: request,
+					};
+					match gossip_messages.try_send(message) {
+						Ok(()) => {},
+						Err(TrySendError::Full(_)) => {
+							debug!(%peer, "GRANDPA gossip observer is busy, message dropped");
+						},
+						Err(TrySendError::Closed(_)) => {
+							debug!(%peer, "GRANDPA gossip observer stopped, message dropped");
+						},
+					}
+				},
+				request_response::Event::Message {
+					message: request_response::Message::Response { .. },
+					..
+				} => {},
+				request_response::Event::OutboundFailure { peer, error, .. } => {
+					trace!(%peer, "GRANDPA gossip message not sent: {error}");
+				},
+				request_response::Event::InboundFailure { peer, error, .. } => {
+					trace!(%peer, "GRANDPA gossip message not received: {error}");
+				},
+				request_response::Event::ResponseSent { .. } => {},
+			},
 			SwarmEvent::Behaviour(BehaviourEvent::Upnp(event)) => match event {
 				upnp::Event::NewExternalAddr(addr) => {
 					trace!("[UPnP] New external address: {addr}");
@@ -476,6 +766,9 @@ impl EventLoop {
 							// remove peer with failed connection
 							self.swarm.behaviour_mut().kademlia.remove_peer(&peer_id);
 						}
+						if num_established == 0 {
+							self.rate_limiter.prune(StdInstant::now());
+						}
 					},
 					SwarmEvent::IncomingConnection { .. } => {
 						metrics.count(MetricCounter::IncomingConnection).await;
@@ -489,8 +782,11 @@ impl EventLoop {
 							address.to_string()
 						);
 					},
-					SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+					SwarmEvent::ConnectionEstablished {
+						peer_id, endpoint, ..
+					} => {
 						metrics.count(MetricCounter::ConnectionEstablished).await;
+						trace!(%peer_id, address = %endpoint.get_remote_address(), "Connection established");
 						// Notify the connections we're waiting on that we've connected successfully
 						if let Some(ch) = self.pending_swarm_events.remove(&peer_id) {
 							_ = ch.send(Ok(()));
@@ -540,6 +836,9 @@ impl EventLoop {
 			&mut self.pending_kad_queries,
 			&mut self.pending_swarm_events,
 			&mut self.active_blocks,
+			&mut self.pending_cell_requests,
+			&mut self.pending_light_requests,
+			&mut self.pending_compact_block_requests,
 		)) {
 			command.abort(eyre!(err));
 		}
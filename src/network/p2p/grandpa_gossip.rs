@@ -0,0 +1,944 @@
+//! Observation of the GRANDPA gossip, to follow finality as the validators vote.
+//!
+//! Messages are SCALE encoded as the Substrate GRANDPA gossip messages:
+//!
+//! * Vote - signed prevote, precommit or primary propose of the voter in the round
+//! * Commit - precommits justifying the finalized block, sent when the round completes
+//! * Neighbor - view (set ID, round and finalized height) of the sending peer
+//!
+//! Observer keeps the view of the current validator set, and tracks votes of the current and the previous round.
+//! Block is reported as finalized once a commit with the supermajority of valid precommits is received, or the
+//! supermajority of voters precommit for the same block in the round. Ancestry of the votes is not known to the
+//! observer, so only precommits for the exact target are counted.
+//!
+//! Invalid messages cost the sending peer reputation, same as the invalid block announcements (see
+//! [`super::block_announce`]). Catch-up messages are sent only on request, and the observer never requests them.
+//!
+//! # Flow
+//!
+//! * Peers send gossip messages with the [`PROTOCOL_NAME`] protocol, each message is acknowledged with an empty
+//!   response
+//! * Event loop forwards inbound messages to the [`run`] task, which validates them with the [`GrandpaGossip`]
+//! * Precommits of the finalized block are collected into the justification, which is sent to the RPC subscription
+//!   loop, and verified there against the validator set of the header, same as the justifications of the RPC node
+//! * Validator set is switched once the next epoch is stored, and peers below [`BANNED_THRESHOLD`] are blocked
+//!
+//! # Notes
+//!
+//! Protocol is specific to Avail light clients, and is not wire compatible with the Substrate notifications
+//! protocol. Messages are not relayed to the other peers.
+
+use async_trait::async_trait;
+use avail_subxt::utils::H256;
+use codec::{Decode, Encode};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::{request_response, PeerId, StreamProtocol};
+use sp_core::{ed25519, Pair};
+use std::{
+	collections::{BTreeMap, HashMap, HashSet},
+	fmt, io,
+};
+use tokio::sync::{
+	broadcast::{self, error::RecvError},
+	mpsc,
+};
+use tracing::{debug, error, info, warn};
+
+use super::{
+	block_announce::BANNED_THRESHOLD,
+	cell_exchange::{read, write},
+	Client,
+};
+use crate::{
+	data::Database,
+	epochs,
+	finality::{is_signed_by_supermajority, ValidatorSet},
+	network::rpc::Event,
+	types::{Commit, GrandpaJustification, Precommit, SignedPrecommit},
+};
+
+/// Reputation gained for the useful message
+pub const USEFUL_MESSAGE: i32 = 8;
+/// Maximum number of rounds ahead of the local round, for which votes are kept
+pub const MAX_ROUNDS_AHEAD: u64 = 4;
+
+pub const PROTOCOL_NAME: StreamProtocol = StreamProtocol::new("/avail/grandpa-gossip/1");
+
+/// Maximum size of the encoded gossip message, fits the commit of a thousand voters
+const MAX_MESSAGE_SIZE: u64 = 256 * 1024;
+/// Maximum number of inbound messages waiting for the observer
+pub const MAX_QUEUED_MESSAGES: usize = 1024;
+/// Maximum number of justifications waiting for the RPC subscription loop
+pub const MAX_QUEUED_JUSTIFICATIONS: usize = 16;
+
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct Prevote {
+	pub target_hash: H256,
+	pub target_number: u32,
+}
+
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct PrimaryPropose {
+	pub target_hash: H256,
+	pub target_number: u32,
+}
+
+#[derive(Clone, Debug, Encode, Decode)]
+pub enum Message {
+	Prevote(Prevote),
+	Precommit(Precommit),
+	PrimaryPropose(PrimaryPropose),
+}
+
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct SignedMessage {
+	pub message: Message,
+	pub signature: ed25519::Signature,
+	pub id: ed25519::Public,
+}
+
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct VoteMessage {
+	pub round: u64,
+	pub set_id: u64,
+	pub message: SignedMessage,
+}
+
+/// Commit with signatures and voter IDs in the same order as the precommits
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct CompactCommit {
+	pub target_hash: H256,
+	pub target_number: u32,
+	pub precommits: Vec<Precommit>,
+	pub auth_data: Vec<(ed25519::Signature, ed25519::Public)>,
+}
+
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct FullCommitMessage {
+	pub round: u64,
+	pub set_id: u64,
+	pub message: CompactCommit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct NeighborPacket {
+	pub round: u64,
+	pub set_id: u64,
+	pub commit_finalized_height: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum VersionedNeighborPacket {
+	V1(NeighborPacket),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct CatchUpRequest {
+	pub round: u64,
+	pub set_id: u64,
+}
+
+#[derive(Clone, Debug, Encode, Decode)]
+pub enum GossipMessage {
+	Vote(VoteMessage),
+	Commit(FullCommitMessage),
+	Neighbor(VersionedNeighborPacket),
+	CatchUpRequest(CatchUpRequest),
+}
+
+/// Encoding of the signed message, as signed by the voter
+pub fn signing_payload(message: &Message, round: u64, set_id: u64) -> Vec<u8> {
+	(message, round, set_id).encode()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GossipError {
+	Malformed(String),
+	/// Message of the past set or round
+	Stale {
+		set_id: u64,
+		round: u64,
+	},
+	/// Message of the future set, or vote too far ahead of the local round. Local view can be behind the peers, so
+	/// future messages cost nothing.
+	Future {
+		set_id: u64,
+		round: u64,
+	},
+	UnknownVoter(ed25519::Public),
+	BadSignature(ed25519::Public),
+	BadCommit(String),
+	/// Neighbor packet with the view behind the previous one
+	NeighborRegression,
+}
+
+impl GossipError {
+	/// Reputation cost of the invalid message
+	pub fn cost(&self) -> i32 {
+		match self {
+			GossipError::Malformed(_) => -1024,
+			GossipError::Stale { .. } => -32,
+			GossipError::Future { .. } => 0,
+			GossipError::UnknownVoter(_) => -512,
+			GossipError::BadSignature(_) => -1024,
+			GossipError::BadCommit(_) => -1024,
+			GossipError::NeighborRegression => -256,
+		}
+	}
+}
+
+impl fmt::Display for GossipError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			GossipError::Malformed(error) => write!(f, "Malformed gossip message: {error}"),
+			GossipError::Stale { set_id, round } => {
+				write!(f, "Stale message of set {set_id}, round {round}")
+			},
+			GossipError::Future { set_id, round } => {
+				write!(f, "Future message of set {set_id}, round {round}")
+			},
+			GossipError::UnknownVoter(id) => write!(f, "Unknown voter {id:?}"),
+			GossipError::BadSignature(id) => write!(f, "Invalid signature of voter {id:?}"),
+			GossipError::BadCommit(error) => write!(f, "Invalid commit: {error}"),
+			GossipError::NeighborRegression => write!(f, "Neighbor view regressed"),
+		}
+	}
+}
+
+impl std::error::Error for GossipError {}
+
+/// View of the validator set progress, local or of the peer
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct View {
+	pub set_id: u64,
+	pub round: u64,
+	pub finalized: u32,
+}
+
+impl From<NeighborPacket> for View {
+	fn from(packet: NeighborPacket) -> Self {
+		View {
+			set_id: packet.set_id,
+			round: packet.round,
+			finalized: packet.commit_finalized_height,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GossipEvent {
+	Finalized {
+		set_id: u64,
+		round: u64,
+		hash: H256,
+		number: u32,
+	},
+	/// Peer view is updated by the neighbor packet
+	PeerView { peer_id: PeerId, view: View },
+}
+
+#[derive(Default)]
+struct RoundVotes {
+	prevotes: HashMap<ed25519::Public, H256>,
+	precommits: HashMap<ed25519::Public, SignedPrecommit>,
+	completed: bool,
+}
+
+impl RoundVotes {
+	/// Returns precommit target with the supermajority of votes
+	fn precommit_supermajority(&self, voters: usize) -> Option<(H256, u32)> {
+		let mut counts = HashMap::<(H256, u32), usize>::new();
+		for signed in self.precommits.values() {
+			let target = (signed.precommit.target_hash, signed.precommit.target_number);
+			*counts.entry(target).or_default() += 1;
+		}
+		counts
+			.into_iter()
+			.find(|(_, count)| is_signed_by_supermajority(*count, voters))
+			.map(|(target, _)| target)
+	}
+
+	/// Signed precommits for the exact target
+	fn precommits_for(&self, hash: H256) -> Vec<SignedPrecommit> {
+		self.precommits
+			.values()
+			.filter(|signed| signed.precommit.target_hash == hash)
+			.cloned()
+			.collect()
+	}
+}
+
+#[derive(Default)]
+struct PeerState {
+	reputation: i32,
+	view: Option<View>,
+}
+
+/// Validates gossip messages of the current validator set, tracking rounds and the views of the peers
+pub struct GrandpaGossip {
+	validator_set: ValidatorSet,
+	view: View,
+	rounds: BTreeMap<u64, RoundVotes>,
+	peers: HashMap<PeerId, PeerState>,
+	/// Justification of the last finalized block, built from the verified precommits
+	justification: Option<GrandpaJustification>,
+}
+
+impl GrandpaGossip {
+	/// Creates observer of the validator set, starting at the given round and finalized block
+	pub fn new(validator_set: ValidatorSet, round: u64, finalized: u32) -> Self {
+		let view = View {
+			set_id: validator_set.set_id,
+			round,
+			finalized,
+		};
+		GrandpaGossip {
+			validator_set,
+			view,
+			rounds: BTreeMap::new(),
+			peers: HashMap::new(),
+			justification: None,
+		}
+	}
+
+	pub fn view(&self) -> View {
+		self.view
+	}
+
+	/// Neighbor packet with the local view, to be sent to the peers
+	pub fn neighbor_packet(&self) -> GossipMessage {
+		GossipMessage::Neighbor(VersionedNeighborPacket::V1(NeighborPacket {
+			round: self.view.round,
+			set_id: self.view.set_id,
+			commit_finalized_height: self.view.finalized,
+		}))
+	}
+
+	/// Advances the local view to the block finalized by other means (e.g. the RPC node justification)
+	pub fn set_finalized(&mut self, number: u32) {
+		self.view.finalized = self.view.finalized.max(number);
+	}
+
+	/// Takes justification of the last finalized block, reported with [`GossipEvent::Finalized`]
+	pub fn take_justification(&mut self) -> Option<GrandpaJustification> {
+		self.justification.take()
+	}
+
+	/// Switches to the new validator set, which starts voting from the first round
+	pub fn set_validators(&mut self, validator_set: ValidatorSet) {
+		if validator_set.set_id <= self.view.set_id {
+			return;
+		}
+		self.view.set_id = validator_set.set_id;
+		self.view.round = 1;
+		self.validator_set = validator_set;
+		self.rounds.clear();
+	}
+
+	/// Decodes and validates the message, updating the peer reputation
+	pub fn validate(
+		&mut self,
+		peer_id: PeerId,
+		encoded: &[u8],
+	) -> Result<Option<GossipEvent>, GossipError> {
+		let result = GossipMessage::decode(&mut &encoded[..])
+			.map_err(|error| GossipError::Malformed(error.to_string()))
+			.and_then(|message| self.handle(peer_id, message));
+		let peer = self.peers.entry(peer_id).or_default();
+		match &result {
+			Ok(Some(_)) => peer.reputation = peer.reputation.saturating_add(USEFUL_MESSAGE).min(0),
+			Ok(None) => (),
+			Err(error) => {
+				debug!(%peer_id, %error, "Invalid GRANDPA gossip message");
+				peer.reputation = peer.reputation.saturating_add(error.cost());
+			},
+		}
+		result
+	}
+
+	fn handle(
+		&mut self,
+		peer_id: PeerId,
+		message: GossipMessage,
+	) -> Result<Option<GossipEvent>, GossipError> {
+		match message {
+			GossipMessage::Vote(vote) => self.handle_vote(vote),
+			GossipMessage::Commit(commit) => self.handle_commit(commit),
+			GossipMessage::Neighbor(VersionedNeighborPacket::V1(packet)) => {
+				let view = View::from(packet);
+				let peer = self.peers.entry(peer_id).or_default();
+				if peer.view.is_some_and(|previous| view < previous) {
+					return Err(GossipError::NeighborRegression);
+				}
+				peer.view = Some(view);
+				Ok(Some(GossipEvent::PeerView { peer_id, view }))
+			},
+			GossipMessage::CatchUpRequest(_) => Ok(None),
+		}
+	}
+
+	/// Checks round of the message, rounds ahead of the local round are limited only for the votes, since commits are
+	/// verified on their own and move the local round forward
+	fn check_round(&self, set_id: u64, round: u64, is_vote: bool) -> Result<(), GossipError> {
+		if set_id < self.view.set_id || (set_id == self.view.set_id && round + 1 < self.view.round)
+		{
+			return Err(GossipError::Stale { set_id, round });
+		}
+		if set_id > self.view.set_id || (is_vote && round > self.view.round + MAX_ROUNDS_AHEAD) {
+			return Err(GossipError::Future { set_id, round });
+		}
+		Ok(())
+	}
+
+	fn check_signature(
+		&self,
+		message: &Message,
+		signature: &ed25519::Signature,
+		id: &ed25519::Public,
+		round: u64,
+	) -> Result<(), GossipError> {
+		if !self.validator_set.validator_set.contains(id) {
+			return Err(GossipError::UnknownVoter(*id));
+		}
+		let payload = signing_payload(message, round, self.view.set_id);
+		if !<ed25519::Pair as Pair>::verify(signature, payload, id) {
+			return Err(GossipError::BadSignature(*id));
+		}
+		Ok(())
+	}
+
+	fn handle_vote(&mut self, vote: VoteMessage) -> Result<Option<GossipEvent>, GossipError> {
+		let VoteMessage {
+			round,
+			set_id,
+			message,
+		} = vote;
+		self.check_round(set_id, round, true)?;
+		self.check_signature(&message.message, &message.signature, &message.id, round)?;
+
+		let voters = self.validator_set.validator_set.len();
+		let votes = self.rounds.entry(round).or_default();
+		match message.message {
+			Message::Prevote(prevote) => {
+				votes.prevotes.insert(message.id, prevote.target_hash);
+			},
+			Message::Precommit(precommit) => {
+				let signed = SignedPrecommit {
+					precommit,
+					signature: message.signature,
+					id: message.id,
+				};
+				votes.precommits.insert(message.id, signed);
+			},
+			Message::PrimaryPropose(_) => (),
+		}
+		if votes.completed {
+			return Ok(None);
+		}
+		let Some((hash, number)) = votes.precommit_supermajority(voters) else {
+			return Ok(None);
+		};
+		votes.completed = true;
+		let precommits = votes.precommits_for(hash);
+		Ok(self.complete_round(round, hash, number, precommits))
+	}
+
+	fn handle_commit(
+		&mut self,
+		commit: FullCommitMessage,
+	) -> Result<Option<GossipEvent>, GossipError> {
+		let FullCommitMessage {
+			round,
+			set_id,
+			message: commit,
+		} = commit;
+		self.check_round(set_id, round, false)?;
+		if commit.precommits.len() != commit.auth_data.len() {
+			return Err(GossipError::BadCommit(format!(
+				"{} precommits with {} signatures",
+				commit.precommits.len(),
+				commit.auth_data.len()
+			)));
+		}
+		let mut voters = HashSet::new();
+		let mut precommits = vec![];
+		for (precommit, (signature, id)) in commit.precommits.iter().zip(&commit.auth_data) {
+			if precommit.target_number < commit.target_number {
+				return Err(GossipError::BadCommit(format!(
+					"Precommit for block {} is below commit target {}",
+					precommit.target_number, commit.target_number
+				)));
+			}
+			let message = Message::Precommit(precommit.clone());
+			self.check_signature(&message, signature, id, round)?;
+			let is_new_voter = voters.insert(*id);
+			// Precommits for the descendants of the target are valid, but cannot be verified without the ancestry
+			if is_new_voter && precommit.target_hash == commit.target_hash {
+				precommits.push(SignedPrecommit {
+					precommit: precommit.clone(),
+					signature: *signature,
+					id: *id,
+				});
+			}
+		}
+		let voters_count = self.validator_set.validator_set.len();
+		if !is_signed_by_supermajority(voters.len(), voters_count) {
+			return Err(GossipError::BadCommit(format!(
+				"Signed by {} of {voters_count} voters",
+				voters.len(),
+			)));
+		}
+		if !is_signed_by_supermajority(precommits.len(), voters_count) {
+			debug!(
+				round,
+				"Commit without the supermajority of precommits for the exact target"
+			);
+			return Ok(None);
+		}
+		self.rounds.entry(round).or_default().completed = true;
+		Ok(self.complete_round(round, commit.target_hash, commit.target_number, precommits))
+	}
+
+	/// Advances the local view past the completed round, returning finalized event of the new finalized block
+	fn complete_round(
+		&mut self,
+		round: u64,
+		hash: H256,
+		number: u32,
+		precommits: Vec<SignedPrecommit>,
+	) -> Option<GossipEvent> {
+		self.view.round = self.view.round.max(round + 1);
+		// Votes of the current and the previous round are kept
+		let oldest = self.view.round.saturating_sub(1);
+		self.rounds.retain(|&round, _| round >= oldest);
+		if number <= self.view.finalized {
+			return None;
+		}
+		self.view.finalized = number;
+		self.justification = Some(GrandpaJustification {
+			round,
+			commit: Commit {
+				target_hash: hash,
+				target_number: number,
+				precommits,
+			},
+			votes_ancestries: vec![],
+		});
+		Some(GossipEvent::Finalized {
+			set_id: self.view.set_id,
+			round,
+			hash,
+			number,
+		})
+	}
+
+	/// Number of the prevoting and precommitting voters in the tracked round
+	pub fn round_votes(&self, round: u64) -> Option<(usize, usize)> {
+		self.rounds
+			.get(&round)
+			.map(|votes| (votes.prevotes.len(), votes.precommits.len()))
+	}
+
+	pub fn peer_view(&self, peer_id: &PeerId) -> Option<View> {
+		self.peers.get(peer_id).and_then(|peer| peer.view)
+	}
+
+	pub fn reputation(&self, peer_id: &PeerId) -> i32 {
+		self.peers
+			.get(peer_id)
+			.map(|peer| peer.reputation)
+			.unwrap_or_default()
+	}
+
+	pub fn is_banned(&self, peer_id: &PeerId) -> bool {
+		self.reputation(peer_id) < BANNED_THRESHOLD
+	}
+
+	/// Forgets disconnected peer, reputation of the banned peers is kept
+	pub fn remove_peer(&mut self, peer_id: &PeerId) {
+		if !self.is_banned(peer_id) {
+			self.peers.remove(peer_id);
+		}
+	}
+}
+
+/// Inbound gossip message, SCALE encoded [`GossipMessage`] which is not decoded yet
+pub struct InboundGossipMessage {
+	pub peer: PeerId,
+	pub message: Vec<u8>,
+}
+
+pub type GossipMessageSender = mpsc::Sender<InboundGossipMessage>;
+pub type GossipMessageReceiver = mpsc::Receiver<InboundGossipMessage>;
+
+pub fn message_channel() -> (GossipMessageSender, GossipMessageReceiver) {
+	mpsc::channel(MAX_QUEUED_MESSAGES)
+}
+
+/// Gossip messages are sent as requests, and acknowledged with the empty responses
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Codec;
+
+#[async_trait]
+impl request_response::Codec for Codec {
+	type Protocol = StreamProtocol;
+	type Request = Vec<u8>;
+	type Response = ();
+
+	async fn read_request<T>(&mut self, _: &Self::Protocol, io: &mut T) -> io::Result<Vec<u8>>
+	where
+		T: AsyncRead + Unpin + Send,
+	{
+		// Message is validated by the observer, so malformed messages cost the peer reputation
+		let mut message = vec![];
+		io.take(MAX_MESSAGE_SIZE).read_to_end(&mut message).await?;
+		Ok(message)
+	}
+
+	async fn read_response<T>(&mut self, _: &Self::Protocol, io: &mut T) -> io::Result<()>
+	where
+		T: AsyncRead + Unpin + Send,
+	{
+		read(io, 0).await
+	}
+
+	async fn write_request<T>(
+		&mut self,
+		_: &Self::Protocol,
+		io: &mut T,
+		message: Vec<u8>,
+	) -> io::Result<()>
+	where
+		T: AsyncWrite + Unpin + Send,
+	{
+		io.write_all(&message).await?;
+		io.close().await
+	}
+
+	async fn write_response<T>(&mut self, _: &Self::Protocol, io: &mut T, _: ()) -> io::Result<()>
+	where
+		T: AsyncWrite + Unpin + Send,
+	{
+		write(io, ()).await
+	}
+}
+
+/// Inbound messages are accepted only if the observer is enabled
+pub fn behaviour(is_observer: bool) -> request_response::Behaviour<Codec> {
+	let support = match is_observer {
+		true => request_response::ProtocolSupport::Full,
+		false => request_response::ProtocolSupport::Outbound,
+	};
+	request_response::Behaviour::new(
+		[(PROTOCOL_NAME, support)],
+		request_response::Config::default(),
+	)
+}
+
+/// Observes the GRANDPA gossip, and sends justifications of the finalized blocks to the RPC subscription loop.
+/// Observer starts with the latest stored epoch, and follows the finalized headers of the RPC node.
+///
+/// # Arguments
+///
+/// * `db` - Database with the stored epochs
+/// * `p2p_client` - P2P client, used to block the peers with the bad reputation
+/// * `messages` - Inbound gossip messages, forwarded by the event loop
+/// * `rpc_events` - Finalized headers, verified by the RPC subscription loop
+/// * `justifications` - Sender of the justifications, received by the RPC subscription loop
+pub async fn run(
+	db: impl Database,
+	p2p_client: Client,
+	mut messages: GossipMessageReceiver,
+	mut rpc_events: broadcast::Receiver<Event>,
+	justifications: mpsc::Sender<GrandpaJustification>,
+) {
+	let epoch = match epochs::latest_epoch(&db) {
+		Ok(Some(epoch)) => epoch,
+		Ok(None) => {
+			warn!("No epoch is stored, GRANDPA gossip is not observed");
+			return;
+		},
+		Err(error) => {
+			error!("Cannot get latest epoch: {error:#}");
+			return;
+		},
+	};
+	let validator_set = ValidatorSet {
+		set_id: epoch.set_id,
+		validator_set: epoch.validator_set,
+	};
+	// Round of the current set is not known, so it's learned from the commits (up to the maximum rounds ahead)
+	let mut gossip = GrandpaGossip::new(validator_set, 1, epoch.start_block);
+	info!(set_id = epoch.set_id, "Observing GRANDPA gossip...");
+
+	loop {
+		tokio::select! {
+			message = messages.recv() => {
+				let Some(InboundGossipMessage { peer, message }) = message else {
+					info!("GRANDPA gossip channel closed");
+					return;
+				};
+				if let Ok(Some(GossipEvent::Finalized { number, hash, .. })) =
+					gossip.validate(peer, &message)
+				{
+					debug!(block_number = number, %hash, "Block finalized by GRANDPA gossip");
+					let Some(justification) = gossip.take_justification() else {
+						continue;
+					};
+					if justifications.try_send(justification).is_err() {
+						debug!(block_number = number, "Gossip justification dropped");
+					}
+				}
+				if gossip.is_banned(&peer) {
+					warn!(%peer, "Blocking peer with invalid GRANDPA gossip");
+					if let Err(error) = p2p_client.block_peer(peer) {
+						warn!(%peer, "Cannot block peer: {error:#}");
+					}
+				}
+			},
+			event = rpc_events.recv() => match event {
+				Ok(Event::HeaderUpdate { header, .. }) => {
+					gossip.set_finalized(header.number);
+					match epochs::latest_epoch(&db) {
+						Ok(Some(epoch)) => gossip.set_validators(ValidatorSet {
+							set_id: epoch.set_id,
+							validator_set: epoch.validator_set,
+						}),
+						Ok(None) => (),
+						Err(error) => warn!("Cannot get latest epoch: {error:#}"),
+					}
+				},
+				Err(RecvError::Lagged(skipped)) => {
+					warn!(skipped, "Finalized headers receiver lagged");
+				},
+				Err(RecvError::Closed) => {
+					error!("Finalized headers channel closed");
+					return;
+				},
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::finality::check_finality;
+	use futures::io::Cursor;
+	use request_response::Codec as _;
+	use test_case::test_case;
+
+	const SET_ID: u64 = 3;
+
+	fn voters() -> Vec<ed25519::Pair> {
+		(0..4u8)
+			.map(|i| ed25519::Pair::from_seed(&[i; 32]))
+			.collect()
+	}
+
+	fn validator_set() -> ValidatorSet {
+		ValidatorSet {
+			set_id: SET_ID,
+			validator_set: voters().iter().map(|pair| pair.public()).collect(),
+		}
+	}
+
+	fn gossip() -> GrandpaGossip {
+		GrandpaGossip::new(validator_set(), 10, 100)
+	}
+
+	fn precommit(number: u32) -> Precommit {
+		Precommit {
+			target_hash: H256::repeat_byte(number as u8),
+			target_number: number,
+		}
+	}
+
+	fn vote(voter: &ed25519::Pair, round: u64, message: Message) -> Vec<u8> {
+		let signature = voter.sign(&signing_payload(&message, round, SET_ID));
+		GossipMessage::Vote(VoteMessage {
+			round,
+			set_id: SET_ID,
+			message: SignedMessage {
+				message,
+				signature,
+				id: voter.public(),
+			},
+		})
+		.encode()
+	}
+
+	fn commit(voters: &[ed25519::Pair], round: u64, number: u32) -> Vec<u8> {
+		let precommits = vec![precommit(number); voters.len()];
+		let auth_data = voters
+			.iter()
+			.map(|voter| {
+				let message = Message::Precommit(precommit(number));
+				let signature = voter.sign(&signing_payload(&message, round, SET_ID));
+				(signature, voter.public())
+			})
+			.collect();
+		GossipMessage::Commit(FullCommitMessage {
+			round,
+			set_id: SET_ID,
+			message: CompactCommit {
+				target_hash: precommit(number).target_hash,
+				target_number: number,
+				precommits,
+				auth_data,
+			},
+		})
+		.encode()
+	}
+
+	#[test]
+	fn finality_from_precommits() {
+		let mut gossip = gossip();
+		let peer_id = PeerId::random();
+		let voters = voters();
+		for voter in &voters[..2] {
+			let message = vote(voter, 10, Message::Precommit(precommit(105)));
+			assert_eq!(gossip.validate(peer_id, &message), Ok(None));
+		}
+		let message = vote(&voters[2], 10, Message::Precommit(precommit(105)));
+		assert_eq!(
+			gossip.validate(peer_id, &message),
+			Ok(Some(GossipEvent::Finalized {
+				set_id: SET_ID,
+				round: 10,
+				hash: H256::repeat_byte(105),
+				number: 105
+			}))
+		);
+		assert_eq!(gossip.view().round, 11);
+		assert_eq!(gossip.round_votes(10), Some((0, 3)));
+		let justification = gossip.take_justification().unwrap();
+		assert_eq!(justification.commit.precommits.len(), 3);
+		check_finality(&validator_set(), &justification).unwrap();
+
+		// Late precommit of the completed round
+		let message = vote(&voters[3], 10, Message::Precommit(precommit(105)));
+		assert_eq!(gossip.validate(peer_id, &message), Ok(None));
+	}
+
+	#[test]
+	fn finality_from_commit() {
+		let mut gossip = gossip();
+		let peer_id = PeerId::random();
+		let voters = voters();
+		assert!(matches!(
+			gossip.validate(peer_id, &commit(&voters[..2], 12, 110)),
+			Err(GossipError::BadCommit(_))
+		));
+		assert!(matches!(
+			gossip.validate(peer_id, &commit(&voters[..3], 12, 110)),
+			Ok(Some(GossipEvent::Finalized { number: 110, .. }))
+		));
+		assert_eq!(
+			gossip.view(),
+			View {
+				set_id: SET_ID,
+				round: 13,
+				finalized: 110
+			}
+		);
+		check_finality(&validator_set(), &gossip.take_justification().unwrap()).unwrap();
+		assert_eq!(
+			gossip.validate(peer_id, &commit(&voters, 11, 108)),
+			Err(GossipError::Stale {
+				set_id: SET_ID,
+				round: 11
+			})
+		);
+	}
+
+	#[test]
+	fn commit_moves_round_forward() {
+		let mut gossip = gossip();
+		let peer_id = PeerId::random();
+		assert!(matches!(
+			gossip.validate(peer_id, &commit(&voters(), 500, 2000)),
+			Ok(Some(GossipEvent::Finalized { number: 2000, .. }))
+		));
+		assert_eq!(gossip.view().round, 501);
+		assert_eq!(gossip.reputation(&peer_id), 0);
+	}
+
+	#[tokio::test]
+	async fn codec_roundtrip() {
+		let message = commit(&voters(), 12, 110);
+		let mut io = Cursor::new(vec![]);
+		Codec
+			.write_request(&PROTOCOL_NAME, &mut io, message.clone())
+			.await
+			.unwrap();
+		io.set_position(0);
+		let decoded = Codec.read_request(&PROTOCOL_NAME, &mut io).await.unwrap();
+		assert_eq!(decoded, message);
+	}
+
+	#[test_case(11, 2 => Ok(None) ; "valid vote")]
+	#[test_case(8, 2 => Err(GossipError::Stale { set_id: SET_ID, round: 8 }) ; "stale round")]
+	#[test_case(20, 2 => Err(GossipError::Future { set_id: SET_ID, round: 20 }) ; "future round")]
+	fn validate_vote(round: u64, voter: usize) -> Result<Option<GossipEvent>, GossipError> {
+		let message = vote(
+			&voters()[voter],
+			round,
+			Message::Prevote(Prevote {
+				target_hash: H256::zero(),
+				target_number: 101,
+			}),
+		);
+		gossip().validate(PeerId::random(), &message)
+	}
+
+	#[test]
+	fn invalid_votes() {
+		let mut gossip = gossip();
+		let peer_id = PeerId::random();
+		let stranger = ed25519::Pair::from_seed(&[9; 32]);
+		let message = vote(&stranger, 10, Message::Precommit(precommit(105)));
+		assert_eq!(
+			gossip.validate(peer_id, &message),
+			Err(GossipError::UnknownVoter(stranger.public()))
+		);
+
+		let mut message = vote(&voters()[0], 10, Message::Precommit(precommit(105)));
+		let last = message.len() - 40;
+		message[last] ^= 1;
+		assert!(matches!(
+			gossip.validate(peer_id, &message),
+			Err(GossipError::BadSignature(_))
+		));
+		assert!(gossip.reputation(&peer_id) < -1024);
+	}
+
+	#[test]
+	fn neighbor_packets() {
+		let mut gossip = gossip();
+		let peer_id = PeerId::random();
+		let packet = |round| {
+			GossipMessage::Neighbor(VersionedNeighborPacket::V1(NeighborPacket {
+				round,
+				set_id: SET_ID,
+				commit_finalized_height: 99,
+			}))
+			.encode()
+		};
+		assert!(gossip.validate(peer_id, &packet(10)).is_ok());
+		assert_eq!(gossip.peer_view(&peer_id).map(|view| view.round), Some(10));
+		assert_eq!(
+			gossip.validate(peer_id, &packet(9)),
+			Err(GossipError::NeighborRegression)
+		);
+		assert_eq!(
+			gossip.neighbor_packet().encode(),
+			GossipMessage::Neighbor(VersionedNeighborPacket::V1(NeighborPacket {
+				round: 10,
+				set_id: SET_ID,
+				commit_finalized_height: 100
+			}))
+			.encode()
+		);
+	}
+}
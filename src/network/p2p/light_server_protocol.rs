@@ -0,0 +1,164 @@
+//! Request/response protocol of the light server, for serving verified headers and read proofs to peers.
+//!
+//! Supports remote header (by number or hash), remote header range and remote read requests. Header ranges serve
+//! the header download of the [`crate::sync_driver::SyncDriver`] (see [`crate::sync_driver::P2pTransport`]). Protocol is specific to Avail light clients, and is not wire
+//! compatible with the Substrate light client protocol. Requests are served only by nodes with enabled light server,
+//! from the locally verified headers (see [`crate::light_server`]). Messages are SCALE encoded. Cell requests are
+//! served with the [`super::cell_exchange`] protocol.
+//!
+//! Inbound requests are queued in a bounded channel, and dropped if the light server doesn't keep up.
+
+use async_trait::async_trait;
+use codec::{Decode, Encode};
+use futures::{AsyncRead, AsyncWrite};
+use libp2p::{request_response, PeerId, StreamProtocol};
+use std::io;
+use tokio::sync::mpsc;
+
+use super::cell_exchange::{read, write};
+
+pub const PROTOCOL_NAME: StreamProtocol = StreamProtocol::new("/avail/light-server/1");
+
+/// Maximum number of storage keys in a single remote read request
+pub const MAX_READ_KEYS: usize = 256;
+/// Maximum number of headers in a single remote headers response, same as the maximum batch of the header download
+pub const MAX_REMOTE_HEADERS: u32 = 512;
+const MAX_REQUEST_SIZE: u64 = 64 * 1024;
+const MAX_RESPONSE_SIZE: u64 = 16 * 1024 * 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum LightRequest {
+	RemoteHeader {
+		block_number: u32,
+	},
+	/// Storage `keys` at the block, block hash has to match the locally verified header
+	RemoteRead {
+		block_number: u32,
+		block_hash: [u8; 32],
+		keys: Vec<Vec<u8>>,
+	},
+	/// Up to `count` consecutive headers, starting with the block number `from`
+	RemoteHeaders {
+		from: u32,
+		count: u32,
+	},
+	RemoteHeaderByHash {
+		block_hash: [u8; 32],
+	},
+}
+
+/// Responses are `None` if request cannot be served from the verified data
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum LightResponse {
+	/// SCALE encoded header
+	Header(Option<Vec<u8>>),
+	/// Read proof nodes, verified against the header state root
+	ReadProof(Option<Vec<Vec<u8>>>),
+	/// SCALE encoded headers, up to the first header which is not verified
+	Headers(Vec<Vec<u8>>),
+}
+
+/// Inbound request, with the channel for the response
+pub struct InboundLightRequest {
+	pub peer: PeerId,
+	pub request: LightRequest,
+	pub channel: request_response::ResponseChannel<LightResponse>,
+}
+
+/// Maximum number of inbound requests waiting for the light server
+pub const MAX_QUEUED_REQUESTS: usize = 128;
+
+pub type LightRequestSender = mpsc::Sender<InboundLightRequest>;
+pub type LightRequestReceiver = mpsc::Receiver<InboundLightRequest>;
+
+pub fn request_channel() -> (LightRequestSender, LightRequestReceiver) {
+	mpsc::channel(MAX_QUEUED_REQUESTS)
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Codec;
+
+#[async_trait]
+impl request_response::Codec for Codec {
+	type Protocol = StreamProtocol;
+	type Request = LightRequest;
+	type Response = LightResponse;
+
+	async fn read_request<T>(&mut self, _: &Self::Protocol, io: &mut T) -> io::Result<LightRequest>
+	where
+		T: AsyncRead + Unpin + Send,
+	{
+		read(io, MAX_REQUEST_SIZE).await
+	}
+
+	async fn read_response<T>(
+		&mut self,
+		_: &Self::Protocol,
+		io: &mut T,
+	) -> io::Result<LightResponse>
+	where
+		T: AsyncRead + Unpin + Send,
+	{
+		read(io, MAX_RESPONSE_SIZE).await
+	}
+
+	async fn write_request<T>(
+		&mut self,
+		_: &Self::Protocol,
+		io: &mut T,
+		request: LightRequest,
+	) -> io::Result<()>
+	where
+		T: AsyncWrite + Unpin + Send,
+	{
+		write(io, request).await
+	}
+
+	async fn write_response<T>(
+		&mut self,
+		_: &Self::Protocol,
+		io: &mut T,
+		response: LightResponse,
+	) -> io::Result<()>
+	where
+		T: AsyncWrite + Unpin + Send,
+	{
+		write(io, response).await
+	}
+}
+
+/// Inbound requests are accepted only if light server is enabled
+pub fn behaviour(is_server: bool) -> request_response::Behaviour<Codec> {
+	let support = match is_server {
+		true => request_response::ProtocolSupport::Full,
+		false => request_response::ProtocolSupport::Outbound,
+	};
+	request_response::Behaviour::new(
+		[(PROTOCOL_NAME, support)],
+		request_response::Config::default(),
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::io::Cursor;
+	use request_response::Codec as _;
+
+	#[tokio::test]
+	async fn codec_roundtrip() {
+		let request = LightRequest::RemoteRead {
+			block_number: 42,
+			block_hash: [1; 32],
+			keys: vec![vec![2; 32]],
+		};
+		let mut io = Cursor::new(vec![]);
+		Codec
+			.write_request(&PROTOCOL_NAME, &mut io, request.clone())
+			.await
+			.unwrap();
+		io.set_position(0);
+		let decoded = Codec.read_request(&PROTOCOL_NAME, &mut io).await.unwrap();
+		assert_eq!(decoded, request);
+	}
+}
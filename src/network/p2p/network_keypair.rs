@@ -0,0 +1,214 @@
+//! Persistent libp2p identity of the node.
+//!
+//! Keypair is used for Noise handshakes, and defines the PeerId of the node. If keypair path is configured, keypair
+//! is generated on the first startup and stored into the file, so the node keeps stable PeerId across restarts.
+//! Keypair is stored in the protobuf encoding, encrypted with ChaCha20-Poly1305 if password is provided, with the
+//! encryption key derived from the password using PBKDF2-HMAC-SHA256.
+//!
+//! # File format
+//!
+//! * Plain - `0x00` followed by the protobuf encoded keypair
+//! * Encrypted - `0x01`, followed by 16 bytes salt, 12 bytes nonce and encrypted protobuf encoded keypair
+
+use chacha20poly1305::{
+	aead::{Aead, KeyInit},
+	ChaCha20Poly1305, Key, Nonce,
+};
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use hmac::Hmac;
+use libp2p::{identity::Keypair, multiaddr::Protocol, Multiaddr, PeerId};
+use multihash::Hasher;
+use rand::{thread_rng, RngCore};
+use sha2::Sha256;
+use std::{fs, path::Path};
+use tracing::info;
+
+use crate::{
+	types::{LibP2PConfig, SecretKey},
+	utils::write_secret_file,
+};
+
+const PLAIN: u8 = 0;
+const ENCRYPTED: u8 = 1;
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+const PBKDF2_ROUNDS: u32 = 100_000;
+
+/// libp2p identity keypair of the node
+#[derive(Clone, Debug)]
+pub struct NetworkKeypair(Keypair);
+
+fn encryption_key(password: &str, salt: &[u8]) -> Key {
+	let mut key = Key::default();
+	pbkdf2::pbkdf2::<Hmac<Sha256>>(password.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+	key
+}
+
+impl NetworkKeypair {
+	pub fn generate() -> Self {
+		NetworkKeypair(Keypair::generate_ed25519())
+	}
+
+	/// Creates keypair from the configured secret key, generated from seed or imported from hex encoded key
+	pub fn from_secret_key(secret_key: &SecretKey) -> Result<Self> {
+		let keypair = match secret_key {
+			SecretKey::Seed { seed } => {
+				let seed_digest = multihash::Sha3_256::digest(seed.as_bytes());
+				Keypair::ed25519_from_bytes(seed_digest)
+					.wrap_err("error generating secret key from seed")?
+			},
+			SecretKey::Key { key } => {
+				let mut decoded_key = [0u8; 32];
+				hex::decode_to_slice(key.clone().into_bytes(), &mut decoded_key)
+					.wrap_err("error decoding secret key from config")?;
+				Keypair::ed25519_from_bytes(decoded_key).wrap_err("error importing secret key")?
+			},
+		};
+		Ok(NetworkKeypair(keypair))
+	}
+
+	/// Creates keypair from the configuration, with following precedence:
+	///
+	/// * Configured secret key
+	/// * Keypair stored at the keypair path, generated and stored if the file doesn't exist
+	/// * Random keypair
+	pub fn from_config(cfg: &LibP2PConfig, password: Option<&str>) -> Result<Self> {
+		match (&cfg.secret_key, &cfg.keypair_path) {
+			(Some(secret_key), _) => Self::from_secret_key(secret_key),
+			(None, Some(path)) => Self::load_or_generate(path, password),
+			(None, None) => Ok(Self::generate()),
+		}
+	}
+
+	/// Loads keypair from the file, or generates and stores a new one if the file doesn't exist
+	pub fn load_or_generate(path: impl AsRef<Path>, password: Option<&str>) -> Result<Self> {
+		let path = path.as_ref();
+		if path.exists() {
+			let bytes = fs::read(path)
+				.wrap_err_with(|| format!("Cannot read network keypair {}", path.display()))?;
+			let keypair = Self::decode(&bytes, password)?;
+			info!(path = %path.display(), peer_id = %keypair.peer_id(), "Network keypair loaded");
+			return Ok(keypair);
+		}
+
+		let keypair = Self::generate();
+		if let Some(parent) = path.parent() {
+			fs::create_dir_all(parent)?;
+		}
+		write_secret_file(path, &keypair.encode(password)?)
+			.wrap_err_with(|| format!("Cannot store network keypair {}", path.display()))?;
+		info!(path = %path.display(), peer_id = %keypair.peer_id(), "Network keypair generated");
+		Ok(keypair)
+	}
+
+	/// Encodes keypair for storage, encrypted if password is provided
+	pub fn encode(&self, password: Option<&str>) -> Result<Vec<u8>> {
+		let encoded = self
+			.0
+			.to_protobuf_encoding()
+			.wrap_err("Cannot encode network keypair")?;
+		let Some(password) = password else {
+			return Ok([&[PLAIN], &encoded[..]].concat());
+		};
+
+		let mut salt = [0u8; SALT_SIZE];
+		let mut nonce = [0u8; NONCE_SIZE];
+		thread_rng().fill_bytes(&mut salt);
+		thread_rng().fill_bytes(&mut nonce);
+		let cipher = ChaCha20Poly1305::new(&encryption_key(password, &salt));
+		let encrypted = cipher
+			.encrypt(Nonce::from_slice(&nonce), &encoded[..])
+			.map_err(|_| eyre!("Cannot encrypt network keypair"))?;
+		Ok([&[ENCRYPTED], &salt[..], &nonce[..], &encrypted[..]].concat())
+	}
+
+	/// Decodes stored keypair, password is required for the encrypted keypair
+	pub fn decode(bytes: &[u8], password: Option<&str>) -> Result<Self> {
+		let encoded = match (bytes.split_first(), password) {
+			(Some((&PLAIN, encoded)), _) => encoded.to_vec(),
+			(Some((&ENCRYPTED, _)), None) => {
+				return Err(eyre!("Network keypair is encrypted, password is required"))
+			},
+			(Some((&ENCRYPTED, encrypted)), Some(password)) => {
+				if encrypted.len() < SALT_SIZE + NONCE_SIZE {
+					return Err(eyre!("Invalid encrypted network keypair"));
+				}
+				let (salt, encrypted) = encrypted.split_at(SALT_SIZE);
+				let (nonce, encrypted) = encrypted.split_at(NONCE_SIZE);
+				let cipher = ChaCha20Poly1305::new(&encryption_key(password, salt));
+				cipher
+					.decrypt(Nonce::from_slice(nonce), encrypted)
+					.map_err(|_| eyre!("Cannot decrypt network keypair, invalid password"))?
+			},
+			_ => return Err(eyre!("Unknown network keypair format")),
+		};
+		Keypair::from_protobuf_encoding(&encoded)
+			.map(NetworkKeypair)
+			.wrap_err("Invalid network keypair")
+	}
+
+	pub fn keypair(&self) -> &Keypair {
+		&self.0
+	}
+
+	pub fn peer_id(&self) -> PeerId {
+		self.0.public().to_peer_id()
+	}
+
+	/// Address of the node with the PeerId, as used in the reserved peers and bootstrap configuration
+	pub fn peer_address(&self, address: &Multiaddr) -> Multiaddr {
+		address.clone().with(Protocol::P2p(self.peer_id()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use test_case::test_case;
+
+	#[test_case(None ; "plain")]
+	#[test_case(Some("password") ; "encrypted")]
+	fn encode_decode(password: Option<&str>) {
+		let keypair = NetworkKeypair::generate();
+		let encoded = keypair.encode(password).unwrap();
+		let decoded = NetworkKeypair::decode(&encoded, password).unwrap();
+		assert_eq!(decoded.peer_id(), keypair.peer_id());
+	}
+
+	#[test]
+	fn decode_with_invalid_password() {
+		let encoded = NetworkKeypair::generate().encode(Some("password")).unwrap();
+		assert!(NetworkKeypair::decode(&encoded, Some("invalid")).is_err());
+		assert!(NetworkKeypair::decode(&encoded, None).is_err());
+	}
+
+	#[test]
+	fn stable_peer_id() {
+		let path = std::env::temp_dir()
+			.join(format!("avail_light_keypair_{}", PeerId::random()))
+			.join("network.key");
+		let generated = NetworkKeypair::load_or_generate(&path, Some("password")).unwrap();
+		let loaded = NetworkKeypair::load_or_generate(&path, Some("password")).unwrap();
+		assert_eq!(generated.peer_id(), loaded.peer_id());
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			let mode = fs::metadata(&path).unwrap().permissions().mode();
+			assert_eq!(mode & 0o777, 0o600);
+		}
+		fs::remove_dir_all(path.parent().unwrap()).unwrap();
+	}
+
+	#[test]
+	fn secret_key_from_seed() {
+		let secret_key = SecretKey::Seed {
+			seed: "avail".to_string(),
+		};
+		let first = NetworkKeypair::from_secret_key(&secret_key).unwrap();
+		let second = NetworkKeypair::from_secret_key(&secret_key).unwrap();
+		assert_eq!(first.peer_id(), second.peer_id());
+	}
+}
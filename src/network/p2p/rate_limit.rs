@@ -0,0 +1,265 @@
+//! Rate limiting of the inbound request/response protocols.
+//!
+//! Inbound requests are limited per peer and globally, with token buckets refilled at the configured number of
+//! requests per second (burst is equal to one second of requests). Responses are limited by the number of bytes in
+//! flight, reserved when response is sent and released once it is delivered or fails, so a single peer cannot
+//! monopolize the upload bandwidth. Requests over the limits are dropped, and the requesting peer gets an inbound
+//! failure.
+//!
+//! Peer limits are kept after the peer disconnects, until its bucket is refilled, so reconnecting doesn't reset them.
+
+use libp2p::{request_response::InboundRequestId, PeerId};
+use std::{collections::HashMap, fmt, hash::Hash, time::Instant};
+
+use crate::types::RateLimitConfig;
+
+#[derive(Clone, Copy, Debug)]
+pub struct TokenBucket {
+	capacity: f64,
+	tokens: f64,
+	rate: f64,
+	updated: Instant,
+}
+
+impl TokenBucket {
+	/// Creates full bucket, refilled with `rate` tokens per second, up to `rate` tokens
+	pub fn new(rate: u32, now: Instant) -> Self {
+		TokenBucket {
+			capacity: rate.into(),
+			tokens: rate.into(),
+			rate: rate.into(),
+			updated: now,
+		}
+	}
+
+	fn refill(&mut self, now: Instant) {
+		let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+		self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+		self.updated = now;
+	}
+
+	pub fn available(&mut self, now: Instant) -> f64 {
+		self.refill(now);
+		self.tokens
+	}
+
+	/// Bucket is full, so it is the same as the new one
+	pub fn is_full(&mut self, now: Instant) -> bool {
+		self.available(now) >= self.capacity
+	}
+
+	/// Takes one token, if available
+	pub fn try_take(&mut self, now: Instant) -> bool {
+		self.refill(now);
+		if self.tokens < 1.0 {
+			return false;
+		}
+		self.tokens -= 1.0;
+		true
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateLimited {
+	PeerRequests,
+	GlobalRequests,
+	PeerBytes { bytes: usize },
+	GlobalBytes { bytes: usize },
+}
+
+impl fmt::Display for RateLimited {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			RateLimited::PeerRequests => write!(f, "Peer request rate limit exceeded"),
+			RateLimited::GlobalRequests => write!(f, "Global request rate limit exceeded"),
+			RateLimited::PeerBytes { bytes } => {
+				write!(f, "Peer response limit exceeded, {bytes} bytes in flight")
+			},
+			RateLimited::GlobalBytes { bytes } => {
+				write!(f, "Global response limit exceeded, {bytes} bytes in flight")
+			},
+		}
+	}
+}
+
+impl std::error::Error for RateLimited {}
+
+struct PeerLimit {
+	requests: TokenBucket,
+	bytes_in_flight: usize,
+}
+
+/// Per peer and global limits of the inbound requests, keyed by the inbound request ID
+pub struct RateLimiter<K = InboundRequestId> {
+	cfg: RateLimitConfig,
+	requests: TokenBucket,
+	bytes_in_flight: usize,
+	peers: HashMap<PeerId, PeerLimit>,
+	in_flight: HashMap<K, (PeerId, usize)>,
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+	pub fn new(cfg: RateLimitConfig) -> Self {
+		RateLimiter {
+			cfg,
+			requests: TokenBucket::new(cfg.global_requests_per_second, Instant::now()),
+			bytes_in_flight: 0,
+			peers: HashMap::new(),
+			in_flight: HashMap::new(),
+		}
+	}
+
+	/// Checks request rate limits, and takes the request tokens if request is allowed
+	pub fn check_request(&mut self, peer_id: PeerId, now: Instant) -> Result<(), RateLimited> {
+		let rate = self.cfg.peer_requests_per_second;
+		let peer = self.peers.entry(peer_id).or_insert_with(|| PeerLimit {
+			requests: TokenBucket::new(rate, now),
+			bytes_in_flight: 0,
+		});
+		if peer.requests.available(now) < 1.0 {
+			return Err(RateLimited::PeerRequests);
+		}
+		if !self.requests.try_take(now) {
+			return Err(RateLimited::GlobalRequests);
+		}
+		peer.requests.try_take(now);
+		Ok(())
+	}
+
+	/// Reserves bytes of the response, if it fits into limits of bytes in flight
+	pub fn reserve(
+		&mut self,
+		request_id: K,
+		peer_id: PeerId,
+		bytes: usize,
+	) -> Result<(), RateLimited> {
+		let peer_bytes = self
+			.peers
+			.get(&peer_id)
+			.map(|peer| peer.bytes_in_flight)
+			.unwrap_or_default();
+		if peer_bytes + bytes > self.cfg.peer_max_bytes_in_flight {
+			return Err(RateLimited::PeerBytes { bytes: peer_bytes });
+		}
+		if self.bytes_in_flight + bytes > self.cfg.max_bytes_in_flight {
+			return Err(RateLimited::GlobalBytes {
+				bytes: self.bytes_in_flight,
+			});
+		}
+		if let Some(peer) = self.peers.get_mut(&peer_id) {
+			peer.bytes_in_flight += bytes;
+		}
+		self.bytes_in_flight += bytes;
+		self.in_flight.insert(request_id, (peer_id, bytes));
+		Ok(())
+	}
+
+	/// Releases reserved bytes, once response is sent or failed
+	pub fn release(&mut self, request_id: &K) {
+		let Some((peer_id, bytes)) = self.in_flight.remove(request_id) else {
+			return;
+		};
+		self.bytes_in_flight -= bytes;
+		if let Some(peer) = self.peers.get_mut(&peer_id) {
+			peer.bytes_in_flight -= bytes;
+		}
+	}
+
+	pub fn bytes_in_flight(&self) -> usize {
+		self.bytes_in_flight
+	}
+
+	/// Forgets expired peer limits, with full request bucket and no responses in flight
+	pub fn prune(&mut self, now: Instant) {
+		self.peers
+			.retain(|_, peer| peer.bytes_in_flight > 0 || !peer.requests.is_full(now));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	fn config() -> RateLimitConfig {
+		RateLimitConfig {
+			peer_requests_per_second: 2,
+			global_requests_per_second: 3,
+			peer_max_bytes_in_flight: 100,
+			max_bytes_in_flight: 150,
+		}
+	}
+
+	#[test]
+	fn token_bucket_refill() {
+		let now = Instant::now();
+		let mut bucket = TokenBucket::new(2, now);
+		assert!(bucket.try_take(now));
+		assert!(bucket.try_take(now));
+		assert!(!bucket.try_take(now));
+		assert!(bucket.try_take(now + Duration::from_millis(500)));
+		assert!(!bucket.try_take(now + Duration::from_millis(500)));
+		// Bucket is not refilled over capacity
+		assert_eq!(bucket.available(now + Duration::from_secs(10)), 2.0);
+	}
+
+	#[test]
+	fn request_limits() {
+		let now = Instant::now();
+		let mut limiter = RateLimiter::<u32>::new(config());
+		let (first, second) = (PeerId::random(), PeerId::random());
+		assert_eq!(limiter.check_request(first, now), Ok(()));
+		assert_eq!(limiter.check_request(first, now), Ok(()));
+		assert_eq!(
+			limiter.check_request(first, now),
+			Err(RateLimited::PeerRequests)
+		);
+		assert_eq!(limiter.check_request(second, now), Ok(()));
+		assert_eq!(
+			limiter.check_request(second, now),
+			Err(RateLimited::GlobalRequests)
+		);
+	}
+
+	#[test]
+	fn bytes_in_flight_limits() {
+		let now = Instant::now();
+		let mut limiter = RateLimiter::<u32>::new(config());
+		let (first, second) = (PeerId::random(), PeerId::random());
+		limiter.check_request(first, now).unwrap();
+		limiter.check_request(second, now).unwrap();
+
+		assert_eq!(limiter.reserve(1, first, 80), Ok(()));
+		assert_eq!(
+			limiter.reserve(2, first, 30),
+			Err(RateLimited::PeerBytes { bytes: 80 })
+		);
+		assert_eq!(
+			limiter.reserve(3, second, 80),
+			Err(RateLimited::GlobalBytes { bytes: 80 })
+		);
+		limiter.release(&1);
+		assert_eq!(limiter.bytes_in_flight(), 0);
+		assert_eq!(limiter.reserve(3, second, 80), Ok(()));
+	}
+
+	#[test]
+	fn limits_are_kept_until_expired() {
+		let now = Instant::now();
+		let mut limiter = RateLimiter::<u32>::new(config());
+		let peer_id = PeerId::random();
+		limiter.check_request(peer_id, now).unwrap();
+		limiter.check_request(peer_id, now).unwrap();
+
+		// Reconnecting peer doesn't get the full bucket
+		limiter.prune(now);
+		assert_eq!(
+			limiter.check_request(peer_id, now),
+			Err(RateLimited::PeerRequests)
+		);
+
+		let later = now + Duration::from_secs(1);
+		limiter.prune(later);
+		assert!(limiter.peers.is_empty());
+	}
+}
@@ -0,0 +1,94 @@
+//! Signing of DHT records, so the cells are attributable to the peer which published them.
+//!
+//! Signed record value is SCALE encoded [`SignedValue`], with the signature of the record key and the value.
+//! Signer is the record publisher, and its public key is recovered from the publisher peer ID (identity multihash
+//! of the ed25519 public key), so the signature is verified without any additional lookup.
+
+use codec::{Decode, Encode};
+use color_eyre::{eyre::eyre, Result};
+use libp2p::{
+	identity::{Keypair, PublicKey},
+	kad::{Record, RecordKey},
+	PeerId,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct SignedValue {
+	pub value: Vec<u8>,
+	pub signature: Vec<u8>,
+}
+
+fn message(key: &RecordKey, value: &[u8]) -> Vec<u8> {
+	[key.as_ref(), value].concat()
+}
+
+/// Recovers public key from the peer ID, if it is an identity multihash
+fn public_key(peer_id: &PeerId) -> Option<PublicKey> {
+	match peer_id.to_bytes().as_slice() {
+		[0, length, key @ ..] if *length as usize == key.len() => {
+			PublicKey::try_decode_protobuf(key).ok()
+		},
+		_ => None,
+	}
+}
+
+/// Replaces record value with the signed value, and sets the signer as the record publisher
+pub fn sign(keypair: &Keypair, mut record: Record) -> Result<Record> {
+	let signature = keypair.sign(&message(&record.key, &record.value))?;
+	record.value = SignedValue {
+		value: record.value,
+		signature,
+	}
+	.encode();
+	record.publisher = Some(keypair.public().to_peer_id());
+	Ok(record)
+}
+
+/// Verifies signed record and returns its value
+pub fn open(record: &Record) -> Result<Vec<u8>> {
+	let publisher = record
+		.publisher
+		.ok_or_else(|| eyre!("Signed record has no publisher"))?;
+	let public_key =
+		public_key(&publisher).ok_or_else(|| eyre!("Cannot recover public key of {publisher}"))?;
+	let SignedValue { value, signature } = SignedValue::decode(&mut &record.value[..])?;
+	if !public_key.verify(&message(&record.key, &value), &signature) {
+		return Err(eyre!(
+			"Invalid signature of the record published by {publisher}"
+		));
+	}
+	Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn record() -> Record {
+		Record::new(RecordKey::new(&"42:1:2"), vec![7; 80])
+	}
+
+	#[test]
+	fn sign_and_open() {
+		let keypair = Keypair::generate_ed25519();
+		let signed = sign(&keypair, record()).unwrap();
+		assert_eq!(signed.publisher, Some(keypair.public().to_peer_id()));
+		assert_eq!(open(&signed).unwrap(), vec![7; 80]);
+	}
+
+	#[test]
+	fn open_fails() {
+		let keypair = Keypair::generate_ed25519();
+		let mut signed = sign(&keypair, record()).unwrap();
+		signed.key = RecordKey::new(&"42:1:3");
+		assert!(open(&signed).is_err());
+
+		let mut unsigned = record();
+		unsigned.publisher = Some(keypair.public().to_peer_id());
+		assert!(open(&unsigned).is_err());
+
+		let mut forged = sign(&keypair, record()).unwrap();
+		forged.publisher = Some(Keypair::generate_ed25519().public().to_peer_id());
+		assert!(open(&forged).is_err());
+	}
+}
@@ -15,21 +15,28 @@ use tokio::{
 	sync::broadcast,
 	time::{self, timeout},
 };
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 use crate::{
+	cache::Caches,
 	data::Database,
 	network::rpc,
 	types::{GrandpaJustification, RetryConfig, State},
+	verification::WorkerPool,
 };
 
+pub mod chain_head;
 mod client;
+pub mod pipeline;
+pub mod pool;
 mod subscriptions;
 
 use subscriptions::SubscriptionLoop;
 const CELL_SIZE: usize = 32;
 const PROOF_SIZE: usize = 48;
 pub const CELL_WITH_PROOF_SIZE: usize = CELL_SIZE + PROOF_SIZE;
+/// Interval of the endpoint pool checks, if more than one node is configured
+const ENDPOINT_CHECK_INTERVAL: time::Duration = time::Duration::from_secs(60);
 pub use subscriptions::Event;
 
 pub use client::Client;
@@ -66,6 +73,17 @@ pub struct FinalityProof {
 	pub unknown_headers: Vec<Header>,
 }
 
+/// Merkle proof of the submitted data against the block data root
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DataProof {
+	pub root: H256,
+	pub proof: Vec<H256>,
+	pub number_of_leaves: u32,
+	pub leaf_index: u32,
+	pub leaf: H256,
+}
+
 #[derive(Debug, Decode, Clone)]
 pub struct WrappedProof(pub FinalityProof);
 
@@ -207,17 +225,47 @@ pub async fn init<T: Database>(
 	nodes: &[String],
 	genesis_hash: &str,
 	retry_config: RetryConfig,
+	workers: WorkerPool,
+	caches: Option<Arc<Caches>>,
+	chain_head: bool,
 ) -> Result<(Client, broadcast::Sender<Event>, SubscriptionLoop<T>)> {
-	let rpc_client =
-		Client::new(state.clone(), Nodes::new(nodes), genesis_hash, retry_config).await?;
+	let mut rpc_client = Client::new(
+		state.clone(),
+		Nodes::new(nodes),
+		genesis_hash,
+		retry_config,
+		caches,
+	)
+	.await?;
+	if nodes.len() > 1 {
+		rpc_client = rpc_client
+			.with_endpoint_pool(pool::PoolConfig::default())
+			.await;
+	}
+	if chain_head {
+		rpc_client = rpc_client
+			.with_chain_head(chain_head::ChainHeadConfig::default())
+			.await;
+	}
 	// create output channel for RPC Subscription Events
 	let (event_sender, _) = broadcast::channel(1000);
 	let subscriptions =
-		SubscriptionLoop::new(state, db, rpc_client.clone(), event_sender.clone()).await?;
+		SubscriptionLoop::new(state, db, rpc_client.clone(), event_sender.clone(), workers).await?;
 
 	Ok((rpc_client, event_sender, subscriptions))
 }
 
+/// Checks the endpoint pool of the RPC client periodically, see [`Client::check_endpoints`]
+pub async fn check_endpoints(rpc_client: Client) {
+	let mut interval = time::interval(ENDPOINT_CHECK_INTERVAL);
+	loop {
+		interval.tick().await;
+		if let Err(error) = rpc_client.check_endpoints().await {
+			warn!("RPC endpoints check failed: {error:#}");
+		}
+	}
+}
+
 /// Generates random cell positions for sampling
 pub fn generate_random_cells(dimensions: Dimensions, cell_count: u32) -> Vec<Position> {
 	let max_cells = dimensions.extended_size();
@@ -0,0 +1,1048 @@
+//! Client of the new JSON-RPC spec (`chainHead_v1_*` and `transaction_v1_*`), as an alternative to the legacy API
+//! which the nodes are deprecating.
+//!
+//! [`ChainHead::follow`] starts the `chainHead_v1_follow` subscription (with runtime updates, so runtime calls are
+//! allowed), and spawns the task which forwards block events to the returned receiver, and routes operation events
+//! (body, call and storage results) to the pending operations. Requests are sent through the [`Transport`], e.g. [`WsTransport`], which also supports JSON-RPC
+//! batches (see [`super::pipeline`]).
+//!
+//! # Pinning
+//!
+//! Node keeps every reported block pinned until it is unpinned by the client. Pruned blocks are unpinned as soon as
+//! they are reported, and only the last [`ChainHeadConfig::max_pinned_finalized`] finalized blocks are kept pinned.
+//!
+//! # Operation limits
+//!
+//! Node limits the number of concurrent operations. Operations rejected with `limitReached` are retried after
+//! [`ChainHeadConfig::limit_retry_delay`], and storage queries discarded by the node are sent again in the next
+//! operation.
+//!
+//! # Buffering
+//!
+//! Events received before the subscription or operation is registered are buffered, up to [`MAX_BUFFERED_EVENTS`]
+//! per subscription or operation. Subscription or operation whose buffer overflows fails with an error.
+
+use async_trait::async_trait;
+use avail_subxt::{primitives::Header, utils::H256};
+use codec::Decode;
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Report, Result,
+};
+use futures::{
+	future::join_all,
+	stream::{BoxStream, StreamExt},
+	SinkExt,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sp_core::Bytes;
+use std::{
+	collections::{HashMap, HashSet, VecDeque},
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc, Mutex,
+	},
+	time::Duration,
+};
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tracing::{debug, warn};
+
+const FOLLOW: &str = "chainHead_v1_follow";
+const UNFOLLOW: &str = "chainHead_v1_unfollow";
+const HEADER: &str = "chainHead_v1_header";
+const BODY: &str = "chainHead_v1_body";
+const CALL: &str = "chainHead_v1_call";
+const STORAGE: &str = "chainHead_v1_storage";
+const CONTINUE: &str = "chainHead_v1_continue";
+const UNPIN: &str = "chainHead_v1_unpin";
+const BROADCAST: &str = "transaction_v1_broadcast";
+const STOP: &str = "transaction_v1_stop";
+
+/// Maximum number of the subscriptions or operations whose events are buffered before they are registered
+const MAX_BUFFERED_IDS: usize = 64;
+
+/// Maximum number of the buffered events per subscription or operation
+pub const MAX_BUFFERED_EVENTS: usize = 256;
+
+#[async_trait]
+pub trait Transport: Send + Sync + 'static {
+	async fn request(&self, method: &str, params: Vec<Value>) -> Result<Value>;
+
+	/// Sends requests as a single batch, and returns results in the order of requests. Requests are sent
+	/// concurrently by default, for transports without batch support.
+	async fn batch(&self, requests: Vec<(String, Vec<Value>)>) -> Result<Vec<Result<Value>>> {
+		let responses = requests
+			.into_iter()
+			.map(|(method, params)| async move { self.request(&method, params).await });
+		Ok(join_all(responses).await)
+	}
+
+	/// Starts the subscription, and returns its ID and the stream of notification results
+	async fn subscribe(
+		&self,
+		method: &str,
+		params: Vec<Value>,
+	) -> Result<(String, BoxStream<'static, Value>)>;
+}
+
+/// Subscription IDs can be either strings or numbers
+fn subscription_id(value: &Value) -> Option<String> {
+	match value {
+		Value::String(id) => Some(id.clone()),
+		Value::Number(id) => Some(id.to_string()),
+		_ => None,
+	}
+}
+
+/// Events of the subscriptions or operations which are not registered yet
+struct Buffered<E> {
+	events: HashMap<String, Vec<E>>,
+	overflowed: HashSet<String>,
+}
+
+impl<E> Default for Buffered<E> {
+	fn default() -> Self {
+		Buffered {
+			events: HashMap::new(),
+			overflowed: HashSet::new(),
+		}
+	}
+}
+
+impl<E> Buffered<E> {
+	fn push(&mut self, id: String, event: E) {
+		if self.overflowed.contains(&id) {
+			return;
+		}
+		let overflow = match self.events.get(&id) {
+			Some(events) => events.len() >= MAX_BUFFERED_EVENTS,
+			None => self.events.len() >= MAX_BUFFERED_IDS,
+		};
+		if !overflow {
+			self.events.entry(id).or_default().push(event);
+			return;
+		}
+		self.events.remove(&id);
+		if self.overflowed.len() < MAX_BUFFERED_IDS {
+			self.overflowed.insert(id);
+		} else {
+			warn!(%id, "Too many buffered subscriptions and operations, event is dropped");
+		}
+	}
+
+	/// Removes and returns buffered events, fails if too many events are received
+	fn take(&mut self, id: &str) -> Result<Vec<E>> {
+		if self.overflowed.remove(id) {
+			return Err(eyre!(
+				"Too many events of {id} are received before it is registered"
+			));
+		}
+		Ok(self.events.remove(id).unwrap_or_default())
+	}
+}
+
+#[derive(Default)]
+struct Pending {
+	responses: HashMap<u64, oneshot::Sender<Result<Value>>>,
+	subscriptions: HashMap<String, mpsc::UnboundedSender<Value>>,
+	/// Notifications received before the subscription response is handled
+	early: Buffered<Value>,
+}
+
+impl Pending {
+	fn handle(&mut self, message: Value) {
+		if let Some(id) = message.get("id").and_then(Value::as_u64) {
+			if let Some(sender) = self.responses.remove(&id) {
+				let result = match message.get("error") {
+					Some(error) => Err(eyre!("RPC request failed: {error}")),
+					None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+				};
+				_ = sender.send(result);
+			}
+			return;
+		}
+		let Some(params) = message.get("params") else {
+			return;
+		};
+		let Some(id) = params.get("subscription").and_then(subscription_id) else {
+			return;
+		};
+		let result = params.get("result").cloned().unwrap_or(Value::Null);
+		match self.subscriptions.get(&id) {
+			Some(sender) => {
+				if sender.send(result).is_err() {
+					self.subscriptions.remove(&id);
+				}
+			},
+			None => self.early.push(id, result),
+		}
+	}
+}
+
+/// JSON-RPC client over the WebSocket connection
+pub struct WsTransport {
+	outgoing: mpsc::UnboundedSender<String>,
+	pending: Arc<Mutex<Pending>>,
+	next_id: AtomicU64,
+}
+
+impl WsTransport {
+	pub async fn connect(url: &str) -> Result<Self> {
+		let (stream, _) = connect_async(url)
+			.await
+			.wrap_err_with(|| format!("Failed to connect to {url}"))?;
+		let (mut write, mut read) = stream.split();
+
+		let (outgoing, mut receiver) = mpsc::unbounded_channel::<String>();
+		tokio::spawn(async move {
+			while let Some(text) = receiver.recv().await {
+				if let Err(error) = write.send(Message::Text(text)).await {
+					warn!("Failed to send JSON-RPC message: {error}");
+					break;
+				}
+			}
+		});
+
+		let pending = Arc::new(Mutex::new(Pending::default()));
+		let reader_pending = pending.clone();
+		tokio::spawn(async move {
+			while let Some(Ok(message)) = read.next().await {
+				let Message::Text(text) = message else {
+					continue;
+				};
+				match serde_json::from_str(&text) {
+					// Responses of the batch request
+					Ok(Value::Array(messages)) => {
+						let mut pending = reader_pending.lock().unwrap();
+						for message in messages {
+							pending.handle(message);
+						}
+					},
+					Ok(message) => reader_pending.lock().unwrap().handle(message),
+					Err(error) => warn!("Invalid JSON-RPC message: {error}"),
+				}
+			}
+			debug!("JSON-RPC connection is closed");
+			// Dropping senders fails pending requests and ends the subscriptions
+			*reader_pending.lock().unwrap() = Pending::default();
+		});
+
+		Ok(WsTransport {
+			outgoing,
+			pending,
+			next_id: AtomicU64::new(1),
+		})
+	}
+}
+
+#[async_trait]
+impl Transport for WsTransport {
+	async fn request(&self, method: &str, params: Vec<Value>) -> Result<Value> {
+		let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+		let (sender, receiver) = oneshot::channel();
+		self.pending.lock().unwrap().responses.insert(id, sender);
+		let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+		self.outgoing
+			.send(request.to_string())
+			.map_err(|_| eyre!("JSON-RPC connection is closed"))?;
+		receiver
+			.await
+			.map_err(|_| eyre!("JSON-RPC connection is closed"))?
+	}
+
+	async fn batch(&self, requests: Vec<(String, Vec<Value>)>) -> Result<Vec<Result<Value>>> {
+		if requests.is_empty() {
+			return Ok(vec![]);
+		}
+		let mut batch = Vec::with_capacity(requests.len());
+		let mut receivers = Vec::with_capacity(requests.len());
+		{
+			let mut pending = self.pending.lock().unwrap();
+			for (method, params) in requests {
+				let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+				let (sender, receiver) = oneshot::channel();
+				pending.responses.insert(id, sender);
+				receivers.push(receiver);
+				batch.push(
+					json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }),
+				);
+			}
+		}
+		self.outgoing
+			.send(Value::Array(batch).to_string())
+			.map_err(|_| eyre!("JSON-RPC connection is closed"))?;
+		let responses = receivers.into_iter().map(|receiver| async move {
+			receiver
+				.await
+				.map_err(|_| eyre!("JSON-RPC connection is closed"))?
+		});
+		Ok(join_all(responses).await)
+	}
+
+	async fn subscribe(
+		&self,
+		method: &str,
+		params: Vec<Value>,
+	) -> Result<(String, BoxStream<'static, Value>)> {
+		let response = self.request(method, params).await?;
+		let id = subscription_id(&response)
+			.ok_or_else(|| eyre!("Invalid subscription ID {response}"))?;
+		let (sender, receiver) = mpsc::unbounded_channel();
+		let mut pending = self.pending.lock().unwrap();
+		for notification in pending.early.take(&id)? {
+			_ = sender.send(notification);
+		}
+		pending.subscriptions.insert(id.clone(), sender);
+		Ok((id, UnboundedReceiverStream::new(receiver).boxed()))
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StorageQueryType {
+	Value,
+	Hash,
+	ClosestDescendantMerkleValue,
+	DescendantsValues,
+	DescendantsHashes,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct StorageQuery {
+	pub key: Bytes,
+	#[serde(rename = "type")]
+	pub query_type: StorageQueryType,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageResult {
+	pub key: Bytes,
+	#[serde(default)]
+	pub value: Option<Bytes>,
+	#[serde(default)]
+	pub hash: Option<Bytes>,
+	#[serde(default)]
+	pub closest_descendant_merkle_value: Option<Bytes>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BlockEvent {
+	/// First event, with the current finalized block and its recent ancestors
+	Initialized {
+		finalized: Vec<H256>,
+	},
+	NewBlock {
+		hash: H256,
+		parent_hash: H256,
+	},
+	BestBlockChanged {
+		hash: H256,
+	},
+	Finalized {
+		finalized: Vec<H256>,
+		pruned: Vec<H256>,
+	},
+	/// Subscription is stopped by the node, and has to be started again
+	Stop,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum OperationEvent {
+	BodyDone(Vec<Vec<u8>>),
+	CallDone(Vec<u8>),
+	StorageItems(Vec<StorageResult>),
+	WaitingForContinue,
+	StorageDone,
+	Inaccessible,
+	Error(String),
+}
+
+impl OperationEvent {
+	fn is_final(&self) -> bool {
+		!matches!(
+			self,
+			OperationEvent::StorageItems(_) | OperationEvent::WaitingForContinue
+		)
+	}
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "event", rename_all = "camelCase")]
+enum FollowEvent {
+	#[serde(rename_all = "camelCase")]
+	Initialized {
+		finalized_block_hashes: Vec<H256>,
+	},
+	#[serde(rename_all = "camelCase")]
+	NewBlock {
+		block_hash: H256,
+		parent_block_hash: H256,
+	},
+	#[serde(rename_all = "camelCase")]
+	BestBlockChanged {
+		best_block_hash: H256,
+	},
+	#[serde(rename_all = "camelCase")]
+	Finalized {
+		finalized_block_hashes: Vec<H256>,
+		pruned_block_hashes: Vec<H256>,
+	},
+	#[serde(rename_all = "camelCase")]
+	OperationBodyDone {
+		operation_id: String,
+		value: Vec<Bytes>,
+	},
+	#[serde(rename_all = "camelCase")]
+	OperationCallDone {
+		operation_id: String,
+		output: Bytes,
+	},
+	#[serde(rename_all = "camelCase")]
+	OperationStorageItems {
+		operation_id: String,
+		items: Vec<StorageResult>,
+	},
+	#[serde(rename_all = "camelCase")]
+	OperationWaitingForContinue {
+		operation_id: String,
+	},
+	#[serde(rename_all = "camelCase")]
+	OperationStorageDone {
+		operation_id: String,
+	},
+	#[serde(rename_all = "camelCase")]
+	OperationInaccessible {
+		operation_id: String,
+	},
+	#[serde(rename_all = "camelCase")]
+	OperationError {
+		operation_id: String,
+		error: String,
+	},
+	Stop,
+}
+
+enum Routed {
+	Block(BlockEvent),
+	Operation(String, OperationEvent),
+}
+
+impl From<FollowEvent> for Routed {
+	fn from(event: FollowEvent) -> Self {
+		use OperationEvent::*;
+		match event {
+			FollowEvent::Initialized {
+				finalized_block_hashes,
+			} => Routed::Block(BlockEvent::Initialized {
+				finalized: finalized_block_hashes,
+			}),
+			FollowEvent::NewBlock {
+				block_hash,
+				parent_block_hash,
+			} => Routed::Block(BlockEvent::NewBlock {
+				hash: block_hash,
+				parent_hash: parent_block_hash,
+			}),
+			FollowEvent::BestBlockChanged { best_block_hash } => {
+				Routed::Block(BlockEvent::BestBlockChanged {
+					hash: best_block_hash,
+				})
+			},
+			FollowEvent::Finalized {
+				finalized_block_hashes,
+				pruned_block_hashes,
+			} => Routed::Block(BlockEvent::Finalized {
+				finalized: finalized_block_hashes,
+				pruned: pruned_block_hashes,
+			}),
+			FollowEvent::OperationBodyDone {
+				operation_id,
+				value,
+			} => Routed::Operation(
+				operation_id,
+				BodyDone(value.into_iter().map(|bytes| bytes.0).collect()),
+			),
+			FollowEvent::OperationCallDone {
+				operation_id,
+				output,
+			} => Routed::Operation(operation_id, CallDone(output.0)),
+			FollowEvent::OperationStorageItems {
+				operation_id,
+				items,
+			} => Routed::Operation(operation_id, StorageItems(items)),
+			FollowEvent::OperationWaitingForContinue { operation_id } => {
+				Routed::Operation(operation_id, WaitingForContinue)
+			},
+			FollowEvent::OperationStorageDone { operation_id } => {
+				Routed::Operation(operation_id, StorageDone)
+			},
+			FollowEvent::OperationInaccessible { operation_id } => {
+				Routed::Operation(operation_id, Inaccessible)
+			},
+			FollowEvent::OperationError {
+				operation_id,
+				error,
+			} => Routed::Operation(operation_id, Error(error)),
+			FollowEvent::Stop => Routed::Block(BlockEvent::Stop),
+		}
+	}
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "result", rename_all = "camelCase")]
+enum MethodResponse {
+	#[serde(rename_all = "camelCase")]
+	Started {
+		operation_id: String,
+		#[serde(default)]
+		discarded_items: usize,
+	},
+	LimitReached,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChainHeadConfig {
+	/// Number of the most recent finalized blocks which are kept pinned
+	pub max_pinned_finalized: usize,
+	pub limit_retry_delay: Duration,
+	pub max_limit_retries: u32,
+}
+
+impl Default for ChainHeadConfig {
+	fn default() -> Self {
+		ChainHeadConfig {
+			max_pinned_finalized: 16,
+			limit_retry_delay: Duration::from_millis(500),
+			max_limit_retries: 10,
+		}
+	}
+}
+
+#[derive(Default)]
+struct Pinned {
+	blocks: HashSet<H256>,
+	finalized: VecDeque<H256>,
+}
+
+impl Pinned {
+	/// Updates pinned blocks, and returns blocks which should be unpinned
+	fn update(&mut self, event: &BlockEvent, max_finalized: usize) -> Vec<H256> {
+		let mut unpin = vec![];
+		match event {
+			BlockEvent::Initialized { finalized } | BlockEvent::Finalized { finalized, .. } => {
+				self.blocks.extend(finalized);
+				self.finalized.extend(finalized);
+			},
+			BlockEvent::NewBlock { hash, .. } => {
+				self.blocks.insert(*hash);
+			},
+			BlockEvent::BestBlockChanged { .. } | BlockEvent::Stop => (),
+		}
+		if let BlockEvent::Finalized { pruned, .. } = event {
+			unpin.extend(pruned.iter().filter(|hash| self.blocks.remove(*hash)));
+		}
+		while self.finalized.len() > max_finalized {
+			if let Some(hash) = self.finalized.pop_front() {
+				if self.blocks.remove(&hash) {
+					unpin.push(hash);
+				}
+			}
+		}
+		unpin
+	}
+}
+
+#[derive(Default)]
+struct Operations {
+	senders: HashMap<String, mpsc::UnboundedSender<OperationEvent>>,
+	/// Events received before the operation start response is handled
+	buffered: Buffered<OperationEvent>,
+}
+
+struct Inner<T: Transport> {
+	transport: Arc<T>,
+	subscription: String,
+	operations: Mutex<Operations>,
+	pinned: Mutex<Pinned>,
+}
+
+impl<T: Transport> Inner<T> {
+	fn register(&self, operation_id: &str) -> mpsc::UnboundedReceiver<OperationEvent> {
+		let (sender, receiver) = mpsc::unbounded_channel();
+		let mut operations = self.operations.lock().unwrap();
+		let buffered = operations
+			.buffered
+			.take(operation_id)
+			.unwrap_or_else(|error| vec![OperationEvent::Error(error.to_string())]);
+		let done = buffered.iter().any(OperationEvent::is_final);
+		for event in buffered {
+			_ = sender.send(event);
+		}
+		if !done {
+			operations.senders.insert(operation_id.to_string(), sender);
+		}
+		receiver
+	}
+
+	fn dispatch(&self, operation_id: String, event: OperationEvent) {
+		let mut operations = self.operations.lock().unwrap();
+		let done = event.is_final();
+		match operations.senders.get(&operation_id) {
+			Some(sender) => {
+				_ = sender.send(event);
+				if done {
+					operations.senders.remove(&operation_id);
+				}
+			},
+			None => operations.buffered.push(operation_id, event),
+		}
+	}
+
+	async fn unpin(&self, hashes: Vec<H256>) -> Result<()> {
+		let params = vec![json!(self.subscription), json!(hashes)];
+		self.transport.request(UNPIN, params).await?;
+		Ok(())
+	}
+}
+
+async fn drive<T: Transport>(
+	inner: Arc<Inner<T>>,
+	config: ChainHeadConfig,
+	mut stream: BoxStream<'static, Value>,
+	sender: mpsc::UnboundedSender<BlockEvent>,
+) {
+	while let Some(value) = stream.next().await {
+		let event = match serde_json::from_value::<FollowEvent>(value) {
+			Ok(event) => event,
+			Err(error) => {
+				warn!("Invalid chain head follow event: {error}");
+				continue;
+			},
+		};
+		match Routed::from(event) {
+			Routed::Operation(operation_id, event) => inner.dispatch(operation_id, event),
+			Routed::Block(event) => {
+				let unpin = inner
+					.pinned
+					.lock()
+					.unwrap()
+					.update(&event, config.max_pinned_finalized);
+				if !unpin.is_empty() {
+					if let Err(error) = inner.unpin(unpin).await {
+						warn!("Failed to unpin blocks: {error:#}");
+					}
+				}
+				let stop = event == BlockEvent::Stop;
+				// Operations are still routed if the block events receiver is dropped
+				_ = sender.send(event);
+				if stop {
+					break;
+				}
+			},
+		}
+	}
+	// Dropping senders fails the pending operations
+	*inner.operations.lock().unwrap() = Operations::default();
+}
+
+async fn next_event(
+	events: &mut mpsc::UnboundedReceiver<OperationEvent>,
+) -> Result<OperationEvent> {
+	events
+		.recv()
+		.await
+		.ok_or_else(|| eyre!("Chain head follow subscription is stopped"))
+}
+
+fn operation_error(method: &str, event: OperationEvent) -> Report {
+	match event {
+		OperationEvent::Inaccessible => eyre!("{method} failed: block is inaccessible"),
+		OperationEvent::Error(error) => eyre!("{method} failed: {error}"),
+		event => eyre!("{method} failed: unexpected event {event:?}"),
+	}
+}
+
+struct Started {
+	operation_id: String,
+	events: mpsc::UnboundedReceiver<OperationEvent>,
+	discarded_items: usize,
+}
+
+pub struct ChainHead<T: Transport> {
+	inner: Arc<Inner<T>>,
+	config: ChainHeadConfig,
+}
+
+impl<T: Transport> ChainHead<T> {
+	/// Starts the follow subscription, and returns the client with the receiver of block events
+	pub async fn follow(
+		transport: Arc<T>,
+		config: ChainHeadConfig,
+	) -> Result<(Self, mpsc::UnboundedReceiver<BlockEvent>)> {
+		// Runtime calls are rejected by the node, unless the subscription is started with runtime updates
+		let (subscription, stream) = transport.subscribe(FOLLOW, vec![json!(true)]).await?;
+		let inner = Arc::new(Inner {
+			transport,
+			subscription,
+			operations: Default::default(),
+			pinned: Default::default(),
+		});
+		let (sender, receiver) = mpsc::unbounded_channel();
+		tokio::spawn(drive(inner.clone(), config, stream, sender));
+		Ok((ChainHead { inner, config }, receiver))
+	}
+
+	pub fn is_pinned(&self, hash: H256) -> bool {
+		self.inner.pinned.lock().unwrap().blocks.contains(&hash)
+	}
+
+	fn params(&self, hash: H256) -> Vec<Value> {
+		vec![json!(self.inner.subscription), json!(hash)]
+	}
+
+	/// Starts the operation, retrying while the node operations limit is reached
+	async fn start(&self, method: &str, params: Vec<Value>) -> Result<Started> {
+		for attempt in 0..=self.config.max_limit_retries {
+			let response = self.inner.transport.request(method, params.clone()).await?;
+			let response = serde_json::from_value(response)
+				.wrap_err_with(|| format!("Invalid {method} response"))?;
+			match response {
+				MethodResponse::Started {
+					operation_id,
+					discarded_items,
+				} => {
+					let events = self.inner.register(&operation_id);
+					return Ok(Started {
+						operation_id,
+						events,
+						discarded_items,
+					});
+				},
+				MethodResponse::LimitReached => {
+					debug!(method, attempt, "Chain head operations limit is reached");
+					if attempt < self.config.max_limit_retries {
+						tokio::time::sleep(self.config.limit_retry_delay).await;
+					}
+				},
+			}
+		}
+		Err(eyre!("{method} failed: operations limit is reached"))
+	}
+
+	/// Returns header of the pinned block
+	pub async fn header(&self, hash: H256) -> Result<Option<Header>> {
+		let response = self
+			.inner
+			.transport
+			.request(HEADER, self.params(hash))
+			.await?;
+		let Some(encoded) = serde_json::from_value::<Option<Bytes>>(response)? else {
+			return Ok(None);
+		};
+		let header = Header::decode(&mut &encoded[..]).wrap_err("Failed to decode header")?;
+		Ok(Some(header))
+	}
+
+	/// Returns SCALE encoded extrinsics of the pinned block
+	pub async fn body(&self, hash: H256) -> Result<Vec<Vec<u8>>> {
+		let mut started = self.start(BODY, self.params(hash)).await?;
+		match next_event(&mut started.events).await? {
+			OperationEvent::BodyDone(extrinsics) => Ok(extrinsics),
+			event => Err(operation_error(BODY, event)),
+		}
+	}
+
+	/// Calls runtime API function with SCALE encoded parameters at the pinned block
+	pub async fn call(&self, hash: H256, function: &str, parameters: &[u8]) -> Result<Vec<u8>> {
+		let mut params = self.params(hash);
+		params.extend([json!(function), json!(Bytes(parameters.to_vec()))]);
+		let mut started = self.start(CALL, params).await?;
+		match next_event(&mut started.events).await? {
+			OperationEvent::CallDone(output) => Ok(output),
+			event => Err(operation_error(CALL, event)),
+		}
+	}
+
+	/// Queries storage of the pinned block, queries discarded by the node are sent again
+	pub async fn storage(
+		&self,
+		hash: H256,
+		queries: Vec<StorageQuery>,
+	) -> Result<Vec<StorageResult>> {
+		let mut results = vec![];
+		let mut remaining = queries;
+		while !remaining.is_empty() {
+			let mut params = self.params(hash);
+			params.extend([json!(remaining), Value::Null]);
+			let mut started = self.start(STORAGE, params).await?;
+			let accepted = remaining.len().saturating_sub(started.discarded_items);
+			if accepted == 0 {
+				return Err(eyre!("{STORAGE} failed: all queries are discarded"));
+			}
+			remaining = remaining.split_off(accepted);
+			loop {
+				match next_event(&mut started.events).await? {
+					OperationEvent::StorageItems(items) => results.extend(items),
+					OperationEvent::WaitingForContinue => {
+						let params =
+							vec![json!(self.inner.subscription), json!(started.operation_id)];
+						self.inner.transport.request(CONTINUE, params).await?;
+					},
+					OperationEvent::StorageDone => break,
+					event => return Err(operation_error(STORAGE, event)),
+				}
+			}
+		}
+		Ok(results)
+	}
+
+	/// Stops the follow subscription
+	pub async fn unfollow(self) -> Result<()> {
+		let params = vec![json!(self.inner.subscription)];
+		self.inner.transport.request(UNFOLLOW, params).await?;
+		Ok(())
+	}
+}
+
+/// Broadcasts the SCALE encoded extrinsic (`transaction_v1_broadcast`), returns `None` if the node limit is reached
+pub async fn broadcast<T: Transport>(transport: &T, extrinsic: &[u8]) -> Result<Option<String>> {
+	let response = transport
+		.request(BROADCAST, vec![json!(Bytes(extrinsic.to_vec()))])
+		.await?;
+	Ok(subscription_id(&response))
+}
+
+/// Stops broadcasting of the extrinsic
+pub async fn stop_broadcast<T: Transport>(transport: &T, operation_id: &str) -> Result<()> {
+	transport.request(STOP, vec![json!(operation_id)]).await?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use codec::Encode;
+
+	type Handler = Box<dyn Fn(&str, &[Value]) -> Value + Send + Sync>;
+
+	/// Transport which answers requests with the handler, and sends follow events from the test
+	struct TestTransport {
+		handler: Handler,
+		requests: Mutex<Vec<(String, Vec<Value>)>>,
+		events: mpsc::UnboundedSender<Value>,
+		stream: Mutex<Option<mpsc::UnboundedReceiver<Value>>>,
+	}
+
+	impl TestTransport {
+		fn new(handler: impl Fn(&str, &[Value]) -> Value + Send + Sync + 'static) -> Arc<Self> {
+			let (events, stream) = mpsc::unbounded_channel();
+			Arc::new(TestTransport {
+				handler: Box::new(handler),
+				requests: Default::default(),
+				events,
+				stream: Mutex::new(Some(stream)),
+			})
+		}
+
+		fn requests(&self, method: &str) -> Vec<Vec<Value>> {
+			let requests = self.requests.lock().unwrap();
+			requests
+				.iter()
+				.filter(|(request, _)| request == method)
+				.map(|(_, params)| params.clone())
+				.collect()
+		}
+	}
+
+	#[async_trait]
+	impl Transport for TestTransport {
+		async fn request(&self, method: &str, params: Vec<Value>) -> Result<Value> {
+			let response = (self.handler)(method, &params);
+			self.requests
+				.lock()
+				.unwrap()
+				.push((method.to_string(), params));
+			Ok(response)
+		}
+
+		async fn subscribe(
+			&self,
+			_: &str,
+			_: Vec<Value>,
+		) -> Result<(String, BoxStream<'static, Value>)> {
+			let stream = self.stream.lock().unwrap().take().unwrap();
+			Ok((
+				"follow".to_string(),
+				UnboundedReceiverStream::new(stream).boxed(),
+			))
+		}
+	}
+
+	fn hash(byte: u8) -> H256 {
+		H256::repeat_byte(byte)
+	}
+
+	#[tokio::test]
+	async fn block_events_and_pinning() {
+		let transport = TestTransport::new(|_, _| Value::Null);
+		let config = ChainHeadConfig {
+			max_pinned_finalized: 1,
+			..Default::default()
+		};
+		let (chain_head, mut blocks) = ChainHead::follow(transport.clone(), config).await.unwrap();
+		let events = [
+			json!({ "event": "initialized", "finalizedBlockHashes": [hash(1)] }),
+			json!({ "event": "newBlock", "blockHash": hash(2), "parentBlockHash": hash(1) }),
+			json!({ "event": "newBlock", "blockHash": hash(3), "parentBlockHash": hash(1) }),
+			json!({ "event": "bestBlockChanged", "bestBlockHash": hash(2) }),
+			json!({
+				"event": "finalized",
+				"finalizedBlockHashes": [hash(2)],
+				"prunedBlockHashes": [hash(3)],
+			}),
+			json!({ "event": "stop" }),
+		];
+		for event in events {
+			transport.events.send(event).unwrap();
+		}
+		let mut received = vec![];
+		while let Some(event) = blocks.recv().await {
+			received.push(event);
+		}
+		assert_eq!(
+			received[1],
+			BlockEvent::NewBlock {
+				hash: hash(2),
+				parent_hash: hash(1)
+			}
+		);
+		assert_eq!(received.last(), Some(&BlockEvent::Stop));
+		assert_eq!(received.len(), 6);
+
+		// Pruned block and the finalized block beyond the limit are unpinned
+		assert_eq!(
+			transport.requests(UNPIN),
+			[vec![json!("follow"), json!([hash(3), hash(1)])]]
+		);
+		assert!(chain_head.is_pinned(hash(2)));
+		assert!(!chain_head.is_pinned(hash(1)));
+	}
+
+	#[test]
+	fn buffered_overflow() {
+		let mut buffered = Buffered::default();
+		for event in 0..=MAX_BUFFERED_EVENTS {
+			buffered.push("1".to_string(), event);
+			buffered.push("2".to_string(), event);
+		}
+		buffered.push("3".to_string(), 0);
+		assert!(buffered.take("1").is_err());
+		assert!(buffered.take("2").is_err());
+		assert_eq!(buffered.take("3").unwrap(), [0]);
+		// Overflow is reported once
+		assert!(buffered.take("1").unwrap().is_empty());
+	}
+
+	#[tokio::test]
+	async fn operations() {
+		let header = crate::simulation::header(1, hash(1), 0);
+		let encoded_header = Bytes(header.encode());
+		let transport = Arc::new(Mutex::new(None::<Arc<TestTransport>>));
+		let events = transport.clone();
+		let handler = move |method: &str, _: &[Value]| -> Value {
+			let send = |event: Value| {
+				let transport = events.lock().unwrap();
+				transport.as_ref().unwrap().events.send(event).unwrap();
+			};
+			match method {
+				HEADER => json!(encoded_header),
+				BODY => {
+					// Operation result is received before the start response
+					send(json!({
+						"event": "operationBodyDone",
+						"operationId": "1",
+						"value": ["0x080102"],
+					}));
+					json!({ "result": "started", "operationId": "1" })
+				},
+				CALL => json!({ "result": "limitReached" }),
+				_ => Value::Null,
+			}
+		};
+		let test_transport = TestTransport::new(handler);
+		*transport.lock().unwrap() = Some(test_transport.clone());
+		let config = ChainHeadConfig {
+			limit_retry_delay: Duration::from_millis(1),
+			max_limit_retries: 2,
+			..Default::default()
+		};
+		let (chain_head, _blocks) = ChainHead::follow(test_transport.clone(), config)
+			.await
+			.unwrap();
+
+		assert_eq!(chain_head.header(hash(2)).await.unwrap(), Some(header));
+		assert_eq!(chain_head.body(hash(2)).await.unwrap(), [vec![8, 1, 2]]);
+		assert!(chain_head.call(hash(2), "Core_version", &[]).await.is_err());
+		assert_eq!(test_transport.requests(CALL).len(), 3);
+	}
+
+	#[tokio::test]
+	async fn storage_with_continue() {
+		let transport = Arc::new(Mutex::new(None::<Arc<TestTransport>>));
+		let events = transport.clone();
+		let handler = move |method: &str, params: &[Value]| -> Value {
+			let send = |event: Value| {
+				let transport = events.lock().unwrap();
+				transport.as_ref().unwrap().events.send(event).unwrap();
+			};
+			match method {
+				STORAGE => {
+					let queries = params[2].as_array().unwrap();
+					let id = format!("{}", queries.len());
+					let key = &queries[0]["key"];
+					send(json!({
+						"event": "operationStorageItems",
+						"operationId": id,
+						"items": [{ "key": key, "value": "0x01" }],
+					}));
+					send(json!({ "event": "operationWaitingForContinue", "operationId": id }));
+					// Only the first query is accepted
+					json!({
+						"result": "started",
+						"operationId": id,
+						"discardedItems": queries.len() - 1,
+					})
+				},
+				CONTINUE => {
+					send(json!({ "event": "operationStorageDone", "operationId": params[1] }));
+					Value::Null
+				},
+				_ => Value::Null,
+			}
+		};
+		let test_transport = TestTransport::new(handler);
+		*transport.lock().unwrap() = Some(test_transport.clone());
+		let (chain_head, _blocks) = ChainHead::follow(test_transport.clone(), Default::default())
+			.await
+			.unwrap();
+
+		let queries = [vec![1], vec![2]]
+			.map(|key| StorageQuery {
+				key: Bytes(key),
+				query_type: StorageQueryType::Value,
+			})
+			.to_vec();
+		let results = chain_head.storage(hash(2), queries).await.unwrap();
+		let keys = results
+			.iter()
+			.map(|result| result.key.0.clone())
+			.collect::<Vec<_>>();
+		assert_eq!(keys, [vec![1], vec![2]]);
+		assert_eq!(test_transport.requests(STORAGE).len(), 2);
+		assert_eq!(test_transport.requests(CONTINUE).len(), 2);
+	}
+}
@@ -6,32 +6,48 @@ use avail_subxt::{
 	utils::H256,
 	AvailConfig,
 };
+use codec::{Decode, Encode};
 use color_eyre::{eyre::eyre, Report, Result};
 use futures::{Stream, TryFutureExt, TryStreamExt};
 use kate_recovery::{data::Cell, matrix::Position};
+use serde::Deserialize;
 use sp_core::{
+	blake2_256,
 	bytes::from_hex,
 	ed25519::{self, Public},
 };
 use std::sync::{Arc, Mutex};
 use subxt::{
+	blocks::ExtrinsicEvents,
 	rpc::{types::BlockNumber, RpcParams},
 	rpc_params,
 	storage::StorageKey,
-	tx::{PairSigner, SubmittableExtrinsic},
+	tx::{PairSigner, SubmittableExtrinsic, TxProgress},
 	utils::AccountId32,
 };
 use tokio::sync::RwLock;
 use tokio_retry::Retry;
 use tokio_stream::StreamExt;
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
 
-use super::{Node, Nodes, Subscription, WrappedProof, CELL_WITH_PROOF_SIZE};
+use super::{
+	chain_head::{ChainHead, ChainHeadConfig, WsTransport},
+	pipeline::{Pipeline, PipelineConfig},
+	pool::{EndpointPool, PoolConfig, SubxtEndpoint},
+	DataProof, Node, Nodes, Subscription, WrappedProof, CELL_WITH_PROOF_SIZE,
+};
 use crate::{
+	cache::Caches,
 	consts::ExpectedNodeVariant,
+	extrinsic::errors::{validity_error, DispatchError},
 	types::{RetryConfig, RuntimeVersion, State, DEV_FLAG_GENHASH},
 };
 
+#[derive(Deserialize)]
+struct ReadProof {
+	proof: Vec<sp_core::Bytes>,
+}
+
 #[derive(Clone)]
 pub struct Client {
 	subxt_client: Arc<RwLock<avail::Client>>,
@@ -39,6 +55,13 @@ pub struct Client {
 	nodes: Nodes,
 	retry_config: RetryConfig,
 	expected_genesis_hash: String,
+	caches: Option<Arc<Caches>>,
+	/// Pool of the configured nodes, used to rank nodes on failover
+	pool: Option<Arc<EndpointPool<SubxtEndpoint>>>,
+	/// Follow subscription of the new JSON-RPC spec, used for the pinned blocks
+	chain_head: Option<Arc<ChainHead<WsTransport>>>,
+	/// Pipelined requests over the connection of the chain head subscription, used for the bulk header fetching
+	pipeline: Option<Arc<Pipeline<WsTransport>>>,
 }
 
 impl Client {
@@ -47,6 +70,7 @@ impl Client {
 		nodes: Nodes,
 		expected_genesis_hash: &str,
 		retry_config: RetryConfig,
+		caches: Option<Arc<Caches>>,
 	) -> Result<Self> {
 		// try and connect appropriate Node from the provided list
 		// will do retries with the provided Retry Config
@@ -70,9 +94,104 @@ impl Client {
 			nodes,
 			retry_config,
 			expected_genesis_hash: expected_genesis_hash.to_string(),
+			caches,
+			pool: None,
+			chain_head: None,
+			pipeline: None,
 		})
 	}
 
+	/// Connects to all configured nodes, and ranks nodes on failover by their health and latency. Nodes which
+	/// cannot be connected are still used on failover, after the ranked ones.
+	pub async fn with_endpoint_pool(mut self, config: PoolConfig) -> Self {
+		let mut endpoints = vec![];
+		for Node { host, .. } in self.nodes.iter() {
+			match SubxtEndpoint::connect(host).await {
+				Ok(endpoint) => endpoints.push(endpoint),
+				Err(error) => warn!(host, "Node is not added to the endpoint pool: {error:#}"),
+			}
+		}
+		self.pool = Some(Arc::new(EndpointPool::new(endpoints, config)));
+		self
+	}
+
+	/// Follows the chain of the connected node with the new JSON-RPC spec (`chainHead_v1_*`). Bodies and runtime calls
+	/// of the blocks pinned by the subscription are fetched with it, falling back to the legacy methods. Headers are
+	/// fetched in bulk with the pipelined batches over the same connection.
+	pub async fn with_chain_head(mut self, config: ChainHeadConfig) -> Self {
+		let host = self.state.lock().unwrap().connected_node.host.clone();
+		let chain_head = async {
+			let transport = Arc::new(WsTransport::connect(&host).await?);
+			// Block events are not used, operations are routed regardless
+			let (chain_head, _) = ChainHead::follow(transport.clone(), config).await?;
+			Ok::<_, Report>((chain_head, transport))
+		};
+		match chain_head.await {
+			Ok((chain_head, transport)) => {
+				self.chain_head = Some(Arc::new(chain_head));
+				let pipeline = Pipeline::new(transport, PipelineConfig::default());
+				self.pipeline = Some(Arc::new(pipeline));
+			},
+			Err(error) => warn!(host, "Chain head subscription is not started: {error:#}"),
+		}
+		self
+	}
+
+	/// Returns `true` if headers are fetched in bulk with the pipelined batches
+	pub fn is_pipelined(&self) -> bool {
+		self.pipeline.is_some()
+	}
+
+	/// Returns the chain head subscription, if the block is pinned by it
+	fn pinned_chain_head(&self, block_hash: H256) -> Option<&ChainHead<WsTransport>> {
+		self.chain_head
+			.as_deref()
+			.filter(|chain_head| chain_head.is_pinned(block_hash))
+	}
+
+	/// Returns nodes to fail over to, ranked by the endpoint pool if configured, otherwise shuffled
+	fn failover_nodes(&self, current_host: String) -> Vec<Node> {
+		let mut nodes = self.nodes.shuffle(current_host.clone());
+		let Some(pool) = &self.pool else {
+			return nodes;
+		};
+		pool.report_failure(&current_host);
+		let ranked = pool.ranked_urls();
+		let pooled = pool.status();
+		// Faulty nodes are skipped, and nodes outside of the pool are tried last
+		nodes.retain(|node| {
+			ranked.contains(&node.host) || !pooled.iter().any(|status| status.url == node.host)
+		});
+		nodes.sort_by_key(|node| {
+			ranked
+				.iter()
+				.position(|url| *url == node.host)
+				.unwrap_or(ranked.len())
+		});
+		nodes
+	}
+
+	/// Checks health of the pool endpoints, and cross verifies the finalized block hash between them and the
+	/// connected node
+	pub async fn check_endpoints(&self) -> Result<()> {
+		let Some(pool) = &self.pool else {
+			return Ok(());
+		};
+		let healthy = pool.check_health().await;
+		debug!(healthy, "RPC endpoints are checked");
+		// Lagging endpoints may not have the latest finalized block
+		let finalized = self.get_chain_head_header().await?.number;
+		let number = finalized.saturating_sub(pool.config().max_lag);
+		let hash = pool.verify_block_hash(number).await?;
+		if self.get_block_hash(number).await? != hash {
+			let host = self.state.lock().unwrap().connected_node.host.clone();
+			return Err(eyre!(
+				"Connected node {host} is inconsistent with the other nodes at block {number}"
+			));
+		}
+		Ok(())
+	}
+
 	async fn create_subxt_client(
 		host: &str,
 		expected_node: ExpectedNodeVariant,
@@ -178,8 +297,8 @@ impl Client {
 			"Executing RPC call with host: {} failed. Trying to create a new RPC connection.",
 			connected_node.host
 		);
-		// shuffle nodes, if possible
-		let nodes = self.nodes.shuffle(connected_node.host);
+		// rank or shuffle nodes, if possible
+		let nodes = self.failover_nodes(connected_node.host);
 		// go through available Nodes, try to connect, Retry connecting if needed
 		let (client, node, result) = Retry::spawn(self.retry_config.clone(), move || {
 			let nodes = nodes.clone();
@@ -275,15 +394,57 @@ impl Client {
 		Ok(hash)
 	}
 
+	/// Caches of the client, shared with the other components
+	pub fn caches(&self) -> Option<&Arc<Caches>> {
+		self.caches.as_ref()
+	}
+
 	pub async fn get_header_by_hash(&self, block_hash: H256) -> Result<Header> {
+		if let Some(header) = self
+			.caches
+			.as_ref()
+			.and_then(|caches| caches.headers.get(&block_hash))
+		{
+			return Ok(header);
+		}
+
 		let header = self
 			.with_retries(|client| async move { client.rpc().header(Some(block_hash)).await })
 			.await?
 			.ok_or_else(|| eyre!("Block Header with hash: {:?} not found", block_hash))?;
 
+		// Only headers matching the requested hash are cached, so invalid responses are not served again
+		if let Some(caches) = &self.caches {
+			if H256::from(header.using_encoded(blake2_256)) == block_hash {
+				caches.headers.put(block_hash, header.clone());
+			}
+		}
+
 		Ok(header)
 	}
 
+	/// Fetches SCALE encoded extrinsics of the block (without length prefix), which are not verified against the header
+	pub async fn get_block_body(&self, block_hash: H256) -> Result<Vec<Vec<u8>>> {
+		if let Some(chain_head) = self.pinned_chain_head(block_hash) {
+			match chain_head.body(block_hash).await {
+				Ok(extrinsics) => return decode_extrinsics(extrinsics),
+				Err(error) => debug!(?block_hash, "Chain head body failed: {error:#}"),
+			}
+		}
+		let block = self
+			.with_retries(|client| async move { client.rpc().block(Some(block_hash)).await })
+			.await?
+			.ok_or_else(|| eyre!("Block with hash: {:?} not found", block_hash))?;
+
+		decode_extrinsics(
+			block
+				.block
+				.extrinsics
+				.into_iter()
+				.map(|extrinsic| extrinsic.0),
+		)
+	}
+
 	pub async fn get_validator_set_by_hash(&self, block_hash: H256) -> Result<Vec<Public>> {
 		let res = self
 			.with_retries(|client| async move {
@@ -362,6 +523,21 @@ impl Client {
 		Ok(proof)
 	}
 
+	pub async fn request_data_proof(
+		&self,
+		transaction_index: u32,
+		block_hash: H256,
+	) -> Result<DataProof> {
+		let params = rpc_params![transaction_index, block_hash];
+
+		self.with_retries(|client| {
+			let params = params.clone();
+			async move { client.rpc().request("kate_queryDataProof", params).await }
+		})
+		.await
+		.map_err(|e| eyre!("Request failed at Query Data Proof. Error: {e}"))
+	}
+
 	pub async fn get_system_version(&self) -> Result<String> {
 		let res = self
 			.with_retries(|client| async move { client.rpc().system_version().await })
@@ -370,6 +546,17 @@ impl Client {
 		Ok(res)
 	}
 
+	/// Fetches chain name and properties of the chain specification
+	pub async fn get_chain_spec(&self) -> Result<(String, serde_json::Value)> {
+		let name = self
+			.with_retries(|client| async move { client.rpc().system_chain().await })
+			.await?;
+		let properties = self
+			.with_retries(|client| async move { client.rpc().system_properties().await })
+			.await?;
+		Ok((name, serde_json::Value::Object(properties)))
+	}
+
 	pub async fn get_runtime_version(&self) -> Result<RuntimeVersion> {
 		let res: RuntimeVersion = self
 			.with_retries(|client| async move {
@@ -383,6 +570,31 @@ impl Client {
 		Ok(res)
 	}
 
+	/// Fetches runtime version at the given block
+	pub async fn get_runtime_version_at(&self, block_hash: H256) -> Result<RuntimeVersion> {
+		self.with_retries(|client| async move {
+			client
+				.rpc()
+				.request("state_getRuntimeVersion", rpc_params![block_hash])
+				.await
+		})
+		.await
+	}
+
+	/// Fetches metadata of the runtime at the given block
+	pub async fn get_metadata_at(&self, block_hash: H256) -> Result<subxt::Metadata> {
+		let metadata: sp_core::Bytes = self
+			.with_retries(|client| async move {
+				client
+					.rpc()
+					.request("state_getMetadata", rpc_params![block_hash])
+					.await
+			})
+			.await?;
+		<subxt::Metadata as codec::Decode>::decode(&mut &metadata[..])
+			.map_err(|error| eyre!("Failed to decode runtime metadata: {error}"))
+	}
+
 	pub async fn get_validator_set_by_block_number(&self, block_num: u32) -> Result<Vec<Public>> {
 		let hash = self.get_block_hash(block_num).await?;
 		self.get_validator_set_by_hash(hash).await
@@ -412,6 +624,23 @@ impl Client {
 			.map(|header| (header, hash))
 	}
 
+	/// Fetches headers of the blocks, with the pipelined batches if the chain head is followed, otherwise one by one
+	pub async fn get_headers_by_block_number(
+		&self,
+		block_numbers: &[u32],
+	) -> Result<Vec<(Header, H256)>> {
+		if let Some(pipeline) = &self.pipeline {
+			return pipeline
+				.headers_by_number(block_numbers.iter().copied())
+				.await;
+		}
+		let mut headers = Vec::with_capacity(block_numbers.len());
+		for &block_number in block_numbers {
+			headers.push(self.get_header_by_block_number(block_number).await?);
+		}
+		Ok(headers)
+	}
+
 	pub async fn get_validator_set_at(&self, block_hash: H256) -> Result<Option<Vec<AccountId32>>> {
 		let res = self
 			.with_retries(|client| {
@@ -429,7 +658,7 @@ impl Client {
 		call: &Call,
 		signer: &PairSigner<AvailConfig, Pair>,
 		other_params: avail_subxt::primitives::AvailExtrinsicParams,
-	) -> Result<subxt::blocks::ExtrinsicEvents<AvailConfig>> {
+	) -> Result<ExtrinsicEvents<AvailConfig>> {
 		let tx_progress = self
 			.with_retries(|client| {
 				let other_params = other_params.clone();
@@ -442,16 +671,13 @@ impl Client {
 			})
 			.await?;
 
-		tx_progress
-			.wait_for_finalized_success()
-			.await
-			.map_err(Report::from)
+		self.wait_for_finalized_events(tx_progress).await
 	}
 
 	pub async fn submit_from_bytes_and_wait_for_finalized(
 		&self,
 		tx_bytes: Vec<u8>,
-	) -> Result<subxt::blocks::ExtrinsicEvents<AvailConfig>> {
+	) -> Result<ExtrinsicEvents<AvailConfig>> {
 		let tx_progress = self
 			.with_retries(|client| {
 				let tx_bytes = tx_bytes.clone();
@@ -463,12 +689,51 @@ impl Client {
 			})
 			.await?;
 
-		let ext = tx_progress
-			.wait_for_finalized_success()
-			.await
-			.map_err(Report::from)?;
+		self.wait_for_finalized_events(tx_progress).await
+	}
 
-		Ok(ext)
+	/// Waits until the extrinsic is finalized, and returns its events, or its dispatch error described with the
+	/// metadata (e.g. `balances.InsufficientBalance`)
+	async fn wait_for_finalized_events(
+		&self,
+		tx_progress: TxProgress<AvailConfig, avail::Client>,
+	) -> Result<ExtrinsicEvents<AvailConfig>> {
+		let events = tx_progress
+			.wait_for_finalized()
+			.await?
+			.fetch_events()
+			.await?;
+		for event in events.iter() {
+			let event = event?;
+			if event.pallet_name() != "System" || event.variant_name() != "ExtrinsicFailed" {
+				continue;
+			}
+			// Dispatch error is the first field of the event
+			let error = DispatchError::decode(&mut event.field_bytes())
+				.map_err(|error| eyre!("Cannot decode dispatch error: {error}"))?;
+			let metadata = self.current_client().await.metadata();
+			return Err(eyre!("Extrinsic failed: {}", error.describe(&metadata)));
+		}
+		Ok(events)
+	}
+
+	/// Validates the SCALE encoded extrinsic with the transaction pool runtime API, at the finalized block
+	pub async fn validate_transaction(&self, tx_bytes: &[u8]) -> Result<()> {
+		let finalized_hash = self.get_finalized_head_hash().await?;
+		// Transaction source is `External`, extrinsic is already length prefixed
+		let data = [&[2][..], tx_bytes, &finalized_hash.encode()].concat();
+		let result = self
+			.state_call(
+				"TaggedTransactionQueue_validate_transaction",
+				data,
+				Some(finalized_hash),
+			)
+			.await?;
+		match validity_error(&result) {
+			Ok(None) => Ok(()),
+			Ok(Some(error)) => Err(eyre!("Transaction is invalid: {error}")),
+			Err(error) => Err(eyre!("Cannot decode transaction validity: {error}")),
+		}
 	}
 
 	pub async fn get_paged_storage_keys(
@@ -535,9 +800,126 @@ impl Client {
 		Ok(res)
 	}
 
+	pub async fn get_read_proof(
+		&self,
+		keys: Vec<Vec<u8>>,
+		block_hash: H256,
+	) -> Result<Vec<Vec<u8>>> {
+		let mut params = RpcParams::new();
+		params.push(
+			keys.iter()
+				.map(hex::encode)
+				.map(|key| format!("0x{key}"))
+				.collect::<Vec<_>>(),
+		)?;
+		params.push(block_hash)?;
+
+		let res: ReadProof = self
+			.with_retries(|client| {
+				let params = params.clone();
+				async move { client.rpc().request("state_getReadProof", params).await }
+			})
+			.await
+			.map_err(|e| eyre!("Request failed at Read Proof. Error: {e}"))?;
+
+		Ok(res.proof.into_iter().map(|node| node.0).collect())
+	}
+
+	pub async fn get_storage_value(
+		&self,
+		key: Vec<u8>,
+		block_hash: H256,
+	) -> Result<Option<Vec<u8>>> {
+		let res = self
+			.with_retries(|client| {
+				let key = key.clone();
+				async move { client.rpc().storage(&key, Some(block_hash)).await }
+			})
+			.await?;
+
+		Ok(res.map(|data| data.0))
+	}
+
+	pub async fn get_storage_hash(&self, key: Vec<u8>, block_hash: H256) -> Result<Option<H256>> {
+		let params = rpc_params![format!("0x{}", hex::encode(key)), block_hash];
+
+		let res: Option<H256> = self
+			.with_retries(|client| {
+				let params = params.clone();
+				async move { client.rpc().request("state_getStorageHash", params).await }
+			})
+			.await?;
+
+		Ok(res)
+	}
+
+	/// Fetches runtime version and metadata of the latest runtime, and updates the client with them,
+	/// so call indices and transaction versions of submitted extrinsics are up to date.
+	pub async fn update_runtime(&self) -> Result<(RuntimeVersion, subxt::Metadata)> {
+		let runtime_version = self.get_runtime_version().await?;
+		let metadata: sp_core::Bytes = self
+			.with_retries(|client| async move {
+				client
+					.rpc()
+					.request("state_getMetadata", RpcParams::new())
+					.await
+			})
+			.await?;
+		let metadata = <subxt::Metadata as codec::Decode>::decode(&mut &metadata[..])
+			.map_err(|error| eyre!("Failed to decode runtime metadata: {error}"))?;
+
+		let client = self.current_client().await;
+		client.set_metadata(metadata.clone());
+		client.set_runtime_version(subxt::rpc::types::RuntimeVersion {
+			spec_version: runtime_version.spec_version,
+			transaction_version: runtime_version.transaction_version,
+			other: Default::default(),
+		});
+
+		Ok((runtime_version, metadata))
+	}
+
+	/// Calls runtime API method with SCALE encoded arguments, at the given block or at the best block
+	pub async fn state_call(
+		&self,
+		method: &str,
+		data: Vec<u8>,
+		block_hash: Option<H256>,
+	) -> Result<Vec<u8>> {
+		let pinned = block_hash.and_then(|hash| Some((hash, self.pinned_chain_head(hash)?)));
+		if let Some((hash, chain_head)) = pinned {
+			match chain_head.call(hash, method, &data).await {
+				Ok(output) => return Ok(output),
+				Err(error) => debug!(method, "Chain head call failed: {error:#}"),
+			}
+		}
+		let params = rpc_params![method, format!("0x{}", hex::encode(data)), block_hash];
+
+		let res: sp_core::Bytes = self
+			.with_retries(|client| {
+				let params = params.clone();
+				async move { client.rpc().request("state_call", params).await }
+			})
+			.await
+			.map_err(|e| eyre!("Request failed at State Call {method}. Error: {e}"))?;
+
+		Ok(res.0)
+	}
+
 	pub async fn get_genesis_hash(&self) -> Result<H256> {
 		let gen_hash = self.current_client().await.genesis_hash();
 
 		Ok(gen_hash)
 	}
 }
+
+/// Decodes SCALE encoded (length prefixed) extrinsics into their opaque bytes
+fn decode_extrinsics(extrinsics: impl IntoIterator<Item = Vec<u8>>) -> Result<Vec<Vec<u8>>> {
+	extrinsics
+		.into_iter()
+		.map(|extrinsic| {
+			Vec::<u8>::decode(&mut &extrinsic[..])
+				.map_err(|error| eyre!("Invalid extrinsic encoding: {error}"))
+		})
+		.collect()
+}
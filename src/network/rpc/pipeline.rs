@@ -0,0 +1,287 @@
+//! Pipelined execution of the JSON-RPC requests, for bulk fetching of headers and storage entries.
+//!
+//! Requests are grouped into JSON-RPC batches of [`PipelineConfig::batch_size`], and up to
+//! [`PipelineConfig::concurrency`] batches are in flight at once. Results are returned in the order of requests,
+//! regardless of the order in which batches complete.
+//!
+//! Pipeline shares the connection of the chain head subscription (see [`super::Client::with_chain_head`]), and
+//! the sync and backfill prefetch headers of the upcoming blocks with it.
+//!
+//! # Notes
+//!
+//! Fetched headers are verified against the requested block hashes, and storage values against the read proofs
+//! and the state root of the header, so the pipeline can be used with the untrusted nodes. Node can still reply
+//! with the hash of the wrong block, which has to be caught by the finality verification.
+
+use avail_subxt::{primitives::Header, utils::H256};
+use codec::Encode;
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sp_core::{blake2_256, Bytes};
+use std::sync::Arc;
+
+use super::chain_head::Transport;
+use crate::storage_proof::verify_read_proof;
+
+/// Maximum number of keys in a single read proof request
+const MAX_PROOF_KEYS: usize = 64;
+
+#[derive(Deserialize)]
+struct ReadProof {
+	proof: Vec<Bytes>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct PipelineConfig {
+	/// Maximum number of requests in a single batch
+	pub batch_size: usize,
+	/// Maximum number of batches in flight
+	pub concurrency: usize,
+}
+
+impl Default for PipelineConfig {
+	fn default() -> Self {
+		PipelineConfig {
+			batch_size: 64,
+			concurrency: 4,
+		}
+	}
+}
+
+pub struct Pipeline<T: Transport> {
+	transport: Arc<T>,
+	config: PipelineConfig,
+}
+
+impl<T: Transport> Pipeline<T> {
+	pub fn new(transport: Arc<T>, config: PipelineConfig) -> Self {
+		Pipeline { transport, config }
+	}
+
+	/// Executes requests, and returns results in the order of requests
+	pub async fn execute(&self, requests: Vec<(String, Vec<Value>)>) -> Vec<Result<Value>> {
+		let batch_size = self.config.batch_size.max(1);
+		let mut batches = vec![];
+		let mut requests = requests.into_iter().peekable();
+		while requests.peek().is_some() {
+			batches.push(requests.by_ref().take(batch_size).collect::<Vec<_>>());
+		}
+		stream::iter(batches)
+			.map(|batch| async move {
+				let size = batch.len();
+				match self.transport.batch(batch).await {
+					Ok(results) if results.len() == size => results,
+					Ok(_) => (0..size)
+						.map(|_| Err(eyre!("Invalid batch response")))
+						.collect(),
+					// Report is not cloneable, so the error is formatted for each request
+					Err(error) => (0..size)
+						.map(|_| Err(eyre!("Batch request failed: {error:#}")))
+						.collect(),
+				}
+			})
+			.buffered(self.config.concurrency.max(1))
+			.flat_map(stream::iter)
+			.collect()
+			.await
+	}
+
+	async fn execute_all<R: for<'de> Deserialize<'de>>(
+		&self,
+		method: &str,
+		params: impl Iterator<Item = Vec<Value>>,
+	) -> Result<Vec<R>> {
+		let requests = params.map(|params| (method.to_string(), params)).collect();
+		self.execute(requests)
+			.await
+			.into_iter()
+			.map(|result| {
+				let value = result?;
+				serde_json::from_value(value).wrap_err_with(|| format!("Invalid {method} response"))
+			})
+			.collect()
+	}
+
+	/// Fetches hashes of the blocks, fails if any block is not found
+	pub async fn block_hashes(&self, numbers: impl IntoIterator<Item = u32>) -> Result<Vec<H256>> {
+		let numbers = numbers.into_iter().collect::<Vec<_>>();
+		let hashes: Vec<Option<H256>> = self
+			.execute_all(
+				"chain_getBlockHash",
+				numbers.iter().map(|number| vec![json!(number)]),
+			)
+			.await?;
+		numbers
+			.iter()
+			.zip(hashes)
+			.map(|(number, hash)| hash.ok_or_else(|| eyre!("Block {number} is not found")))
+			.collect()
+	}
+
+	/// Fetches headers of the blocks, verified against the block hashes
+	pub async fn headers(&self, hashes: &[H256]) -> Result<Vec<Header>> {
+		let headers: Vec<Option<Header>> = self
+			.execute_all(
+				"chain_getHeader",
+				hashes.iter().map(|hash| vec![json!(hash)]),
+			)
+			.await?;
+		hashes
+			.iter()
+			.zip(headers)
+			.map(|(&hash, header)| {
+				let header = header.ok_or_else(|| eyre!("Header {hash:?} is not found"))?;
+				if H256(Encode::using_encoded(&header, blake2_256)) != hash {
+					return Err(eyre!("Header doesn't match block hash {hash:?}"));
+				}
+				Ok(header)
+			})
+			.collect()
+	}
+
+	/// Fetches headers of the blocks with their hashes
+	pub async fn headers_by_number(
+		&self,
+		numbers: impl IntoIterator<Item = u32>,
+	) -> Result<Vec<(Header, H256)>> {
+		let hashes = self.block_hashes(numbers).await?;
+		let headers = self.headers(&hashes).await?;
+		Ok(headers.into_iter().zip(hashes).collect())
+	}
+
+	/// Fetches storage values at the block, verified against the state root of the header (which has to be verified
+	/// by the caller), `None` if the value doesn't exist
+	pub async fn storage(&self, keys: &[Vec<u8>], header: &Header) -> Result<Vec<Option<Vec<u8>>>> {
+		let at = H256(Encode::using_encoded(header, blake2_256));
+		let chunks = keys.chunks(MAX_PROOF_KEYS).collect::<Vec<_>>();
+		let proofs: Vec<ReadProof> = self
+			.execute_all(
+				"state_getReadProof",
+				chunks.iter().map(|keys| {
+					let keys = keys.iter().cloned().map(Bytes).collect::<Vec<_>>();
+					vec![json!(keys), json!(at)]
+				}),
+			)
+			.await?;
+		let mut values = Vec::with_capacity(keys.len());
+		for (keys, ReadProof { proof }) in chunks.into_iter().zip(proofs) {
+			let proof = proof.into_iter().map(|node| node.0).collect();
+			let verified = verify_read_proof(header.state_root, proof, keys)?;
+			values.extend(verified.into_iter().map(|(_, value)| value));
+		}
+		Ok(values)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{simulation::header, storage_proof::build_trie};
+	use async_trait::async_trait;
+	use futures::stream::BoxStream;
+	use std::sync::Mutex;
+	use test_case::test_case;
+
+	/// Transport which answers with the request parameters (or the read proof), and records batch sizes
+	#[derive(Default)]
+	struct TestTransport {
+		batches: Mutex<Vec<usize>>,
+		proof: Vec<Vec<u8>>,
+	}
+
+	#[async_trait]
+	impl Transport for TestTransport {
+		async fn request(&self, method: &str, params: Vec<Value>) -> Result<Value> {
+			if method == "state_getReadProof" {
+				let proof = self.proof.iter().cloned().map(Bytes).collect::<Vec<_>>();
+				return Ok(json!({ "at": params[1], "proof": proof }));
+			}
+			match params.first() {
+				Some(Value::Number(number)) if number.as_u64() == Some(0) => {
+					Err(eyre!("Request failed"))
+				},
+				_ => Ok(json!(params)),
+			}
+		}
+
+		async fn batch(&self, requests: Vec<(String, Vec<Value>)>) -> Result<Vec<Result<Value>>> {
+			self.batches.lock().unwrap().push(requests.len());
+			let mut results = vec![];
+			for (method, params) in requests {
+				results.push(self.request(&method, params).await);
+			}
+			Ok(results)
+		}
+
+		async fn subscribe(
+			&self,
+			_: &str,
+			_: Vec<Value>,
+		) -> Result<(String, BoxStream<'static, Value>)> {
+			Err(eyre!("Subscriptions are not supported"))
+		}
+	}
+
+	#[test_case(4, 1, &[4, 4, 2] ; "sequential batches")]
+	#[test_case(3, 4, &[3, 3, 3, 1] ; "concurrent batches")]
+	#[test_case(16, 2, &[10] ; "single batch")]
+	#[tokio::test]
+	async fn pipelined_batches(batch_size: usize, concurrency: usize, expected: &[usize]) {
+		let transport = Arc::new(TestTransport::default());
+		let config = PipelineConfig {
+			batch_size,
+			concurrency,
+		};
+		let pipeline = Pipeline::new(transport.clone(), config);
+		let requests = (1..=10)
+			.map(|index| ("test".to_string(), vec![json!(index)]))
+			.collect();
+		let results = pipeline.execute(requests).await;
+		let results = results.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+		assert_eq!(
+			results,
+			(1..=10).map(|index| json!([index])).collect::<Vec<_>>()
+		);
+		let mut batches = transport.batches.lock().unwrap().clone();
+		batches.sort_unstable_by(|a, b| b.cmp(a));
+		assert_eq!(batches, expected);
+	}
+
+	#[tokio::test]
+	async fn failed_requests() {
+		let pipeline = Pipeline::new(Arc::new(TestTransport::default()), Default::default());
+		let requests = (0..3)
+			.map(|index| ("test".to_string(), vec![json!(index)]))
+			.collect();
+		let results = pipeline.execute(requests).await;
+		assert!(results[0].is_err());
+		assert!(results[1..].iter().all(Result::is_ok));
+		// Responses are not hashes
+		assert!(pipeline.block_hashes([1, 2]).await.is_err());
+	}
+
+	#[tokio::test]
+	async fn verified_storage() {
+		let (state_root, proof) = build_trie(&[(b"key1", b"value1"), (b"key2", b"value2")]);
+		let transport = TestTransport {
+			proof,
+			..Default::default()
+		};
+		let pipeline = Pipeline::new(Arc::new(transport), Default::default());
+		let mut header = header(1, H256::zero(), 0);
+		header.state_root = state_root;
+		let keys = vec![b"key1".to_vec(), b"key3".to_vec()];
+		let values = pipeline.storage(&keys, &header).await.unwrap();
+		assert_eq!(values, vec![Some(b"value1".to_vec()), None]);
+
+		// Proof doesn't match the state root
+		header.state_root = H256::zero();
+		assert!(pipeline.storage(&keys, &header).await.is_err());
+	}
+}
@@ -0,0 +1,490 @@
+//! Pool of the RPC endpoints, with health checks, latency based selection, failover and cross verification.
+//!
+//! Requests are sent to the healthy endpoint with the lowest latency, and retried on the next one on errors and
+//! timeouts. Endpoint is marked unhealthy after [`PoolConfig::max_failures`] consecutive failures, or if its health
+//! check fails, it is syncing, or its finalized block lags behind the other endpoints. Unhealthy endpoints are
+//! still used as the last resort, until the next health check.
+//!
+//! [`EndpointPool::verify_block_hash`] fetches the block hash from all endpoints, and endpoints which disagree with
+//! the majority are marked faulty and are not used for [`PoolConfig::fault_timeout`]. After that, they are used and
+//! cross verified again, so endpoints which recovered (e.g. after a reorg or resync) are not excluded forever.
+//!
+//! [`crate::network::rpc::Client`] uses the pool to rank nodes on failover, and checks the endpoints periodically.
+
+use async_trait::async_trait;
+use avail_subxt::{avail, build_client, utils::H256};
+use color_eyre::{eyre::eyre, Result};
+use futures::future::{join_all, BoxFuture};
+use mockall::automock;
+use std::{
+	collections::HashMap,
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+use subxt::rpc::types::BlockNumber;
+use tokio::time::timeout;
+use tracing::{debug, warn};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Health {
+	pub is_syncing: bool,
+	pub peers: usize,
+	pub finalized: u32,
+}
+
+#[async_trait]
+#[automock]
+pub trait Endpoint: Send + Sync {
+	fn url(&self) -> String;
+
+	async fn health(&self) -> Result<Health>;
+
+	async fn block_hash(&self, number: u32) -> Result<Option<H256>>;
+}
+
+/// Endpoint backed by the subxt client connected to the node
+pub struct SubxtEndpoint {
+	pub url: String,
+	pub client: avail::Client,
+}
+
+impl SubxtEndpoint {
+	pub async fn connect(url: &str) -> Result<Self> {
+		let (client, _) = build_client(url, false)
+			.await
+			.map_err(|error| eyre!(error))?;
+		Ok(SubxtEndpoint {
+			url: url.to_string(),
+			client,
+		})
+	}
+}
+
+#[async_trait]
+impl Endpoint for SubxtEndpoint {
+	fn url(&self) -> String {
+		self.url.clone()
+	}
+
+	async fn health(&self) -> Result<Health> {
+		let health = self.client.rpc().system_health().await?;
+		let finalized = self.client.rpc().finalized_head().await?;
+		let header = self
+			.client
+			.rpc()
+			.header(Some(finalized))
+			.await?
+			.ok_or_else(|| eyre!("Finalized header {finalized:?} is not found"))?;
+		Ok(Health {
+			is_syncing: health.is_syncing,
+			peers: health.peers,
+			finalized: header.number,
+		})
+	}
+
+	async fn block_hash(&self, number: u32) -> Result<Option<H256>> {
+		let number = BlockNumber::from(number);
+		Ok(self.client.rpc().block_hash(Some(number)).await?)
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolConfig {
+	pub request_timeout: Duration,
+	/// Number of consecutive failures after which endpoint is marked unhealthy
+	pub max_failures: u32,
+	/// Maximum number of blocks the endpoint finalized block can lag behind the best endpoint
+	pub max_lag: u32,
+	/// Minimum number of endpoints which need to agree on the block hash
+	pub quorum: usize,
+	/// Duration for which the faulty endpoint is not used
+	pub fault_timeout: Duration,
+}
+
+impl Default for PoolConfig {
+	fn default() -> Self {
+		PoolConfig {
+			request_timeout: Duration::from_secs(10),
+			max_failures: 3,
+			max_lag: 10,
+			quorum: 2,
+			fault_timeout: Duration::from_secs(600),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EndpointStatus {
+	pub url: String,
+	pub healthy: bool,
+	/// Endpoint returned data inconsistent with the other endpoints
+	pub faulty: bool,
+	/// Smoothed latency of the successful requests
+	pub latency: Option<Duration>,
+	pub failures: u32,
+}
+
+struct Stats {
+	healthy: bool,
+	/// Time when the endpoint was marked faulty
+	faulty_since: Option<Instant>,
+	latency: Option<Duration>,
+	failures: u32,
+}
+
+impl Stats {
+	fn is_faulty(&self, config: &PoolConfig) -> bool {
+		self.faulty_since
+			.is_some_and(|since| since.elapsed() < config.fault_timeout)
+	}
+}
+
+pub struct EndpointPool<E: Endpoint> {
+	endpoints: Vec<E>,
+	stats: Mutex<Vec<Stats>>,
+	config: PoolConfig,
+}
+
+impl<E: Endpoint> EndpointPool<E> {
+	pub fn new(endpoints: Vec<E>, config: PoolConfig) -> Self {
+		let stats = endpoints
+			.iter()
+			.map(|_| Stats {
+				healthy: true,
+				faulty_since: None,
+				latency: None,
+				failures: 0,
+			})
+			.collect();
+		EndpointPool {
+			endpoints,
+			stats: Mutex::new(stats),
+			config,
+		}
+	}
+
+	pub fn status(&self) -> Vec<EndpointStatus> {
+		let stats = self.stats.lock().unwrap();
+		self.endpoints
+			.iter()
+			.zip(stats.iter())
+			.map(|(endpoint, stats)| EndpointStatus {
+				url: endpoint.url(),
+				healthy: stats.healthy,
+				faulty: stats.is_faulty(&self.config),
+				latency: stats.latency,
+				failures: stats.failures,
+			})
+			.collect()
+	}
+
+	fn record_success(&self, index: usize, elapsed: Duration) {
+		let stats = &mut self.stats.lock().unwrap()[index];
+		stats.healthy = true;
+		stats.failures = 0;
+		stats.latency = Some(match stats.latency {
+			Some(latency) => (latency * 4 + elapsed) / 5,
+			None => elapsed,
+		});
+	}
+
+	pub fn config(&self) -> &PoolConfig {
+		&self.config
+	}
+
+	/// Returns URLs of the endpoints which are not faulty, ranked the same way as on failover
+	pub fn ranked_urls(&self) -> Vec<String> {
+		self.candidates()
+			.into_iter()
+			.map(|index| self.endpoints[index].url())
+			.collect()
+	}
+
+	/// Records failure of the request which was sent to the endpoint outside of the pool
+	pub fn report_failure(&self, url: &str) {
+		if let Some(index) = self
+			.endpoints
+			.iter()
+			.position(|endpoint| endpoint.url() == url)
+		{
+			self.record_failure(index);
+		}
+	}
+
+	fn record_failure(&self, index: usize) {
+		let stats = &mut self.stats.lock().unwrap()[index];
+		stats.failures += 1;
+		if stats.failures >= self.config.max_failures {
+			stats.healthy = false;
+		}
+	}
+
+	/// Returns indices of the endpoints which are not faulty, healthy ones first, by latency
+	fn candidates(&self) -> Vec<usize> {
+		let stats = self.stats.lock().unwrap();
+		let mut candidates = (0..self.endpoints.len())
+			.filter(|&index| !stats[index].is_faulty(&self.config))
+			.collect::<Vec<_>>();
+		candidates.sort_by_key(|&index| {
+			let stats = &stats[index];
+			(!stats.healthy, stats.latency.is_none(), stats.latency)
+		});
+		candidates
+	}
+
+	/// Checks health of all endpoints, and returns number of the healthy ones
+	pub async fn check_health(&self) -> usize {
+		let checks = self.endpoints.iter().map(|endpoint| async move {
+			let start = Instant::now();
+			let health = timeout(self.config.request_timeout, endpoint.health()).await;
+			(health, start.elapsed())
+		});
+		let results = join_all(checks).await;
+		let best_finalized = results
+			.iter()
+			.filter_map(|(result, _)| match result {
+				Ok(Ok(health)) => Some(health.finalized),
+				_ => None,
+			})
+			.max()
+			.unwrap_or_default();
+
+		for (index, (result, elapsed)) in results.into_iter().enumerate() {
+			let url = self.endpoints[index].url();
+			match result {
+				Ok(Ok(health)) if health.is_syncing => {
+					debug!(url, "Endpoint is syncing");
+					self.stats.lock().unwrap()[index].healthy = false;
+				},
+				Ok(Ok(health)) if best_finalized - health.finalized > self.config.max_lag => {
+					debug!(
+						url,
+						finalized = health.finalized,
+						best_finalized,
+						"Endpoint is lagging"
+					);
+					self.stats.lock().unwrap()[index].healthy = false;
+				},
+				Ok(Ok(_)) => self.record_success(index, elapsed),
+				Ok(Err(error)) => {
+					warn!(url, "Endpoint health check failed: {error:#}");
+					self.stats.lock().unwrap()[index].healthy = false;
+				},
+				Err(_) => {
+					warn!(url, "Endpoint health check timed out");
+					self.stats.lock().unwrap()[index].healthy = false;
+				},
+			}
+		}
+		let stats = self.stats.lock().unwrap();
+		stats
+			.iter()
+			.filter(|stats| stats.healthy && !stats.is_faulty(&self.config))
+			.count()
+	}
+
+	/// Executes the request on the best endpoint, failing over to the next one on errors and timeouts
+	pub async fn call<T, F>(&self, f: F) -> Result<T>
+	where
+		F: for<'a> Fn(&'a E) -> BoxFuture<'a, Result<T>>,
+	{
+		let mut last_error = eyre!("No RPC endpoints are available");
+		for index in self.candidates() {
+			let endpoint = &self.endpoints[index];
+			let start = Instant::now();
+			match timeout(self.config.request_timeout, f(endpoint)).await {
+				Ok(Ok(result)) => {
+					self.record_success(index, start.elapsed());
+					return Ok(result);
+				},
+				Ok(Err(error)) => {
+					warn!(url = endpoint.url(), "RPC request failed: {error:#}");
+					last_error = error;
+				},
+				Err(_) => {
+					warn!(url = endpoint.url(), "RPC request timed out");
+					last_error = eyre!("RPC request to {} timed out", endpoint.url());
+				},
+			}
+			self.record_failure(index);
+		}
+		Err(last_error.wrap_err("All RPC endpoints failed"))
+	}
+
+	/// Fetches block hash from all endpoints, and returns the hash confirmed by the majority and at least
+	/// [`PoolConfig::quorum`] endpoints. Endpoints which return the different hash are marked as faulty.
+	pub async fn verify_block_hash(&self, number: u32) -> Result<H256> {
+		let candidates = self.candidates();
+		let requests = candidates.iter().map(|&index| {
+			let endpoint = &self.endpoints[index];
+			timeout(self.config.request_timeout, endpoint.block_hash(number))
+		});
+		let responses = candidates
+			.into_iter()
+			.zip(join_all(requests).await)
+			.filter_map(|(index, response)| match response {
+				Ok(Ok(Some(hash))) => Some((index, hash)),
+				_ => {
+					self.record_failure(index);
+					None
+				},
+			})
+			.collect::<Vec<_>>();
+
+		let mut votes = HashMap::<H256, usize>::new();
+		for (_, hash) in &responses {
+			*votes.entry(*hash).or_default() += 1;
+		}
+		let Some((hash, count)) = votes.into_iter().max_by_key(|(_, count)| *count) else {
+			return Err(eyre!("No endpoint returned hash of the block {number}"));
+		};
+		if count < self.config.quorum || count * 2 <= responses.len() {
+			return Err(eyre!(
+				"Block {number} hash {hash:?} is confirmed by {count} of {} endpoints",
+				responses.len()
+			));
+		}
+		let mut stats = self.stats.lock().unwrap();
+		for (index, other) in responses {
+			if other == hash {
+				// Endpoint is consistent again after the fault timeout
+				stats[index].faulty_since = None;
+				continue;
+			}
+			let url = self.endpoints[index].url();
+			warn!(
+				url,
+				number,
+				?other,
+				?hash,
+				"Endpoint returned inconsistent block hash"
+			);
+			stats[index].faulty_since = Some(Instant::now());
+		}
+		Ok(hash)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn endpoint(url: &str, finalized: u32, hash: H256) -> MockEndpoint {
+		let mut endpoint = MockEndpoint::new();
+		endpoint.expect_url().return_const(url.to_string());
+		endpoint.expect_health().returning(move || {
+			Box::pin(async move {
+				Ok(Health {
+					is_syncing: false,
+					peers: 5,
+					finalized,
+				})
+			})
+		});
+		endpoint
+			.expect_block_hash()
+			.returning(move |_| Box::pin(async move { Ok(Some(hash)) }));
+		endpoint
+	}
+
+	fn failing(url: &str) -> MockEndpoint {
+		let mut endpoint = MockEndpoint::new();
+		endpoint.expect_url().return_const(url.to_string());
+		endpoint
+			.expect_health()
+			.returning(|| Box::pin(async { Err(eyre!("Connection refused")) }));
+		endpoint
+			.expect_block_hash()
+			.returning(|_| Box::pin(async { Err(eyre!("Connection refused")) }));
+		endpoint
+	}
+
+	#[tokio::test]
+	async fn failover() {
+		let config = PoolConfig {
+			max_failures: 1,
+			..Default::default()
+		};
+		let pool = EndpointPool::new(
+			vec![failing("a"), endpoint("b", 100, H256::repeat_byte(1))],
+			config,
+		);
+		let hash = pool.call(|endpoint| endpoint.block_hash(1)).await.unwrap();
+		assert_eq!(hash, Some(H256::repeat_byte(1)));
+		let status = pool.status();
+		assert!(!status[0].healthy);
+		assert_eq!(status[0].failures, 1);
+		assert!(status[1].healthy && status[1].latency.is_some());
+		// Unhealthy endpoint is tried last
+		assert_eq!(pool.candidates(), [1, 0]);
+		assert_eq!(pool.ranked_urls(), ["b", "a"]);
+		pool.report_failure("b");
+		assert_eq!(pool.status()[1].failures, 1);
+
+		let pool = EndpointPool::new(vec![failing("a")], config);
+		assert!(pool.call(|endpoint| endpoint.block_hash(1)).await.is_err());
+	}
+
+	#[tokio::test]
+	async fn health_checks() {
+		let hash = H256::repeat_byte(1);
+		let mut syncing = MockEndpoint::new();
+		syncing.expect_url().return_const("syncing".to_string());
+		syncing.expect_health().returning(|| {
+			Box::pin(async {
+				Ok(Health {
+					is_syncing: true,
+					peers: 5,
+					finalized: 100,
+				})
+			})
+		});
+		let endpoints = vec![
+			endpoint("a", 100, hash),
+			endpoint("lagging", 50, hash),
+			syncing,
+			failing("b"),
+		];
+		let pool = EndpointPool::new(endpoints, PoolConfig::default());
+		assert_eq!(pool.check_health().await, 1);
+		assert_eq!(pool.candidates()[0], 0);
+	}
+
+	#[tokio::test]
+	async fn cross_verification() {
+		let hash = H256::repeat_byte(1);
+		let endpoints = vec![
+			endpoint("a", 100, hash),
+			endpoint("b", 100, H256::repeat_byte(2)),
+			endpoint("c", 100, hash),
+		];
+		let pool = EndpointPool::new(endpoints, PoolConfig::default());
+		assert_eq!(pool.verify_block_hash(10).await.unwrap(), hash);
+		assert!(pool.status()[1].faulty);
+		assert_eq!(pool.candidates().len(), 2);
+
+		// Faulty endpoint is used again after the timeout
+		let endpoints = vec![
+			endpoint("a", 100, hash),
+			endpoint("b", 100, H256::repeat_byte(2)),
+			endpoint("c", 100, hash),
+		];
+		let config = PoolConfig {
+			fault_timeout: Duration::ZERO,
+			..Default::default()
+		};
+		let pool = EndpointPool::new(endpoints, config);
+		assert_eq!(pool.verify_block_hash(10).await.unwrap(), hash);
+		assert!(pool.stats.lock().unwrap()[1].faulty_since.is_some());
+		assert!(!pool.status()[1].faulty);
+		assert_eq!(pool.candidates().len(), 3);
+
+		let endpoints = vec![
+			endpoint("a", 100, hash),
+			endpoint("b", 100, H256::repeat_byte(2)),
+		];
+		let pool = EndpointPool::new(endpoints, PoolConfig::default());
+		assert!(pool.verify_block_hash(10).await.is_err());
+		assert!(pool.status().iter().all(|status| !status.faulty));
+	}
+}
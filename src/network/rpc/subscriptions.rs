@@ -1,25 +1,37 @@
-use avail_subxt::primitives::{grandpa::AuthorityId, Header};
+use avail_subxt::{
+	primitives::{grandpa::AuthorityId, Header},
+	utils::H256,
+};
 use codec::Encode;
 use color_eyre::{eyre::eyre, Result};
-use sp_core::{
-	blake2_256,
-	ed25519::{self, Public},
-};
+use libp2p::PeerId;
+use sp_core::ed25519::{self, Public};
 use std::{
 	sync::{Arc, Mutex},
 	time::Instant,
 };
-use tokio::sync::broadcast::Sender;
+use tokio::sync::{broadcast::Sender, mpsc};
 use tokio_stream::StreamExt;
-use tracing::{debug, info, trace};
+use tracing::{debug, info, trace, warn};
 
 use super::{Client, Subscription};
 use crate::{
 	data::Database,
-	data::{FinalitySyncCheckpoint, Key},
+	data::{EpochDescriptor, FinalitySyncCheckpoint, Key},
+	epochs::{self, RETAINED_EPOCHS},
+	equivocation::EquivocationReporter,
 	finality::{check_finality, ValidatorSet},
+	fork_choice::{BlockInfo, LongestChain},
+	header::{consistency::ExtensionLimits, HeaderHash},
+	memory::MemoryBudget,
+	network::p2p::block_announce::BlockAnnounce,
+	sync_driver::{RpcTransport, SyncDriver},
+	sync_journal::{PendingEpoch, SyncEntry, SyncJournal, CHECKPOINT_INTERVAL},
+	sync_machine::{Action, Input, SyncMachine},
 	types::{GrandpaJustification, OptionBlockRange, State},
 	utils::filter_auth_set_changes,
+	verification::WorkerPool,
+	verified_rpc::HeaderStore,
 };
 
 #[derive(Clone, Debug)]
@@ -43,7 +55,19 @@ pub struct SubscriptionLoop<T: Database> {
 	event_sender: Sender<Event>,
 	state: Arc<Mutex<State>>,
 	db: T,
+	workers: WorkerPool,
 	block_data: BlockData,
+	journal: SyncJournal,
+	/// Epoch of the validator set fetched from RPC, stored once a justification signed by the set is verified
+	unverified_epoch: Option<EpochDescriptor>,
+	reporter: Option<EquivocationReporter>,
+	/// Store of the verified headers, filled with the finalized headers
+	header_store: Option<Arc<HeaderStore>>,
+	/// Header sync, with the RPC node as the single peer
+	sync: SyncDriver<RpcTransport>,
+	rpc_peer: PeerId,
+	/// Justifications of the blocks finalized by the GRANDPA gossip
+	gossip_justifications: Option<mpsc::Receiver<GrandpaJustification>>,
 }
 
 impl<T: Database> SubscriptionLoop<T> {
@@ -52,6 +76,7 @@ impl<T: Database> SubscriptionLoop<T> {
 		db: T,
 		rpc_client: Client,
 		event_sender: Sender<Event>,
+		workers: WorkerPool,
 	) -> Result<Self> {
 		// get the Hash of the Finalized Head [with Retries]
 		let last_finalized_block_hash = rpc_client.get_finalized_head_hash().await?;
@@ -71,54 +96,198 @@ impl<T: Database> SubscriptionLoop<T> {
 			.get_header_by_hash(last_finalized_block_hash)
 			.await?;
 
+		let unverified_epoch = epochs::get_epoch(&db, set_id)?
+			.is_none()
+			.then(|| EpochDescriptor {
+				set_id,
+				start_block: last_finalized_block_header.number,
+				validator_set: validator_set.clone(),
+				next_validator_set: None,
+			});
+
+		// Sync resumes from the last header sent before the restart, so blocks finalized meanwhile are sent as skipped
+		let journal = SyncJournal::open(&db, CHECKPOINT_INTERVAL)?;
+		let progress = journal.progress();
+		let last_finalized_block_header = match &progress.finalized {
+			Some(header) if header.number < last_finalized_block_header.number => header.clone(),
+			_ => last_finalized_block_header,
+		};
+		if progress.finalized.is_some() {
+			info!(
+				block_number = last_finalized_block_header.number,
+				unfinalized = progress.unfinalized.len(),
+				"Resuming sync from the journal"
+			);
+		}
+		let current_valset = ValidatorSet {
+			set_id,
+			validator_set,
+		};
+		let unverified_headers = progress
+			.unfinalized
+			.iter()
+			.filter(|header| header.number > last_finalized_block_header.number)
+			.map(|header| (header.clone(), Instant::now(), current_valset.clone()))
+			.collect();
+		let next_valset = progress
+			.next_epoch
+			.clone()
+			.filter(|epoch| epoch.set_id == set_id + 1)
+			.map(|epoch| ValidatorSet {
+				set_id: epoch.set_id,
+				validator_set: epoch.validator_set,
+			});
+
+		let finalized = BlockInfo {
+			hash: last_finalized_block_header.header_hash(),
+			number: last_finalized_block_header.number,
+			parent_hash: last_finalized_block_header.parent_hash,
+			confidence: None,
+		};
+		let machine = SyncMachine::new(
+			finalized,
+			Box::new(LongestChain),
+			ExtensionLimits::default(),
+		);
+		let mut sync = SyncDriver::new(machine, RpcTransport::new(rpc_client.clone()));
+		let rpc_peer = PeerId::random();
+		sync.handle(Input::PeerConnected {
+			peer_id: rpc_peer,
+			best_number: last_finalized_block_header.number,
+		});
+
 		Ok(Self {
 			rpc_client,
 			event_sender,
 			state,
 			db,
+			workers,
 			block_data: BlockData {
 				justifications: Default::default(),
-				unverified_headers: Default::default(),
-				current_valset: ValidatorSet {
-					set_id,
-					validator_set,
-				},
-				next_valset: None,
+				unverified_headers,
+				current_valset,
+				next_valset,
 				last_finalized_block_header: Some(last_finalized_block_header),
 			},
+			journal,
+			unverified_epoch,
+			reporter: None,
+			header_store: None,
+			sync,
+			rpc_peer,
+			gossip_justifications: None,
 		})
 	}
 
+	/// Detects equivocations in the received headers and justifications
+	pub fn with_reporter(mut self, reporter: EquivocationReporter) -> Self {
+		self.reporter = Some(reporter);
+		self
+	}
+
+	/// Accounts blocks imported by the header sync in the memory budget
+	pub fn with_budget(self, budget: Arc<MemoryBudget>) -> Self {
+		SubscriptionLoop {
+			sync: self.sync.with_budget(budget),
+			..self
+		}
+	}
+
+	/// Inserts the verified finalized headers into the store
+	pub fn with_header_store(mut self, header_store: Arc<HeaderStore>) -> Self {
+		self.header_store = Some(header_store);
+		self
+	}
+
+	/// Verifies and applies the justifications of the blocks finalized by the GRANDPA gossip
+	pub fn with_gossip_justifications(
+		mut self,
+		justifications: mpsc::Receiver<GrandpaJustification>,
+	) -> Self {
+		self.gossip_justifications = Some(justifications);
+		self
+	}
+
+	fn store_verified(&self, header: &Header) {
+		if let Some(header_store) = self.header_store.as_ref() {
+			header_store.insert(header.clone());
+		}
+	}
+
 	pub async fn run(mut self) -> Result<()> {
 		// create subscriptions stream
 		let subscriptions = self.rpc_client.clone().subscription_stream().await;
 		futures::pin_mut!(subscriptions);
+		let mut gossip_justifications = self.gossip_justifications.take();
 
-		while let Some(result) = subscriptions.next().await {
-			match result {
-				Ok(sub) => {
-					self.handle_new_subscription(sub).await;
+		loop {
+			tokio::select! {
+				result = subscriptions.next() => match result {
+					Some(Ok(sub)) => self.handle_new_subscription(sub).await,
+					Some(Err(err)) => return Err(eyre!(err)),
+					None => return Ok(()),
 				},
-				Err(err) => return Err(eyre!(err)),
-			};
+				justification = next_justification(&mut gossip_justifications) => match justification {
+					Some(justification) => self.handle_gossip_justification(justification).await,
+					None => gossip_justifications = None,
+				},
+				// Missing headers are fetched, and expired requests retried, between the subscription items
+				actions = self.sync.next() => self.handle_sync_actions(actions),
+			}
+		}
+	}
+
+	fn handle_sync_actions(&mut self, actions: Vec<Action>) {
+		for action in actions {
+			match action {
+				Action::NewBest { number, .. } => self.state.lock().unwrap().latest = number,
+				Action::DisconnectPeer { .. } => warn!("RPC node sent invalid headers"),
+				_ => (),
+			}
 		}
+	}
 
-		Ok(())
+	/// Records sync progress, before the change is applied
+	fn record(&mut self, entry: SyncEntry) {
+		if let Err(error) = self.journal.record(&self.db, entry) {
+			warn!("Cannot record sync progress: {error:#}");
+		}
 	}
 
 	async fn handle_new_subscription(&mut self, subscription: Subscription) {
 		match subscription {
 			Subscription::Header(header) => {
 				let received_at = Instant::now();
-				self.state.lock().unwrap().latest = header.clone().number;
 				info!("Header no.: {}", header.number);
+				// Best block is selected by the header sync, and its missing ancestors are fetched
+				let announce = BlockAnnounce {
+					header: header.encode(),
+					is_best: true,
+				};
+				let peer_id = self.rpc_peer;
+				let actions = self.sync.handle(Input::Announce { peer_id, announce });
+				self.handle_sync_actions(actions);
+				if let Some(reporter) = self.reporter.as_mut() {
+					reporter.observe_header(&header);
+				}
 
 				// if new validator set becomes active, replace the current one
-				if self.block_data.next_valset.is_some() {
+				if let Some(set_id) = self.block_data.next_valset.as_ref().map(|next| next.set_id) {
+					self.record(SyncEntry::EpochActivated(set_id));
 					self.block_data.current_valset = self.block_data.next_valset.take().unwrap();
+					let epoch = EpochDescriptor {
+						set_id: self.block_data.current_valset.set_id,
+						start_block: header.number,
+						validator_set: self.block_data.current_valset.validator_set.clone(),
+						next_validator_set: None,
+					};
+					if let Err(error) = epochs::store_epoch(&self.db, epoch) {
+						warn!("Cannot store epoch: {error:#}");
+					}
 				}
 
 				// push new Unverified Header
+				self.record(SyncEntry::Unfinalized(header.clone()));
 				self.block_data.unverified_headers.push((
 					header.clone(),
 					received_at,
@@ -139,13 +308,25 @@ impl<T: Database> SubscriptionLoop<T> {
 						.into_iter()
 						.map(|(a, _)| ed25519::Public::from_raw(a.0 .0 .0))
 						.collect::<Vec<Public>>();
+					let new_valset_keys = new_valset.clone();
 
+					self.record(SyncEntry::EpochAnnounced(PendingEpoch {
+						set_id: self.block_data.current_valset.set_id + 1,
+						validator_set: new_valset.clone(),
+					}));
 					self.block_data.next_valset = Some(ValidatorSet {
 						set_id: self.block_data.current_valset.set_id + 1,
 						validator_set: new_valset,
 					});
 
 					debug!("Validator set change: {:?}", self.block_data.next_valset);
+					if let Err(error) = epochs::announce_next_epoch(
+						&self.db,
+						self.block_data.current_valset.set_id,
+						new_valset_keys,
+					) {
+						warn!("Cannot store next epoch: {error:#}");
+					}
 				}
 			},
 			Subscription::Justification(justification) => {
@@ -153,6 +334,23 @@ impl<T: Database> SubscriptionLoop<T> {
 					"New justification at block no.: {}, hash: {:?}",
 					justification.commit.target_number, justification.commit.target_hash
 				);
+				if let Some(reporter) = self.reporter.as_mut() {
+					let set_id = self.block_data.current_valset.set_id;
+					reporter.observe_justification(set_id, &justification);
+				}
+				// Block can be already finalized by the GRANDPA gossip
+				if self
+					.block_data
+					.last_finalized_block_header
+					.as_ref()
+					.is_some_and(|header| justification.commit.target_number <= header.number)
+				{
+					debug!(
+						block_number = justification.commit.target_number,
+						"Justification of the finalized block skipped"
+					);
+					return;
+				}
 				self.block_data.justifications.push(justification);
 			},
 		}
@@ -160,6 +358,39 @@ impl<T: Database> SubscriptionLoop<T> {
 		self.verify_and_output_block_headers().await;
 	}
 
+	/// Gossip justification is verified against the validator set of the matching header, before it's queued with
+	/// the justifications of the RPC node. Justifications of the unknown headers cannot be verified, so they are
+	/// dropped, and the block is finalized by the RPC node justification instead.
+	async fn handle_gossip_justification(&mut self, justification: GrandpaJustification) {
+		let target_hash = justification.commit.target_hash;
+		let Some(valset) = self
+			.block_data
+			.unverified_headers
+			.iter()
+			.find(|(header, _, _)| header.header_hash() == target_hash)
+			.map(|(_, _, valset)| valset.clone())
+		else {
+			trace!(%target_hash, "Gossip justification of unknown header dropped");
+			return;
+		};
+		let target = justification.clone();
+		let is_final = self
+			.workers
+			.execute(move || check_finality(&valset, &target))
+			.await
+			.and_then(|is_final| is_final);
+		if let Err(error) = is_final {
+			debug!(%target_hash, "Invalid gossip justification: {error:#}");
+			return;
+		}
+		info!(
+			block_number = justification.commit.target_number,
+			"Justification received with GRANDPA gossip"
+		);
+		self.block_data.justifications.push(justification);
+		self.verify_and_output_block_headers().await;
+	}
+
 	async fn verify_and_output_block_headers(&mut self) {
 		let mut finality_synced = false;
 		while let Some(justification) = self.block_data.justifications.pop() {
@@ -168,17 +399,36 @@ impl<T: Database> SubscriptionLoop<T> {
 				.block_data
 				.unverified_headers
 				.iter()
-				.map(|(h, _, _)| Encode::using_encoded(h, blake2_256).into())
+				.map(|(h, _, _)| h.header_hash())
 				.position(|hash| justification.commit.target_hash == hash)
 			{
 				// basically, pop it out of the collection
 				let (header, received_at, valset) =
 					self.block_data.unverified_headers.swap_remove(pos);
+				let finalized_set_id = valset.set_id;
 
-				let is_final = check_finality(&valset, &justification);
+				// signature checks are offloaded, so subscriptions are received meanwhile
+				let target = justification.clone();
+				let is_final = self
+					.workers
+					.execute(move || check_finality(&valset, &target))
+					.await
+					.and_then(|is_final| is_final);
 
 				is_final.expect("Finality check failed");
 
+				if self
+					.unverified_epoch
+					.as_ref()
+					.is_some_and(|epoch| epoch.set_id == finalized_set_id)
+				{
+					if let Some(epoch) = self.unverified_epoch.take() {
+						if let Err(error) = epochs::store_epoch(&self.db, epoch) {
+							warn!("Cannot store epoch: {error:#}");
+						}
+					}
+				}
+
 				// To avoid locking the global state all the time, after finality is synced, it will not be necessary to read the state
 				if !finality_synced {
 					finality_synced = self.state.lock().unwrap().finality_synced;
@@ -198,15 +448,23 @@ impl<T: Database> SubscriptionLoop<T> {
 						.unwrap();
 				}
 
-				// try and get get all the skipped blocks, if they exist
-				if let Some(last_header) = self.block_data.last_finalized_block_header.as_ref() {
-					for bl_num in (last_header.number + 1)..header.number {
-						info!("Sending skipped block {bl_num}");
+				// try and get get all the skipped blocks, if they exist, after the last one which is already sent
+				let backlog = self.journal.progress().backlog.unwrap_or(0);
+				let first = self
+					.block_data
+					.last_finalized_block_header
+					.as_ref()
+					.map(|last_header| last_header.number.max(backlog) + 1);
+				if let Some(first) = first {
+					// Skipped blocks are the ancestors of the finalized block, so blocks of other forks are not sent
+					let mut skipped = vec![];
+					let mut parent_hash = header.parent_hash;
+					for bl_num in (first..header.number).rev() {
 						let (header, received_at) = match self
 							.block_data
 							.unverified_headers
 							.iter()
-							.position(|(h, _, _)| h.number == bl_num)
+							.position(|(h, _, _)| h.header_hash() == parent_hash)
 						{
 							Some(pos) => {
 								info!("Fetching header from unverified headers");
@@ -214,16 +472,23 @@ impl<T: Database> SubscriptionLoop<T> {
 								(p.0, p.1)
 							},
 							None => {
-								info!("Fetching header from RPC");
+								info!(block_number = bl_num, "Fetching header from sync");
 								let a = self
-									.rpc_client
-									.get_header_by_block_number(bl_num)
+									.sync
+									.fetch_header(self.rpc_peer, parent_hash)
 									.await
 									.unwrap()
-									.0;
+									.into_inner();
 								(a, Instant::now())
 							},
 						};
+						parent_hash = header.parent_hash;
+						skipped.push((header, received_at));
+					}
+					for (header, received_at) in skipped.into_iter().rev() {
+						let bl_num = header.number;
+						info!(block_number = bl_num, "Sending skipped block {bl_num}");
+						self.store_verified(&header);
 						// send as output event
 						self.event_sender
 							.send(Event::HeaderUpdate {
@@ -231,12 +496,34 @@ impl<T: Database> SubscriptionLoop<T> {
 								received_at,
 							})
 							.unwrap();
+						self.record(SyncEntry::BacklogProcessed(bl_num));
 					}
 				}
 
-				info!("Sending finalized block {}", header.number);
+				info!(
+					block_number = header.number,
+					block_hash = %justification.commit.target_hash,
+					"Sending finalized block {}",
+					header.number
+				);
 				// reset Last Finalized Block Header
 				self.block_data.last_finalized_block_header = Some(header.clone());
+				// headers of the abandoned forks are never finalized
+				self.block_data
+					.unverified_headers
+					.retain(|(h, _, _)| h.number > header.number);
+				let hash = justification.commit.target_hash;
+				let actions = self.sync.handle(Input::Finalized { hash });
+				self.handle_sync_actions(actions);
+				if let Some(reporter) = self.reporter.as_mut() {
+					reporter.finalized(&header, finalized_set_id);
+				}
+
+				match epochs::prune_epochs(&self.db, finalized_set_id, RETAINED_EPOCHS) {
+					Ok(0) => (),
+					Ok(pruned) => debug!(pruned, "Pruned finalized epochs"),
+					Err(error) => warn!("Cannot prune epochs: {error:#}"),
+				}
 
 				// finally, send the Verified Block Header
 				self.state
@@ -244,12 +531,14 @@ impl<T: Database> SubscriptionLoop<T> {
 					.unwrap()
 					.header_verified
 					.set(header.number);
+				self.store_verified(&header);
 				self.event_sender
 					.send(Event::HeaderUpdate {
-						header,
+						header: header.clone(),
 						received_at,
 					})
 					.unwrap();
+				self.record(SyncEntry::Finalized(header));
 			} else {
 				trace!("Matched pair of header/justification not found.");
 				self.block_data.justifications.push(justification);
@@ -258,3 +547,13 @@ impl<T: Database> SubscriptionLoop<T> {
 		}
 	}
 }
+
+/// Receives next gossip justification, or never completes if gossip is not observed
+async fn next_justification(
+	receiver: &mut Option<mpsc::Receiver<GrandpaJustification>>,
+) -> Option<GrandpaJustification> {
+	match receiver {
+		Some(receiver) => receiver.recv().await,
+		None => std::future::pending().await,
+	}
+}
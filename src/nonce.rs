@@ -0,0 +1,245 @@
+//! Nonce management for concurrent submissions from the same account.
+//!
+//! [`AccountNonceProvider`] combines the on-chain nonce with the nonces of locally submitted extrinsics which
+//! are not included yet, so concurrent submissions get distinct nonces:
+//!
+//! * On-chain nonce is the `System.Account` nonce at the finalized block, which (unlike `system_accountNextIndex`)
+//!   doesn't include transactions in the pool, so in-pool extrinsics stay pending until they are finalized
+//! * Pending nonces below the on-chain nonce are considered included and are pruned
+//! * Released nonces (e.g. extrinsic was dropped) leave a gap, which is filled by the next allocation
+//! * Replacement (e.g. resubmission with a higher tip) reuses the pending nonce
+
+use async_trait::async_trait;
+use codec::Decode;
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use mockall::automock;
+use std::{
+	collections::{BTreeSet, HashMap},
+	sync::Mutex,
+};
+use subxt::utils::AccountId32;
+use tracing::debug;
+
+use crate::{account::system_account_storage_key, network::rpc::Client as RpcClient};
+
+#[async_trait]
+#[automock]
+pub trait NonceSource {
+	/// Returns the nonce of the account in the finalized state, not including transactions in the pool
+	async fn account_nonce(&self, account: &AccountId32) -> Result<u32>;
+}
+
+/// Decodes nonce from the `System.Account` value, which is the first field of the `AccountInfo`
+fn decode_account_nonce(value: Option<&[u8]>) -> Result<u32> {
+	let Some(mut value) = value else {
+		return Ok(0);
+	};
+	u32::decode(&mut value).wrap_err("Failed to decode account nonce")
+}
+
+#[async_trait]
+impl NonceSource for RpcClient {
+	async fn account_nonce(&self, account: &AccountId32) -> Result<u32> {
+		let block_hash = self.get_finalized_head_hash().await?;
+		let value = self
+			.get_storage_value(system_account_storage_key(&account.0), block_hash)
+			.await?;
+		decode_account_nonce(value.as_deref())
+	}
+}
+
+#[derive(Debug, Default)]
+struct AccountNonces {
+	on_chain: u32,
+	pending: BTreeSet<u32>,
+}
+
+impl AccountNonces {
+	fn update(&mut self, on_chain: u32) {
+		// Stale on-chain nonce (fetched concurrently) is ignored
+		self.on_chain = self.on_chain.max(on_chain);
+		self.pending = self.pending.split_off(&self.on_chain);
+	}
+
+	fn allocate(&mut self) -> u32 {
+		let nonce = (self.on_chain..)
+			.find(|nonce| !self.pending.contains(nonce))
+			.expect("Nonce space is not exhausted");
+		self.pending.insert(nonce);
+		nonce
+	}
+}
+
+pub struct AccountNonceProvider<S: NonceSource> {
+	source: S,
+	accounts: Mutex<HashMap<AccountId32, AccountNonces>>,
+}
+
+impl<S: NonceSource> AccountNonceProvider<S> {
+	pub fn new(source: S) -> Self {
+		AccountNonceProvider {
+			source,
+			accounts: Default::default(),
+		}
+	}
+
+	/// Allocates the lowest nonce which is neither included nor pending
+	pub async fn next_nonce(&self, account: &AccountId32) -> Result<u32> {
+		let on_chain = self.source.account_nonce(account).await?;
+		let mut accounts = self.accounts.lock().unwrap();
+		let nonces = accounts.entry(account.clone()).or_default();
+		nonces.update(on_chain);
+		let nonce = nonces.allocate();
+		debug!(%account, on_chain, nonce, "Nonce allocated");
+		Ok(nonce)
+	}
+
+	/// Allocates nonce as [`AccountNonceProvider::next_nonce`], which is released when dropped, unless the extrinsic
+	/// is marked as included, so failed and cancelled submissions don't leave pending nonces behind.
+	pub async fn allocate<'a>(&'a self, account: &'a AccountId32) -> Result<PendingNonce<'a, S>> {
+		let nonce = self.next_nonce(account).await?;
+		Ok(PendingNonce {
+			provider: self,
+			account,
+			nonce,
+			included: false,
+		})
+	}
+
+	/// Returns pending nonce for the replacement extrinsic, fails if the nonce is not pending anymore
+	pub async fn replacement_nonce(&self, account: &AccountId32, nonce: u32) -> Result<u32> {
+		let on_chain = self.source.account_nonce(account).await?;
+		let mut accounts = self.accounts.lock().unwrap();
+		let nonces = accounts.entry(account.clone()).or_default();
+		nonces.update(on_chain);
+		if !nonces.pending.contains(&nonce) {
+			return Err(eyre!("Nonce {nonce} of {account} is not pending"));
+		}
+		Ok(nonce)
+	}
+
+	/// Releases pending nonce of the extrinsic which failed or is dropped, so it can be reused
+	pub fn release(&self, account: &AccountId32, nonce: u32) {
+		if let Some(nonces) = self.accounts.lock().unwrap().get_mut(account) {
+			nonces.pending.remove(&nonce);
+		}
+	}
+
+	pub fn pending(&self, account: &AccountId32) -> Vec<u32> {
+		self.accounts
+			.lock()
+			.unwrap()
+			.get(account)
+			.map(|nonces| nonces.pending.iter().cloned().collect())
+			.unwrap_or_default()
+	}
+}
+
+/// Nonce of the extrinsic which is being submitted
+pub struct PendingNonce<'a, S: NonceSource> {
+	provider: &'a AccountNonceProvider<S>,
+	account: &'a AccountId32,
+	nonce: u32,
+	included: bool,
+}
+
+impl<S: NonceSource> PendingNonce<'_, S> {
+	pub fn value(&self) -> u32 {
+		self.nonce
+	}
+
+	/// Marks extrinsic as included, so the nonce is not released
+	pub fn included(mut self) {
+		self.included = true;
+	}
+}
+
+impl<S: NonceSource> Drop for PendingNonce<'_, S> {
+	fn drop(&mut self) {
+		if !self.included {
+			self.provider.release(self.account, self.nonce);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::{
+		atomic::{AtomicU32, Ordering},
+		Arc,
+	};
+
+	const ALICE: AccountId32 = AccountId32([1; 32]);
+
+	fn provider(on_chain: Arc<AtomicU32>) -> AccountNonceProvider<MockNonceSource> {
+		let mut source = MockNonceSource::new();
+		source.expect_account_nonce().returning(move |_| {
+			let nonce = on_chain.load(Ordering::SeqCst);
+			Box::pin(async move { Ok(nonce) })
+		});
+		AccountNonceProvider::new(source)
+	}
+
+	#[tokio::test]
+	async fn concurrent_submissions() {
+		let on_chain = Arc::new(AtomicU32::new(5));
+		let provider = provider(on_chain.clone());
+		assert_eq!(provider.next_nonce(&ALICE).await.unwrap(), 5);
+		assert_eq!(provider.next_nonce(&ALICE).await.unwrap(), 6);
+		assert_eq!(provider.next_nonce(&ALICE).await.unwrap(), 7);
+
+		on_chain.store(6, Ordering::SeqCst);
+		assert_eq!(provider.next_nonce(&ALICE).await.unwrap(), 8);
+		assert_eq!(provider.pending(&ALICE), vec![6, 7, 8]);
+	}
+
+	#[tokio::test]
+	async fn released_nonce_is_reused() {
+		let provider = provider(Arc::new(AtomicU32::new(0)));
+		for _ in 0..3 {
+			provider.next_nonce(&ALICE).await.unwrap();
+		}
+		provider.release(&ALICE, 1);
+		assert_eq!(provider.next_nonce(&ALICE).await.unwrap(), 1);
+		assert_eq!(provider.next_nonce(&ALICE).await.unwrap(), 3);
+	}
+
+	#[tokio::test]
+	async fn dropped_nonce_is_released() {
+		let provider = provider(Arc::new(AtomicU32::new(0)));
+		let first = provider.allocate(&ALICE).await.unwrap();
+		let second = provider.allocate(&ALICE).await.unwrap();
+		assert_eq!((first.value(), second.value()), (0, 1));
+		first.included();
+		drop(second);
+		assert_eq!(provider.pending(&ALICE), vec![0]);
+	}
+
+	#[tokio::test]
+	async fn replacement() {
+		let on_chain = Arc::new(AtomicU32::new(0));
+		let provider = provider(on_chain.clone());
+		let nonce = provider.next_nonce(&ALICE).await.unwrap();
+		assert_eq!(
+			provider.replacement_nonce(&ALICE, nonce).await.unwrap(),
+			nonce
+		);
+
+		on_chain.store(1, Ordering::SeqCst);
+		assert!(provider.replacement_nonce(&ALICE, nonce).await.is_err());
+	}
+
+	#[test]
+	fn decode_nonce() {
+		use codec::Encode;
+
+		let account_info = (7u32, 0u32, 1u32, 0u32, [0u128; 4]).encode();
+		assert_eq!(decode_account_nonce(Some(&account_info)).unwrap(), 7);
+		assert_eq!(decode_account_nonce(None).unwrap(), 0);
+		assert!(decode_account_nonce(Some(&[1])).is_err());
+	}
+}
@@ -0,0 +1,499 @@
+//! Human readable rendering of the dynamically decoded calls and events, for the CLI and log output.
+//!
+//! Calls are rendered as `balances.transfer_keep_alive(dest: 5GrwvaEF.., value: 1.5 AVAIL)`. Arguments are rendered
+//! by their metadata type:
+//!
+//! * Balances (fields with the `Balance` type name) - with decimals and symbol, see [`BalanceFormat`]
+//! * Accounts (`AccountId32` and `MultiAddress::Id`) - as SS58 or hex, optionally shortened, see [`AddressFormat`]
+//! * Byte sequences and arrays (and their single field wrappers) - as hex
+//! * Other values - as composites and variants, similar to the Rust syntax
+//!
+//! Nested values are rendered by their metadata types as well. Values rendered without the type information (see
+//! [`Formatter::value`]) are always rendered as composites, since e.g. `AppId(3)` cannot be told apart from a byte
+//! sequence.
+
+use codec::Decode;
+use color_eyre::{eyre::eyre, Result};
+use scale_info::{form::PortableForm, Field, TypeDef, TypeDefPrimitive};
+use sp_core::crypto::{AccountId32, Ss58AddressFormat, Ss58Codec};
+use subxt::{
+	ext::scale_value::{Composite, Primitive, Value, ValueDef},
+	Metadata,
+};
+
+use crate::{dynamic, extrinsic::decode::Call};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressFormat {
+	Ss58 { prefix: u16 },
+	Hex,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BalanceFormat {
+	pub decimals: u8,
+	pub symbol: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Formatter {
+	pub address: AddressFormat,
+	/// Number of the address characters to keep, addresses are not shortened if `None`
+	pub address_length: Option<usize>,
+	pub balance: BalanceFormat,
+}
+
+impl Default for Formatter {
+	fn default() -> Self {
+		Formatter {
+			address: AddressFormat::Ss58 { prefix: 42 },
+			address_length: Some(8),
+			balance: BalanceFormat {
+				decimals: 18,
+				symbol: "AVAIL".to_string(),
+			},
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+	Balance,
+	Address,
+	Other,
+}
+
+fn kind(metadata: &Metadata, type_id: u32, type_name: Option<&str>) -> Kind {
+	if type_name.is_some_and(|name| name.contains("Balance")) {
+		return Kind::Balance;
+	}
+	let name = metadata
+		.types()
+		.resolve(type_id)
+		.and_then(|ty| ty.path.segments.last());
+	match name.map(String::as_str) {
+		Some("AccountId32" | "MultiAddress") => Kind::Address,
+		_ => Kind::Other,
+	}
+}
+
+/// Converts pallet or storage name to snake case, e.g. `DataAvailability` to `data_availability`
+pub(crate) fn snake_case(name: &str) -> String {
+	let mut snake = String::new();
+	for (index, char) in name.char_indices() {
+		if char.is_uppercase() && index > 0 {
+			snake.push('_');
+		}
+		snake.extend(char.to_lowercase());
+	}
+	snake
+}
+
+fn is_u8(metadata: &Metadata, type_id: u32) -> bool {
+	matches!(
+		metadata.types().resolve(type_id).map(|ty| &ty.type_def),
+		Some(TypeDef::Primitive(TypeDefPrimitive::U8))
+	)
+}
+
+/// Checks if the type is byte sequence or array, or a single field wrapper of one (e.g. `AccountId32`)
+fn is_bytes(metadata: &Metadata, type_id: u32) -> bool {
+	match metadata.types().resolve(type_id).map(|ty| &ty.type_def) {
+		Some(TypeDef::Sequence(sequence)) => is_u8(metadata, sequence.type_param.id),
+		Some(TypeDef::Array(array)) => is_u8(metadata, array.type_param.id),
+		Some(TypeDef::Composite(composite)) => match &composite.fields[..] {
+			[field] => is_bytes(metadata, field.ty.id),
+			_ => false,
+		},
+		_ => false,
+	}
+}
+
+/// Returns bytes of the byte sequence or array, unwrapping single field tuples (e.g. `AccountId32`)
+fn bytes<T>(value: &Value<T>) -> Option<Vec<u8>> {
+	let ValueDef::Composite(Composite::Unnamed(values)) = &value.value else {
+		return None;
+	};
+	if let [inner] = &values[..] {
+		if let Some(bytes) = bytes(inner) {
+			return Some(bytes);
+		}
+	}
+	values
+		.iter()
+		.map(|value| match value.value {
+			ValueDef::Primitive(Primitive::U128(byte)) => u8::try_from(byte).ok(),
+			_ => None,
+		})
+		.collect()
+}
+
+/// Returns account of the `AccountId32` or `MultiAddress::Id` value
+fn account<T>(value: &Value<T>) -> Option<[u8; 32]> {
+	let account = match &value.value {
+		ValueDef::Variant(variant) if variant.name == "Id" => {
+			variant.values.values().next().and_then(bytes)
+		},
+		_ => bytes(value),
+	};
+	account.and_then(|account| <[u8; 32]>::try_from(account).ok())
+}
+
+impl Formatter {
+	pub fn balance(&self, amount: u128) -> String {
+		let BalanceFormat { decimals, symbol } = &self.balance;
+		// Unit of more than 38 decimals doesn't fit, so the amount is always below it
+		let Some(unit) = 10u128.checked_pow(*decimals as u32) else {
+			let fraction = format!("{amount:0width$}", width = *decimals as usize);
+			return match fraction.trim_end_matches('0') {
+				"" => format!("0 {symbol}"),
+				fraction => format!("0.{fraction} {symbol}"),
+			};
+		};
+		let fraction = amount % unit;
+		if fraction == 0 {
+			return format!("{} {symbol}", amount / unit);
+		}
+		let fraction = format!("{fraction:0width$}", width = *decimals as usize);
+		format!(
+			"{}.{} {symbol}",
+			amount / unit,
+			fraction.trim_end_matches('0')
+		)
+	}
+
+	pub fn address(&self, account: [u8; 32]) -> String {
+		let address = match self.address {
+			AddressFormat::Ss58 { prefix } => AccountId32::new(account)
+				.to_ss58check_with_version(Ss58AddressFormat::custom(prefix)),
+			AddressFormat::Hex => format!("0x{}", hex::encode(account)),
+		};
+		match self.address_length {
+			Some(length) if length < address.len() => format!("{}..", &address[..length]),
+			_ => address,
+		}
+	}
+
+	fn composite<T>(&self, composite: &Composite<T>) -> String {
+		match composite {
+			Composite::Named(fields) => {
+				let fields = fields
+					.iter()
+					.map(|(name, value)| format!("{name}: {}", self.value(value)))
+					.collect::<Vec<_>>();
+				format!("{{ {} }}", fields.join(", "))
+			},
+			Composite::Unnamed(values) => {
+				let values = values
+					.iter()
+					.map(|value| self.value(value))
+					.collect::<Vec<_>>();
+				format!("({})", values.join(", "))
+			},
+		}
+	}
+
+	/// Renders value without the type information
+	pub fn value<T>(&self, value: &Value<T>) -> String {
+		match &value.value {
+			ValueDef::Composite(composite) => self.composite(composite),
+			ValueDef::Variant(variant) if variant.values.is_empty() => variant.name.clone(),
+			ValueDef::Variant(variant) => {
+				format!("{}{}", variant.name, self.composite(&variant.values))
+			},
+			ValueDef::Primitive(Primitive::Bool(bool)) => bool.to_string(),
+			ValueDef::Primitive(Primitive::Char(char)) => format!("{char:?}"),
+			ValueDef::Primitive(Primitive::String(string)) => format!("{string:?}"),
+			ValueDef::Primitive(Primitive::U128(number)) => number.to_string(),
+			ValueDef::Primitive(Primitive::I128(number)) => number.to_string(),
+			ValueDef::Primitive(Primitive::U256(bytes) | Primitive::I256(bytes)) => {
+				format!("0x{}", hex::encode(bytes))
+			},
+			ValueDef::BitSequence(bits) => format!("{bits:?}"),
+		}
+	}
+
+	/// Renders composite with the types of its fields, or without the types if the shape doesn't match
+	fn typed_composite<T>(
+		&self,
+		metadata: &Metadata,
+		fields: &[Field<PortableForm>],
+		composite: &Composite<T>,
+	) -> String {
+		if fields.len() != composite.len() {
+			return self.composite(composite);
+		}
+		let types = fields
+			.iter()
+			.map(|field| (field.ty.id, field.type_name.as_deref()));
+		match composite {
+			Composite::Named(values) => {
+				let values = values.iter().map(|(name, value)| (name.as_str(), value));
+				format!("{{ {} }}", self.fields(metadata, types, values))
+			},
+			Composite::Unnamed(values) => {
+				let values = types
+					.zip(values)
+					.map(|((type_id, type_name), value)| {
+						self.typed_value(metadata, type_id, type_name, value)
+					})
+					.collect::<Vec<_>>();
+				format!("({})", values.join(", "))
+			},
+		}
+	}
+
+	/// Renders values of the unnamed composite, all of the given types
+	fn typed_values<T>(
+		&self,
+		metadata: &Metadata,
+		types: impl Iterator<Item = u32>,
+		composite: &Composite<T>,
+	) -> String {
+		let values = types
+			.zip(composite.values())
+			.map(|(type_id, value)| self.typed_value(metadata, type_id, None, value))
+			.collect::<Vec<_>>();
+		format!("({})", values.join(", "))
+	}
+
+	/// Renders value of the type, type name of the field is used to recognize balances
+	fn typed_value<T>(
+		&self,
+		metadata: &Metadata,
+		type_id: u32,
+		type_name: Option<&str>,
+		value: &Value<T>,
+	) -> String {
+		match (kind(metadata, type_id, type_name), &value.value) {
+			(Kind::Balance, ValueDef::Primitive(Primitive::U128(amount))) => {
+				return self.balance(*amount)
+			},
+			(Kind::Address, _) => {
+				if let Some(account) = account(value) {
+					return self.address(account);
+				}
+			},
+			_ => (),
+		}
+		if is_bytes(metadata, type_id) {
+			if let Some(bytes) = bytes(value) {
+				return format!("0x{}", hex::encode(bytes));
+			}
+		}
+		let Some(ty) = metadata.types().resolve(type_id) else {
+			return self.value(value);
+		};
+		match (&ty.type_def, &value.value) {
+			(TypeDef::Composite(composite), ValueDef::Composite(values)) => {
+				self.typed_composite(metadata, &composite.fields, values)
+			},
+			(TypeDef::Variant(variants), ValueDef::Variant(variant))
+				if !variant.values.is_empty() =>
+			{
+				match variants
+					.variants
+					.iter()
+					.find(|def| def.name == variant.name)
+				{
+					Some(def) => format!(
+						"{}{}",
+						variant.name,
+						self.typed_composite(metadata, &def.fields, &variant.values)
+					),
+					None => self.value(value),
+				}
+			},
+			(TypeDef::Sequence(sequence), ValueDef::Composite(values)) => {
+				let types = std::iter::repeat(sequence.type_param.id);
+				self.typed_values(metadata, types, values)
+			},
+			(TypeDef::Array(array), ValueDef::Composite(values)) => {
+				let types = std::iter::repeat(array.type_param.id);
+				self.typed_values(metadata, types, values)
+			},
+			(TypeDef::Tuple(tuple), ValueDef::Composite(values))
+				if tuple.fields.len() == values.len() =>
+			{
+				let types = tuple.fields.iter().map(|ty| ty.id);
+				self.typed_values(metadata, types, values)
+			},
+			(TypeDef::Compact(compact), _) => {
+				self.typed_value(metadata, compact.type_param.id, type_name, value)
+			},
+			_ => self.value(value),
+		}
+	}
+
+	/// Renders fields with their type IDs and type names
+	fn fields<'a, T: 'a>(
+		&self,
+		metadata: &Metadata,
+		types: impl Iterator<Item = (u32, Option<&'a str>)>,
+		values: impl Iterator<Item = (&'a str, &'a Value<T>)>,
+	) -> String {
+		let fields = types
+			.zip(values)
+			.map(|((type_id, type_name), (name, value))| {
+				let value = self.typed_value(metadata, type_id, type_name, value);
+				format!("{name}: {value}")
+			})
+			.collect::<Vec<_>>();
+		fields.join(", ")
+	}
+
+	/// Renders decoded call as `pallet.call(arg: value, ..)`
+	pub fn call(&self, metadata: &Metadata, call: &Call) -> Result<String> {
+		let variant = metadata
+			.pallet_by_name(&call.pallet)
+			.and_then(|pallet| pallet.call_variant_by_name(&call.variant))
+			.ok_or_else(|| eyre!("Call {}.{} is not found", call.pallet, call.variant))?;
+		let variant_fields = variant
+			.fields
+			.iter()
+			.map(|field| (field.ty.id, field.type_name.as_deref()));
+		let args = call.args.iter().map(|(name, value)| (name.as_str(), value));
+		Ok(format!(
+			"{}.{}({})",
+			snake_case(&call.pallet),
+			call.variant,
+			self.fields(metadata, variant_fields, args)
+		))
+	}
+
+	/// Decodes and renders event as `pallet.Event(field: value, ..)`, event is encoded with pallet and variant indices
+	pub fn event(&self, metadata: &Metadata, encoded: &[u8]) -> Result<String> {
+		let input = &mut &encoded[..];
+		let (pallet_index, event_index) = <(u8, u8)>::decode(input)
+			.map_err(|error| eyre!("Cannot decode event index: {error}"))?;
+		let pallet = metadata
+			.pallet_by_index(pallet_index)
+			.ok_or_else(|| eyre!("Pallet {pallet_index} is not found in metadata"))?;
+		let variant = pallet.event_variant_by_index(event_index).ok_or_else(|| {
+			eyre!(
+				"Event {event_index} of pallet {} is not found",
+				pallet.name()
+			)
+		})?;
+		let mut values = vec![];
+		for (index, field) in variant.fields.iter().enumerate() {
+			let name = field.name.clone().unwrap_or_else(|| index.to_string());
+			let value = dynamic::decode_as_type(input, field.ty.id, metadata.types())
+				.map_err(|error| eyre!("Failed to decode event field {name}: {error}"))?;
+			values.push((name, value));
+		}
+		let variant_fields = variant
+			.fields
+			.iter()
+			.map(|field| (field.ty.id, field.type_name.as_deref()));
+		let fields = values.iter().map(|(name, value)| (name.as_str(), value));
+		Ok(format!(
+			"{}.{}({})",
+			snake_case(pallet.name()),
+			variant.name,
+			self.fields(metadata, variant_fields, fields)
+		))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		extrinsic::{decode::Extrinsic, encode_unsigned},
+		test_utils::metadata,
+	};
+	use codec::Encode;
+	use hex_literal::hex;
+	use test_case::test_case;
+
+	#[test_case(1_500_000_000_000_000_000 => "1.5 AVAIL" ; "fraction")]
+	#[test_case(2_000_000_000_000_000_000 => "2 AVAIL" ; "whole")]
+	#[test_case(1 => "0.000000000000000001 AVAIL" ; "smallest unit")]
+	#[test_case(0 => "0 AVAIL" ; "zero")]
+	fn balance(amount: u128) -> String {
+		Formatter::default().balance(amount)
+	}
+
+	#[test_case(40, 12 => "0.000000000000000000000000000000000000012 AVAIL" ; "fraction")]
+	#[test_case(255, 0 => "0 AVAIL" ; "zero")]
+	fn balance_with_large_decimals(decimals: u8, amount: u128) -> String {
+		let mut formatter = Formatter::default();
+		formatter.balance.decimals = decimals;
+		formatter.balance(amount)
+	}
+
+	#[test_case("Balances" => "balances")]
+	#[test_case("DataAvailability" => "data_availability")]
+	fn pallet_name(name: &str) -> String {
+		snake_case(name)
+	}
+
+	#[test]
+	fn addresses() {
+		// Alice
+		let account = hex!("d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d");
+		let mut formatter = Formatter::default();
+		assert_eq!(formatter.address(account), "5GrwvaEF..");
+		formatter.address = AddressFormat::Hex;
+		formatter.address_length = None;
+		assert!(formatter.address(account).starts_with("0xd43593c7"));
+
+		let id = Value::unnamed_variant("Id", [Value::from_bytes(account)]);
+		formatter.address_length = Some(6);
+		assert_eq!(formatter.address(super::account(&id).unwrap()), "0xd435..");
+	}
+
+	#[test]
+	fn values() {
+		let formatter = Formatter::default();
+		let value = Value::named_composite([
+			("remark", Value::from_bytes([1, 2])),
+			("kind", Value::unnamed_variant("Some", [Value::bool(true)])),
+			("none", Value::unnamed_variant("None", [])),
+			("name", Value::string("avail")),
+		]);
+		assert_eq!(
+			formatter.value(&value),
+			"{ remark: (1, 2), kind: Some(true), none: None, name: \"avail\" }"
+		);
+	}
+
+	#[test]
+	fn render_by_metadata_types() {
+		let metadata = metadata::metadata();
+		let formatter = Formatter::default();
+		let event = [
+			vec![metadata::DATA_AVAILABILITY_INDEX, 0],
+			metadata::DataAvailabilityEvent::ApplicationKeyCreated {
+				key: b"key".to_vec(),
+				owner: [1; 32],
+				id: metadata::AppId(3),
+			}
+			.encode()[1..]
+				.to_vec(),
+		]
+		.concat();
+		// App ID is not a byte sequence, even though its value fits into a byte
+		let owner = hex::encode([1; 32]);
+		assert_eq!(
+			formatter.event(&metadata, &event).unwrap(),
+			format!(
+				"data_availability.ApplicationKeyCreated(key: 0x6b6579, owner: 0x{owner}, id: (3))"
+			)
+		);
+
+		let extrinsic = encode_unsigned(
+			&[
+				vec![metadata::DATA_AVAILABILITY_INDEX, 1],
+				b"avail".to_vec().encode(),
+			]
+			.concat(),
+		);
+		let parts = Extrinsic(extrinsic).decode_parts(&metadata).unwrap();
+		assert_eq!(
+			formatter.call(&metadata, &parts.call).unwrap(),
+			"data_availability.submit_data(data: 0x617661696c)"
+		);
+	}
+}
@@ -1,6 +1,10 @@
 //! Parallelized proof verification
+//!
+//! Large number of cells is verified with batched pairing checks (see [`multiproof`]). If `blst` feature is enabled
+//! and supported by the CPU, SIMD accelerated backend is used instead. Smaller number of cells is verified one by one.
+//! All verification jobs are executed on the verification workers (see [`crate::verification`]).
 
-use color_eyre::eyre;
+use color_eyre::eyre::{self, eyre};
 use dusk_plonk::commitment_scheme::kzg10::PublicParameters;
 use itertools::{Either, Itertools};
 use kate_recovery::{
@@ -9,15 +13,28 @@ use kate_recovery::{
 	proof,
 };
 use std::sync::Arc;
-use tokio::{task::JoinSet, time::Instant};
+use tokio::time::Instant;
 use tracing::debug;
 
-async fn verify_proof(
+use crate::{
+	counters::{self, Counter},
+	verification::WorkerPool,
+};
+
+#[cfg(feature = "blst")]
+pub mod blst;
+pub mod multiproof;
+
+/// Minimal number of cells for which batched verification is used
+pub const MIN_BATCHED_CELLS: usize = 32;
+
+fn verify_proof(
 	public_parameters: Arc<PublicParameters>,
 	dimensions: Dimensions,
 	commitment: [u8; 48],
 	cell: Cell,
 ) -> Result<(Position, bool), proof::Error> {
+	counters::increment(Counter::CellsVerified);
 	proof::verify(&public_parameters, dimensions, &commitment, &cell)
 		.map(|verified| (cell.position, verified))
 }
@@ -29,6 +46,7 @@ pub async fn verify(
 	cells: &[Cell],
 	commitments: &[[u8; 48]],
 	public_parameters: Arc<PublicParameters>,
+	workers: &WorkerPool,
 ) -> eyre::Result<(Vec<Position>, Vec<Position>)> {
 	if cells.is_empty() {
 		return Ok((Vec::new(), Vec::new()));
@@ -36,21 +54,44 @@ pub async fn verify(
 
 	let start_time = Instant::now();
 
-	let mut tasks = JoinSet::new();
+	#[cfg(feature = "blst")]
+	if cells.len() >= MIN_BATCHED_CELLS && blst::is_available() {
+		let result = blst::verify(dimensions, cells, commitments, public_parameters, workers).await;
+		debug!(block_num, duration = ?start_time.elapsed(), "Accelerated proof verification completed");
+		return result;
+	}
 
-	for cell in cells {
-		tasks.spawn(verify_proof(
-			public_parameters.clone(),
+	if cells.len() >= MIN_BATCHED_CELLS {
+		let result = multiproof::verify(
 			dimensions,
-			commitments[cell.position.row as usize],
-			cell.clone(),
-		));
+			cells,
+			commitments,
+			public_parameters,
+			multiproof::RegionSize::default(),
+			workers,
+		)
+		.await;
+		debug!(block_num, duration = ?start_time.elapsed(), "Batched proof verification completed");
+		return result;
 	}
 
-	let mut results = Vec::with_capacity(cells.len());
-	while let Some(result) = tasks.join_next().await {
-		results.push(result??)
-	}
+	let jobs = cells
+		.iter()
+		.map(|cell| {
+			let public_parameters = public_parameters.clone();
+			let commitment = *commitments
+				.get(cell.position.row as usize)
+				.ok_or_else(|| eyre!("Missing commitment for row {}", cell.position.row))?;
+			let cell = cell.clone();
+			Ok(move || verify_proof(public_parameters, dimensions, commitment, cell))
+		})
+		.collect::<eyre::Result<Vec<_>>>()?;
+
+	let results = workers
+		.execute_ordered(jobs)
+		.await?
+		.into_iter()
+		.collect::<Result<Vec<_>, _>>()?;
 
 	debug!(block_num, duration = ?start_time.elapsed(), "Proof verification completed");
 
@@ -61,3 +102,92 @@ pub async fn verify(
 			false => Either::Right(position),
 		}))
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use dusk_bytes::Serializable;
+	use dusk_plonk::{bls12_381::G1Affine, fft::EvaluationDomain, prelude::BlsScalar};
+	use kate_recovery::testnet;
+
+	/// Cells of the matrix where row `i` is the polynomial `a + bx`, with `a = i + 1` and `b = i + 2`.
+	/// Commitment is `aG + bτG`, and opening proof at any point is `bG` (witness polynomial is constant `b`).
+	pub(crate) fn linear_rows(
+		public_parameters: &PublicParameters,
+		dimensions: Dimensions,
+	) -> (Vec<[u8; 48]>, Vec<Cell>) {
+		let (commit_key, _) = public_parameters.trim(2).unwrap();
+		let tau_g =
+			G1Affine::from_bytes(commit_key.to_var_bytes()[48..96].try_into().unwrap()).unwrap();
+		let g = G1Affine::generator();
+		let coefficients = |row: u32| {
+			(
+				BlsScalar::from(row as u64 + 1),
+				BlsScalar::from(row as u64 + 2),
+			)
+		};
+
+		let cols: usize = dimensions.cols().get().into();
+		let points = EvaluationDomain::new(cols)
+			.unwrap()
+			.elements()
+			.take(cols)
+			.collect::<Vec<_>>();
+		let rows = dimensions.extended_rows();
+		let commitments = (0..rows)
+			.map(|row| {
+				let (a, b) = coefficients(row);
+				G1Affine::from(g * a + tau_g * b).to_bytes()
+			})
+			.collect();
+		let cells = (0..rows)
+			.flat_map(|row| (0..dimensions.cols().get()).map(move |col| Position { row, col }))
+			.map(|position| {
+				let (a, b) = coefficients(position.row);
+				let value = a + b * points[position.col as usize];
+				let mut content = [0u8; 80];
+				content[..48].copy_from_slice(&G1Affine::from(g * b).to_bytes());
+				content[48..].copy_from_slice(&value.to_bytes());
+				Cell { position, content }
+			})
+			.collect();
+		(commitments, cells)
+	}
+
+	#[tokio::test]
+	async fn verify_valid_cells() {
+		let dimensions = Dimensions::new(1, 4).unwrap();
+		let public_parameters = Arc::new(testnet::public_params(1024));
+		let (commitments, cells) = linear_rows(&public_parameters, dimensions);
+		let workers = WorkerPool::new(1, 8).unwrap();
+		let (verified, unverified) = verify(
+			1,
+			dimensions,
+			&cells,
+			&commitments,
+			public_parameters,
+			&workers,
+		)
+		.await
+		.unwrap();
+		assert_eq!(verified.len(), cells.len());
+		assert!(unverified.is_empty());
+	}
+
+	#[tokio::test]
+	async fn missing_commitment_fails() {
+		let dimensions = Dimensions::new(1, 4).unwrap();
+		let public_parameters = Arc::new(testnet::public_params(1024));
+		let (commitments, cells) = linear_rows(&public_parameters, dimensions);
+		let workers = WorkerPool::new(1, 8).unwrap();
+		let result = verify(
+			1,
+			dimensions,
+			&cells,
+			&commitments[..1],
+			public_parameters,
+			&workers,
+		);
+		assert!(result.await.is_err());
+	}
+}
@@ -0,0 +1,385 @@
+//! SIMD accelerated batch verification of the cell proofs, using `blst` BLS12-381 backend.
+//!
+//! Enabled with `blst` feature. Openings of all cells in a chunk are combined with random scalars, and checked with
+//! two multi-scalar multiplications and a single pairing check:
+//!
+//! `e(Σ rᵢ(Cᵢ - yᵢG + zᵢπᵢ), H) = e(Σ rᵢπᵢ, τH)`
+//!
+//! Multi-scalar multiplications use parallel Pippenger algorithm of the `blst`. Chunks are verified in parallel, and
+//! cells of the chunk which fails the check are verified one by one. Assembly optimized code paths are used only if
+//! CPU supports them (checked at runtime with [`is_available`]), otherwise verification falls back to the default
+//! backend.
+//!
+//! # Safety
+//!
+//! `blst` functions are called through its C API, with the following invariants:
+//!
+//! * Compressed points are read from fixed size arrays, so `blst` never reads past the input
+//! * Decoded points are checked to be on the curve and in the prime order subgroup, which pairing check requires
+//! * Miller loop is not defined for the point at infinity, so pairings with it (which equal one) are skipped
+//! * All output pointers are references to initialized values, which are not aliased with the inputs
+
+use ::blst::{
+	blst_final_exp, blst_fp12, blst_fp12_is_one, blst_fp12_mul, blst_miller_loop, blst_p1,
+	blst_p1_affine, blst_p1_affine_in_g1, blst_p1_affine_is_inf, blst_p1_cneg, blst_p1_from_affine,
+	blst_p1_to_affine, blst_p1_uncompress, blst_p2_affine, blst_p2_affine_in_g2,
+	blst_p2_uncompress, p1_affines, BLST_ERROR,
+};
+use color_eyre::eyre::{self, eyre};
+use dusk_bytes::Serializable;
+use dusk_plonk::{
+	commitment_scheme::kzg10::PublicParameters, fft::EvaluationDomain, prelude::BlsScalar,
+};
+use kate_recovery::{
+	data::Cell,
+	matrix::{Dimensions, Position},
+	proof,
+};
+use rand::thread_rng;
+use std::sync::{Arc, OnceLock};
+use tracing::{debug, info};
+
+use crate::{
+	counters::{self, Counter},
+	verification::WorkerPool,
+};
+
+/// Number of cells verified with a single pairing check
+const CHUNK_SIZE: usize = 256;
+const G1_SIZE: usize = 48;
+const G2_SIZE: usize = 96;
+
+/// Checks (once) if CPU supports instructions used by the optimized `blst` code paths
+pub fn is_available() -> bool {
+	static AVAILABLE: OnceLock<bool> = OnceLock::new();
+	*AVAILABLE.get_or_init(|| {
+		#[cfg(target_arch = "x86_64")]
+		let available = std::arch::is_x86_feature_detected!("adx")
+			&& std::arch::is_x86_feature_detected!("bmi2");
+		#[cfg(target_arch = "aarch64")]
+		let available = std::arch::is_aarch64_feature_detected!("neon");
+		#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+		let available = false;
+		info!(available, "SIMD accelerated proof verification");
+		available
+	})
+}
+
+/// Decodes compressed G1 point, fails if it is not in the G1 subgroup
+fn p1(bytes: &[u8]) -> Option<blst_p1_affine> {
+	let bytes: &[u8; G1_SIZE] = bytes.try_into().ok()?;
+	let mut point = blst_p1_affine::default();
+	// SAFETY: `blst_p1_uncompress` reads exactly `G1_SIZE` bytes, which is the length of `bytes`.
+	// It checks that the point is on the curve, but not that it is in the subgroup, so it is checked separately.
+	let decoded = unsafe { blst_p1_uncompress(&mut point, bytes.as_ptr()) };
+	(decoded == BLST_ERROR::BLST_SUCCESS && unsafe { blst_p1_affine_in_g1(&point) })
+		.then_some(point)
+}
+
+/// Decodes compressed G2 point, fails if it is not in the G2 subgroup
+fn p2(bytes: &[u8]) -> Option<blst_p2_affine> {
+	let bytes: &[u8; G2_SIZE] = bytes.try_into().ok()?;
+	let mut point = blst_p2_affine::default();
+	// SAFETY: `blst_p2_uncompress` reads exactly `G2_SIZE` bytes, which is the length of `bytes`.
+	// It checks that the point is on the curve, but not that it is in the subgroup, so it is checked separately.
+	let decoded = unsafe { blst_p2_uncompress(&mut point, bytes.as_ptr()) };
+	(decoded == BLST_ERROR::BLST_SUCCESS && unsafe { blst_p2_affine_in_g2(&point) })
+		.then_some(point)
+}
+
+fn is_infinity(point: &blst_p1_affine) -> bool {
+	// SAFETY: `point` is a valid reference
+	unsafe { blst_p1_affine_is_inf(point) }
+}
+
+/// Miller loop of the pairing, `None` if the G1 point is at infinity (pairing is one)
+fn miller_loop(q: &blst_p2_affine, p: &blst_p1_affine) -> Option<blst_fp12> {
+	if is_infinity(p) {
+		return None;
+	}
+	let mut result = blst_fp12::default();
+	// SAFETY: `p` is not at infinity, and both points are decoded or computed from subgroup points.
+	// `blst_p2_affine` of the opening key is not at infinity, since it is a generator or its multiple.
+	unsafe { blst_miller_loop(&mut result, q, p) };
+	Some(result)
+}
+
+fn projective(point: &blst_p1_affine) -> blst_p1 {
+	let mut projective = blst_p1::default();
+	// SAFETY: output and input are distinct valid references
+	unsafe { blst_p1_from_affine(&mut projective, point) };
+	projective
+}
+
+fn affine(point: &blst_p1) -> blst_p1_affine {
+	let mut affine = blst_p1_affine::default();
+	// SAFETY: output and input are distinct valid references
+	unsafe { blst_p1_to_affine(&mut affine, point) };
+	affine
+}
+
+fn msm(points: &[blst_p1], scalars: &[BlsScalar]) -> blst_p1 {
+	let scalars = scalars
+		.iter()
+		.flat_map(|scalar| scalar.to_bytes())
+		.collect::<Vec<u8>>();
+	p1_affines::from(points).mult(&scalars, 255)
+}
+
+/// Verifier with the opening key converted to the `blst` representation
+pub struct BlstVerifier {
+	g: blst_p1,
+	h: blst_p2_affine,
+	beta_h: blst_p2_affine,
+	points: Vec<BlsScalar>,
+}
+
+struct Opening {
+	commitment: blst_p1,
+	proof: blst_p1,
+	value: BlsScalar,
+	point: BlsScalar,
+}
+
+impl BlstVerifier {
+	pub fn new(public_parameters: &PublicParameters, dimensions: Dimensions) -> eyre::Result<Self> {
+		// Opening key is serialized as G1 generator, G2 generator and G2 generator multiplied by the secret
+		let key = public_parameters.opening_key().to_bytes();
+		let g = p1(&key[..G1_SIZE]).ok_or_else(|| eyre!("Invalid opening key"))?;
+		let h = p2(&key[G1_SIZE..G1_SIZE + G2_SIZE]).ok_or_else(|| eyre!("Invalid opening key"))?;
+		let beta_h = p2(&key[G1_SIZE + G2_SIZE..]).ok_or_else(|| eyre!("Invalid opening key"))?;
+
+		let cols: usize = dimensions.cols().get().into();
+		let domain = EvaluationDomain::new(cols)
+			.map_err(|error| eyre!("Invalid evaluation domain: {error:?}"))?;
+		Ok(BlstVerifier {
+			g: projective(&g),
+			h,
+			beta_h,
+			points: domain.elements().take(cols).collect(),
+		})
+	}
+
+	fn opening(&self, commitments: &[[u8; 48]], cell: &Cell) -> Option<Opening> {
+		let commitment = commitments.get(cell.position.row as usize)?;
+		Some(Opening {
+			commitment: projective(&p1(commitment)?),
+			proof: projective(&p1(&cell.proof())?),
+			value: BlsScalar::from_bytes(&cell.data()).ok()?,
+			point: *self.points.get(cell.position.col as usize)?,
+		})
+	}
+
+	/// Verifies all cells with one pairing check, fails if any of the cells is invalid or malformed
+	pub fn verify_batch(&self, commitments: &[[u8; 48]], cells: &[Cell]) -> bool {
+		let Some(openings) = cells
+			.iter()
+			.map(|cell| self.opening(commitments, cell))
+			.collect::<Option<Vec<_>>>()
+		else {
+			return false;
+		};
+
+		for _ in cells {
+			counters::increment(Counter::CellsVerified);
+		}
+
+		let mut rng = thread_rng();
+		let randoms = openings
+			.iter()
+			.map(|_| BlsScalar::random(&mut rng))
+			.collect::<Vec<_>>();
+
+		// Left side: Σ rᵢCᵢ + Σ rᵢzᵢπᵢ - (Σ rᵢyᵢ)G
+		let mut points = Vec::with_capacity(openings.len() * 2 + 1);
+		let mut scalars = Vec::with_capacity(openings.len() * 2 + 1);
+		let mut value = BlsScalar::zero();
+		for (opening, random) in openings.iter().zip(&randoms) {
+			points.push(opening.commitment);
+			scalars.push(*random);
+			points.push(opening.proof);
+			scalars.push(random * opening.point);
+			value += random * opening.value;
+		}
+		points.push(self.g);
+		scalars.push(-value);
+		let left = affine(&msm(&points, &scalars));
+
+		// Right side: Σ rᵢπᵢ, negated so both pairings can be multiplied and compared with one
+		let proofs = openings
+			.iter()
+			.map(|opening| opening.proof)
+			.collect::<Vec<_>>();
+		let mut right = msm(&proofs, &randoms);
+		// SAFETY: `right` is a valid reference, negated in place
+		unsafe { blst_p1_cneg(&mut right, true) };
+		let right = affine(&right);
+
+		let product = match (
+			miller_loop(&self.h, &left),
+			miller_loop(&self.beta_h, &right),
+		) {
+			(None, None) => return true,
+			(Some(product), None) | (None, Some(product)) => product,
+			(Some(left_loop), Some(right_loop)) => {
+				let mut product = blst_fp12::default();
+				// SAFETY: output and inputs are distinct valid references
+				unsafe { blst_fp12_mul(&mut product, &left_loop, &right_loop) };
+				product
+			},
+		};
+		let mut result = blst_fp12::default();
+		// SAFETY: output and input are distinct valid references
+		unsafe {
+			blst_final_exp(&mut result, &product);
+			blst_fp12_is_one(&result)
+		}
+	}
+}
+
+fn verify_chunk(
+	public_parameters: &PublicParameters,
+	verifier: &BlstVerifier,
+	dimensions: Dimensions,
+	commitments: &[[u8; 48]],
+	cells: Vec<Cell>,
+) -> eyre::Result<Vec<(Position, bool)>> {
+	if verifier.verify_batch(commitments, &cells) {
+		return Ok(cells.iter().map(|cell| (cell.position, true)).collect());
+	}
+
+	debug!(
+		cells = cells.len(),
+		"Batched verification failed, verifying cells one by one"
+	);
+	cells
+		.iter()
+		.map(|cell| {
+			let commitment = commitments
+				.get(cell.position.row as usize)
+				.ok_or_else(|| eyre!("Missing commitment for row {}", cell.position.row))?;
+			let verified = proof::verify(public_parameters, dimensions, commitment, cell)?;
+			Ok((cell.position, verified))
+		})
+		.collect()
+}
+
+/// Verifies proofs for given cells and commitments, with one pairing check per chunk of cells.
+/// Chunks are verified on the verification workers. Returns verified and unverified positions.
+pub async fn verify(
+	dimensions: Dimensions,
+	cells: &[Cell],
+	commitments: &[[u8; 48]],
+	public_parameters: Arc<PublicParameters>,
+	workers: &WorkerPool,
+) -> eyre::Result<(Vec<Position>, Vec<Position>)> {
+	let verifier = Arc::new(BlstVerifier::new(&public_parameters, dimensions)?);
+	let commitments: Arc<[[u8; 48]]> = commitments.into();
+
+	let jobs = cells
+		.chunks(CHUNK_SIZE)
+		.map(|chunk| {
+			let cells = chunk.to_vec();
+			let verifier = verifier.clone();
+			let commitments = commitments.clone();
+			let public_parameters = public_parameters.clone();
+			move || {
+				verify_chunk(
+					&public_parameters,
+					&verifier,
+					dimensions,
+					&commitments,
+					cells,
+				)
+			}
+		})
+		.collect::<Vec<_>>();
+
+	let mut verified = vec![];
+	let mut unverified = vec![];
+	for result in workers.execute_ordered(jobs).await? {
+		for (position, is_verified) in result? {
+			match is_verified {
+				true => verified.push(position),
+				false => unverified.push(position),
+			}
+		}
+	}
+	Ok((verified, unverified))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::proof::{multiproof, tests::linear_rows};
+	use kate_recovery::testnet;
+	use std::collections::HashSet;
+
+	#[test]
+	fn opening_key_conversion() {
+		let public_parameters = testnet::public_params(1024);
+		let dimensions = Dimensions::new(1, 16).unwrap();
+		let verifier = BlstVerifier::new(&public_parameters, dimensions).unwrap();
+		assert_eq!(verifier.points.len(), 16);
+	}
+
+	#[test]
+	fn malformed_cells_fail_batch() {
+		let public_parameters = testnet::public_params(1024);
+		let dimensions = Dimensions::new(1, 16).unwrap();
+		let verifier = BlstVerifier::new(&public_parameters, dimensions).unwrap();
+		let cell = Cell {
+			position: Position { row: 0, col: 0 },
+			content: [0xff; 80],
+		};
+		assert!(!verifier.verify_batch(&[[0xff; 48]], &[cell]));
+	}
+
+	#[test]
+	fn valid_cells_pass_batch() {
+		let public_parameters = testnet::public_params(1024);
+		let dimensions = Dimensions::new(2, 8).unwrap();
+		let (commitments, cells) = linear_rows(&public_parameters, dimensions);
+		let verifier = BlstVerifier::new(&public_parameters, dimensions).unwrap();
+		assert!(verifier.verify_batch(&commitments, &cells));
+
+		let mut invalid = cells.clone();
+		invalid[3].content[48..].copy_from_slice(&BlsScalar::from(7).to_bytes());
+		assert!(!verifier.verify_batch(&commitments, &invalid));
+	}
+
+	#[tokio::test]
+	async fn matches_default_backend() {
+		let public_parameters = Arc::new(testnet::public_params(1024));
+		let dimensions = Dimensions::new(2, 8).unwrap();
+		let (commitments, mut cells) = linear_rows(&public_parameters, dimensions);
+		cells[3].content[48..].copy_from_slice(&BlsScalar::from(7).to_bytes());
+
+		let workers = WorkerPool::new(2, 8).unwrap();
+		let (verified, unverified) = verify(
+			dimensions,
+			&cells,
+			&commitments,
+			public_parameters.clone(),
+			&workers,
+		)
+		.await
+		.unwrap();
+		let (expected_verified, expected_unverified) = multiproof::verify(
+			dimensions,
+			&cells,
+			&commitments,
+			public_parameters,
+			multiproof::RegionSize::default(),
+			&workers,
+		)
+		.await
+		.unwrap();
+		assert_eq!(
+			verified.into_iter().collect::<HashSet<_>>(),
+			expected_verified.into_iter().collect::<HashSet<_>>()
+		);
+		assert_eq!(unverified, expected_unverified);
+		assert_eq!(unverified, vec![cells[3].position]);
+	}
+}
@@ -0,0 +1,307 @@
+//! Batched verification of the cell proofs, grouped by the grid regions.
+//!
+//! Matrix is split into [`GridRegion`]s, and openings of all cells in the region are verified with a single batched
+//! pairing check, by random linear combination of the openings. Verifying cells one by one takes two pairings per
+//! cell, while batched check takes two pairings per region. If batched check of the region fails, its cells are
+//! verified one by one, to find out which of them are invalid.
+
+use color_eyre::eyre::{self, eyre};
+use dusk_bytes::Serializable;
+use dusk_plonk::{
+	bls12_381::G1Affine,
+	commitment_scheme::kzg10::{
+		commitment::Commitment, proof::Proof, OpeningKey, PublicParameters,
+	},
+	fft::EvaluationDomain,
+	prelude::BlsScalar,
+};
+use itertools::Itertools;
+use kate_recovery::{
+	data::Cell,
+	matrix::{Dimensions, Position},
+	proof,
+};
+use merlin::Transcript;
+use std::{collections::BTreeMap, sync::Arc};
+use tracing::debug;
+
+use crate::{
+	counters::{self, Counter},
+	verification::WorkerPool,
+};
+
+const TRANSCRIPT_LABEL: &[u8] = b"avail-light-multiproof";
+
+/// Rectangular region of the extended matrix, with inclusive start and exclusive end
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GridRegion {
+	pub start_row: u32,
+	pub start_col: u16,
+	pub end_row: u32,
+	pub end_col: u16,
+}
+
+impl GridRegion {
+	pub fn contains(&self, position: &Position) -> bool {
+		(self.start_row..self.end_row).contains(&position.row)
+			&& (self.start_col..self.end_col).contains(&position.col)
+	}
+}
+
+/// Size of the grid regions, in rows and columns
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegionSize {
+	pub rows: u32,
+	pub cols: u16,
+}
+
+impl Default for RegionSize {
+	fn default() -> Self {
+		RegionSize { rows: 16, cols: 64 }
+	}
+}
+
+impl RegionSize {
+	/// Region which contains the position, clipped to the matrix dimensions
+	pub fn region(&self, dimensions: Dimensions, position: &Position) -> GridRegion {
+		let rows = self.rows.max(1);
+		let cols = self.cols.max(1);
+		let start_row = position.row / rows * rows;
+		let start_col = position.col / cols * cols;
+		GridRegion {
+			start_row,
+			start_col,
+			end_row: (start_row + rows).min(dimensions.extended_rows()),
+			end_col: (start_col + cols).min(dimensions.cols().get()),
+		}
+	}
+
+	/// Groups cells by the regions which contain them
+	pub fn group<'a>(
+		&self,
+		dimensions: Dimensions,
+		cells: &'a [Cell],
+	) -> BTreeMap<GridRegion, Vec<&'a Cell>> {
+		cells
+			.iter()
+			.map(|cell| (self.region(dimensions, &cell.position), cell))
+			.into_group_map()
+			.into_iter()
+			.collect()
+	}
+}
+
+/// Verifies cells of the single block with batched pairing checks
+pub struct MultiProofVerifier {
+	opening_key: OpeningKey,
+	points: Vec<BlsScalar>,
+}
+
+impl MultiProofVerifier {
+	pub fn new(public_parameters: &PublicParameters, dimensions: Dimensions) -> eyre::Result<Self> {
+		let cols: usize = dimensions.cols().get().into();
+		let domain = EvaluationDomain::new(cols)
+			.map_err(|error| eyre!("Invalid evaluation domain: {error:?}"))?;
+		Ok(MultiProofVerifier {
+			opening_key: public_parameters.opening_key(),
+			points: domain.elements().take(cols).collect(),
+		})
+	}
+
+	fn opening(&self, commitment: &[u8; 48], cell: &Cell) -> Option<(BlsScalar, Proof)> {
+		let point = *self.points.get(cell.position.col as usize)?;
+		let commitment_to_witness = G1Affine::from_bytes(&cell.proof())
+			.map(Commitment::from)
+			.ok()?;
+		let evaluated_point = BlsScalar::from_bytes(&cell.data()).ok()?;
+		let commitment_to_polynomial = G1Affine::from_bytes(commitment)
+			.map(Commitment::from)
+			.ok()?;
+		let proof = Proof {
+			commitment_to_witness,
+			evaluated_point,
+			commitment_to_polynomial,
+		};
+		Some((point, proof))
+	}
+
+	/// Verifies all cells with one batched pairing check, fails if any of the cells is invalid or malformed
+	pub fn verify_batch(&self, commitments: &[[u8; 48]], cells: &[&Cell]) -> bool {
+		let Some((points, proofs)): Option<(Vec<_>, Vec<_>)> = cells
+			.iter()
+			.map(|cell| {
+				let commitment = commitments.get(cell.position.row as usize)?;
+				self.opening(commitment, cell)
+			})
+			.collect::<Option<Vec<_>>>()
+			.map(|openings| openings.into_iter().unzip())
+		else {
+			return false;
+		};
+
+		for _ in cells {
+			counters::increment(Counter::CellsVerified);
+		}
+		let mut transcript = Transcript::new(TRANSCRIPT_LABEL);
+		self.opening_key
+			.batch_check(&points, &proofs, &mut transcript)
+			.is_ok()
+	}
+}
+
+fn verify_region(
+	public_parameters: &PublicParameters,
+	verifier: &MultiProofVerifier,
+	dimensions: Dimensions,
+	commitments: &[[u8; 48]],
+	cells: Vec<Cell>,
+) -> eyre::Result<Vec<(Position, bool)>> {
+	let refs = cells.iter().collect::<Vec<_>>();
+	if verifier.verify_batch(commitments, &refs) {
+		return Ok(cells.iter().map(|cell| (cell.position, true)).collect());
+	}
+
+	debug!(
+		cells = cells.len(),
+		"Batched verification failed, verifying cells one by one"
+	);
+	cells
+		.iter()
+		.map(|cell| {
+			let commitment = commitments
+				.get(cell.position.row as usize)
+				.ok_or_else(|| eyre!("Missing commitment for row {}", cell.position.row))?;
+			let verified = proof::verify(public_parameters, dimensions, commitment, cell)?;
+			Ok((cell.position, verified))
+		})
+		.collect()
+}
+
+/// Verifies proofs for given cells and commitments, with one batched pairing check per grid region.
+/// Regions are verified on the verification workers. Returns verified and unverified positions.
+pub async fn verify(
+	dimensions: Dimensions,
+	cells: &[Cell],
+	commitments: &[[u8; 48]],
+	public_parameters: Arc<PublicParameters>,
+	region_size: RegionSize,
+	workers: &WorkerPool,
+) -> eyre::Result<(Vec<Position>, Vec<Position>)> {
+	let verifier = Arc::new(MultiProofVerifier::new(&public_parameters, dimensions)?);
+	let commitments: Arc<[[u8; 48]]> = commitments.into();
+
+	let jobs = region_size
+		.group(dimensions, cells)
+		.into_values()
+		.map(|cells| {
+			let cells = cells.into_iter().cloned().collect::<Vec<_>>();
+			let verifier = verifier.clone();
+			let commitments = commitments.clone();
+			let public_parameters = public_parameters.clone();
+			move || {
+				verify_region(
+					&public_parameters,
+					&verifier,
+					dimensions,
+					&commitments,
+					cells,
+				)
+			}
+		})
+		.collect::<Vec<_>>();
+
+	let mut verified = vec![];
+	let mut unverified = vec![];
+	for result in workers.execute_ordered(jobs).await? {
+		for (position, is_verified) in result? {
+			match is_verified {
+				true => verified.push(position),
+				false => unverified.push(position),
+			}
+		}
+	}
+	Ok((verified, unverified))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::proof::tests::linear_rows;
+	use kate_recovery::testnet;
+	use test_case::test_case;
+
+	fn cell(row: u32, col: u16) -> Cell {
+		Cell {
+			position: Position { row, col },
+			content: [0; 80],
+		}
+	}
+
+	#[test_case(Position { row: 0, col: 0 } => GridRegion { start_row: 0, start_col: 0, end_row: 16, end_col: 64 } ; "first region")]
+	#[test_case(Position { row: 17, col: 70 } => GridRegion { start_row: 16, start_col: 64, end_row: 32, end_col: 128 } ; "inner region")]
+	#[test_case(Position { row: 60, col: 250 } => GridRegion { start_row: 48, start_col: 192, end_row: 64, end_col: 256 } ; "last region")]
+	fn region(position: Position) -> GridRegion {
+		let dimensions = Dimensions::new(32, 256).unwrap();
+		RegionSize::default().region(dimensions, &position)
+	}
+
+	#[test]
+	fn region_is_clipped() {
+		let dimensions = Dimensions::new(2, 32).unwrap();
+		let region = RegionSize::default().region(dimensions, &Position { row: 3, col: 31 });
+		assert_eq!(region.end_row, 4);
+		assert_eq!(region.end_col, 32);
+		assert!(region.contains(&Position { row: 3, col: 31 }));
+		assert!(!region.contains(&Position { row: 4, col: 31 }));
+	}
+
+	#[test]
+	fn group_cells() {
+		let dimensions = Dimensions::new(32, 256).unwrap();
+		let cells = [cell(0, 0), cell(15, 63), cell(16, 0), cell(0, 64)];
+		let groups = RegionSize::default().group(dimensions, &cells);
+		assert_eq!(groups.len(), 3);
+		let first = groups.values().next().unwrap();
+		assert_eq!(first.len(), 2);
+		assert!(groups
+			.iter()
+			.all(|(region, cells)| cells.iter().all(|cell| region.contains(&cell.position))));
+	}
+
+	#[test]
+	fn valid_cells_pass_batch() {
+		let public_parameters = testnet::public_params(1024);
+		let dimensions = Dimensions::new(2, 8).unwrap();
+		let (commitments, cells) = linear_rows(&public_parameters, dimensions);
+		let verifier = MultiProofVerifier::new(&public_parameters, dimensions).unwrap();
+		let refs = cells.iter().collect::<Vec<_>>();
+		assert!(verifier.verify_batch(&commitments, &refs));
+
+		let mut invalid = cells[3].clone();
+		invalid.content[48..].copy_from_slice(&BlsScalar::from(7).to_bytes());
+		assert!(!verifier.verify_batch(&commitments, &[&cells[0], &invalid]));
+		assert!(!verifier.verify_batch(&commitments[..1], &refs));
+	}
+
+	#[tokio::test]
+	async fn verify_with_batches() {
+		let public_parameters = Arc::new(testnet::public_params(1024));
+		let dimensions = Dimensions::new(2, 8).unwrap();
+		let (commitments, mut cells) = linear_rows(&public_parameters, dimensions);
+		cells[3].content[48..].copy_from_slice(&BlsScalar::from(7).to_bytes());
+		let region_size = RegionSize { rows: 2, cols: 4 };
+		let workers = WorkerPool::new(2, 8).unwrap();
+		let (verified, unverified) = verify(
+			dimensions,
+			&cells,
+			&commitments,
+			public_parameters,
+			region_size,
+			&workers,
+		)
+		.await
+		.unwrap();
+		assert_eq!(verified.len(), cells.len() - 1);
+		assert_eq!(unverified, vec![cells[3].position]);
+	}
+}
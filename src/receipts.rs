@@ -0,0 +1,235 @@
+//! Signed sampling receipts, as the proof of sampling work for the incentivized light clients and external audits.
+//!
+//! # Flow
+//!
+//! * On each verified block, receipt is created from the block [`AuditReport`], with the sampled cell coordinates
+//!   and verification results
+//! * Receipt is timestamped and signed with the client (Avail account) key, and stored in the database
+//! * Receipts of the block range are exported in batches with [`batch`] (served on `/v2/receipts`)
+//!
+//! # Notes
+//!
+//! Signed message is the SCALE encoded receipt, prefixed with the [`SIGNING_CONTEXT`], so receipts can't be replayed
+//! as signatures of the other messages. Receipts can be verified by anyone with [`SignedReceipt::verify`].
+
+use avail_subxt::utils::H256;
+use codec::{Decode, Encode};
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use serde::{Deserialize, Serialize};
+use sp_core::{sr25519, Pair};
+use std::{
+	ops::RangeInclusive,
+	time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+	audit::{self, AuditReport},
+	data::{Database, Key},
+	types::BlockVerified,
+};
+
+/// Prefix of the signed receipt message
+pub const SIGNING_CONTEXT: &[u8] = b"avail-light-sampling-receipt";
+
+/// Maximum number of receipts in a single batch
+pub const MAX_BATCH_SIZE: u32 = 1000;
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellVerification {
+	pub row: u32,
+	pub col: u16,
+	pub verified: bool,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct Receipt {
+	pub block_number: u32,
+	pub block_hash: H256,
+	pub cells: Vec<CellVerification>,
+	/// Time of the signing, in seconds since the Unix epoch
+	pub timestamp: u64,
+}
+
+impl Receipt {
+	pub fn new(report: &AuditReport, timestamp: u64) -> Self {
+		let cells = report
+			.cells
+			.iter()
+			.map(|cell| CellVerification {
+				row: cell.row,
+				col: cell.col,
+				verified: cell.source.is_some(),
+			})
+			.collect();
+		Receipt {
+			block_number: report.block_number,
+			block_hash: report.block_hash,
+			cells,
+			timestamp,
+		}
+	}
+
+	fn message(&self) -> Vec<u8> {
+		(SIGNING_CONTEXT, self).encode()
+	}
+
+	pub fn sign(self, pair: &sr25519::Pair) -> SignedReceipt {
+		let signature = pair.sign(&self.message());
+		SignedReceipt {
+			receipt: self,
+			signer: pair.public(),
+			signature,
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct SignedReceipt {
+	pub receipt: Receipt,
+	pub signer: sr25519::Public,
+	pub signature: sr25519::Signature,
+}
+
+impl SignedReceipt {
+	/// Verifies that the receipt is signed by the signer
+	pub fn verify(&self) -> bool {
+		sr25519::Pair::verify(&self.signature, self.receipt.message(), &self.signer)
+	}
+}
+
+pub fn store(db: &impl Database, receipt: SignedReceipt) -> Result<()> {
+	db.put(Key::SamplingReceipt(receipt.receipt.block_number), receipt)
+		.wrap_err("Failed to store sampling receipt")
+}
+
+/// Returns stored receipts of the block range, skipping blocks which are not sampled
+pub fn batch(db: &impl Database, blocks: RangeInclusive<u32>) -> Result<Vec<SignedReceipt>> {
+	let (from, to) = (*blocks.start(), *blocks.end());
+	if to < from || to - from >= MAX_BATCH_SIZE {
+		return Err(eyre!(
+			"Invalid batch range {from}..={to}, maximum size is {MAX_BATCH_SIZE}"
+		));
+	}
+	let mut receipts = vec![];
+	for block_number in blocks {
+		let receipt = db
+			.get(Key::SamplingReceipt(block_number))
+			.wrap_err("Failed to get sampling receipt")?;
+		receipts.extend(receipt);
+	}
+	Ok(receipts)
+}
+
+fn timestamp() -> Result<u64> {
+	Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+fn sign_block(db: &impl Database, pair: &sr25519::Pair, block_number: u32) -> Result<bool> {
+	let Some(report) = audit::load(db, block_number)? else {
+		return Ok(false);
+	};
+	store(db, Receipt::new(&report, timestamp()?).sign(pair))?;
+	Ok(true)
+}
+
+/// Signs receipts of the verified blocks
+pub async fn run(
+	db: impl Database,
+	pair: sr25519::Pair,
+	mut block_receiver: broadcast::Receiver<BlockVerified>,
+) {
+	info!(signer = %pair.public(), "Starting sampling receipts...");
+	loop {
+		let block = match block_receiver.recv().await {
+			Ok(block) => block,
+			Err(RecvError::Lagged(skipped)) => {
+				warn!(
+					skipped,
+					"Verified blocks receiver lagged, receipts are not signed"
+				);
+				continue;
+			},
+			Err(RecvError::Closed) => {
+				error!("Verified blocks channel closed");
+				return;
+			},
+		};
+		match sign_block(&db, &pair, block.block_num) {
+			Ok(true) => debug!(block_number = block.block_num, "Sampling receipt signed"),
+			Ok(false) => debug!(block_number = block.block_num, "Block is not sampled"),
+			Err(error) => error!(
+				block_number = block.block_num,
+				"Cannot sign sampling receipt: {error:#}"
+			),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		audit::{CellSource, SampledCell},
+		data::mem_db::MemoryDB,
+	};
+
+	fn report(block_number: u32) -> AuditReport {
+		let mut report = AuditReport::new(block_number, H256::repeat_byte(1));
+		report.cells = vec![
+			SampledCell {
+				row: 0,
+				col: 1,
+				source: Some(CellSource::Dht),
+			},
+			SampledCell {
+				row: 1,
+				col: 3,
+				source: None,
+			},
+		];
+		report
+	}
+
+	#[test]
+	fn signed_receipt() {
+		let (pair, _) = sr25519::Pair::generate();
+		let receipt = Receipt::new(&report(1), 1_700_000_000);
+		assert_eq!(
+			receipt
+				.cells
+				.iter()
+				.map(|cell| cell.verified)
+				.collect::<Vec<_>>(),
+			[true, false]
+		);
+		let mut signed = receipt.sign(&pair);
+		assert!(signed.verify());
+
+		signed.receipt.cells[1].verified = true;
+		assert!(!signed.verify());
+	}
+
+	#[test]
+	fn receipts_batch() {
+		let db = MemoryDB::default();
+		let (pair, _) = sr25519::Pair::generate();
+		for block_number in [1, 3] {
+			audit::store(&db, report(block_number)).unwrap();
+		}
+		for block_number in 1..=3 {
+			let signed = sign_block(&db, &pair, block_number).unwrap();
+			assert_eq!(signed, block_number != 2);
+		}
+
+		let receipts = batch(&db, 1..=5).unwrap();
+		let blocks = receipts.iter().map(|signed| signed.receipt.block_number);
+		assert_eq!(blocks.collect::<Vec<_>>(), [1, 3]);
+		assert!(receipts.iter().all(SignedReceipt::verify));
+		assert!(batch(&db, 1..=MAX_BATCH_SIZE + 1).is_err());
+	}
+}
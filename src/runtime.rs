@@ -0,0 +1,109 @@
+//! Executor and timer abstraction for the supervised tasks, so the embedders can choose the executor.
+//!
+//! Supervised background tasks are spawned, and restarts are delayed, through the [`Runtime`] (see
+//! [`crate::supervisor::Supervisor`]). Adapters are provided for tokio ([`TokioRuntime`], the default) and async-std
+//! ([`AsyncStdRuntime`]).
+//!
+//! # Notes
+//!
+//! Abstraction is limited to the supervisor. Networking (libp2p tokio transports), HTTP server (warp), RPC
+//! clients and their timeouts still depend on tokio, so the tokio runtime has to be running regardless of the
+//! executor of the supervised tasks. Browser (`wasm32`) targets are not supported.
+
+use futures::{
+	future::{self, Either},
+	Future, Stream,
+};
+use std::time::Duration;
+
+pub type RuntimeFuture<T> = futures::future::BoxFuture<'static, T>;
+
+pub trait Runtime: Clone + Send + Sync + 'static {
+	/// Spawns the future in the background, to run until completion
+	fn spawn(&self, future: RuntimeFuture<()>);
+
+	/// Returns future which completes after the duration
+	fn sleep(&self, duration: Duration) -> RuntimeFuture<()>;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioRuntime;
+
+impl Runtime for TokioRuntime {
+	fn spawn(&self, future: RuntimeFuture<()>) {
+		tokio::spawn(future);
+	}
+
+	fn sleep(&self, duration: Duration) -> RuntimeFuture<()> {
+		Box::pin(tokio::time::sleep(duration))
+	}
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AsyncStdRuntime;
+
+impl Runtime for AsyncStdRuntime {
+	fn spawn(&self, future: RuntimeFuture<()>) {
+		async_std::task::spawn(future);
+	}
+
+	fn sleep(&self, duration: Duration) -> RuntimeFuture<()> {
+		Box::pin(async_std::task::sleep(duration))
+	}
+}
+
+/// Awaits the future until the timeout, returning `None` if the timeout elapsed first
+pub async fn timeout<R: Runtime, F: Future>(
+	runtime: &R,
+	duration: Duration,
+	future: F,
+) -> Option<F::Output> {
+	futures::pin_mut!(future);
+	match future::select(future, runtime.sleep(duration)).await {
+		Either::Left((output, _)) => Some(output),
+		Either::Right(_) => None,
+	}
+}
+
+/// Stream which yields after every period, starting one period from now
+pub fn interval<R: Runtime>(runtime: R, period: Duration) -> impl Stream<Item = ()> {
+	futures::stream::unfold(runtime, move |runtime| async move {
+		runtime.sleep(period).await;
+		Some(((), runtime))
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::{channel::oneshot, StreamExt};
+
+	async fn spawn_and_wait(runtime: impl Runtime) {
+		let (sender, receiver) = oneshot::channel();
+		let task_runtime = runtime.clone();
+		runtime.spawn(Box::pin(async move {
+			task_runtime.sleep(Duration::from_millis(1)).await;
+			_ = sender.send(());
+		}));
+		let received = timeout(&runtime, Duration::from_secs(5), receiver).await;
+		assert!(matches!(received, Some(Ok(()))));
+
+		let pending = future::pending::<()>();
+		assert!(timeout(&runtime, Duration::from_millis(1), pending)
+			.await
+			.is_none());
+
+		let ticks = interval(runtime, Duration::from_millis(1));
+		assert_eq!(ticks.take(3).count().await, 3);
+	}
+
+	#[tokio::test]
+	async fn tokio_runtime() {
+		spawn_and_wait(TokioRuntime).await;
+	}
+
+	#[async_std::test]
+	async fn async_std_runtime() {
+		spawn_and_wait(AsyncStdRuntime).await;
+	}
+}
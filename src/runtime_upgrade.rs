@@ -0,0 +1,250 @@
+//! Detection of runtime upgrades, with metadata hot-reload.
+//!
+//! # Flow
+//!
+//! * For each finalized header, check for `RuntimeEnvironmentUpdated` digest item
+//! * Compare `:code` storage hash and `System.LastRuntimeUpgrade` spec version with the last known runtime
+//! * On upgrade, refetch runtime version and metadata, re-derive call indices and invalidate caches
+//! * Emit [`RuntimeUpdated`] event
+//!
+//! # Notes
+//!
+//! Digest item alone is not sufficient, since it is also emitted on heap pages changes, and it can be missed
+//! if finalized headers are skipped, so storage is checked on every finalized header.
+
+use avail_subxt::{config::substrate::DigestItem, primitives::Header, utils::H256};
+use codec::{Compact, Decode, Encode};
+use color_eyre::{eyre::eyre, Result};
+use sp_core::{blake2_256, twox_128};
+use std::sync::Arc;
+use subxt::Metadata;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, info, warn};
+
+use crate::{
+	cache::Caches,
+	network::rpc::{Client as RpcClient, Event},
+};
+
+/// Storage key of the runtime code
+pub const CODE_KEY: &[u8] = b":code";
+
+/// Storage key of the `System.LastRuntimeUpgrade`
+pub fn last_runtime_upgrade_key() -> Vec<u8> {
+	[twox_128(b"System"), twox_128(b"LastRuntimeUpgrade")].concat()
+}
+
+/// Decoded `System.LastRuntimeUpgrade` storage value
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct LastRuntimeUpgrade {
+	pub spec_version: Compact<u32>,
+	pub spec_name: String,
+}
+
+/// Call indices used by the light client, derived from the runtime metadata
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallIndices {
+	/// `DataAvailability.submit_data` pallet and call index
+	pub submit_data: (u8, u8),
+	/// `DataAvailability.create_application_key` pallet and call index
+	pub create_application_key: (u8, u8),
+}
+
+/// Returns pallet and call index of the call
+pub(crate) fn call_index(metadata: &Metadata, pallet: &str, call: &str) -> Result<(u8, u8)> {
+	let pallet_metadata = metadata
+		.pallet_by_name(pallet)
+		.ok_or_else(|| eyre!("Pallet {pallet} is not found in metadata"))?;
+	let variant = pallet_metadata
+		.call_variant_by_name(call)
+		.ok_or_else(|| eyre!("Call {pallet}.{call} is not found in metadata"))?;
+	Ok((pallet_metadata.index(), variant.index))
+}
+
+impl CallIndices {
+	pub fn from_metadata(metadata: &Metadata) -> Result<Self> {
+		Ok(CallIndices {
+			submit_data: call_index(metadata, "DataAvailability", "submit_data")?,
+			create_application_key: call_index(
+				metadata,
+				"DataAvailability",
+				"create_application_key",
+			)?,
+		})
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuntimeUpdated {
+	pub block_number: u32,
+	pub block_hash: H256,
+	pub spec_version: u32,
+	pub transaction_version: u32,
+	pub call_indices: CallIndices,
+}
+
+/// Checks if header contains `RuntimeEnvironmentUpdated` digest item
+pub fn has_runtime_environment_updated(header: &Header) -> bool {
+	header
+		.digest
+		.logs
+		.iter()
+		.any(|log| matches!(log, DigestItem::RuntimeEnvironmentUpdated))
+}
+
+/// Last known runtime, compared with the runtime at each finalized block
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RuntimeMonitor {
+	spec_version: Option<u32>,
+	code_hash: Option<H256>,
+}
+
+impl RuntimeMonitor {
+	/// Checks runtime at the block, and returns `true` if runtime is upgraded since the last check.
+	/// First check only initializes the monitor.
+	pub fn check(
+		&mut self,
+		environment_updated: bool,
+		code_hash: Option<H256>,
+		last_upgrade: Option<&LastRuntimeUpgrade>,
+	) -> bool {
+		let spec_version = last_upgrade.map(|upgrade| upgrade.spec_version.0);
+		let initialized = self.code_hash.is_some() || self.spec_version.is_some();
+		let code_changed = code_hash.is_some() && self.code_hash != code_hash;
+		let spec_changed = spec_version.is_some() && self.spec_version != spec_version;
+
+		self.code_hash = code_hash.or(self.code_hash);
+		self.spec_version = spec_version.or(self.spec_version);
+
+		initialized && (code_changed || spec_changed || environment_updated)
+	}
+}
+
+async fn check_block(
+	rpc_client: &RpcClient,
+	monitor: &mut RuntimeMonitor,
+	header: &Header,
+) -> Result<Option<RuntimeUpdated>> {
+	let block_hash: H256 = Encode::using_encoded(header, blake2_256).into();
+	let code_hash = rpc_client
+		.get_storage_hash(CODE_KEY.to_vec(), block_hash)
+		.await?;
+	let last_upgrade = rpc_client
+		.get_storage_value(last_runtime_upgrade_key(), block_hash)
+		.await?
+		.map(|value| LastRuntimeUpgrade::decode(&mut &value[..]))
+		.transpose()?;
+
+	let environment_updated = has_runtime_environment_updated(header);
+	if !monitor.check(environment_updated, code_hash, last_upgrade.as_ref()) {
+		return Ok(None);
+	}
+
+	let (runtime_version, metadata) = rpc_client.update_runtime().await?;
+	Ok(Some(RuntimeUpdated {
+		block_number: header.number,
+		block_hash,
+		spec_version: runtime_version.spec_version,
+		transaction_version: runtime_version.transaction_version,
+		call_indices: CallIndices::from_metadata(&metadata)?,
+	}))
+}
+
+/// Monitors finalized headers for runtime upgrades, and sends [`RuntimeUpdated`] notifications.
+///
+/// # Arguments
+///
+/// * `rpc_client` - RPC client, which is updated with the new runtime metadata
+/// * `caches` - Optional caches, invalidated after the upgrade
+/// * `rpc_events` - GRANDPA verified headers
+/// * `sender` - Notifications sender
+pub async fn run(
+	rpc_client: RpcClient,
+	caches: Option<Arc<Caches>>,
+	mut rpc_events: broadcast::Receiver<Event>,
+	sender: broadcast::Sender<RuntimeUpdated>,
+) {
+	info!("Starting runtime upgrade monitor...");
+	let mut monitor = RuntimeMonitor::default();
+
+	loop {
+		let header = match rpc_events.recv().await {
+			Ok(Event::HeaderUpdate { header, .. }) => header,
+			Err(RecvError::Lagged(skipped)) => {
+				warn!(skipped, "Finalized headers receiver lagged");
+				continue;
+			},
+			Err(RecvError::Closed) => {
+				error!("Finalized headers channel closed");
+				return;
+			},
+		};
+
+		let block_number = header.number;
+		let updated = match check_block(&rpc_client, &mut monitor, &header).await {
+			Ok(Some(updated)) => updated,
+			Ok(None) => continue,
+			Err(error) => {
+				error!(block_number, "Failed to check runtime upgrade: {error:#}");
+				continue;
+			},
+		};
+
+		if let Some(caches) = caches.as_ref() {
+			caches.clear();
+		}
+
+		info!(
+			block_number,
+			spec_version = updated.spec_version,
+			transaction_version = updated.transaction_version,
+			"Runtime updated"
+		);
+
+		// Sending fails only if there are no receivers, which is not an error
+		let _ = sender.send(updated);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use test_case::test_case;
+
+	fn upgrade(spec_version: u32) -> LastRuntimeUpgrade {
+		LastRuntimeUpgrade {
+			spec_version: Compact(spec_version),
+			spec_name: "avail".to_string(),
+		}
+	}
+
+	#[test]
+	fn decode_last_runtime_upgrade() {
+		let encoded = (Compact(13u32), "avail".to_string()).encode();
+		assert_eq!(
+			LastRuntimeUpgrade::decode(&mut &encoded[..]).unwrap(),
+			upgrade(13)
+		);
+	}
+
+	#[test_case(false, Some(H256::repeat_byte(1)), Some(upgrade(1)) => false ; "same runtime")]
+	#[test_case(true, Some(H256::repeat_byte(1)), Some(upgrade(1)) => true ; "environment updated")]
+	#[test_case(false, Some(H256::repeat_byte(2)), Some(upgrade(1)) => true ; "code changed")]
+	#[test_case(false, Some(H256::repeat_byte(1)), Some(upgrade(2)) => true ; "spec version changed")]
+	#[test_case(false, None, None => false ; "storage not available")]
+	fn check_upgrade(
+		environment_updated: bool,
+		code_hash: Option<H256>,
+		last_upgrade: Option<LastRuntimeUpgrade>,
+	) -> bool {
+		let mut monitor = RuntimeMonitor::default();
+		assert!(!monitor.check(false, Some(H256::repeat_byte(1)), Some(&upgrade(1))));
+		monitor.check(environment_updated, code_hash, last_upgrade.as_ref())
+	}
+
+	#[test]
+	fn first_check_initializes() {
+		let mut monitor = RuntimeMonitor::default();
+		assert!(!monitor.check(true, Some(H256::repeat_byte(1)), Some(&upgrade(1))));
+	}
+}
@@ -0,0 +1,241 @@
+//! Pluggable sampling policy of the light client.
+//!
+//! Sampling policy consists of the [`SamplingStrategy`], which selects positions of the sampled cells, and the
+//! [`ConfidenceModel`], which maps required confidence to the number of samples and verified samples back to the
+//! achieved confidence. Defaults are random sampling, and confidence of `1 - (1/2)^k` for `k` verified cells.
+
+use avail_core::{AppId, DataLookup};
+use kate_recovery::{
+	com::app_specific_rows,
+	matrix::{Dimensions, Position},
+};
+use rand::{seq::index, thread_rng, Rng};
+use std::{collections::HashSet, sync::Arc};
+
+use crate::{
+	network::rpc::{self, CELL_COUNT_99_99},
+	types::{RuntimeConfig, SamplingMode},
+};
+
+pub trait SamplingStrategy: Send + Sync {
+	/// Selects up to `cell_count` distinct positions to sample
+	fn positions(
+		&self,
+		dimensions: Dimensions,
+		lookup: &DataLookup,
+		cell_count: u32,
+	) -> Vec<Position>;
+}
+
+pub trait ConfidenceModel: Send + Sync {
+	/// Number of cells required to achieve confidence (in percents)
+	fn cell_count(&self, confidence: f64) -> u32;
+	/// Confidence (in percents) achieved with given number of verified cells
+	fn confidence(&self, verified: u32) -> f64;
+}
+
+/// Uniform random sampling of the extended matrix
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RandomSampling;
+
+impl SamplingStrategy for RandomSampling {
+	fn positions(&self, dimensions: Dimensions, _: &DataLookup, cell_count: u32) -> Vec<Position> {
+		rpc::generate_random_cells(dimensions, cell_count)
+	}
+}
+
+/// Samples cells from random rows, with `cells_per_row` random cells per row, so they can be fetched as rows
+#[derive(Clone, Copy, Debug)]
+pub struct RowSampling {
+	pub cells_per_row: u16,
+}
+
+fn sample_rows(
+	dimensions: Dimensions,
+	rows: &[u32],
+	cells_per_row: u16,
+	cell_count: u32,
+) -> Vec<Position> {
+	let mut rng = thread_rng();
+	let cols = dimensions.cols().get();
+	let cells_per_row = cells_per_row.clamp(1, cols);
+	let rows_count = (cell_count as usize).div_ceil(cells_per_row as usize);
+
+	index::sample(&mut rng, rows.len(), rows_count.min(rows.len()))
+		.into_iter()
+		.flat_map(|i| {
+			let row = rows[i];
+			index::sample(&mut rng, cols as usize, cells_per_row as usize)
+				.into_iter()
+				.map(move |col| Position {
+					row,
+					col: col as u16,
+				})
+				.collect::<Vec<_>>()
+		})
+		.take(cell_count as usize)
+		.collect()
+}
+
+impl SamplingStrategy for RowSampling {
+	fn positions(&self, dimensions: Dimensions, _: &DataLookup, cell_count: u32) -> Vec<Position> {
+		let rows = (0..dimensions.extended_rows()).collect::<Vec<_>>();
+		sample_rows(dimensions, &rows, self.cells_per_row, cell_count)
+	}
+}
+
+/// Samples half of the cells from the rows of the application, and the rest randomly.
+/// Falls back to random sampling if block contains no data of the application.
+#[derive(Clone, Copy, Debug)]
+pub struct AppSampling {
+	pub app_id: AppId,
+}
+
+impl SamplingStrategy for AppSampling {
+	fn positions(
+		&self,
+		dimensions: Dimensions,
+		lookup: &DataLookup,
+		cell_count: u32,
+	) -> Vec<Position> {
+		let app_rows = app_specific_rows(lookup, dimensions, self.app_id);
+		if app_rows.is_empty() {
+			return RandomSampling.positions(dimensions, lookup, cell_count);
+		}
+
+		let targeted = cell_count.div_ceil(2);
+		let mut positions = sample_rows(dimensions, &app_rows, 1, targeted)
+			.into_iter()
+			.collect::<HashSet<_>>();
+
+		let mut rng = thread_rng();
+		let max_cells = dimensions.extended_size().min(cell_count) as usize;
+		while positions.len() < max_cells {
+			let row = rng.gen_range(0..dimensions.extended_rows());
+			let col = rng.gen_range(0..dimensions.cols().get());
+			positions.insert(Position { row, col });
+		}
+		positions.into_iter().collect()
+	}
+}
+
+/// Confidence of `1 - p^k`, where `p` is the probability that a single sample misses unavailable data
+#[derive(Clone, Copy, Debug)]
+pub struct ExponentialConfidence {
+	pub miss_probability: f64,
+	pub max_cells: u32,
+}
+
+impl Default for ExponentialConfidence {
+	fn default() -> Self {
+		ExponentialConfidence {
+			miss_probability: 0.5,
+			max_cells: CELL_COUNT_99_99,
+		}
+	}
+}
+
+impl ConfidenceModel for ExponentialConfidence {
+	fn cell_count(&self, confidence: f64) -> u32 {
+		if self.miss_probability == 0.5 {
+			return rpc::cell_count_for_confidence(confidence).min(self.max_cells);
+		}
+		let confidence = confidence.clamp(50.0, 99.99) / 100.0;
+		let cell_count = ((1.0 - confidence).ln() / self.miss_probability.ln()).ceil() as u32;
+		cell_count.clamp(1, self.max_cells)
+	}
+
+	fn confidence(&self, verified: u32) -> f64 {
+		100f64 * (1f64 - self.miss_probability.powi(verified as i32))
+	}
+}
+
+#[derive(Clone)]
+pub struct SamplingPolicy {
+	pub strategy: Arc<dyn SamplingStrategy>,
+	pub confidence: Arc<dyn ConfidenceModel>,
+}
+
+impl Default for SamplingPolicy {
+	fn default() -> Self {
+		SamplingPolicy {
+			strategy: Arc::new(RandomSampling),
+			confidence: Arc::new(ExponentialConfidence::default()),
+		}
+	}
+}
+
+impl From<&RuntimeConfig> for SamplingPolicy {
+	fn from(cfg: &RuntimeConfig) -> Self {
+		let strategy: Arc<dyn SamplingStrategy> = match (cfg.sampling_mode, cfg.app_id) {
+			(SamplingMode::Rows, _) => Arc::new(RowSampling {
+				cells_per_row: cfg.sampling_cells_per_row,
+			}),
+			(SamplingMode::App, Some(app_id)) => Arc::new(AppSampling {
+				app_id: AppId(app_id),
+			}),
+			_ => Arc::new(RandomSampling),
+		};
+		let confidence = ExponentialConfidence {
+			miss_probability: cfg.sample_miss_probability,
+			..Default::default()
+		};
+		SamplingPolicy {
+			strategy,
+			confidence: Arc::new(confidence),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::utils::calculate_confidence;
+	use test_case::test_case;
+
+	fn lookup() -> DataLookup {
+		DataLookup::from_id_and_len_iter([(0, 2), (1, 6)].into_iter()).unwrap()
+	}
+
+	#[test_case(&RandomSampling ; "random")]
+	#[test_case(&RowSampling { cells_per_row: 4 } ; "rows")]
+	#[test_case(&AppSampling { app_id: AppId(1) } ; "app")]
+	#[test_case(&AppSampling { app_id: AppId(7) } ; "missing app")]
+	fn distinct_positions(strategy: &dyn SamplingStrategy) {
+		let dimensions = Dimensions::new(4, 16).unwrap();
+		let positions = strategy.positions(dimensions, &lookup(), 10);
+		assert_eq!(positions.len(), 10);
+		assert_eq!(positions.iter().collect::<HashSet<_>>().len(), 10);
+		assert!(positions
+			.iter()
+			.all(|p| p.row < dimensions.extended_rows() && p.col < dimensions.cols().get()));
+	}
+
+	#[test]
+	fn row_sampling_uses_few_rows() {
+		let dimensions = Dimensions::new(8, 16).unwrap();
+		let strategy = RowSampling { cells_per_row: 4 };
+		let positions = strategy.positions(dimensions, &lookup(), 8);
+		let rows = positions.iter().map(|p| p.row).collect::<HashSet<_>>();
+		assert_eq!(rows.len(), 2);
+	}
+
+	#[test_case(0.5, 99.9 => 10 ; "halving")]
+	#[test_case(0.25, 99.9 => 5 ; "quarter")]
+	#[test_case(0.25, 100.0 => 7 ; "clamped confidence")]
+	fn cell_count(miss_probability: f64, confidence: f64) -> u32 {
+		let model = ExponentialConfidence {
+			miss_probability,
+			..Default::default()
+		};
+		model.cell_count(confidence)
+	}
+
+	#[test]
+	fn default_confidence_matches_halving() {
+		let model = ExponentialConfidence::default();
+		for verified in 0..CELL_COUNT_99_99 {
+			assert_eq!(model.confidence(verified), calculate_confidence(verified));
+		}
+	}
+}
@@ -0,0 +1,233 @@
+//! Session key rotation helpers for validators.
+//!
+//! Avail session keys are BABE, GRANDPA, ImOnline and AuthorityDiscovery public keys, encoded in this order. Key
+//! bundle is derived from a single secret phrase with hard derivation paths (`//babe`, `//grandpa`, `//im_online` and
+//! `//authority_discovery`), so all keys can be restored into the node keystore from the phrase.
+//!
+//! # Rotation
+//!
+//! * Generate key bundle with [`SessionKeyPairs::generate`], and insert the keys into the node keystore
+//! * Submit `Session.set_keys` call created with [`set_keys_call`], signed by the validator account
+//! * Once included, keys are stored in `Session::NextKeys`, which is checked against the local keys with
+//!   [`verify_next_keys`]. Keys are active from the next session.
+//!
+//! Ownership proof is the SCALE encoded tuple of signatures of the validator account ID by each of the keys.
+//! Runtimes which don't check the ownership proof accept it as well.
+
+use avail_subxt::utils::H256;
+use bip39::{Language, Mnemonic, MnemonicType};
+use codec::{Decode, Encode};
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use sp_core::{ed25519, sr25519, twox_128, twox_64, Pair};
+use subxt::Metadata;
+
+use crate::{extrinsic::calls::CallIndex, network::rpc::Client as RpcClient};
+
+/// Public session keys, encoded as the runtime `SessionKeys`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct SessionKeys {
+	pub babe: [u8; 32],
+	pub grandpa: [u8; 32],
+	pub im_online: [u8; 32],
+	pub authority_discovery: [u8; 32],
+}
+
+/// Session key pairs, derived from the secret phrase
+#[derive(Clone)]
+pub struct SessionKeyPairs {
+	pub babe: sr25519::Pair,
+	pub grandpa: ed25519::Pair,
+	pub im_online: sr25519::Pair,
+	pub authority_discovery: sr25519::Pair,
+}
+
+fn derive<P: Pair>(phrase: &str, path: &str, password: Option<&str>) -> Result<P> {
+	P::from_string(&format!("{phrase}//{path}"), password)
+		.map_err(|error| eyre!("Cannot derive {path} session key: {error:?}"))
+}
+
+impl SessionKeyPairs {
+	/// Generates key pairs from the new random phrase, which is returned for the backup
+	pub fn generate(password: Option<&str>) -> Result<(Self, String)> {
+		let phrase = Mnemonic::new(MnemonicType::Words24, Language::English).into_phrase();
+		let pairs = Self::from_phrase(&phrase, password)?;
+		Ok((pairs, phrase))
+	}
+
+	pub fn from_phrase(phrase: &str, password: Option<&str>) -> Result<Self> {
+		Ok(SessionKeyPairs {
+			babe: derive(phrase, "babe", password)?,
+			grandpa: derive(phrase, "grandpa", password)?,
+			im_online: derive(phrase, "im_online", password)?,
+			authority_discovery: derive(phrase, "authority_discovery", password)?,
+		})
+	}
+
+	pub fn public(&self) -> SessionKeys {
+		SessionKeys {
+			babe: self.babe.public().0,
+			grandpa: self.grandpa.public().0,
+			im_online: self.im_online.public().0,
+			authority_discovery: self.authority_discovery.public().0,
+		}
+	}
+
+	/// Proves ownership of all keys, by signing the validator account ID with each key
+	pub fn ownership_proof(&self, owner: &[u8; 32]) -> Vec<u8> {
+		(
+			self.babe.sign(owner),
+			self.grandpa.sign(owner),
+			self.im_online.sign(owner),
+			self.authority_discovery.sign(owner),
+		)
+			.encode()
+	}
+}
+
+/// Verifies ownership proof of the session keys for the validator account ID
+pub fn verify_ownership_proof(keys: &SessionKeys, owner: &[u8; 32], proof: &[u8]) -> bool {
+	type Signatures = (
+		sr25519::Signature,
+		ed25519::Signature,
+		sr25519::Signature,
+		sr25519::Signature,
+	);
+	let Ok((babe, grandpa, im_online, authority_discovery)) = Signatures::decode(&mut &proof[..])
+	else {
+		return false;
+	};
+	let sr25519_verify = |signature: &sr25519::Signature, key: [u8; 32]| {
+		sr25519::Pair::verify(signature, owner, &sr25519::Public::from_raw(key))
+	};
+	sr25519_verify(&babe, keys.babe)
+		&& ed25519::Pair::verify(&grandpa, owner, &ed25519::Public::from_raw(keys.grandpa))
+		&& sr25519_verify(&im_online, keys.im_online)
+		&& sr25519_verify(&authority_discovery, keys.authority_discovery)
+}
+
+/// Index of the `Session.set_keys` call in the current runtime
+pub fn set_keys_call_index(metadata: &Metadata) -> Result<CallIndex> {
+	CallIndex::from_metadata(metadata, "Session", "set_keys")
+}
+
+/// Encodes `Session.set_keys` call, with the ownership proof of the keys
+pub fn set_keys_call(index: CallIndex, keys: &SessionKeys, proof: &[u8]) -> Vec<u8> {
+	let mut encoded = index.encode();
+	keys.encode_to(&mut encoded);
+	proof.encode_to(&mut encoded);
+	encoded
+}
+
+/// Storage key of the `Session::NextKeys` entry of the validator
+pub fn next_keys_storage_key(validator: &[u8; 32]) -> Vec<u8> {
+	[
+		&twox_128(b"Session")[..],
+		&twox_128(b"NextKeys")[..],
+		&twox_64(validator)[..],
+		&validator[..],
+	]
+	.concat()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NextKeysStatus {
+	/// On-chain keys match the local keys
+	Matching,
+	/// Validator has no keys set
+	NotSet,
+	/// On-chain keys are different, e.g. if keys were rotated from another keystore
+	Mismatch { on_chain: SessionKeys },
+}
+
+/// Compares SCALE encoded `Session::NextKeys` storage value with the local keys
+pub fn check_next_keys(stored: Option<&[u8]>, local: &SessionKeys) -> Result<NextKeysStatus> {
+	let Some(mut stored) = stored else {
+		return Ok(NextKeysStatus::NotSet);
+	};
+	let on_chain =
+		SessionKeys::decode(&mut stored).wrap_err("Failed to decode on-chain session keys")?;
+	if on_chain == *local {
+		return Ok(NextKeysStatus::Matching);
+	}
+	Ok(NextKeysStatus::Mismatch { on_chain })
+}
+
+/// Fetches `Session::NextKeys` of the validator at the given block, and compares them with the local keys
+pub async fn verify_next_keys(
+	rpc_client: &RpcClient,
+	block_hash: H256,
+	validator: &[u8; 32],
+	local: &SessionKeys,
+) -> Result<NextKeysStatus> {
+	let stored = rpc_client
+		.get_storage_value(next_keys_storage_key(validator), block_hash)
+		.await
+		.wrap_err("Failed to get on-chain session keys")?;
+	check_next_keys(stored.as_deref(), local)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const PHRASE: &str = "bottom drive obey lake curtain smoke basket hold race lonely fit walk";
+
+	#[test]
+	fn derive_keys_from_phrase() {
+		let first = SessionKeyPairs::from_phrase(PHRASE, None).unwrap().public();
+		let second = SessionKeyPairs::from_phrase(PHRASE, None).unwrap().public();
+		assert_eq!(first, second);
+		assert_ne!(first.babe, first.im_online);
+		assert_ne!(
+			first,
+			SessionKeyPairs::from_phrase(PHRASE, Some("password"))
+				.unwrap()
+				.public()
+		);
+	}
+
+	#[test]
+	fn ownership_proof() {
+		let (pairs, _) = SessionKeyPairs::generate(None).unwrap();
+		let keys = pairs.public();
+		let proof = pairs.ownership_proof(&[1; 32]);
+		assert!(verify_ownership_proof(&keys, &[1; 32], &proof));
+		assert!(!verify_ownership_proof(&keys, &[2; 32], &proof));
+		assert!(!verify_ownership_proof(&keys, &[1; 32], &proof[1..]));
+	}
+
+	#[test]
+	fn encode_set_keys() {
+		let keys = SessionKeyPairs::from_phrase(PHRASE, None).unwrap().public();
+		let call = set_keys_call(CallIndex(9, 0), &keys, &[1, 2]);
+		assert_eq!(call[..2], [9, 0]);
+		assert_eq!(call[2..34], keys.babe);
+		assert_eq!(call[34..66], keys.grandpa);
+		assert_eq!(call[130..], [8, 1, 2]);
+	}
+
+	#[test]
+	fn next_keys() {
+		let keys = SessionKeyPairs::from_phrase(PHRASE, None).unwrap().public();
+		let other = SessionKeys {
+			babe: [1; 32],
+			..keys
+		};
+		assert_eq!(
+			check_next_keys(None, &keys).unwrap(),
+			NextKeysStatus::NotSet
+		);
+		assert_eq!(
+			check_next_keys(Some(&keys.encode()), &keys).unwrap(),
+			NextKeysStatus::Matching
+		);
+		assert_eq!(
+			check_next_keys(Some(&other.encode()), &keys).unwrap(),
+			NextKeysStatus::Mismatch { on_chain: other }
+		);
+		assert!(check_next_keys(Some(&[0; 8]), &keys).is_err());
+	}
+}
@@ -0,0 +1,443 @@
+//! Deterministic simulation of the sync, driving the [`SyncMachine`] with simulated peers on a virtual clock.
+//!
+//! Peers are connected over the simulated links with configurable latency, jitter and packet loss, and can behave
+//! maliciously (see [`Behavior`]). All randomness comes from the seeded generator, and time only advances with
+//! [`Simulation::run_for`], so the same scenario with the same seed always produces the same [`Action`]s. This allows
+//! reproducible regression tests of the sync edge cases (lost responses, unresponsive or lying peers, deep forks).
+
+use avail_subxt::{
+	api::runtime_types::avail_core::{
+		data_lookup::compact::CompactDataLookup,
+		header::extension::{v3, HeaderExtension},
+		kate_commitment::v3::KateCommitment,
+	},
+	config::substrate::{Digest, DigestItem},
+	primitives::Header,
+	utils::H256,
+};
+use codec::Encode;
+use libp2p::{identity::Keypair, PeerId};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::{
+	collections::{BTreeMap, HashMap},
+	time::{Duration, Instant},
+};
+
+use crate::{
+	fork_choice::{BlockInfo, LongestChain},
+	header::{consistency::ExtensionLimits, HeaderHash},
+	network::p2p::block_announce::BlockAnnounce,
+	sync_machine::{Action, Input, SyncMachine, TICK_INTERVAL},
+};
+
+/// Simulated network link between the peer and the client
+#[derive(Clone, Copy, Debug)]
+pub struct Link {
+	pub latency: Duration,
+	/// Maximum random delay, added to the latency
+	pub jitter: Duration,
+	/// Probability that the message is lost
+	pub loss: f64,
+}
+
+impl Default for Link {
+	fn default() -> Self {
+		Link {
+			latency: Duration::from_millis(50),
+			jitter: Duration::ZERO,
+			loss: 0.0,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Behavior {
+	Honest,
+	/// Never responds to the requests
+	Silent,
+	/// Responds with the other header than requested
+	WrongHeaders,
+	/// Announces malformed headers
+	InvalidAnnounces,
+}
+
+struct Peer {
+	behavior: Behavior,
+	link: Link,
+	headers: HashMap<H256, Header>,
+	best_number: u32,
+}
+
+/// Sealed header with the valid extension, forks are distinguished by the state root
+pub fn header(number: u32, parent_hash: H256, fork: u8) -> Header {
+	Header {
+		parent_hash,
+		number,
+		state_root: H256::repeat_byte(fork),
+		extrinsics_root: H256::zero(),
+		digest: Digest {
+			logs: vec![DigestItem::Seal(*b"BABE", vec![1; 64])],
+		},
+		extension: HeaderExtension::V3(v3::HeaderExtension {
+			commitment: KateCommitment {
+				rows: 1,
+				cols: 4,
+				data_root: H256::zero(),
+				commitment: vec![0; 2 * 48],
+			},
+			app_lookup: CompactDataLookup {
+				size: 0,
+				index: vec![],
+			},
+		}),
+	}
+}
+
+/// Extends the chain from the parent header with the given number of headers
+pub fn chain(parent: &Header, len: u32, fork: u8) -> Vec<Header> {
+	let mut headers = vec![];
+	let mut parent = parent.clone();
+	for _ in 0..len {
+		let child = header(parent.number + 1, parent.header_hash(), fork);
+		headers.push(child.clone());
+		parent = child;
+	}
+	headers
+}
+
+pub struct Simulation {
+	rng: ChaCha8Rng,
+	start: Instant,
+	elapsed: Duration,
+	sequence: u64,
+	/// Inputs to the machine, by the delivery time
+	events: BTreeMap<(Duration, u64), Input>,
+	machine: SyncMachine,
+	peers: HashMap<PeerId, Peer>,
+	actions: Vec<(Duration, Action)>,
+}
+
+impl Simulation {
+	pub fn new(seed: u64, genesis: &Header) -> Self {
+		let finalized = BlockInfo {
+			hash: genesis.header_hash(),
+			number: genesis.number,
+			parent_hash: genesis.parent_hash,
+			confidence: None,
+		};
+		let machine = SyncMachine::new(
+			finalized,
+			Box::new(LongestChain),
+			ExtensionLimits::default(),
+		);
+		let mut simulation = Simulation {
+			rng: ChaCha8Rng::seed_from_u64(seed),
+			start: Instant::now(),
+			elapsed: Duration::ZERO,
+			sequence: 0,
+			events: BTreeMap::new(),
+			machine,
+			peers: HashMap::new(),
+			actions: vec![],
+		};
+		simulation.schedule(TICK_INTERVAL, Input::Tick);
+		simulation
+	}
+
+	pub fn machine(&self) -> &SyncMachine {
+		&self.machine
+	}
+
+	/// Actions returned by the machine, with the virtual time
+	pub fn actions(&self) -> &[(Duration, Action)] {
+		&self.actions
+	}
+
+	pub fn elapsed(&self) -> Duration {
+		self.elapsed
+	}
+
+	pub fn is_connected(&self, peer_id: &PeerId) -> bool {
+		self.peers.contains_key(peer_id)
+	}
+
+	/// Hash and number of the best block of the machine
+	pub fn best(&self) -> Option<(H256, u32)> {
+		let best = self.machine.tree().best_block()?;
+		Some((best.hash, best.number))
+	}
+
+	fn schedule(&mut self, delay: Duration, input: Input) {
+		self.sequence += 1;
+		self.events
+			.insert((self.elapsed + delay, self.sequence), input);
+	}
+
+	/// Returns link delay, or `None` if the message is lost
+	fn transmit(&mut self, link: Link) -> Option<Duration> {
+		if self.rng.gen_bool(link.loss) {
+			return None;
+		}
+		Some(link.latency + link.jitter.mul_f64(self.rng.gen::<f64>()))
+	}
+
+	/// Adds peer with the given headers, which connects after the link latency
+	pub fn add_peer(&mut self, behavior: Behavior, link: Link, headers: &[Header]) -> PeerId {
+		// Peer IDs are derived from the seeded generator, since peer selection depends on their order
+		let peer_id = Keypair::ed25519_from_bytes(self.rng.gen::<[u8; 32]>())
+			.expect("Key length is valid")
+			.public()
+			.to_peer_id();
+		let peer = Peer {
+			behavior,
+			link,
+			headers: HashMap::new(),
+			best_number: 0,
+		};
+		self.peers.insert(peer_id, peer);
+		self.add_headers(peer_id, headers, false);
+		let best_number = self.peers[&peer_id].best_number;
+		let input = Input::PeerConnected {
+			peer_id,
+			best_number,
+		};
+		self.schedule(link.latency, input);
+		peer_id
+	}
+
+	fn add_headers(&mut self, peer_id: PeerId, headers: &[Header], announce: bool) {
+		let Some(peer) = self.peers.get_mut(&peer_id) else {
+			return;
+		};
+		for header in headers {
+			peer.best_number = peer.best_number.max(header.number);
+			peer.headers.insert(header.header_hash(), header.clone());
+		}
+		let (behavior, link) = (peer.behavior, peer.link);
+		let Some(best) = headers.last().filter(|_| announce) else {
+			return;
+		};
+		let header = match behavior {
+			Behavior::InvalidAnnounces => vec![0; 8],
+			_ => best.encode(),
+		};
+		if let Some(delay) = self.transmit(link) {
+			let announce = BlockAnnounce {
+				header,
+				is_best: true,
+			};
+			self.schedule(delay, Input::Announce { peer_id, announce });
+		}
+	}
+
+	/// Peer imports the headers, and announces the last one
+	pub fn import(&mut self, peer_id: PeerId, headers: &[Header]) {
+		self.add_headers(peer_id, headers, true);
+	}
+
+	/// Finalizes the block, as if the justification was received
+	pub fn finalize(&mut self, hash: H256) {
+		self.schedule(Duration::ZERO, Input::Finalized { hash });
+	}
+
+	/// Advances virtual time, delivering scheduled inputs and executing the returned actions
+	pub fn run_for(&mut self, duration: Duration) {
+		let until = self.elapsed + duration;
+		while let Some(entry) = self.events.first_entry() {
+			if entry.key().0 > until {
+				break;
+			}
+			let ((elapsed, _), input) = entry.remove_entry();
+			self.elapsed = elapsed;
+			if matches!(input, Input::Tick) {
+				self.schedule(TICK_INTERVAL, Input::Tick);
+			}
+			let actions = self.machine.handle(self.start + elapsed, input);
+			for action in actions {
+				self.execute(&action);
+				self.actions.push((elapsed, action));
+			}
+		}
+		self.elapsed = until;
+	}
+
+	fn execute(&mut self, action: &Action) {
+		match action {
+			Action::RequestHeader {
+				request_id,
+				peer_id,
+				hash,
+			} => {
+				let Some(peer) = self.peers.get(peer_id) else {
+					return;
+				};
+				let header = match peer.behavior {
+					Behavior::Silent => return,
+					Behavior::WrongHeaders => peer
+						.headers
+						.values()
+						.filter(|header| header.header_hash() != *hash)
+						.min_by_key(|header| header.number)
+						.map(Encode::encode),
+					_ => peer.headers.get(hash).map(Encode::encode),
+				};
+				let link = peer.link;
+				// Both the request and the response can be lost
+				let Some(delay) = self.transmit(link) else {
+					return;
+				};
+				let Some(response_delay) = self.transmit(link) else {
+					return;
+				};
+				let input = Input::HeaderResponse {
+					request_id: *request_id,
+					header,
+				};
+				self.schedule(delay + response_delay, input);
+			},
+			Action::RequestHeaders {
+				request_id,
+				peer_id,
+				from,
+				count,
+			} => {
+				let Some(peer) = self.peers.get(peer_id) else {
+					return;
+				};
+				// Peers keep a single chain, so headers are found by the block number
+				let by_number = |number: u32| {
+					peer.headers
+						.values()
+						.find(|header| header.number == number)
+						.map(Encode::encode)
+				};
+				let headers = match peer.behavior {
+					Behavior::Silent => return,
+					// Headers of the range are shifted by one block
+					Behavior::WrongHeaders => (*from..*from + *count)
+						.map_while(|number| by_number(number + 1))
+						.collect(),
+					_ => (*from..*from + *count).map_while(by_number).collect(),
+				};
+				let link = peer.link;
+				let Some(delay) = self.transmit(link) else {
+					return;
+				};
+				let Some(response_delay) = self.transmit(link) else {
+					return;
+				};
+				let input = Input::HeadersResponse {
+					request_id: *request_id,
+					headers,
+				};
+				self.schedule(delay + response_delay, input);
+			},
+			Action::DisconnectPeer { peer_id } => {
+				if self.peers.remove(peer_id).is_some() {
+					let peer_id = *peer_id;
+					self.schedule(Duration::ZERO, Input::PeerDisconnected { peer_id });
+				}
+			},
+			Action::Announce { .. } | Action::NewBest { .. } | Action::Finalized { .. } => (),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn genesis() -> Header {
+		header(0, H256::zero(), 0)
+	}
+
+	fn lossy() -> Link {
+		Link {
+			latency: Duration::from_millis(100),
+			jitter: Duration::from_millis(400),
+			loss: 0.3,
+		}
+	}
+
+	fn sync_with_loss(seed: u64) -> Simulation {
+		let genesis = genesis();
+		let headers = chain(&genesis, 5, 0);
+		let mut simulation = Simulation::new(seed, &genesis);
+		let peers = (0..3)
+			.map(|_| simulation.add_peer(Behavior::Honest, lossy(), &[]))
+			.collect::<Vec<_>>();
+		simulation.run_for(Duration::from_secs(1));
+		for peer_id in peers {
+			simulation.import(peer_id, &headers);
+		}
+		simulation.run_for(Duration::from_secs(120));
+		simulation
+	}
+
+	#[test]
+	fn reproducible_with_seed() {
+		let first = sync_with_loss(7);
+		let second = sync_with_loss(7);
+		assert_eq!(first.actions(), second.actions());
+	}
+
+	#[test]
+	fn sync_from_unreliable_peers() {
+		let genesis = genesis();
+		let headers = chain(&genesis, 10, 0);
+		let mut simulation = Simulation::new(1, &genesis);
+		let silent = simulation.add_peer(Behavior::Silent, Link::default(), &headers);
+		let lying = simulation.add_peer(Behavior::WrongHeaders, Link::default(), &headers);
+		let honest = simulation.add_peer(Behavior::Honest, Link::default(), &headers);
+		simulation.run_for(Duration::from_secs(1));
+		simulation.import(silent, &headers);
+		simulation.import(lying, &headers);
+		simulation.run_for(Duration::from_secs(5));
+		simulation.import(honest, &headers);
+		simulation.run_for(Duration::from_secs(600));
+
+		let best = headers.last().unwrap();
+		assert_eq!(simulation.best(), Some((best.header_hash(), 10)));
+		assert_eq!(simulation.machine().orphans(), 0);
+	}
+
+	#[test]
+	fn finalize_fork() {
+		let genesis = genesis();
+		let short = chain(&genesis, 3, 1);
+		let long = chain(&genesis, 6, 2);
+		let mut simulation = Simulation::new(2, &genesis);
+		let first = simulation.add_peer(Behavior::Honest, Link::default(), &short);
+		let second = simulation.add_peer(Behavior::Honest, Link::default(), &long);
+		simulation.run_for(Duration::from_secs(1));
+		simulation.import(first, &short);
+		simulation.import(second, &long);
+		simulation.run_for(Duration::from_secs(60));
+		assert_eq!(simulation.best(), Some((long[5].header_hash(), 6)));
+
+		let finalized = short[2].header_hash();
+		simulation.finalize(finalized);
+		simulation.run_for(Duration::from_secs(1));
+		assert_eq!(simulation.machine().tree().finalized(), finalized);
+		assert_eq!(simulation.best(), Some((finalized, 3)));
+		assert!(simulation.actions().iter().any(|(_, action)| *action
+			== Action::Finalized {
+				hash: finalized,
+				number: 3
+			}));
+	}
+
+	#[test]
+	fn disconnect_malicious_peer() {
+		let genesis = genesis();
+		let mut simulation = Simulation::new(3, &genesis);
+		let peer_id = simulation.add_peer(Behavior::InvalidAnnounces, Link::default(), &[]);
+		simulation.run_for(Duration::from_secs(1));
+		for header in chain(&genesis, 5, 0) {
+			simulation.import(peer_id, &[header]);
+			simulation.run_for(Duration::from_secs(1));
+		}
+		assert!(!simulation.is_connected(&peer_id));
+	}
+}
@@ -0,0 +1,158 @@
+//! Snapshot of the full state at a finalized block, in the raw genesis storage format of the chain spec.
+//!
+//! # Flow
+//!
+//! * Iterate through all state keys in pages, verifying completeness and values of each page against the state root
+//! * Collect entries into `genesis.raw.top`, as hex encoded keys and values
+//!
+//! # Notes
+//!
+//! Snapshot can be used as the `genesis` section of a chain spec, to start a fork or a testnet from the live state.
+//! Child tries are not exported, since their roots are not provable by the top trie read proofs, so entries under
+//! the `:child_storage:` prefix are skipped.
+
+use avail_subxt::utils::H256;
+use color_eyre::{eyre::WrapErr, Result};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs::File, io::BufWriter};
+use tracing::{debug, info, warn};
+
+use crate::state_client::{Client, StateClient};
+
+/// Prefix of the child trie roots in the top trie
+const CHILD_STORAGE_PREFIX: &[u8] = b":child_storage:";
+
+/// Number of keys fetched with a single proof
+const SNAPSHOT_PAGE_SIZE: u32 = 1000;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RawGenesis {
+	pub top: BTreeMap<String, String>,
+	pub children_default: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Genesis {
+	pub raw: RawGenesis,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct GenesisStorage {
+	pub genesis: Genesis,
+}
+
+impl GenesisStorage {
+	/// Writes snapshot as pretty printed JSON file
+	pub fn write(&self, path: &str) -> Result<()> {
+		let file =
+			File::create(path).wrap_err_with(|| format!("Cannot create snapshot file {path}"))?;
+		serde_json::to_writer_pretty(BufWriter::new(file), self).wrap_err("Cannot write snapshot")
+	}
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+	format!("0x{}", hex::encode(bytes))
+}
+
+impl<T: Client> StateClient<T> {
+	/// Exports the full verified state at the given block. Block should be finalized, since the state of the
+	/// non-finalized block can be pruned by the node during the export.
+	pub async fn state_snapshot(&self, at_finalized_hash: H256) -> Result<GenesisStorage> {
+		let mut top = BTreeMap::new();
+		let mut skipped = 0;
+		let entries = self.storage_iter(vec![], at_finalized_hash, SNAPSHOT_PAGE_SIZE);
+		futures::pin_mut!(entries);
+		while let Some(entry) = entries.next().await {
+			let (key, value) = entry.wrap_err("Snapshot failed to get storage page")?;
+			if key.starts_with(CHILD_STORAGE_PREFIX) {
+				skipped += 1;
+				continue;
+			}
+			top.insert(hex_encode(&key), hex_encode(&value));
+			if top.len() % SNAPSHOT_PAGE_SIZE as usize == 0 {
+				debug!(entries = top.len(), "Fetched snapshot entries");
+			}
+		}
+
+		if skipped > 0 {
+			warn!(skipped, "Child tries are not included in the snapshot");
+		}
+		info!(
+			?at_finalized_hash,
+			entries = top.len(),
+			"State snapshot completed"
+		);
+		Ok(GenesisStorage {
+			genesis: Genesis {
+				raw: RawGenesis {
+					top,
+					children_default: BTreeMap::new(),
+				},
+			},
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{state_client::MockClient, storage_proof::build_trie};
+	use std::num::NonZeroUsize;
+
+	#[tokio::test]
+	async fn snapshot() {
+		let mut entries = (0..2500u32)
+			.map(|i| (i.to_be_bytes().to_vec(), vec![1; 40]))
+			.collect::<Vec<_>>();
+		entries.push((b":child_storage:default:1".to_vec(), vec![2]));
+		entries.sort();
+		let trie_entries = entries
+			.iter()
+			.map(|(key, value)| (key.as_slice(), value.as_slice()))
+			.collect::<Vec<_>>();
+		let (root, proof) = build_trie(&trie_entries);
+
+		let mut mock_client = MockClient::new();
+		mock_client
+			.expect_get_state_root()
+			.returning(move |_| Box::pin(async move { Ok(root) }));
+		mock_client
+			.expect_get_read_proof()
+			.times(3)
+			.returning(move |_, _| {
+				let proof = proof.clone();
+				Box::pin(async move { Ok(proof) })
+			});
+		let keys = entries
+			.iter()
+			.map(|(key, _)| key.clone())
+			.collect::<Vec<_>>();
+		mock_client
+			.expect_get_keys_paged()
+			.returning(move |_, count, start_key, _| {
+				let page: Vec<Vec<u8>> = keys
+					.iter()
+					.filter(|&key| start_key.as_ref().map_or(true, |start| key > start))
+					.take(count as usize)
+					.cloned()
+					.collect();
+				Box::pin(async move { Ok(page) })
+			});
+
+		let client = StateClient::new(mock_client, NonZeroUsize::new(16).unwrap());
+		let snapshot = client.state_snapshot(H256::zero()).await.unwrap();
+		let top = snapshot.genesis.raw.top;
+		assert_eq!(top.len(), 2500);
+		assert_eq!(top["0x000009c3"], hex_encode(&[1; 40]));
+
+		let json = serde_json::to_value(GenesisStorage {
+			genesis: Genesis {
+				raw: RawGenesis::default(),
+			},
+		})
+		.unwrap();
+		assert!(json["genesis"]["raw"]["childrenDefault"].is_object());
+	}
+}
@@ -0,0 +1,425 @@
+//! Verified staking queries and calls for validator and nominator tooling.
+//!
+//! Queries are read through the [`StateClient`], so values are verified against the state root of the block:
+//!
+//! * `Staking::ActiveEra` - index and start of the active era
+//! * `Staking::ErasStakersOverview` and `Staking::ErasStakersPaged` - paged exposure of the validator (falls back to
+//!   the legacy `Staking::ErasStakers` for eras before the paged exposure)
+//! * `Staking::Nominators` - nominations of the nominator
+//! * `Staking::ErasValidatorReward`, `Staking::ErasRewardPoints` and `Staking::ClaimedRewards` - pending rewards
+//!
+//! Reward of the validator is its share of the era reward by reward points, which is paid out to the validator and
+//! its nominators with `Staking.payout_stakers`, once per exposure page. Eras before the paged exposure have a single
+//! page, and their claimed rewards are tracked in the staking ledger, which is not checked.
+
+use avail_subxt::utils::H256;
+use codec::{Compact, Decode, Encode};
+use color_eyre::{eyre::WrapErr, Result};
+use sp_core::{twox_128, twox_64};
+use std::collections::BTreeMap;
+use subxt::Metadata;
+
+use crate::{
+	extrinsic::calls::CallIndex,
+	state_client::{Client, StateClient},
+};
+
+pub type AccountId = [u8; 32];
+pub type EraIndex = u32;
+
+/// `MultiAddress::Id` variant index
+const MULTI_ADDRESS_ID: u8 = 0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct ActiveEraInfo {
+	pub index: EraIndex,
+	/// Start of the era in milliseconds, set once the first block of the era is produced
+	pub start: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct IndividualExposure {
+	pub who: AccountId,
+	#[codec(compact)]
+	pub value: u128,
+}
+
+/// Stake backing the validator in the era
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct Exposure {
+	#[codec(compact)]
+	pub total: u128,
+	#[codec(compact)]
+	pub own: u128,
+	pub others: Vec<IndividualExposure>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+struct PagedExposureMetadata {
+	#[codec(compact)]
+	total: u128,
+	#[codec(compact)]
+	own: u128,
+	nominator_count: u32,
+	page_count: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+struct ExposurePage {
+	#[codec(compact)]
+	page_total: u128,
+	others: Vec<IndividualExposure>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct Nominations {
+	pub targets: Vec<AccountId>,
+	/// Era in which nominations were submitted
+	pub submitted_in: EraIndex,
+	/// Nominations were suppressed due to slashing
+	pub suppressed: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct EraRewardPoints {
+	pub total: u32,
+	pub individual: BTreeMap<AccountId, u32>,
+}
+
+/// Reward of the validator and its nominators in the era
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EraReward {
+	pub era: EraIndex,
+	pub reward: u128,
+	/// Exposure pages which are not paid out yet
+	pub unclaimed_pages: u32,
+}
+
+/// Share of the era reward, by reward points of the validator
+pub fn validator_reward(era_reward: u128, points: &EraRewardPoints, validator: &AccountId) -> u128 {
+	if points.total == 0 {
+		return 0;
+	}
+	let validator_points = points.individual.get(validator).copied().unwrap_or(0);
+	era_reward.saturating_mul(validator_points.into()) / u128::from(points.total)
+}
+
+fn plain_key(storage: &str) -> Vec<u8> {
+	[twox_128(b"Staking"), twox_128(storage.as_bytes())].concat()
+}
+
+/// Storage key of the `Staking` map entry, with `Twox64Concat` hashed keys
+fn map_key(storage: &str, keys: &[&[u8]]) -> Vec<u8> {
+	let mut key = plain_key(storage);
+	for part in keys {
+		key.extend_from_slice(&twox_64(part));
+		key.extend_from_slice(part);
+	}
+	key
+}
+
+pub fn active_era_storage_key() -> Vec<u8> {
+	plain_key("ActiveEra")
+}
+
+pub fn nominators_storage_key(nominator: &AccountId) -> Vec<u8> {
+	map_key("Nominators", &[nominator])
+}
+
+pub fn eras_stakers_storage_key(era: EraIndex, validator: &AccountId) -> Vec<u8> {
+	map_key("ErasStakers", &[&era.encode(), validator])
+}
+
+fn eras_stakers_overview_storage_key(era: EraIndex, validator: &AccountId) -> Vec<u8> {
+	map_key("ErasStakersOverview", &[&era.encode(), validator])
+}
+
+fn eras_stakers_paged_storage_key(era: EraIndex, validator: &AccountId, page: u32) -> Vec<u8> {
+	map_key(
+		"ErasStakersPaged",
+		&[&era.encode(), validator, &page.encode()],
+	)
+}
+
+fn eras_validator_reward_storage_key(era: EraIndex) -> Vec<u8> {
+	map_key("ErasValidatorReward", &[&era.encode()])
+}
+
+fn eras_reward_points_storage_key(era: EraIndex) -> Vec<u8> {
+	map_key("ErasRewardPoints", &[&era.encode()])
+}
+
+fn claimed_rewards_storage_key(era: EraIndex, validator: &AccountId) -> Vec<u8> {
+	map_key("ClaimedRewards", &[&era.encode(), validator])
+}
+
+fn decode<T: Decode>(value: Option<Vec<u8>>, name: &str) -> Result<Option<T>> {
+	value
+		.map(|value| T::decode(&mut &value[..]))
+		.transpose()
+		.wrap_err_with(|| format!("Failed to decode {name}"))
+}
+
+impl<T: Client> StateClient<T> {
+	pub async fn active_era(&self, block_hash: H256) -> Result<Option<ActiveEraInfo>> {
+		let value = self.storage(active_era_storage_key(), block_hash).await?;
+		decode(value, "active era")
+	}
+
+	pub async fn nominations(
+		&self,
+		nominator: &AccountId,
+		block_hash: H256,
+	) -> Result<Option<Nominations>> {
+		let value = self
+			.storage(nominators_storage_key(nominator), block_hash)
+			.await?;
+		decode(value, "nominations")
+	}
+
+	/// Returns exposure of the validator in the era, with nominators from all exposure pages
+	pub async fn exposure(
+		&self,
+		era: EraIndex,
+		validator: &AccountId,
+		block_hash: H256,
+	) -> Result<Option<Exposure>> {
+		let overview = self
+			.storage(
+				eras_stakers_overview_storage_key(era, validator),
+				block_hash,
+			)
+			.await?;
+		let Some(overview) = decode::<PagedExposureMetadata>(overview, "exposure overview")? else {
+			let value = self
+				.storage(eras_stakers_storage_key(era, validator), block_hash)
+				.await?;
+			return decode(value, "exposure");
+		};
+		let keys = (0..overview.page_count)
+			.map(|page| eras_stakers_paged_storage_key(era, validator, page))
+			.collect();
+		let mut others = vec![];
+		for page in self.storage_batch(keys, block_hash).await? {
+			if let Some(page) = decode::<ExposurePage>(page, "exposure page")? {
+				others.extend(page.others);
+			}
+		}
+		Ok(Some(Exposure {
+			total: overview.total,
+			own: overview.own,
+			others,
+		}))
+	}
+
+	/// Returns reward of the validator in the era, if era reward is already known
+	pub async fn pending_reward(
+		&self,
+		era: EraIndex,
+		validator: &AccountId,
+		block_hash: H256,
+	) -> Result<Option<EraReward>> {
+		let keys = vec![
+			eras_validator_reward_storage_key(era),
+			eras_reward_points_storage_key(era),
+			eras_stakers_overview_storage_key(era, validator),
+			claimed_rewards_storage_key(era, validator),
+		];
+		let mut values = self.storage_batch(keys, block_hash).await?.into_iter();
+		let mut next = || values.next().flatten();
+		let Some(era_reward) = decode::<u128>(next(), "era reward")? else {
+			return Ok(None);
+		};
+		let points: EraRewardPoints = decode(next(), "reward points")?.unwrap_or_default();
+		let page_count = decode::<PagedExposureMetadata>(next(), "exposure overview")?
+			.map_or(1, |overview| overview.page_count);
+		let claimed: Vec<u32> = decode(next(), "claimed rewards")?.unwrap_or_default();
+		Ok(Some(EraReward {
+			era,
+			reward: validator_reward(era_reward, &points, validator),
+			unclaimed_pages: page_count.saturating_sub(claimed.len() as u32),
+		}))
+	}
+}
+
+/// Destination of the staking rewards
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum RewardDestination {
+	/// Rewards are added to the bonded stake
+	Staked,
+	/// Rewards are paid to the stash account, without increasing the stake
+	Stash,
+	/// Rewards are paid to the controller account, deprecated by the runtime
+	Controller,
+	Account(AccountId),
+	None,
+}
+
+/// Indices of the staking calls in the current runtime
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StakingCalls {
+	pub bond: CallIndex,
+	pub bond_extra: CallIndex,
+	pub nominate: CallIndex,
+	pub payout_stakers: CallIndex,
+}
+
+impl StakingCalls {
+	pub fn from_metadata(metadata: &Metadata) -> Result<Self> {
+		Ok(StakingCalls {
+			bond: CallIndex::from_metadata(metadata, "Staking", "bond")?,
+			bond_extra: CallIndex::from_metadata(metadata, "Staking", "bond_extra")?,
+			nominate: CallIndex::from_metadata(metadata, "Staking", "nominate")?,
+			payout_stakers: CallIndex::from_metadata(metadata, "Staking", "payout_stakers")?,
+		})
+	}
+
+	pub fn bond(&self, value: u128, payee: RewardDestination) -> Vec<u8> {
+		(self.bond, Compact(value), payee).encode()
+	}
+
+	pub fn bond_extra(&self, value: u128) -> Vec<u8> {
+		(self.bond_extra, Compact(value)).encode()
+	}
+
+	pub fn nominate(&self, targets: &[AccountId]) -> Vec<u8> {
+		let targets = targets
+			.iter()
+			.map(|target| (MULTI_ADDRESS_ID, target))
+			.collect::<Vec<_>>();
+		(self.nominate, targets).encode()
+	}
+
+	/// Pays out the next unclaimed exposure page of the validator in the era
+	pub fn payout_stakers(&self, validator: &AccountId, era: EraIndex) -> Vec<u8> {
+		(self.payout_stakers, validator, era).encode()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{state_client::MockClient, storage_proof::build_trie};
+	use std::num::NonZeroUsize;
+
+	const VALIDATOR: AccountId = [1; 32];
+
+	fn client(entries: Vec<(Vec<u8>, Vec<u8>)>) -> StateClient<MockClient> {
+		let entries = entries
+			.iter()
+			.map(|(key, value)| (&key[..], &value[..]))
+			.collect::<Vec<_>>();
+		let (root, proof) = build_trie(&entries);
+		let mut mock_client = MockClient::new();
+		mock_client
+			.expect_get_state_root()
+			.returning(move |_| Box::pin(async move { Ok(root) }));
+		mock_client.expect_get_read_proof().returning(move |_, _| {
+			let proof = proof.clone();
+			Box::pin(async move { Ok(proof) })
+		});
+		StateClient::new(mock_client, NonZeroUsize::new(16).unwrap())
+	}
+
+	#[tokio::test]
+	async fn paged_exposure() {
+		let nominator = |who: u8| IndividualExposure {
+			who: [who; 32],
+			value: 10,
+		};
+		let overview = PagedExposureMetadata {
+			total: 50,
+			own: 20,
+			nominator_count: 3,
+			page_count: 2,
+		};
+		let pages = [vec![nominator(2), nominator(3)], vec![nominator(4)]];
+		let mut entries = vec![(
+			eras_stakers_overview_storage_key(7, &VALIDATOR),
+			overview.encode(),
+		)];
+		for (page, others) in pages.iter().enumerate() {
+			let page_total = 10 * others.len() as u128;
+			entries.push((
+				eras_stakers_paged_storage_key(7, &VALIDATOR, page as u32),
+				ExposurePage {
+					page_total,
+					others: others.clone(),
+				}
+				.encode(),
+			));
+		}
+		let client = client(entries);
+		let exposure = client.exposure(7, &VALIDATOR, H256::zero()).await.unwrap();
+		assert_eq!(
+			exposure,
+			Some(Exposure {
+				total: 50,
+				own: 20,
+				others: pages.concat()
+			})
+		);
+	}
+
+	#[tokio::test]
+	async fn legacy_exposure() {
+		let exposure = Exposure {
+			total: 20,
+			own: 20,
+			others: vec![],
+		};
+		let client = client(vec![(
+			eras_stakers_storage_key(7, &VALIDATOR),
+			exposure.encode(),
+		)]);
+		let result = client.exposure(7, &VALIDATOR, H256::zero()).await.unwrap();
+		assert_eq!(result, Some(exposure));
+		let result = client.exposure(8, &VALIDATOR, H256::zero()).await.unwrap();
+		assert_eq!(result, None);
+	}
+
+	#[tokio::test]
+	async fn pending_reward() {
+		let points = EraRewardPoints {
+			total: 400,
+			individual: [(VALIDATOR, 100), ([2; 32], 300)].into(),
+		};
+		let client = client(vec![
+			(eras_validator_reward_storage_key(7), 1000u128.encode()),
+			(eras_reward_points_storage_key(7), points.encode()),
+			(
+				claimed_rewards_storage_key(7, &VALIDATOR),
+				vec![0u32].encode(),
+			),
+		]);
+		let reward = client.pending_reward(7, &VALIDATOR, H256::zero()).await;
+		assert_eq!(
+			reward.unwrap(),
+			Some(EraReward {
+				era: 7,
+				reward: 250,
+				unclaimed_pages: 0
+			})
+		);
+		let reward = client.pending_reward(8, &VALIDATOR, H256::zero()).await;
+		assert_eq!(reward.unwrap(), None);
+	}
+
+	#[test]
+	fn encode_calls() {
+		let calls = StakingCalls {
+			bond: CallIndex(10, 0),
+			bond_extra: CallIndex(10, 1),
+			nominate: CallIndex(10, 5),
+			payout_stakers: CallIndex(10, 18),
+		};
+		assert_eq!(
+			calls.bond(1, RewardDestination::Account([2; 32]))[..5],
+			[10, 0, 4, 3, 2]
+		);
+		let nominate = calls.nominate(&[[2; 32], [3; 32]]);
+		assert_eq!(nominate[..4], [10, 5, 8, 0]);
+		assert_eq!(nominate.len(), 3 + 2 * 33);
+		assert_eq!(calls.payout_stakers(&VALIDATOR, 7)[34..], [7, 0, 0, 0]);
+	}
+}
@@ -0,0 +1,380 @@
+//! Verified state queries at arbitrary block hashes.
+//!
+//! # Flow
+//!
+//! * Look up (block hash, key) pairs in the verification cache
+//! * Fetch header of the block and check it matches the requested hash, to get trusted state root
+//! * Fetch single read proof for all missing keys, and verify it against the state root
+//! * Store verified values in the cache
+//!
+//! Prefix iteration ([`StateClient::storage_iter`]) fetches keys in pages, and proves each page is complete, by
+//! including the page start key in the read proof together with the page keys.
+//!
+//! # Notes
+//!
+//! Verified values are immutable for a given block hash, so cache entries never need to be invalidated.
+
+use async_trait::async_trait;
+use avail_subxt::utils::H256;
+use codec::Encode;
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use futures::Stream;
+use lru::LruCache;
+use mockall::automock;
+use sp_core::blake2_256;
+use std::{
+	collections::HashMap,
+	num::NonZeroUsize,
+	sync::{Arc, Mutex},
+};
+use tracing::debug;
+
+use crate::{
+	network::rpc::Client as RpcClient,
+	storage_proof::{verify_read_proof, verify_storage_page},
+};
+
+#[async_trait]
+#[automock]
+pub trait Client {
+	/// Returns state root of the block, verified against the block hash.
+	async fn get_state_root(&self, block_hash: H256) -> Result<H256>;
+	async fn get_read_proof(&self, keys: Vec<Vec<u8>>, block_hash: H256) -> Result<Vec<Vec<u8>>>;
+	/// Returns up to `count` keys with the given prefix, after the start key (`state_getKeysPaged` semantics).
+	async fn get_keys_paged(
+		&self,
+		prefix: Vec<u8>,
+		count: u32,
+		start_key: Option<Vec<u8>>,
+		block_hash: H256,
+	) -> Result<Vec<Vec<u8>>>;
+}
+
+#[async_trait]
+impl Client for RpcClient {
+	async fn get_state_root(&self, block_hash: H256) -> Result<H256> {
+		let header = self
+			.get_header_by_hash(block_hash)
+			.await
+			.wrap_err("State Client failed to get Block Header")?;
+		let hash: H256 = Encode::using_encoded(&header, blake2_256).into();
+		if hash != block_hash {
+			return Err(eyre!(
+				"Received header {hash:?} doesn't match requested {block_hash:?}"
+			));
+		}
+		Ok(header.state_root)
+	}
+
+	async fn get_read_proof(&self, keys: Vec<Vec<u8>>, block_hash: H256) -> Result<Vec<Vec<u8>>> {
+		RpcClient::get_read_proof(self, keys, block_hash).await
+	}
+
+	async fn get_keys_paged(
+		&self,
+		prefix: Vec<u8>,
+		count: u32,
+		start_key: Option<Vec<u8>>,
+		block_hash: H256,
+	) -> Result<Vec<Vec<u8>>> {
+		let keys = self
+			.get_paged_storage_keys(prefix, count, start_key, Some(block_hash))
+			.await?;
+		Ok(keys.into_iter().map(|key| key.0).collect())
+	}
+}
+
+type CacheKey = (H256, Vec<u8>);
+
+#[derive(Clone)]
+pub struct StateClient<T: Client> {
+	client: T,
+	state_roots: Arc<Mutex<LruCache<H256, H256>>>,
+	values: Arc<Mutex<LruCache<CacheKey, Option<Vec<u8>>>>>,
+}
+
+impl<T: Client> StateClient<T> {
+	pub fn new(client: T, cache_capacity: NonZeroUsize) -> Self {
+		StateClient {
+			client,
+			state_roots: Arc::new(Mutex::new(LruCache::new(cache_capacity))),
+			values: Arc::new(Mutex::new(LruCache::new(cache_capacity))),
+		}
+	}
+
+	async fn state_root(&self, block_hash: H256) -> Result<H256> {
+		if let Some(state_root) = self.state_roots.lock().unwrap().get(&block_hash) {
+			return Ok(*state_root);
+		}
+		let state_root = self.client.get_state_root(block_hash).await?;
+		self.state_roots.lock().unwrap().put(block_hash, state_root);
+		Ok(state_root)
+	}
+
+	/// Returns verified value of the storage key at the given block.
+	pub async fn storage(&self, key: Vec<u8>, block_hash: H256) -> Result<Option<Vec<u8>>> {
+		let mut values = self.storage_batch(vec![key], block_hash).await?;
+		Ok(values.pop().flatten())
+	}
+
+	/// Returns verified values of the storage keys at the given block, in the same order as keys.
+	pub async fn storage_batch(
+		&self,
+		keys: Vec<Vec<u8>>,
+		block_hash: H256,
+	) -> Result<Vec<Option<Vec<u8>>>> {
+		let mut results = {
+			let mut values = self.values.lock().unwrap();
+			keys.iter()
+				.map(|key| values.get(&(block_hash, key.clone())).cloned())
+				.collect::<Vec<_>>()
+		};
+		let missing = keys
+			.iter()
+			.zip(&results)
+			.filter(|(_, value)| value.is_none())
+			.map(|(key, _)| key.clone())
+			.collect::<Vec<_>>();
+
+		if !missing.is_empty() {
+			debug!(
+				?block_hash,
+				cached = keys.len() - missing.len(),
+				missing = missing.len(),
+				"Fetching storage proofs"
+			);
+			let state_root = self.state_root(block_hash).await?;
+			let proof = self
+				.client
+				.get_read_proof(missing.clone(), block_hash)
+				.await
+				.wrap_err("State Client failed to get Read Proof")?;
+			let verified = verify_read_proof(state_root, proof, &missing)?
+				.into_iter()
+				.collect::<HashMap<_, _>>();
+
+			let mut values = self.values.lock().unwrap();
+			for (key, result) in keys.iter().zip(results.iter_mut()) {
+				if result.is_some() {
+					continue;
+				}
+				let value = verified
+					.get(key)
+					.cloned()
+					.ok_or_else(|| eyre!("Read proof verification is missing a key"))?;
+				values.put((block_hash, key.clone()), value.clone());
+				*result = Some(value);
+			}
+		}
+
+		Ok(results.into_iter().map(Option::flatten).collect())
+	}
+
+	/// Returns verified page of up to `count` storage entries with the given prefix, after the start key.
+	/// Page is proven to be complete, so entries cannot be omitted by the node.
+	pub async fn storage_page(
+		&self,
+		prefix: &[u8],
+		start_key: Option<Vec<u8>>,
+		count: u32,
+		block_hash: H256,
+	) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+		let keys = self
+			.client
+			.get_keys_paged(prefix.to_vec(), count, start_key.clone(), block_hash)
+			.await
+			.wrap_err("State Client failed to get Storage Keys")?;
+		let state_root = self.state_root(block_hash).await?;
+
+		let mut proof_keys = keys.clone();
+		proof_keys.push(start_key.clone().unwrap_or_else(|| prefix.to_vec()));
+		let proof = self
+			.client
+			.get_read_proof(proof_keys, block_hash)
+			.await
+			.wrap_err("State Client failed to get Read Proof")?;
+		verify_storage_page(
+			state_root,
+			proof,
+			prefix,
+			start_key.as_deref(),
+			&keys,
+			count as usize,
+		)
+	}
+
+	/// Lazily iterates verified storage entries with the given prefix at the given block, fetching pages of
+	/// `page_size` entries when needed.
+	pub fn storage_iter<'a>(
+		&'a self,
+		prefix: Vec<u8>,
+		block_hash: H256,
+		page_size: u32,
+	) -> impl Stream<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a {
+		async_stream::try_stream! {
+			let mut start_key = None;
+			loop {
+				let page = self
+					.storage_page(&prefix, start_key.take(), page_size, block_hash)
+					.await?;
+				let is_last = page.len() < page_size as usize;
+				start_key = page.last().map(|(key, _)| key.clone());
+				for entry in page {
+					yield entry;
+				}
+				if is_last || start_key.is_none() {
+					break;
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::storage_proof::build_trie;
+
+	#[tokio::test]
+	async fn storage_is_verified_and_cached() {
+		let (root, proof) = build_trie(&[(b"key1", b"value1"), (b"key2", b"value2")]);
+		let block_hash = H256::repeat_byte(1);
+
+		let mut mock_client = MockClient::new();
+		mock_client
+			.expect_get_state_root()
+			.times(1)
+			.returning(move |_| Box::pin(async move { Ok(root) }));
+		mock_client
+			.expect_get_read_proof()
+			.times(1)
+			.returning(move |_, _| {
+				let proof = proof.clone();
+				Box::pin(async move { Ok(proof) })
+			});
+
+		let client = StateClient::new(mock_client, NonZeroUsize::new(16).unwrap());
+		let keys = vec![b"key1".to_vec(), b"missing".to_vec()];
+		let values = client.storage_batch(keys, block_hash).await.unwrap();
+		assert_eq!(values, vec![Some(b"value1".to_vec()), None]);
+
+		// Served from cache, client is not called again
+		let value = client.storage(b"key1".to_vec(), block_hash).await.unwrap();
+		assert_eq!(value, Some(b"value1".to_vec()));
+	}
+
+	#[tokio::test]
+	async fn batch_larger_than_cache() {
+		let (root, proof) = build_trie(&[(b"key1", b"value1"), (b"key2", b"value2")]);
+		let mut mock_client = MockClient::new();
+		mock_client
+			.expect_get_state_root()
+			.returning(move |_| Box::pin(async move { Ok(root) }));
+		mock_client
+			.expect_get_read_proof()
+			.times(1)
+			.returning(move |_, _| {
+				let proof = proof.clone();
+				Box::pin(async move { Ok(proof) })
+			});
+
+		let client = StateClient::new(mock_client, NonZeroUsize::new(1).unwrap());
+		let keys = vec![b"key1".to_vec(), b"key2".to_vec()];
+		let values = client.storage_batch(keys, H256::zero()).await.unwrap();
+		assert_eq!(
+			values,
+			vec![Some(b"value1".to_vec()), Some(b"value2".to_vec())]
+		);
+	}
+
+	#[tokio::test]
+	async fn invalid_proof_fails() {
+		let (_, proof) = build_trie(&[(b"key1", b"value1")]);
+		let mut mock_client = MockClient::new();
+		mock_client
+			.expect_get_state_root()
+			.returning(|_| Box::pin(async move { Ok(H256::repeat_byte(2)) }));
+		mock_client.expect_get_read_proof().returning(move |_, _| {
+			let proof = proof.clone();
+			Box::pin(async move { Ok(proof) })
+		});
+
+		let client = StateClient::new(mock_client, NonZeroUsize::new(16).unwrap());
+		let result = client.storage(b"key1".to_vec(), H256::zero()).await;
+		assert!(result.is_err());
+	}
+
+	#[tokio::test]
+	async fn omitted_page_key_fails() {
+		let (root, proof) = build_trie(&[(b"key1", b"value1"), (b"key2", b"value2")]);
+		let mut mock_client = MockClient::new();
+		mock_client
+			.expect_get_state_root()
+			.returning(move |_| Box::pin(async move { Ok(root) }));
+		mock_client.expect_get_read_proof().returning(move |_, _| {
+			let proof = proof.clone();
+			Box::pin(async move { Ok(proof) })
+		});
+		mock_client
+			.expect_get_keys_paged()
+			.returning(|_, _, _, _| Box::pin(async move { Ok(vec![b"key2".to_vec()]) }));
+
+		let client = StateClient::new(mock_client, NonZeroUsize::new(16).unwrap());
+		let result = client.storage_page(b"key", None, 10, H256::zero()).await;
+		assert!(result.is_err());
+	}
+
+	#[tokio::test]
+	async fn storage_iter_pages() {
+		use futures::TryStreamExt;
+
+		let entries: [(&[u8], &[u8]); 5] = [
+			(b"a", b"0"),
+			(b"key1", b"value1"),
+			(b"key2", b"value2"),
+			(b"key3", b"value3"),
+			(b"z", b"0"),
+		];
+		let (root, proof) = build_trie(&entries);
+		let keys = entries
+			.iter()
+			.map(|(key, _)| key.to_vec())
+			.collect::<Vec<_>>();
+
+		let mut mock_client = MockClient::new();
+		mock_client
+			.expect_get_state_root()
+			.returning(move |_| Box::pin(async move { Ok(root) }));
+		mock_client
+			.expect_get_read_proof()
+			.times(2)
+			.returning(move |_, _| {
+				let proof = proof.clone();
+				Box::pin(async move { Ok(proof) })
+			});
+		mock_client
+			.expect_get_keys_paged()
+			.returning(move |prefix, count, start_key, _| {
+				let page: Vec<Vec<u8>> = keys
+					.iter()
+					.filter(|key| key.starts_with(&prefix))
+					.filter(|&key| start_key.as_ref().map_or(true, |start| key > start))
+					.take(count as usize)
+					.cloned()
+					.collect();
+				Box::pin(async move { Ok(page) })
+			});
+
+		let client = StateClient::new(mock_client, NonZeroUsize::new(16).unwrap());
+		let values = client
+			.storage_iter(b"key".to_vec(), H256::zero(), 2)
+			.map_ok(|(_, value)| value)
+			.try_collect::<Vec<_>>()
+			.await
+			.unwrap();
+		assert_eq!(values, vec![b"value1", b"value2", b"value3"]);
+	}
+}
@@ -0,0 +1,255 @@
+//! Trustless storage queries and subscriptions, backed by read proofs.
+//!
+//! # Flow
+//!
+//! * Subscribe to finalized heads
+//! * On each finalized head, fetch read proof for watched keys at the block hash
+//! * Verify read proof against the header state root
+//! * Emit [`StorageChange`] for each key whose value differs from the previously verified one
+//! * With [`run`], publish the changes to the [`Subscriptions`], so they are received with
+//!   [`Subscriptions::storage_changes`]
+//!
+//! # Notes
+//!
+//! Unlike `state_subscribeStorage`, values are not trusted from the RPC node, so node can only withhold,
+//! but not forge the changes. Proof nodes are kept in the [`ProofArena`] during the verification, so verification of
+//! large proofs doesn't allocate for each node.
+
+use avail_subxt::{primitives::Header, utils::H256};
+use codec::Encode;
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use futures::{pin_mut, Stream, StreamExt};
+use sp_core::{blake2_256, Blake2Hasher};
+use sp_trie::{LayoutV1, TrieDBBuilder, TrieDBKeyIterator};
+use std::collections::HashMap;
+use tracing::{debug, info, warn};
+
+use crate::{
+	counters::{self, Counter},
+	network::rpc::Client,
+	subscriptions::{LagPolicy, StorageChange, Subscriptions},
+	trie::arena::ProofArena,
+};
+
+/// Verifies read proof against the state root, and returns values of given keys.
+pub fn verify_read_proof(
+	state_root: H256,
+	proof: Vec<Vec<u8>>,
+	keys: &[Vec<u8>],
+) -> Result<Vec<(Vec<u8>, Option<Vec<u8>>)>> {
+	counters::increment(Counter::ReadProofsVerified);
+	let db = ProofArena::new(&proof);
+	keys.iter()
+		.map(|key| {
+			sp_trie::read_trie_value::<LayoutV1<Blake2Hasher>, _>(&db, &state_root, key, None, None)
+				.map(|value| (key.clone(), value))
+				.map_err(|error| {
+					eyre!("Invalid read proof for key 0x{}: {error}", hex::encode(key))
+				})
+		})
+		.collect()
+}
+
+/// Verifies page of the storage keys with the given prefix, and returns their values.
+/// Proof has to contain the start key (or the prefix), and the page keys, so it also proves that there are no
+/// omitted keys between them, and no keys after the last one if page is not full.
+pub fn verify_storage_page(
+	state_root: H256,
+	proof: Vec<Vec<u8>>,
+	prefix: &[u8],
+	start_key: Option<&[u8]>,
+	keys: &[Vec<u8>],
+	count: usize,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+	counters::increment(Counter::ReadProofsVerified);
+	let db = ProofArena::new(&proof);
+	let trie = TrieDBBuilder::<LayoutV1<Blake2Hasher>>::new(&db, &state_root).build();
+	let invalid = |error| {
+		eyre!(
+			"Invalid storage page proof for prefix 0x{}: {error}",
+			hex::encode(prefix)
+		)
+	};
+
+	let mut iter =
+		TrieDBKeyIterator::new_prefixed_then_seek(&trie, prefix, start_key.unwrap_or(prefix))
+			.map_err(invalid)?;
+	let mut proven = vec![];
+	while proven.len() < count {
+		match iter.next().transpose().map_err(invalid)? {
+			// Start key is excluded from the page
+			Some(key) if Some(key.as_slice()) == start_key => continue,
+			Some(key) => proven.push(key),
+			None => break,
+		}
+	}
+	if proven != keys {
+		return Err(eyre!(
+			"Storage page keys for prefix 0x{} don't match the proof",
+			hex::encode(prefix)
+		));
+	}
+
+	keys.iter()
+		.map(|key| {
+			sp_trie::read_trie_value::<LayoutV1<Blake2Hasher>, _>(&db, &state_root, key, None, None)
+				.map_err(invalid)?
+				.map(|value| (key.clone(), value))
+				.ok_or_else(|| eyre!("Missing value for key 0x{}", hex::encode(key)))
+		})
+		.collect()
+}
+
+/// Fetches values of given keys at the header, verified against its state root.
+pub async fn verified_storage(
+	rpc_client: &Client,
+	header: &Header,
+	keys: &[Vec<u8>],
+) -> Result<Vec<(Vec<u8>, Option<Vec<u8>>)>> {
+	let block_hash: H256 = Encode::using_encoded(header, blake2_256).into();
+	let proof = rpc_client
+		.get_read_proof(keys.to_vec(), block_hash)
+		.await
+		.wrap_err("Failed to fetch read proof")?;
+	verify_read_proof(header.state_root, proof, keys)
+}
+
+/// Subscribes to verified changes of given storage keys on each finalized head.
+/// First emitted changes are current values of the keys.
+pub fn subscribe_storage(
+	rpc_client: Client,
+	subscriptions: &Subscriptions,
+	keys: Vec<Vec<u8>>,
+	policy: LagPolicy,
+) -> impl Stream<Item = Result<StorageChange>> {
+	let mut finalized_heads = subscriptions.finalized_heads(policy);
+	async_stream::stream! {
+		let mut values: HashMap<Vec<u8>, Option<Vec<u8>>> = HashMap::new();
+		loop {
+			let header = match finalized_heads.next().await {
+				Ok(Some(header)) => header,
+				Ok(None) => return,
+				Err(error) => {
+					yield Err(error);
+					return;
+				},
+			};
+			let block_number = header.number;
+			let block_hash: H256 = Encode::using_encoded(&header, blake2_256).into();
+
+			let verified = match verified_storage(&rpc_client, &header, &keys).await {
+				Ok(verified) => verified,
+				Err(error) => {
+					yield Err(error);
+					return;
+				},
+			};
+
+			for (key, value) in verified {
+				if values.get(&key) == Some(&value) {
+					continue;
+				}
+				debug!(block_number, key = hex::encode(&key), "Storage value changed");
+				values.insert(key.clone(), value.clone());
+				yield Ok(StorageChange { block_hash, key, value });
+			}
+		}
+	}
+}
+
+/// Publishes verified changes of given storage keys to the subscriptions, until finalized heads are closed.
+/// Subscription is restarted if verification fails or finalized heads are lagging, in which case current values of
+/// the keys are published again.
+pub async fn run(rpc_client: Client, subscriptions: Subscriptions, keys: Vec<Vec<u8>>) {
+	info!(keys = keys.len(), "Starting storage watcher...");
+	loop {
+		let changes = subscribe_storage(
+			rpc_client.clone(),
+			&subscriptions,
+			keys.clone(),
+			LagPolicy::Fail,
+		);
+		pin_mut!(changes);
+		let mut failed = false;
+		while let Some(change) = changes.next().await {
+			match change {
+				Ok(change) => subscriptions.publish_storage_change(change),
+				Err(error) => {
+					warn!("Storage watch failed, resubscribing: {error:#}");
+					failed = true;
+				},
+			}
+		}
+		if !failed {
+			info!("Finalized heads closed, storage watcher stopped");
+			return;
+		}
+	}
+}
+
+/// Builds trie from given entries, returns state root and proof containing all trie nodes.
+#[cfg(any(test, feature = "arbitrary", feature = "test-utils"))]
+pub fn build_trie(entries: &[(&[u8], &[u8])]) -> (H256, Vec<Vec<u8>>) {
+	use sp_trie::{MemoryDB, TrieDBMutBuilder, TrieMut};
+
+	let mut db = MemoryDB::<Blake2Hasher>::default();
+	let mut root = H256::default();
+	{
+		let mut trie = TrieDBMutBuilder::<LayoutV1<Blake2Hasher>>::new(&mut db, &mut root).build();
+		for (key, value) in entries {
+			trie.insert(key, value).unwrap();
+		}
+	}
+	// All trie nodes are superset of the read proof nodes
+	let proof = db.drain().into_values().map(|(node, _)| node).collect();
+	(root, proof)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn verify_valid_proof() {
+		let (root, proof) = build_trie(&[(b"key1", b"value1"), (b"key2", b"value2")]);
+		let keys = vec![b"key1".to_vec(), b"missing".to_vec()];
+		let values = verify_read_proof(root, proof, &keys).unwrap();
+		assert_eq!(
+			values,
+			vec![
+				(b"key1".to_vec(), Some(b"value1".to_vec())),
+				(b"missing".to_vec(), None)
+			]
+		);
+	}
+
+	#[test]
+	fn verify_proof_with_wrong_root() {
+		let (_, proof) = build_trie(&[(b"key1", b"value1")]);
+		let keys = vec![b"key1".to_vec()];
+		assert!(verify_read_proof(H256::repeat_byte(1), proof, &keys).is_err());
+	}
+
+	#[test]
+	fn verify_page() {
+		let (root, proof) = build_trie(&[
+			(b"a", b"0"),
+			(b"key1", b"value1"),
+			(b"key2", b"value2"),
+			(b"key3", b"value3"),
+		]);
+		let keys = vec![b"key2".to_vec(), b"key3".to_vec()];
+		let page =
+			verify_storage_page(root, proof.clone(), b"key", Some(b"key1"), &keys, 10).unwrap();
+		assert_eq!(page[1], (b"key3".to_vec(), b"value3".to_vec()));
+
+		// Page is full, so following keys are not required
+		let keys = vec![b"key1".to_vec()];
+		assert!(verify_storage_page(root, proof.clone(), b"key", None, &keys, 1).is_ok());
+		// Page is not full, so key3 is omitted
+		assert!(verify_storage_page(root, proof, b"key", None, &keys, 2).is_err());
+	}
+}
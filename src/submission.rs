@@ -0,0 +1,432 @@
+//! Submission of application data, split across extrinsics and blocks.
+//!
+//! Blobs larger than the maximum data length of a single extrinsic are split into chunks, and chunks are grouped into
+//! rounds which fit into a single block. Extrinsics of one round are signed with consecutive nonces and submitted
+//! concurrently, and the next round is submitted once all extrinsics of the previous one are finalized.
+//! Each finalized chunk is returned as an [`InclusionReceipt`], with the proof against the block data root. If a round
+//! fails, receipts of the already finalized chunks are returned in [`BatchReceipts`] together with the error.
+//! Extrinsics are tipped with the configured [`crate::tip::TipStrategy`], and stalled extrinsics are replaced with
+//! the same nonce and the tip of the next attempt. Replaced extrinsics are still awaited, in case they are finalized
+//! first.
+//! Blobs are compressed before splitting (see [`crate::compression`]), and encrypted to the recipients if configured
+//! (see [`crate::encryption`]), so the concatenated chunks have to be decrypted and decompressed on retrieval.
+
+use avail_subxt::{utils::H256, AvailConfig};
+use codec::{Compact, Encode};
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Report, Result,
+};
+use futures::{future::join_all, stream::FuturesUnordered, StreamExt};
+use sp_core::{sr25519, Pair};
+use std::time::Instant;
+use subxt::{blocks::ExtrinsicEvents, utils::AccountId32};
+use tokio::time::sleep;
+use tracing::{debug, info, warn};
+use x25519_dalek::PublicKey;
+
+use crate::{
+	compression::{self, CompressionConfig},
+	encryption,
+	extrinsic::{ExtrinsicBuilder, ExtrinsicParams},
+	network::rpc::{Client as RpcClient, DataProof},
+	nonce::AccountNonceProvider,
+	runtime_upgrade::CallIndices,
+	tip::{replacement_tip, Attempt, TipConfig},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SubmissionLimits {
+	/// Maximum data length of a single `submit_data` extrinsic
+	pub max_extrinsic_data: usize,
+	/// Maximum data length submitted in a single block
+	pub max_block_data: usize,
+}
+
+impl Default for SubmissionLimits {
+	fn default() -> Self {
+		SubmissionLimits {
+			max_extrinsic_data: 512 * 1024,
+			max_block_data: 2 * 1024 * 1024,
+		}
+	}
+}
+
+/// Chunk of the blob, submitted in a single extrinsic
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Chunk {
+	blob: usize,
+	data: Vec<u8>,
+}
+
+/// Splits blobs into chunks, and groups chunks into rounds which fit into a single block
+fn plan(blobs: Vec<Vec<u8>>, limits: &SubmissionLimits) -> Result<Vec<Vec<Chunk>>> {
+	if limits.max_extrinsic_data == 0 || limits.max_extrinsic_data > limits.max_block_data {
+		return Err(eyre!("Invalid submission limits: {limits:?}"));
+	}
+
+	let mut rounds: Vec<Vec<Chunk>> = vec![];
+	let mut round_size = 0;
+	for (blob, data) in blobs.into_iter().enumerate() {
+		if data.is_empty() {
+			return Err(eyre!("Blob {blob} is empty"));
+		}
+		for data in data.chunks(limits.max_extrinsic_data) {
+			if rounds.is_empty() || round_size + data.len() > limits.max_block_data {
+				rounds.push(vec![]);
+				round_size = 0;
+			}
+			round_size += data.len();
+			let chunk = Chunk {
+				blob,
+				data: data.to_vec(),
+			};
+			rounds.last_mut().expect("Round is pushed").push(chunk);
+		}
+	}
+	Ok(rounds)
+}
+
+fn submit_data_call(call_indices: &CallIndices, data: &[u8]) -> Vec<u8> {
+	let (pallet, call) = call_indices.submit_data;
+	let mut encoded = vec![pallet, call];
+	Compact(data.len() as u32).encode_to(&mut encoded);
+	encoded.extend_from_slice(data);
+	encoded
+}
+
+/// Finalized chunk of the submitted blob
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InclusionReceipt {
+	pub block_number: u32,
+	pub block_hash: H256,
+	pub extrinsic_hash: H256,
+	pub extrinsic_index: u32,
+	pub data_proof: DataProof,
+}
+
+/// Receipts of the submitted blobs, in the same order as submitted blobs
+#[derive(Debug, Default)]
+pub struct BatchReceipts {
+	/// Receipts of the finalized chunks, which are incomplete for the blobs of the failed round
+	pub receipts: Vec<Vec<InclusionReceipt>>,
+	/// Error of the failed round, later rounds are not submitted
+	pub error: Option<Report>,
+}
+
+impl BatchReceipts {
+	/// Returns receipts if all chunks are finalized, otherwise the submission error
+	pub fn into_result(self) -> Result<Vec<Vec<InclusionReceipt>>> {
+		match self.error {
+			Some(error) => Err(error),
+			None => Ok(self.receipts),
+		}
+	}
+}
+
+pub struct DataSubmitter {
+	rpc_client: RpcClient,
+	pair: sr25519::Pair,
+	account: AccountId32,
+	nonces: AccountNonceProvider<RpcClient>,
+	limits: SubmissionLimits,
+	compression: CompressionConfig,
+	/// Recipients of the encrypted blobs, blobs are submitted in plain if empty
+	recipients: Vec<PublicKey>,
+	tips: Option<TipConfig>,
+}
+
+impl DataSubmitter {
+	pub fn new(
+		rpc_client: RpcClient,
+		pair: sr25519::Pair,
+		limits: SubmissionLimits,
+		compression: CompressionConfig,
+	) -> Self {
+		DataSubmitter {
+			nonces: AccountNonceProvider::new(rpc_client.clone()),
+			account: AccountId32(pair.public().0),
+			rpc_client,
+			pair,
+			limits,
+			compression,
+			recipients: vec![],
+			tips: None,
+		}
+	}
+
+	/// Tips submitted extrinsics, and replaces the stalled ones
+	pub fn with_tips(mut self, tips: TipConfig) -> Self {
+		self.tips = Some(tips);
+		self
+	}
+
+	/// Encrypts submitted blobs to the recipients
+	pub fn with_recipients(mut self, recipients: Vec<PublicKey>) -> Self {
+		self.recipients = recipients;
+		self
+	}
+
+	/// Maximum length added to the submitted blobs, if they are not compressed
+	pub fn blob_overhead(&self) -> usize {
+		match self.recipients.len() {
+			0 => 0,
+			recipients => encryption::overhead(recipients),
+		}
+	}
+
+	fn encode_blob(&self, blob: &[u8]) -> Result<Vec<u8>> {
+		let compressed = compression::compress(&self.compression, blob)?;
+		if self.recipients.is_empty() {
+			return Ok(compressed);
+		}
+		encryption::seal(&self.recipients, &compressed)
+	}
+
+	pub fn limits(&self) -> &SubmissionLimits {
+		&self.limits
+	}
+
+	/// Submits blob, returning receipts of its chunks in order
+	pub async fn submit_data(&self, app_id: u32, blob: Vec<u8>) -> Result<Vec<InclusionReceipt>> {
+		let mut receipts = self
+			.submit_data_batch(app_id, vec![blob])
+			.await?
+			.into_result()?;
+		Ok(receipts.pop().unwrap_or_default())
+	}
+
+	/// Submits blobs, returning receipts of their chunks. Failure of the submission round is returned with the
+	/// receipts of the chunks finalized before it, so they are not lost.
+	pub async fn submit_data_batch(
+		&self,
+		app_id: u32,
+		blobs: Vec<Vec<u8>>,
+	) -> Result<BatchReceipts> {
+		let mut receipts = vec![vec![]; blobs.len()];
+		let blobs = blobs
+			.iter()
+			.map(|blob| self.encode_blob(blob))
+			.collect::<Result<Vec<_>>>()?;
+		let rounds = plan(blobs, &self.limits)?;
+
+		let genesis_hash = self.rpc_client.get_genesis_hash().await?;
+		let runtime_version = self.rpc_client.get_runtime_version().await?;
+		let builder = ExtrinsicBuilder::new(genesis_hash, (&runtime_version).into());
+		let metadata = self.rpc_client.current_client().await.metadata();
+		let call_indices = CallIndices::from_metadata(&metadata)?;
+
+		let total = rounds.len();
+		for (round, chunks) in rounds.into_iter().enumerate() {
+			debug!(round, total, chunks = chunks.len(), "Submitting data round");
+			let submissions = chunks.iter().map(|chunk| {
+				let call = submit_data_call(&call_indices, &chunk.data);
+				self.submit_chunk(&builder, app_id, call)
+			});
+			let mut error = None;
+			for (chunk, result) in chunks.iter().zip(join_all(submissions).await) {
+				match result {
+					Ok(receipt) => receipts[chunk.blob].push(receipt),
+					Err(round_error) => {
+						warn!(
+							round,
+							blob = chunk.blob,
+							"Chunk submission failed: {round_error:#}"
+						);
+						error.get_or_insert(round_error);
+					},
+				}
+			}
+			if let Some(error) = error {
+				return Ok(BatchReceipts {
+					receipts,
+					error: Some(error.wrap_err(format!("Data round {round} of {total} failed"))),
+				});
+			}
+		}
+
+		info!(app_id, rounds = total, "Data submitted");
+		Ok(BatchReceipts {
+			receipts,
+			error: None,
+		})
+	}
+
+	fn sign(
+		&self,
+		builder: &ExtrinsicBuilder,
+		app_id: u32,
+		call: &[u8],
+		nonce: u32,
+		tip: u128,
+	) -> Vec<u8> {
+		let params = ExtrinsicParams {
+			nonce,
+			app_id,
+			tip,
+			..Default::default()
+		};
+		builder.sign(&builder.payload(call.to_vec(), params), &self.pair)
+	}
+
+	/// Validates and submits extrinsic, so invalid extrinsics fail with the reason (e.g. `invalid.Payment`), and
+	/// waits until it is finalized
+	async fn submit(&self, extrinsic: Vec<u8>) -> Result<ExtrinsicEvents<AvailConfig>> {
+		self.rpc_client.validate_transaction(&extrinsic).await?;
+		self.rpc_client
+			.submit_from_bytes_and_wait_for_finalized(extrinsic)
+			.await
+	}
+
+	/// Submits extrinsic, replacing it with the higher tip while it is stalled, until one of the submitted
+	/// extrinsics is finalized
+	async fn submit_and_replace(
+		&self,
+		builder: &ExtrinsicBuilder,
+		app_id: u32,
+		call: &[u8],
+		nonce: u32,
+	) -> Result<ExtrinsicEvents<AvailConfig>> {
+		let Some(tips) = &self.tips else {
+			let extrinsic = self.sign(builder, app_id, call, nonce, 0);
+			return self.submit(extrinsic).await;
+		};
+
+		let started = Instant::now();
+		let mut submitted = FuturesUnordered::new();
+		let mut replacements = 0;
+		let mut replaced_tip = None;
+		loop {
+			let attempt = Attempt {
+				replacements,
+				elapsed: started.elapsed(),
+			};
+			let mut tip = tips.strategy.tip(&tips.history.blocks(), attempt);
+			if let Some(replaced) = replaced_tip {
+				tip = replacement_tip(replaced, tip);
+			}
+			replaced_tip = Some(tip);
+			debug!(nonce, tip, replacements, "Submitting data extrinsic");
+			let extrinsic = self.sign(builder, app_id, call, nonce, tip);
+			submitted.push(self.submit(extrinsic));
+
+			let stall = sleep(tips.stall_timeout);
+			tokio::pin!(stall);
+			let can_replace = replacements < tips.max_replacements;
+			loop {
+				tokio::select! {
+					result = submitted.next() => match result {
+						Some(Ok(events)) => return Ok(events),
+						// Replaced extrinsics are usurped, so only the error of the last one is returned
+						Some(Err(error)) if submitted.is_empty() => return Err(error),
+						Some(Err(error)) => debug!(nonce, "Replaced extrinsic failed: {error:#}"),
+						None => return Err(eyre!("No extrinsic is submitted")),
+					},
+					_ = &mut stall, if can_replace => break,
+				}
+			}
+
+			// Extrinsic can be included meanwhile, and awaited without replacement
+			if let Err(error) = self.nonces.replacement_nonce(&self.account, nonce).await {
+				warn!(nonce, "Extrinsic is not replaced: {error:#}");
+				while let Some(result) = submitted.next().await {
+					match result {
+						Ok(events) => return Ok(events),
+						Err(error) if submitted.is_empty() => return Err(error),
+						Err(_) => continue,
+					}
+				}
+				return Err(eyre!("No extrinsic is submitted"));
+			}
+			replacements += 1;
+			info!(nonce, replacements, "Replacing stalled extrinsic");
+		}
+	}
+
+	async fn submit_chunk(
+		&self,
+		builder: &ExtrinsicBuilder,
+		app_id: u32,
+		call: Vec<u8>,
+	) -> Result<InclusionReceipt> {
+		// Nonce is released if submission fails or this future is dropped
+		let nonce = self.nonces.allocate(&self.account).await?;
+		let events = self
+			.submit_and_replace(builder, app_id, &call, nonce.value())
+			.await
+			.wrap_err_with(|| format!("Failed to submit data with nonce {}", nonce.value()))?;
+		nonce.included();
+
+		let block_hash = events.block_hash();
+		let extrinsic_index = events.extrinsic_index();
+		let block_number = self.rpc_client.get_header_by_hash(block_hash).await?.number;
+		let data_proof = self
+			.rpc_client
+			.request_data_proof(extrinsic_index, block_hash)
+			.await
+			.wrap_err("Failed to fetch data proof")?;
+
+		Ok(InclusionReceipt {
+			block_number,
+			block_hash,
+			extrinsic_hash: events.extrinsic_hash(),
+			extrinsic_index,
+			data_proof,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use test_case::test_case;
+
+	const LIMITS: SubmissionLimits = SubmissionLimits {
+		max_extrinsic_data: 4,
+		max_block_data: 8,
+	};
+
+	fn sizes(rounds: &[Vec<Chunk>]) -> Vec<Vec<(usize, usize)>> {
+		rounds
+			.iter()
+			.map(|round| round.iter().map(|c| (c.blob, c.data.len())).collect())
+			.collect()
+	}
+
+	#[test_case(vec![vec![1; 3]] => vec![vec![(0, 3)]] ; "single chunk")]
+	#[test_case(vec![vec![1; 10]] => vec![vec![(0, 4), (0, 4)], vec![(0, 2)]] ; "oversized blob")]
+	#[test_case(vec![vec![1; 3], vec![2; 3], vec![3; 3]] => vec![vec![(0, 3), (1, 3)], vec![(2, 3)]] ; "multiple blobs")]
+	fn plan_rounds(blobs: Vec<Vec<u8>>) -> Vec<Vec<(usize, usize)>> {
+		sizes(&plan(blobs, &LIMITS).unwrap())
+	}
+
+	#[test]
+	fn plan_preserves_data() {
+		let blob = (0..=255).collect::<Vec<u8>>();
+		let rounds = plan(vec![blob.clone()], &LIMITS).unwrap();
+		let data = rounds
+			.into_iter()
+			.flatten()
+			.flat_map(|c| c.data)
+			.collect::<Vec<_>>();
+		assert_eq!(data, blob);
+	}
+
+	#[test_case(vec![vec![]], LIMITS ; "empty blob")]
+	#[test_case(vec![vec![1]], SubmissionLimits { max_extrinsic_data: 16, max_block_data: 8 } ; "extrinsic over block limit")]
+	fn plan_fails(blobs: Vec<Vec<u8>>, limits: SubmissionLimits) {
+		assert!(plan(blobs, &limits).is_err());
+	}
+
+	#[test]
+	fn encode_submit_data_call() {
+		let call_indices = CallIndices {
+			submit_data: (29, 1),
+			create_application_key: (29, 0),
+		};
+		assert_eq!(
+			submit_data_call(&call_indices, &[7, 7]),
+			vec![29, 1, 8, 7, 7]
+		);
+	}
+}
@@ -0,0 +1,275 @@
+//! Unified subscriptions to light client events.
+//!
+//! Each event type is backed by its own broadcast channel, so slow consumers of one event type don't affect
+//! the others. Behaviour of slow consumers (lagging behind the channel capacity) is defined by [`LagPolicy`].
+//!
+//! # Event types
+//!
+//! * New heads - headers as they are imported
+//! * Finalized heads - GRANDPA verified headers
+//! * Best block changes
+//! * Storage changes - verified changes of the watched storage keys, published by
+//!   [`storage_proof::run`](crate::storage_proof::run)
+//! * DA confidence updates - confidence achieved by sampling subsystem
+//! * Runtime updates - runtime upgrades detected by [`runtime_upgrade`](crate::runtime_upgrade) monitor
+
+use avail_subxt::{primitives::Header, utils::H256};
+use codec::Encode;
+use color_eyre::{eyre::eyre, Result};
+use futures::Stream;
+use sp_core::blake2_256;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, info, warn};
+
+use crate::{network::rpc::Event, runtime_upgrade::RuntimeUpdated, types::BlockVerified};
+
+/// Defines what happens when consumer is lagging behind the channel capacity
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LagPolicy {
+	/// Skip missed events and continue with the oldest retained event
+	#[default]
+	Skip,
+	/// Fail the subscription, consumer is expected to resubscribe and resync
+	Fail,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BestBlock {
+	pub number: u32,
+	pub hash: H256,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StorageChange {
+	pub block_hash: H256,
+	pub key: Vec<u8>,
+	/// New value, `None` if value is removed
+	pub value: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfidenceUpdate {
+	pub block_number: u32,
+	pub block_hash: H256,
+	pub confidence: Option<f64>,
+}
+
+pub struct Subscription<T: Clone> {
+	name: &'static str,
+	receiver: broadcast::Receiver<T>,
+	policy: LagPolicy,
+}
+
+impl<T: Clone> Subscription<T> {
+	/// Receives next event, returns `None` if publisher is dropped.
+	pub async fn next(&mut self) -> Result<Option<T>> {
+		loop {
+			match self.receiver.recv().await {
+				Ok(event) => return Ok(Some(event)),
+				Err(RecvError::Closed) => return Ok(None),
+				Err(RecvError::Lagged(skipped)) => match self.policy {
+					LagPolicy::Skip => {
+						warn!(subscription = self.name, skipped, "Subscriber lagged");
+						continue;
+					},
+					LagPolicy::Fail => {
+						return Err(eyre!(
+							"{} subscriber lagged, {skipped} events skipped",
+							self.name
+						));
+					},
+				},
+			}
+		}
+	}
+
+	pub fn into_stream(mut self) -> impl Stream<Item = Result<T>> {
+		async_stream::stream! {
+			loop {
+				match self.next().await {
+					Ok(Some(event)) => yield Ok(event),
+					Ok(None) => return,
+					Err(error) => {
+						yield Err(error);
+						return;
+					},
+				}
+			}
+		}
+	}
+}
+
+/// Publishers and subscription factory for all the event types
+#[derive(Clone)]
+pub struct Subscriptions {
+	new_heads: broadcast::Sender<Header>,
+	finalized_heads: broadcast::Sender<Header>,
+	best_block: broadcast::Sender<BestBlock>,
+	storage_changes: broadcast::Sender<StorageChange>,
+	confidence: broadcast::Sender<ConfidenceUpdate>,
+	runtime_updates: broadcast::Sender<RuntimeUpdated>,
+}
+
+impl Subscriptions {
+	/// Creates subscriptions with given capacity of each event channel.
+	pub fn new(capacity: usize) -> Self {
+		Subscriptions {
+			new_heads: broadcast::channel(capacity).0,
+			finalized_heads: broadcast::channel(capacity).0,
+			best_block: broadcast::channel(capacity).0,
+			storage_changes: broadcast::channel(capacity).0,
+			confidence: broadcast::channel(capacity).0,
+			runtime_updates: broadcast::channel(capacity).0,
+		}
+	}
+
+	// Sending fails only if there are no subscribers, which is not an error
+	pub fn publish_new_head(&self, header: Header) {
+		let _ = self.new_heads.send(header);
+	}
+
+	pub fn publish_finalized_head(&self, header: Header) {
+		let _ = self.finalized_heads.send(header);
+	}
+
+	pub fn publish_best_block(&self, best_block: BestBlock) {
+		let _ = self.best_block.send(best_block);
+	}
+
+	pub fn publish_storage_change(&self, change: StorageChange) {
+		let _ = self.storage_changes.send(change);
+	}
+
+	pub fn publish_confidence(&self, update: ConfidenceUpdate) {
+		let _ = self.confidence.send(update);
+	}
+
+	/// Sender of runtime updates, to be used by runtime upgrade monitor
+	pub fn runtime_updates_sender(&self) -> broadcast::Sender<RuntimeUpdated> {
+		self.runtime_updates.clone()
+	}
+
+	pub fn new_heads(&self, policy: LagPolicy) -> Subscription<Header> {
+		subscription("new_heads", &self.new_heads, policy)
+	}
+
+	pub fn finalized_heads(&self, policy: LagPolicy) -> Subscription<Header> {
+		subscription("finalized_heads", &self.finalized_heads, policy)
+	}
+
+	pub fn best_block(&self, policy: LagPolicy) -> Subscription<BestBlock> {
+		subscription("best_block", &self.best_block, policy)
+	}
+
+	pub fn storage_changes(&self, policy: LagPolicy) -> Subscription<StorageChange> {
+		subscription("storage_changes", &self.storage_changes, policy)
+	}
+
+	pub fn confidence(&self, policy: LagPolicy) -> Subscription<ConfidenceUpdate> {
+		subscription("confidence", &self.confidence, policy)
+	}
+
+	pub fn runtime_updates(&self, policy: LagPolicy) -> Subscription<RuntimeUpdated> {
+		subscription("runtime_updates", &self.runtime_updates, policy)
+	}
+}
+
+fn subscription<T: Clone>(
+	name: &'static str,
+	sender: &broadcast::Sender<T>,
+	policy: LagPolicy,
+) -> Subscription<T> {
+	Subscription {
+		name,
+		receiver: sender.subscribe(),
+		policy,
+	}
+}
+
+/// Forwards finalized headers from RPC subscription loop and confidence from the light client to subscriptions.
+/// Since only finalized headers are processed, each finalized header is published as a new head and best block too.
+pub async fn run(
+	subscriptions: Subscriptions,
+	mut rpc_events: broadcast::Receiver<Event>,
+	mut block_receiver: broadcast::Receiver<BlockVerified>,
+) {
+	info!("Starting subscriptions...");
+
+	loop {
+		tokio::select! {
+			event = rpc_events.recv() => match event {
+				Ok(Event::HeaderUpdate { header, .. }) => {
+					let hash = Encode::using_encoded(&header, blake2_256).into();
+					subscriptions.publish_new_head(header.clone());
+					subscriptions.publish_best_block(BestBlock { number: header.number, hash });
+					subscriptions.publish_finalized_head(header);
+				},
+				Err(RecvError::Lagged(skipped)) => warn!(skipped, "Finalized headers receiver lagged"),
+				Err(RecvError::Closed) => {
+					error!("Finalized headers channel closed");
+					return;
+				},
+			},
+			block = block_receiver.recv() => match block {
+				Ok(block) => subscriptions.publish_confidence(ConfidenceUpdate {
+					block_number: block.block_num,
+					block_hash: block.header_hash,
+					confidence: block.confidence,
+				}),
+				Err(RecvError::Lagged(skipped)) => warn!(skipped, "Verified blocks receiver lagged"),
+				Err(RecvError::Closed) => {
+					error!("Verified blocks channel closed");
+					return;
+				},
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::StreamExt;
+
+	fn best_block(number: u32) -> BestBlock {
+		BestBlock {
+			number,
+			hash: H256::repeat_byte(number as u8),
+		}
+	}
+
+	#[tokio::test]
+	async fn skip_lagged_events() {
+		let subscriptions = Subscriptions::new(2);
+		let mut subscription = subscriptions.best_block(LagPolicy::Skip);
+		for number in 1..=3 {
+			subscriptions.publish_best_block(best_block(number));
+		}
+		assert_eq!(subscription.next().await.unwrap(), Some(best_block(2)));
+		assert_eq!(subscription.next().await.unwrap(), Some(best_block(3)));
+	}
+
+	#[tokio::test]
+	async fn fail_on_lagged_events() {
+		let subscriptions = Subscriptions::new(2);
+		let subscription = subscriptions.best_block(LagPolicy::Fail);
+		for number in 1..=3 {
+			subscriptions.publish_best_block(best_block(number));
+		}
+		drop(subscriptions);
+		let results = subscription.into_stream().collect::<Vec<_>>().await;
+		assert_eq!(results.len(), 1);
+		assert!(results[0].is_err());
+	}
+
+	#[tokio::test]
+	async fn stream_ends_when_publisher_is_dropped() {
+		let subscriptions = Subscriptions::new(2);
+		let subscription = subscriptions.best_block(LagPolicy::Skip);
+		subscriptions.publish_best_block(best_block(1));
+		drop(subscriptions);
+		let results = subscription.into_stream().collect::<Vec<_>>().await;
+		assert_eq!(results.len(), 1);
+		assert_eq!(results[0].as_ref().unwrap(), &best_block(1));
+	}
+}
@@ -0,0 +1,219 @@
+//! Supervision of the background tasks.
+//!
+//! Every task (sync, network, sampling, RPC...) is spawned through the [`Supervisor`], which cancels it on shutdown
+//! and keeps its completion receiver, so the shutdown can wait for all tasks to finish. Dropping the receiver (e.g.
+//! when the join times out) doesn't cancel the task. Panicking task either triggers shutdown,
+//! or is restarted, depending on its [`RestartPolicy`]. Tasks are spawned with the [`Runtime`], which is tokio by
+//! default.
+
+use futures::{channel::oneshot, future::Shared, FutureExt};
+use std::{
+	future::Future,
+	panic::AssertUnwindSafe,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+use tracing::{debug, error, warn};
+
+use crate::{
+	runtime::{Runtime, TokioRuntime},
+	shutdown::Controller,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RestartPolicy {
+	/// Panic of the task triggers shutdown
+	Never,
+	/// Task is restarted after the delay, until `max_restarts` is reached, after which shutdown is triggered
+	OnPanic {
+		max_restarts: usize,
+		delay: Duration,
+	},
+}
+
+#[derive(Clone)]
+pub struct Supervisor<R: Runtime = TokioRuntime> {
+	shutdown: Controller<String>,
+	runtime: R,
+	tasks: Arc<Mutex<Vec<(&'static str, Shared<oneshot::Receiver<()>>)>>>,
+}
+
+fn panicked(shutdown: &Controller<String>, name: &'static str) {
+	error!(task = name, "Task panicked");
+	let _ = shutdown.trigger_shutdown(format!("Task {name} panicked"));
+}
+
+impl Supervisor {
+	pub fn new(shutdown: Controller<String>) -> Self {
+		Supervisor::with_runtime(shutdown, TokioRuntime)
+	}
+}
+
+impl<R: Runtime> Supervisor<R> {
+	pub fn with_runtime(shutdown: Controller<String>, runtime: R) -> Self {
+		Supervisor {
+			shutdown,
+			runtime,
+			tasks: Default::default(),
+		}
+	}
+
+	pub fn shutdown(&self) -> &Controller<String> {
+		&self.shutdown
+	}
+
+	pub fn runtime(&self) -> &R {
+		&self.runtime
+	}
+
+	/// Spawns the supervising future, keeping its completion receiver until joined
+	fn register(&self, name: &'static str, future: impl Future<Output = ()> + Send + 'static) {
+		let (sender, receiver) = oneshot::channel();
+		self.runtime.spawn(Box::pin(async move {
+			future.await;
+			_ = sender.send(());
+		}));
+		debug!(task = name, "Task spawned");
+		self.tasks.lock().unwrap().push((name, receiver.shared()));
+	}
+
+	/// Spawns task which runs until completion or shutdown. Panic of the task triggers shutdown.
+	pub fn spawn<F>(&self, name: &'static str, future: F)
+	where
+		F: Future + Send + 'static,
+		F::Output: Send + 'static,
+	{
+		let shutdown = self.shutdown.clone();
+		let task = AssertUnwindSafe(shutdown.with_cancel(future)).catch_unwind();
+		self.register(name, async move {
+			if task.await.is_err() {
+				panicked(&shutdown, name);
+			}
+		});
+	}
+
+	/// Spawns task created by the `factory`, which is called again to restart the task after panic.
+	pub fn spawn_restartable<F, Fut>(&self, name: &'static str, policy: RestartPolicy, factory: F)
+	where
+		F: Fn() -> Fut + Send + 'static,
+		Fut: Future + Send + 'static,
+		Fut::Output: Send + 'static,
+	{
+		let shutdown = self.shutdown.clone();
+		let runtime = self.runtime.clone();
+		self.register(name, async move {
+			let mut restarts = 0;
+			loop {
+				let result = AssertUnwindSafe(shutdown.with_cancel(factory()))
+					.catch_unwind()
+					.await;
+				if result.is_ok() {
+					return;
+				}
+				match policy {
+					RestartPolicy::OnPanic {
+						max_restarts,
+						delay,
+					} if restarts < max_restarts => {
+						restarts += 1;
+						warn!(task = name, restarts, ?delay, "Restarting panicked task");
+						if shutdown.with_cancel(runtime.sleep(delay)).await.is_err() {
+							return;
+						}
+					},
+					_ => return panicked(&shutdown, name),
+				}
+			}
+		});
+	}
+
+	/// Waits until all spawned tasks are finished, including tasks spawned while waiting.
+	/// Tasks are removed only when finished, so the join can be retried after it is dropped.
+	pub async fn join(&self) {
+		loop {
+			let Some((name, task)) = self.tasks.lock().unwrap().first().cloned() else {
+				return;
+			};
+			// Sender is dropped without sending only if the runtime dropped the task
+			_ = task.await;
+			debug!(task = name, "Task finished");
+			self.tasks
+				.lock()
+				.unwrap()
+				.retain(|(_, task)| task.peek().is_none());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::runtime::AsyncStdRuntime;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	#[tokio::test]
+	async fn join_after_shutdown() {
+		let supervisor = Supervisor::new(Controller::new());
+		supervisor.spawn("pending", futures::future::pending::<()>());
+		supervisor.spawn("ready", async {});
+		supervisor
+			.shutdown()
+			.trigger_shutdown("test".to_string())
+			.unwrap();
+		supervisor.join().await;
+	}
+
+	#[tokio::test]
+	async fn dropped_join_keeps_tasks_running() {
+		let supervisor = Supervisor::new(Controller::new());
+		let (sender, receiver) = oneshot::channel();
+		supervisor.spawn("delayed", async move {
+			tokio::time::sleep(Duration::from_millis(20)).await;
+			_ = sender.send(());
+		});
+		let join = tokio::time::timeout(Duration::from_millis(1), supervisor.join());
+		assert!(join.await.is_err());
+		receiver.await.unwrap();
+		supervisor.join().await;
+	}
+
+	#[tokio::test]
+	async fn panic_triggers_shutdown() {
+		let supervisor = Supervisor::new(Controller::new());
+		supervisor.spawn("panicking", async { panic!("test") });
+		supervisor.join().await;
+		assert_eq!(
+			supervisor.shutdown().shutdown_reason(),
+			Some("Task panicking panicked".to_string())
+		);
+	}
+
+	#[async_std::test]
+	async fn panic_with_async_std_runtime() {
+		let supervisor = Supervisor::with_runtime(Controller::new(), AsyncStdRuntime);
+		supervisor.spawn("panicking", async { panic!("test") });
+		supervisor.join().await;
+		assert!(supervisor.shutdown().is_shutdown_triggered());
+	}
+
+	#[tokio::test]
+	async fn restart_on_panic() {
+		let supervisor = Supervisor::new(Controller::new());
+		let runs = Arc::new(AtomicUsize::new(0));
+		let task_runs = runs.clone();
+		let policy = RestartPolicy::OnPanic {
+			max_restarts: 2,
+			delay: Duration::from_millis(1),
+		};
+		supervisor.spawn_restartable("restartable", policy, move || {
+			let runs = task_runs.clone();
+			async move {
+				runs.fetch_add(1, Ordering::SeqCst);
+				panic!("test");
+			}
+		});
+		supervisor.join().await;
+		assert_eq!(runs.load(Ordering::SeqCst), 3);
+		assert!(supervisor.shutdown().is_shutdown_triggered());
+	}
+}
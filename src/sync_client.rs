@@ -4,6 +4,8 @@
 //!
 //! # Flow
 //!
+//! * Headers of the upcoming blocks are prefetched in pipelined batches, if the RPC client follows the chain head
+//!   (see [`crate::network::rpc::pipeline`])
 //! * For each block, fetches block header from RPC and stores it into database
 //! * Generate random cells for random data sampling
 //! * Retrieve cell proofs from a) DHT and/or b) via RPC call from the node, in that order
@@ -17,36 +19,42 @@
 
 use crate::{
 	data::{Database, Key},
+	header::cached::CachedHeader,
 	network::{
 		self,
 		rpc::{self, Client as RpcClient},
 	},
 	types::{BlockVerified, OptionBlockRange, State, SyncClientConfig},
-	utils::{calculate_confidence, extract_app_lookup, extract_kate},
+	utils::{extract_app_lookup, extract_kate},
 };
 
 use async_trait::async_trait;
 use avail_subxt::{primitives::Header as DaHeader, utils::H256};
-use codec::Encode;
 use color_eyre::{
 	eyre::{eyre, WrapErr},
 	Result,
 };
 use kate_recovery::{commitments, matrix::Dimensions};
 use mockall::automock;
-use sp_core::blake2_256;
 use std::{
+	collections::HashMap,
 	ops::Range,
 	sync::{Arc, Mutex},
 	time::Instant,
 };
 use tokio::sync::broadcast;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, instrument, warn};
+
+/// Number of the upcoming blocks, whose headers are prefetched at once
+pub const PREFETCH_WINDOW: usize = 256;
 
 #[async_trait]
 #[automock]
 pub trait Client {
 	async fn get_header_by_block_number(&self, block_number: u32) -> Result<(DaHeader, H256)>;
+	/// Prefetches headers of the blocks, so they are not fetched one by one. Failures are not fatal, headers which are
+	/// not prefetched are fetched by the block number.
+	async fn prefetch_headers(&self, block_numbers: &[u32]);
 	fn is_confidence_stored(&self, block_number: u32) -> Result<bool>;
 	fn store_confidence(&self, count: u32, block_number: u32) -> Result<()>;
 }
@@ -55,11 +63,17 @@ pub trait Client {
 pub struct SyncClient<T: Database + Sync> {
 	db: T,
 	rpc_client: RpcClient,
+	/// Prefetched headers of the current window, by the block number
+	prefetched: Arc<Mutex<HashMap<u32, (DaHeader, H256)>>>,
 }
 
 impl<T: Database + Sync> SyncClient<T> {
 	pub fn new(db: T, rpc_client: RpcClient) -> Self {
-		SyncClient { db, rpc_client }
+		SyncClient {
+			db,
+			rpc_client,
+			prefetched: Default::default(),
+		}
 	}
 }
 
@@ -71,21 +85,23 @@ impl<T: Database + Sync> Client for SyncClient<T> {
 			.get(Key::BlockHeader(block_number))
 			.wrap_err("Sync Client failed to get Block Header from the storage")?
 		{
-			let hash: H256 = Encode::using_encoded(&header, blake2_256).into();
-			return Ok((header, hash));
+			let header = CachedHeader::new(header);
+			let hash = header.hash();
+			return Ok((header.into_inner(), hash));
 		}
 
-		let (header, hash) = match self
-			.rpc_client
-			.get_header_by_block_number(block_number)
-			.await
-			.wrap_err_with(|| {
-				format!(
-					"Sync Client failed to get Block {block_number:#?} by Block Number from storage",
-				)
-			}) {
-			Ok(value) => value,
-			Err(error) => return Err(error),
+		let prefetched = self.prefetched.lock().unwrap().remove(&block_number);
+		let (header, hash) = match prefetched {
+			Some(value) => value,
+			None => self
+				.rpc_client
+				.get_header_by_block_number(block_number)
+				.await
+				.wrap_err_with(|| {
+					format!(
+						"Sync Client failed to get Block {block_number:#?} by Block Number from storage",
+					)
+				})?,
 		};
 
 		self.db
@@ -95,6 +111,36 @@ impl<T: Database + Sync> Client for SyncClient<T> {
 		Ok((header, hash))
 	}
 
+	async fn prefetch_headers(&self, block_numbers: &[u32]) {
+		if !self.rpc_client.is_pipelined() {
+			return;
+		}
+		let missing = block_numbers
+			.iter()
+			.copied()
+			.filter(|&block_number| {
+				matches!(
+					self.db.get::<DaHeader>(Key::BlockHeader(block_number)),
+					Ok(None)
+				)
+			})
+			.collect::<Vec<_>>();
+		if missing.is_empty() {
+			return;
+		}
+		match self.rpc_client.get_headers_by_block_number(&missing).await {
+			Ok(headers) => {
+				let mut prefetched = self.prefetched.lock().unwrap();
+				prefetched.clear();
+				prefetched.extend(missing.into_iter().zip(headers));
+			},
+			Err(error) => debug!(
+				count = missing.len(),
+				"Headers are not prefetched: {error:#}"
+			),
+		}
+	}
+
 	fn is_confidence_stored(&self, block_number: u32) -> Result<bool> {
 		self.db
 			.get(Key::VerifiedCellCount(block_number))
@@ -109,6 +155,7 @@ impl<T: Database + Sync> Client for SyncClient<T> {
 	}
 }
 
+#[instrument(skip_all, fields(block_number = header.number, block_hash = %header_hash), level = "info")]
 async fn process_block(
 	client: &impl Client,
 	network_client: &impl network::Client,
@@ -130,9 +177,14 @@ async fn process_block(
 
 	let commitments = commitments::from_slice(&commitment)?;
 
-	// now this is in `u64`
-	let cell_count = rpc::cell_count_for_confidence(cfg.confidence);
-	let positions = rpc::generate_random_cells(dimensions, cell_count);
+	let cell_count = cfg.sampling.confidence.cell_count(cfg.confidence);
+	let positions = match &app_lookup {
+		Ok(lookup) => cfg
+			.sampling
+			.strategy
+			.positions(dimensions, lookup, cell_count),
+		Err(_) => rpc::generate_random_cells(dimensions, cell_count),
+	};
 
 	let (fetched, unfetched, _fetch_stats) = network_client
 		.fetch_verified(
@@ -152,7 +204,7 @@ async fn process_block(
 	// write confidence factor into on-disk database
 	client.store_confidence(fetched.len().try_into()?, block_number)?;
 
-	let confidence = Some(calculate_confidence(fetched.len() as u32));
+	let confidence = Some(cfg.sampling.confidence.confidence(fetched.len() as u32));
 	let client_msg =
 		BlockVerified::try_from((header, confidence)).wrap_err("converting to message failed")?;
 
@@ -160,6 +212,8 @@ async fn process_block(
 		error!("Cannot send block verified message: {error}");
 	}
 
+	debug!(block_number, elapsed = ?begin.elapsed(), "Synced block processed");
+
 	Ok(())
 }
 
@@ -189,7 +243,12 @@ pub async fn run(
 	}
 
 	info!("Syncing block headers for {sync_range:?}");
-	for block_number in sync_range {
+	let block_numbers = sync_range.collect::<Vec<_>>();
+	for (index, &block_number) in block_numbers.iter().enumerate() {
+		if index % PREFETCH_WINDOW == 0 {
+			let window = &block_numbers[index..(index + PREFETCH_WINDOW).min(block_numbers.len())];
+			client.prefetch_headers(window).await;
+		}
 		// TODO: This is still an ambiguous check since data fetch can fail.
 		// We should write block status in DB explicitly.
 		match client.is_confidence_stored(block_number) {
@@ -0,0 +1,373 @@
+//! Driver of the [`SyncMachine`], which executes its requests with the [`Transport`].
+//!
+//! # Flow
+//!
+//! * Inputs of the caller (announcements, finality, peers) are handled by the machine at the current time
+//! * Requested headers and header ranges are fetched with the transport in the background, and the responses are fed
+//!   back to the machine
+//! * Machine is ticked every [`TICK_INTERVAL`], so the expired requests are retried
+//! * Remaining actions (new best block, finalized block, announcements) are returned to the caller
+//!
+//! # Notes
+//!
+//! Received headers are kept until finalized (up to [`MAX_HEADERS`]), so the caller can read the unfinalized chain
+//! by hash. [`RpcTransport`] fetches the headers from the RPC node, which is the single peer of the machine.
+//! [`P2pTransport`] fetches the headers from the light server peers, with remote header requests, and from the RPC
+//! node for its peer ID, if configured.
+
+use async_trait::async_trait;
+use avail_subxt::{primitives::Header, utils::H256};
+use codec::Encode;
+use color_eyre::{eyre::eyre, Result};
+use futures::{future::BoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
+use libp2p::PeerId;
+use mockall::automock;
+use std::{collections::HashMap, sync::Arc, time::Instant};
+use tokio::time::{self, Interval, MissedTickBehavior};
+use tracing::debug;
+
+use crate::{
+	header::cached::CachedHeader,
+	memory::MemoryBudget,
+	network::{
+		p2p::{
+			self,
+			light_server_protocol::{LightRequest, LightResponse},
+		},
+		rpc,
+	},
+	sync_machine::{Action, Input, SyncMachine, MAX_ORPHANS, TICK_INTERVAL},
+};
+
+/// Maximum number of kept unfinalized headers
+pub const MAX_HEADERS: usize = 2 * MAX_ORPHANS;
+
+#[async_trait]
+#[automock]
+pub trait Transport {
+	/// Fetches SCALE encoded header from the peer, `None` if peer doesn't have it
+	async fn header(&self, peer_id: PeerId, hash: H256) -> Result<Option<Vec<u8>>>;
+	/// Fetches up to `count` SCALE encoded headers of the peer's best chain, starting with the block number `from`
+	async fn headers(&self, peer_id: PeerId, from: u32, count: u32) -> Result<Vec<Vec<u8>>>;
+}
+
+/// Fetches headers from the RPC node, regardless of the peer
+#[derive(Clone)]
+pub struct RpcTransport {
+	rpc_client: rpc::Client,
+}
+
+impl RpcTransport {
+	pub fn new(rpc_client: rpc::Client) -> Self {
+		RpcTransport { rpc_client }
+	}
+}
+
+#[async_trait]
+impl Transport for RpcTransport {
+	async fn header(&self, _: PeerId, hash: H256) -> Result<Option<Vec<u8>>> {
+		let header = self.rpc_client.get_header_by_hash(hash).await?;
+		Ok(Some(header.encode()))
+	}
+
+	async fn headers(&self, _: PeerId, from: u32, count: u32) -> Result<Vec<Vec<u8>>> {
+		let mut headers = vec![];
+		for number in from..from.saturating_add(count) {
+			match self.rpc_client.get_header_by_block_number(number).await {
+				Ok((header, _)) => headers.push(header.encode()),
+				// Partial responses are accepted, the rest of the range is requested again
+				Err(_) if !headers.is_empty() => break,
+				Err(error) => return Err(error),
+			}
+		}
+		Ok(headers)
+	}
+}
+
+/// Fetches headers from the light server peers, and from the RPC node for its peer ID
+#[derive(Clone)]
+pub struct P2pTransport {
+	p2p_client: p2p::Client,
+	rpc: Option<(PeerId, RpcTransport)>,
+}
+
+impl P2pTransport {
+	pub fn new(p2p_client: p2p::Client) -> Self {
+		P2pTransport {
+			p2p_client,
+			rpc: None,
+		}
+	}
+
+	/// Fetches headers of the given peer from the RPC node
+	pub fn with_rpc(mut self, peer_id: PeerId, rpc_transport: RpcTransport) -> Self {
+		self.rpc = Some((peer_id, rpc_transport));
+		self
+	}
+
+	fn rpc(&self, peer_id: PeerId) -> Option<&RpcTransport> {
+		self.rpc
+			.as_ref()
+			.filter(|(rpc_peer, _)| *rpc_peer == peer_id)
+			.map(|(_, rpc_transport)| rpc_transport)
+	}
+}
+
+#[async_trait]
+impl Transport for P2pTransport {
+	async fn header(&self, peer_id: PeerId, hash: H256) -> Result<Option<Vec<u8>>> {
+		if let Some(rpc_transport) = self.rpc(peer_id) {
+			return rpc_transport.header(peer_id, hash).await;
+		}
+		let request = LightRequest::RemoteHeaderByHash { block_hash: hash.0 };
+		match self.p2p_client.request_light(peer_id, request).await? {
+			LightResponse::Header(header) => Ok(header),
+			_ => Err(eyre!("Unexpected response to the remote header request")),
+		}
+	}
+
+	async fn headers(&self, peer_id: PeerId, from: u32, count: u32) -> Result<Vec<Vec<u8>>> {
+		if let Some(rpc_transport) = self.rpc(peer_id) {
+			return rpc_transport.headers(peer_id, from, count).await;
+		}
+		let request = LightRequest::RemoteHeaders { from, count };
+		match self.p2p_client.request_light(peer_id, request).await? {
+			// Headers are checked by the machine, same as the announced ones
+			LightResponse::Headers(headers) if headers.len() <= count as usize => Ok(headers),
+			_ => Err(eyre!("Unexpected response to the remote headers request")),
+		}
+	}
+}
+
+pub struct SyncDriver<T: Transport> {
+	machine: SyncMachine,
+	transport: Arc<T>,
+	responses: FuturesUnordered<BoxFuture<'static, Input>>,
+	ticks: Interval,
+	/// Received unfinalized headers, by hash
+	headers: HashMap<H256, CachedHeader>,
+}
+
+impl<T: Transport + Send + Sync + 'static> SyncDriver<T> {
+	pub fn new(machine: SyncMachine, transport: T) -> Self {
+		let mut ticks = time::interval_at(time::Instant::now() + TICK_INTERVAL, TICK_INTERVAL);
+		ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+		SyncDriver {
+			machine,
+			transport: Arc::new(transport),
+			responses: FuturesUnordered::new(),
+			ticks,
+			headers: HashMap::new(),
+		}
+	}
+
+	/// Accounts imported blocks in the memory budget, see [`SyncMachine::with_budget`]
+	pub fn with_budget(self, budget: Arc<MemoryBudget>) -> Self {
+		SyncDriver {
+			machine: self.machine.with_budget(budget),
+			..self
+		}
+	}
+
+	pub fn machine(&self) -> &SyncMachine {
+		&self.machine
+	}
+
+	/// Returns received unfinalized header
+	pub fn header(&self, hash: &H256) -> Option<&CachedHeader> {
+		self.headers.get(hash)
+	}
+
+	fn keep(&mut self, encoded: &[u8]) {
+		if self.headers.len() >= MAX_HEADERS {
+			return;
+		}
+		if let Ok(header) = CachedHeader::<Header>::decode(encoded) {
+			self.headers.insert(header.hash(), header);
+		}
+	}
+
+	/// Handles the input, executes requests and returns the rest of the actions
+	pub fn handle(&mut self, input: Input) -> Vec<Action> {
+		match &input {
+			Input::Announce { announce, .. } => self.keep(&announce.header),
+			Input::HeaderResponse {
+				header: Some(header),
+				..
+			} => self.keep(header),
+			Input::HeadersResponse { headers, .. } => {
+				headers.iter().for_each(|header| self.keep(header))
+			},
+			_ => (),
+		}
+		let mut actions = vec![];
+		for action in self.machine.handle(Instant::now(), input) {
+			match action {
+				Action::RequestHeader {
+					request_id,
+					peer_id,
+					hash,
+				} => {
+					let transport = self.transport.clone();
+					let response = async move {
+						match transport.header(peer_id, hash).await {
+							Ok(header) => Input::HeaderResponse { request_id, header },
+							Err(error) => {
+								debug!(%peer_id, ?hash, "Header request failed: {error:#}");
+								Input::RequestFailed { request_id }
+							},
+						}
+					};
+					self.responses.push(response.boxed());
+				},
+				Action::RequestHeaders {
+					request_id,
+					peer_id,
+					from,
+					count,
+				} => {
+					let transport = self.transport.clone();
+					let response = async move {
+						match transport.headers(peer_id, from, count).await {
+							Ok(headers) => Input::HeadersResponse {
+								request_id,
+								headers,
+							},
+							Err(error) => {
+								debug!(%peer_id, from, count, "Headers request failed: {error:#}");
+								Input::RequestFailed { request_id }
+							},
+						}
+					};
+					self.responses.push(response.boxed());
+				},
+				Action::Finalized { hash, number } => {
+					self.headers.retain(|_, header| header.number > number);
+					actions.push(Action::Finalized { hash, number });
+				},
+				action => actions.push(action),
+			}
+		}
+		actions
+	}
+
+	/// Waits for the next response or tick, and returns the resulting actions
+	pub async fn next(&mut self) -> Vec<Action> {
+		let input = tokio::select! {
+			biased;
+			Some(input) = self.responses.next() => input,
+			_ = self.ticks.tick() => Input::Tick,
+		};
+		self.handle(input)
+	}
+
+	/// Fetches the header from the peer, if it is not received already
+	pub async fn fetch_header(&self, peer_id: PeerId, hash: H256) -> Result<CachedHeader> {
+		if let Some(header) = self.headers.get(&hash) {
+			return Ok(header.clone());
+		}
+		let encoded = self
+			.transport
+			.header(peer_id, hash)
+			.await?
+			.ok_or_else(|| eyre!("Header {hash:?} is not found"))?;
+		let header = CachedHeader::<Header>::decode(&encoded)?;
+		if header.hash() != hash {
+			return Err(eyre!("Received header doesn't match hash {hash:?}"));
+		}
+		Ok(header)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		fork_choice::{BlockInfo, LongestChain},
+		header::consistency::ExtensionLimits,
+		header::HeaderHash,
+		network::p2p::block_announce::BlockAnnounce,
+		simulation::{chain, header},
+	};
+
+	/// Genesis, and the chain of three headers
+	fn headers() -> (Header, Vec<Header>) {
+		let genesis = header(0, H256::zero(), 0);
+		let headers = chain(&genesis, 3, 0);
+		(genesis, headers)
+	}
+
+	fn driver(genesis: &Header, transport: MockTransport) -> SyncDriver<MockTransport> {
+		let finalized = BlockInfo {
+			hash: genesis.header_hash(),
+			number: 0,
+			parent_hash: H256::zero(),
+			confidence: None,
+		};
+		let machine = SyncMachine::new(
+			finalized,
+			Box::new(LongestChain),
+			ExtensionLimits::default(),
+		);
+		SyncDriver::new(machine, transport)
+	}
+
+	#[tokio::test]
+	async fn fetch_missing_parents() {
+		let (genesis, headers) = headers();
+		let parents = headers[..2].iter().map(Encode::encode).collect::<Vec<_>>();
+		let mut transport = MockTransport::new();
+		transport
+			.expect_headers()
+			.withf(|_, from, count| *from == 1 && *count == 2)
+			.returning(move |_, _, _| {
+				let parents = parents.clone();
+				Box::pin(async move { Ok(parents) })
+			});
+		let mut driver = driver(&genesis, transport);
+		let peer_id = PeerId::random();
+		driver.handle(Input::PeerConnected {
+			peer_id,
+			best_number: 3,
+		});
+
+		let announce = BlockAnnounce {
+			header: headers[2].encode(),
+			is_best: true,
+		};
+		assert!(driver
+			.handle(Input::Announce { peer_id, announce })
+			.is_empty());
+		assert_eq!(driver.machine().orphans(), 1);
+
+		let actions = driver.next().await;
+		let hash = headers[2].header_hash();
+		assert_eq!(actions[0], Action::NewBest { hash, number: 3 });
+		let parent_hash = headers[1].header_hash();
+		assert!(driver.header(&parent_hash).is_some());
+
+		let actions = driver.handle(Input::Finalized { hash });
+		assert_eq!(actions, vec![Action::Finalized { hash, number: 3 }]);
+		assert!(driver.header(&parent_hash).is_none());
+	}
+
+	#[tokio::test]
+	async fn fetch_header_by_hash() {
+		let (genesis, headers) = headers();
+		let encoded = headers[0].encode();
+		let mut transport = MockTransport::new();
+		transport.expect_header().returning(move |_, _| {
+			let encoded = encoded.clone();
+			Box::pin(async move { Ok(Some(encoded)) })
+		});
+		let driver = driver(&genesis, transport);
+		let peer_id = PeerId::random();
+		let hash = headers[0].header_hash();
+		let fetched = driver.fetch_header(peer_id, hash).await.unwrap();
+		assert_eq!(fetched.hash(), hash);
+
+		// Peer responds with the other header
+		let hash = headers[1].header_hash();
+		assert!(driver.fetch_header(peer_id, hash).await.is_err());
+	}
+}
@@ -1,13 +1,11 @@
 use async_trait::async_trait;
 use avail_subxt::primitives::Header;
-use codec::Encode;
 use color_eyre::{
 	eyre::{eyre, Context},
 	Result,
 };
 use futures::future::join_all;
 use sp_core::{
-	blake2_256,
 	ed25519::{self},
 	twox_128, H256,
 };
@@ -19,8 +17,10 @@ use subxt::{storage::StorageKey, utils::AccountId32};
 use tracing::{error, info, trace};
 
 use crate::{
-	data::{Database, FinalitySyncCheckpoint, Key},
+	data::{Database, EpochDescriptor, FinalitySyncCheckpoint, Key},
+	epochs::{self, RETAINED_EPOCHS},
 	finality::{check_finality, ValidatorSet},
+	header::cached::CachedHeader,
 	network::rpc::{self, WrappedProof},
 	shutdown::Controller,
 	types::State,
@@ -32,6 +32,9 @@ pub trait Client {
 	fn store_block_header(&self, block_number: u32, header: Header) -> Result<()>;
 	fn get_checkpoint(&self) -> Result<Option<FinalitySyncCheckpoint>>;
 	fn store_checkpoint(&self, checkpoint: FinalitySyncCheckpoint) -> Result<()>;
+	fn get_latest_epoch(&self) -> Result<Option<EpochDescriptor>>;
+	/// Stores the epoch as the next epoch of the previous one, and prunes old epochs
+	fn store_epoch(&self, epoch: EpochDescriptor) -> Result<()>;
 	async fn get_paged_storage_keys(
 		&self,
 		key: Vec<u8>,
@@ -150,6 +153,24 @@ impl<T: Database + Sync> Client for SyncFinality<T> {
 			.put(Key::FinalitySyncCheckpoint, checkpoint)
 			.wrap_err("Finality Sync Client failed to store Checkpoint")
 	}
+
+	fn get_latest_epoch(&self) -> Result<Option<EpochDescriptor>> {
+		epochs::latest_epoch(&self.db).wrap_err("Finality Sync Client failed to get latest Epoch")
+	}
+
+	fn store_epoch(&self, epoch: EpochDescriptor) -> Result<()> {
+		let set_id = epoch.set_id;
+		if let Some(previous) = set_id.checked_sub(1) {
+			epochs::announce_next_epoch(&self.db, previous, epoch.validator_set.clone())
+				.wrap_err("Finality Sync Client failed to store next Epoch")?;
+		}
+		epochs::store_epoch(&self.db, epoch)
+			.wrap_err("Finality Sync Client failed to store Epoch")?;
+		let pruned = epochs::prune_epochs(&self.db, set_id, RETAINED_EPOCHS)
+			.wrap_err("Finality Sync Client failed to prune Epochs")?;
+		trace!(set_id, pruned, "Epoch stored");
+		Ok(())
+	}
 }
 
 const GRANDPA_KEY_ID: [u8; 4] = *b"gran";
@@ -227,11 +248,12 @@ pub async fn run(
 pub async fn sync(
 	client: impl Client,
 	state: Arc<Mutex<State>>,
-	mut from_header: Header,
+	from_header: Header,
 ) -> Result<()> {
 	let gen_hash = client.get_genesis_hash().await?;
 
 	let checkpoint = client.get_checkpoint()?;
+	let latest_epoch = client.get_latest_epoch()?;
 
 	info!("Starting finality validation sync.");
 	let mut set_id: u64;
@@ -242,6 +264,14 @@ pub async fn sync(
 		set_id = ch.set_id;
 		validator_set = ch.validator_set;
 		curr_block_num = ch.number;
+	} else if let Some(epoch) = latest_epoch {
+		info!(
+			"No checkpoint found, continuing from epoch {}",
+			epoch.set_id
+		);
+		set_id = epoch.set_id;
+		validator_set = epoch.validator_set;
+		curr_block_num = epoch.start_block;
 	} else {
 		info!("No checkpoint found, starting from genesis.");
 		validator_set = get_valset_at_genesis(&client, gen_hash).await?;
@@ -251,6 +281,12 @@ pub async fn sync(
 			.await
 			.wrap_err(format!("Couldn't get set_id at {}", gen_hash))?;
 		info!("Set ID at genesis is {set_id}");
+		client.store_epoch(EpochDescriptor {
+			set_id,
+			start_block: curr_block_num,
+			validator_set: validator_set.clone(),
+			next_validator_set: None,
+		})?;
 	}
 
 	let last_block_num = from_header.number;
@@ -273,19 +309,17 @@ pub async fn sync(
 				"Couldn't get hash for block no. {}",
 				curr_block_num
 			))?;
-		from_header = client
+		let header = client
 			.get_header_by_hash(hash)
 			.await
+			.map(CachedHeader::new)
 			.wrap_err(format!("Couldn't get header for {}", hash))?;
-		client.store_block_header(curr_block_num, from_header.clone())?;
+		client.store_block_header(curr_block_num, header.header().clone())?;
 
-		assert_eq!(
-			from_header.parent_hash, prev_hash,
-			"Parent hash doesn't match!"
-		);
-		prev_hash = from_header.using_encoded(blake2_256).into();
+		assert_eq!(header.parent_hash, prev_hash, "Parent hash doesn't match!");
+		prev_hash = header.hash();
 
-		let next_validator_set = filter_auth_set_changes(&from_header);
+		let next_validator_set = filter_auth_set_changes(&header);
 		if next_validator_set.is_empty() {
 			curr_block_num += 1;
 			continue;
@@ -317,6 +351,12 @@ pub async fn sync(
 			.iter()
 			.map(|a| ed25519::Public::from_raw(a.0 .0 .0 .0))
 			.collect();
+		client.store_epoch(EpochDescriptor {
+			set_id: set_id + 1,
+			start_block: curr_block_num,
+			validator_set: validator_set.clone(),
+			next_validator_set: None,
+		})?;
 		set_id += 1;
 		client.store_checkpoint(FinalitySyncCheckpoint {
 			number: curr_block_num,
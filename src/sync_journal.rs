@@ -0,0 +1,248 @@
+//! Write-ahead journal of the sync progress, for resuming the sync after a crash.
+//!
+//! Sync progress ([`SyncProgress`]) consists of the headers which wait for finality, the announced validator set
+//! which is not active yet, and the position of the DA backlog (latest block sent to the sampling). Each change is
+//! recorded as a [`SyncEntry`] before it's applied, and the progress is checkpointed every [`CHECKPOINT_INTERVAL`]
+//! entries. After a restart, the checkpoint is loaded and the entries recorded after it are replayed, so the client
+//! resumes from the exact position where it stopped.
+//!
+//! # Notes
+//!
+//! Checkpoint is stored before the replayed entries are removed, so entries are never applied twice. Position of
+//! the DA backlog is recorded once the block is sent, so the block which was being sent during the crash is sent
+//! again after the restart.
+
+use avail_subxt::primitives::Header;
+use codec::{Decode, Encode};
+use color_eyre::{eyre::WrapErr, Result};
+use serde::{Deserialize, Serialize};
+use sp_core::ed25519;
+
+use crate::data::{Database, Key};
+
+/// Number of recorded entries after which the progress is checkpointed
+pub const CHECKPOINT_INTERVAL: u64 = 256;
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct PendingEpoch {
+	pub set_id: u64,
+	pub validator_set: Vec<ed25519::Public>,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone)]
+pub enum SyncEntry {
+	/// Header is received, and waits for finality
+	Unfinalized(Header),
+	/// Next validator set is announced
+	EpochAnnounced(PendingEpoch),
+	/// Announced validator set with the given set ID became active
+	EpochActivated(u64),
+	/// Header is finalized and sent to the sampling
+	Finalized(Header),
+	/// Skipped block with the given number is sent to the sampling
+	BacklogProcessed(u32),
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Default)]
+pub struct SyncProgress {
+	/// Latest finalized header sent to the sampling
+	pub finalized: Option<Header>,
+	/// Headers above the finalized block, in the order they were received
+	pub unfinalized: Vec<Header>,
+	pub next_epoch: Option<PendingEpoch>,
+	/// Number of the latest block sent to the sampling
+	pub backlog: Option<u32>,
+}
+
+impl SyncProgress {
+	fn apply(&mut self, entry: SyncEntry) {
+		match entry {
+			SyncEntry::Unfinalized(header) => {
+				let finalized = self
+					.finalized
+					.as_ref()
+					.is_some_and(|finalized| header.number <= finalized.number);
+				if !finalized {
+					self.unfinalized.push(header);
+				}
+			},
+			SyncEntry::EpochAnnounced(epoch) => self.next_epoch = Some(epoch),
+			SyncEntry::EpochActivated(set_id) => {
+				if self
+					.next_epoch
+					.as_ref()
+					.is_some_and(|epoch| epoch.set_id == set_id)
+				{
+					self.next_epoch = None;
+				}
+			},
+			SyncEntry::Finalized(header) => {
+				self.unfinalized
+					.retain(|unfinalized| unfinalized.number > header.number);
+				self.backlog = Some(header.number);
+				self.finalized = Some(header);
+			},
+			SyncEntry::BacklogProcessed(number) => self.backlog = Some(number),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Default)]
+struct Checkpoint {
+	progress: SyncProgress,
+	/// Sequence of the first entry which is not included in the progress
+	next_sequence: u64,
+}
+
+pub struct SyncJournal {
+	checkpoint: Checkpoint,
+	progress: SyncProgress,
+	/// Sequence of the next recorded entry
+	next_sequence: u64,
+	interval: u64,
+}
+
+impl SyncJournal {
+	/// Loads the checkpoint, and replays entries recorded after it
+	pub fn open(db: &impl Database, interval: u64) -> Result<Self> {
+		let checkpoint: Checkpoint = db
+			.get(Key::SyncCheckpoint)
+			.wrap_err("Failed to get sync checkpoint")?
+			.unwrap_or_default();
+		let mut progress = checkpoint.progress.clone();
+		let mut next_sequence = checkpoint.next_sequence;
+		while let Some(entry) = db
+			.get::<SyncEntry>(Key::SyncJournal(next_sequence))
+			.wrap_err("Failed to get sync journal entry")?
+		{
+			progress.apply(entry);
+			next_sequence += 1;
+		}
+		Ok(SyncJournal {
+			checkpoint,
+			progress,
+			next_sequence,
+			interval: interval.max(1),
+		})
+	}
+
+	pub fn progress(&self) -> &SyncProgress {
+		&self.progress
+	}
+
+	/// Number of entries recorded after the latest checkpoint
+	pub fn pending(&self) -> u64 {
+		self.next_sequence - self.checkpoint.next_sequence
+	}
+
+	/// Records the entry, which has to be done before its effects are applied
+	pub fn record(&mut self, db: &impl Database, entry: SyncEntry) -> Result<()> {
+		db.put(Key::SyncJournal(self.next_sequence), &entry)
+			.wrap_err("Failed to store sync journal entry")?;
+		self.next_sequence += 1;
+		self.progress.apply(entry);
+		if self.pending() >= self.interval {
+			self.checkpoint(db)?;
+		}
+		Ok(())
+	}
+
+	/// Stores the progress as the checkpoint, and removes the entries included in it
+	pub fn checkpoint(&mut self, db: &impl Database) -> Result<()> {
+		let checkpoint = Checkpoint {
+			progress: self.progress.clone(),
+			next_sequence: self.next_sequence,
+		};
+		db.put(Key::SyncCheckpoint, &checkpoint)
+			.wrap_err("Failed to store sync checkpoint")?;
+		db.flush().wrap_err("Failed to flush sync checkpoint")?;
+		for sequence in self.checkpoint.next_sequence..self.next_sequence {
+			db.delete(Key::SyncJournal(sequence))
+				.wrap_err("Failed to delete sync journal entry")?;
+		}
+		self.checkpoint = checkpoint;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{data::mem_db::MemoryDB, simulation::header};
+	use avail_subxt::utils::H256;
+
+	fn numbers(headers: &[Header]) -> Vec<u32> {
+		headers.iter().map(|header| header.number).collect()
+	}
+
+	fn epoch(set_id: u64) -> PendingEpoch {
+		PendingEpoch {
+			set_id,
+			validator_set: vec![ed25519::Public::from_raw([set_id as u8; 32])],
+		}
+	}
+
+	fn record(journal: &mut SyncJournal, db: &MemoryDB, entries: Vec<SyncEntry>) {
+		for entry in entries {
+			journal.record(db, entry).unwrap();
+		}
+	}
+
+	#[test]
+	fn resume_after_crash() {
+		let db = MemoryDB::default();
+		let mut journal = SyncJournal::open(&db, 100).unwrap();
+		let header = |number| header(number, H256::zero(), 0);
+		record(
+			&mut journal,
+			&db,
+			vec![
+				SyncEntry::Unfinalized(header(1)),
+				SyncEntry::Unfinalized(header(2)),
+				SyncEntry::Unfinalized(header(4)),
+				SyncEntry::EpochAnnounced(epoch(2)),
+				SyncEntry::Finalized(header(2)),
+				SyncEntry::BacklogProcessed(3),
+			],
+		);
+		assert_eq!(journal.pending(), 6);
+
+		// Journal is dropped without the checkpoint, as if the client crashed
+		let journal = SyncJournal::open(&db, 100).unwrap();
+		let progress = journal.progress();
+		assert_eq!(
+			progress.finalized.as_ref().map(|header| header.number),
+			Some(2)
+		);
+		assert_eq!(numbers(&progress.unfinalized), [4]);
+		assert_eq!(progress.next_epoch, Some(epoch(2)));
+		assert_eq!(progress.backlog, Some(3));
+		assert_eq!(journal.pending(), 6);
+	}
+
+	#[test]
+	fn checkpoint_removes_entries() {
+		let db = MemoryDB::default();
+		let mut journal = SyncJournal::open(&db, 3).unwrap();
+		let header = |number| header(number, H256::zero(), 0);
+		record(
+			&mut journal,
+			&db,
+			vec![
+				SyncEntry::EpochAnnounced(epoch(2)),
+				SyncEntry::Unfinalized(header(1)),
+				SyncEntry::EpochActivated(2),
+				SyncEntry::Unfinalized(header(2)),
+			],
+		);
+		assert_eq!(journal.pending(), 1);
+		let entry: Option<SyncEntry> = db.get(Key::SyncJournal(0)).unwrap();
+		assert!(entry.is_none());
+
+		let journal = SyncJournal::open(&db, 3).unwrap();
+		let progress = journal.progress();
+		assert_eq!(numbers(&progress.unfinalized), [1, 2]);
+		assert_eq!(progress.next_epoch, None);
+		assert_eq!(journal.pending(), 1);
+	}
+}
@@ -0,0 +1,776 @@
+//! Sans-IO core of the header sync, as an explicit state machine.
+//!
+//! [`SyncMachine`] doesn't perform any IO, and doesn't read the clock. Caller feeds it with the [`Input`] events
+//! (peer connections, block announcements, responses, finality and ticks), together with the current time, and
+//! executes the returned [`Action`]s (requests to the peers, announcements, notifications). Same inputs always result
+//! in the same actions, so sync can be tested deterministically, and driven by any transport.
+//!
+//! # Flow
+//!
+//! * Valid announced headers are imported into the [`ForkTree`]
+//! * Headers with unknown parents are kept as orphans, and their parents are requested from the announcing peer
+//! * Longer gaps are downloaded in ranges from all peers in parallel, with the [`Downloader`], up to
+//!   [`MAX_ORPHANS`] blocks ahead of the best block
+//! * Requests which time out, or fail, are retried with another peer, up to [`MAX_REQUEST_ATTEMPTS`] times
+//! * Finalized block is requested if unknown, and the tree is pruned once it's imported
+
+use avail_subxt::utils::H256;
+use color_eyre::Result;
+use libp2p::PeerId;
+use std::{
+	collections::{BTreeSet, HashMap, HashSet},
+	sync::Arc,
+	time::{Duration, Instant},
+};
+use tracing::debug;
+
+use crate::{
+	fork_choice::{BlockInfo, ForkChoice, ForkTree},
+	header::{
+		cached::CachedHeader, consistency::ExtensionLimits, digest::SmallHeader,
+		download::Downloader,
+	},
+	memory::MemoryBudget,
+	network::p2p::block_announce::{BlockAnnounce, BlockAnnounceValidator},
+};
+
+/// Timeout of the header request
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Maximum number of attempts to fetch the header
+pub const MAX_REQUEST_ATTEMPTS: u32 = 3;
+/// Maximum number of kept headers with unknown parents
+pub const MAX_ORPHANS: usize = 1024;
+/// Interval of the [`Input::Tick`] events, expected by the drivers
+pub const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub type RequestId = u64;
+
+#[derive(Clone, Debug)]
+pub enum Input {
+	PeerConnected {
+		peer_id: PeerId,
+		best_number: u32,
+	},
+	PeerDisconnected {
+		peer_id: PeerId,
+	},
+	Announce {
+		peer_id: PeerId,
+		announce: BlockAnnounce,
+	},
+	/// Response to the header request, SCALE encoded header or `None` if peer doesn't have it
+	HeaderResponse {
+		request_id: RequestId,
+		header: Option<Vec<u8>>,
+	},
+	/// Response to the header range request, SCALE encoded headers in the block number order
+	HeadersResponse {
+		request_id: RequestId,
+		headers: Vec<Vec<u8>>,
+	},
+	RequestFailed {
+		request_id: RequestId,
+	},
+	/// Block is finalized, e.g. by the verified justification
+	Finalized {
+		hash: H256,
+	},
+	/// Time has passed, expired requests are retried
+	Tick,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+	RequestHeader {
+		request_id: RequestId,
+		peer_id: PeerId,
+		hash: H256,
+	},
+	/// Request `count` consecutive headers of the peer's best chain, starting with the block number `from`
+	RequestHeaders {
+		request_id: RequestId,
+		peer_id: PeerId,
+		from: u32,
+		count: u32,
+	},
+	/// Announce the new best block to the peers
+	Announce {
+		hash: H256,
+		number: u32,
+	},
+	NewBest {
+		hash: H256,
+		number: u32,
+	},
+	Finalized {
+		hash: H256,
+		number: u32,
+	},
+	DisconnectPeer {
+		peer_id: PeerId,
+	},
+}
+
+struct Request {
+	hash: H256,
+	peer_id: PeerId,
+	deadline: Instant,
+	attempts: u32,
+	/// Peers which failed to respond
+	failed: HashSet<PeerId>,
+}
+
+pub struct SyncMachine {
+	tree: ForkTree,
+	limits: ExtensionLimits,
+	announces: BlockAnnounceValidator,
+	/// Best block numbers of the connected peers
+	peers: HashMap<PeerId, u32>,
+	requests: HashMap<RequestId, Request>,
+	downloads: Downloader,
+	next_request_id: RequestId,
+	/// Headers with unknown parents, by the parent hash
+	orphans: HashMap<H256, Vec<CachedHeader<SmallHeader>>>,
+	finalizing: Option<H256>,
+	best: Option<H256>,
+}
+
+impl SyncMachine {
+	pub fn new(
+		finalized: BlockInfo,
+		fork_choice: Box<dyn ForkChoice + Send + Sync>,
+		limits: ExtensionLimits,
+	) -> Self {
+		let best = Some(finalized.hash);
+		SyncMachine {
+			tree: ForkTree::new(finalized, fork_choice),
+			limits,
+			announces: BlockAnnounceValidator::new(limits),
+			peers: HashMap::new(),
+			requests: HashMap::new(),
+			downloads: Downloader::new(),
+			next_request_id: 0,
+			orphans: HashMap::new(),
+			finalizing: None,
+			best,
+		}
+	}
+
+	/// Accounts imported blocks in the memory budget, see [`ForkTree::with_budget`]
+	pub fn with_budget(self, budget: Arc<MemoryBudget>) -> Self {
+		SyncMachine {
+			tree: self.tree.with_budget(budget),
+			..self
+		}
+	}
+
+	pub fn tree(&self) -> &ForkTree {
+		&self.tree
+	}
+
+	pub fn pending_requests(&self) -> usize {
+		self.requests.len() + self.downloads.in_flight()
+	}
+
+	pub fn downloads(&self) -> &Downloader {
+		&self.downloads
+	}
+
+	pub fn orphans(&self) -> usize {
+		self.orphans.values().map(Vec::len).sum()
+	}
+
+	fn finalized_number(&self) -> u32 {
+		self.tree
+			.get(&self.tree.finalized())
+			.map_or(0, |block| block.number)
+	}
+
+	fn best_number(&self) -> u32 {
+		self.tree
+			.best_block()
+			.map_or_else(|| self.finalized_number(), |block| block.number)
+	}
+
+	/// Handles the input at the given time, returning actions to be executed by the caller
+	pub fn handle(&mut self, now: Instant, input: Input) -> Vec<Action> {
+		let mut actions = vec![];
+		match input {
+			Input::PeerConnected {
+				peer_id,
+				best_number,
+			} => {
+				self.peers.insert(peer_id, best_number);
+			},
+			Input::PeerDisconnected { peer_id } => {
+				self.peers.remove(&peer_id);
+				self.announces.remove_peer(&peer_id);
+				self.downloads.remove_peer(&peer_id, &self.peers);
+				let failed = self
+					.requests
+					.iter()
+					.filter(|(_, request)| request.peer_id == peer_id)
+					.map(|(request_id, _)| *request_id)
+					.collect::<BTreeSet<_>>();
+				for request_id in failed {
+					self.retry(now, request_id, &mut actions);
+				}
+			},
+			Input::Announce { peer_id, announce } => {
+				self.handle_announce(now, peer_id, announce, &mut actions)
+			},
+			Input::HeaderResponse { request_id, header } => {
+				let Some(request) = self.requests.get(&request_id) else {
+					return actions;
+				};
+				let header =
+					header.and_then(|encoded| CachedHeader::<SmallHeader>::decode(&encoded).ok());
+				match header {
+					Some(header) if header.hash() == request.hash => {
+						let peer_id = request.peer_id;
+						self.requests.remove(&request_id);
+						self.import(now, header, Some(peer_id), &mut actions);
+					},
+					_ => self.retry(now, request_id, &mut actions),
+				}
+			},
+			Input::HeadersResponse {
+				request_id,
+				headers,
+			} => {
+				let Some(peer_id) = self.downloads.peer(request_id) else {
+					return actions;
+				};
+				let headers = headers
+					.iter()
+					.map(|encoded| CachedHeader::<SmallHeader>::decode(encoded))
+					.collect::<Result<Vec<_>>>()
+					.unwrap_or_default();
+				for header in self
+					.downloads
+					.complete(now, request_id, headers, &self.peers)
+				{
+					self.import(now, header, Some(peer_id), &mut actions);
+				}
+			},
+			Input::RequestFailed { request_id } if self.requests.contains_key(&request_id) => {
+				self.retry(now, request_id, &mut actions)
+			},
+			Input::RequestFailed { request_id } => self.downloads.fail(request_id, &self.peers),
+			Input::Finalized { hash } => {
+				self.finalizing = Some(hash);
+				if self.tree.get(&hash).is_none() {
+					self.request(now, hash, None, 0, &mut actions);
+				}
+			},
+			Input::Tick => {
+				let expired = self
+					.requests
+					.iter()
+					.filter(|(_, request)| request.deadline <= now)
+					.map(|(request_id, _)| *request_id)
+					.collect::<BTreeSet<_>>();
+				for request_id in expired {
+					self.retry(now, request_id, &mut actions);
+				}
+				self.downloads.expire(now, &self.peers);
+			},
+		}
+		self.download(now, &mut actions);
+		self.finalize(&mut actions);
+		self.update_best(&mut actions);
+		actions
+	}
+
+	fn handle_announce(
+		&mut self,
+		now: Instant,
+		peer_id: PeerId,
+		announce: BlockAnnounce,
+		actions: &mut Vec<Action>,
+	) {
+		let valid = match self.announces.validate(peer_id, &announce) {
+			Ok(valid) => valid,
+			Err(error) => {
+				debug!(%peer_id, %error, "Invalid block announcement");
+				if self.announces.is_banned(&peer_id) {
+					actions.push(Action::DisconnectPeer { peer_id });
+				}
+				return;
+			},
+		};
+		if let Some(best_number) = self.peers.get_mut(&peer_id) {
+			*best_number = (*best_number).max(valid.number);
+		}
+		if self.tree.get(&valid.hash).is_some() {
+			return;
+		}
+		let Ok(header) = CachedHeader::<SmallHeader>::decode(&announce.header) else {
+			return;
+		};
+		self.import(now, header, Some(peer_id), actions);
+	}
+
+	/// Imports header and its orphaned descendants, requesting the parent if unknown
+	fn import(
+		&mut self,
+		now: Instant,
+		header: CachedHeader<SmallHeader>,
+		peer_id: Option<PeerId>,
+		actions: &mut Vec<Action>,
+	) {
+		let finalized_number = self.finalized_number();
+		let mut headers = vec![header];
+		while let Some(header) = headers.pop() {
+			if header.number <= finalized_number {
+				continue;
+			}
+			if self.tree.get(&header.parent_hash).is_none() {
+				let parent_hash = header.parent_hash;
+				let parent_number = header.number - 1;
+				let hash = header.hash();
+				let known = self
+					.orphans
+					.get(&parent_hash)
+					.is_some_and(|orphans| orphans.iter().any(|orphan| orphan.hash() == hash));
+				if !known && self.orphans() < MAX_ORPHANS {
+					self.orphans.entry(parent_hash).or_default().push(header);
+				}
+				// Parent is downloaded with its range, or the missing blocks are queued if there are more of them
+				let best_number = self.best_number();
+				if self.downloads.is_pending(parent_number)
+					|| self.is_orphan(parent_hash, parent_number)
+				{
+					continue;
+				}
+				if parent_number > best_number + 1 {
+					self.downloads.queue(best_number + 1, parent_number);
+					continue;
+				}
+				self.request(now, parent_hash, peer_id, parent_number, actions);
+				continue;
+			}
+			match self.tree.import_cached_header(&header, &self.limits) {
+				Ok(hash) => headers.extend(self.orphans.remove(&hash).unwrap_or_default()),
+				Err(error) => debug!(number = header.number, %error, "Cannot import header"),
+			}
+		}
+	}
+
+	/// Checks if the block is kept as orphan, in which case its ancestors are already requested
+	fn is_orphan(&self, hash: H256, number: u32) -> bool {
+		self.orphans
+			.values()
+			.flatten()
+			.any(|orphan| orphan.number == number && orphan.hash() == hash)
+	}
+
+	/// Requests header from the preferred peer, or from the peer which should have the block
+	fn request(
+		&mut self,
+		now: Instant,
+		hash: H256,
+		peer_id: Option<PeerId>,
+		number: u32,
+		actions: &mut Vec<Action>,
+	) {
+		if self.requests.values().any(|request| request.hash == hash) {
+			return;
+		}
+		let peer_id = peer_id
+			.filter(|peer_id| self.peers.contains_key(peer_id))
+			.or_else(|| self.select_peer(number, &HashSet::new()));
+		let Some(peer_id) = peer_id else {
+			debug!(?hash, "No peers to request header from");
+			return;
+		};
+		let request = Request {
+			hash,
+			peer_id,
+			deadline: now,
+			attempts: 0,
+			failed: HashSet::new(),
+		};
+		self.send(now, request, actions);
+	}
+
+	/// Selects peer with the block, lowest peer ID is selected for the deterministic result
+	fn select_peer(&self, number: u32, excluded: &HashSet<PeerId>) -> Option<PeerId> {
+		self.peers
+			.iter()
+			.filter(|(peer_id, best_number)| **best_number >= number && !excluded.contains(peer_id))
+			.map(|(peer_id, _)| *peer_id)
+			.min()
+	}
+
+	fn send(&mut self, now: Instant, mut request: Request, actions: &mut Vec<Action>) {
+		let request_id = self.next_request_id;
+		self.next_request_id += 1;
+		request.deadline = now + REQUEST_TIMEOUT;
+		request.attempts += 1;
+		actions.push(Action::RequestHeader {
+			request_id,
+			peer_id: request.peer_id,
+			hash: request.hash,
+		});
+		self.requests.insert(request_id, request);
+	}
+
+	/// Retries request with another peer, or gives up after the maximum number of attempts
+	fn retry(&mut self, now: Instant, request_id: RequestId, actions: &mut Vec<Action>) {
+		let Some(mut request) = self.requests.remove(&request_id) else {
+			return;
+		};
+		request.failed.insert(request.peer_id);
+		if request.attempts >= MAX_REQUEST_ATTEMPTS {
+			debug!(hash = ?request.hash, "Header request failed");
+			return;
+		}
+		// Number of the requested block is not known, any peer can have it
+		let Some(peer_id) = self.select_peer(0, &request.failed) else {
+			debug!(hash = ?request.hash, "No peers to retry header request with");
+			return;
+		};
+		self.send(now, Request { peer_id, ..request }, actions);
+	}
+
+	/// Assigns queued header ranges to the idle peers
+	fn download(&mut self, now: Instant, actions: &mut Vec<Action>) {
+		let window_end = self.best_number().saturating_add(MAX_ORPHANS as u32);
+		let requests =
+			self.downloads
+				.assign(now, &self.peers, window_end, &mut self.next_request_id);
+		actions.extend(requests.into_iter().map(|request| Action::RequestHeaders {
+			request_id: request.request_id,
+			peer_id: request.peer_id,
+			from: request.from,
+			count: request.count,
+		}));
+	}
+
+	fn finalize(&mut self, actions: &mut Vec<Action>) {
+		let Some(hash) = self.finalizing else {
+			return;
+		};
+		let Some(number) = self.tree.get(&hash).map(|block| block.number) else {
+			return;
+		};
+		self.finalizing = None;
+		if hash == self.tree.finalized() {
+			return;
+		}
+		if let Err(error) = self.tree.finalize(hash) {
+			debug!(?hash, %error, "Cannot finalize block");
+			return;
+		}
+		self.tree.prune();
+		self.downloads.prune(number);
+		self.orphans.retain(|_, headers| {
+			headers.retain(|header| header.number > number);
+			!headers.is_empty()
+		});
+		actions.push(Action::Finalized { hash, number });
+	}
+
+	fn update_best(&mut self, actions: &mut Vec<Action>) {
+		let Some(best) = self.tree.best_block() else {
+			return;
+		};
+		if Some(best.hash) == self.best {
+			return;
+		}
+		let (hash, number) = (best.hash, best.number);
+		self.best = Some(hash);
+		actions.push(Action::NewBest { hash, number });
+		actions.push(Action::Announce { hash, number });
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{fork_choice::LongestChain, header::HeaderHash};
+	use avail_subxt::{
+		api::runtime_types::avail_core::{
+			data_lookup::compact::CompactDataLookup,
+			header::extension::{v3, HeaderExtension},
+			kate_commitment::v3::KateCommitment,
+		},
+		config::substrate::{Digest, DigestItem},
+		primitives::Header,
+	};
+	use codec::Encode;
+
+	fn header(number: u32, parent_hash: H256) -> Header {
+		Header {
+			parent_hash,
+			number,
+			state_root: H256::zero(),
+			extrinsics_root: H256::zero(),
+			digest: Digest {
+				logs: vec![DigestItem::Seal(*b"BABE", vec![1; 64])],
+			},
+			extension: HeaderExtension::V3(v3::HeaderExtension {
+				commitment: KateCommitment {
+					rows: 1,
+					cols: 4,
+					data_root: H256::zero(),
+					commitment: vec![0; 2 * 48],
+				},
+				app_lookup: CompactDataLookup {
+					size: 0,
+					index: vec![],
+				},
+			}),
+		}
+	}
+
+	fn chain(len: u32) -> Vec<Header> {
+		let mut headers = vec![header(0, H256::zero())];
+		for number in 1..len {
+			let parent_hash = headers[number as usize - 1].header_hash();
+			headers.push(header(number, parent_hash));
+		}
+		headers
+	}
+
+	fn machine(genesis: &Header) -> SyncMachine {
+		let finalized = BlockInfo {
+			hash: genesis.header_hash(),
+			number: 0,
+			parent_hash: H256::zero(),
+			confidence: None,
+		};
+		SyncMachine::new(
+			finalized,
+			Box::new(LongestChain),
+			ExtensionLimits::default(),
+		)
+	}
+
+	fn announce(peer_id: PeerId, header: &Header) -> Input {
+		Input::Announce {
+			peer_id,
+			announce: BlockAnnounce {
+				header: header.encode(),
+				is_best: true,
+			},
+		}
+	}
+
+	#[test]
+	fn sync_announced_blocks() {
+		let headers = chain(4);
+		let mut machine = machine(&headers[0]);
+		let now = Instant::now();
+		let peer_id = PeerId::random();
+		machine.handle(
+			now,
+			Input::PeerConnected {
+				peer_id,
+				best_number: 3,
+			},
+		);
+
+		let actions = machine.handle(now, announce(peer_id, &headers[1]));
+		let hash = headers[1].header_hash();
+		assert_eq!(
+			actions,
+			vec![
+				Action::NewBest { hash, number: 1 },
+				Action::Announce { hash, number: 1 }
+			]
+		);
+
+		// Parent of the announced block is requested from the announcing peer
+		let actions = machine.handle(now, announce(peer_id, &headers[3]));
+		assert_eq!(
+			actions,
+			vec![Action::RequestHeader {
+				request_id: 0,
+				peer_id,
+				hash: headers[2].header_hash()
+			}]
+		);
+		assert_eq!(machine.orphans(), 1);
+
+		let actions = machine.handle(
+			now,
+			Input::HeaderResponse {
+				request_id: 0,
+				header: Some(headers[2].encode()),
+			},
+		);
+		let hash = headers[3].header_hash();
+		assert_eq!(actions[0], Action::NewBest { hash, number: 3 });
+		assert_eq!(machine.orphans(), 0);
+		assert_eq!(machine.pending_requests(), 0);
+	}
+
+	#[test]
+	fn retry_expired_requests() {
+		let headers = chain(3);
+		let mut machine = machine(&headers[0]);
+		let now = Instant::now();
+		let mut peers = [PeerId::random(), PeerId::random()];
+		peers.sort();
+		for peer_id in peers {
+			machine.handle(
+				now,
+				Input::PeerConnected {
+					peer_id,
+					best_number: 2,
+				},
+			);
+		}
+		machine.handle(now, announce(peers[1], &headers[2]));
+		assert!(machine.handle(now, Input::Tick).is_empty());
+
+		let later = now + REQUEST_TIMEOUT;
+		let actions = machine.handle(later, Input::Tick);
+		assert_eq!(
+			actions,
+			vec![Action::RequestHeader {
+				request_id: 1,
+				peer_id: peers[0],
+				hash: headers[1].header_hash()
+			}]
+		);
+
+		// Response with the wrong header is retried, until all peers failed
+		let actions = machine.handle(
+			later,
+			Input::HeaderResponse {
+				request_id: 1,
+				header: Some(headers[2].encode()),
+			},
+		);
+		assert!(actions.is_empty());
+		assert_eq!(machine.pending_requests(), 0);
+	}
+
+	#[test]
+	fn finalize_unknown_block() {
+		let headers = chain(3);
+		let mut machine = machine(&headers[0]);
+		let now = Instant::now();
+		let peer_id = PeerId::random();
+		machine.handle(
+			now,
+			Input::PeerConnected {
+				peer_id,
+				best_number: 2,
+			},
+		);
+		machine.handle(now, announce(peer_id, &headers[1]));
+
+		let hash = headers[2].header_hash();
+		let actions = machine.handle(now, Input::Finalized { hash });
+		assert_eq!(
+			actions,
+			vec![Action::RequestHeader {
+				request_id: 0,
+				peer_id,
+				hash
+			}]
+		);
+		let actions = machine.handle(
+			now,
+			Input::HeaderResponse {
+				request_id: 0,
+				header: Some(headers[2].encode()),
+			},
+		);
+		assert_eq!(
+			actions,
+			vec![
+				Action::Finalized { hash, number: 2 },
+				Action::NewBest { hash, number: 2 },
+				Action::Announce { hash, number: 2 }
+			]
+		);
+		assert_eq!(machine.tree().finalized(), hash);
+	}
+
+	#[test]
+	fn download_ranges_in_parallel() {
+		let headers = chain(150);
+		let mut machine = machine(&headers[0]);
+		let now = Instant::now();
+		let mut peers = [PeerId::random(), PeerId::random()];
+		peers.sort();
+		for peer_id in peers {
+			machine.handle(
+				now,
+				Input::PeerConnected {
+					peer_id,
+					best_number: 149,
+				},
+			);
+		}
+		let encoded = |range: std::ops::Range<usize>| {
+			headers[range]
+				.iter()
+				.map(Encode::encode)
+				.collect::<Vec<_>>()
+		};
+		let request = |request_id, peer_id, from, count| Action::RequestHeaders {
+			request_id,
+			peer_id,
+			from,
+			count,
+		};
+
+		let actions = machine.handle(now, announce(peers[0], &headers[149]));
+		assert_eq!(
+			actions,
+			vec![request(0, peers[0], 1, 64), request(1, peers[1], 65, 64)]
+		);
+
+		// Later ranges are kept as orphans, and the rest of the gap is assigned to the idle peer
+		let response = Input::HeadersResponse {
+			request_id: 1,
+			headers: encoded(65..129),
+		};
+		let actions = machine.handle(now, response);
+		assert_eq!(actions, vec![request(2, peers[1], 129, 20)]);
+		let response = Input::HeadersResponse {
+			request_id: 2,
+			headers: encoded(129..149),
+		};
+		assert!(machine.handle(now, response).is_empty());
+		assert_eq!(machine.orphans(), 85);
+
+		// Expired range is reassigned to the other peer
+		let later = now + REQUEST_TIMEOUT;
+		let actions = machine.handle(later, Input::Tick);
+		assert_eq!(actions, vec![request(3, peers[1], 1, 64)]);
+		let response = Input::HeadersResponse {
+			request_id: 3,
+			headers: encoded(1..65),
+		};
+		let actions = machine.handle(later, response);
+		let hash = headers[149].header_hash();
+		assert_eq!(actions[0], Action::NewBest { hash, number: 149 });
+		assert_eq!(machine.orphans(), 0);
+		assert_eq!(machine.pending_requests(), 0);
+	}
+
+	#[test]
+	fn disconnect_banned_peer() {
+		let headers = chain(1);
+		let mut machine = machine(&headers[0]);
+		let peer_id = PeerId::random();
+		let invalid = Input::Announce {
+			peer_id,
+			announce: BlockAnnounce {
+				header: vec![0],
+				is_best: true,
+			},
+		};
+		let actions = (0..5)
+			.flat_map(|_| machine.handle(Instant::now(), invalid.clone()))
+			.collect::<Vec<_>>();
+		assert_eq!(actions, vec![Action::DisconnectPeer { peer_id }]);
+	}
+}
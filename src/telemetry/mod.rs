@@ -9,6 +9,9 @@ use mockall::automock;
 use opentelemetry_api::metrics::{Counter, Meter};
 
 pub mod otlp;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+pub mod substrate;
 
 pub enum MetricCounter {
 	SessionBlock,
@@ -18,6 +21,7 @@ pub enum MetricCounter {
 	ConnectionEstablished,
 	IncomingPutRecord,
 	IncomingGetRecord,
+	RateLimitedRequest,
 }
 
 impl Display for MetricCounter {
@@ -30,6 +34,7 @@ impl Display for MetricCounter {
 			MetricCounter::ConnectionEstablished => write!(f, "established_connections"),
 			MetricCounter::IncomingPutRecord => write!(f, "incoming_put_record_counter"),
 			MetricCounter::IncomingGetRecord => write!(f, "incoming_get_record_counter"),
+			MetricCounter::RateLimitedRequest => write!(f, "rate_limited_requests"),
 		}
 	}
 }
@@ -45,6 +50,7 @@ impl MetricCounter {
 			MetricCounter::ConnectionEstablished,
 			MetricCounter::IncomingPutRecord,
 			MetricCounter::IncomingGetRecord,
+			MetricCounter::RateLimitedRequest,
 		] {
 			counter_map.insert(
 				counter.to_string(),
@@ -73,6 +79,8 @@ pub enum MetricValue {
 	PingLatency(f64),
 	ReplicationFactor(u16),
 	QueryTimeout(u32),
+	HeaderCacheHitRate(f64),
+	ProofVerificationDuration(f64),
 	#[cfg(feature = "crawl")]
 	CrawlCellsSuccessRate(f64),
 	#[cfg(feature = "crawl")]
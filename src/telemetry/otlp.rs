@@ -135,6 +135,13 @@ impl super::Metrics for Metrics {
 			super::MetricValue::PingLatency(number) => {
 				self.record_f64("ping_latency", number).await?;
 			},
+			super::MetricValue::HeaderCacheHitRate(number) => {
+				self.record_f64("header_cache_hit_rate", number).await?;
+			},
+			super::MetricValue::ProofVerificationDuration(number) => {
+				self.record_f64("proof_verification_duration", number)
+					.await?;
+			},
 			#[cfg(feature = "crawl")]
 			super::MetricValue::CrawlCellsSuccessRate(number) => {
 				self.record_f64("crawl_cells_success_rate", number).await?;
@@ -0,0 +1,210 @@
+//! Prometheus metrics, exposed through the [`Registry`] which embedders can scrape or serve with [`serve`].
+//!
+//! Counters are exposed as `avail_light_<name>` counters, durations and delays as histograms (in seconds),
+//! and all other values as gauges. E.g. headers imported per second are `rate(avail_light_session_block_counter)`.
+
+use async_trait::async_trait;
+use color_eyre::{eyre::WrapErr, Result};
+use prometheus::{
+	Encoder, Gauge, Histogram, HistogramOpts, IntCounter, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::{collections::HashMap, net::SocketAddr, sync::Mutex};
+use tracing::info;
+use warp::Filter;
+
+use super::{MetricCounter, MetricValue};
+
+const NAMESPACE: &str = "avail_light";
+
+enum Kind {
+	/// Exposed as a gauge
+	Value,
+	/// Exposed as a histogram
+	Duration,
+}
+
+fn observation(value: MetricValue) -> (&'static str, Kind, f64) {
+	match value {
+		MetricValue::TotalBlockNumber(number) => ("total_block_number", Kind::Value, number as f64),
+		MetricValue::DHTFetched(number) => ("dht_fetched", Kind::Value, number),
+		MetricValue::DHTFetchedPercentage(number) => {
+			("dht_fetched_percentage", Kind::Value, number)
+		},
+		MetricValue::DHTFetchDuration(number) => ("dht_fetch_duration", Kind::Duration, number),
+		MetricValue::NodeRPCFetched(number) => ("node_rpc_fetched", Kind::Value, number),
+		MetricValue::NodeRPCFetchDuration(number) => {
+			("node_rpc_fetch_duration", Kind::Duration, number)
+		},
+		MetricValue::BlockConfidence(number) => ("block_confidence", Kind::Value, number),
+		MetricValue::BlockConfidenceTreshold(number) => {
+			("block_confidence_treshold", Kind::Value, number)
+		},
+		MetricValue::RPCCallDuration(number) => ("rpc_call_duration", Kind::Duration, number),
+		MetricValue::DHTPutDuration(number) => ("dht_put_duration", Kind::Duration, number),
+		MetricValue::DHTPutSuccess(number) => ("dht_put_success", Kind::Value, number),
+		MetricValue::ConnectedPeersNum(number) => {
+			("connected_peers_num", Kind::Value, number as f64)
+		},
+		MetricValue::HealthCheck() => ("up", Kind::Value, 1.0),
+		MetricValue::BlockProcessingDelay(number) => {
+			("block_processing_delay", Kind::Duration, number)
+		},
+		MetricValue::PingLatency(number) => ("ping_latency", Kind::Duration, number),
+		MetricValue::ReplicationFactor(number) => {
+			("replication_factor", Kind::Value, number as f64)
+		},
+		MetricValue::QueryTimeout(number) => ("query_timeout", Kind::Value, number as f64),
+		MetricValue::HeaderCacheHitRate(number) => ("header_cache_hit_rate", Kind::Value, number),
+		MetricValue::ProofVerificationDuration(number) => {
+			("proof_verification_duration", Kind::Duration, number)
+		},
+		#[cfg(feature = "crawl")]
+		MetricValue::CrawlCellsSuccessRate(number) => ("crawl_cells_success_rate", Kind::Value, number),
+		#[cfg(feature = "crawl")]
+		MetricValue::CrawlRowsSuccessRate(number) => ("crawl_rows_success_rate", Kind::Value, number),
+		#[cfg(feature = "crawl")]
+		MetricValue::CrawlBlockDelay(number) => ("crawl_block_delay", Kind::Duration, number),
+	}
+}
+
+pub struct Metrics {
+	registry: Registry,
+	counters: HashMap<String, IntCounter>,
+	gauges: Mutex<HashMap<&'static str, Gauge>>,
+	histograms: Mutex<HashMap<&'static str, Histogram>>,
+	multiaddress: IntGaugeVec,
+}
+
+impl Metrics {
+	pub fn new() -> Result<Self> {
+		let registry = Registry::new_custom(Some(NAMESPACE.to_string()), None)?;
+		let mut counters = HashMap::new();
+		for counter in [
+			MetricCounter::SessionBlock,
+			MetricCounter::OutgoingConnectionError,
+			MetricCounter::IncomingConnectionError,
+			MetricCounter::IncomingConnection,
+			MetricCounter::ConnectionEstablished,
+			MetricCounter::IncomingPutRecord,
+			MetricCounter::IncomingGetRecord,
+			MetricCounter::RateLimitedRequest,
+		] {
+			let name = counter.to_string();
+			let instrument = IntCounter::new(&name, &name)?;
+			registry.register(Box::new(instrument.clone()))?;
+			counters.insert(name, instrument);
+		}
+		let multiaddress = IntGaugeVec::new(
+			Opts::new("multiaddress", "Multiaddress of the light client"),
+			&["multiaddress"],
+		)?;
+		registry.register(Box::new(multiaddress.clone()))?;
+		Ok(Metrics {
+			registry,
+			counters,
+			gauges: Default::default(),
+			histograms: Default::default(),
+			multiaddress,
+		})
+	}
+
+	/// Registry handle, which can be scraped by the embedder
+	pub fn registry(&self) -> Registry {
+		self.registry.clone()
+	}
+
+	fn gauge(&self, name: &'static str) -> Result<Gauge> {
+		let mut gauges = self.gauges.lock().unwrap();
+		if let Some(gauge) = gauges.get(name) {
+			return Ok(gauge.clone());
+		}
+		let gauge = Gauge::new(name, name)?;
+		self.registry.register(Box::new(gauge.clone()))?;
+		gauges.insert(name, gauge.clone());
+		Ok(gauge)
+	}
+
+	fn histogram(&self, name: &'static str) -> Result<Histogram> {
+		let mut histograms = self.histograms.lock().unwrap();
+		if let Some(histogram) = histograms.get(name) {
+			return Ok(histogram.clone());
+		}
+		let histogram = Histogram::with_opts(HistogramOpts::new(name, name))?;
+		self.registry.register(Box::new(histogram.clone()))?;
+		histograms.insert(name, histogram.clone());
+		Ok(histogram)
+	}
+}
+
+#[async_trait]
+impl super::Metrics for Metrics {
+	async fn count(&self, counter: MetricCounter) {
+		self.counters[&counter.to_string()].inc();
+	}
+
+	async fn record(&self, value: MetricValue) -> Result<()> {
+		match observation(value) {
+			(name, Kind::Value, value) => self.gauge(name)?.set(value),
+			(name, Kind::Duration, value) => self.histogram(name)?.observe(value),
+		};
+		Ok(())
+	}
+
+	async fn set_multiaddress(&self, multiaddr: String) {
+		self.multiaddress.reset();
+		self.multiaddress.with_label_values(&[&multiaddr]).set(1);
+	}
+}
+
+/// Encodes all metrics in the registry using Prometheus text format.
+pub fn encode(registry: &Registry) -> Result<String> {
+	let mut buffer = vec![];
+	TextEncoder::new()
+		.encode(&registry.gather(), &mut buffer)
+		.wrap_err("Failed to encode metrics")?;
+	String::from_utf8(buffer).wrap_err("Metrics are not valid UTF-8")
+}
+
+/// Serves metrics from the registry on `/metrics` endpoint.
+pub async fn serve(registry: Registry, addr: SocketAddr) {
+	let metrics = warp::path("metrics")
+		.and(warp::get())
+		.map(move || encode(&registry).unwrap_or_else(|error| format!("{error:#}")));
+	info!("Prometheus metrics are served on {addr}");
+	warp::serve(metrics).run(addr).await;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::telemetry::Metrics as _;
+
+	#[tokio::test]
+	async fn record_metrics() {
+		let metrics = Metrics::new().unwrap();
+		metrics.count(MetricCounter::SessionBlock).await;
+		metrics.count(MetricCounter::SessionBlock).await;
+		metrics
+			.record(MetricValue::BlockConfidence(99.5))
+			.await
+			.unwrap();
+		metrics
+			.record(MetricValue::ConnectedPeersNum(3))
+			.await
+			.unwrap();
+		metrics
+			.record(MetricValue::ProofVerificationDuration(0.2))
+			.await
+			.unwrap();
+		metrics
+			.record(MetricValue::ProofVerificationDuration(0.4))
+			.await
+			.unwrap();
+
+		let encoded = encode(&metrics.registry()).unwrap();
+		assert!(encoded.contains("avail_light_session_block_counter 2"));
+		assert!(encoded.contains("avail_light_block_confidence 99.5"));
+		assert!(encoded.contains("avail_light_connected_peers_num 3"));
+		assert!(encoded.contains("avail_light_proof_verification_duration_count 2"));
+	}
+}
@@ -0,0 +1,285 @@
+//! Client of Substrate telemetry protocol, which reports node status to telemetry servers (e.g. `wss://telemetry.polkadot.io/submit/`).
+//!
+//! Messages are sent as JSON over WebSocket, in the same format as Substrate nodes use, so light clients are shown
+//! on telemetry dashboards next to the full nodes. Light client follows finalized headers, so best and finalized blocks
+//! are the same, and blocks imported while syncing are reported with `NetworkInitialSync` origin.
+
+use avail_subxt::{primitives::Header, utils::H256};
+use chrono::{SecondsFormat, Utc};
+use codec::Encode;
+use color_eyre::{eyre::WrapErr, Result};
+use futures::SinkExt;
+use serde::Serialize;
+use sp_core::blake2_256;
+use std::{
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+use tokio::{
+	sync::broadcast::{self, error::RecvError},
+	time::{self, Instant},
+};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+	network::{p2p::Client as P2pClient, rpc::Event},
+	types::State,
+};
+
+const IMPLEMENTATION: &str = "Avail Light Client";
+const INTERVAL: Duration = Duration::from_secs(5);
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum BlockOrigin {
+	NetworkInitialSync,
+	NetworkBroadcast,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "msg")]
+pub enum Payload {
+	#[serde(rename = "system.connected")]
+	SystemConnected {
+		name: String,
+		chain: String,
+		genesis_hash: H256,
+		implementation: String,
+		version: String,
+		authority: bool,
+		network_id: String,
+		startup_time: String,
+		config: String,
+	},
+	#[serde(rename = "system.interval")]
+	SystemInterval {
+		peers: usize,
+		txcount: u32,
+		best: H256,
+		height: u32,
+		finalized_hash: H256,
+		finalized_height: u32,
+	},
+	#[serde(rename = "block.import")]
+	BlockImport {
+		best: H256,
+		height: u32,
+		origin: BlockOrigin,
+	},
+	#[serde(rename = "notify.finalized")]
+	NotifyFinalized {
+		best: H256,
+		// Substrate nodes are sending finalized height as a string
+		height: String,
+	},
+}
+
+#[derive(Serialize)]
+struct TelemetryMessage<'a> {
+	id: u64,
+	ts: String,
+	payload: &'a Payload,
+}
+
+/// Encodes payload in the telemetry message envelope
+pub fn encode(id: u64, payload: &Payload) -> Result<String> {
+	let message = TelemetryMessage {
+		id,
+		ts: Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+		payload,
+	};
+	serde_json::to_string(&message).wrap_err("Failed to encode telemetry message")
+}
+
+/// Identification of the light client on the telemetry dashboard
+#[derive(Clone, Debug)]
+pub struct NodeInfo {
+	pub name: String,
+	pub version: String,
+	pub network_id: String,
+	pub startup_time: String,
+}
+
+/// Latest imported and finalized block, reported to telemetry
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Status {
+	best: H256,
+	height: u32,
+}
+
+impl Status {
+	fn import(&mut self, header: &Header) {
+		self.best = Encode::using_encoded(header, blake2_256).into();
+		self.height = header.number;
+	}
+
+	fn interval(&self, peers: usize) -> Payload {
+		Payload::SystemInterval {
+			peers,
+			txcount: 0,
+			best: self.best,
+			height: self.height,
+			finalized_hash: self.best,
+			finalized_height: self.height,
+		}
+	}
+
+	fn imported(&self, origin: BlockOrigin) -> [Payload; 2] {
+		[
+			Payload::BlockImport {
+				best: self.best,
+				height: self.height,
+				origin,
+			},
+			Payload::NotifyFinalized {
+				best: self.best,
+				height: self.height.to_string(),
+			},
+		]
+	}
+}
+
+fn connected(node: &NodeInfo, state: &Mutex<State>) -> Payload {
+	let state = state.lock().unwrap();
+	Payload::SystemConnected {
+		name: node.name.clone(),
+		chain: state.connected_node.spec_name.clone(),
+		genesis_hash: state.connected_node.genesis_hash,
+		implementation: IMPLEMENTATION.to_string(),
+		version: node.version.clone(),
+		authority: false,
+		network_id: node.network_id.clone(),
+		startup_time: node.startup_time.clone(),
+		config: "".to_string(),
+	}
+}
+
+fn origin(state: &Mutex<State>) -> BlockOrigin {
+	match state.lock().unwrap().synced {
+		Some(false) => BlockOrigin::NetworkInitialSync,
+		_ => BlockOrigin::NetworkBroadcast,
+	}
+}
+
+async fn report(
+	endpoint: &str,
+	node: &NodeInfo,
+	p2p_client: &P2pClient,
+	state: &Mutex<State>,
+	status: &mut Status,
+	rpc_events: &mut broadcast::Receiver<Event>,
+) -> Result<()> {
+	let (mut socket, _) = connect_async(endpoint)
+		.await
+		.wrap_err("Failed to connect to telemetry server")?;
+	info!(endpoint, "Connected to telemetry server");
+
+	let mut id = 0;
+	let mut send = |payload: Payload| {
+		id += 1;
+		encode(id, &payload).map(Message::Text)
+	};
+
+	socket.send(send(connected(node, state))?).await?;
+
+	let mut interval = time::interval_at(Instant::now() + INTERVAL, INTERVAL);
+	loop {
+		tokio::select! {
+			_ = interval.tick() => {
+				let peers = p2p_client.count_dht_entries().await.unwrap_or_else(|error| {
+					warn!("Unable to count peers: {error:#}");
+					0
+				});
+				socket.send(send(status.interval(peers))?).await?;
+			},
+			event = rpc_events.recv() => match event {
+				Ok(Event::HeaderUpdate { header, .. }) => {
+					status.import(&header);
+					for payload in status.imported(origin(state)) {
+						socket.send(send(payload)?).await?;
+					}
+				},
+				Err(RecvError::Lagged(skipped)) => {
+					warn!(skipped, "Finalized headers receiver lagged");
+				},
+				Err(RecvError::Closed) => {
+					error!("Finalized headers channel closed");
+					return Ok(());
+				},
+			},
+		}
+	}
+}
+
+/// Reports light client status to Substrate telemetry server, reconnecting if connection is lost.
+///
+/// # Arguments
+///
+/// * `endpoint` - WebSocket endpoint of telemetry server
+/// * `node` - Node identification shown on the dashboard
+/// * `p2p_client` - Used for counting peers
+/// * `state` - Light client state, with connected node and sync status
+/// * `rpc_events` - GRANDPA verified headers
+pub async fn run(
+	endpoint: String,
+	node: NodeInfo,
+	p2p_client: P2pClient,
+	state: Arc<Mutex<State>>,
+	mut rpc_events: broadcast::Receiver<Event>,
+) {
+	info!("Starting Substrate telemetry client...");
+	let mut status = Status::default();
+
+	loop {
+		match report(
+			&endpoint,
+			&node,
+			&p2p_client,
+			&state,
+			&mut status,
+			&mut rpc_events,
+		)
+		.await
+		{
+			Ok(()) => return,
+			Err(error) => warn!(endpoint, "Telemetry connection failed: {error:#}"),
+		}
+		debug!("Reconnecting to telemetry server in {RECONNECT_DELAY:?}");
+		time::sleep(RECONNECT_DELAY).await;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::{json, Value};
+	use test_case::test_case;
+
+	#[test_case(Payload::BlockImport { best: H256::repeat_byte(1), height: 42, origin: BlockOrigin::NetworkInitialSync } => json!({
+		"msg": "block.import",
+		"best": format!("0x{}", "01".repeat(32)),
+		"height": 42,
+		"origin": "NetworkInitialSync"
+	}) ; "block import")]
+	#[test_case(Payload::NotifyFinalized { best: H256::repeat_byte(1), height: "42".to_string() } => json!({
+		"msg": "notify.finalized",
+		"best": format!("0x{}", "01".repeat(32)),
+		"height": "42"
+	}) ; "notify finalized")]
+	#[test_case(Status { best: H256::zero(), height: 7 }.interval(3) => json!({
+		"msg": "system.interval",
+		"peers": 3,
+		"txcount": 0,
+		"best": format!("0x{}", "00".repeat(32)),
+		"height": 7,
+		"finalized_hash": format!("0x{}", "00".repeat(32)),
+		"finalized_height": 7
+	}) ; "system interval")]
+	fn encode_payload(payload: Payload) -> Value {
+		let message: Value = serde_json::from_str(&encode(1, &payload).unwrap()).unwrap();
+		assert_eq!(message["id"], 1);
+		assert!(message["ts"].is_string());
+		message["payload"].clone()
+	}
+}
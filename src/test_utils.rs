@@ -0,0 +1,213 @@
+//! Proptest strategies for property testing of sync and verification logic.
+//!
+//! Generated values are consistent, so they pass verification performed by this crate:
+//!
+//! * Header chains have valid parent links and extrinsics roots
+//! * Read proofs are created from an in-memory trie, and verify against its root
+//! * Justifications are signed by all validators of the generated validator set
+
+use avail_subxt::{
+	api::runtime_types::avail_core::{
+		data_lookup::compact::CompactDataLookup,
+		header::extension::{v3, HeaderExtension},
+		kate_commitment::v3::KateCommitment,
+	},
+	config::substrate::Digest,
+	primitives::Header,
+	utils::H256,
+};
+use codec::Encode;
+use proptest::{
+	collection::{btree_map, hash_set, vec},
+	prelude::any,
+	strategy::Strategy,
+};
+use sp_core::{ed25519, Pair};
+use std::ops::Range;
+
+use crate::{
+	block_builder::extrinsics_root,
+	body::Block,
+	finality::ValidatorSet,
+	header::HeaderHash,
+	storage_proof::build_trie,
+	types::{Commit, GrandpaJustification, Precommit, SignedPrecommit, SignerMessage},
+};
+
+pub mod adversary;
+mod chain_builder;
+pub mod metadata;
+
+pub use chain_builder::{BlockSpec, ChainBuilder};
+
+/// Chain of blocks starting at block 1, with parent of the first block being zero hash
+pub fn arb_block_chain(len: Range<usize>) -> impl Strategy<Value = Vec<Block>> {
+	let block = (
+		any::<[u8; 32]>(),
+		any::<[u8; 32]>(),
+		vec(vec(any::<u8>(), 1..64), 0..4),
+	);
+	vec(block, len).prop_map(|blocks| {
+		let mut parent_hash = H256::zero();
+		blocks
+			.into_iter()
+			.enumerate()
+			.map(|(index, (state_root, data_root, extrinsics))| {
+				let header = Header {
+					parent_hash,
+					number: index as u32 + 1,
+					state_root: H256(state_root),
+					extrinsics_root: extrinsics_root(&extrinsics),
+					digest: Digest { logs: vec![] },
+					extension: HeaderExtension::V3(v3::HeaderExtension {
+						commitment: KateCommitment {
+							rows: 1,
+							cols: 4,
+							data_root: H256(data_root),
+							commitment: vec![0; 48],
+						},
+						app_lookup: CompactDataLookup {
+							size: 1,
+							index: vec![],
+						},
+					}),
+				};
+				parent_hash = header.header_hash();
+				Block { header, extrinsics }
+			})
+			.collect()
+	})
+}
+
+pub fn arb_header_chain(len: Range<usize>) -> impl Strategy<Value = Vec<Header>> {
+	arb_block_chain(len).prop_map(|blocks| blocks.into_iter().map(|block| block.header).collect())
+}
+
+#[derive(Clone, Debug)]
+pub struct ReadProof {
+	pub state_root: H256,
+	pub proof: Vec<Vec<u8>>,
+	/// Proven key-value pairs
+	pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+pub fn arb_read_proof(entries: Range<usize>) -> impl Strategy<Value = ReadProof> {
+	btree_map(vec(any::<u8>(), 1..32), vec(any::<u8>(), 1..64), entries).prop_map(|entries| {
+		let entries = entries.into_iter().collect::<Vec<_>>();
+		let (state_root, proof) = build_trie(
+			&entries
+				.iter()
+				.map(|(key, value)| (&key[..], &value[..]))
+				.collect::<Vec<_>>(),
+		);
+		ReadProof {
+			state_root,
+			proof,
+			entries,
+		}
+	})
+}
+
+/// Creates justification of the header, signed by all given validators
+pub fn sign_justification(
+	header: &Header,
+	validators: &[ed25519::Pair],
+	set_id: u64,
+	round: u64,
+) -> GrandpaJustification {
+	let precommit = Precommit {
+		target_hash: header.header_hash(),
+		target_number: header.number,
+	};
+	let message = Encode::encode(&(
+		&SignerMessage::PrecommitMessage(precommit.clone()),
+		&round,
+		&set_id,
+	));
+	let precommits = validators
+		.iter()
+		.map(|pair| SignedPrecommit {
+			precommit: precommit.clone(),
+			signature: pair.sign(&message),
+			id: pair.public(),
+		})
+		.collect();
+	GrandpaJustification {
+		round,
+		commit: Commit {
+			target_hash: precommit.target_hash,
+			target_number: precommit.target_number,
+			precommits,
+		},
+		votes_ancestries: vec![],
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct SignedHeader {
+	pub header: Header,
+	pub validator_set: ValidatorSet,
+	pub justification: GrandpaJustification,
+}
+
+/// Header with justification signed by all validators of the validator set
+pub fn arb_signed_header(validators: Range<usize>) -> impl Strategy<Value = SignedHeader> {
+	(
+		arb_header_chain(1..2),
+		hash_set(any::<[u8; 32]>(), validators),
+		10..u32::MAX as u64,
+		any::<u64>(),
+	)
+		.prop_map(|(mut headers, seeds, set_id, round)| {
+			let header = headers.pop().expect("Chain is not empty");
+			let pairs = seeds
+				.iter()
+				.map(ed25519::Pair::from_seed)
+				.collect::<Vec<_>>();
+			SignedHeader {
+				validator_set: ValidatorSet {
+					set_id,
+					validator_set: pairs.iter().map(|pair| pair.public()).collect(),
+				},
+				justification: sign_justification(&header, &pairs, set_id, round),
+				header,
+			}
+		})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{finality::check_finality, storage_proof::verify_read_proof};
+	use proptest::proptest;
+
+	proptest! {
+	#[test]
+	fn header_chain_is_linked(blocks in arb_block_chain(1..16)) {
+		for pair in blocks.windows(2) {
+			assert_eq!(pair[1].header.parent_hash, pair[0].header.header_hash());
+			assert_eq!(pair[1].header.number, pair[0].header.number + 1);
+		}
+		for block in blocks {
+			assert_eq!(block.header.extrinsics_root, extrinsics_root(&block.extrinsics));
+		}
+	}
+	}
+
+	proptest! {
+	#[test]
+	fn read_proof_verifies(read_proof in arb_read_proof(1..32)) {
+		let (keys, values): (Vec<_>, Vec<_>) = read_proof.entries.into_iter().unzip();
+		let verified = verify_read_proof(read_proof.state_root, read_proof.proof, &keys).unwrap();
+		let verified = verified.into_iter().map(|(_, value)| value.unwrap()).collect::<Vec<_>>();
+		assert_eq!(verified, values);
+	}
+	}
+
+	proptest! {
+	#[test]
+	fn justification_is_valid(signed in arb_signed_header(1..8)) {
+		assert!(check_finality(&signed.validator_set, &signed.justification).is_ok());
+	}
+	}
+}
@@ -0,0 +1,254 @@
+//! Invalid, but plausible artifacts of malicious peers, for robustness tests.
+//!
+//! Artifacts are derived from the valid ones, and pass the structural checks (encoding, lengths, signature formats),
+//! so tests can assert that the client rejects them at the right verification step, with the right error and peer
+//! penalty:
+//!
+//! * Headers sealed with the foreign key, without the seal, or truncated
+//! * Read proofs with truncated or missing trie nodes
+//! * Cells with proofs of the other cells
+//! * Justifications with forged signatures, and equivocating justifications of the same round
+
+use avail_subxt::{config::substrate::DigestItem, primitives::Header};
+use codec::Encode;
+use kate_recovery::data::Cell;
+use sp_core::{ed25519, Pair};
+
+use super::{chain_builder::AURA_ENGINE_ID, sign_justification};
+use crate::{header::HeaderHash, types::GrandpaJustification};
+
+/// Size of the KZG proof at the beginning of the cell content
+const PROOF_SIZE: usize = 48;
+
+/// Replaces the seal with signature of the pre-seal hash by the key which is not the block author
+pub fn with_foreign_seal(header: &Header, seed: [u8; 32]) -> Header {
+	let engine_id = header
+		.digest
+		.logs
+		.iter()
+		.find_map(|item| match item {
+			DigestItem::Seal(engine_id, _) => Some(*engine_id),
+			_ => None,
+		})
+		.unwrap_or(AURA_ENGINE_ID);
+	let mut header = without_seal(header);
+	let signature = ed25519::Pair::from_seed(&seed).sign(header.header_hash().as_bytes());
+	header
+		.digest
+		.logs
+		.push(DigestItem::Seal(engine_id, signature.0.to_vec()));
+	header
+}
+
+pub fn without_seal(header: &Header) -> Header {
+	let mut header = header.clone();
+	header
+		.digest
+		.logs
+		.retain(|item| !matches!(item, DigestItem::Seal(..)));
+	header
+}
+
+/// Encoded header, with the extension cut off in the middle
+pub fn truncated_header(header: &Header) -> Vec<u8> {
+	let mut encoded = header.encode();
+	encoded.truncate(encoded.len() - header.extension.encoded_size() / 2);
+	encoded
+}
+
+/// Truncates the largest trie node (usually the root) of the read proof by half
+pub fn with_truncated_node(proof: &[Vec<u8>]) -> Vec<Vec<u8>> {
+	let mut proof = proof.to_vec();
+	if let Some(node) = proof.iter_mut().max_by_key(|node| node.len()) {
+		node.truncate(node.len() / 2);
+	}
+	proof
+}
+
+/// Removes the trie node of the read proof
+pub fn without_node(proof: &[Vec<u8>], index: usize) -> Vec<Vec<u8>> {
+	let mut proof = proof.to_vec();
+	if index < proof.len() {
+		proof.remove(index);
+	}
+	proof
+}
+
+/// Cell with its own data, and the proof of the other cell, which is a valid point but doesn't open the commitment
+pub fn with_proof_of(cell: &Cell, other: &Cell) -> Cell {
+	let mut content = cell.content;
+	content[..PROOF_SIZE].copy_from_slice(&other.content[..PROOF_SIZE]);
+	Cell {
+		position: cell.position,
+		content,
+	}
+}
+
+/// Shifts proofs of the cells by one, so each cell gets proof of the next one
+pub fn rotate_proofs(cells: &[Cell]) -> Vec<Cell> {
+	cells
+		.iter()
+		.enumerate()
+		.map(|(index, cell)| with_proof_of(cell, &cells[(index + 1) % cells.len()]))
+		.collect()
+}
+
+/// Justification of the header, with precommits signed for the next round
+pub fn with_forged_signatures(
+	header: &Header,
+	validators: &[ed25519::Pair],
+	set_id: u64,
+	round: u64,
+) -> GrandpaJustification {
+	let mut justification = sign_justification(header, validators, set_id, round);
+	let next_round = sign_justification(header, validators, set_id, round.wrapping_add(1));
+	for (precommit, signed) in justification
+		.commit
+		.precommits
+		.iter_mut()
+		.zip(next_round.commit.precommits)
+	{
+		precommit.signature = signed.signature;
+	}
+	justification
+}
+
+/// Valid justifications of the two different blocks, signed by the same validators in the same round
+pub fn equivocating_justifications(
+	first: &Header,
+	second: &Header,
+	validators: &[ed25519::Pair],
+	set_id: u64,
+	round: u64,
+) -> (GrandpaJustification, GrandpaJustification) {
+	(
+		sign_justification(first, validators, set_id, round),
+		sign_justification(second, validators, set_id, round),
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		equivocation::GrandpaEquivocationDetector,
+		finality::{check_finality, ValidatorSet},
+		network::p2p::block_announce::{check_announce, AnnounceError, BlockAnnounce},
+		simulation,
+		storage_proof::{build_trie, verify_read_proof},
+		test_utils::ChainBuilder,
+	};
+	use avail_subxt::utils::H256;
+	use kate_recovery::matrix::Position;
+
+	fn pairs(count: u8) -> Vec<ed25519::Pair> {
+		(1..=count)
+			.map(|seed| ed25519::Pair::from_seed(&[seed; 32]))
+			.collect()
+	}
+
+	#[test]
+	fn reject_invalid_seals() {
+		let mut chain = ChainBuilder::new(&[[1; 32]]).with_seal_key([9; 32]);
+		let hash = chain.extend(1).unwrap()[0];
+		let header = chain.header(&hash).unwrap();
+
+		let forged = with_foreign_seal(header, [8; 32]);
+		let Some(DigestItem::Seal(_, signature)) = forged.digest.logs.last() else {
+			panic!("Seal is missing");
+		};
+		let signature = ed25519::Signature(signature.clone().try_into().unwrap());
+		let author = ed25519::Pair::from_seed(&[9; 32]).public();
+		let pre_hash = without_seal(&forged).header_hash();
+		assert!(!ed25519::Pair::verify(
+			&signature,
+			pre_hash.as_bytes(),
+			&author
+		));
+
+		// Header with the consistent extension, so only the seal is checked
+		let header = simulation::header(1, H256::zero(), 0);
+		let announces = [
+			(
+				without_seal(&header).encode(),
+				AnnounceError::MissingSeal.cost(),
+			),
+			(truncated_header(&header), -1024),
+		];
+		for (header, cost) in announces {
+			let announce = BlockAnnounce {
+				header,
+				is_best: true,
+			};
+			let error = check_announce(&announce, &Default::default()).unwrap_err();
+			assert_eq!(error.cost(), cost);
+		}
+	}
+
+	#[test]
+	fn reject_invalid_read_proofs() {
+		let entries = (0u8..64)
+			.map(|index| (vec![index; 4], vec![index; 40]))
+			.collect::<Vec<_>>();
+		let (root, proof) = build_trie(
+			&entries
+				.iter()
+				.map(|(key, value)| (&key[..], &value[..]))
+				.collect::<Vec<_>>(),
+		);
+		let keys = entries.into_iter().map(|(key, _)| key).collect::<Vec<_>>();
+		assert!(verify_read_proof(root, proof.clone(), &keys).is_ok());
+		assert!(verify_read_proof(root, with_truncated_node(&proof), &keys).is_err());
+		assert!(verify_read_proof(root, without_node(&proof, 0), &keys).is_err());
+	}
+
+	#[test]
+	fn swap_cell_proofs() {
+		let cells = (0..3u8)
+			.map(|col| Cell {
+				position: Position {
+					row: 0,
+					col: col as u16,
+				},
+				content: [col; 80],
+			})
+			.collect::<Vec<_>>();
+		let rotated = rotate_proofs(&cells);
+		for (index, cell) in rotated.iter().enumerate() {
+			assert_eq!(cell.position, cells[index].position);
+			assert_eq!(
+				cell.content[PROOF_SIZE..],
+				cells[index].content[PROOF_SIZE..]
+			);
+			assert_eq!(
+				cell.content[..PROOF_SIZE],
+				cells[(index + 1) % 3].content[..PROOF_SIZE]
+			);
+		}
+	}
+
+	#[test]
+	fn reject_invalid_justifications() {
+		let mut chain = ChainBuilder::new(&[[1; 32], [2; 32], [3; 32]]);
+		let main = chain.extend(2).unwrap();
+		let fork = chain.extend_from(main[0], 1).unwrap();
+		let validators = pairs(3);
+		let validator_set = ValidatorSet {
+			set_id: 10,
+			validator_set: validators.iter().map(|pair| pair.public()).collect(),
+		};
+
+		let header = chain.header(&main[1]).unwrap();
+		let forged = with_forged_signatures(header, &validators, 10, 1);
+		assert_eq!(forged.commit.target_hash, main[1]);
+		assert!(check_finality(&validator_set, &forged).is_err());
+
+		let fork_header = chain.header(&fork[0]).unwrap();
+		let (first, second) = equivocating_justifications(header, fork_header, &validators, 10, 1);
+		assert!(check_finality(&validator_set, &first).is_ok());
+		assert!(check_finality(&validator_set, &second).is_ok());
+		let mut detector = GrandpaEquivocationDetector::default();
+		assert!(detector.observe_justification(10, &first).is_empty());
+		assert_eq!(detector.observe_justification(10, &second).len(), 3);
+	}
+}
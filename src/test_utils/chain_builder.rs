@@ -0,0 +1,395 @@
+//! Deterministic chain builder for integration tests of sync and fork choice.
+//!
+//! Blocks are built with [`BlockBuilder`], on top of any known block, so forks are created by building on
+//! a non-best parent. Every block gets a unique slot, which makes sibling blocks with equal content distinct.
+//!
+//! # Seals and authorities
+//!
+//! * Blocks are sealed with Aura-style ed25519 signature of the pre-seal hash if seal key is set
+//! * Authority set changes are emitted as GRANDPA scheduled change consensus logs, enacted immediately
+//! * Justifications are signed by the authority set which is active on the block parent
+
+use avail_subxt::{
+	api::runtime_types::avail_core::{
+		data_lookup::compact::CompactDataLookup,
+		header::extension::{v3, HeaderExtension},
+		kate_commitment::v3::KateCommitment,
+	},
+	config::substrate::{Digest, DigestItem},
+	primitives::Header,
+	utils::H256,
+};
+use codec::Encode;
+use color_eyre::{eyre::eyre, Result};
+use sp_core::{blake2_256, ed25519, Pair};
+use std::collections::HashMap;
+
+use super::sign_justification;
+use crate::{
+	block_builder::{BlockBuilder, Executor, FinalizedState, SealProvider},
+	body::Block,
+	finality::ValidatorSet,
+	header::HeaderHash,
+	inherents::{
+		create_inherent_data, InherentData, TimestampProvider, TIMESTAMP_INHERENT_IDENTIFIER,
+	},
+	types::GrandpaJustification,
+};
+
+pub const AURA_ENGINE_ID: [u8; 4] = *b"aura";
+pub const GRANDPA_ENGINE_ID: [u8; 4] = *b"FRNK";
+const SLOT_DURATION_MS: u64 = 20_000;
+
+/// Content of the block to build
+#[derive(Clone, Debug, Default)]
+pub struct BlockSpec {
+	pub extrinsics: Vec<Vec<u8>>,
+	/// Additional digest items, emitted by the executor
+	pub logs: Vec<DigestItem>,
+	/// Seeds of the new authority set, scheduled in this block
+	pub authority_change: Option<Vec<[u8; 32]>>,
+}
+
+fn extension(data_root: H256) -> HeaderExtension {
+	HeaderExtension::V3(v3::HeaderExtension {
+		commitment: KateCommitment {
+			rows: 1,
+			cols: 4,
+			data_root,
+			commitment: vec![0; 48],
+		},
+		app_lookup: CompactDataLookup {
+			size: 1,
+			index: vec![],
+		},
+	})
+}
+
+/// Encodes GRANDPA scheduled change consensus log, with zero delay
+fn scheduled_change(authorities: &[ed25519::Pair]) -> DigestItem {
+	const SCHEDULED_CHANGE: u8 = 1;
+	let next_authorities = authorities
+		.iter()
+		.map(|pair| (pair.public().0, 1u64))
+		.collect::<Vec<_>>();
+	let log = (SCHEDULED_CHANGE, next_authorities, 0u32).encode();
+	DigestItem::Consensus(GRANDPA_ENGINE_ID, log)
+}
+
+struct ChainExecutor {
+	parent_state_root: H256,
+	applied: Vec<Vec<u8>>,
+	logs: Vec<DigestItem>,
+}
+
+impl Executor for ChainExecutor {
+	fn initialize_block(&mut self, _: &Header) -> Result<()> {
+		Ok(())
+	}
+
+	fn inherent_extrinsics(&mut self, inherent_data: &InherentData) -> Result<Vec<Vec<u8>>> {
+		let timestamp: Option<u64> = inherent_data.get(&TIMESTAMP_INHERENT_IDENTIFIER)?;
+		Ok(timestamp.into_iter().map(|t| t.encode()).collect())
+	}
+
+	fn apply_extrinsic(&mut self, extrinsic: &[u8]) -> Result<()> {
+		self.applied.push(extrinsic.to_vec());
+		Ok(())
+	}
+
+	fn finalize_block(&mut self) -> Result<FinalizedState> {
+		let state_root = blake2_256(&(self.parent_state_root, &self.applied).encode()).into();
+		Ok(FinalizedState {
+			state_root,
+			extension: extension(blake2_256(&self.applied.concat()).into()),
+			logs: std::mem::take(&mut self.logs),
+		})
+	}
+}
+
+struct ChainSealer<'a> {
+	slot: u64,
+	key: Option<&'a ed25519::Pair>,
+}
+
+impl SealProvider for ChainSealer<'_> {
+	fn pre_runtime_digest(&self, _: &Header) -> Result<Option<DigestItem>> {
+		Ok(Some(DigestItem::PreRuntime(
+			AURA_ENGINE_ID,
+			self.slot.encode(),
+		)))
+	}
+
+	fn seal(&self, pre_hash: H256) -> Result<DigestItem> {
+		let signature = self
+			.key
+			.map(|key| key.sign(pre_hash.as_bytes()).0.to_vec())
+			.unwrap_or_default();
+		Ok(DigestItem::Seal(AURA_ENGINE_ID, signature))
+	}
+}
+
+struct Entry {
+	block: Block,
+	/// Authority set which finalizes this block
+	set_id: u64,
+	authorities: Vec<ed25519::Pair>,
+	/// Authority set active for the children of this block
+	next: (u64, Vec<ed25519::Pair>),
+}
+
+pub struct ChainBuilder {
+	genesis_hash: H256,
+	best_hash: H256,
+	entries: HashMap<H256, Entry>,
+	seal_key: Option<ed25519::Pair>,
+	slot: u64,
+}
+
+impl ChainBuilder {
+	/// Creates chain with genesis block and initial authority set created from given seeds.
+	pub fn new(authorities: &[[u8; 32]]) -> Self {
+		let header = Header {
+			parent_hash: H256::zero(),
+			number: 0,
+			state_root: H256::zero(),
+			extrinsics_root: H256::zero(),
+			digest: Digest { logs: vec![] },
+			extension: extension(H256::zero()),
+		};
+		let genesis_hash = header.header_hash();
+		let authorities = authorities
+			.iter()
+			.map(ed25519::Pair::from_seed)
+			.collect::<Vec<_>>();
+		let entry = Entry {
+			block: Block {
+				header,
+				extrinsics: vec![],
+			},
+			set_id: 0,
+			authorities: authorities.clone(),
+			next: (0, authorities),
+		};
+		ChainBuilder {
+			genesis_hash,
+			best_hash: genesis_hash,
+			entries: HashMap::from([(genesis_hash, entry)]),
+			seal_key: None,
+			slot: 0,
+		}
+	}
+
+	/// Seals blocks with the key created from given seed.
+	pub fn with_seal_key(mut self, seed: [u8; 32]) -> Self {
+		self.seal_key = Some(ed25519::Pair::from_seed(&seed));
+		self
+	}
+
+	pub fn genesis_hash(&self) -> H256 {
+		self.genesis_hash
+	}
+
+	/// Hash of the most recently built block
+	pub fn best_hash(&self) -> H256 {
+		self.best_hash
+	}
+
+	pub fn block(&self, hash: &H256) -> Option<&Block> {
+		self.entries.get(hash).map(|entry| &entry.block)
+	}
+
+	pub fn header(&self, hash: &H256) -> Option<&Header> {
+		self.block(hash).map(|block| &block.header)
+	}
+
+	/// Builds block on top of the given parent, returns its hash.
+	pub fn build_on(&mut self, parent_hash: H256, spec: BlockSpec) -> Result<H256> {
+		let parent = self
+			.entries
+			.get(&parent_hash)
+			.ok_or_else(|| eyre!("Unknown parent {parent_hash:?}"))?;
+		let (set_id, authorities) = parent.next.clone();
+
+		let mut logs = spec.logs;
+		let next = match spec.authority_change {
+			Some(seeds) => {
+				let next_authorities = seeds
+					.iter()
+					.map(ed25519::Pair::from_seed)
+					.collect::<Vec<_>>();
+				logs.push(scheduled_change(&next_authorities));
+				(set_id + 1, next_authorities)
+			},
+			None => (set_id, authorities.clone()),
+		};
+
+		let executor = ChainExecutor {
+			parent_state_root: parent.block.header.state_root,
+			applied: vec![],
+			logs,
+		};
+		let mut builder = BlockBuilder::new(parent.block.header.clone(), executor);
+		for extrinsic in spec.extrinsics {
+			builder.push(extrinsic);
+		}
+
+		self.slot += 1;
+		let timestamp = TimestampProvider {
+			timestamp: self.slot * SLOT_DURATION_MS,
+		};
+		let sealer = ChainSealer {
+			slot: self.slot,
+			key: self.seal_key.as_ref(),
+		};
+		let sealed = builder.build(&create_inherent_data(&[&timestamp])?, &sealer)?;
+
+		let hash = sealed.hash();
+		let entry = Entry {
+			block: Block {
+				header: sealed.header,
+				extrinsics: sealed.extrinsics,
+			},
+			set_id,
+			authorities,
+			next,
+		};
+		self.entries.insert(hash, entry);
+		self.best_hash = hash;
+		Ok(hash)
+	}
+
+	/// Builds given number of empty blocks on top of the given parent, returns their hashes.
+	pub fn extend_from(&mut self, parent_hash: H256, count: usize) -> Result<Vec<H256>> {
+		let mut hashes = vec![];
+		let mut parent_hash = parent_hash;
+		for _ in 0..count {
+			parent_hash = self.build_on(parent_hash, BlockSpec::default())?;
+			hashes.push(parent_hash);
+		}
+		Ok(hashes)
+	}
+
+	/// Builds given number of empty blocks on top of the best block, returns their hashes.
+	pub fn extend(&mut self, count: usize) -> Result<Vec<H256>> {
+		self.extend_from(self.best_hash, count)
+	}
+
+	/// Returns blocks from the genesis (excluded) to the given block, in ascending order.
+	pub fn chain_to(&self, hash: &H256) -> Result<Vec<&Block>> {
+		let mut blocks = vec![];
+		let mut hash = *hash;
+		while hash != self.genesis_hash {
+			let block = self
+				.block(&hash)
+				.ok_or_else(|| eyre!("Unknown block {hash:?}"))?;
+			hash = block.header.parent_hash;
+			blocks.push(block);
+		}
+		blocks.reverse();
+		Ok(blocks)
+	}
+
+	/// Validator set which finalizes the given block
+	pub fn validator_set(&self, hash: &H256) -> Option<ValidatorSet> {
+		self.entries.get(hash).map(|entry| ValidatorSet {
+			set_id: entry.set_id,
+			validator_set: entry.authorities.iter().map(|pair| pair.public()).collect(),
+		})
+	}
+
+	/// Creates justification of the given block, signed by its validator set.
+	pub fn justification(&self, hash: &H256, round: u64) -> Result<GrandpaJustification> {
+		let entry = self
+			.entries
+			.get(hash)
+			.ok_or_else(|| eyre!("Unknown block {hash:?}"))?;
+		Ok(sign_justification(
+			&entry.block.header,
+			&entry.authorities,
+			entry.set_id,
+			round,
+		))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{block_builder::extrinsics_root, utils::filter_auth_set_changes};
+
+	fn seeds(seed: u8, count: u8) -> Vec<[u8; 32]> {
+		(0..count).map(|index| [seed + index; 32]).collect()
+	}
+
+	#[test]
+	fn build_chain_with_fork() {
+		let mut chain = ChainBuilder::new(&seeds(1, 3));
+		let main = chain.extend(5).unwrap();
+		let fork = chain.extend_from(main[1], 2).unwrap();
+		assert_ne!(main[2], fork[0]);
+		assert_eq!(chain.best_hash(), fork[1]);
+
+		let blocks = chain.chain_to(&fork[1]).unwrap();
+		assert_eq!(blocks.len(), 4);
+		assert_eq!(blocks[0].header.parent_hash, chain.genesis_hash());
+		for pair in blocks.windows(2) {
+			assert_eq!(pair[1].header.parent_hash, pair[0].header.header_hash());
+		}
+		for block in blocks {
+			assert_eq!(
+				block.header.extrinsics_root,
+				extrinsics_root(&block.extrinsics)
+			);
+		}
+	}
+
+	#[test]
+	fn deterministic_chain() {
+		let build = || {
+			let mut chain = ChainBuilder::new(&seeds(1, 3)).with_seal_key([9; 32]);
+			chain.extend(3).unwrap()
+		};
+		assert_eq!(build(), build());
+	}
+
+	#[test]
+	fn valid_seal() {
+		let mut chain = ChainBuilder::new(&seeds(1, 1)).with_seal_key([9; 32]);
+		let hash = chain.extend(1).unwrap()[0];
+		let mut header = chain.header(&hash).unwrap().clone();
+		let Some(DigestItem::Seal(AURA_ENGINE_ID, signature)) = header.digest.logs.pop() else {
+			panic!("Seal is missing");
+		};
+		let signature = ed25519::Signature(signature.try_into().unwrap());
+		let public = ed25519::Pair::from_seed(&[9; 32]).public();
+		assert!(ed25519::Pair::verify(
+			&signature,
+			header.header_hash().as_bytes(),
+			&public
+		));
+	}
+
+	#[test]
+	fn authority_set_change() {
+		let mut chain = ChainBuilder::new(&seeds(1, 3));
+		let spec = BlockSpec {
+			authority_change: Some(seeds(10, 2)),
+			..Default::default()
+		};
+		let change = chain.build_on(chain.genesis_hash(), spec).unwrap();
+		let next = chain.extend(1).unwrap()[0];
+
+		let changes = filter_auth_set_changes(chain.header(&change).unwrap());
+		assert_eq!(changes.len(), 1);
+		assert_eq!(changes[0].len(), 2);
+
+		let change_set = chain.validator_set(&change).unwrap();
+		assert_eq!((change_set.set_id, change_set.validator_set.len()), (0, 3));
+		let next_set = chain.validator_set(&next).unwrap();
+		assert_eq!((next_set.set_id, next_set.validator_set.len()), (1, 2));
+
+		let justification = chain.justification(&next, 1).unwrap();
+		assert!(crate::finality::check_finality(&next_set, &justification).is_ok());
+	}
+}
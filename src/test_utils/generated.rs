@@ -0,0 +1,94 @@
+// Generated from the runtime metadata, do not edit.
+
+#[allow(dead_code, clippy::all)]
+pub mod types {
+	#[derive(Clone, Debug, PartialEq, Eq, ::codec::Encode, ::codec::Decode)]
+	pub struct AppId(#[codec(compact)] pub u32);
+
+	#[derive(Clone, Debug, PartialEq, Eq, ::codec::Encode, ::codec::Decode)]
+	pub struct AppKeyInfo {
+		pub owner: [u8; 32],
+		pub id: AppId,
+	}
+}
+
+#[allow(dead_code, unused_imports, clippy::all)]
+pub mod data_availability {
+	pub const PALLET_INDEX: u8 = 29;
+
+	pub mod calls {
+		use super::super::types::*;
+
+		#[derive(Clone, Debug, PartialEq, Eq, ::codec::Encode, ::codec::Decode)]
+		pub struct CreateApplicationKey {
+			pub key: Vec<u8>,
+		}
+
+		impl CreateApplicationKey {
+			pub const CALL_INDEX: u8 = 0;
+
+			/// Encoded call, prefixed with the pallet and call indices
+			pub fn encode_call(&self) -> Vec<u8> {
+				let mut call = vec![super::PALLET_INDEX, Self::CALL_INDEX];
+				::codec::Encode::encode_to(self, &mut call);
+				call
+			}
+		}
+
+		#[derive(Clone, Debug, PartialEq, Eq, ::codec::Encode, ::codec::Decode)]
+		pub struct SubmitData {
+			pub data: Vec<u8>,
+		}
+
+		impl SubmitData {
+			pub const CALL_INDEX: u8 = 1;
+
+			/// Encoded call, prefixed with the pallet and call indices
+			pub fn encode_call(&self) -> Vec<u8> {
+				let mut call = vec![super::PALLET_INDEX, Self::CALL_INDEX];
+				::codec::Encode::encode_to(self, &mut call);
+				call
+			}
+		}
+	}
+
+	pub mod events {
+		use super::super::types::*;
+
+		#[derive(Clone, Debug, PartialEq, Eq, ::codec::Encode, ::codec::Decode)]
+		pub enum Event {
+			#[codec(index = 0)]
+			ApplicationKeyCreated {
+				key: Vec<u8>,
+				owner: [u8; 32],
+				id: AppId,
+			},
+			#[codec(index = 1)]
+			DataSubmitted {
+				who: [u8; 32],
+				data_hash: [u8; 32],
+			},
+		}
+	}
+
+	pub mod storage {
+		use super::super::types::*;
+
+		pub type NextAppId = AppId;
+
+		/// Storage key of `DataAvailability.NextAppId`
+		pub fn next_app_id_key() -> Vec<u8> {
+			vec![144, 94, 89, 246, 200, 252, 151, 78, 198, 65, 22, 230, 246, 71, 153, 40, 189, 33, 95, 138, 209, 89, 208, 65, 3, 214, 26, 61, 67, 15, 238, 52]
+		}
+
+		pub type AppKeys = AppKeyInfo;
+
+		/// Storage key of `DataAvailability.AppKeys`
+		pub fn app_keys_key(key0: &Vec<u8>) -> Vec<u8> {
+			let mut storage_key = vec![144, 94, 89, 246, 200, 252, 151, 78, 198, 65, 22, 230, 246, 71, 153, 40, 41, 172, 52, 67, 12, 73, 52, 197, 227, 170, 238, 213, 171, 229, 62, 57];
+			let key = ::codec::Encode::encode(key0);
+			storage_key.extend([&::sp_core::blake2_128(&key)[..], &key[..]].concat());
+			storage_key
+		}
+	}
+}
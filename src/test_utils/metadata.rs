@@ -0,0 +1,168 @@
+//! Minimal runtime metadata, for the tests of the metadata driven encoding, decoding and code generation.
+//!
+//! Runtime has the `DataAvailability` pallet (index 29) with calls, events and storage, the `System` pallet (index 0)
+//! with an event only, and `CheckNonce` and `CheckAppId` signed extensions.
+
+use codec::{Decode, Encode};
+use scale_info::{meta_type, TypeInfo};
+use std::marker::PhantomData;
+use subxt::{
+	ext::frame_metadata::{
+		v14::{
+			ExtrinsicMetadata, PalletCallMetadata, PalletEventMetadata, PalletMetadata,
+			PalletStorageMetadata, RuntimeMetadataV14, SignedExtensionMetadata,
+			StorageEntryMetadata, StorageEntryModifier, StorageEntryType, StorageHasher,
+		},
+		RuntimeMetadataPrefixed,
+	},
+	Metadata,
+};
+
+pub const DATA_AVAILABILITY_INDEX: u8 = 29;
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode, TypeInfo)]
+pub struct AppId(#[codec(compact)] pub u32);
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode, TypeInfo)]
+pub struct AppKeyInfo {
+	pub owner: [u8; 32],
+	pub id: AppId,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode, TypeInfo)]
+#[allow(non_camel_case_types)]
+pub enum DataAvailabilityCall {
+	#[codec(index = 0)]
+	create_application_key { key: Vec<u8> },
+	#[codec(index = 1)]
+	submit_data { data: Vec<u8> },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode, TypeInfo)]
+pub enum DataAvailabilityEvent {
+	#[codec(index = 0)]
+	ApplicationKeyCreated {
+		key: Vec<u8>,
+		owner: [u8; 32],
+		id: AppId,
+	},
+	#[codec(index = 1)]
+	DataSubmitted { who: [u8; 32], data_hash: [u8; 32] },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode, TypeInfo)]
+pub enum SystemEvent {
+	#[codec(index = 0)]
+	ExtrinsicSuccess,
+	#[codec(index = 1)]
+	ExtrinsicFailed { error: u8 },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode, TypeInfo)]
+pub enum RuntimeCall {
+	#[codec(index = 29)]
+	DataAvailability(DataAvailabilityCall),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode, TypeInfo)]
+pub enum RuntimeEvent {
+	#[codec(index = 0)]
+	System(SystemEvent),
+	#[codec(index = 29)]
+	DataAvailability(DataAvailabilityEvent),
+}
+
+#[derive(Encode, Decode, TypeInfo)]
+pub struct CheckNonce(#[codec(compact)] pub u32);
+
+/// Opaque extrinsic, which references the call and the signed extensions
+#[derive(TypeInfo)]
+pub struct UncheckedExtrinsic<Address, Call, Signature, Extra>(
+	pub PhantomData<(Address, Call, Signature, Extra)>,
+);
+
+#[derive(TypeInfo)]
+pub struct Runtime {
+	pub call: RuntimeCall,
+	pub event: RuntimeEvent,
+}
+
+fn data_availability() -> PalletMetadata {
+	let entries = vec![
+		StorageEntryMetadata {
+			name: "NextAppId",
+			modifier: StorageEntryModifier::Default,
+			ty: StorageEntryType::Plain(meta_type::<AppId>()),
+			default: AppId(0).encode(),
+			docs: vec![],
+		},
+		StorageEntryMetadata {
+			name: "AppKeys",
+			modifier: StorageEntryModifier::Optional,
+			ty: StorageEntryType::Map {
+				hashers: vec![StorageHasher::Blake2_128Concat],
+				key: meta_type::<Vec<u8>>(),
+				value: meta_type::<AppKeyInfo>(),
+			},
+			default: vec![0],
+			docs: vec![],
+		},
+	];
+	PalletMetadata {
+		name: "DataAvailability",
+		storage: Some(PalletStorageMetadata {
+			prefix: "DataAvailability",
+			entries,
+		}),
+		calls: Some(PalletCallMetadata {
+			ty: meta_type::<DataAvailabilityCall>(),
+		}),
+		event: Some(PalletEventMetadata {
+			ty: meta_type::<DataAvailabilityEvent>(),
+		}),
+		constants: vec![],
+		error: None,
+		index: DATA_AVAILABILITY_INDEX,
+	}
+}
+
+fn system() -> PalletMetadata {
+	PalletMetadata {
+		name: "System",
+		storage: None,
+		calls: None,
+		event: Some(PalletEventMetadata {
+			ty: meta_type::<SystemEvent>(),
+		}),
+		constants: vec![],
+		error: None,
+		index: 0,
+	}
+}
+
+pub fn metadata() -> Metadata {
+	let signed_extensions = vec![
+		SignedExtensionMetadata {
+			identifier: "CheckNonce",
+			ty: meta_type::<CheckNonce>(),
+			additional_signed: meta_type::<()>(),
+		},
+		SignedExtensionMetadata {
+			identifier: "CheckAppId",
+			ty: meta_type::<AppId>(),
+			additional_signed: meta_type::<()>(),
+		},
+	];
+	let extrinsic = ExtrinsicMetadata {
+		ty: meta_type::<UncheckedExtrinsic<[u8; 32], RuntimeCall, [u8; 64], (CheckNonce, AppId)>>(),
+		version: 4,
+		signed_extensions,
+	};
+	let runtime = RuntimeMetadataV14::new(
+		vec![system(), data_availability()],
+		extrinsic,
+		meta_type::<Runtime>(),
+	);
+	let encoded = RuntimeMetadataPrefixed::from(runtime).encode();
+	Metadata::decode(&mut &encoded[..]).expect("Test metadata is valid")
+}
@@ -0,0 +1,389 @@
+//! Tip strategies for the submitted extrinsics, chosen from the recent block fullness and paid tips.
+//!
+//! [`FeeHistory`] keeps fullness and tips (from the `TransactionPayment::TransactionFeePaid` events) of the recent
+//! blocks, fed by [`run`] with the finalized blocks, and [`TipStrategy`] chooses the tip of the next submission
+//! attempt:
+//!
+//! * [`Fixed`] - the same tip for all attempts
+//! * [`Percentile`] - percentile of the tips paid in the recent blocks, or the minimum tip if the blocks are not
+//!   congested
+//! * [`Deadline`] - tip of the base strategy, escalated towards the maximum tip as the deadline approaches
+//!
+//! Extrinsics which are not finalized within the stall timeout are replaced by the
+//! [`crate::submission::DataSubmitter`], with the same nonce and the tip of the next attempt. Transaction pool
+//! accepts the replacement only with the higher priority, so the tip is bumped by at least
+//! [`REPLACEMENT_BUMP_PERCENT`] of the replaced tip (see [`replacement_tip`]).
+//!
+//! # Notes
+//!
+//! Block weight and events are read with the storage proofs, verified against the state root of the block.
+
+use avail_subxt::{primitives::Header, utils::H256, AvailConfig};
+use codec::Encode;
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use sp_core::blake2_256;
+use std::{
+	collections::VecDeque,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+use subxt::{events::Events, ext::scale_value::Composite, Metadata};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, warn};
+
+use crate::{
+	block_builder::extrinsics_root,
+	body::Block,
+	export::events_storage_key,
+	network::rpc::{Client as RpcClient, Event},
+	state_client::{Client, StateClient},
+	utilization::BlockUtilization,
+};
+
+/// Minimum increase of the tip of the replacement extrinsic, in percents of the replaced tip
+pub const REPLACEMENT_BUMP_PERCENT: u128 = 10;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockFees {
+	pub number: u32,
+	/// Largest of the used weight, length and data matrix parts of the block
+	pub fullness: f64,
+	/// Tips paid by the block extrinsics
+	pub tips: Vec<u128>,
+}
+
+fn tip_from_fields<T>(fields: &Composite<T>) -> Option<u128> {
+	match fields {
+		Composite::Named(fields) => fields
+			.iter()
+			.find(|(name, _)| name == "tip")
+			.and_then(|(_, value)| value.as_u128()),
+		Composite::Unnamed(_) => None,
+	}
+}
+
+impl BlockFees {
+	pub fn new(utilization: &BlockUtilization, events: &Events<AvailConfig>) -> Result<Self> {
+		let mut tips = vec![];
+		for event in events.iter() {
+			let event = event.wrap_err("Failed to decode event")?;
+			if event.pallet_name() != "TransactionPayment"
+				|| event.variant_name() != "TransactionFeePaid"
+			{
+				continue;
+			}
+			let fields = event
+				.field_values()
+				.wrap_err("Failed to decode fee paid event")?;
+			tips.extend(tip_from_fields(&fields));
+		}
+		Ok(BlockFees {
+			number: utilization.number,
+			fullness: utilization
+				.weight_ratio()
+				.max(utilization.length_ratio())
+				.max(utilization.data_ratio().unwrap_or(0.0)),
+			tips,
+		})
+	}
+}
+
+/// Fees of the recent blocks, with the oldest blocks dropped over the capacity
+pub struct FeeHistory {
+	capacity: usize,
+	blocks: Mutex<VecDeque<BlockFees>>,
+}
+
+impl FeeHistory {
+	pub fn new(capacity: usize) -> Self {
+		FeeHistory {
+			capacity,
+			blocks: Mutex::new(VecDeque::with_capacity(capacity)),
+		}
+	}
+
+	pub fn push(&self, fees: BlockFees) {
+		let mut blocks = self.blocks.lock().unwrap();
+		if blocks.len() == self.capacity {
+			blocks.pop_front();
+		}
+		blocks.push_back(fees);
+	}
+
+	pub fn blocks(&self) -> Vec<BlockFees> {
+		self.blocks.lock().unwrap().iter().cloned().collect()
+	}
+
+	/// Pushes fees of the block, with the block weight and events verified at the block state
+	pub async fn track<T: Client>(
+		&self,
+		state_client: &StateClient<T>,
+		metadata: &Metadata,
+		block: &Block,
+	) -> Result<()> {
+		let utilization = state_client.block_utilization(metadata, block).await?;
+		let encoded_events = state_client
+			.storage(events_storage_key(), utilization.hash)
+			.await
+			.wrap_err("Failed to get events")?
+			.unwrap_or_default();
+		let events = Events::<AvailConfig>::new(metadata.clone(), utilization.hash, encoded_events);
+		self.push(BlockFees::new(&utilization, &events)?);
+		Ok(())
+	}
+}
+
+async fn track_finalized(
+	history: &FeeHistory,
+	rpc_client: &RpcClient,
+	state_client: &StateClient<RpcClient>,
+	header: Header,
+) -> Result<()> {
+	let hash = H256(header.using_encoded(blake2_256));
+	let extrinsics = rpc_client.get_block_body(hash).await?;
+	if extrinsics_root(&extrinsics) != header.extrinsics_root {
+		return Err(eyre!(
+			"Block {} body doesn't match extrinsics root",
+			header.number
+		));
+	}
+	let metadata = rpc_client.current_client().await.metadata();
+	history
+		.track(state_client, &metadata, &Block { header, extrinsics })
+		.await
+}
+
+/// Pushes fees of the finalized blocks to the history, until the finalized headers channel is closed
+pub async fn run(
+	history: Arc<FeeHistory>,
+	rpc_client: RpcClient,
+	state_client: StateClient<RpcClient>,
+	mut rpc_events: broadcast::Receiver<Event>,
+) {
+	loop {
+		let header = match rpc_events.recv().await {
+			Ok(Event::HeaderUpdate { header, .. }) => header,
+			Err(RecvError::Lagged(skipped)) => {
+				warn!(skipped, "Finalized headers receiver lagged");
+				continue;
+			},
+			Err(RecvError::Closed) => {
+				error!("Finalized headers channel closed");
+				return;
+			},
+		};
+		let block_number = header.number;
+		if let Err(error) = track_finalized(&history, &rpc_client, &state_client, header).await {
+			warn!(block_number, "Failed to track block fees: {error:#}");
+		}
+	}
+}
+
+/// Submission attempt of the extrinsic
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Attempt {
+	/// Number of replaced submissions
+	pub replacements: u32,
+	/// Time since the first submission
+	pub elapsed: Duration,
+}
+
+pub trait TipStrategy: Send + Sync {
+	/// Returns tip of the submission attempt
+	fn tip(&self, recent: &[BlockFees], attempt: Attempt) -> u128;
+}
+
+pub struct Fixed(pub u128);
+
+impl TipStrategy for Fixed {
+	fn tip(&self, _: &[BlockFees], _: Attempt) -> u128 {
+		self.0
+	}
+}
+
+pub struct Percentile {
+	/// Percentile of the recent tips, from 0 to 100
+	pub percentile: u8,
+	/// Average fullness of the recent blocks, over which the blocks are congested
+	pub congestion: f64,
+	pub min: u128,
+	pub max: u128,
+}
+
+impl Percentile {
+	pub fn new(percentile: u8, congestion: f64, min: u128, max: u128) -> Result<Self> {
+		if percentile > 100 {
+			return Err(eyre!("Tip percentile {percentile} exceeds 100"));
+		}
+		if min > max {
+			return Err(eyre!("Minimum tip {min} exceeds maximum tip {max}"));
+		}
+		Ok(Percentile {
+			percentile,
+			congestion,
+			min,
+			max,
+		})
+	}
+}
+
+impl TipStrategy for Percentile {
+	fn tip(&self, recent: &[BlockFees], _: Attempt) -> u128 {
+		if recent.is_empty() {
+			return self.min;
+		}
+		let fullness = recent.iter().map(|block| block.fullness).sum::<f64>() / recent.len() as f64;
+		let mut tips = recent
+			.iter()
+			.flat_map(|block| block.tips.iter().copied())
+			.collect::<Vec<_>>();
+		if fullness < self.congestion || tips.is_empty() {
+			return self.min;
+		}
+		tips.sort_unstable();
+		let index = (tips.len() - 1) * self.percentile.min(100) as usize / 100;
+		// Unlike `clamp`, doesn't panic if the minimum exceeds the maximum
+		tips[index].max(self.min).min(self.max)
+	}
+}
+
+pub struct Deadline<S: TipStrategy> {
+	pub base: S,
+	/// Time until the extrinsic should be finalized, when the maximum tip is paid
+	pub deadline: Duration,
+	pub max: u128,
+}
+
+impl<S: TipStrategy> TipStrategy for Deadline<S> {
+	fn tip(&self, recent: &[BlockFees], attempt: Attempt) -> u128 {
+		let base = self.base.tip(recent, attempt).min(self.max);
+		if self.deadline.is_zero() || attempt.elapsed >= self.deadline {
+			return self.max;
+		}
+		let progress = attempt.elapsed.as_secs_f64() / self.deadline.as_secs_f64();
+		base + ((self.max - base) as f64 * progress) as u128
+	}
+}
+
+/// Returns tip of the replacement extrinsic, bumped over the replaced tip if the strategy tip is not higher
+pub fn replacement_tip(replaced: u128, tip: u128) -> u128 {
+	let bump = (replaced.saturating_mul(REPLACEMENT_BUMP_PERCENT) / 100).max(1);
+	tip.max(replaced.saturating_add(bump))
+}
+
+/// Tips of the data submissions, with the replacement of the stalled extrinsics
+pub struct TipConfig {
+	pub strategy: Box<dyn TipStrategy>,
+	/// Fees of the recent blocks, fed by [`run`]
+	pub history: Arc<FeeHistory>,
+	/// Time after which the extrinsic which is not finalized is replaced
+	pub stall_timeout: Duration,
+	pub max_replacements: u32,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use subxt::ext::scale_value::Value;
+	use test_case::test_case;
+
+	fn blocks(fullness: f64) -> Vec<BlockFees> {
+		vec![
+			BlockFees {
+				number: 1,
+				fullness,
+				tips: vec![10, 50, 30],
+			},
+			BlockFees {
+				number: 2,
+				fullness,
+				tips: vec![0, 20],
+			},
+		]
+	}
+
+	fn attempt(elapsed: u64) -> Attempt {
+		Attempt {
+			replacements: 0,
+			elapsed: Duration::from_secs(elapsed),
+		}
+	}
+
+	const PERCENTILE: Percentile = Percentile {
+		percentile: 50,
+		congestion: 0.5,
+		min: 5,
+		max: 40,
+	};
+
+	#[test_case(0.9, 50 => 20 ; "median")]
+	#[test_case(0.9, 100 => 40 ; "capped at maximum")]
+	#[test_case(0.2, 50 => 5 ; "not congested")]
+	fn percentile_tip(fullness: f64, percentile: u8) -> u128 {
+		let strategy = Percentile {
+			percentile,
+			..PERCENTILE
+		};
+		strategy.tip(&blocks(fullness), attempt(0))
+	}
+
+	#[test_case(0 => 20 ; "first attempt")]
+	#[test_case(30 => 60 ; "half way")]
+	#[test_case(90 => 100 ; "deadline passed")]
+	fn deadline_tip(elapsed: u64) -> u128 {
+		let strategy = Deadline {
+			base: Fixed(20),
+			deadline: Duration::from_secs(60),
+			max: 100,
+		};
+		strategy.tip(&[], attempt(elapsed))
+	}
+
+	#[test]
+	fn percentile_config() {
+		assert!(Percentile::new(50, 0.5, 5, 40).is_ok());
+		assert!(Percentile::new(101, 0.5, 5, 40).is_err());
+		assert!(Percentile::new(50, 0.5, 40, 5).is_err());
+		// Invalid strategy doesn't panic
+		let strategy = Percentile {
+			min: 50,
+			max: 40,
+			..PERCENTILE
+		};
+		assert_eq!(strategy.tip(&blocks(0.9), attempt(0)), 40);
+	}
+
+	#[test_case(0, 0 => 1 ; "zero tip")]
+	#[test_case(100, 0 => 110 ; "bumped")]
+	#[test_case(100, 200 => 200 ; "higher strategy tip")]
+	#[test_case(u128::MAX, 0 => u128::MAX ; "saturated")]
+	fn replacement(replaced: u128, tip: u128) -> u128 {
+		replacement_tip(replaced, tip)
+	}
+
+	#[test]
+	fn fee_history() {
+		let history = FeeHistory::new(2);
+		for block in blocks(0.5).into_iter().chain(blocks(0.7)) {
+			history.push(block);
+		}
+		let numbers = history
+			.blocks()
+			.iter()
+			.map(|block| block.number)
+			.collect::<Vec<_>>();
+		assert_eq!(numbers, [1, 2]);
+		assert_eq!(history.blocks()[0].fullness, 0.7);
+		assert_eq!(PERCENTILE.tip(&[], attempt(0)), 5);
+
+		let fields = Composite::Named(vec![
+			("who".to_string(), Value::u128(1)),
+			("actual_fee".to_string(), Value::u128(100)),
+			("tip".to_string(), Value::u128(7)),
+		]);
+		assert_eq!(tip_from_fields(&fields), Some(7));
+	}
+}
@@ -0,0 +1,232 @@
+//! Decoding of the raw trie nodes, in the Substrate trie node format.
+//!
+//! Nodes are decoded without the trie database, so they can be examined one by one, e.g. from the storage proof.
+//! Proof debugging tools are in the [`inspect`] module, proof generation from the full state is in the
+//! [`recorder`] module, and diff of two states is in the [`diff`] module. Partial trie used for the proof
+//! verification is in the [`arena`] module. With the `parallel` feature, ordered trie roots of many entries can be
+//! computed in parallel with the `ordered_root` module.
+//!
+//! # Node format
+//!
+//! * Header, with node type and number of the partial key nibbles
+//! * Partial key, padded to full bytes
+//! * Branch children bitmap (branches only)
+//! * Value, inline or hash of the value node (since state version 1)
+//! * Branch children, as hash of the child node, or inline encoded child if it is shorter than a hash
+
+use avail_subxt::utils::H256;
+use codec::{Compact, Decode};
+use color_eyre::{eyre::eyre, Result};
+use sp_core::blake2_256;
+use std::{collections::HashMap, fmt};
+
+pub mod arena;
+pub mod diff;
+pub mod inspect;
+#[cfg(feature = "parallel")]
+pub mod ordered_root;
+pub mod recorder;
+
+const HASH_LENGTH: usize = 32;
+
+/// Sequence of 4-bit key parts, used as path in the trie
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Nibbles(pub Vec<u8>);
+
+impl Nibbles {
+	pub fn from_key(key: &[u8]) -> Self {
+		Nibbles(
+			key.iter()
+				.flat_map(|byte| [byte >> 4, byte & 0x0f])
+				.collect(),
+		)
+	}
+}
+
+impl fmt::Display for Nibbles {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for nibble in &self.0 {
+			write!(f, "{nibble:x}")?;
+		}
+		Ok(())
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+	Inline(Vec<u8>),
+	/// Hash of the value node, for values longer than a hash (state version 1)
+	Hashed(H256),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Child {
+	Hash(H256),
+	/// Encoded child node, shorter than a hash
+	Inline(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Node {
+	Empty,
+	Leaf {
+		partial: Nibbles,
+		value: Value,
+	},
+	Branch {
+		partial: Nibbles,
+		value: Option<Value>,
+		children: Box<[Option<Child>; 16]>,
+	},
+}
+
+fn take<'a>(input: &mut &'a [u8], length: usize) -> Result<&'a [u8]> {
+	if input.len() < length {
+		return Err(eyre!("Unexpected end of node, expected {length} bytes"));
+	}
+	let (taken, rest) = input.split_at(length);
+	*input = rest;
+	Ok(taken)
+}
+
+fn decode_length(input: &mut &[u8]) -> Result<usize> {
+	let Compact(length) = Compact::<u32>::decode(input).map_err(|error| eyre!("{error}"))?;
+	Ok(length as usize)
+}
+
+/// Decodes number of partial key nibbles, stored in the header bits not used by the node type prefix
+fn decode_size(first: u8, input: &mut &[u8], prefix_bits: u8) -> Result<usize> {
+	let max_value = 255u8 >> prefix_bits;
+	let mut size = (first & max_value) as usize;
+	if size < max_value as usize {
+		return Ok(size);
+	}
+	loop {
+		let byte = take(input, 1)?[0] as usize;
+		size += byte;
+		if byte < 255 {
+			return Ok(size);
+		}
+	}
+}
+
+fn decode_partial(input: &mut &[u8], nibble_count: usize) -> Result<Nibbles> {
+	let bytes = take(input, (nibble_count + 1) / 2)?;
+	let mut nibbles = Nibbles::from_key(bytes).0;
+	if nibble_count % 2 == 1 {
+		if nibbles[0] != 0 {
+			return Err(eyre!("Invalid partial key padding"));
+		}
+		nibbles.remove(0);
+	}
+	Ok(Nibbles(nibbles))
+}
+
+fn decode_value(input: &mut &[u8], hashed: bool) -> Result<Value> {
+	if hashed {
+		return Ok(Value::Hashed(H256::from_slice(take(input, HASH_LENGTH)?)));
+	}
+	let length = decode_length(input)?;
+	Ok(Value::Inline(take(input, length)?.to_vec()))
+}
+
+/// Indexes proof nodes by their hash
+pub fn index_nodes(proof: &[Vec<u8>]) -> HashMap<H256, Vec<u8>> {
+	proof
+		.iter()
+		.map(|node| (H256::from(blake2_256(node)), node.clone()))
+		.collect()
+}
+
+/// Decodes encoded trie node
+pub fn decode_node(data: &[u8]) -> Result<Node> {
+	let input = &mut &data[..];
+	let first = take(input, 1)?[0];
+	// (is branch, has value, is value hashed, number of prefix bits)
+	let (is_branch, has_value, hashed, prefix_bits) = match first {
+		0 => return Ok(Node::Empty),
+		_ if first >> 6 == 0b01 => (false, true, false, 2),
+		_ if first >> 6 == 0b10 => (true, false, false, 2),
+		_ if first >> 6 == 0b11 => (true, true, false, 2),
+		_ if first >> 5 == 0b001 => (false, true, true, 3),
+		_ if first >> 4 == 0b0001 => (true, true, true, 4),
+		_ => return Err(eyre!("Invalid node header {first:#010b}")),
+	};
+	let nibble_count = decode_size(first, input, prefix_bits)?;
+	let partial = decode_partial(input, nibble_count)?;
+
+	let node = if is_branch {
+		let bitmap = u16::from_le_bytes(take(input, 2)?.try_into().expect("2 bytes"));
+		let value = has_value.then(|| decode_value(input, hashed)).transpose()?;
+		let mut children: Box<[Option<Child>; 16]> = Box::default();
+		for (index, child) in children.iter_mut().enumerate() {
+			if bitmap & (1 << index) == 0 {
+				continue;
+			}
+			let length = decode_length(input)?;
+			let data = take(input, length)?;
+			*child = Some(if length == HASH_LENGTH {
+				Child::Hash(H256::from_slice(data))
+			} else {
+				Child::Inline(data.to_vec())
+			});
+		}
+		Node::Branch {
+			partial,
+			value,
+			children,
+		}
+	} else {
+		Node::Leaf {
+			partial,
+			value: decode_value(input, hashed)?,
+		}
+	};
+
+	if !input.is_empty() {
+		return Err(eyre!("Node has {} trailing bytes", input.len()));
+	}
+	Ok(node)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::storage_proof::build_trie;
+
+	#[test]
+	fn decode_leaf() {
+		// Leaf with 3 nibbles (0x0abc) and inline value [1, 2]
+		let node = decode_node(&[0b0100_0011, 0x0a, 0xbc, 0x08, 1, 2]).unwrap();
+		assert_eq!(
+			node,
+			Node::Leaf {
+				partial: Nibbles(vec![0xa, 0xb, 0xc]),
+				value: Value::Inline(vec![1, 2]),
+			}
+		);
+		assert!(decode_node(&[0b0100_0011, 0x1a, 0xbc, 0x08, 1, 2]).is_err());
+		assert!(decode_node(&[0b0100_0011, 0x0a, 0xbc, 0x08, 1]).is_err());
+	}
+
+	#[test]
+	fn decode_proof_nodes() {
+		let (root, proof) = build_trie(&[(b"key1", &[1; 40]), (b"key2", b"value2")]);
+		let root_node = proof
+			.iter()
+			.find(|node| H256::from(blake2_256(node)) == root)
+			.unwrap();
+		let Node::Branch {
+			partial, children, ..
+		} = decode_node(root_node).unwrap()
+		else {
+			panic!("Root node is not a branch");
+		};
+		assert_eq!(partial.to_string(), "6b65793");
+		assert_eq!(children.iter().filter(|child| child.is_some()).count(), 2);
+		// Value node is not a trie node
+		for node in proof.iter().filter(|&node| node != &vec![1; 40]) {
+			decode_node(node).unwrap();
+		}
+	}
+}
@@ -0,0 +1,395 @@
+//! Diff of two states, e.g. before and after the runtime upgrade, to verify storage migrations.
+//!
+//! # Flow
+//!
+//! * Walk both tries in key order, from the state roots
+//! * Skip subtrees with the same hash at the same path on both sides, since their entries are equal
+//! * Merge entries of the remaining subtrees into added, removed and changed keys
+//!
+//! # Notes
+//!
+//! Nodes are read from the [`NodeSource`], which is either local state, or proof nodes. Since unchanged subtrees
+//! are skipped, proofs only need to contain the changed paths. Missing node that has to be visited is an error.
+
+use avail_subxt::utils::H256;
+use color_eyre::{eyre::eyre, Result};
+use sp_core::Blake2Hasher;
+use sp_trie::{HashDBT, MemoryDB, EMPTY_PREFIX};
+use std::{cmp::Ordering, collections::HashMap, fmt};
+
+use super::{decode_node, Child, Nibbles, Node, Value};
+
+/// Access to the trie nodes by hash
+pub trait NodeSource {
+	fn node(&self, hash: &H256) -> Option<Vec<u8>>;
+}
+
+/// Proof nodes, indexed with [`super::index_nodes`]
+impl NodeSource for HashMap<H256, Vec<u8>> {
+	fn node(&self, hash: &H256) -> Option<Vec<u8>> {
+		self.get(hash).cloned()
+	}
+}
+
+/// Local state
+impl NodeSource for MemoryDB<Blake2Hasher> {
+	fn node(&self, hash: &H256) -> Option<Vec<u8>> {
+		HashDBT::get(self, hash, EMPTY_PREFIX)
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StateChange {
+	Added {
+		key: Vec<u8>,
+		value: Value,
+	},
+	Removed {
+		key: Vec<u8>,
+		value: Value,
+	},
+	Changed {
+		key: Vec<u8>,
+		old: Value,
+		new: Value,
+	},
+}
+
+impl StateChange {
+	pub fn key(&self) -> &[u8] {
+		match self {
+			StateChange::Added { key, .. }
+			| StateChange::Removed { key, .. }
+			| StateChange::Changed { key, .. } => key,
+		}
+	}
+}
+
+fn format_value(value: &Value) -> String {
+	match value {
+		Value::Inline(value) => format!("0x{}", hex::encode(value)),
+		Value::Hashed(hash) => format!("(value hash {hash:?})"),
+	}
+}
+
+/// Changed keys, in key order
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StateDiff {
+	pub changes: Vec<StateChange>,
+}
+
+/// One line per change, `+` for added, `-` for removed and `~` for changed keys
+impl fmt::Display for StateDiff {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for change in &self.changes {
+			let key = hex::encode(change.key());
+			match change {
+				StateChange::Added { value, .. } => {
+					writeln!(f, "+ 0x{key} = {}", format_value(value))?
+				},
+				StateChange::Removed { value, .. } => {
+					writeln!(f, "- 0x{key} = {}", format_value(value))?
+				},
+				StateChange::Changed { old, new, .. } => writeln!(
+					f,
+					"~ 0x{key}: {} -> {}",
+					format_value(old),
+					format_value(new)
+				)?,
+			}
+		}
+		Ok(())
+	}
+}
+
+enum Item {
+	/// Subtree with all keys starting with the path
+	Node {
+		path: Vec<u8>,
+		child: Child,
+	},
+	Entry {
+		key: Vec<u8>,
+		value: Value,
+	},
+}
+
+impl Item {
+	fn path(&self) -> &[u8] {
+		match self {
+			Item::Node { path, .. } => path,
+			Item::Entry { key, .. } => key,
+		}
+	}
+}
+
+/// Depth-first walk of the trie in key order, with the next item on the top of the stack
+struct Walker<'a> {
+	source: &'a dyn NodeSource,
+	stack: Vec<Item>,
+}
+
+impl<'a> Walker<'a> {
+	fn new(source: &'a dyn NodeSource, root: H256) -> Self {
+		let root = Item::Node {
+			path: vec![],
+			child: Child::Hash(root),
+		};
+		Walker {
+			source,
+			stack: vec![root],
+		}
+	}
+
+	fn peek(&self) -> Option<&Item> {
+		self.stack.last()
+	}
+
+	fn pop_entry(&mut self) -> Result<(Vec<u8>, Value)> {
+		let Some(Item::Entry { key, value }) = self.stack.pop() else {
+			unreachable!("Entry is on the top of the stack");
+		};
+		if key.len() % 2 == 1 {
+			return Err(eyre!("Value at odd nibble path '{}'", Nibbles(key)));
+		}
+		let key = key.chunks(2).map(|pair| (pair[0] << 4) | pair[1]).collect();
+		Ok((key, self.resolve_value(value)))
+	}
+
+	/// Replaces hashed value with the value, if the value node is available
+	fn resolve_value(&self, value: Value) -> Value {
+		match value {
+			Value::Hashed(hash) => self.source.node(&hash).map_or(value, Value::Inline),
+			value => value,
+		}
+	}
+
+	fn expand(&mut self) -> Result<()> {
+		let Some(Item::Node { path, child }) = self.stack.pop() else {
+			unreachable!("Node is on the top of the stack");
+		};
+		let node = match child {
+			Child::Hash(hash) => self.source.node(&hash).ok_or_else(|| {
+				eyre!(
+					"Missing node {hash:?} at nibble path '{}'",
+					Nibbles(path.clone())
+				)
+			})?,
+			Child::Inline(node) => node,
+		};
+		match decode_node(&node)? {
+			Node::Empty => (),
+			Node::Leaf { partial, value } => self.stack.push(Item::Entry {
+				key: [path, partial.0].concat(),
+				value,
+			}),
+			Node::Branch {
+				partial,
+				value,
+				children,
+			} => {
+				let path = [path, partial.0].concat();
+				let children: [Option<Child>; 16] = *children;
+				for (index, child) in children.into_iter().enumerate().rev() {
+					if let Some(child) = child {
+						let mut path = path.clone();
+						path.push(index as u8);
+						self.stack.push(Item::Node { path, child });
+					}
+				}
+				// Value is ordered before children, since its key is a prefix of their keys
+				if let Some(value) = value {
+					self.stack.push(Item::Entry { key: path, value });
+				}
+			},
+		}
+		Ok(())
+	}
+}
+
+/// Computes changes from the old to the new state.
+pub fn diff(
+	old_source: &dyn NodeSource,
+	old_root: H256,
+	new_source: &dyn NodeSource,
+	new_root: H256,
+) -> Result<StateDiff> {
+	let mut old = Walker::new(old_source, old_root);
+	let mut new = Walker::new(new_source, new_root);
+	let mut changes = vec![];
+	loop {
+		match (old.peek(), new.peek()) {
+			(None, None) => break,
+			(
+				Some(Item::Node {
+					path: old_path,
+					child: old_child,
+				}),
+				Some(Item::Node {
+					path: new_path,
+					child: new_child,
+				}),
+			) if old_path == new_path && old_child == new_child => {
+				old.stack.pop();
+				new.stack.pop();
+			},
+			(Some(Item::Entry { key: old_key, .. }), Some(Item::Entry { key: new_key, .. })) => {
+				match old_key.cmp(new_key) {
+					Ordering::Less => {
+						let (key, value) = old.pop_entry()?;
+						changes.push(StateChange::Removed { key, value });
+					},
+					Ordering::Greater => {
+						let (key, value) = new.pop_entry()?;
+						changes.push(StateChange::Added { key, value });
+					},
+					Ordering::Equal => {
+						let (key, old_value) = old.pop_entry()?;
+						let (_, new_value) = new.pop_entry()?;
+						if old_value != new_value {
+							changes.push(StateChange::Changed {
+								key,
+								old: old_value,
+								new: new_value,
+							});
+						}
+					},
+				}
+			},
+			(Some(Item::Node { .. }), None) => old.expand()?,
+			(None, Some(Item::Node { .. })) => new.expand()?,
+			(Some(Item::Entry { .. }), None) => {
+				let (key, value) = old.pop_entry()?;
+				changes.push(StateChange::Removed { key, value });
+			},
+			(None, Some(Item::Entry { .. })) => {
+				let (key, value) = new.pop_entry()?;
+				changes.push(StateChange::Added { key, value });
+			},
+			// Node is expanded, unless the other side entry is ordered before all keys of the subtree
+			(Some(old_item @ Item::Node { .. }), Some(new_item))
+				if old_item.path() <= new_item.path() =>
+			{
+				old.expand()?
+			},
+			(Some(old_item), Some(new_item @ Item::Node { .. }))
+				if new_item.path() <= old_item.path() =>
+			{
+				new.expand()?
+			},
+			(Some(Item::Node { .. }), Some(_)) => {
+				let (key, value) = new.pop_entry()?;
+				changes.push(StateChange::Added { key, value });
+			},
+			(Some(_), Some(_)) => {
+				let (key, value) = old.pop_entry()?;
+				changes.push(StateChange::Removed { key, value });
+			},
+		}
+	}
+	Ok(StateDiff { changes })
+}
+
+/// Computes changes between two states, with nodes from the proofs of the changed keys.
+pub fn diff_proofs(
+	old_root: H256,
+	old_proof: &[Vec<u8>],
+	new_root: H256,
+	new_proof: &[Vec<u8>],
+) -> Result<StateDiff> {
+	diff(
+		&super::index_nodes(old_proof),
+		old_root,
+		&super::index_nodes(new_proof),
+		new_root,
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{storage_proof::build_trie, trie::recorder::ProofRecorder};
+
+	#[test]
+	fn diff_states() {
+		let (old_root, old_proof) = build_trie(&[
+			(b"a", b"1"),
+			(b"key1", b"value1"),
+			(b"key2", &[2; 40]),
+			(b"key3", b"value3"),
+		]);
+		let (new_root, new_proof) = build_trie(&[
+			(b"a", b"1"),
+			(b"key1", b"value1"),
+			(b"key2", &[3; 40]),
+			(b"key4", b"value4"),
+			(b"key", b"prefix"),
+		]);
+		let diff = diff_proofs(old_root, &old_proof, new_root, &new_proof).unwrap();
+		assert_eq!(
+			diff.changes,
+			vec![
+				StateChange::Added {
+					key: b"key".to_vec(),
+					value: Value::Inline(b"prefix".to_vec())
+				},
+				StateChange::Changed {
+					key: b"key2".to_vec(),
+					old: Value::Inline(vec![2; 40]),
+					new: Value::Inline(vec![3; 40])
+				},
+				StateChange::Removed {
+					key: b"key3".to_vec(),
+					value: Value::Inline(b"value3".to_vec())
+				},
+				StateChange::Added {
+					key: b"key4".to_vec(),
+					value: Value::Inline(b"value4".to_vec())
+				},
+			]
+		);
+		assert!(diff
+			.to_string()
+			.starts_with("+ 0x6b6579 = 0x707265666978\n"));
+
+		let same = diff_proofs(old_root, &old_proof, old_root, &[]).unwrap();
+		assert!(same.changes.is_empty());
+	}
+
+	#[test]
+	fn diff_with_changed_paths_only() {
+		let entries = (0..100u8)
+			.map(|i| ([b"key".to_vec(), vec![i]].concat(), vec![i; 40]))
+			.collect::<Vec<_>>();
+		let trie = |changed: Option<u8>| {
+			ProofRecorder::from_entries(entries.iter().map(|(key, value)| {
+				let value: &[u8] = if Some(key[3]) == changed {
+					b"new"
+				} else {
+					value
+				};
+				(key.as_slice(), value)
+			}))
+			.unwrap()
+		};
+		let (old, new) = (trie(None), trie(Some(7)));
+		// Proofs of the changed key contain all nodes of the changed paths
+		old.storage(b"key\x07").unwrap();
+		new.storage(b"key\x07").unwrap();
+
+		let diff = diff_proofs(
+			old.root(),
+			&old.proof_nodes(),
+			new.root(),
+			&new.proof_nodes(),
+		)
+		.unwrap();
+		assert_eq!(diff.changes.len(), 1);
+		assert_eq!(diff.changes[0].key(), b"key\x07");
+
+		// Proof without the changed path is not enough
+		let other = trie(None);
+		other.storage(b"key\x08").unwrap();
+		assert!(diff_proofs(other.root(), &other.proof_nodes(), new.root(), &[]).is_err());
+	}
+}
@@ -0,0 +1,287 @@
+//! Debugging of the storage proofs.
+//!
+//! [`ProofInspector`] indexes proof nodes by hash, and can:
+//! * decode all nodes ([`ProofInspector::nodes`])
+//! * render the partial trie reachable from the state root ([`ProofInspector::render`])
+//! * explain the result of a key lookup ([`ProofInspector::lookup`]), including the hash and nibble path of the
+//!   node missing from the proof
+
+use avail_subxt::utils::H256;
+use color_eyre::Result;
+use std::{collections::HashMap, fmt, fmt::Write};
+
+use super::{decode_node, index_nodes, Child, Nibbles, Node, Value};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Lookup {
+	Found(Vec<u8>),
+	/// Proof shows that the key doesn't exist, trie path ends at the given nibble path
+	Absent {
+		path: Nibbles,
+	},
+	/// Node required for the lookup is not in the proof
+	MissingNode {
+		hash: H256,
+		path: Nibbles,
+	},
+	/// Value node of the hashed value is not in the proof
+	MissingValue {
+		hash: H256,
+		path: Nibbles,
+	},
+	/// Node required for the lookup cannot be decoded
+	InvalidNode {
+		path: Nibbles,
+		error: String,
+	},
+}
+
+impl fmt::Display for Lookup {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Lookup::Found(value) => write!(f, "found value 0x{}", hex::encode(value)),
+			Lookup::Absent { path } => {
+				write!(f, "key is absent, trie ends at nibble path '{path}'")
+			},
+			Lookup::MissingNode { hash, path } => {
+				write!(f, "missing node hash {hash:?} at nibble path '{path}'")
+			},
+			Lookup::MissingValue { hash, path } => {
+				write!(
+					f,
+					"missing value node hash {hash:?} at nibble path '{path}'"
+				)
+			},
+			Lookup::InvalidNode { path, error } => {
+				write!(f, "invalid node at nibble path '{path}': {error}")
+			},
+		}
+	}
+}
+
+pub struct ProofInspector {
+	nodes: HashMap<H256, Vec<u8>>,
+}
+
+impl ProofInspector {
+	pub fn new(proof: &[Vec<u8>]) -> Self {
+		ProofInspector {
+			nodes: index_nodes(proof),
+		}
+	}
+
+	/// Decodes all proof nodes. Value nodes of hashed values are not trie nodes, so they fail to decode.
+	pub fn nodes(&self) -> impl Iterator<Item = (&H256, Result<Node>)> {
+		self.nodes
+			.iter()
+			.map(|(hash, node)| (hash, decode_node(node)))
+	}
+
+	fn resolve(&self, child: &Child) -> Result<Vec<u8>, H256> {
+		match child {
+			Child::Hash(hash) => self.nodes.get(hash).cloned().ok_or(*hash),
+			Child::Inline(node) => Ok(node.clone()),
+		}
+	}
+
+	fn value(&self, value: Value, path: Nibbles) -> Lookup {
+		match value {
+			Value::Inline(value) => Lookup::Found(value),
+			Value::Hashed(hash) => match self.nodes.get(&hash) {
+				Some(value) => Lookup::Found(value.clone()),
+				None => Lookup::MissingValue { hash, path },
+			},
+		}
+	}
+
+	/// Looks up the key in the partial trie, explaining why the lookup failed.
+	pub fn lookup(&self, root: H256, key: &[u8]) -> Lookup {
+		let key = Nibbles::from_key(key).0;
+		let path = |depth: usize| Nibbles(key[..depth].to_vec());
+		let mut child = Child::Hash(root);
+		let mut depth = 0;
+		loop {
+			let node = match self.resolve(&child) {
+				Ok(node) => node,
+				Err(hash) => {
+					return Lookup::MissingNode {
+						hash,
+						path: path(depth),
+					}
+				},
+			};
+			let node = match decode_node(&node) {
+				Ok(node) => node,
+				Err(error) => {
+					return Lookup::InvalidNode {
+						path: path(depth),
+						error: error.to_string(),
+					}
+				},
+			};
+			let (partial, value, children) = match node {
+				Node::Empty => return Lookup::Absent { path: path(depth) },
+				Node::Leaf { partial, value } => (partial, Some(value), None),
+				Node::Branch {
+					partial,
+					value,
+					children,
+				} => (partial, value, Some(children)),
+			};
+			if !key[depth..].starts_with(&partial.0) {
+				return Lookup::Absent { path: path(depth) };
+			}
+			depth += partial.0.len();
+			if depth == key.len() {
+				return match value {
+					Some(value) => self.value(value, path(depth)),
+					None => Lookup::Absent { path: path(depth) },
+				};
+			}
+			match children.and_then(|children| children[key[depth] as usize].clone()) {
+				Some(next) => child = next,
+				None => return Lookup::Absent { path: path(depth) },
+			}
+			depth += 1;
+		}
+	}
+
+	/// Renders partial trie reachable from the root, one node per line, indented by depth.
+	pub fn render(&self, root: H256) -> String {
+		let mut output = String::new();
+		self.render_node(
+			&mut output,
+			&Child::Hash(root),
+			"root",
+			Nibbles::default(),
+			0,
+		);
+		output
+	}
+
+	fn render_value(&self, value: &Option<Value>) -> String {
+		match value {
+			Some(Value::Inline(value)) => format!(" value=inline({} bytes)", value.len()),
+			Some(Value::Hashed(hash)) if self.nodes.contains_key(hash) => {
+				format!(" value=hashed {hash:?}")
+			},
+			Some(Value::Hashed(hash)) => format!(" value=hashed {hash:?} (missing)"),
+			None => String::new(),
+		}
+	}
+
+	fn render_node(
+		&self,
+		output: &mut String,
+		child: &Child,
+		label: &str,
+		path: Nibbles,
+		depth: usize,
+	) {
+		let indent = "  ".repeat(depth);
+		let reference = match child {
+			Child::Hash(hash) => format!("{hash:?}"),
+			Child::Inline(_) => "inline".to_string(),
+		};
+		let Ok(node) = self.resolve(child) else {
+			_ = writeln!(
+				output,
+				"{indent}{label} {reference} missing at path '{path}'"
+			);
+			return;
+		};
+		match decode_node(&node) {
+			Err(error) => {
+				_ = writeln!(output, "{indent}{label} {reference} invalid: {error}");
+			},
+			Ok(Node::Empty) => {
+				_ = writeln!(output, "{indent}{label} {reference} empty");
+			},
+			Ok(Node::Leaf { partial, value }) => {
+				let key = Nibbles([path.0, partial.0.clone()].concat());
+				let value = self.render_value(&Some(value));
+				_ = writeln!(
+					output,
+					"{indent}{label} {reference} leaf partial='{partial}' key=0x{key}{value}"
+				);
+			},
+			Ok(Node::Branch {
+				partial,
+				value,
+				children,
+			}) => {
+				let path = Nibbles([path.0, partial.0.clone()].concat());
+				let value = self.render_value(&value);
+				_ = writeln!(
+					output,
+					"{indent}{label} {reference} branch partial='{partial}' path='{path}'{value}"
+				);
+				for (index, child) in children.iter().enumerate() {
+					let Some(child) = child else {
+						continue;
+					};
+					let mut child_path = path.clone();
+					child_path.0.push(index as u8);
+					let label = format!("[{index:x}]");
+					self.render_node(output, child, &label, child_path, depth + 1);
+				}
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::storage_proof::build_trie;
+
+	#[test]
+	fn lookup() {
+		let (root, proof) = build_trie(&[(b"key1", &[1; 40]), (b"key2", b"value2")]);
+		let inspector = ProofInspector::new(&proof);
+		assert_eq!(inspector.lookup(root, b"key1"), Lookup::Found(vec![1; 40]));
+		assert_eq!(
+			inspector.lookup(root, b"key2"),
+			Lookup::Found(b"value2".to_vec())
+		);
+		assert!(matches!(
+			inspector.lookup(root, b"key3"),
+			Lookup::Absent { .. }
+		));
+		assert!(
+			matches!(inspector.lookup(root, b"other"), Lookup::Absent { path } if path.0.is_empty())
+		);
+
+		// Proof without the value node of the hashed value
+		let partial = proof
+			.iter()
+			.filter(|&node| node != &vec![1; 40])
+			.cloned()
+			.collect::<Vec<_>>();
+		let inspector = ProofInspector::new(&partial);
+		let lookup = inspector.lookup(root, b"key1");
+		assert!(matches!(lookup, Lookup::MissingValue { .. }));
+		assert!(lookup.to_string().ends_with("at nibble path '6b657931'"));
+
+		let lookup = ProofInspector::new(&[]).lookup(root, b"key1");
+		assert_eq!(
+			lookup,
+			Lookup::MissingNode {
+				hash: root,
+				path: Nibbles::default()
+			}
+		);
+	}
+
+	#[test]
+	fn render() {
+		let (root, proof) = build_trie(&[(b"key1", b"value1"), (b"key2", b"value2")]);
+		let rendered = ProofInspector::new(&proof).render(root);
+		let lines = rendered.lines().collect::<Vec<_>>();
+		assert_eq!(lines.len(), 3);
+		assert!(lines[0].starts_with("root"));
+		assert!(lines[0].contains("branch partial='6b65793'"));
+		assert!(lines[1].starts_with("  [1] "));
+		assert!(lines[2].contains("key=0x6b657932"));
+	}
+}
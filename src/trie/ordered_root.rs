@@ -0,0 +1,183 @@
+//! Parallel computation of the ordered trie root (e.g. extrinsics root of big blocks).
+//!
+//! Root is the same as [`sp_trie::TrieConfiguration::ordered_trie_root`] of [`sp_trie::LayoutV0`]: keys are SCALE
+//! compact encoded indices, values are SCALE encoded items, and values are always inlined. Instead of inserting
+//! entries one by one, sorted entries are split by the first differing nibble, so each subtree is a contiguous range
+//! of entries. Subtrees with at least [`PARALLEL_THRESHOLD`] entries are constructed on the rayon thread pool, and
+//! smaller ones (including the leaf encoding and hashing) on the current thread.
+
+use avail_subxt::utils::H256;
+use codec::{Compact, Encode};
+use rayon::prelude::*;
+use sp_core::blake2_256;
+
+use super::HASH_LENGTH;
+
+/// Minimal number of entries, for which subtrees are constructed in parallel
+pub const PARALLEL_THRESHOLD: usize = 256;
+
+const EMPTY_NODE: u8 = 0;
+const LEAF: u8 = 0b01 << 6;
+const BRANCH_WITHOUT_VALUE: u8 = 0b10 << 6;
+const BRANCH_WITH_VALUE: u8 = 0b11 << 6;
+/// Maximum number of partial key nibbles stored in the header bits not used by the node type prefix
+const MAX_HEADER_SIZE: usize = 63;
+
+type Entry = (Vec<u8>, Vec<u8>);
+
+fn nibble(key: &[u8], index: usize) -> u8 {
+	let byte = key[index / 2];
+	if index % 2 == 0 {
+		byte >> 4
+	} else {
+		byte & 0x0f
+	}
+}
+
+fn encode_header(output: &mut Vec<u8>, prefix: u8, nibble_count: usize) {
+	if nibble_count < MAX_HEADER_SIZE {
+		output.push(prefix | nibble_count as u8);
+		return;
+	}
+	output.push(prefix | MAX_HEADER_SIZE as u8);
+	let mut remaining = nibble_count - MAX_HEADER_SIZE;
+	while remaining >= 255 {
+		output.push(255);
+		remaining -= 255;
+	}
+	output.push(remaining as u8);
+}
+
+/// Encodes nibbles of the key in the given range, odd number of nibbles is padded at the start
+fn encode_partial(output: &mut Vec<u8>, key: &[u8], from: usize, to: usize) {
+	let mut index = from;
+	if (to - from) % 2 == 1 {
+		output.push(nibble(key, index));
+		index += 1;
+	}
+	while index < to {
+		output.push(nibble(key, index) << 4 | nibble(key, index + 1));
+		index += 2;
+	}
+}
+
+/// Returns encoded child node if it's shorter than a hash, otherwise its hash
+fn child_reference(node: Vec<u8>) -> Vec<u8> {
+	if node.len() < HASH_LENGTH {
+		return node;
+	}
+	blake2_256(&node).to_vec()
+}
+
+/// Encodes node of the sorted entries, which share the first `depth` nibbles of their keys
+fn encode_node(entries: &[Entry], depth: usize) -> Vec<u8> {
+	let mut output = vec![];
+	if let [(key, value)] = entries {
+		encode_header(&mut output, LEAF, key.len() * 2 - depth);
+		encode_partial(&mut output, key, depth, key.len() * 2);
+		value.encode_to(&mut output);
+		return output;
+	}
+
+	// Entries are sorted, so the common prefix of the first and last key is shared by all keys
+	let (first, last) = (&entries[0].0, &entries[entries.len() - 1].0);
+	let mut split = depth;
+	while split < first.len() * 2
+		&& split < last.len() * 2
+		&& nibble(first, split) == nibble(last, split)
+	{
+		split += 1;
+	}
+	// Key which ends at the branch sorts first, and its value is stored in the branch
+	let (value, children) = match entries.split_first() {
+		Some(((key, value), rest)) if key.len() * 2 == split => (Some(value), rest),
+		_ => (None, entries),
+	};
+
+	let mut groups = vec![];
+	let mut rest = children;
+	while let Some((key, _)) = rest.first() {
+		let index = nibble(key, split);
+		let end = rest
+			.iter()
+			.position(|(key, _)| nibble(key, split) != index)
+			.unwrap_or(rest.len());
+		let (group, tail) = rest.split_at(end);
+		groups.push((index, group));
+		rest = tail;
+	}
+	let encode_child =
+		|&(index, group): &(u8, &[Entry])| (index, child_reference(encode_node(group, split + 1)));
+	let references: Vec<(u8, Vec<u8>)> = if children.len() >= PARALLEL_THRESHOLD {
+		groups.par_iter().map(encode_child).collect()
+	} else {
+		groups.iter().map(encode_child).collect()
+	};
+
+	let prefix = if value.is_some() {
+		BRANCH_WITH_VALUE
+	} else {
+		BRANCH_WITHOUT_VALUE
+	};
+	encode_header(&mut output, prefix, split - depth);
+	encode_partial(&mut output, first, depth, split);
+	let bitmap = references
+		.iter()
+		.fold(0u16, |bitmap, &(index, _)| bitmap | 1 << index);
+	output.extend_from_slice(&bitmap.to_le_bytes());
+	if let Some(value) = value {
+		value.encode_to(&mut output);
+	}
+	for (_, reference) in references {
+		reference.encode_to(&mut output);
+	}
+	output
+}
+
+/// Calculates ordered trie root of the SCALE encoded items, encoding and sorting them in parallel
+pub fn ordered_trie_root<T: Encode + Sync>(items: &[T]) -> H256 {
+	let mut entries = items
+		.par_iter()
+		.enumerate()
+		.map(|(index, item)| (Compact(index as u32).encode(), item.encode()))
+		.collect::<Vec<Entry>>();
+	if entries.is_empty() {
+		return blake2_256(&[EMPTY_NODE]).into();
+	}
+	entries.par_sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+	blake2_256(&encode_node(&entries, 0)).into()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::Blake2Hasher;
+	use sp_trie::{LayoutV0, TrieConfiguration};
+	use test_case::test_case;
+
+	fn expected(items: &[Vec<u8>]) -> H256 {
+		LayoutV0::<Blake2Hasher>::ordered_trie_root(items.iter().map(Encode::encode))
+	}
+
+	#[test_case(0 ; "empty")]
+	#[test_case(1 ; "single leaf")]
+	#[test_case(2 ; "small branch")]
+	#[test_case(17 ; "inline children")]
+	#[test_case(300 ; "parallel subtrees")]
+	#[test_case(20_000 ; "four byte keys")]
+	fn same_root_as_sp_trie(count: usize) {
+		let items = (0..count)
+			.map(|index| vec![index as u8; index % 70])
+			.collect::<Vec<_>>();
+		assert_eq!(ordered_trie_root(&items), expected(&items));
+	}
+
+	#[test]
+	fn long_partial_keys() {
+		let mut output = vec![];
+		encode_header(&mut output, LEAF, 63 + 255 + 3);
+		assert_eq!(output, [LEAF | 63, 255, 3]);
+		let decoded = crate::trie::decode_size(output[0], &mut &output[1..], 2).unwrap();
+		assert_eq!(decoded, 63 + 255 + 3);
+	}
+}
@@ -0,0 +1,158 @@
+//! Generation of storage proofs from the full state.
+//!
+//! [`ProofRecorder`] wraps the local trie database, and records all trie nodes touched by the state queries.
+//! Recorded nodes form the [`StorageProof`] of all queried values, which can be verified with
+//! [`crate::storage_proof::verify_read_proof`] (or [`crate::storage_proof::verify_storage_page`] for key pages),
+//! so proofs can be served, not only verified (e.g. in tests, or in the server mode).
+
+use avail_subxt::utils::H256;
+use color_eyre::{eyre::eyre, Result};
+use sp_core::Blake2Hasher;
+use sp_trie::{
+	recorder::Recorder, LayoutV1, MemoryDB, StorageProof, TrieDBBuilder, TrieDBKeyIterator,
+	TrieDBMutBuilder, TrieMut,
+};
+
+type Layout = LayoutV1<Blake2Hasher>;
+
+pub struct ProofRecorder {
+	db: MemoryDB<Blake2Hasher>,
+	root: H256,
+	recorder: Recorder<Blake2Hasher>,
+}
+
+impl ProofRecorder {
+	pub fn new(db: MemoryDB<Blake2Hasher>, root: H256) -> Self {
+		ProofRecorder {
+			db,
+			root,
+			recorder: Recorder::default(),
+		}
+	}
+
+	/// Builds the state trie from the given entries.
+	pub fn from_entries<'a>(
+		entries: impl IntoIterator<Item = (&'a [u8], &'a [u8])>,
+	) -> Result<Self> {
+		let mut db = MemoryDB::<Blake2Hasher>::default();
+		let mut root = H256::default();
+		{
+			let mut trie = TrieDBMutBuilder::<Layout>::new(&mut db, &mut root).build();
+			for (key, value) in entries {
+				trie.insert(key, value)
+					.map_err(|error| eyre!("Cannot insert trie entry: {error}"))?;
+			}
+		}
+		Ok(Self::new(db, root))
+	}
+
+	pub fn root(&self) -> H256 {
+		self.root
+	}
+
+	/// Returns value of the key, recording nodes on the lookup path.
+	pub fn storage(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+		let mut recorder = self.recorder.as_trie_recorder(self.root);
+		sp_trie::read_trie_value::<Layout, _>(&self.db, &self.root, key, Some(&mut recorder), None)
+			.map_err(|error| eyre!("Cannot read key 0x{}: {error}", hex::encode(key)))
+	}
+
+	/// Returns up to `count` keys with the given prefix after the start key, recording nodes which prove the page
+	/// is complete. Values of the returned keys are recorded too.
+	pub fn keys_paged(
+		&self,
+		prefix: &[u8],
+		start_key: Option<&[u8]>,
+		count: usize,
+	) -> Result<Vec<Vec<u8>>> {
+		let keys = {
+			let mut recorder = self.recorder.as_trie_recorder(self.root);
+			let trie = TrieDBBuilder::<Layout>::new(&self.db, &self.root)
+				.with_recorder(&mut recorder)
+				.build();
+			TrieDBKeyIterator::new_prefixed_then_seek(&trie, prefix, start_key.unwrap_or(prefix))
+				.map_err(|error| eyre!("Cannot iterate keys: {error}"))?
+				.filter(|key| {
+					key.as_ref()
+						.map_or(true, |key| Some(key.as_slice()) != start_key)
+				})
+				.take(count)
+				.collect::<Result<Vec<_>, _>>()
+				.map_err(|error| eyre!("Cannot iterate keys: {error}"))?
+		};
+		// Start key lookup proves there are no keys between the start key and the first page key
+		self.storage(start_key.unwrap_or(prefix))?;
+		for key in &keys {
+			self.storage(key)?;
+		}
+		Ok(keys)
+	}
+
+	/// Returns proof of all queries since the recorder is created.
+	pub fn proof(&self) -> StorageProof {
+		self.recorder.to_storage_proof()
+	}
+
+	/// Returns the proof nodes, in the read proof response format
+	pub fn proof_nodes(&self) -> Vec<Vec<u8>> {
+		self.proof().into_iter_nodes().collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		storage_proof::{verify_read_proof, verify_storage_page},
+		trie::inspect::{Lookup, ProofInspector},
+	};
+
+	fn recorder() -> ProofRecorder {
+		let values = (0..100u8).map(|i| vec![i; 40]).collect::<Vec<_>>();
+		let keys = (0..100u8)
+			.map(|i| [b"key".to_vec(), vec![i]].concat())
+			.collect::<Vec<_>>();
+		ProofRecorder::from_entries(
+			keys.iter()
+				.zip(&values)
+				.map(|(key, value)| (key.as_slice(), value.as_slice())),
+		)
+		.unwrap()
+	}
+
+	#[test]
+	fn record_read_proof() {
+		let recorder = recorder();
+		let key = b"key\x07".to_vec();
+		assert_eq!(recorder.storage(&key).unwrap(), Some(vec![7; 40]));
+		assert_eq!(recorder.storage(b"missing").unwrap(), None);
+
+		let proof = recorder.proof_nodes();
+		let values = verify_read_proof(recorder.root(), proof.clone(), &[key]).unwrap();
+		assert_eq!(values[0].1, Some(vec![7; 40]));
+
+		// Only touched nodes are recorded
+		let lookup = ProofInspector::new(&proof).lookup(recorder.root(), b"key\x08");
+		assert!(matches!(lookup, Lookup::MissingNode { .. }));
+	}
+
+	#[test]
+	fn record_keys_page() {
+		let recorder = recorder();
+		let start_key = b"key\x10".to_vec();
+		let keys = recorder.keys_paged(b"key", Some(&start_key), 5).unwrap();
+		assert_eq!(keys.first(), Some(&b"key\x11".to_vec()));
+		assert_eq!(keys.len(), 5);
+
+		let page = verify_storage_page(
+			recorder.root(),
+			recorder.proof_nodes(),
+			b"key",
+			Some(&start_key),
+			&keys,
+			5,
+		)
+		.unwrap();
+		assert_eq!(page[4].1, vec![0x15; 40]);
+	}
+}
@@ -0,0 +1,273 @@
+//! Loading of the KZG trusted setup.
+//!
+//! Setup is loaded lazily, on the first [`TrustedSetup::public_params`] call, from one of the sources:
+//!
+//! * Ceremony file set with `trusted_setup_path`, verified against `trusted_setup_checksum` (SHA-256) if set
+//! * Setup embedded in the binary with `embedded-setup` feature, from the file set with `AVAIL_TRUSTED_SETUP`
+//!   environment variable at build time
+//! * Default (couscous) setup, shipped with `kate-recovery`
+//!
+//! # Formats
+//!
+//! * [`SetupFormat::Json`] - ceremony output with hex encoded `g1_monomial` and `g2_monomial` powers
+//! * [`SetupFormat::Text`] - number of G1 and G2 powers on the first two lines, followed by hex encoded powers
+//! * [`SetupFormat::Raw`] - serialized public parameters, as used by the default setup
+//!
+//! All formats use compressed point encoding. Only first two G2 powers are used for verification.
+
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use dusk_plonk::commitment_scheme::kzg10::PublicParameters;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+	fs,
+	path::Path,
+	sync::{Arc, OnceLock},
+};
+use tracing::info;
+
+use crate::types::RuntimeConfig;
+
+const G1_SIZE: usize = 48;
+const G2_SIZE: usize = 96;
+
+#[cfg(feature = "embedded-setup")]
+const EMBEDDED_SETUP: &[u8] = include_bytes!(env!("AVAIL_TRUSTED_SETUP"));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetupFormat {
+	Json,
+	Text,
+	Raw,
+}
+
+impl SetupFormat {
+	/// Detects format from the file extension, or the content if extension is unknown
+	pub fn detect(path: Option<&Path>, bytes: &[u8]) -> Self {
+		match path.and_then(|path| path.extension()?.to_str()) {
+			Some("json") => return SetupFormat::Json,
+			Some("txt") => return SetupFormat::Text,
+			_ => (),
+		}
+		match bytes.iter().find(|byte| !byte.is_ascii_whitespace()) {
+			Some(b'{') => SetupFormat::Json,
+			Some(byte) if byte.is_ascii_digit() && bytes.is_ascii() => SetupFormat::Text,
+			_ => SetupFormat::Raw,
+		}
+	}
+}
+
+#[derive(Deserialize)]
+struct JsonSetup {
+	g1_monomial: Vec<String>,
+	g2_monomial: Vec<String>,
+}
+
+fn decode_point(hex_point: &str, size: usize) -> Result<Vec<u8>> {
+	let point = hex::decode(hex_point.trim().trim_start_matches("0x"))
+		.wrap_err("Invalid hex encoded point")?;
+	if point.len() != size {
+		return Err(eyre!("Invalid point size {}, expected {size}", point.len()));
+	}
+	Ok(point)
+}
+
+/// Serializes powers in the format of public parameters: G1 and G2 generators, G2 generator multiplied by the secret,
+/// followed by the G1 powers
+fn serialize_powers(g1: &[String], g2: &[String]) -> Result<Vec<u8>> {
+	if g1.is_empty() || g2.len() < 2 {
+		return Err(eyre!(
+			"Setup requires at least one G1 and two G2 powers, found {} and {}",
+			g1.len(),
+			g2.len()
+		));
+	}
+	let mut bytes = Vec::with_capacity(G1_SIZE * (g1.len() + 1) + G2_SIZE * 2);
+	bytes.extend(decode_point(&g1[0], G1_SIZE)?);
+	bytes.extend(decode_point(&g2[0], G2_SIZE)?);
+	bytes.extend(decode_point(&g2[1], G2_SIZE)?);
+	for point in g1 {
+		bytes.extend(decode_point(point, G1_SIZE)?);
+	}
+	Ok(bytes)
+}
+
+fn parse_json(bytes: &[u8]) -> Result<Vec<u8>> {
+	let setup: JsonSetup = serde_json::from_slice(bytes).wrap_err("Invalid JSON trusted setup")?;
+	serialize_powers(&setup.g1_monomial, &setup.g2_monomial)
+}
+
+fn parse_text(bytes: &[u8]) -> Result<Vec<u8>> {
+	let text = std::str::from_utf8(bytes).wrap_err("Invalid text trusted setup")?;
+	let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+	let mut count = || -> Result<usize> {
+		lines
+			.next()
+			.ok_or_else(|| eyre!("Missing number of powers"))?
+			.parse()
+			.wrap_err("Invalid number of powers")
+	};
+	let (g1_count, g2_count) = (count()?, count()?);
+	let points = lines.map(str::to_string).collect::<Vec<_>>();
+	if points.len() != g1_count + g2_count {
+		return Err(eyre!(
+			"Expected {} powers, found {}",
+			g1_count + g2_count,
+			points.len()
+		));
+	}
+	let (g1, g2) = points.split_at(g1_count);
+	serialize_powers(g1, g2)
+}
+
+/// Parses public parameters from the setup in the given format
+pub fn parse(format: SetupFormat, bytes: &[u8]) -> Result<PublicParameters> {
+	let raw = match format {
+		SetupFormat::Json => parse_json(bytes)?,
+		SetupFormat::Text => parse_text(bytes)?,
+		SetupFormat::Raw => bytes.to_vec(),
+	};
+	PublicParameters::from_slice(&raw).map_err(|error| eyre!("Invalid trusted setup: {error:?}"))
+}
+
+/// Verifies SHA-256 checksum (hex encoded) of the setup file
+pub fn verify_checksum(bytes: &[u8], checksum: &str) -> Result<()> {
+	let actual = hex::encode(Sha256::digest(bytes));
+	if !actual.eq_ignore_ascii_case(checksum.trim_start_matches("0x")) {
+		return Err(eyre!(
+			"Trusted setup checksum mismatch, expected {checksum}, found {actual}"
+		));
+	}
+	Ok(())
+}
+
+/// Source of the trusted setup, with lazy loaded public parameters
+#[derive(Debug, Default)]
+pub struct TrustedSetup {
+	path: Option<String>,
+	checksum: Option<String>,
+	public_params: OnceLock<Arc<PublicParameters>>,
+}
+
+impl From<&RuntimeConfig> for TrustedSetup {
+	fn from(cfg: &RuntimeConfig) -> Self {
+		TrustedSetup::new(
+			cfg.trusted_setup_path.clone(),
+			cfg.trusted_setup_checksum.clone(),
+		)
+	}
+}
+
+impl TrustedSetup {
+	pub fn new(path: Option<String>, checksum: Option<String>) -> Self {
+		TrustedSetup {
+			path,
+			checksum,
+			public_params: OnceLock::new(),
+		}
+	}
+
+	fn load_file(path: &str, checksum: Option<&str>) -> Result<PublicParameters> {
+		let bytes = fs::read(path).wrap_err_with(|| format!("Cannot read trusted setup {path}"))?;
+		if let Some(checksum) = checksum {
+			verify_checksum(&bytes, checksum)?;
+		}
+		let format = SetupFormat::detect(Some(Path::new(path)), &bytes);
+		info!(path, ?format, "Loading trusted setup");
+		parse(format, &bytes)
+	}
+
+	fn load(&self) -> Result<PublicParameters> {
+		if let Some(path) = &self.path {
+			return Self::load_file(path, self.checksum.as_deref());
+		}
+
+		#[cfg(feature = "embedded-setup")]
+		{
+			info!("Loading embedded trusted setup");
+			if let Some(checksum) = &self.checksum {
+				verify_checksum(EMBEDDED_SETUP, checksum)?;
+			}
+			return parse(SetupFormat::detect(None, EMBEDDED_SETUP), EMBEDDED_SETUP);
+		}
+
+		#[allow(unreachable_code)]
+		{
+			info!("Loading default trusted setup");
+			Ok(kate_recovery::couscous::public_params())
+		}
+	}
+
+	/// Public parameters of the setup, loaded on the first call
+	pub fn public_params(&self) -> Result<Arc<PublicParameters>> {
+		if let Some(public_params) = self.public_params.get() {
+			return Ok(public_params.clone());
+		}
+		let public_params = Arc::new(self.load()?);
+		Ok(self.public_params.get_or_init(|| public_params).clone())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use test_case::test_case;
+
+	#[test_case(Some("setup.json"), b"" => SetupFormat::Json ; "json extension")]
+	#[test_case(Some("setup.txt"), b"" => SetupFormat::Text ; "text extension")]
+	#[test_case(None, b"  {\"g1_monomial\": []}" => SetupFormat::Json ; "json content")]
+	#[test_case(None, b"4096\n65\n" => SetupFormat::Text ; "text content")]
+	#[test_case(Some("pp_1024.data"), &[0x97, 0xf1, 0xd3] => SetupFormat::Raw ; "raw content")]
+	fn detect_format(path: Option<&str>, bytes: &[u8]) -> SetupFormat {
+		SetupFormat::detect(path.map(Path::new), bytes)
+	}
+
+	#[test]
+	fn checksum() {
+		let checksum = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+		assert!(verify_checksum(b"hello", checksum).is_ok());
+		assert!(verify_checksum(b"hello", &checksum.to_uppercase()).is_ok());
+		assert!(verify_checksum(b"hello!", checksum).is_err());
+	}
+
+	#[test_case("2\n2\n" ; "missing powers")]
+	#[test_case("1\n1\nabcd\nabcd\n" ; "single g2 power")]
+	#[test_case("1\n2\nzz\nzz\nzz\n" ; "invalid hex")]
+	fn invalid_text_setup(setup: &str) {
+		assert!(parse(SetupFormat::Text, setup.as_bytes()).is_err());
+	}
+
+	#[test]
+	fn text_setup_roundtrip() {
+		let public_params = kate_recovery::couscous::public_params();
+		// Serialized as G1 generator, two G2 powers and G1 powers
+		let bytes = public_params.to_var_bytes();
+		let (key, powers) = bytes.split_at(G1_SIZE + 2 * G2_SIZE);
+		let g1 = powers.chunks(G1_SIZE).map(hex::encode).collect::<Vec<_>>();
+		let g2 = key[G1_SIZE..]
+			.chunks(G2_SIZE)
+			.map(hex::encode)
+			.collect::<Vec<_>>();
+		let text = format!(
+			"{}\n{}\n{}\n{}\n",
+			g1.len(),
+			g2.len(),
+			g1.join("\n"),
+			g2.join("\n")
+		);
+
+		let parsed = parse(SetupFormat::Text, text.as_bytes()).unwrap();
+		assert_eq!(parsed.to_var_bytes(), bytes);
+	}
+
+	#[test]
+	fn lazy_default_setup() {
+		let setup = TrustedSetup::default();
+		let first = setup.public_params().unwrap();
+		let second = setup.public_params().unwrap();
+		assert!(Arc::ptr_eq(&first, &second));
+	}
+}
@@ -1,7 +1,10 @@
 //! Shared light client structs and enums.
 
+use crate::compression::CompressionConfig;
+use crate::header::cached::CachedHeader;
 use crate::network::p2p::MemoryStoreConfig;
 use crate::network::rpc::{Event, Node as RpcNode};
+use crate::sampling::SamplingPolicy;
 use crate::utils::{extract_app_lookup, extract_kate};
 use avail_core::DataLookup;
 use avail_subxt::{primitives::Header as DaHeader, utils::H256};
@@ -17,12 +20,14 @@ use kate_recovery::{
 	matrix::{Dimensions, Partition},
 };
 use libp2p::kad::Mode as KadMode;
-use libp2p::{Multiaddr, PeerId};
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
 use serde::{de::Error, Deserialize, Serialize};
 use sp_core::crypto::Ss58Codec;
-use sp_core::{blake2_256, bytes, ed25519};
+use sp_core::{bytes, ed25519};
+use std::borrow::Cow;
 use std::fmt::{self, Display, Formatter};
 use std::fs;
+use std::net::Ipv4Addr;
 use std::num::{NonZeroU8, NonZeroUsize};
 use std::ops::Range;
 use std::str::FromStr;
@@ -73,6 +78,12 @@ pub struct CliOpts {
 	/// Avail secret seed phrase password
 	#[arg(long)]
 	pub avail_passphrase: Option<String>,
+	/// Password used for encryption of the stored network keypair
+	#[arg(long)]
+	pub network_keypair_password: Option<String>,
+	/// Print PeerId and listener addresses of the node, for the reserved peers configuration, and exit
+	#[arg(long)]
+	pub export_peer_id: bool,
 	/// Seed string for libp2p keypair generation
 	#[arg(long)]
 	pub seed: Option<String>,
@@ -90,7 +101,7 @@ pub struct RuntimeVersion {
 	pub impl_version: u32,
 	pub spec_name: String,
 	pub spec_version: u32,
-	transaction_version: u32,
+	pub transaction_version: u32,
 }
 
 /// Light to app client channel message struct
@@ -112,7 +123,14 @@ pub struct ClientChannels {
 impl TryFrom<(DaHeader, Option<f64>)> for BlockVerified {
 	type Error = Report;
 	fn try_from((header, confidence): (DaHeader, Option<f64>)) -> Result<Self, Self::Error> {
-		let hash: H256 = Encode::using_encoded(&header, blake2_256).into();
+		BlockVerified::try_from((&CachedHeader::new(header), confidence))
+	}
+}
+
+impl TryFrom<(&CachedHeader, Option<f64>)> for BlockVerified {
+	type Error = Report;
+	fn try_from((header, confidence): (&CachedHeader, Option<f64>)) -> Result<Self, Self::Error> {
+		let hash = header.hash();
 		let enc_lookup = extract_app_lookup(&header.extension)
 			.map_err(|e| eyre!("Invalid DataLookup: {}", e))?
 			.encode();
@@ -169,6 +187,95 @@ impl TryFrom<String> for KademliaMode {
 	}
 }
 
+/// Transport used for P2P connections
+///
+/// * `Tcp` - TCP transport, with DNS resolution
+/// * `WebSocket` - WebSocket transport, for connections with browser peers
+/// * `WebRtc` - WebRTC transport over UDP, for connections with browser peers (requires `webrtc` feature)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum P2PTransport {
+	Tcp,
+	WebSocket,
+	WebRtc,
+}
+
+impl Display for P2PTransport {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			P2PTransport::Tcp => write!(f, "tcp"),
+			P2PTransport::WebSocket => write!(f, "ws"),
+			P2PTransport::WebRtc => write!(f, "webrtc"),
+		}
+	}
+}
+
+impl TryFrom<String> for P2PTransport {
+	type Error = color_eyre::Report;
+
+	fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+		match value.to_lowercase().as_str() {
+			"tcp" => Ok(P2PTransport::Tcp),
+			"ws" | "websocket" => Ok(P2PTransport::WebSocket),
+			"webrtc" => Ok(P2PTransport::WebRtc),
+			_ => Err(eyre!(
+				"Wrong P2P transport. Expecting 'tcp', 'ws' or 'webrtc'."
+			)),
+		}
+	}
+}
+
+/// Sampling strategy used by the light client
+///
+/// * `Random` - cells are sampled uniformly from the extended matrix
+/// * `Rows` - cells are sampled from random rows
+/// * `App` - half of the cells are sampled from the rows of the configured application
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String")]
+pub enum SamplingMode {
+	Random,
+	Rows,
+	App,
+}
+
+impl TryFrom<String> for SamplingMode {
+	type Error = color_eyre::Report;
+
+	fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+		match value.to_lowercase().as_str() {
+			"random" => Ok(SamplingMode::Random),
+			"rows" => Ok(SamplingMode::Rows),
+			"app" => Ok(SamplingMode::App),
+			_ => Err(eyre!(
+				"Wrong sampling mode. Expecting 'random', 'rows' or 'app'."
+			)),
+		}
+	}
+}
+
+/// Format of the exported records
+///
+/// * `JsonLines` - single file, with one JSON record per line
+/// * `Csv` - directory with one CSV file per record type
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum ExportFormat {
+	JsonLines,
+	Csv,
+}
+
+impl TryFrom<String> for ExportFormat {
+	type Error = color_eyre::Report;
+
+	fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+		match value.to_lowercase().as_str() {
+			"jsonl" => Ok(ExportFormat::JsonLines),
+			"csv" => Ok(ExportFormat::Csv),
+			_ => Err(eyre!("Wrong export format. Expecting 'jsonl' or 'csv'.")),
+		}
+	}
+}
+
 /// Client mode
 ///
 /// * `LightClient` - light client is running
@@ -332,9 +439,16 @@ pub struct RuntimeConfig {
 	/// If set to key, a valid ed25519 private key must be provided, else the client will fail
 	/// If `secret_key` is not set, random seed will be used.
 	pub secret_key: Option<SecretKey>,
+	/// Path to the file with the network keypair, generated and stored on the first startup, so PeerId is stable across restarts. Ignored if `secret_key` is set. (default: None).
+	pub network_keypair_path: Option<String>,
 	/// P2P service port (default: 37000).
 	pub port: u16,
+	/// Use WebSocket transport instead of TCP, same as setting `transports` to `["ws"]` (default: false).
 	pub ws_transport_enable: bool,
+	/// P2P transports, any of `tcp`, `ws` and `webrtc`. WebRTC listens on the P2P port over UDP, and requires `webrtc` feature. (default: ["tcp"]).
+	pub transports: Vec<P2PTransport>,
+	/// WebSocket listener port. If omitted, P2P port is used, which requires TCP transport to be disabled. (default: None).
+	pub ws_port: Option<u16>,
 	/// Configures AutoNAT behaviour to reject probes as a server for clients that are observed at a non-global ip address (default: false)
 	pub autonat_only_global_ips: bool,
 	/// AutoNat throttle period for re-using a peer as server for a dial-request. (default: 1 sec)
@@ -360,6 +474,12 @@ pub struct RuntimeConfig {
 	pub app_id: Option<u32>,
 	/// Confidence threshold, used to calculate how many cells need to be sampled to achieve desired confidence (default: 92.0).
 	pub confidence: f64,
+	/// Sampling strategy: `random`, `rows` or `app`, which requires `app_id` to be set (default: random).
+	pub sampling_mode: SamplingMode,
+	/// Number of cells sampled from each row, if sampling strategy is `rows` (default: 4).
+	pub sampling_cells_per_row: u16,
+	/// Probability that a single sampled cell misses unavailable data, used to calculate confidence (default: 0.5).
+	pub sample_miss_probability: f64,
 	/// File system path where RocksDB used by light client, stores its data.
 	pub avail_path: String,
 	/// Log level, default is `INFO`. See `<https://docs.rs/log/0.4.14/log/enum.LevelFilter.html>` for possible log level values. (default: `INFO`).
@@ -367,8 +487,15 @@ pub struct RuntimeConfig {
 	pub origin: String,
 	/// If set to true, logs are displayed in JSON format, which is used for structured logging. Otherwise, plain text format is used (default: false).
 	pub log_format_json: bool,
+	/// Tracing filter directives (e.g. `avail_light=debug,libp2p_kad=info`), see `<https://docs.rs/tracing-subscriber/latest/tracing_subscriber/filter/struct.EnvFilter.html>` for the syntax.
+	/// If set, it overrides the `log_level` (default: None).
+	pub tracing_filter: Option<String>,
 	/// OpenTelemetry Collector endpoint (default: `http://otelcollector.avail.tools:4317`)
 	pub ot_collector_endpoint: String,
+	/// Substrate telemetry server endpoint, e.g. `wss://telemetry.polkadot.io/submit/`. If not set, telemetry is not reported (default: None).
+	pub substrate_telemetry_endpoint: Option<String>,
+	/// Name of the light client shown on Substrate telemetry dashboard. If not set, peer ID is used (default: None).
+	pub substrate_telemetry_name: Option<String>,
 	/// Disables fetching of cells from RPC, set to true if client expects cells to be available in DHT (default: false).
 	pub disable_rpc: bool,
 	/// Maximum number of parallel tasks spawned for GET and PUT operations on DHT (default: 20).
@@ -384,8 +511,31 @@ pub struct RuntimeConfig {
 	pub sync_start_block: Option<u32>,
 	/// Enable or disable synchronizing finality. If disabled, finality is assumed to be verified until the starting block at the point the LC is started and is only checked for new blocks. (default: true)
 	pub sync_finality_enable: bool,
+	/// Path to the KZG trusted setup file, in JSON, text or raw format. If omitted, embedded or default setup is used. (default: None).
+	pub trusted_setup_path: Option<String>,
+	/// Hex encoded SHA-256 checksum of the trusted setup file, verified before loading. (default: None).
+	pub trusted_setup_checksum: Option<String>,
+	/// Number of blocks before the finalized head to verify in the historical backfill mode. Omitting it will disable backfill. (default: None).
+	pub backfill_depth: Option<u32>,
+	/// Enable serving of the light client requests (remote header and remote read) to peers, from the locally verified headers. (default: false).
+	pub light_server_enable: bool,
+	/// Enable observing of the GRANDPA gossip of the peers, justifications of the blocks finalized by the gossip are
+	/// verified the same as the justifications of the RPC node. (default: false).
+	pub grandpa_gossip_enable: bool,
+	/// Enable signing of the sampling receipts of the verified blocks with the Avail account key, exported on `/v2/receipts`. (default: false).
+	pub sampling_receipts_enable: bool,
+	/// Path of the export file (`jsonl` format) or directory (`csv` format) for the verified blocks, extrinsics, events and DA stats. Omitting it will disable export. (default: None).
+	pub export_path: Option<String>,
+	/// Format of the exported records, `jsonl` or `csv` (default: "jsonl").
+	pub export_format: ExportFormat,
+	/// Starting block of the export, if nothing is exported yet. If omitted, export starts from the next verified block. (default: None).
+	pub export_start_block: Option<u32>,
 	/// Maximum number of cells per request for proof queries (default: 30).
 	pub max_cells_per_rpc: Option<usize>,
+	/// Number of worker threads for the finality signature and cell proof verification (default: number of CPUs).
+	pub verification_workers: Option<usize>,
+	/// Maximum number of verification jobs waiting for the workers, before the submitting task is paused (default: 1024).
+	pub verification_queue_size: usize,
 	/// Threshold for the number of cells fetched via DHT for the app client (default: 5000)
 	pub threshold: usize,
 	/// Kademlia configuration - WARNING: Changing the default values might cause the peer to suffer poor performance!
@@ -413,6 +563,14 @@ pub struct RuntimeConfig {
 	pub task_command_buffer_size: usize,
 	pub per_connection_event_buffer_size: usize,
 	pub dial_concurrency_factor: u8,
+	/// Maximum number of inbound cell and light client requests per second, from a single peer (default: 50).
+	pub peer_requests_per_second: u32,
+	/// Maximum number of inbound cell and light client requests per second, from all peers (default: 500).
+	pub global_requests_per_second: u32,
+	/// Maximum number of response bytes in flight, for a single peer (default: 8MB).
+	pub peer_max_response_bytes_in_flight: usize,
+	/// Maximum number of response bytes in flight, for all peers (default: 64MB).
+	pub max_response_bytes_in_flight: usize,
 	/// Sets the timeout for a single Kademlia query. (default: 60s).
 	pub store_pruning_interval: u32,
 	/// Sets the allowed level of parallelism for iterative Kademlia queries. (default: 3).
@@ -432,6 +590,9 @@ pub struct RuntimeConfig {
 	pub max_kad_record_size: u64,
 	/// The maximum number of provider records for which the local node is the provider. (default: 1024).
 	pub max_kad_provided_keys: u64,
+	/// Sign published DHT records, and reject unsigned or invalid records (default: false).
+	/// All peers in the network need to have the same setting, since signed and unsigned records are not compatible.
+	pub sign_dht_records: bool,
 	/// Set the configuration based on which the retries will be orchestrated, max duration [in seconds] between retries and number of tries.
 	/// (default:
 	/// fibonacci:
@@ -440,6 +601,33 @@ pub struct RuntimeConfig {
 	///     retries: 6,
 	/// )
 	pub retry_config: RetryConfig,
+	/// Maximum number of decoded headers kept in the cache (default: 1024).
+	pub header_cache_capacity: usize,
+	/// Memory budget in bytes, shared by the fork tree, caches, pending block bodies and sampling buffers. If omitted,
+	/// memory usage is only tracked (default: None).
+	pub memory_budget: Option<usize>,
+	/// Report detected BABE and GRANDPA equivocations with signed extrinsics of the Avail account (default: false).
+	pub report_equivocations: bool,
+	/// Fetch bodies of the exported blocks as compact blocks from the peers with enabled light server, falling back
+	/// to RPC (default: false).
+	pub compact_block_sync: bool,
+	/// Fetch block bodies and runtime calls with the new JSON-RPC spec (`chainHead_v1_*`) if the node supports it,
+	/// falling back to the legacy methods (default: false).
+	pub chain_head_rpc: bool,
+	/// Serve the new JSON-RPC spec (`chainHead_v1_*`) at the `/rpc` WebSocket path of the HTTP server, backed by the
+	/// verified headers (default: false).
+	pub chain_head_server: bool,
+	/// Serve the Kate JSON-RPC methods at the `/kate` path of the HTTP server, with the cells and rows from the DHT,
+	/// for the blocks with verified headers (default: false).
+	pub kate_server: bool,
+	/// Hex encoded storage keys, whose verified changes are published as storage change subscription events
+	/// (default: []).
+	pub watched_storage_keys: Vec<String>,
+	/// Parachain IDs, whose heads are synced and verified through the finalized relay chain headers (default: []).
+	pub parachains: Vec<u32>,
+	/// Compression of the data submitted with the `/v2/submit` endpoint, compressed data is decompressed by the data
+	/// endpoints regardless of the configuration (default: codec = none).
+	pub compression: CompressionConfig,
 	#[cfg(feature = "crawl")]
 	#[serde(flatten)]
 	pub crawl: crate::crawl_client::CrawlConfig,
@@ -449,6 +637,132 @@ impl RuntimeConfig {
 	pub fn is_fat_client(&self) -> bool {
 		self.block_matrix_partition.is_some()
 	}
+
+	/// Configured P2P transports, with TCP replaced by WebSocket if `ws_transport_enable` is set
+	pub fn p2p_transports(&self) -> Vec<P2PTransport> {
+		let mut transports = self.transports.clone();
+		if self.ws_transport_enable {
+			transports.retain(|transport| *transport != P2PTransport::Tcp);
+			if !transports.contains(&P2PTransport::WebSocket) {
+				transports.insert(0, P2PTransport::WebSocket);
+			}
+		}
+		transports
+	}
+
+	/// Checks that configuration values are consistent, so the client doesn't fail after startup.
+	pub fn validate(&self) -> Result<()> {
+		if self.bootstraps.is_empty() {
+			return Err(eyre!("Bootstrap node list must not be empty. Either use a '--network' flag or add a list of bootstrap nodes in the configuration file"));
+		}
+		if self.full_node_ws.is_empty() {
+			return Err(eyre!("Full node WebSocket endpoint list must not be empty"));
+		}
+		if self.avail_path.is_empty() {
+			return Err(eyre!("Database path must not be empty"));
+		}
+		if !(self.confidence > 0.0 && self.confidence < 100.0) {
+			return Err(eyre!("Confidence must be between 0 and 100"));
+		}
+		if self.sampling_mode == SamplingMode::App && self.app_id.is_none() {
+			return Err(eyre!("App sampling mode requires app_id to be set"));
+		}
+		if !(self.sample_miss_probability > 0.0 && self.sample_miss_probability < 1.0) {
+			return Err(eyre!("Sample miss probability must be between 0 and 1"));
+		}
+		if self.http_server_port == self.port {
+			return Err(eyre!(
+				"HTTP server port and P2P port must be different, both are set to {}",
+				self.port
+			));
+		}
+		let transports = self.p2p_transports();
+		if transports.is_empty() {
+			return Err(eyre!("At least one P2P transport must be enabled"));
+		}
+		if transports.contains(&P2PTransport::WebRtc) && !cfg!(feature = "webrtc") {
+			return Err(eyre!("WebRTC transport requires webrtc feature"));
+		}
+		if transports.contains(&P2PTransport::Tcp)
+			&& transports.contains(&P2PTransport::WebSocket)
+			&& self.ws_port.unwrap_or(self.port) == self.port
+		{
+			return Err(eyre!(
+				"TCP and WebSocket transports must listen on different ports, both are set to {}",
+				self.port
+			));
+		}
+		if let Some(Partition { number, fraction }) = self.block_matrix_partition {
+			if fraction == 0 || number > fraction {
+				return Err(eyre!("Invalid block matrix partition {number}/{fraction}"));
+			}
+			if self.disable_rpc {
+				return Err(eyre!(
+					"Fat client fetches partitions from RPC, which is disabled"
+				));
+			}
+		}
+		if self.peer_requests_per_second == 0 || self.global_requests_per_second == 0 {
+			return Err(eyre!("Request rate limits must be greater than 0"));
+		}
+		if self.peer_max_response_bytes_in_flight > self.max_response_bytes_in_flight {
+			return Err(eyre!(
+				"Peer response bytes in flight must not exceed the global limit"
+			));
+		}
+		if self.backfill_depth == Some(0) {
+			return Err(eyre!("Backfill depth must be greater than 0"));
+		}
+		if self.max_cells_per_rpc == Some(0) {
+			return Err(eyre!(
+				"Maximum number of cells per RPC request must be greater than 0"
+			));
+		}
+		if self.verification_workers == Some(0) || self.verification_queue_size == 0 {
+			return Err(eyre!(
+				"Number of verification workers and queue size must be greater than 0"
+			));
+		}
+		if self.query_proof_rpc_parallel_tasks == 0 || self.dht_parallelization_limit == 0 {
+			return Err(eyre!(
+				"Number of parallel RPC and DHT tasks must be greater than 0"
+			));
+		}
+		if self.store_pruning_interval == 0 {
+			return Err(eyre!("Store pruning interval must be greater than 0"));
+		}
+		if self.header_cache_capacity == 0 {
+			return Err(eyre!("Header cache capacity must be greater than 0"));
+		}
+		self.watched_storage_keys()?;
+		if self.compression.min_savings > 100 {
+			return Err(eyre!("Compression savings must not exceed 100 percent"));
+		}
+		if !(self.kad_record_ttl > self.publication_interval as u64
+			&& self.publication_interval > self.replication_interval)
+		{
+			return Err(eyre!("Record TTL, publication and replication intervals must satisfy: TTL > publication_interval > replication_interval"));
+		}
+		Ok(())
+	}
+
+	/// Decoded storage keys watched for changes
+	pub fn watched_storage_keys(&self) -> Result<Vec<Vec<u8>>> {
+		self.watched_storage_keys
+			.iter()
+			.map(|key| {
+				hex::decode(key.trim_start_matches("0x"))
+					.map_err(|error| eyre!("Invalid watched storage key {key}: {error}"))
+			})
+			.collect()
+	}
+
+	/// Tracing filter directives, defaults to the light client logs on given log level
+	pub fn tracing_directives(&self, log_level: tracing::Level) -> String {
+		self.tracing_filter
+			.clone()
+			.unwrap_or_else(|| format!("avail_light={log_level}"))
+	}
 }
 
 pub struct Delay(pub Option<Duration>);
@@ -457,6 +771,7 @@ pub struct Delay(pub Option<Duration>);
 pub struct LightClientConfig {
 	pub confidence: f64,
 	pub block_processing_delay: Delay,
+	pub sampling: SamplingPolicy,
 }
 
 impl Delay {
@@ -476,6 +791,7 @@ impl From<&RuntimeConfig> for LightClientConfig {
 		LightClientConfig {
 			confidence: val.confidence,
 			block_processing_delay: Delay(block_processing_delay),
+			sampling: val.into(),
 		}
 	}
 }
@@ -514,6 +830,7 @@ impl From<&RuntimeConfig> for FatClientConfig {
 #[derive(Clone)]
 pub struct LibP2PConfig {
 	pub secret_key: Option<SecretKey>,
+	pub keypair_path: Option<String>,
 	pub port: u16,
 	pub identify: IdentifyConfig,
 	pub autonat: AutoNATConfig,
@@ -525,6 +842,32 @@ pub struct LibP2PConfig {
 	pub task_command_buffer_size: NonZeroUsize,
 	pub per_connection_event_buffer_size: usize,
 	pub dial_concurrency_factor: NonZeroU8,
+	pub light_server: bool,
+	pub grandpa_gossip: bool,
+	pub rate_limit: RateLimitConfig,
+	pub transports: Vec<P2PTransport>,
+	pub ws_port: u16,
+}
+
+impl LibP2PConfig {
+	/// Listener addresses of the configured transports
+	pub fn listen_addresses(&self) -> Vec<Multiaddr> {
+		let unspecified = Multiaddr::empty().with(Protocol::from(Ipv4Addr::UNSPECIFIED));
+		self.transports
+			.iter()
+			.map(|transport| match transport {
+				P2PTransport::Tcp => unspecified.clone().with(Protocol::Tcp(self.port)),
+				P2PTransport::WebSocket => unspecified
+					.clone()
+					.with(Protocol::Tcp(self.ws_port))
+					.with(Protocol::Ws(Cow::Borrowed("avail-light"))),
+				P2PTransport::WebRtc => unspecified
+					.clone()
+					.with(Protocol::Udp(self.port))
+					.with(Protocol::WebRTCDirect),
+			})
+			.collect()
+	}
 }
 
 impl From<&LibP2PConfig> for libp2p::kad::Config {
@@ -567,6 +910,7 @@ impl From<&RuntimeConfig> for LibP2PConfig {
 	fn from(val: &RuntimeConfig) -> Self {
 		Self {
 			secret_key: val.secret_key.clone(),
+			keypair_path: val.network_keypair_path.clone(),
 			port: val.port,
 			identify: val.into(),
 			autonat: val.into(),
@@ -580,6 +924,11 @@ impl From<&RuntimeConfig> for LibP2PConfig {
 			per_connection_event_buffer_size: val.per_connection_event_buffer_size,
 			dial_concurrency_factor: std::num::NonZeroU8::new(val.dial_concurrency_factor)
 				.expect("Invalid dial concurrency factor"),
+			light_server: val.light_server_enable,
+			grandpa_gossip: val.grandpa_gossip_enable,
+			rate_limit: val.into(),
+			transports: val.p2p_transports(),
+			ws_port: val.ws_port.unwrap_or(val.port),
 		}
 	}
 }
@@ -599,6 +948,7 @@ pub struct KademliaConfig {
 	pub max_kad_record_size: usize,
 	pub max_kad_provided_keys: usize,
 	pub kademlia_mode: KademliaMode,
+	pub sign_records: bool,
 }
 
 impl From<&RuntimeConfig> for KademliaConfig {
@@ -618,6 +968,27 @@ impl From<&RuntimeConfig> for KademliaConfig {
 			max_kad_record_size: val.max_kad_record_size as usize,
 			max_kad_provided_keys: val.max_kad_provided_keys as usize,
 			kademlia_mode: val.operation_mode,
+			sign_records: val.sign_dht_records,
+		}
+	}
+}
+
+/// Inbound requests rate limit configuration (see [RuntimeConfig] for details)
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimitConfig {
+	pub peer_requests_per_second: u32,
+	pub global_requests_per_second: u32,
+	pub peer_max_bytes_in_flight: usize,
+	pub max_bytes_in_flight: usize,
+}
+
+impl From<&RuntimeConfig> for RateLimitConfig {
+	fn from(val: &RuntimeConfig) -> Self {
+		Self {
+			peer_requests_per_second: val.peer_requests_per_second,
+			global_requests_per_second: val.global_requests_per_second,
+			peer_max_bytes_in_flight: val.peer_max_response_bytes_in_flight,
+			max_bytes_in_flight: val.max_response_bytes_in_flight,
 		}
 	}
 }
@@ -722,6 +1093,7 @@ pub struct SyncClientConfig {
 	pub disable_rpc: bool,
 	pub dht_parallelization_limit: usize,
 	pub is_last_step: bool,
+	pub sampling: SamplingPolicy,
 }
 
 impl From<&RuntimeConfig> for SyncClientConfig {
@@ -731,6 +1103,39 @@ impl From<&RuntimeConfig> for SyncClientConfig {
 			disable_rpc: val.disable_rpc,
 			dht_parallelization_limit: val.dht_parallelization_limit,
 			is_last_step: val.app_id.is_none(),
+			sampling: val.into(),
+		}
+	}
+}
+
+/// Backfill configuration (see [RuntimeConfig] for details)
+#[derive(Clone)]
+pub struct BackfillConfig {
+	pub confidence: f64,
+	pub depth: u32,
+	pub sampling: SamplingPolicy,
+}
+
+impl From<&RuntimeConfig> for BackfillConfig {
+	fn from(val: &RuntimeConfig) -> Self {
+		BackfillConfig {
+			confidence: val.confidence,
+			depth: val.backfill_depth.unwrap_or_default(),
+			sampling: val.into(),
+		}
+	}
+}
+
+/// Export configuration (see [RuntimeConfig] for details)
+#[derive(Clone)]
+pub struct ExportConfig {
+	pub start_block: Option<u32>,
+}
+
+impl From<&RuntimeConfig> for ExportConfig {
+	fn from(val: &RuntimeConfig) -> Self {
+		ExportConfig {
+			start_block: val.export_start_block,
 		}
 	}
 }
@@ -758,7 +1163,10 @@ impl Default for RuntimeConfig {
 			http_server_port: 7000,
 			port: 37000,
 			ws_transport_enable: false,
+			transports: vec![P2PTransport::Tcp],
+			ws_port: None,
 			secret_key: None,
+			network_keypair_path: None,
 			autonat_only_global_ips: false,
 			autonat_refresh_interval: 360,
 			autonat_retry_interval: 20,
@@ -771,10 +1179,16 @@ impl Default for RuntimeConfig {
 			genesis_hash: "DEV".to_owned(),
 			app_id: None,
 			confidence: 99.9,
+			sampling_mode: SamplingMode::Random,
+			sampling_cells_per_row: 4,
+			sample_miss_probability: 0.5,
 			avail_path: "avail_path".to_owned(),
 			log_level: "INFO".to_owned(),
 			log_format_json: false,
+			tracing_filter: None,
 			ot_collector_endpoint: "http://127.0.0.1:4317".to_string(),
+			substrate_telemetry_endpoint: None,
+			substrate_telemetry_name: None,
 			disable_rpc: false,
 			dht_parallelization_limit: 20,
 			query_proof_rpc_parallel_tasks: 8,
@@ -782,7 +1196,18 @@ impl Default for RuntimeConfig {
 			block_matrix_partition: None,
 			sync_start_block: None,
 			sync_finality_enable: false,
+			trusted_setup_path: None,
+			trusted_setup_checksum: None,
+			backfill_depth: None,
+			light_server_enable: false,
+			grandpa_gossip_enable: false,
+			sampling_receipts_enable: false,
+			export_path: None,
+			export_format: ExportFormat::JsonLines,
+			export_start_block: None,
 			max_cells_per_rpc: Some(30),
+			verification_workers: None,
+			verification_queue_size: 1024,
 			kad_record_ttl: 24 * 60 * 60,
 			threshold: 5000,
 			replication_factor: 5,
@@ -793,6 +1218,10 @@ impl Default for RuntimeConfig {
 			task_command_buffer_size: 32,
 			per_connection_event_buffer_size: 7,
 			dial_concurrency_factor: 8,
+			peer_requests_per_second: 50,
+			global_requests_per_second: 500,
+			peer_max_response_bytes_in_flight: 8 * 1024 * 1024,
+			max_response_bytes_in_flight: 64 * 1024 * 1024,
 			store_pruning_interval: 180,
 			query_timeout: 10,
 			query_parallelism: 3,
@@ -801,6 +1230,7 @@ impl Default for RuntimeConfig {
 			max_kad_record_number: 2400000,
 			max_kad_record_size: 8192,
 			max_kad_provided_keys: 1024,
+			sign_dht_records: false,
 			#[cfg(feature = "crawl")]
 			crawl: crate::crawl_client::CrawlConfig::default(),
 			origin: "external".to_string(),
@@ -810,6 +1240,16 @@ impl Default for RuntimeConfig {
 				max_delay: 10,
 				retries: 6,
 			}),
+			header_cache_capacity: 1024,
+			memory_budget: None,
+			report_equivocations: false,
+			compact_block_sync: false,
+			chain_head_rpc: false,
+			chain_head_server: false,
+			kate_server: false,
+			watched_storage_keys: vec![],
+			parachains: vec![],
+			compression: CompressionConfig::disabled(),
 		}
 	}
 }
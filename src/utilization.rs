@@ -0,0 +1,265 @@
+//! Block fullness analytics, for the dashboards about chain capacity.
+//!
+//! [`BlockUtilization`] combines `System::BlockWeight` (consumed weight per dispatch class, read at the block) with
+//! the block length used by the extrinsics, and compares them with the `System::BlockWeights` and
+//! `System::BlockLength` limits from the metadata (see [`crate::constants`]). Data availability usage is the size of
+//! the block data matrix from the header extension, compared with the maximum matrix of the Avail runtime.
+//!
+//! # Notes
+//!
+//! `System::AllExtrinsicsLen` is removed when the block is finalized, so the block length is computed from the block
+//! body, the same way as the runtime does (encoded length of each extrinsic).
+
+use avail_subxt::utils::H256;
+use codec::Encode;
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use sp_core::{blake2_256, twox_128};
+use subxt::{
+	ext::scale_value::{At, Value},
+	metadata::types::StorageEntryType,
+	Metadata,
+};
+
+use crate::{
+	body::Block,
+	constants::{self, weight_from_value, BlockLength, BlockWeights, Weight},
+	dynamic,
+	state_client::{Client, StateClient},
+	utils::extract_kate,
+};
+
+/// Consumed weight per dispatch class
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClassWeights {
+	pub normal: Weight,
+	pub operational: Weight,
+	pub mandatory: Weight,
+}
+
+impl ClassWeights {
+	pub fn total(&self) -> Weight {
+		let classes = [self.normal, self.operational, self.mandatory];
+		Weight {
+			ref_time: classes
+				.iter()
+				.map(|weight| weight.ref_time)
+				.fold(0, u64::saturating_add),
+			proof_size: classes
+				.iter()
+				.map(|weight| weight.proof_size)
+				.fold(0, u64::saturating_add),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockUtilization {
+	pub number: u32,
+	pub hash: H256,
+	pub weights: ClassWeights,
+	pub max_weight: Weight,
+	/// Encoded length of all extrinsics, in bytes
+	pub length: u64,
+	/// Maximum length of the extrinsics of any dispatch class, in bytes
+	pub max_length: u32,
+	/// Number of cells (rows * columns) of the block data matrix
+	pub cells: u64,
+	/// Maximum number of cells of the data matrix, on Avail runtimes
+	pub max_cells: Option<u64>,
+}
+
+fn ratio(used: u64, max: u64) -> f64 {
+	match max {
+		0 => 0.0,
+		max => used as f64 / max as f64,
+	}
+}
+
+impl BlockUtilization {
+	pub fn new(
+		block: &Block,
+		weights: ClassWeights,
+		limits: &BlockWeights,
+		length: &BlockLength,
+	) -> Self {
+		let (rows, cols, _, _) = extract_kate(&block.header.extension);
+		BlockUtilization {
+			number: block.header.number,
+			hash: H256(block.header.using_encoded(blake2_256)),
+			weights,
+			max_weight: limits.max_block,
+			length: block
+				.extrinsics
+				.iter()
+				.map(|extrinsic| extrinsic.encoded_size() as u64)
+				.sum(),
+			// Normal class limit is a part of the limit of the other classes
+			max_length: length.normal.max(length.operational).max(length.mandatory),
+			cells: rows as u64 * cols as u64,
+			max_cells: length
+				.rows
+				.zip(length.cols)
+				.map(|(rows, cols)| rows as u64 * cols as u64),
+		}
+	}
+
+	/// Consumed part of the maximum block reference time, of all dispatch classes
+	pub fn weight_ratio(&self) -> f64 {
+		ratio(self.weights.total().ref_time, self.max_weight.ref_time)
+	}
+
+	/// Used part of the maximum block length
+	pub fn length_ratio(&self) -> f64 {
+		ratio(self.length, self.max_length as u64)
+	}
+
+	/// Used part of the maximum data matrix, `None` if the runtime has no matrix limits
+	pub fn data_ratio(&self) -> Option<f64> {
+		self.max_cells.map(|max_cells| ratio(self.cells, max_cells))
+	}
+}
+
+/// Storage key of the `System::BlockWeight` entry
+pub fn block_weight_storage_key() -> Vec<u8> {
+	[twox_128(b"System"), twox_128(b"BlockWeight")].concat()
+}
+
+fn class<'a, T>(value: &'a Value<T>, name: &str) -> Result<&'a Value<T>> {
+	value
+		.at(name)
+		.ok_or_else(|| eyre!("Block weight of the {name} class is missing"))
+}
+
+/// Maps decoded `PerDispatchClass<Weight>` value to the typed weights
+pub fn class_weights_from_value<T>(value: &Value<T>) -> Result<ClassWeights> {
+	Ok(ClassWeights {
+		normal: weight_from_value(class(value, "normal")?)?,
+		operational: weight_from_value(class(value, "operational")?)?,
+		mandatory: weight_from_value(class(value, "mandatory")?)?,
+	})
+}
+
+/// Decodes `System::BlockWeight` storage value, using the value type from the metadata
+pub fn decode_block_weight(metadata: &Metadata, encoded: &[u8]) -> Result<ClassWeights> {
+	let entry = metadata
+		.pallet_by_name("System")
+		.and_then(|pallet| pallet.storage())
+		.and_then(|storage| storage.entry_by_name("BlockWeight"))
+		.ok_or_else(|| eyre!("Storage System.BlockWeight is not found in metadata"))?;
+	let StorageEntryType::Plain(value_ty) = entry.entry_type() else {
+		return Err(eyre!("Storage System.BlockWeight is not a plain value"));
+	};
+	let value = dynamic::decode_as_type(&mut &encoded[..], *value_ty, metadata.types())
+		.map_err(|error| eyre!("Failed to decode block weight: {error}"))?;
+	class_weights_from_value(&value)
+}
+
+impl<T: Client> StateClient<T> {
+	/// Returns utilization of the block, with the block weight verified at the block state
+	pub async fn block_utilization(
+		&self,
+		metadata: &Metadata,
+		block: &Block,
+	) -> Result<BlockUtilization> {
+		let hash = H256(block.header.using_encoded(blake2_256));
+		let weights = self
+			.storage(block_weight_storage_key(), hash)
+			.await
+			.wrap_err("Failed to get block weight")?;
+		let weights = match weights {
+			Some(encoded) => decode_block_weight(metadata, &encoded)?,
+			None => ClassWeights::default(),
+		};
+		Ok(BlockUtilization::new(
+			block,
+			weights,
+			&constants::block_weights(metadata)?,
+			&constants::block_length(metadata)?,
+		))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::ChainBuilder;
+
+	fn weight(ref_time: u128, proof_size: u128) -> Value<()> {
+		Value::named_composite([
+			("ref_time", Value::u128(ref_time)),
+			("proof_size", Value::u128(proof_size)),
+		])
+	}
+
+	#[test]
+	fn class_weights() {
+		let value = Value::named_composite([
+			("normal", weight(300, 10)),
+			("operational", weight(100, 0)),
+			("mandatory", weight(100, 5)),
+		]);
+		let weights = class_weights_from_value(&value).unwrap();
+		assert_eq!(weights.normal.ref_time, 300);
+		assert_eq!(
+			weights.total(),
+			Weight {
+				ref_time: 500,
+				proof_size: 15
+			}
+		);
+		assert!(class_weights_from_value(&weight(1, 1)).is_err());
+	}
+
+	#[test]
+	fn utilization() {
+		let mut chain = ChainBuilder::new(&[[1; 32]]);
+		let hash = chain.extend(1).unwrap()[0];
+		let block = Block {
+			header: chain.header(&hash).unwrap().clone(),
+			extrinsics: vec![vec![0; 10], vec![0; 100]],
+		};
+		let weights = ClassWeights {
+			normal: Weight {
+				ref_time: 250,
+				proof_size: 0,
+			},
+			..Default::default()
+		};
+		let limits = BlockWeights {
+			max_block: Weight {
+				ref_time: 1000,
+				proof_size: 0,
+			},
+			..Default::default()
+		};
+		let length = BlockLength {
+			normal: 200,
+			operational: 222,
+			mandatory: 222,
+			rows: Some(2),
+			cols: Some(8),
+		};
+		let utilization = BlockUtilization::new(&block, weights, &limits, &length);
+		assert_eq!(utilization.hash, hash);
+		// Extrinsics are encoded with the one byte compact length prefix
+		assert_eq!(utilization.length, 111);
+		assert_eq!(utilization.weight_ratio(), 0.25);
+		assert_eq!(utilization.length_ratio(), 0.5);
+		// Header has the matrix of one row and four columns
+		assert_eq!(utilization.cells, 4);
+		assert_eq!(utilization.data_ratio(), Some(0.25));
+		assert_eq!(
+			BlockUtilization::new(&block, weights, &Default::default(), &length).weight_ratio(),
+			0.0
+		);
+		let length = BlockLength {
+			normal: 222,
+			..Default::default()
+		};
+		let utilization = BlockUtilization::new(&block, weights, &limits, &length);
+		assert_eq!(utilization.data_ratio(), None);
+	}
+}
@@ -20,13 +20,36 @@ use kate_recovery::{
 	data::Cell,
 	matrix::{Dimensions, Position},
 };
+use std::{
+	fs::OpenOptions,
+	io::{self, Write},
+	path::Path,
+};
+
+/// Writes secret to the new file, readable and writable only by the owner on Unix
+pub fn write_secret_file(path: &Path, secret: &[u8]) -> io::Result<()> {
+	let mut options = OpenOptions::new();
+	options.write(true).create_new(true);
+	#[cfg(unix)]
+	std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+	options.open(path)?.write_all(secret)
+}
 
 pub fn decode_app_data(data: &[u8]) -> Result<Option<Vec<u8>>> {
+	Ok(decode_app_extrinsic(data)?.map(|(_, data)| data))
+}
+
+/// Decodes application ID and data of the data submission extrinsic (unsigned extrinsics have application ID 0)
+pub fn decode_app_extrinsic(data: &[u8]) -> Result<Option<(u32, Vec<u8>)>> {
 	let extrisic: AppUncheckedExtrinsic =
 		<_ as Decode>::decode(&mut &data[..]).wrap_err("Couldn't decode AvailExtrinsic")?;
+	let app_id = extrisic
+		.signature
+		.as_ref()
+		.map_or(0, |(_, _, extra)| extra.app_id.0);
 
 	match extrisic.function {
-		RuntimeCall::DataAvailability(Call::submit_data { data, .. }) => Ok(Some(data.0)),
+		RuntimeCall::DataAvailability(Call::submit_data { data, .. }) => Ok(Some((app_id, data.0))),
 		_ => Ok(None),
 	}
 }
@@ -0,0 +1,173 @@
+//! CPU worker pool for the finality signature and cell proof verification.
+//!
+//! Verification is CPU bound, and running it on the async tasks blocks the reactor, delaying networking and RPC
+//! subscriptions. Verification jobs are offloaded to the dedicated worker threads through the bounded queue, so
+//! submitting tasks are paused (without blocking the reactor) once the workers cannot keep up. Results of the job
+//! batches are returned in the submission order, regardless of the order in which the workers complete them.
+//!
+//! Worker threads stop once all the pool handles are dropped.
+//!
+//! # Notes
+//!
+//! Panicking job is caught on the worker, and the worker keeps running. Global panic hook is still invoked for the
+//! panic, so the hook has to check [`is_worker_thread`] to avoid shutting down the client.
+//!
+//! Headers are accepted only with GRANDPA justifications, so finality signatures are verified instead of BABE seals
+//! and VRF outputs, which are not verified by the light client.
+
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use futures::future::try_join_all;
+use std::{
+	panic::{self, AssertUnwindSafe},
+	sync::{Arc, Mutex},
+	thread,
+};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, error};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+const WORKER_THREAD_PREFIX: &str = "verification-";
+
+/// Checks if the current thread is the verification worker
+pub fn is_worker_thread() -> bool {
+	thread::current()
+		.name()
+		.is_some_and(|name| name.starts_with(WORKER_THREAD_PREFIX))
+}
+
+/// Handle to the verification workers, cloned handles share the same workers and queue
+#[derive(Clone)]
+pub struct WorkerPool {
+	sender: mpsc::Sender<Job>,
+}
+
+impl WorkerPool {
+	/// Spawns `workers` threads, with up to `queue_size` jobs waiting for the free worker
+	pub fn new(workers: usize, queue_size: usize) -> Result<Self> {
+		let (sender, receiver) = mpsc::channel::<Job>(queue_size);
+		let receiver = Arc::new(Mutex::new(receiver));
+
+		for index in 0..workers.max(1) {
+			let receiver = receiver.clone();
+			thread::Builder::new()
+				.name(format!("{WORKER_THREAD_PREFIX}{index}"))
+				.spawn(move || loop {
+					// Lock is released before the job is executed
+					let Some(job) = receiver
+						.lock()
+						.expect("lock is not poisoned")
+						.blocking_recv()
+					else {
+						debug!(worker = index, "Verification worker stopped");
+						return;
+					};
+					if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+						error!(worker = index, "Verification job panicked");
+					}
+				})
+				.wrap_err("Cannot spawn verification worker")?;
+		}
+
+		Ok(WorkerPool { sender })
+	}
+
+	/// Queues the job, waiting for the free slot if the queue is full.
+	/// Returned receiver resolves to the job result, or fails if the job panicked.
+	pub async fn submit<F, R>(&self, job: F) -> Result<oneshot::Receiver<R>>
+	where
+		F: FnOnce() -> R + Send + 'static,
+		R: Send + 'static,
+	{
+		let (result_sender, result_receiver) = oneshot::channel();
+		let job: Job = Box::new(move || {
+			_ = result_sender.send(job());
+		});
+		self.sender
+			.send(job)
+			.await
+			.map_err(|_| eyre!("Verification workers stopped"))?;
+		Ok(result_receiver)
+	}
+
+	/// Executes the job on the worker
+	pub async fn execute<F, R>(&self, job: F) -> Result<R>
+	where
+		F: FnOnce() -> R + Send + 'static,
+		R: Send + 'static,
+	{
+		self.submit(job)
+			.await?
+			.await
+			.wrap_err("Verification job failed")
+	}
+
+	/// Executes jobs on the workers in parallel, returning results in the order of the jobs
+	pub async fn execute_ordered<F, R>(&self, jobs: impl IntoIterator<Item = F>) -> Result<Vec<R>>
+	where
+		F: FnOnce() -> R + Send + 'static,
+		R: Send + 'static,
+	{
+		let mut results = vec![];
+		for job in jobs {
+			results.push(self.submit(job).await?);
+		}
+		try_join_all(results)
+			.await
+			.wrap_err("Verification job failed")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	#[tokio::test]
+	async fn ordered_results() {
+		let pool = WorkerPool::new(4, 2).unwrap();
+		// Earlier jobs are completed later
+		let jobs = (0..16u64).map(|i| {
+			move || {
+				thread::sleep(Duration::from_millis(16 - i));
+				i
+			}
+		});
+		let results = pool.execute_ordered(jobs).await.unwrap();
+		assert_eq!(results, (0..16).collect::<Vec<_>>());
+	}
+
+	#[tokio::test]
+	async fn panicked_job() {
+		let pool = WorkerPool::new(1, 1).unwrap();
+		assert!(pool.execute(|| panic!("invalid job")).await.is_err());
+		assert!(pool.execute(is_worker_thread).await.unwrap());
+		assert!(!is_worker_thread());
+		// Worker survives the panic
+		assert_eq!(pool.execute(|| 42).await.unwrap(), 42);
+	}
+
+	#[tokio::test]
+	async fn bounded_queue() {
+		let pool = WorkerPool::new(1, 1).unwrap();
+		let (unblock, blocked) = std::sync::mpsc::channel::<()>();
+		let running = pool.submit(move || blocked.recv().unwrap()).await.unwrap();
+		// Wait until the worker takes the blocking job, so the queue has one free slot
+		while pool.sender.capacity() == 0 {
+			tokio::task::yield_now().await;
+		}
+		let queued = pool.submit(|| ()).await.unwrap();
+		let submit = pool.submit(|| ());
+		assert!(tokio::time::timeout(Duration::from_millis(50), submit)
+			.await
+			.is_err());
+
+		unblock.send(()).unwrap();
+		running.await.unwrap();
+		queued.await.unwrap();
+		pool.execute(|| ()).await.unwrap();
+	}
+}
@@ -0,0 +1,285 @@
+//! JSON-RPC queries backed by the proofs against the locally verified headers.
+//!
+//! [`VerifiedRpc`] mirrors `state_getStorage`, `state_queryStorageAt` and `chain_getBlock`, but never returns data
+//! which is not proven:
+//!
+//! * Header of the queried block has to be verified locally (e.g. finalized by a checked justification), and is
+//!   taken from [`VerifiedHeaders`], the node is not trusted for it
+//! * Storage values are read from the read proof, verified against the header state root
+//! * Block body is checked against the header extrinsics root
+//!
+//! Queries at blocks which are not verified yet fail, instead of falling back to the unverified responses. The light
+//! client fills the shared [`HeaderStore`] with the finalized headers verified by the finality sync.
+//!
+//! With [`VerifiedRpc::with_budget`], memory of the fetched block bodies is reserved in the [`MemoryBudget`] by their
+//! encoded size while they are verified, so concurrent block queries wait while the budget is exhausted.
+
+use async_trait::async_trait;
+use avail_subxt::{primitives::Header, utils::H256};
+use codec::Encode;
+use color_eyre::{
+	eyre::{eyre, WrapErr},
+	Result,
+};
+use lru::LruCache;
+use mockall::automock;
+use sp_core::blake2_256;
+use std::{
+	num::NonZeroUsize,
+	sync::{Arc, Mutex},
+};
+
+use crate::{
+	block_builder::extrinsics_root,
+	body::{Block, BodyLimits},
+	memory::{Component, MemoryBudget},
+	network::rpc::Client as RpcClient,
+	storage_proof::verify_read_proof,
+};
+
+#[automock]
+pub trait VerifiedHeaders {
+	/// Returns header with the given hash, if it is verified locally
+	fn verified_header(&self, block_hash: H256) -> Option<Header>;
+}
+
+/// Bounded in-memory store of the verified headers, filled by the finality verification
+pub struct HeaderStore {
+	headers: Mutex<LruCache<H256, Header>>,
+}
+
+impl HeaderStore {
+	pub fn new(capacity: NonZeroUsize) -> Self {
+		HeaderStore {
+			headers: Mutex::new(LruCache::new(capacity)),
+		}
+	}
+
+	/// Stores the header, which has to be verified by the caller
+	pub fn insert(&self, header: Header) -> H256 {
+		let hash = Encode::using_encoded(&header, blake2_256).into();
+		self.headers.lock().unwrap().put(hash, header);
+		hash
+	}
+}
+
+impl VerifiedHeaders for HeaderStore {
+	fn verified_header(&self, block_hash: H256) -> Option<Header> {
+		self.headers.lock().unwrap().get(&block_hash).cloned()
+	}
+}
+
+impl<H: VerifiedHeaders> VerifiedHeaders for Arc<H> {
+	fn verified_header(&self, block_hash: H256) -> Option<Header> {
+		self.as_ref().verified_header(block_hash)
+	}
+}
+
+#[async_trait]
+#[automock]
+pub trait Client {
+	async fn get_read_proof(&self, keys: Vec<Vec<u8>>, block_hash: H256) -> Result<Vec<Vec<u8>>>;
+	/// Returns SCALE encoded extrinsics of the block, without the length prefix
+	async fn get_block_body(&self, block_hash: H256) -> Result<Vec<Vec<u8>>>;
+}
+
+#[async_trait]
+impl Client for RpcClient {
+	async fn get_read_proof(&self, keys: Vec<Vec<u8>>, block_hash: H256) -> Result<Vec<Vec<u8>>> {
+		RpcClient::get_read_proof(self, keys, block_hash).await
+	}
+
+	async fn get_block_body(&self, block_hash: H256) -> Result<Vec<Vec<u8>>> {
+		RpcClient::get_block_body(self, block_hash).await
+	}
+}
+
+pub struct VerifiedRpc<C: Client, H: VerifiedHeaders> {
+	client: C,
+	headers: H,
+	budget: Option<Arc<MemoryBudget>>,
+}
+
+impl<C: Client, H: VerifiedHeaders> VerifiedRpc<C, H> {
+	pub fn new(client: C, headers: H) -> Self {
+		VerifiedRpc {
+			client,
+			headers,
+			budget: None,
+		}
+	}
+
+	/// Reserves memory of the fetched block bodies in the memory budget
+	pub fn with_budget(mut self, budget: Arc<MemoryBudget>) -> Self {
+		self.budget = Some(budget);
+		self
+	}
+
+	/// Returns the store of the verified headers
+	pub fn headers(&self) -> &H {
+		&self.headers
+	}
+
+	fn header(&self, block_hash: H256) -> Result<Header> {
+		self.headers
+			.verified_header(block_hash)
+			.ok_or_else(|| eyre!("Header {block_hash:?} is not verified"))
+	}
+
+	/// Returns verified value of the storage key at the block (`state_getStorage`)
+	pub async fn state_get_storage(
+		&self,
+		key: Vec<u8>,
+		block_hash: H256,
+	) -> Result<Option<Vec<u8>>> {
+		let mut values = self.state_query_storage_at(vec![key], block_hash).await?;
+		Ok(values.pop().flatten())
+	}
+
+	/// Returns verified values of the storage keys at the block, in the same order as keys (`state_queryStorageAt`)
+	pub async fn state_query_storage_at(
+		&self,
+		keys: Vec<Vec<u8>>,
+		block_hash: H256,
+	) -> Result<Vec<Option<Vec<u8>>>> {
+		let header = self.header(block_hash)?;
+		let proof = self
+			.client
+			.get_read_proof(keys.clone(), block_hash)
+			.await
+			.wrap_err("Failed to get read proof")?;
+		let values = verify_read_proof(header.state_root, proof, &keys)?;
+		Ok(values.into_iter().map(|(_, value)| value).collect())
+	}
+
+	/// Returns block with the body verified against the header extrinsics root (`chain_getBlock`)
+	pub async fn chain_get_block(&self, block_hash: H256) -> Result<Block> {
+		let header = self.header(block_hash)?;
+		let extrinsics = self
+			.client
+			.get_block_body(block_hash)
+			.await
+			.wrap_err("Failed to get block body")?;
+		let body_size = extrinsics.iter().map(Vec::len).sum::<usize>();
+		let max_body_size = BodyLimits::default().max_body_size;
+		if body_size > max_body_size {
+			return Err(eyre!(
+				"Block {block_hash:?} body size {body_size} exceeds the maximum {max_body_size}"
+			));
+		}
+		let _reservation = match &self.budget {
+			Some(budget) => Some(budget.reserve(Component::PendingBodies, body_size).await?),
+			None => None,
+		};
+		let root = extrinsics_root(&extrinsics);
+		if root != header.extrinsics_root {
+			return Err(eyre!(
+				"Block {block_hash:?} body root {root:?} doesn't match extrinsics root {:?}",
+				header.extrinsics_root
+			));
+		}
+		Ok(Block { header, extrinsics })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		storage_proof::build_trie,
+		test_utils::{BlockSpec, ChainBuilder},
+	};
+	use mockall::predicate::eq;
+
+	#[tokio::test]
+	async fn verified_storage() {
+		let (state_root, proof) = build_trie(&[(b"key", b"value"), (b"other", b"value")]);
+		let mut chain = ChainBuilder::new(&[[1; 32]]);
+		let hash = chain.extend(1).unwrap()[0];
+		let mut header = chain.header(&hash).unwrap().clone();
+		header.state_root = state_root;
+		let headers = HeaderStore::new(NonZeroUsize::new(8).unwrap());
+		let hash = headers.insert(header);
+
+		let mut client = MockClient::new();
+		client.expect_get_read_proof().returning(move |_, _| {
+			let proof = proof.clone();
+			Box::pin(async move { Ok(proof) })
+		});
+		let rpc = VerifiedRpc::new(client, headers);
+		let value = rpc.state_get_storage(b"key".to_vec(), hash).await.unwrap();
+		assert_eq!(value, Some(b"value".to_vec()));
+		let values = rpc
+			.state_query_storage_at(vec![b"other".to_vec(), b"missing".to_vec()], hash)
+			.await
+			.unwrap();
+		assert_eq!(values, [Some(b"value".to_vec()), None]);
+		// Block is not verified locally
+		let unverified = rpc.state_get_storage(b"key".to_vec(), H256::zero()).await;
+		assert!(unverified.is_err());
+	}
+
+	#[tokio::test]
+	async fn verified_block() {
+		let mut chain = ChainBuilder::new(&[[1; 32]]);
+		let spec = BlockSpec {
+			extrinsics: vec![vec![4, 1, 2, 3]],
+			..Default::default()
+		};
+		let hash = chain.build_on(chain.genesis_hash(), spec).unwrap();
+		let block = chain.block(&hash).unwrap().clone();
+		let mut headers = MockVerifiedHeaders::new();
+		let header = block.header.clone();
+		headers
+			.expect_verified_header()
+			.with(eq(hash))
+			.returning(move |_| Some(header.clone()));
+
+		let mut client = MockClient::new();
+		let extrinsics = block.extrinsics.clone();
+		client.expect_get_block_body().times(1).returning(move |_| {
+			let extrinsics = extrinsics.clone();
+			Box::pin(async move { Ok(extrinsics) })
+		});
+		client
+			.expect_get_block_body()
+			.returning(|_| Box::pin(async move { Ok(vec![vec![4, 1, 2, 4]]) }));
+		let rpc = VerifiedRpc::new(client, headers);
+		assert_eq!(rpc.chain_get_block(hash).await.unwrap(), block);
+		assert!(rpc.chain_get_block(hash).await.is_err());
+	}
+
+	#[tokio::test]
+	async fn reserve_body_size() {
+		let mut chain = ChainBuilder::new(&[[1; 32]]);
+		let spec = BlockSpec {
+			extrinsics: vec![vec![4, 1, 2, 3]],
+			..Default::default()
+		};
+		let hash = chain.build_on(chain.genesis_hash(), spec).unwrap();
+		let block = chain.block(&hash).unwrap().clone();
+		let mut headers = MockVerifiedHeaders::new();
+		let header = block.header.clone();
+		headers
+			.expect_verified_header()
+			.returning(move |_| Some(header.clone()));
+		let mut client = MockClient::new();
+		let extrinsics = block.extrinsics.clone();
+		client.expect_get_block_body().returning(move |_| {
+			let extrinsics = extrinsics.clone();
+			Box::pin(async move { Ok(extrinsics) })
+		});
+
+		let budget = MemoryBudget::new(Some(4));
+		let rpc = VerifiedRpc::new(client, headers).with_budget(budget.clone());
+		assert_eq!(rpc.chain_get_block(hash).await.unwrap(), block);
+		assert_eq!(budget.usage(Component::PendingBodies), 0);
+		// Body doesn't fit into the budget
+		let budget = MemoryBudget::new(Some(3));
+		let rpc = VerifiedRpc {
+			budget: Some(budget),
+			..rpc
+		};
+		assert!(rpc.chain_get_block(hash).await.is_err());
+	}
+}